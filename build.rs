@@ -5,4 +5,15 @@ fn main() {
         let new_path = format!(r"{};.\src\windows", path);
         std::env::set_var("PATH", new_path);
     }
+
+    // the regex engine version is reported when verifying patterns
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    let lock = std::fs::read_to_string("Cargo.lock").unwrap_or_default();
+    let version = lock
+        .split("[[package]]")
+        .find(|package| package.contains("name = \"regex\"\n"))
+        .and_then(|package| package.lines().find(|line| line.starts_with("version = ")))
+        .map(|line| line.trim_start_matches("version = ").trim_matches('"'))
+        .unwrap_or("unknown");
+    println!("cargo:rustc-env=CLF_REGEX_VERSION={}", version);
 }
@@ -1,15 +1,16 @@
 //! Manage command line arguments here.
 use std::path::PathBuf;
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg};
 use simplelog::LevelFilter;
 
-use crate::logfile::lookup::ReaderCallType;
-use crate::misc::extension::Expect;
+use crate::init::Expect;
+use crate::logfile::{lookup::ReaderCallType, record::OutputFormat};
 use crate::misc::{
     nagios::{Nagios, NagiosVersion},
     util::*,
 };
+use crate::search::SearchOptions;
 
 /// This structure holds the command line arguments.
 #[derive(Debug)]
@@ -28,6 +29,76 @@ pub struct CliOptions {
     pub extra_vars: Option<Vec<String>>,
     pub show_rendered: bool,
     pub reset_log: bool,
+    pub annotate: Option<AnnotateOptions>,
+    pub output_format: Option<OutputFormat>,
+    pub snapshot_verify: bool,
+    pub snapshot_diff: Option<SnapshotDiffOptions>,
+    pub snapshot_history: Option<SnapshotHistoryOptions>,
+    pub snapshot_remap: Option<SnapshotRemapOptions>,
+    pub snapshot_reset: Option<SnapshotResetOptions>,
+    pub checkpoint_create: Option<String>,
+    pub replay_from: Option<String>,
+    pub heal_offsets: bool,
+    pub follow: bool,
+    pub summary_line: bool,
+    pub trace_lines: f64,
+    pub verify_patterns: Option<VerifyPatternsOptions>,
+    pub dry_run: bool,
+    pub print_config: bool,
+    pub test_pattern: Option<TestPatternOptions>,
+    pub build_info: bool,
+}
+
+/// Arguments of the `annotate` subcommand: the logfile to annotate and an optional output file.
+#[derive(Debug)]
+pub struct AnnotateOptions {
+    pub file: PathBuf,
+    pub out: Option<PathBuf>,
+}
+
+/// Arguments of the `snapshot diff` subcommand: the snapshot files to compare and whether to print JSON.
+#[derive(Debug)]
+pub struct SnapshotDiffOptions {
+    pub old: PathBuf,
+    pub new: PathBuf,
+    pub json: bool,
+}
+
+/// Arguments of the `snapshot history` subcommand: an optional logfile to restrict the history to, and whether to print
+/// JSON.
+#[derive(Debug)]
+pub struct SnapshotHistoryOptions {
+    pub logfile: Option<PathBuf>,
+    pub json: bool,
+}
+
+/// Arguments of the `snapshot remap` subcommand: the directory logfiles were moved from, and the one they were moved to.
+#[derive(Debug)]
+pub struct SnapshotRemapOptions {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// Arguments of the `snapshot reset` subcommand: the logfile whose counters and offsets are cleared, and an optional tag
+/// to only clear this one.
+#[derive(Debug)]
+pub struct SnapshotResetOptions {
+    pub logfile: PathBuf,
+    pub tag: Option<String>,
+}
+
+/// Arguments of the `verify-patterns` subcommand: whether to print JSON.
+#[derive(Debug)]
+pub struct VerifyPatternsOptions {
+    pub json: bool,
+}
+
+/// Arguments of the `test-pattern` subcommand: the lines to test, read from the standard input if none is given, and
+/// whether to print JSON.
+#[derive(Debug)]
+pub struct TestPatternOptions {
+    pub lines: Vec<String>,
+    pub json: bool,
 }
 
 /// Implements `Default` trait for `CliOptions`.
@@ -52,6 +123,24 @@ impl Default for CliOptions {
             extra_vars: None,
             show_rendered: false,
             reset_log: false,
+            annotate: None,
+            output_format: None,
+            snapshot_verify: false,
+            snapshot_diff: None,
+            snapshot_history: None,
+            snapshot_remap: None,
+            snapshot_reset: None,
+            checkpoint_create: None,
+            replay_from: None,
+            heal_offsets: false,
+            follow: false,
+            summary_line: false,
+            trace_lines: 0.0,
+            verify_patterns: None,
+            dry_run: false,
+            print_config: false,
+            test_pattern: None,
+            build_info: false,
         }
     }
 }
@@ -71,7 +160,8 @@ impl CliOptions {
                     .long_about("Mandatory argument. The name and path of the YAML configuration file, containing logfiles to search for and patterns to match")
                     .short('c')
                     .long("config")
-                    .required(true)
+                    .required(false)
+                    .global(true)
                     .long_about("Name of the YAML configuration file")
                     .takes_value(true),
             )
@@ -149,6 +239,14 @@ impl CliOptions {
                     .long_about("Don't run any callback, just read all logfiles in the configuration file and print out matching line. Used to check whether regexes are correct")
                     .takes_value(false),
             )
+            .arg(
+                Arg::new("dry-run")
+                    .long("dry-run")
+                    .required(false)
+                    .conflicts_with_all(&["no-callback", "follow"])
+                    .long_about("Search all logfiles like a normal run, with thresholds, exclusions and ok patterns, but don't run any callback, script or claim, don't write any output file and don't save snapshots. A report of the matches and the callback calls which would have been done is printed out for each tag, followed by the plugin output")
+                    .takes_value(false),
+            )
             .arg(
                 Arg::new("snapshot")
                     .short('p')
@@ -182,18 +280,255 @@ impl CliOptions {
                     .long_about("Overwrite clf log if specified")
                     .takes_value(false),
             )
+            .arg(
+                Arg::new("heal-offsets")
+                    .long("heal-offsets")
+                    .required(false)
+                    .long_about("Reset snapshot offsets which are beyond the logfile size, e.g. after logfiles were restored from a backup, so these logfiles are read again from their beginning")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::new("follow")
+                    .long("follow")
+                    .required(false)
+                    .conflicts_with("no-callback")
+                    .long_about("Run as a daemon, like tail -f: logfiles are searched again at each follow_interval from the global options, callbacks are called for new matching lines, and snapshots are saved at each follow_save_interval. clf stops on SIGTERM or SIGINT, saving snapshots and printing the plugin output of the last search, and reloads its configuration on SIGHUP")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::new("summary-line")
+                    .long("summary-line")
+                    .required(false)
+                    .long_about("Print a last line summarizing the run as key=value pairs, for scrapers of the plugin output: 'CLF_SUMMARY version=1 state=CRITICAL criticals=3 warnings=0 files=2 duration=0.012 errors=0'. This format is stable")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::new("replay-from")
+                    .long("replay-from")
+                    .required(false)
+                    .long_about("Read logfiles again from the offsets saved in this checkpoint, created with the 'snapshot checkpoint create' command. Counters are kept, and logfiles rotated since the checkpoint are read from their current offsets")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::new("output-format")
+                    .short('f')
+                    .long("output-format")
+                    .required(false)
+                    .long_about("When used with --no-callback, the format of matching lines printed out, overriding the one in the configuration file. Defaults to 'text'")
+                    .possible_values(&["text", "ndjson"])
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::new("build-info")
+                    .long("build-info")
+                    .required(false)
+                    .long_about("Print the version, the optional features this binary was built with, and the compression schemes and callbacks they enable, and exit")
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::new("trace-lines")
+                    .long("trace-lines")
+                    .required(false)
+                    .long_about("Fraction of lines, between 0 and 1, whose decision path is logged as JSON in the clf log: exclusion, matching regex and exception, counters and thresholds, and whether the callback was called. Used to find out why a line didn't trigger a callback, without the Trace log level")
+                    .takes_value(true),
+            )
+            .subcommand(
+                App::new("annotate")
+                    .about("Write each line of a logfile prefixed with the decision taken for each tag: critical, warning, ok, excluded, header or none, along with the matching regex and threshold reasoning. No callback is called and the snapshot file is not updated")
+                    .arg(
+                        Arg::new("file")
+                            .short('f')
+                            .long("file")
+                            .required(true)
+                            .long_about("The logfile to annotate. It must be defined in a search of the configuration file")
+                            .takes_value(true),
+                    )
+                    .arg(
+                        Arg::new("out")
+                            .short('o')
+                            .long("out")
+                            .required(false)
+                            .long_about("The name of the annotated file. Defaults to the standard output")
+                            .takes_value(true),
+                    ),
+            )
+            .subcommand(
+                App::new("snapshot")
+                    .about("Manage snapshot files")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        App::new("verify")
+                            .about("Cross-check all snapshot entries against the filesystem and the configuration file, and print stale, rotated or inconsistent entries with a suggested fix. No logfile is searched and the snapshot file is not updated"),
+                    )
+                    .subcommand(
+                        App::new("diff")
+                            .about("Print the differences of offsets and counters for each logfile and tag between 2 snapshot files, along with added or removed entries. The configuration file is not needed")
+                            .arg(Arg::new("old").required(true).about("The older snapshot file"))
+                            .arg(Arg::new("new").required(true).about("The newer snapshot file"))
+                            .arg(
+                                Arg::new("json")
+                                    .short('j')
+                                    .long("json")
+                                    .required(false)
+                                    .long_about("Print the differences as JSON instead of a table")
+                                    .takes_value(false),
+                            ),
+                    )
+                    .subcommand(
+                        App::new("history")
+                            .about("Print the summary of the last runs kept in the snapshot files: time, exit status, duration and counters. The number of runs kept is set by the snapshot_history global option. No logfile is searched")
+                            .arg(Arg::new("logfile").required(false).about("Only print the runs which searched this logfile, with its own counters"))
+                            .arg(
+                                Arg::new("json")
+                                    .short('j')
+                                    .long("json")
+                                    .required(false)
+                                    .long_about("Print the history as JSON instead of a table")
+                                    .takes_value(false),
+                            ),
+                    )
+                    .subcommand(
+                        App::new("remap")
+                            .about("Move the entries of the logfiles found in a directory to the same paths in another one, once these logfiles were moved, so they're read on from their previous offsets instead of from their beginning. Signatures are calculated again from the moved logfiles, and checkpoints and history follow. No logfile is searched")
+                            .arg(
+                                Arg::new("from")
+                                    .long("from")
+                                    .required(true)
+                                    .long_about("The directory the logfiles were moved from")
+                                    .takes_value(true),
+                            )
+                            .arg(
+                                Arg::new("to")
+                                    .long("to")
+                                    .required(true)
+                                    .long_about("The directory the logfiles were moved to")
+                                    .takes_value(true),
+                            ),
+                    )
+                    .subcommand(
+                        App::new("reset")
+                            .about("Clear the counters and offsets of a logfile, or only those of one of its tags, in the snapshot files used by the searches, e.g. to arm checks again after a maintenance window. The logfile is read again from its beginning at next run. No logfile is searched")
+                            .arg(
+                                Arg::new("logfile")
+                                    .long("logfile")
+                                    .required(true)
+                                    .long_about("The logfile whose counters and offsets are cleared")
+                                    .takes_value(true),
+                            )
+                            .arg(
+                                Arg::new("tag")
+                                    .long("tag")
+                                    .required(false)
+                                    .long_about("Only clear the counters and offsets of this tag")
+                                    .takes_value(true),
+                            ),
+                    )
+                    .subcommand(
+                        App::new("checkpoint")
+                            .about("Manage named checkpoints, which are copies of the offsets of all tags kept in the snapshot files")
+                            .setting(AppSettings::SubcommandRequiredElseHelp)
+                            .subcommand(
+                                App::new("create")
+                                    .about("Save the current offsets of all tags under a name, replacing any checkpoint with the same name. Logfiles are read again from this point with --replay-from")
+                                    .arg(Arg::new("name").required(true).about("The checkpoint name")),
+                            ),
+                    ),
+            )
+            .subcommand(
+                App::new("print-config")
+                    .about("Print the configuration which is run, as YAML, once rendered with the Tera context, logfile sources expanded and default options merged into tag options. Secrets like authorization headers are redacted. No logfile is searched"),
+            )
+            .subcommand(
+                App::new("test-pattern")
+                    .about("Match sample lines against the patterns of all tags of the configuration file, and print which pattern type and regex matched each line, along with the capture groups which would be passed to the callback as CLF_CG_* variables. No logfile is searched and no callback is called")
+                    .arg(
+                        Arg::new("line")
+                            .short('l')
+                            .long("line")
+                            .required(false)
+                            .long_about("A line to test. Could be repeated. If not given, lines are read from the standard input")
+                            .multiple(true)
+                            .takes_value(true),
+                    )
+                    .arg(
+                        Arg::new("json")
+                            .short('j')
+                            .long("json")
+                            .required(false)
+                            .long_about("Print the results as JSON instead of a table")
+                            .takes_value(false),
+                    ),
+            )
+            .subcommand(
+                App::new("verify-patterns")
+                    .about("Compile all regexes, exceptions and exclude expressions of the configuration file, and print the regex engine version along with the compile time, errors and engine-dependent constructs of each expression. No logfile is searched")
+                    .arg(
+                        Arg::new("json")
+                            .short('j')
+                            .long("json")
+                            .required(false)
+                            .long_about("Print the results as JSON instead of a table")
+                            .takes_value(false),
+                    ),
+            )
             .get_matches();
 
         // save all cli options into a structure
         let mut options = CliOptions::default();
 
-        // config file is mandatory. Try to canonicalize() at the same time.
-        let config_file = PathBuf::from(matches.value_of("config").unwrap());
+        // snapshot subcommand
+        if let Some(snapshot) = matches.subcommand_matches("snapshot") {
+            options.snapshot_verify = snapshot.subcommand_matches("verify").is_some();
+            options.snapshot_diff =
+                snapshot
+                    .subcommand_matches("diff")
+                    .map(|diff| SnapshotDiffOptions {
+                        old: PathBuf::from(diff.value_of("old").unwrap()),
+                        new: PathBuf::from(diff.value_of("new").unwrap()),
+                        json: diff.is_present("json"),
+                    });
+            options.snapshot_history =
+                snapshot
+                    .subcommand_matches("history")
+                    .map(|history| SnapshotHistoryOptions {
+                        logfile: history.value_of("logfile").map(PathBuf::from),
+                        json: history.is_present("json"),
+                    });
+            options.snapshot_remap =
+                snapshot
+                    .subcommand_matches("remap")
+                    .map(|remap| SnapshotRemapOptions {
+                        from: PathBuf::from(remap.value_of("from").unwrap()),
+                        to: PathBuf::from(remap.value_of("to").unwrap()),
+                    });
+            options.snapshot_reset =
+                snapshot
+                    .subcommand_matches("reset")
+                    .map(|reset| SnapshotResetOptions {
+                        logfile: PathBuf::from(reset.value_of("logfile").unwrap()),
+                        tag: reset.value_of("tag").map(|x| x.to_string()),
+                    });
+            options.checkpoint_create = snapshot
+                .subcommand_matches("checkpoint")
+                .and_then(|checkpoint| checkpoint.subcommand_matches("create"))
+                .map(|create| create.value_of("name").unwrap().to_string());
+        }
 
-        options.config_file = config_file.canonicalize().expect_critical(&format!(
-            "error trying to canonicalize config file: {}",
-            config_file.display()
-        ));
+        options.build_info = matches.is_present("build-info");
+
+        // config file is mandatory, except to compare snapshots or print the build. Try to canonicalize() at the same
+        // time.
+        match matches.value_of("config") {
+            Some(config) => {
+                let config_file = PathBuf::from(config);
+                options.config_file = config_file.canonicalize().expect_critical(&format!(
+                    "error trying to canonicalize config file: {}",
+                    config_file.display()
+                ));
+            }
+            None if options.snapshot_diff.is_some() || options.build_info => (),
+            None => Nagios::exit_critical("the configuration file argument --config is mandatory"),
+        }
 
         // optional log file
         if matches.is_present("log") {
@@ -211,6 +546,11 @@ impl CliOptions {
         options.show_options = matches.is_present("show-options");
         options.show_rendered = matches.is_present("show-rendered");
         options.reset_log = matches.is_present("overwrite-log");
+        options.heal_offsets = matches.is_present("heal-offsets");
+        options.follow = matches.is_present("follow");
+        options.summary_line = matches.is_present("summary-line");
+        options.dry_run = matches.is_present("dry-run");
+        options.replay_from = matches.value_of("replay-from").map(|x| x.to_string());
 
         options.logger_level = matches.value_of_t("log-level").unwrap_or(LevelFilter::Info);
 
@@ -218,9 +558,8 @@ impl CliOptions {
             .value_of_t("nagios-version")
             .unwrap_or(NagiosVersion::Nrpe3);
 
-        if matches.is_present("snapshot") {
-            options.snapshot_file = Some(PathBuf::from(matches.value_of("snapshot").unwrap()));
-        }
+        // the snapshot subcommand shares its name with this option, so is_present() can't be used
+        options.snapshot_file = matches.value_of("snapshot").map(PathBuf::from);
 
         options.max_logger_size = matches
             .value_of_t("max-logsize")
@@ -233,6 +572,47 @@ impl CliOptions {
             options.extra_vars = Some(vars.iter().map(|x| x.to_string()).collect());
         }
 
+        options.output_format = matches.value_of_t("output-format").ok();
+
+        if matches.is_present("trace-lines") {
+            let ratio: f64 = matches
+                .value_of_t("trace-lines")
+                .unwrap_or_else(|e| Nagios::exit_critical(&format!("--trace-lines: {}", e)));
+            if !(0.0..=1.0).contains(&ratio) {
+                Nagios::exit_critical("--trace-lines must be between 0 and 1");
+            }
+            options.trace_lines = ratio;
+        }
+
+        // annotate subcommand
+        if let Some(annotate) = matches.subcommand_matches("annotate") {
+            options.annotate = Some(AnnotateOptions {
+                file: PathBuf::from(annotate.value_of("file").unwrap()),
+                out: annotate.value_of("out").map(PathBuf::from),
+            });
+        }
+
+        // verify-patterns subcommand
+        options.verify_patterns =
+            matches
+                .subcommand_matches("verify-patterns")
+                .map(|verify| VerifyPatternsOptions {
+                    json: verify.is_present("json"),
+                });
+
+        options.print_config = matches.subcommand_matches("print-config").is_some();
+
+        // test-pattern subcommand
+        options.test_pattern =
+            matches
+                .subcommand_matches("test-pattern")
+                .map(|test| TestPatternOptions {
+                    lines: test
+                        .values_of("line")
+                        .map_or(Vec::new(), |lines| lines.map(|x| x.to_string()).collect()),
+                    json: test.is_present("json"),
+                });
+
         options.show_options = matches.is_present("show-options");
         if options.show_options {
             // print out options if requested and exits
@@ -241,4 +621,13 @@ impl CliOptions {
 
         options
     }
+
+    /// How logfiles are searched, from the command line
+    pub fn search_options(&self) -> SearchOptions {
+        SearchOptions {
+            reader_type: self.reader_type,
+            dry_run: self.dry_run,
+            trace_lines: self.trace_lines,
+        }
+    }
 }
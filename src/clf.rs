@@ -20,7 +20,10 @@
 // - implement logfilemissing
 
 use log::{debug, info};
+use std::collections::HashSet;
 use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use std::thread;
 use std::time::{Duration, Instant};
 
@@ -30,17 +33,34 @@ extern crate simplelog;
 
 use wait_timeout::ChildExt;
 
-mod configuration;
-use configuration::callback::ChildData;
+// searches are done by the library, the binary adds what's specific to a Nagios plugin
+use clf::{configuration, logfile, misc, search};
+
+use configuration::{
+    callback::ChildData, claim::ClaimStatus, config::Config, email, search::Search,
+    vars::GlobalVars,
+};
 
-mod logfile;
 use logfile::{
     logfileerror::LogFileAccessErrorList,
-    lookup::{BypassReader, FullReader, ReaderCallType},
+    lookup::ReaderCallType,
+    output::RunOutputs,
+    snapshot::{Snapshot, SnapshotList},
+    watcher::Watcher,
+};
+
+use misc::{
+    error::{AppCustomErrorKind, AppError, AppResult},
+    extension::ReadFs,
+    nagios::{Nagios, NagiosError},
+    rundir, signal,
+    util::{from_epoch_secs, DEFAULT_KILL_GRACE_PERIOD},
 };
 
-mod misc;
-use misc::{extension::ReadFs, nagios::Nagios};
+#[cfg(target_os = "linux")]
+use misc::systemd;
+
+use search::{drain_queues, resume_logfile, search_logfile};
 
 mod args;
 use args::CliOptions;
@@ -74,6 +94,33 @@ fn main() {
     //---------------------------------------------------------------------------------------------------
     init_log(&options);
 
+    // a shutdown signal stops the searches, but the snapshot is still saved and scripts are terminated
+    signal::install_handlers();
+
+    // print the features of this binary if requested and exit: no configuration file is needed
+    if options.build_info {
+        let exit_code = print_build_info();
+        Nagios::exit_with(exit_code);
+    }
+
+    // compare snapshot files if requested and exit: no configuration file is needed
+    if let Some(diff) = &options.snapshot_diff {
+        let exit_code = diff_snapshots(diff);
+        Nagios::exit_with(exit_code);
+    }
+
+    // compile all patterns if requested and exit: the configuration file doesn't need to be valid
+    if let Some(verify) = &options.verify_patterns {
+        let exit_code = verify_patterns(&options, verify);
+        Nagios::exit_with(exit_code);
+    }
+
+    // print the configuration which is run if requested and exit
+    if options.print_config {
+        let exit_code = print_config(&options);
+        Nagios::exit_with(exit_code);
+    }
+
     //---------------------------------------------------------------------------------------------------
     // which kind or reader do we want ?
     //---------------------------------------------------------------------------------------------------
@@ -82,7 +129,7 @@ fn main() {
     //---------------------------------------------------------------------------------------------------
     // load configuration file as specified from the command line
     //---------------------------------------------------------------------------------------------------
-    let config = init_config(&options);
+    let mut config = init_config(&options);
     debug!("{:#?}", config);
 
     // print out config if requested and exit
@@ -90,10 +137,73 @@ fn main() {
         Nagios::exit_ok(&format!("{:#?}", config));
     }
 
+    // test lines against patterns if requested and exit
+    if let Some(test) = &options.test_pattern {
+        let exit_code = test_pattern(&config, test);
+        Nagios::exit_with(exit_code);
+    }
+
+    // annotate a logfile if requested and exit
+    if let Some(annotate) = &options.annotate {
+        annotate_logfile(&config, annotate);
+    }
+
     //---------------------------------------------------------------------------------------------------
     // manage snapshot file: overrides the snapshot file is provided as a command line argument
     //---------------------------------------------------------------------------------------------------
-    let (mut snapshot, snapfile) = load_snapshot(&options, &config.global.snapshot_file);
+    let default_snapfile = snapshot_file(&options, &config.global.snapshot_file);
+
+    // verify snapshot files if requested and exit
+    if options.snapshot_verify {
+        let exit_code = verify_snapshots(&config, &options, &default_snapfile);
+        Nagios::exit_with(exit_code);
+    }
+
+    // print the last runs kept in snapshot files if requested and exit
+    if let Some(history) = &options.snapshot_history {
+        let exit_code = snapshot_history(&config, &options, &default_snapfile, history);
+        Nagios::exit_with(exit_code);
+    }
+
+    // move the snapshot entries of the logfiles moved from a directory to another one if requested and exit
+    if let Some(remap) = &options.snapshot_remap {
+        let exit_code = remap_snapshots(&config, &options, &default_snapfile, remap);
+        Nagios::exit_with(exit_code);
+    }
+
+    if let Some(reset) = &options.snapshot_reset {
+        let exit_code = reset_snapshots(&config, &options, &default_snapfile, reset);
+        Nagios::exit_with(exit_code);
+    }
+
+    if let Some(name) = &options.checkpoint_create {
+        let exit_code = create_checkpoint(&config, &options, &default_snapfile, name);
+        Nagios::exit_with(exit_code);
+    }
+
+    // snapshots are loaded when first needed by a search, because each search can use its own snapshot file
+    let mut snapshots = SnapshotList::default();
+
+    // offsets beyond the logfile sizes are checked at startup, before any logfile is read
+    check_offsets(
+        &config,
+        &options,
+        &default_snapfile,
+        &mut snapshots,
+        &mut access_errors,
+    );
+
+    // logfiles are read again from a checkpoint if requested, instead of the last offsets
+    if let Some(name) = &options.replay_from {
+        replay_checkpoint(&mut snapshots, name);
+    }
+
+    // files given to scripts are written to the directory of this run, and those of old runs are deleted
+    if !options.dry_run {
+        if let Err(e) = rundir::init(&config.global.output_dir, config.global.output_retention) {
+            error!("unable to delete old run directories: {}", e);
+        }
+    }
 
     //---------------------------------------------------------------------------------------------------
     // start prescripts if any
@@ -101,176 +211,688 @@ fn main() {
     // we'll keep all prescript pid's in order to send them back, if any, to the postscript
     let mut prescript_pids = Vec::new();
 
-    if config.global.prescript.is_some() {
-        for prescript in config.global.prescript.as_ref().unwrap() {
-            prescript_pids.push(spawn_prescript(prescript, Some(&config.global.global_vars)));
+    // variables exported by prescripts and searches skipped are kept when the configuration is reloaded
+    let mut prescript_vars = GlobalVars::new();
+    let mut skipped_searches = Vec::new();
+
+    if config.global.prescript.is_some() && !options.dry_run {
+        // variables exported by a prescript are given to the following ones and to callbacks
+        for prescript in config.global.prescript.clone().unwrap() {
+            let output = spawn_prescript(&prescript, Some(&config.global.global_vars));
+            prescript_pids.push(output.pid);
+            config.global.global_vars.extend(output.vars.clone());
+            prescript_vars.extend(output.vars);
+
+            if !output.success {
+                info!(
+                    "prescript {:?} failed, skipping searches for logfiles {:?}",
+                    prescript.command, prescript.skip_searches
+                );
+                skipped_searches.extend(prescript.skip_searches);
+            }
         }
+        config
+            .searches
+            .retain(|search| !skipped_searches.contains(search.logfile.path()));
     }
 
     //---------------------------------------------------------------------------------------------------
     // loop through all searches
     //---------------------------------------------------------------------------------------------------
+    // the output and protocol files are only created if a line is kept
+    let outputs = RunOutputs::new(&config.global);
+
+    // when started as a systemd notify service, the service is ready once the configuration is loaded
+    #[cfg(target_os = "linux")]
+    if let Err(e) = systemd::notify(systemd::State::Ready) {
+        error!("unable to notify systemd: {}", e);
+    }
+
+    // payloads left in the queues of callbacks by a previous run are sent first, even if no new line matches
+    if !options.dry_run && reader_type == &ReaderCallType::FullReaderCall {
+        drain_queues(&config);
+    }
+
+    search_logfiles(
+        &config,
+        &options,
+        &default_snapfile,
+        &mut snapshots,
+        &mut access_errors,
+        &mut children_list,
+        &outputs,
+    );
+
+    // just exit if the '--no-callback' option was used
+    if reader_type == &ReaderCallType::BypassReaderCall {
+        Nagios::exit_ok("read complete");
+    }
+
+    // in a dry run, report what would have been done and exit without saving snapshots
+    if options.dry_run {
+        print!("{}", snapshots.dry_run_report());
+        Nagios::exit_with(snapshots.exit_message(&access_errors, &config.global));
+    }
+
+    // the local agent gets the report of the run, whatever the matches. In follow mode, it gets one for each search
+    send_report(&config, &snapshots, &access_errors, now.elapsed());
+
+    // in follow mode, logfiles are searched again until a shutdown signal is received
+    if options.follow {
+        signal::install_reload_handler();
+        follow(
+            &mut config,
+            || reload_config(&options, &prescript_vars, &skipped_searches),
+            &options,
+            &default_snapfile,
+            &mut snapshots,
+            &mut access_errors,
+            &mut children_list,
+            &outputs,
+        );
+    }
+
+    // matches for e-mail callbacks are sent in a single message, before saving snapshots to keep those not sent
+    send_digests(&default_snapfile, &mut snapshots);
+
+    // the summary of this run is kept in the history of each snapshot
+    let exit_code = NagiosError::from(&snapshots.global_exit(&access_errors));
+    let end_time = from_epoch_secs().unwrap_or_default();
+    for snapshot in snapshots.values_mut() {
+        snapshot.record_run(
+            end_time,
+            &exit_code,
+            now.elapsed().as_secs_f64(),
+            config.global.snapshot_history,
+        );
+    }
+
+    // save snapshots and optionally delete old entries
+    save_snapshots(&config, &mut snapshots);
+
+    // teardown
+    if !children_list.is_empty() {
+        info!(
+            "waiting for all processes to finish, nb of children: {}",
+            children_list.len()
+        );
+        wait_children(children_list);
+    }
+
+    // optionally call postscript
+    if let Some(postscript) = config.global.postscript.as_mut() {
+        spawn_postscript(postscript, &prescript_pids);
+    }
+
+    info!(
+        "end of searches, elapsed: {} seconds",
+        now.elapsed().as_secs_f32()
+    );
+
+    // optionally push counters to a statsd or graphite endpoint. This is not fatal for the plugin output
+    if let Some(exporter) = &config.global.exporter {
+        if let Err(e) = exporter.export(&snapshots) {
+            error!("error exporting counters to {:?}: {}", exporter.exporter, e);
+        }
+    }
+
+    // now we can prepare the global hit counters to exit the relevant Nagios code
+    let exit_code = snapshots.exit_message(&access_errors, &config.global);
+    if options.summary_line {
+        println!("{}", snapshots.summary(&access_errors, now.elapsed()));
+    }
+
+    #[cfg(target_os = "linux")]
+    for state in [
+        systemd::State::Status(format!("last run: {}", String::from(&exit_code))),
+        systemd::State::Stopping,
+    ] {
+        if let Err(e) = systemd::notify(state) {
+            error!("unable to notify systemd: {}", e);
+        }
+    }
+
+    Nagios::exit_with(exit_code);
+}
+
+/// Searches all logfiles of the configuration once, from the offsets of their snapshot. With `max_threads`, logfiles are
+/// searched by a pool of workers.
+fn search_logfiles(
+    config: &Config,
+    options: &CliOptions,
+    default_snapfile: &Path,
+    snapshots: &mut SnapshotList,
+    access_errors: &mut LogFileAccessErrorList,
+    children_list: &mut Vec<ChildData>,
+    outputs: &RunOutputs,
+) {
+    if config.global.max_threads > 1 {
+        search_logfiles_in_pool(
+            config,
+            options,
+            default_snapfile,
+            snapshots,
+            access_errors,
+            children_list,
+            outputs,
+        );
+        return;
+    }
+
+    let search_options = options.search_options();
+    let mut sliced = Vec::new();
     for search in &config.searches {
+        #[cfg(target_os = "linux")]
+        systemd::watchdog();
+
+        // remaining searches are skipped on shutdown
+        if signal::shutdown_requested() {
+            warn!("shutdown signal received, remaining searches are skipped");
+            break;
+        }
+
         // log some :qeful info
         info!("==> searching into logfile: {:?}", &search.logfile.path());
 
-        // checks if logfile is accessible. If not, no need to move further, just record last error
-        if let Err(e) = search.logfile.path().is_usable() {
-            error!(
-                "logfile: {:?} is not a file or is not accessible, error: {}",
-                &search.logfile.path, e
-            );
-
-            // this is an error for this logfile which boils down to a Nagios error
-            access_errors.set_error(&search.logfile.path(), e, &search.logfile.logfilemissing);
+        if !logfile_searchable(config, options, search, access_errors) {
             continue;
         }
 
-        // create a LogFile struct or get it from snapshot
-        let logfile_from_snapshot = {
-            let temp = snapshot.logfile_mut(&search.logfile.path(), &search.logfile);
-            if let Err(e) = temp {
-                error!(
-                    "error fetching logfile {} from snapshot: {}",
-                    search.logfile.path().display(),
-                    e,
-                );
+        // get the snapshot for this search, which is loaded only once
+        let snapfile = search_snapfile(options, default_snapfile, search);
+        let snapshot = snapshots
+            .entry(snapfile.clone())
+            .or_insert_with_key(|snapfile| load_snapshot(options, snapfile));
+
+        if search_logfile(
+            config,
+            &search_options,
+            search,
+            snapshot,
+            access_errors,
+            children_list,
+            outputs,
+        ) {
+            sliced.push((search, snapfile));
+        }
+    }
+
+    // with the interleaved fairness, the searches not complete after their first slice go on in turn
+    while !sliced.is_empty() {
+        #[cfg(target_os = "linux")]
+        systemd::watchdog();
 
-                // this is a error for this logfile which boils down to a Nagios unknown error
-                access_errors.set_error(&search.logfile.path(), e, &search.logfile.logfilemissing);
-                continue;
+        if signal::shutdown_requested() {
+            warn!("shutdown signal received, remaining slices are skipped");
+            break;
+        }
+
+        sliced.retain(|(search, snapfile)| match snapshots.get_mut(snapfile) {
+            Some(snapshot) => resume_logfile(
+                config,
+                &search_options,
+                search,
+                snapshot,
+                access_errors,
+                children_list,
+            ),
+            None => false,
+        });
+    }
+}
+
+// the searches of the same logfile in the same snapshot, run one after the other by a worker
+struct Job<'a> {
+    snapfile: PathBuf,
+    path: PathBuf,
+    searches: Vec<&'a Search>,
+
+    // with the interleaved fairness, the searches to go on with at the next slice
+    started: bool,
+    sliced: Vec<&'a Search>,
+
+    // the snapshot entry of the logfile, and what's found when searching it
+    shard: Snapshot,
+    access_errors: LogFileAccessErrorList,
+    children_list: Vec<ChildData>,
+}
+
+/// Searches all logfiles with `max_threads` workers. Each logfile is searched by a single worker, with its own shard of
+/// the snapshot: shards, errors and scripts started are merged back once all logfiles are searched.
+fn search_logfiles_in_pool(
+    config: &Config,
+    options: &CliOptions,
+    default_snapfile: &Path,
+    snapshots: &mut SnapshotList,
+    access_errors: &mut LogFileAccessErrorList,
+    children_list: &mut Vec<ChildData>,
+    outputs: &RunOutputs,
+) {
+    let search_options = options.search_options();
+    let mut jobs: Vec<Job> = Vec::new();
+    for search in &config.searches {
+        let snapfile = search_snapfile(options, default_snapfile, search);
+        let path = search.logfile.path();
+
+        match jobs
+            .iter_mut()
+            .find(|job| job.snapfile == snapfile && &job.path == path)
+        {
+            Some(job) => job.searches.push(search),
+            None => {
+                let shard = snapshots
+                    .entry(snapfile.clone())
+                    .or_insert_with_key(|snapfile| load_snapshot(options, snapfile))
+                    .take_shard(path);
+                jobs.push(Job {
+                    snapfile,
+                    path: path.clone(),
+                    searches: vec![search],
+                    started: false,
+                    sliced: Vec::new(),
+                    shard,
+                    access_errors: LogFileAccessErrorList::default(),
+                    children_list: Vec::new(),
+                });
             }
-            temp.unwrap()
-        };
+        }
+    }
 
-        // in case the configuration file changed since the last run and for a logfile, the tags configuration
-        // changed, we need to adjust. There're some cases where there could be more tags in the snapshot than
-        // in the configuration file. So we need to keep in the snapshot only those in the config file.
-        let tag_names = search.tag_names();
-        logfile_from_snapshot
-            .run_data
-            .retain(|k, _| tag_names.contains(&k.as_str()));
-
-        // check if the rotation occured. This means the logfile signature has changed
-        trace!(
-            "checking if logfile {:?} has changed",
-            logfile_from_snapshot.id.canon_path.display()
+    // workers take the jobs in the order of the configuration. A job not complete after a slice is queued again
+    let workers = config.global.max_threads.min(jobs.len());
+    info!("searching {} logfiles with {} workers", jobs.len(), workers);
+    let queue = Mutex::new(jobs.iter_mut().rev().collect::<Vec<_>>());
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let job = match queue.lock().unwrap_or_else(|e| e.into_inner()).pop() {
+                    Some(job) => job,
+                    None => break,
+                };
+
+                let searches = if job.started {
+                    std::mem::take(&mut job.sliced)
+                } else {
+                    job.searches.clone()
+                };
+
+                for search in searches {
+                    #[cfg(target_os = "linux")]
+                    systemd::watchdog();
+
+                    // remaining searches are skipped on shutdown
+                    if signal::shutdown_requested() {
+                        break;
+                    }
+
+                    let sliced = if job.started {
+                        resume_logfile(
+                            config,
+                            &search_options,
+                            search,
+                            &mut job.shard,
+                            &mut job.access_errors,
+                            &mut job.children_list,
+                        )
+                    } else {
+                        info!("==> searching into logfile: {:?}", &search.logfile.path());
+                        logfile_searchable(config, options, search, &mut job.access_errors)
+                            && search_logfile(
+                                config,
+                                &search_options,
+                                search,
+                                &mut job.shard,
+                                &mut job.access_errors,
+                                &mut job.children_list,
+                                outputs,
+                            )
+                    };
+                    if sliced {
+                        job.sliced.push(search);
+                    }
+                }
+
+                job.started = true;
+                if !job.sliced.is_empty() && !signal::shutdown_requested() {
+                    queue
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .insert(0, job);
+                }
+            });
+        }
+    });
+
+    if signal::shutdown_requested() {
+        warn!("shutdown signal received, remaining searches are skipped");
+    }
+
+    for job in jobs {
+        if let Some(snapshot) = snapshots.get_mut(&job.snapfile) {
+            snapshot.merge_shard(job.shard);
+        }
+        access_errors.merge(job.access_errors);
+        children_list.extend(job.children_list);
+    }
+}
+
+// the snapshot file of a search
+fn search_snapfile(options: &CliOptions, default_snapfile: &Path, search: &Search) -> PathBuf {
+    match &search.snapshot_file {
+        Some(file_or_dir) => search_snapshot_file(options, file_or_dir),
+        None => default_snapfile.to_path_buf(),
+    }
+}
+
+// whether the logfile of a search could be searched by this instance. If not, the error is recorded
+fn logfile_searchable(
+    config: &Config,
+    options: &CliOptions,
+    search: &Search,
+    access_errors: &mut LogFileAccessErrorList,
+) -> bool {
+    let reader_type = &options.reader_type;
+
+    // checks if logfile is accessible. If not, no need to move further, just record last error. A journal, event log or
+    // remote source is checked when journalctl, PowerShell or ssh is started
+    let source = &search.logfile.path;
+    let usable =
+        if source.is_journal() || source.is_eventlog() || source.is_remote() || source.is_stdin() {
+            Ok(())
+        } else {
+            search.logfile.path().is_usable()
+        };
+    if let Err(e) = usable {
+        error!(
+            "logfile: {:?} is not a file or is not accessible, error: {}",
+            &search.logfile.path, e
         );
-        let logfile_is_archived = {
-            let temp = logfile_from_snapshot.hash_been_rotated();
-            if let Err(e) = temp {
-                error!(
-                    "error on fetching metadata on logfile {}: {}",
-                    logfile_from_snapshot.id.canon_path.display(),
-                    e
+
+        // this is an error for this logfile which boils down to a Nagios error
+        access_errors.set_error(search.logfile.path(), e, search.logfilemissing());
+        return false;
+    }
+
+    // when several instances search the same logfiles, only the one holding the claim searches this logfile. Claims
+    // are not taken just to check regexes
+    if let (Some(claim), ReaderCallType::FullReaderCall, false) =
+        (&config.global.claim, reader_type, options.dry_run)
+    {
+        match from_epoch_secs().and_then(|now| claim.claim(search.logfile.path(), now)) {
+            Ok(ClaimStatus::Claimed) => (),
+            Ok(ClaimStatus::ClaimedBy(lease)) => {
+                info!(
+                    "logfile {:?} skipped, claimed by {}",
+                    search.logfile.path(),
+                    lease.owner
                 );
-                continue;
+                let e = AppError::new_custom(
+                    AppCustomErrorKind::LogfileClaimed,
+                    &format!("skipped, claimed by {}", lease.owner),
+                );
+                access_errors.set_error(search.logfile.path(), e, &NagiosError::OK);
+                return false;
             }
-            temp.unwrap()
-        };
+            // better alert twice than not at all
+            Err(e) => error!(
+                "unable to claim logfile {:?}, searching it anyway: {}",
+                search.logfile.path(),
+                e
+            ),
+        }
+    }
 
-        if logfile_is_archived {
-            info!(
-                "logfile {} has changed, probably archived and rotated",
-                logfile_from_snapshot.id.canon_path.display()
-            );
+    true
+}
 
-            //let archive_path = LogArchive::default_path(search.logfile.path());
-            let archive_path = search.logfile.archive_path();
-            trace!("archived logfile = {:?}", &archive_path);
+/// Searches all logfiles again at each follow interval, like `tail -f`, until a shutdown signal is received. Scripts
+/// called are waited for after each search, and snapshots are saved at each save interval. On SIGHUP, the configuration
+/// is replaced by the one `reload` gives: offsets are kept in the snapshots, logfiles removed from the configuration
+/// are not searched anymore and new ones are searched from the next search.
+#[allow(clippy::too_many_arguments)]
+fn follow(
+    config: &mut Config,
+    reload: impl Fn() -> AppResult<Config>,
+    options: &CliOptions,
+    default_snapfile: &Path,
+    snapshots: &mut SnapshotList,
+    access_errors: &mut LogFileAccessErrorList,
+    children_list: &mut Vec<ChildData>,
+    outputs: &RunOutputs,
+) {
+    // a null interval would search logfiles without a break
+    let mut interval = Duration::from_secs(config.global.follow_interval.max(1));
+    let mut save_interval = Duration::from_secs(config.global.follow_save_interval);
+    let mut last_save = Instant::now();
+    info!(
+        "following logfiles, interval: {:?}, save interval: {:?}",
+        interval, save_interval
+    );
+
+    // with follow_events, the directories of the logfiles are watched to only search those which changed
+    let mut watcher = None;
+    if config.global.follow_events {
+        watcher = watch_logfiles(config);
+    }
+
+    // on Windows, where there's no SIGHUP, a change of the configuration file asks for a reload
+    #[cfg(target_family = "windows")]
+    let mut config_time = config_modified(options);
+
+    loop {
+        wait_children(std::mem::take(children_list));
+        send_digests(default_snapfile, snapshots);
+
+        if last_save.elapsed() >= save_interval {
+            save_snapshots(config, snapshots);
+            last_save = Instant::now();
+        }
 
-            // clone search and assign archive logfile instead of original logfile
-            let mut archived_logfile = logfile_from_snapshot.clone();
-            if let Err(e) = archived_logfile
-                .id
-                .update(&archive_path, archived_logfile.definition.hash_window)
+        // wait for the next search, but stop as soon as a shutdown signal is received
+        let start = Instant::now();
+        let mut changed = HashSet::new();
+        while start.elapsed() < interval
+            && !signal::shutdown_requested()
+            && !signal::reload_requested()
+        {
+            #[cfg(target_os = "linux")]
+            systemd::watchdog();
+            #[cfg(target_family = "windows")]
+            if config_time != config_modified(options) {
+                config_time = config_modified(options);
+                signal::request_reload();
+            }
+            match watcher
+                .as_mut()
+                .map(|w| w.changes(Duration::from_millis(100)))
             {
-                error!(
-                    "error on updating core data on logfile {}: {}",
-                    logfile_from_snapshot.id.canon_path.display(),
-                    e
-                )
+                None => thread::sleep(Duration::from_millis(100)),
+                Some(Ok(paths)) => {
+                    changed.extend(paths);
+                    if !changed.is_empty() {
+                        break;
+                    }
+                }
+                Some(Err(e)) => {
+                    error!(
+                        "error watching logfiles, back to searching them at each interval: {}",
+                        e
+                    );
+                    watcher = None;
+                }
             }
+        }
+        if signal::shutdown_requested() {
+            info!("shutdown signal received, logfiles are not followed anymore");
+            break;
+        }
 
-            // call adequate reader according to command line
-            if reader_type == &ReaderCallType::BypassReaderCall {
-                archived_logfile.lookup_tags::<BypassReader>(
-                    &config.global,
-                    &search.tags,
-                    &mut children_list,
-                );
-            } else if reader_type == &ReaderCallType::FullReaderCall {
-                archived_logfile.lookup_tags::<FullReader>(
-                    &config.global,
-                    &search.tags,
-                    &mut children_list,
-                );
-            }
+        // the previous configuration is kept if the new one can't be loaded
+        if signal::take_reload() {
+            match reload() {
+                Ok(new_config) => {
+                    let (added, removed) = config.diff_searches(&new_config);
+                    info!(
+                        "configuration reloaded, logfiles added: {:?}, removed: {:?}",
+                        added, removed
+                    );
+                    *config = new_config;
+
+                    interval = Duration::from_secs(config.global.follow_interval.max(1));
+                    save_interval = Duration::from_secs(config.global.follow_save_interval);
+                    if !config.global.follow_events {
+                        watcher = None;
+                    } else if watcher.is_none() {
+                        watcher = watch_logfiles(config);
+                    }
 
-            // reset run_data into original search because this is a new file
-            for tag in &search.tags {
-                if !tag.options.savethresholds {
-                    logfile_from_snapshot.reset_tag(&tag.name);
-                } else {
-                    logfile_from_snapshot.reset_tag_offsets(&tag.name);
-                    logfile_from_snapshot.copy_counters(&archived_logfile, &tag.name);
+                    // all logfiles are searched after a reload
+                    changed.clear();
                 }
+                Err(e) => error!(
+                    "unable to reload configuration, the previous one is kept: {}",
+                    e
+                ),
             }
         }
 
-        // call adequate reader according to command line
-        if reader_type == &ReaderCallType::BypassReaderCall {
-            logfile_from_snapshot.lookup_tags::<BypassReader>(
-                &config.global,
-                &search.tags,
-                &mut children_list,
-            );
-        } else if reader_type == &ReaderCallType::FullReaderCall {
-            logfile_from_snapshot.lookup_tags::<FullReader>(
-                &config.global,
-                &search.tags,
-                &mut children_list,
-            );
+        // logfiles matching a glob pattern could have been created or deleted
+        if let Err(e) = config.rescan_globs() {
+            error!("error expanding glob patterns: {}", e);
+        }
+        if watcher
+            .as_ref()
+            .is_some_and(|w| !w.watches(watched_paths(config)))
+        {
+            watcher = watch_logfiles(config);
         }
-    }
 
-    // just exit if the '--no-callback' option was used
-    if reader_type == &ReaderCallType::BypassReaderCall {
-        Nagios::exit_ok("read complete");
+        // only the logfiles which changed are searched, unless the interval elapsed
+        let all_searches = if changed.is_empty() {
+            None
+        } else {
+            debug!("logfiles changed: {:?}", changed);
+            let all_searches = config.searches.clone();
+            config
+                .searches
+                .retain(|search| changed.contains(search.logfile.path()));
+            Some(all_searches)
+        };
+
+        // the plugin output only reports the errors of the last search
+        *access_errors = LogFileAccessErrorList::default();
+        let start = Instant::now();
+        search_logfiles(
+            config,
+            options,
+            default_snapfile,
+            snapshots,
+            access_errors,
+            children_list,
+            outputs,
+        );
+        send_report(config, snapshots, access_errors, start.elapsed());
+
+        if let Some(all_searches) = all_searches {
+            config.searches = all_searches;
+        }
     }
+}
 
-    // save snapshot and optionally delete old entries
-    save_snapshot(&mut snapshot, &snapfile, config.global.snapshot_retention);
-    trace!("snapshot = {:#?}", &snapshot);
+// the last modification time of the configuration file
+#[cfg(target_family = "windows")]
+fn config_modified(options: &CliOptions) -> Option<std::time::SystemTime> {
+    std::fs::metadata(&options.config_file)
+        .and_then(|m| m.modified())
+        .ok()
+}
 
-    // teardown
-    if !children_list.is_empty() {
-        info!(
-            "waiting for all processes to finish, nb of children: {}",
-            children_list.len()
-        );
-        wait_children(children_list);
+// the paths of the logfiles whose directory could be watched
+fn watched_paths(config: &Config) -> impl Iterator<Item = &Path> {
+    config
+        .searches
+        .iter()
+        .filter(|search| {
+            let source = &search.logfile.path;
+            !source.is_journal()
+                && !source.is_eventlog()
+                && !source.is_remote()
+                && !source.is_stdin()
+        })
+        .map(|search| search.logfile.path().as_path())
+}
+
+// starts watching the logfiles, or returns None to search them at each interval
+fn watch_logfiles(config: &Config) -> Option<Watcher> {
+    match Watcher::new(watched_paths(config)) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            error!(
+                "unable to watch logfiles, searching them at each interval: {}",
+                e
+            );
+            None
+        }
     }
+}
 
-    // optionally call postscript
-    if config.global.postscript.is_some() {
-        spawn_postscript(&mut config.global.postscript.unwrap(), &prescript_pids);
+/// Sends the report of a run to the report socket, if any. This is not fatal for the plugin output.
+fn send_report(
+    config: &Config,
+    snapshots: &SnapshotList,
+    access_errors: &LogFileAccessErrorList,
+    duration: Duration,
+) {
+    if let Some(report_socket) = &config.global.report_socket {
+        if let Err(e) = report_socket.send(&snapshots.run_report(access_errors, duration)) {
+            error!(
+                "error sending run report to {:?}: {}",
+                report_socket.socket, e
+            );
+        }
     }
+}
 
-    info!(
-        "end of searches, elapsed: {} seconds",
-        now.elapsed().as_secs_f32()
-    );
+/// Saves all snapshots, deleting entries older than the retention time.
+fn save_snapshots(config: &Config, snapshots: &mut SnapshotList) {
+    for (snapfile, snapshot) in snapshots.iter_mut() {
+        save_snapshot(snapshot, snapfile, &config.global);
+        trace!("snapshot = {:#?}", &snapshot);
+    }
+}
 
-    // now we can prepare the global hit counters to exit the relevant Nagios code
-    let exit_code = snapshot.exit_message(&access_errors);
-    Nagios::exit_with(exit_code);
+/// Sends e-mail digests, along with those not sent at the previous run. Those which can't be sent are kept in the
+/// default snapshot, or any other one, to be sent at the next run.
+fn send_digests(default_snapfile: &Path, snapshots: &mut SnapshotList) {
+    let mut digests = Vec::new();
+    for snapshot in snapshots.values_mut() {
+        for digest in snapshot.take_digests() {
+            digest.collect(&mut digests);
+        }
+    }
+    if digests.is_empty() {
+        return;
+    }
+
+    let digests = email::send_digests(digests);
+    if digests.is_empty() {
+        return;
+    }
+    let snapshot = match snapshots.contains_key(default_snapfile) {
+        true => snapshots.get_mut(default_snapfile),
+        false => snapshots.values_mut().next(),
+    };
+    match snapshot {
+        Some(snapshot) => snapshot.keep_digests(digests),
+        None => error!("no snapshot to keep {} e-mail digests", digests.len()),
+    }
 }
 
 /// Manage end of all started processes from clf.
 fn wait_children(children_list: Vec<ChildData>) {
     // just wait a little for all commands to finish. Otherwise, the last process will not be considered to be finished.
-    if !children_list.is_empty() {
+    if !children_list.is_empty() && !signal::shutdown_requested() {
         let wait_timeout = std::time::Duration::from_millis(1000);
         thread::sleep(wait_timeout);
     }
@@ -305,42 +927,50 @@ fn wait_children(children_list: Vec<ChildData>) {
                 status
             ),
 
-            // child has not exited. Spawn a new thread to wait at most the timeout defined
+            // child has not exited. Wait at most the timeout defined
             Ok(None) => {
                 debug!("command has not exited yet, try to wait a little!");
 
-                // now if timeout has not yet occured, start a new thread to wait and kill process ??
                 let elapsed = started_child.start_time.unwrap().elapsed().as_secs();
 
-                // if timeout occured, try to kill anyway ;-)
-                if elapsed > started_child.timeout {
-                    match child.kill() {
-                        Ok(_) => info!("process {} killed", child.id()),
+                // if timeout has not yet occured, we'll wait at least the remaining seconds, except on shutdown
+                if elapsed <= started_child.timeout && !signal::shutdown_requested() {
+                    let secs_to_wait = Duration::from_secs(started_child.timeout - elapsed);
+
+                    match child.wait_timeout(secs_to_wait) {
+                        Ok(Some(status)) => {
+                            debug!("process {} exited with: {}", pid, status);
+                            continue;
+                        }
+                        Ok(None) => (),
                         Err(e) => {
-                            if e.kind() == ErrorKind::InvalidInput {
-                                info!("process {} already killed", child.id());
-                            } else {
-                                info!(
-                                    "error:{} trying to kill process pid:{}, path: {}",
-                                    e,
-                                    pid,
-                                    path.display()
-                                );
-                            }
+                            error!("error:{} waiting for process pid:{}", e, pid);
+                            continue;
                         }
                     }
-                } else {
-                    // we'll wait at least the remaining seconds
-                    let secs_to_wait = Duration::from_secs(started_child.timeout - elapsed);
+                }
 
-                    let _status_code = match child.wait_timeout(secs_to_wait).unwrap() {
-                        Some(status) => status.code(),
-                        None => {
-                            // child hasn't exited yet
-                            child.kill().unwrap();
-                            child.wait().unwrap().code()
+                // child is still running: terminate it, unless it's meant to outlive clf
+                if !started_child.kill_on_exit {
+                    info!("process {} left running, path: {}", pid, path.display());
+                    continue;
+                }
+
+                let grace = Duration::from_secs(DEFAULT_KILL_GRACE_PERIOD);
+                match signal::terminate(&mut child, grace) {
+                    Ok(status) => info!("process {} terminated: {}", pid, status),
+                    Err(e) => {
+                        if e.kind() == ErrorKind::InvalidInput {
+                            info!("process {} already killed", pid);
+                        } else {
+                            info!(
+                                "error:{} trying to kill process pid:{}, path: {}",
+                                e,
+                                pid,
+                                path.display()
+                            );
                         }
-                    };
+                    }
                 }
             }
 
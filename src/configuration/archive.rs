@@ -1,12 +1,25 @@
 //! Contains the configuration of the archiving process of a logfile. We can define here how, where and the naming convention
 //! of an archived file that has been rotated, usually using `logrotate` UNIX process.
 use std::{
+    cmp::Reverse,
     fmt::Debug,
     path::{Path, PathBuf},
 };
 
+use regex::Regex;
 use serde::Deserialize;
 
+use crate::context;
+use crate::misc::error::{AppError, AppResult};
+
+/// File name conventions of rotated logfiles, `{name}` being the file name of the logfile: `file.1` or `file-20230101`,
+/// possibly compressed. The `index` capture group is the rotation number, the lowest being the most recent, while the
+/// `date` one is the rotation date, the highest being the most recent.
+pub const ROTATION_PATTERNS: &[&str] = &[
+    r"^{name}\.(?P<index>\d+)(\.(gz|bz2|xz|zst|lz4))?$",
+    r"^{name}-(?P<date>\d{8,10})(\.(gz|bz2|xz|zst|lz4))?$",
+];
+
 /// This structure keeps everything related to log rotations
 #[derive(Debug, Deserialize, Clone)]
 #[serde(deny_unknown_fields)]
@@ -19,9 +32,66 @@ pub struct LogArchive {
 
     /// a regex pattern to determine which archive to get
     pub pattern: Option<String>,
+
+    /// if `true`, rotated files are found in the archive directory by their name, instead of `extension`
+    #[serde(default)]
+    pub discover: bool,
+
+    /// regexes of the file names of rotated files, instead of the usual conventions. `{name}` is replaced by the file
+    /// name of the logfile
+    pub patterns: Option<Vec<String>>,
 }
 
 impl LogArchive {
+    /// The rotated files of a logfile found in the archive directory, the most recent first.
+    pub fn discover<P: AsRef<Path>>(&self, path: P) -> AppResult<Vec<PathBuf>> {
+        let path = path.as_ref();
+        let dir = match (&self.dir, path.parent()) {
+            (Some(dir), _) => dir,
+            (None, Some(dir)) => dir,
+            (None, None) => return Ok(Vec::new()),
+        };
+        let name = path
+            .file_name()
+            .map_or(String::new(), |n| n.to_string_lossy().to_string());
+
+        let patterns = match &self.patterns {
+            Some(patterns) => patterns.iter().map(String::as_str).collect(),
+            None => ROTATION_PATTERNS.to_vec(),
+        };
+        let mut regexes = Vec::new();
+        for pattern in patterns {
+            let pattern = pattern.replace("{name}", &regex::escape(&name));
+            regexes
+                .push(Regex::new(&pattern).map_err(|e| context!(e, "error in regex {}", pattern))?);
+        }
+
+        // the index or date of each rotated file: a later date and a lower index are more recent
+        let mut archives = Vec::new();
+        let entries = std::fs::read_dir(dir)
+            .map_err(|e| context!(e, "error trying to read files from {:?}", dir))?;
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let caps = match regexes.iter().find_map(|re| re.captures(&file_name)) {
+                Some(caps) => caps,
+                None => continue,
+            };
+            let rank = |group| {
+                caps.name(group)
+                    .and_then(|m| m.as_str().parse::<i64>().ok())
+            };
+            let recency = match (rank("date"), rank("index")) {
+                (Some(date), _) => date,
+                (None, Some(index)) => -index,
+                (None, None) => 0,
+            };
+            archives.push((recency, entry.path()));
+        }
+
+        archives.sort_by_key(|(recency, path)| (Reverse(*recency), path.clone()));
+        Ok(archives.into_iter().map(|(_, path)| path).collect())
+    }
+
     /// When no archive is specified, just get the standard logrotate file name: add .1 at the end of the logfile
     pub fn default_path<P: AsRef<Path> + Clone>(path: P) -> PathBuf {
         // build the file name by appending .1 to its path
@@ -126,6 +196,8 @@ extension: xz
             dir: None,
             extension: None,
             pattern: None,
+            discover: false,
+            patterns: None,
         };
         assert_eq!(
             archive.archived_path(&p),
@@ -167,6 +239,8 @@ extension: gz
             dir: None,
             extension: None,
             pattern: None,
+            discover: false,
+            patterns: None,
         };
         assert_eq!(
             archive.archived_path(&p),
@@ -177,6 +251,8 @@ extension: gz
             dir: Some(PathBuf::from(r"c:\Windows\Temp")),
             extension: None,
             pattern: None,
+            discover: false,
+            patterns: None,
         };
         assert_eq!(
             archive.archived_path(&p),
@@ -187,6 +263,8 @@ extension: gz
             dir: None,
             extension: Some("gz".to_string()),
             pattern: None,
+            discover: false,
+            patterns: None,
         };
         assert_eq!(
             archive.archived_path(&p),
@@ -197,10 +275,60 @@ extension: gz
             dir: Some(PathBuf::from(r"c:\Windows\Temp")),
             extension: Some("gz".to_string()),
             pattern: None,
+            discover: false,
+            patterns: None,
         };
         assert_eq!(
             archive.archived_path(&p),
             PathBuf::from(r"c:\Windows\Temp\WindowsUpdate.log.gz")
         );
     }
+
+    #[test]
+    fn discover() {
+        let dir = std::env::temp_dir().join("clf_archive_discover");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        for name in [
+            "app.log",
+            "app.log.1",
+            "app.log.2.gz",
+            "app.log.10.gz",
+            "app.log-20230101.gz",
+            "app.log-20230102",
+            "app.logger.1",
+            "other.log.1",
+        ] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+
+        let yaml = "discover: true";
+        let archive: LogArchive = serde_yaml::from_str(yaml).expect("unable to read YAML");
+        let archives = archive.discover(dir.join("app.log")).unwrap();
+        let names: Vec<_> = archives
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "app.log-20230102",
+                "app.log-20230101.gz",
+                "app.log.1",
+                "app.log.2.gz",
+                "app.log.10.gz"
+            ]
+        );
+
+        // only the given conventions
+        let yaml = r#"
+discover: true
+patterns: ['^{name}\.(?P<index>\d+)$']
+"#;
+        let archive: LogArchive = serde_yaml::from_str(yaml).expect("unable to read YAML");
+        let archives = archive.discover(dir.join("app.log")).unwrap();
+        assert_eq!(archives, vec![dir.join("app.log.1")]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
@@ -1,32 +1,41 @@
 //! Contains the configuration of what is executed each time a pattern is found in the logfile. It could be either a spawned script, a TCP socket to which send
-//! relevant data, or a Unix Datagram Socket. For the 2 latter cases, found data are sent as a JSON string. Otherwise, when a script is called, data are sent
-//! through environment variables.
+//! relevant data, a Unix Datagram Socket or an HTTP endpoint. For the 3 latter cases, found data are sent as a JSON string. Otherwise, when a script is called,
+//! data are sent through environment variables.
 use std::cell::RefCell;
-use std::convert::TryFrom;
+use std::collections::{BTreeMap, VecDeque};
 use std::fmt::Debug;
+use std::fs::OpenOptions;
 use std::io::Write;
-use std::net::TcpStream;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::{borrow::Cow, time::Duration};
 
 #[cfg(target_family = "unix")]
 use std::os::unix::net::UnixStream;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command};
 use std::time::Instant;
 
-use log::debug;
-use serde::Deserialize;
-use serde_json::json;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
 
-use crate::configuration::vars::{GlobalVars, RuntimeVars};
+use crate::configuration::{
+    email::{Digest, Email},
+    protocol,
+    vars::{GlobalVars, PendingVars, RuntimeVars},
+    wal::Wal,
+    webhook::{self, Endpoint},
+};
 use crate::misc::{
-    error::{AppError, AppResult},
+    error::{AppCustomErrorKind, AppError, AppResult},
+    rundir,
     util::*,
 };
-use crate::{context, fromstr};
+use crate::{context, fromstr, prefix_var};
 
-/// A callback is either a script, or a TCP socket or a UNIX domain socket
+/// A callback is either a script, or a TCP socket or a UNIX domain socket, or an HTTP endpoint, or an e-mail digest
 #[derive(Debug, Deserialize, PartialEq, Hash, Eq, Clone)]
 #[serde(deny_unknown_fields)]
 pub enum CallbackType {
@@ -36,9 +45,15 @@ pub enum CallbackType {
     #[serde(rename = "address")]
     Tcp(Option<String>),
 
+    // always defined, so a configuration file using it on another platform is reported when validated
     #[serde(rename = "domain")]
-    #[cfg(target_family = "unix")]
     Domain(Option<PathBuf>),
+
+    #[serde(rename = "url")]
+    Http(Option<String>),
+
+    #[serde(rename = "email")]
+    Email(Option<Email>),
 }
 
 /// Represent a TCP or UNIX socket
@@ -48,6 +63,22 @@ pub struct CallbackHandle {
     tcp_socket: Option<TcpStream>,
     #[cfg(target_family = "unix")]
     domain_socket: Option<UnixStream>,
+
+    // number of payloads sent through the socket, used as the sequence number of these payloads
+    seq: u64,
+
+    // set when the socket can't be used anymore during this run: payloads are only queued
+    offline: bool,
+
+    // payloads not sent yet, when they're batched
+    pending: PendingBatch,
+
+    // payloads kept in memory while the socket can't be reached, and when to try to connect again
+    held: VecDeque<PendingVars>,
+    reconnect_at: Option<Instant>,
+
+    // matches to be sent by e-mail at the end of the run
+    digest: Option<Digest>,
 }
 
 /// A fake implementation because TcpStream etc don't implement Clone
@@ -58,7 +89,105 @@ impl Clone for CallbackHandle {
             tcp_socket: None,
             #[cfg(target_family = "unix")]
             domain_socket: None,
+            seq: 0,
+            offline: false,
+            pending: PendingBatch::default(),
+            held: VecDeque::new(),
+            reconnect_at: None,
+            digest: None,
+        }
+    }
+}
+
+impl CallbackHandle {
+    /// Takes the matches for an e-mail callback, to be added to the digests of the run.
+    pub fn take_digest(&mut self) -> Option<Digest> {
+        self.digest.take()
+    }
+
+    /// Sends the end-of-run message through the socket, if any was opened. It holds the number of payloads sent
+    /// through this connection, so the receiver can check none was lost.
+    pub fn end_of_run(&mut self) -> AppResult<()> {
+        if !self.held.is_empty() {
+            warn!(
+                "socket still unreachable, {} payloads kept in memory are lost",
+                self.held.len()
+            );
+            self.held.clear();
+        }
+
+        let json = protocol::end_of_run(self.seq + 1, self.seq).to_string();
+
+        // with batches, it's sent along with the payloads not sent yet
+        let json = if self.pending.enabled {
+            self.pending.messages.push(json);
+            let frame = protocol::batch(&self.pending.messages);
+            self.pending.clear();
+            frame
+        } else {
+            json
+        };
+
+        if let Some(stream) = &self.tcp_socket {
+            write_payload(stream, json.clone(), "TCP socket")?;
+            self.seq += 1;
         }
+        #[cfg(target_family = "unix")]
+        if let Some(stream) = &self.domain_socket {
+            write_payload(stream, json, "UNIX socket")?;
+            self.seq += 1;
+        }
+
+        Ok(())
+    }
+}
+
+// payloads waiting to be sent in a single frame
+#[derive(Debug, Default)]
+struct PendingBatch {
+    enabled: bool,
+    messages: Vec<String>,
+    since: Option<Instant>,
+}
+
+impl PendingBatch {
+    fn clear(&mut self) {
+        self.messages.clear();
+        self.since = None;
+    }
+
+    // writes the pending payloads as a batch, if any
+    #[cfg(feature = "sockets")]
+    fn flush<T: Write, U: Debug>(&mut self, stream: T, addr: U) -> AppResult<()> {
+        if self.messages.is_empty() {
+            return Ok(());
+        }
+        let frame = protocol::batch(&self.messages);
+        self.clear();
+        write_payload(stream, frame, addr)
+    }
+}
+
+/// Coalescing of TCP or UNIX socket payloads: several payloads are sent in a single frame, which is written once it
+/// would be larger than `max_size` bytes, or once its first payload is older than `max_delay` milliseconds when a
+/// new payload is added. Payloads not sent yet are always written at the end of the search.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Batch {
+    #[serde(default = "Batch::default_max_size")]
+    pub max_size: usize,
+
+    #[serde(default = "Batch::default_max_delay")]
+    pub max_delay: u64,
+}
+
+impl Batch {
+    fn default_max_size() -> usize {
+        DEFAULT_BATCH_MAX_SIZE
+    }
+
+    fn default_max_delay() -> u64 {
+        DEFAULT_BATCH_MAX_DELAY
     }
 }
 
@@ -75,6 +204,48 @@ pub struct Callback {
     /// A timeout in seconds to for wait command completion.
     #[serde(default = "Callback::default_timeout")]
     timeout: u64,
+
+    /// A timeout in seconds to connect to each address of a TCP callback. 0 means the system default.
+    #[serde(default = "Callback::default_connect_timeout")]
+    connect_timeout: u64,
+
+    /// Whether a script still running when clf exits is terminated, or left running.
+    #[serde(default = "Callback::default_kill_on_exit")]
+    pub kill_on_exit: bool,
+
+    /// An optional local queue where TCP or UNIX socket payloads are written before being sent, so they're not lost
+    /// if they can't be delivered.
+    pub queue: Option<Wal>,
+
+    /// Optional coalescing of TCP or UNIX socket payloads into batches.
+    pub batch: Option<Batch>,
+
+    /// Headers added to the requests of an HTTP callback, like `Authorization`.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+
+    /// Number of times the request of an HTTP callback is sent again when it fails, or the connection to a TCP or
+    /// UNIX socket is tried again.
+    #[serde(default = "Callback::default_retries")]
+    pub retries: u32,
+
+    /// Time in milliseconds before the first retry, doubled at each retry.
+    #[serde(default = "Callback::default_retry_delay")]
+    pub retry_delay: u64,
+
+    /// Number of TCP or UNIX socket payloads kept in memory while the socket can't be reached, so the search goes on
+    /// until it's reachable again. 0 means a socket error is a callback error.
+    #[serde(default)]
+    pub memory_queue: usize,
+
+    /// Whether a script inherits the environment of clf. If not, it only gets the variables of `env_allowlist`, along
+    /// with those set by clf.
+    #[serde(default = "Callback::default_inherit_env")]
+    pub inherit_env: bool,
+
+    /// Names of the environment variables inherited by a script when `inherit_env` is false.
+    #[serde(default)]
+    pub env_allowlist: Vec<String>,
 }
 
 impl Callback {
@@ -83,6 +254,31 @@ impl Callback {
         DEFAULT_WRITE_TIMEOUT
     }
 
+    /// Default timeout in seconds when connecting to a TCP address
+    fn default_connect_timeout() -> u64 {
+        DEFAULT_CONNECT_TIMEOUT
+    }
+
+    /// By default, scripts are terminated when clf exits
+    fn default_kill_on_exit() -> bool {
+        true
+    }
+
+    /// Default number of retries of an HTTP request or a socket connection
+    fn default_retries() -> u32 {
+        DEFAULT_CALLBACK_RETRIES
+    }
+
+    /// Default time in milliseconds before the first retry
+    fn default_retry_delay() -> u64 {
+        DEFAULT_RETRY_DELAY
+    }
+
+    /// By default, scripts inherit the whole environment
+    fn default_inherit_env() -> bool {
+        true
+    }
+
     /// Calls the relevant callback with arguments
     pub fn call(
         &self,
@@ -109,6 +305,16 @@ impl Callback {
 
                 let mut cmd = Command::new(path.as_ref().unwrap());
 
+                // start from a controlled environment, so secrets like proxy credentials don't leak to scripts
+                if !self.inherit_env {
+                    cmd.env_clear();
+                    for name in &self.env_allowlist {
+                        if let Some(value) = std::env::var_os(name) {
+                            cmd.env(name, value);
+                        }
+                    }
+                }
+
                 // user vars don't change so we can add them right now
                 if global_vars.len() != 0 {
                     cmd.envs(global_vars);
@@ -122,8 +328,20 @@ impl Callback {
                 //handle.cmd = Some(cmd);
                 debug!("creating Command for: {:?}", path.as_ref().unwrap());
 
-                // runtime variables are always there.
+                // files given to the script are written to the directory of this run
+                let run_dir = rundir::create()?;
+                cmd.env(prefix_var!("RUN_DIR"), run_dir);
+
+                // runtime variables are always there. Raw bytes of the line can't be held by an environment variable,
+                // so they're written to a file given in CLF_LINE_RAW_FILE
+                let mut raw_file = None;
                 for (var, value) in runtime_vars.inner() {
+                    if var == prefix_var!("LINE_RAW") {
+                        let file = write_raw_line(run_dir, &value.to_string())?;
+                        cmd.env(prefix_var!("LINE_RAW_FILE"), &file);
+                        raw_file = Some(file);
+                        continue;
+                    }
                     match var {
                         Cow::Borrowed(s) => cmd.env(s, value.to_string()),
                         Cow::Owned(s) => cmd.env(s, value.to_string()),
@@ -146,19 +364,36 @@ impl Callback {
                     path: path.as_ref().unwrap().clone(),
                     timeout: self.timeout,
                     start_time: Some(Instant::now()),
+                    kill_on_exit: self.kill_on_exit,
+                    raw_file,
                 }))
             }
+            #[cfg(feature = "sockets")]
             CallbackType::Tcp(address) => {
                 debug_assert!(address.is_some());
                 let addr = address.as_ref().unwrap();
 
+                // with a queue, the payload is written to it before any attempt to send it
+                if let Some(queue) = &self.queue {
+                    queue.append(runtime_vars)?;
+                }
+
                 // this is to control to send globals only once
                 let mut first_time = false;
 
                 // test whether a TCP socket is already created
                 if handle.tcp_socket.is_none() {
-                    let stream = TcpStream::connect(addr)
-                        .map_err(|e| context!(e, "unable to connect to TCP address: {}", addr))?;
+                    if handle.offline {
+                        return Ok(None);
+                    }
+                    if self.is_held(handle, runtime_vars) {
+                        return Ok(None);
+                    }
+                    let stream =
+                        match self.with_retries(handle, || connect(addr, self.connect_timeout)) {
+                            Ok(stream) => stream,
+                            Err(e) => return self.go_offline(handle, e, runtime_vars),
+                        };
 
                     // set timeout for write operations
                     let write_timeout = Duration::new(self.timeout, 0);
@@ -168,35 +403,59 @@ impl Callback {
 
                     // save socket
                     handle.tcp_socket = Some(stream);
+                    handle.reconnect_at = None;
                     debug!("creating TCP socket for: {}", address.as_ref().unwrap());
 
+                    handle.pending.enabled = self.batch.is_some();
                     first_time = true;
                 }
 
                 // send JSON data through TCP socket
                 let stream = handle.tcp_socket.as_ref().unwrap();
-                send_json_data(
-                    &self.args,
+                if let Err(e) = self.send(
                     stream,
                     global_vars,
                     runtime_vars,
                     first_time,
+                    &mut handle.seq,
+                    &mut handle.pending,
+                    &mut handle.held,
                     addr,
-                )
+                ) {
+                    return self.go_offline(handle, e, runtime_vars);
+                }
+
+                Ok(None)
             }
-            #[cfg(target_family = "unix")]
+            #[cfg(all(feature = "sockets", target_family = "unix"))]
             CallbackType::Domain(address) => {
                 debug_assert!(address.is_some());
                 let addr = address.as_ref().unwrap();
 
+                // with a queue, the payload is written to it before any attempt to send it
+                if let Some(queue) = &self.queue {
+                    queue.append(runtime_vars)?;
+                }
+
                 // this is to control to send globals only once
                 let mut first_time = false;
 
                 // test whether a UNIX socket is already created
                 if handle.domain_socket.is_none() {
-                    let stream = UnixStream::connect(address.as_ref().unwrap()).map_err(|e| {
-                        context!(e, "unable to connect to UNIX socket address: {:?}", addr)
-                    })?;
+                    if handle.offline {
+                        return Ok(None);
+                    }
+                    if self.is_held(handle, runtime_vars) {
+                        return Ok(None);
+                    }
+                    let stream = match self.with_retries(handle, || {
+                        UnixStream::connect(addr).map_err(|e| {
+                            context!(e, "unable to connect to UNIX socket address: {:?}", addr)
+                        })
+                    }) {
+                        Ok(stream) => stream,
+                        Err(e) => return self.go_offline(handle, e, runtime_vars),
+                    };
 
                     // set timeout for write operations
                     let write_timeout = Duration::new(self.timeout, 0);
@@ -205,21 +464,415 @@ impl Callback {
                         .map_err(|e| context!(e, "unable to set socket timeout: {:?}", addr))?;
 
                     handle.domain_socket = Some(stream);
+                    handle.reconnect_at = None;
                     debug!("creating UNIX socket for: {:?}", address.as_ref().unwrap());
 
+                    handle.pending.enabled = self.batch.is_some();
                     first_time = true;
                 }
 
                 // send JSON data through UNIX socket
                 let stream = handle.domain_socket.as_ref().unwrap();
-                send_json_data(
-                    &self.args,
+                if let Err(e) = self.send(
                     stream,
                     global_vars,
                     runtime_vars,
                     first_time,
+                    &mut handle.seq,
+                    &mut handle.pending,
+                    &mut handle.held,
                     addr,
-                )
+                ) {
+                    return self.go_offline(handle, e, runtime_vars);
+                }
+
+                Ok(None)
+            }
+            #[cfg(all(feature = "sockets", not(target_family = "unix")))]
+            CallbackType::Domain(address) => Err(AppError::new_custom(
+                AppCustomErrorKind::UnsupportedOnPlatform,
+                &format!("UNIX domain socket {:?} is not supported", address),
+            )),
+            #[cfg(not(feature = "sockets"))]
+            CallbackType::Tcp(_) | CallbackType::Domain(_) => Err(AppError::new_custom(
+                AppCustomErrorKind::NotBuiltIn,
+                &format!(
+                    "socket callback {:?} is not built into this binary",
+                    self.callback
+                ),
+            )),
+            CallbackType::Http(url) => {
+                debug_assert!(url.is_some());
+                let endpoint = Endpoint::from_url(url.as_ref().unwrap())?;
+
+                // with a queue, the payload is written to it before any attempt to send it
+                if let Some(queue) = &self.queue {
+                    queue.append(runtime_vars)?;
+                }
+                if handle.offline {
+                    return Ok(None);
+                }
+
+                if let Err(e) = self.post(&endpoint, global_vars, runtime_vars, &mut handle.seq) {
+                    return self.go_offline(handle, e, runtime_vars);
+                }
+
+                Ok(None)
+            }
+            CallbackType::Email(email) => {
+                debug_assert!(email.is_some());
+
+                // matches are only sent once all logfiles are searched
+                handle
+                    .digest
+                    .get_or_insert_with(|| {
+                        Digest::new(email.as_ref().unwrap(), self.connect_timeout, self.timeout)
+                    })
+                    .push(runtime_vars);
+
+                Ok(None)
+            }
+        }
+    }
+
+    /// Sends the payloads left in the queue by previous runs, before any logfile is searched, so they're delivered even
+    /// if no new line matches. Returns the number of payloads sent. They're kept in the queue if the receiver still
+    /// can't be reached.
+    pub fn drain_queue(&self, global_vars: &GlobalVars) -> AppResult<usize> {
+        let queued = match &self.queue {
+            Some(queue) => queue.entries()?.len(),
+            None => return Ok(0),
+        };
+        if queued == 0 {
+            return Ok(0);
+        }
+
+        // all queued payloads are sent along with the first payload of a call
+        let mut handle = CallbackHandle::default();
+        let vars = RuntimeVars::default();
+        match &self.callback {
+            #[cfg(feature = "sockets")]
+            CallbackType::Tcp(address) => {
+                debug_assert!(address.is_some());
+                let addr = address.as_ref().unwrap();
+                let stream = self.with_retries(&handle, || connect(addr, self.connect_timeout))?;
+                stream
+                    .set_write_timeout(Some(Duration::new(self.timeout, 0)))
+                    .map_err(|e| context!(e, "unable to set socket timeout: {}", addr))?;
+
+                handle.pending.enabled = self.batch.is_some();
+                self.send(
+                    &stream,
+                    global_vars,
+                    &vars,
+                    true,
+                    &mut handle.seq,
+                    &mut handle.pending,
+                    &mut handle.held,
+                    addr,
+                )?;
+                handle.tcp_socket = Some(stream);
+            }
+            #[cfg(all(feature = "sockets", target_family = "unix"))]
+            CallbackType::Domain(address) => {
+                debug_assert!(address.is_some());
+                let addr = address.as_ref().unwrap();
+                let stream = self.with_retries(&handle, || {
+                    UnixStream::connect(addr).map_err(|e| {
+                        context!(e, "unable to connect to UNIX socket address: {:?}", addr)
+                    })
+                })?;
+                stream
+                    .set_write_timeout(Some(Duration::new(self.timeout, 0)))
+                    .map_err(|e| context!(e, "unable to set socket timeout: {:?}", addr))?;
+
+                handle.pending.enabled = self.batch.is_some();
+                self.send(
+                    &stream,
+                    global_vars,
+                    &vars,
+                    true,
+                    &mut handle.seq,
+                    &mut handle.pending,
+                    &mut handle.held,
+                    addr,
+                )?;
+                handle.domain_socket = Some(stream);
+            }
+            CallbackType::Http(url) => {
+                debug_assert!(url.is_some());
+                let endpoint = Endpoint::from_url(url.as_ref().unwrap())?;
+                self.post(&endpoint, global_vars, &vars, &mut handle.seq)?;
+            }
+            _ => return Ok(0),
+        }
+
+        handle.end_of_run()?;
+        Ok(queued)
+    }
+
+    // posts the payload to the HTTP endpoint. Each request is independent, so it holds the arguments and globals
+    // too. Like sockets, all queued payloads are sent when there's a queue
+    fn post(
+        &self,
+        endpoint: &Endpoint,
+        global_vars: &GlobalVars,
+        runtime_vars: &RuntimeVars,
+        seq: &mut u64,
+    ) -> AppResult<()> {
+        match &self.queue {
+            None => {
+                self.post_payload(endpoint, global_vars, runtime_vars, *seq + 1)?;
+                *seq += 1;
+            }
+            Some(queue) => {
+                for vars in queue.entries()? {
+                    self.post_payload(endpoint, global_vars, &vars, *seq + 1)?;
+                    *seq += 1;
+                }
+                queue.clear()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn post_payload<V: Serialize>(
+        &self,
+        endpoint: &Endpoint,
+        global_vars: &GlobalVars,
+        vars: &V,
+        seq: u64,
+    ) -> AppResult<()> {
+        let json = protocol::payload(seq, &self.args, global_vars, vars, true, false);
+
+        // the receiver can discard a match delivered twice, unless the header is already set by the user
+        let mut headers = Cow::Borrowed(&self.headers);
+        if let Some(key) = json["vars"]["CLF_IDEMPOTENCY_KEY"].as_str() {
+            if !self.headers.contains_key("Idempotency-Key") {
+                headers
+                    .to_mut()
+                    .insert("Idempotency-Key".to_string(), key.to_string());
+            }
+        }
+
+        webhook::post(
+            endpoint,
+            &headers,
+            &json.to_string(),
+            self.connect_timeout,
+            self.timeout,
+            self.retries,
+            self.retry_delay,
+        )
+    }
+
+    // sends the payload through the socket. With a queue, the payload was already queued and all queued payloads are
+    // sent, those not delivered by previous runs coming first. The queue is emptied once they're all delivered, so a
+    // batch holding queued payloads is written right away. Otherwise, payloads held in memory come first
+    #[cfg(feature = "sockets")]
+    #[allow(clippy::too_many_arguments)]
+    fn send<T: Write + Copy, U: Debug + Copy>(
+        &self,
+        stream: T,
+        global_vars: &GlobalVars,
+        runtime_vars: &RuntimeVars,
+        mut first_time: bool,
+        seq: &mut u64,
+        pending: &mut PendingBatch,
+        held: &mut VecDeque<PendingVars>,
+        addr: U,
+    ) -> AppResult<()> {
+        let batched = self.batch.is_some();
+
+        match &self.queue {
+            None => {
+                while let Some(vars) = held.front() {
+                    let json = protocol::payload(
+                        *seq + 1,
+                        &self.args,
+                        global_vars,
+                        vars,
+                        first_time,
+                        batched,
+                    );
+                    self.write(stream, json.to_string(), pending, addr)?;
+                    held.pop_front();
+                    *seq += 1;
+                    first_time = false;
+                }
+
+                let json = protocol::payload(
+                    *seq + 1,
+                    &self.args,
+                    global_vars,
+                    runtime_vars,
+                    first_time,
+                    batched,
+                );
+                self.write(stream, json.to_string(), pending, addr)?;
+                *seq += 1;
+            }
+            Some(queue) => {
+                for (i, vars) in queue.entries()?.iter().enumerate() {
+                    let json = protocol::payload(
+                        *seq + 1,
+                        &self.args,
+                        global_vars,
+                        vars,
+                        first_time && i == 0,
+                        batched,
+                    );
+                    self.write(stream, json.to_string(), pending, addr)?;
+                    *seq += 1;
+                }
+                pending.flush(stream, addr)?;
+                queue.clear()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // writes a payload, or adds it to the pending batch which is written once full or old enough
+    #[cfg(feature = "sockets")]
+    fn write<T: Write + Copy, U: Debug + Copy>(
+        &self,
+        stream: T,
+        json: String,
+        pending: &mut PendingBatch,
+        addr: U,
+    ) -> AppResult<()> {
+        let batch = match &self.batch {
+            Some(batch) => batch,
+            None => return write_payload(stream, json, addr),
+        };
+        let max_size = batch.max_size.min(protocol::MAX_MESSAGE_SIZE);
+
+        // the batch would be too large with this payload
+        if !pending.messages.is_empty()
+            && protocol::batch_size(&pending.messages) + json.len() + 1 > max_size
+        {
+            pending.flush(stream, addr)?;
+        }
+
+        pending.messages.push(json);
+        let since = *pending.since.get_or_insert_with(Instant::now);
+
+        if protocol::batch_size(&pending.messages) >= max_size
+            || since.elapsed() >= Duration::from_millis(batch.max_delay)
+        {
+            pending.flush(stream, addr)?;
+        }
+
+        Ok(())
+    }
+
+    // connects to the socket, trying again up to `retries` times with an exponential backoff. Once payloads are held in
+    // memory, a single attempt is made so the search is not slowed down
+    #[cfg(feature = "sockets")]
+    fn with_retries<S>(
+        &self,
+        handle: &CallbackHandle,
+        connect: impl Fn() -> AppResult<S>,
+    ) -> AppResult<S> {
+        let retries = if handle.reconnect_at.is_some() {
+            0
+        } else {
+            self.retries
+        };
+
+        let mut attempt = 0;
+        loop {
+            match connect() {
+                Ok(stream) => {
+                    if handle.reconnect_at.is_some() {
+                        info!(
+                            "socket reachable again, sending {} payloads kept in memory",
+                            handle.held.len()
+                        );
+                    }
+                    return Ok(stream);
+                }
+                Err(e) if attempt == retries => return Err(e),
+                Err(e) => {
+                    warn!("{}, retrying ({}/{})", e, attempt + 1, retries);
+                    std::thread::sleep(backoff(self.retry_delay, attempt));
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    // whether the payload is held in memory, as the socket was unreachable and it's not time to connect again yet
+    #[cfg(feature = "sockets")]
+    fn is_held(&self, handle: &mut CallbackHandle, runtime_vars: &RuntimeVars) -> bool {
+        match handle.reconnect_at {
+            Some(reconnect_at) if Instant::now() < reconnect_at => {
+                self.hold(handle, runtime_vars);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // keeps the payload in memory, the oldest one being dropped when the memory queue is full
+    fn hold(&self, handle: &mut CallbackHandle, runtime_vars: &RuntimeVars) {
+        handle.held.push_back(runtime_vars.to_pending());
+        if handle.held.len() > self.memory_queue {
+            handle.held.pop_front();
+            warn!(
+                "memory queue of {} payloads is full, oldest payload dropped",
+                self.memory_queue
+            );
+        }
+    }
+
+    // without a queue, a socket error is a callback error. With a queue, the socket is not used anymore for this run
+    // and payloads are kept in the queue until the next run. With a memory queue, payloads are kept in memory and the
+    // socket is connected again later
+    fn go_offline(
+        &self,
+        handle: &mut CallbackHandle,
+        e: AppError,
+        runtime_vars: &RuntimeVars,
+    ) -> AppResult<Option<ChildData>> {
+        match &self.queue {
+            None if self.memory_queue != 0 && !matches!(self.callback, CallbackType::Http(_)) => {
+                if handle.reconnect_at.is_none() {
+                    warn!("{}, payloads are kept in memory until it's reachable", e);
+                }
+                handle.tcp_socket = None;
+                #[cfg(target_family = "unix")]
+                {
+                    handle.domain_socket = None;
+                }
+                if !handle.pending.messages.is_empty() {
+                    warn!(
+                        "{} batched payloads not sent are lost",
+                        handle.pending.messages.len()
+                    );
+                    handle.pending.clear();
+                }
+                self.hold(handle, runtime_vars);
+                handle.reconnect_at =
+                    Some(Instant::now() + backoff(self.retry_delay, self.retries));
+                Ok(None)
+            }
+            None => Err(e),
+            Some(queue) => {
+                warn!(
+                    "{}, payloads are kept in queue {:?} until next run",
+                    e, queue.path
+                );
+                handle.tcp_socket = None;
+                #[cfg(target_family = "unix")]
+                {
+                    handle.domain_socket = None;
+                }
+                handle.offline = true;
+                handle.pending.clear();
+                Ok(None)
             }
         }
     }
@@ -228,65 +881,136 @@ impl Callback {
 // Auto-implement FromStr
 fromstr!(Callback);
 
-// send data through Tcp or Unix stream
-fn send_json_data<T: Write, U: Debug>(
-    args: &Option<Vec<String>>,
-    mut stream: T,
-    global_vars: &GlobalVars,
-    runtime_vars: &RuntimeVars,
-    first_time: bool,
-    addr: U,
-) -> AppResult<Option<ChildData>> {
-    // create a dedicated JSON structure
-    let mut json = match args {
-        Some(args) => {
-            if first_time {
-                json!({
-                    "args": &args,
-                    "global": global_vars,
-                    "vars": runtime_vars
-                })
-            } else {
-                json!({
-                    //"args": &args,
-                    "vars": runtime_vars
-                })
+/// Resolves a TCP callback address, which is either an IPv4 or a bracketed IPv6 address with a port like
+/// `127.0.0.1:8900` or `[::1]:8900`, or a hostname with a port like `localhost:8900`.
+fn resolve_address(address: &str) -> AppResult<Vec<SocketAddr>> {
+    // literal addresses don't need any resolution
+    if let Ok(addr) = address.parse::<SocketAddr>() {
+        return Ok(vec![addr]);
+    }
+
+    let (host, port) = address.rsplit_once(':').ok_or_else(|| {
+        AppError::new_custom(
+            AppCustomErrorKind::InvalidAddress,
+            &format!("port is missing in TCP address: {}", address),
+        )
+    })?;
+
+    if host.contains(':') {
+        return Err(AppError::new_custom(
+            AppCustomErrorKind::InvalidAddress,
+            &format!(
+                "IPv6 addresses must be enclosed in brackets like [::1]:8900, TCP address: {}",
+                address
+            ),
+        ));
+    }
+
+    let port: u16 = port
+        .parse()
+        .map_err(|e| context!(e, "invalid port in TCP address: {}", address))?;
+
+    let addrs: Vec<_> = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| context!(e, "unable to resolve TCP address: {}", address))?
+        .collect();
+
+    if addrs.is_empty() {
+        return Err(AppError::new_custom(
+            AppCustomErrorKind::InvalidAddress,
+            &format!("no IP address found for TCP address: {}", address),
+        ));
+    }
+
+    Ok(interleave_families(addrs))
+}
+
+/// Orders addresses by alternating IPv6 and IPv4, starting with the family of the first address, as described
+/// in the Happy Eyeballs RFC 8305. So an unreachable family doesn't delay the connection for each of its addresses.
+fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_is_ipv6 = addrs.first().is_some_and(|x| x.is_ipv6());
+    let (preferred, others): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|x| x.is_ipv6() == first_is_ipv6);
+
+    let mut ordered = Vec::with_capacity(preferred.len() + others.len());
+    let mut preferred = preferred.into_iter();
+    let mut others = others.into_iter();
+    loop {
+        match (preferred.next(), others.next()) {
+            (None, None) => break,
+            (a, b) => {
+                ordered.extend(a);
+                ordered.extend(b);
             }
         }
-        None => {
-            if first_time {
-                json!({
-                    "global": global_vars,
-                    "vars": runtime_vars
-                })
-            } else {
-                json!({ "vars": runtime_vars })
+    }
+
+    ordered
+}
+
+/// Connects to the first reachable address of a TCP callback, each address being tried at most `timeout` seconds.
+pub fn connect(address: &str, timeout: u64) -> AppResult<TcpStream> {
+    let addrs = resolve_address(address)?;
+
+    let mut last_error = None;
+    for addr in &addrs {
+        let stream = if timeout == 0 {
+            TcpStream::connect(addr)
+        } else {
+            TcpStream::connect_timeout(addr, Duration::from_secs(timeout))
+        };
+
+        match stream {
+            Ok(stream) => {
+                debug!("connected to {} for TCP address: {}", addr, address);
+                return Ok(stream);
+            }
+            Err(e) => {
+                debug!("unable to connect to {}: {}", addr, e);
+                last_error = Some(e);
             }
         }
     }
-    .to_string();
 
-    // 64KB a payload is more than enough
-    json.truncate(u16::MAX as usize);
-    let json_raw = json.as_bytes();
+    // at least one address was tried
+    let e = last_error.unwrap();
+    Err(context!(
+        e,
+        "unable to connect to TCP address: {}, tried: {:?}",
+        address,
+        addrs
+    ))
+}
 
-    // send data length first in network order, and then send payload
-    let size = u16::try_from(json_raw.len())
-        .unwrap_or_else(|_| panic!("unexpected conversion error at {}-{}", file!(), line!()));
+// writes the raw bytes of a line, given as base64, to a new file only readable by the current user
+fn write_raw_line(run_dir: &Path, base64: &str) -> AppResult<PathBuf> {
+    static SEQ: AtomicU64 = AtomicU64::new(0);
 
-    stream.write(&size.to_be_bytes()).map_err(|e| {
-        context!(
-            e,
-            "error writing payload size: {} to address: {:?}",
-            size,
-            addr
+    let bytes = STANDARD.decode(base64).map_err(|e| {
+        AppError::new_custom(
+            AppCustomErrorKind::InvalidRawLine,
+            &format!("raw line is not valid base64: {}", e),
         )
     })?;
-    stream
-        .write(&json.as_bytes())
-        .map_err(|e| context!(e, "error writing JSON data to Domain socket: {:?}", addr))?;
+    let path = run_dir.join(format!("line_{}.raw", SEQ.fetch_add(1, Ordering::Relaxed)));
 
-    Ok(None)
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(target_family = "unix")]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(&path)
+        .and_then(|mut file| file.write_all(&bytes))
+        .map_err(|e| context!(e, "unable to write raw line to {:?}", path))?;
+
+    Ok(path)
+}
+
+// write a JSON string prefixed by its length
+fn write_payload<T: Write, U: Debug>(stream: T, json: String, addr: U) -> AppResult<()> {
+    protocol::write_frame(stream, &json)
+        .map_err(|e| context!(e, "error writing JSON data to address: {:?}", addr))
 }
 
 /// Return structure from a call to a script. Gathers all relevant data, instead of a mere tuple.
@@ -296,6 +1020,21 @@ pub struct ChildData {
     pub path: PathBuf,
     pub timeout: u64,
     pub start_time: Option<Instant>,
+    pub kill_on_exit: bool,
+
+    /// file holding the raw bytes of the line given to the script, with the `rawline` option
+    pub raw_file: Option<PathBuf>,
+}
+
+// the raw line file is deleted once the script has exited, and left to a script still running
+impl Drop for ChildData {
+    fn drop(&mut self) {
+        if let (Some(file), Some(child)) = (&self.raw_file, &self.child) {
+            if matches!(child.borrow_mut().try_wait(), Ok(Some(_))) {
+                let _ = std::fs::remove_file(file);
+            }
+        }
+    }
 }
 
 impl ChildData {
@@ -335,34 +1074,41 @@ pub mod tests {
 
     use crate::configuration::vars::VarType;
 
+    #[cfg_attr(not(feature = "sockets"), allow(dead_code))]
     #[derive(Debug, Deserialize)]
     struct JSONStream {
+        pub seq: u64,
+        #[serde(default)]
         pub args: Vec<String>,
+        #[serde(default)]
         pub vars: std::collections::HashMap<String, VarType<String>>,
+        pub end_of_run: Option<EndOfRun>,
+    }
+
+    #[cfg_attr(not(feature = "sockets"), allow(dead_code))]
+    #[derive(Debug, Deserialize)]
+    struct EndOfRun {
+        pub sent: u64,
     }
 
     // utility fn to receive JSON from a stream
+    #[cfg_attr(not(feature = "sockets"), allow(dead_code))]
     fn get_json_from_stream<T: std::io::Read>(socket: &mut T) -> Result<JSONStream> {
-        // try to read size first
-        let mut size_buffer = [0; std::mem::size_of::<u16>()];
-        let bytes_read = socket.read(&mut size_buffer)?;
-        //dbg!(bytes_read);
-        if bytes_read == 0 {
-            return Err(Error::new(ErrorKind::Interrupted, "socket closed"));
-        }
-
-        let json_size = u16::from_be_bytes(size_buffer);
-
-        // read JSON raw data
-        let mut json_buffer = vec![0; json_size as usize];
-        socket.read_exact(&mut json_buffer).unwrap();
-
-        // get JSON
-        let s = std::str::from_utf8(&json_buffer).unwrap();
-        //println!("s={}", s);
+        let json = protocol::read_frame(socket)?
+            .ok_or_else(|| Error::new(ErrorKind::Interrupted, "socket closed"))?;
+        Ok(serde_json::from_slice(&json).unwrap())
+    }
 
-        let json: JSONStream = serde_json::from_str(&s).unwrap();
-        Ok(json)
+    // utility fn to receive all JSON messages of a frame, which could be a batch
+    #[cfg_attr(not(feature = "sockets"), allow(dead_code))]
+    fn get_jsons_from_stream<T: std::io::Read>(socket: &mut T) -> Result<Vec<JSONStream>> {
+        let json = protocol::read_frame(socket)?
+            .ok_or_else(|| Error::new(ErrorKind::Interrupted, "socket closed"))?;
+        let mut frame: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        match frame.get_mut("batch") {
+            Some(batch) => Ok(serde_json::from_value(batch.take()).unwrap()),
+            None => Ok(vec![serde_json::from_value(frame).unwrap()]),
+        }
     }
 
     #[test]
@@ -402,6 +1148,62 @@ pub mod tests {
     }
 
     #[test]
+    #[cfg(target_family = "unix")]
+    fn callback_env() {
+        assert!(Callback::from_str("script: /bin/true").unwrap().inherit_env);
+
+        // HOME is not inherited, unlike PATH
+        let yaml = r#"
+            script: /bin/sh
+            args: ['-c', 'test -z "$HOME" && test -n "$PATH" && test "$CLF_LINE" = "line"']
+            inherit_env: false
+            env_allowlist: [PATH]
+        "#;
+        let cb = Callback::from_str(yaml).expect("unable to read YAML");
+        assert_eq!(cb.env_allowlist, vec!["PATH"]);
+
+        let mut vars = RuntimeVars::default();
+        vars.insert_runtime_var("CLF_LINE", "line");
+
+        let mut handle = CallbackHandle::default();
+        let mut child_data = cb
+            .call(None, &GlobalVars::default(), &vars, &mut handle)
+            .unwrap()
+            .unwrap();
+        assert_eq!(child_data.exit_code().unwrap(), Some(0));
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn callback_rawline() {
+        // the raw bytes are only given through the file
+        let yaml = r#"
+            script: /bin/sh
+            args: ['-c', 'test -z "$CLF_LINE_RAW" && test -d "$CLF_RUN_DIR" && printf "caf\\351" | cmp -s - "$CLF_LINE_RAW_FILE"']
+        "#;
+        let cb = Callback::from_str(yaml).expect("unable to read YAML");
+
+        let mut vars = RuntimeVars::default();
+        vars.insert_runtime_var("CLF_LINE", "caf\u{FFFD}");
+        vars.insert_runtime_var("CLF_LINE_RAW", "Y2Fm6Q==");
+
+        let mut handle = CallbackHandle::default();
+        let mut child_data = cb
+            .call(None, &GlobalVars::default(), &vars, &mut handle)
+            .unwrap()
+            .unwrap();
+        assert_eq!(child_data.exit_code().unwrap(), Some(0));
+
+        // the file is deleted once the script has exited
+        let raw_file = child_data.raw_file.clone().unwrap();
+        assert!(raw_file.exists());
+        assert!(raw_file.starts_with(rundir::create().unwrap()));
+        drop(child_data);
+        assert!(!raw_file.exists());
+    }
+
+    #[test]
+    #[cfg(feature = "sockets")]
     fn callback_tcp() {
         let yaml = r#"
             address: 127.0.0.1:8900
@@ -438,6 +1240,18 @@ pub mod tests {
                             json.vars.get("CLF_CG_LASTNAME").unwrap(),
                             &VarType::from("kennedy")
                         );
+                        assert_eq!(json.seq, 1);
+                        assert!(json.end_of_run.is_none());
+
+                        let json = get_json_from_stream(&mut socket)
+                            .expect("unable to get JSON data from stream");
+                        assert_eq!(json.seq, 2);
+
+                        // the end-of-run message closes the connection
+                        let json = get_json_from_stream(&mut socket)
+                            .expect("unable to get JSON data from stream");
+                        assert_eq!(json.seq, 3);
+                        assert_eq!(json.end_of_run.unwrap().sent, 2);
                     }
                     Err(e) => panic!("couldn't get client: {:?}", e),
                 }
@@ -457,16 +1271,58 @@ pub mod tests {
 
         // some work here
         let mut handle = CallbackHandle::default();
-        let data = cb
-            .call(None, &GlobalVars::default(), &vars, &mut handle)
-            .unwrap();
-        assert!(data.is_none());
+        for _ in 0..2 {
+            let data = cb
+                .call(None, &GlobalVars::default(), &vars, &mut handle)
+                .unwrap();
+            assert!(data.is_none());
+        }
+        handle.end_of_run().unwrap();
 
         let _res = child.join();
     }
 
     #[test]
-    #[cfg(target_family = "unix")]
+    fn tcp_address() {
+        let yaml = r#"
+            address: "[::1]:8900"
+            connect_timeout: 1
+        "#;
+        let cb = Callback::from_str(yaml).expect("unable to read YAML");
+        assert!(matches!(&cb.callback, CallbackType::Tcp(Some(x)) if x == "[::1]:8900"));
+        assert_eq!(cb.connect_timeout, 1);
+        assert_eq!(
+            Callback::from_str("address: 127.0.0.1:8900")
+                .unwrap()
+                .connect_timeout,
+            DEFAULT_CONNECT_TIMEOUT
+        );
+
+        let v4: SocketAddr = "127.0.0.1:8900".parse().unwrap();
+        let v6: SocketAddr = "[::1]:8900".parse().unwrap();
+        assert_eq!(resolve_address("127.0.0.1:8900").unwrap(), vec![v4]);
+        assert_eq!(resolve_address("[::1]:8900").unwrap(), vec![v6]);
+
+        let addrs = resolve_address("localhost:8900").unwrap();
+        assert!(!addrs.is_empty());
+        assert!(addrs.iter().all(|x| x.port() == 8900));
+
+        assert!(resolve_address("::1:8900").is_err());
+        assert!(resolve_address("localhost").is_err());
+        assert!(resolve_address("localhost:http").is_err());
+
+        // families alternate, starting with the first one
+        let v4_2: SocketAddr = "127.0.0.2:8900".parse().unwrap();
+        let v6_2: SocketAddr = "[::2]:8900".parse().unwrap();
+        assert_eq!(
+            interleave_families(vec![v4, v4_2, v6, v6_2]),
+            vec![v4, v6, v4_2, v6_2]
+        );
+        assert_eq!(interleave_families(vec![v6, v6_2, v4]), vec![v6, v4, v6_2]);
+    }
+
+    #[test]
+    #[cfg(all(feature = "sockets", target_family = "unix"))]
     fn callback_domain() {
         let yaml = r#"
             domain: /tmp/callback.sock
@@ -534,4 +1390,270 @@ pub mod tests {
 
         let _res = child.join();
     }
+
+    #[test]
+    #[cfg(all(feature = "sockets", target_family = "unix"))]
+    fn callback_queue() {
+        let addr = PathBuf::from("/tmp/callback_queue.sock");
+        let queue = PathBuf::from("/tmp/callback_queue.ndjson");
+        let _ = std::fs::remove_file(&addr);
+        let _ = std::fs::remove_file(&queue);
+
+        let yaml = format!(
+            "domain: {}\nargs: ['one']\nqueue:\n  path: {}",
+            addr.display(),
+            queue.display()
+        );
+        let cb = Callback::from_str(&yaml).expect("unable to read YAML");
+        assert_eq!(cb.queue.as_ref().unwrap().path, queue);
+
+        let mut vars = RuntimeVars::default();
+        vars.insert_runtime_var("CLF_LINE_NUMBER", 1_u64);
+
+        // nobody is listening: payloads are kept in the queue
+        let mut handle = CallbackHandle::default();
+        for _ in 0..2 {
+            let data = cb
+                .call(None, &GlobalVars::default(), &vars, &mut handle)
+                .unwrap();
+            assert!(data.is_none());
+        }
+        assert_eq!(cb.queue.as_ref().unwrap().entries().unwrap().len(), 2);
+
+        // next run: queued payloads are sent first
+        let listener = std::os::unix::net::UnixListener::bind(&addr).unwrap();
+        let child = std::thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            for seq in 1..=3 {
+                let json =
+                    get_json_from_stream(&mut socket).expect("unable to get JSON data from stream");
+                assert_eq!(json.seq, seq);
+                assert_eq!(json.args.is_empty(), seq != 1);
+                assert!(json.vars.contains_key("CLF_LINE_NUMBER"));
+            }
+        });
+
+        let mut handle = CallbackHandle::default();
+        cb.call(None, &GlobalVars::default(), &vars, &mut handle)
+            .unwrap();
+        child.join().unwrap();
+
+        assert!(cb.queue.as_ref().unwrap().entries().unwrap().is_empty());
+        std::fs::remove_file(&addr).unwrap();
+        std::fs::remove_file(&queue).unwrap();
+    }
+
+    #[test]
+    #[cfg(all(feature = "sockets", target_family = "unix"))]
+    fn drain_queue() {
+        let addr = PathBuf::from("/tmp/drain_queue.sock");
+        let queue = PathBuf::from("/tmp/drain_queue.ndjson");
+        let _ = std::fs::remove_file(&addr);
+        let _ = std::fs::remove_file(&queue);
+
+        let yaml = format!(
+            "domain: {}\nargs: ['one']\nqueue:\n  path: {}",
+            addr.display(),
+            queue.display()
+        );
+        let cb = Callback::from_str(&yaml).expect("unable to read YAML");
+
+        // nothing queued: nothing is sent
+        assert_eq!(cb.drain_queue(&GlobalVars::default()).unwrap(), 0);
+
+        let mut vars = RuntimeVars::default();
+        vars.insert_runtime_var("CLF_LINE_NUMBER", 1_u64);
+        let queued = cb.queue.as_ref().unwrap();
+        queued.append(&vars).unwrap();
+        queued.append(&vars).unwrap();
+
+        // nobody is listening: payloads are kept
+        assert!(cb.drain_queue(&GlobalVars::default()).is_err());
+        assert_eq!(queued.entries().unwrap().len(), 2);
+
+        // queued payloads are sent without any new match, followed by the end of the run
+        let listener = std::os::unix::net::UnixListener::bind(&addr).unwrap();
+        let child = std::thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            for seq in 1..=2 {
+                let json =
+                    get_json_from_stream(&mut socket).expect("unable to get JSON data from stream");
+                assert_eq!(json.seq, seq);
+                assert_eq!(json.args.is_empty(), seq != 1);
+                assert!(json.vars.contains_key("CLF_LINE_NUMBER"));
+            }
+            let json =
+                get_json_from_stream(&mut socket).expect("unable to get JSON data from stream");
+            assert_eq!(json.end_of_run.unwrap().sent, 2);
+        });
+
+        assert_eq!(cb.drain_queue(&GlobalVars::default()).unwrap(), 2);
+        child.join().unwrap();
+
+        assert!(queued.entries().unwrap().is_empty());
+        std::fs::remove_file(&addr).unwrap();
+        std::fs::remove_file(&queue).unwrap();
+    }
+
+    #[test]
+    #[cfg(all(feature = "sockets", target_family = "unix"))]
+    fn callback_memory_queue() {
+        let addr = PathBuf::from("/tmp/callback_memory_queue.sock");
+        let _ = std::fs::remove_file(&addr);
+
+        let yaml = format!(
+            "domain: {}\nargs: ['one']\nretries: 2\nretry_delay: 1\nmemory_queue: 10",
+            addr.display()
+        );
+        let cb = Callback::from_str(&yaml).expect("unable to read YAML");
+        assert_eq!(cb.retry_delay, 1);
+        assert_eq!(cb.memory_queue, 10);
+
+        // connections are tried again
+        let attempts = std::cell::Cell::new(0);
+        let handle = CallbackHandle::default();
+        let connect = || -> AppResult<()> {
+            attempts.set(attempts.get() + 1);
+            Err(AppError::new_custom(AppCustomErrorKind::HttpError, "down"))
+        };
+        assert!(cb.with_retries(&handle, connect).is_err());
+        assert_eq!(attempts.get(), 3);
+
+        // nobody is listening: payloads are kept in memory
+        let mut vars = RuntimeVars::default();
+        vars.insert_runtime_var("CLF_LINE_NUMBER", 1_u64);
+        let mut handle = CallbackHandle::default();
+        for _ in 0..2 {
+            let data = cb
+                .call(None, &GlobalVars::default(), &vars, &mut handle)
+                .unwrap();
+            assert!(data.is_none());
+        }
+        assert_eq!(handle.held.len(), 2);
+
+        // the socket is reachable again: payloads kept are sent first
+        let listener = std::os::unix::net::UnixListener::bind(&addr).unwrap();
+        let child = std::thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            for seq in 1..=3 {
+                let json =
+                    get_json_from_stream(&mut socket).expect("unable to get JSON data from stream");
+                assert_eq!(json.seq, seq);
+                assert_eq!(json.args.is_empty(), seq != 1);
+            }
+        });
+
+        std::thread::sleep(Duration::from_millis(10));
+        cb.call(None, &GlobalVars::default(), &vars, &mut handle)
+            .unwrap();
+        child.join().unwrap();
+
+        assert!(handle.held.is_empty());
+        assert!(handle.reconnect_at.is_none());
+        std::fs::remove_file(&addr).unwrap();
+    }
+
+    #[test]
+    #[cfg(all(feature = "sockets", target_family = "unix"))]
+    fn callback_batch() {
+        let yaml = r#"
+            domain: /tmp/clf_callback_batch.sock
+            batch:
+                max_size: 200
+                max_delay: 60000
+        "#;
+        let cb = Callback::from_str(yaml).expect("unable to read YAML");
+        assert_eq!(
+            cb.batch,
+            Some(Batch {
+                max_size: 200,
+                max_delay: 60000
+            })
+        );
+
+        let addr = PathBuf::from("/tmp/clf_callback_batch.sock");
+        let _ = std::fs::remove_file(&addr);
+        let listener = std::os::unix::net::UnixListener::bind(&addr).unwrap();
+
+        // payloads are written to the socket buffer, before being read
+        let mut vars = RuntimeVars::default();
+        vars.insert_runtime_var("CLF_LINE", "a matching line");
+        let mut handle = CallbackHandle::default();
+        for _ in 0..5 {
+            let data = cb
+                .call(None, &GlobalVars::default(), &vars, &mut handle)
+                .unwrap();
+            assert!(data.is_none());
+        }
+        handle.end_of_run().unwrap();
+
+        let (mut socket, _) = listener.accept().unwrap();
+        let mut frames = Vec::new();
+        while let Ok(messages) = get_jsons_from_stream(&mut socket) {
+            frames.push(messages);
+            if frames.last().unwrap().last().unwrap().end_of_run.is_some() {
+                break;
+            }
+        }
+        let messages: Vec<_> = frames.iter().flatten().collect();
+
+        // all payloads are received in order, in less frames
+        assert!(frames.len() > 1 && frames.len() < messages.len());
+        assert_eq!(
+            messages.iter().map(|m| m.seq).collect::<Vec<_>>(),
+            vec![1, 2, 3, 4, 5, 6]
+        );
+        assert_eq!(messages[5].end_of_run.as_ref().unwrap().sent, 5);
+
+        let _ = std::fs::remove_file(&addr);
+    }
+
+    #[test]
+    fn callback_http() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let yaml = format!(
+            r#"
+            url: http://{}/api/v2/alerts
+            args: ['one']
+            headers:
+                Authorization: GenieKey xyz
+            retries: 0
+        "#,
+            listener.local_addr().unwrap()
+        );
+        let cb = Callback::from_str(&yaml).expect("unable to read YAML");
+        assert!(
+            matches!(&cb.callback, CallbackType::Http(Some(x)) if x.ends_with("/api/v2/alerts"))
+        );
+        assert_eq!(cb.headers["Authorization"], "GenieKey xyz");
+        assert_eq!(
+            Callback::from_str("url: http://localhost").unwrap().retries,
+            DEFAULT_CALLBACK_RETRIES
+        );
+
+        let server = std::thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut request = vec![0; 4096];
+            let size = std::io::Read::read(&mut socket, &mut request).unwrap();
+            socket.write_all(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+            String::from_utf8_lossy(&request[..size]).to_string()
+        });
+
+        let mut vars = RuntimeVars::default();
+        vars.insert_runtime_var("CLF_LINE", "a matching line");
+        let mut handle = CallbackHandle::default();
+        let data = cb
+            .call(None, &GlobalVars::default(), &vars, &mut handle)
+            .unwrap();
+        assert!(data.is_none());
+
+        // each request holds the arguments
+        let request = server.join().unwrap();
+        assert!(request.contains("Authorization: GenieKey xyz\r\n"));
+        let body = request.split("\r\n\r\n").nth(1).unwrap();
+        let json: JSONStream = serde_json::from_str(body).unwrap();
+        assert_eq!(json.seq, 1);
+        assert_eq!(json.args, vec!["one"]);
+        assert_eq!(json.vars["CLF_LINE"], VarType::from("a matching line"));
+    }
 }
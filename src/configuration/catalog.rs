@@ -0,0 +1,142 @@
+//! A catalog of common patterns shipped with clf, selected by name in the configuration file with
+//! `patterns: { preset: linux_kernel_errors }`, so usual errors could be searched for without writing any regex.
+use serde::Deserialize;
+
+/// The name of a preset in the catalog.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum Preset {
+    /// kernel panics, oom-killer and segfaults
+    linux_kernel_errors,
+
+    /// JVM out of memory and other fatal errors
+    jvm_errors,
+
+    /// ORA- error codes
+    oracle_errors,
+
+    /// failed and suspicious SSH logins
+    ssh_bruteforce,
+}
+
+/// The regexes of a preset, for each pattern type.
+#[derive(Debug, Default)]
+pub struct PresetRegexes {
+    pub critical: &'static [&'static str],
+    pub warning: &'static [&'static str],
+}
+
+impl Preset {
+    /// Returns the regexes of the preset.
+    pub fn regexes(&self) -> PresetRegexes {
+        match self {
+            Preset::linux_kernel_errors => PresetRegexes {
+                critical: &[
+                    r"Kernel panic - not syncing",
+                    r"invoked oom-killer",
+                    r"Out of memory: Kill(ed)? process (?P<pid>\d+) \((?P<process>[^)]+)\)",
+                    r"(?P<process>\S+)\[(?P<pid>\d+)\]: segfault at",
+                    r"BUG: unable to handle (kernel|page fault)",
+                ],
+                warning: &[
+                    r"I/O error, dev (?P<device>\w+)",
+                    r"EXT4-fs error \(device (?P<device>\w+)\)",
+                    r"task \S+ blocked for more than \d+ seconds",
+                ],
+            },
+            Preset::jvm_errors => PresetRegexes {
+                critical: &[
+                    r"java\.lang\.OutOfMemoryError(: (?P<reason>.+))?",
+                    r"java\.lang\.StackOverflowError",
+                    r"A fatal error has been detected by the Java Runtime Environment",
+                ],
+                warning: &[r#"Exception in thread "(?P<thread>[^"]+)""#],
+            },
+            Preset::oracle_errors => PresetRegexes {
+                critical: &[r"ORA-(?P<code>00600|07445|04031|01578)"],
+                warning: &[r"ORA-(?P<code>\d{5})"],
+            },
+            Preset::ssh_bruteforce => PresetRegexes {
+                critical: &[
+                    r"maximum authentication attempts exceeded for (invalid user )?(?P<user>\S+) from (?P<ip>\S+)",
+                    r"POSSIBLE BREAK-IN ATTEMPT",
+                ],
+                warning: &[
+                    r"Failed password for (invalid user )?(?P<user>\S+) from (?P<ip>\S+)",
+                    r"Invalid user (?P<user>\S+) from (?P<ip>\S+)",
+                ],
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::pattern::{PatternSet, PatternType};
+    use std::str::FromStr;
+
+    // returns the pattern type of a line for a preset
+    fn pattern_type(preset: &str, line: &str) -> Option<PatternType> {
+        let set = PatternSet::from_str(&format!("preset: {}", preset)).unwrap();
+        set.is_match(line).map(|m| m.pattern_type)
+    }
+
+    #[test]
+    fn presets() {
+        // all regexes are valid
+        let all = [
+            Preset::linux_kernel_errors,
+            Preset::jvm_errors,
+            Preset::oracle_errors,
+            Preset::ssh_bruteforce,
+        ];
+        for preset in all {
+            let regexes = preset.regexes();
+            for re in regexes.critical.iter().chain(regexes.warning) {
+                assert!(regex::Regex::new(re).is_ok(), "{:?}: {}", preset, re);
+            }
+        }
+
+        let samples = [
+            ("linux_kernel_errors", "Kernel panic - not syncing: Fatal exception", Some(PatternType::critical)),
+            ("linux_kernel_errors", "kernel: [1234.5] java invoked oom-killer: gfp_mask=0x100cca", Some(PatternType::critical)),
+            ("linux_kernel_errors", "kernel: Out of memory: Killed process 4242 (java) total-vm:1024kB", Some(PatternType::critical)),
+            ("linux_kernel_errors", "kernel: nginx[1234]: segfault at 0 ip 00007f sp 00007ffd error 4", Some(PatternType::critical)),
+            ("linux_kernel_errors", "kernel: blk_update_request: I/O error, dev sda, sector 2048", Some(PatternType::warning)),
+            ("linux_kernel_errors", "kernel: eth0: link up", None),
+            ("jvm_errors", "java.lang.OutOfMemoryError: Java heap space", Some(PatternType::critical)),
+            ("jvm_errors", r#"Exception in thread "main" java.lang.NullPointerException"#, Some(PatternType::warning)),
+            ("jvm_errors", "INFO started in 2.3s", None),
+            ("oracle_errors", "ORA-00600: internal error code, arguments: [kcbz]", Some(PatternType::critical)),
+            ("oracle_errors", "ORA-01017: invalid username/password; logon denied", Some(PatternType::warning)),
+            ("oracle_errors", "Completed checkpoint up to RBA", None),
+            ("ssh_bruteforce", "sshd[999]: error: maximum authentication attempts exceeded for root from 10.0.0.1 port 22 ssh2", Some(PatternType::critical)),
+            ("ssh_bruteforce", "sshd[999]: Failed password for invalid user admin from 10.0.0.1 port 22 ssh2", Some(PatternType::warning)),
+            ("ssh_bruteforce", "sshd[999]: Accepted publickey for john from 10.0.0.2 port 22 ssh2", None),
+        ];
+
+        for (preset, line, expected) in samples {
+            assert_eq!(pattern_type(preset, line), expected, "{}: {}", preset, line);
+        }
+
+        // categories defined along with the preset replace those of the preset
+        let set = PatternSet::from_str(
+            "{ preset: ssh_bruteforce, warning: { regexes: ['Invalid user'] }, ok: { regexes: ['Accepted'] } }",
+        )
+        .unwrap();
+        assert!(set.critical.is_some());
+        assert!(set
+            .is_match("Failed password for root from 10.0.0.1")
+            .is_none());
+        assert_eq!(
+            set.is_match("Accepted publickey for john")
+                .unwrap()
+                .pattern_type,
+            PatternType::ok
+        );
+
+        // unknown preset
+        assert!(PatternSet::from_str("preset: windows_errors").is_err());
+    }
+}
@@ -0,0 +1,194 @@
+//! Claims on logfiles, to coordinate several clf instances searching the same logfiles, like both nodes of an
+//! active/active monitoring pair reading logfiles from a shared NFS filesystem. Before a logfile is searched, the
+//! instance writes a lease file in a shared directory. As long as this lease is renewed, other instances skip the
+//! logfile. When it expires, because the instance holding it is down, another instance takes the logfile over.
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::misc::{
+    error::{AppError, AppResult},
+    util::DEFAULT_CLAIM_LEASE,
+};
+use crate::{context, fromstr};
+
+/// The claim definition, as found in the global configuration.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Claim {
+    /// directory where lease files are written, shared by all instances
+    pub dir: PathBuf,
+
+    /// lease duration in seconds, which should be larger than the interval between 2 runs
+    #[serde(default = "Claim::default_lease")]
+    pub lease: u64,
+
+    /// name of this instance in lease files. Defaults to the hostname
+    #[serde(default = "Claim::default_owner")]
+    pub owner: String,
+}
+
+/// The content of a lease file.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Lease {
+    /// instance holding the lease
+    pub owner: String,
+
+    /// logfile claimed
+    pub path: PathBuf,
+
+    /// time when the lease expires, in seconds since the UNIX epoch
+    pub expires: u64,
+}
+
+/// The result of claiming a logfile.
+#[derive(Debug, PartialEq)]
+pub enum ClaimStatus {
+    /// the logfile could be searched by this instance
+    Claimed,
+
+    /// the logfile is claimed by another instance
+    ClaimedBy(Lease),
+}
+
+impl Claim {
+    /// Default lease duration
+    fn default_lease() -> u64 {
+        DEFAULT_CLAIM_LEASE
+    }
+
+    /// Default instance name
+    fn default_owner() -> String {
+        whoami::fallible::hostname().unwrap_or_else(|_| std::process::id().to_string())
+    }
+
+    /// Claims the logfile `path` at time `now`: the lease is written or renewed unless another instance holds a
+    /// lease which is not expired yet.
+    pub fn claim(&self, path: &Path, now: u64) -> AppResult<ClaimStatus> {
+        let lease_file = self.lease_file(path);
+
+        if let Some(lease) = self.read(&lease_file)? {
+            if lease.owner != self.owner && lease.expires > now {
+                return Ok(ClaimStatus::ClaimedBy(lease));
+            }
+        }
+
+        let lease = Lease {
+            owner: self.owner.clone(),
+            path: path.to_path_buf(),
+            expires: now + self.lease,
+        };
+        self.write(&lease_file, &lease)?;
+
+        // another instance could have taken over the expired lease at the same time: the last one written wins
+        match self.read(&lease_file)? {
+            Some(current) if current.owner != self.owner => Ok(ClaimStatus::ClaimedBy(current)),
+            _ => Ok(ClaimStatus::Claimed),
+        }
+    }
+
+    // a lease file for each logfile, named from the hash of its path
+    fn lease_file(&self, path: &Path) -> PathBuf {
+        let hash = crc::crc64::checksum_iso(path.to_string_lossy().as_bytes());
+        self.dir.join(format!("{:016x}.lease", hash))
+    }
+
+    // reads a lease file. A missing or invalid lease file is not a lease
+    fn read(&self, lease_file: &Path) -> AppResult<Option<Lease>> {
+        match std::fs::read_to_string(lease_file) {
+            Ok(data) => Ok(serde_json::from_str(&data)
+                .map_err(|e| debug!("invalid lease file {:?}: {}", lease_file, e))
+                .ok()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(context!(e, "unable to read lease file {:?}", lease_file)),
+        }
+    }
+
+    // writes a lease to a file of this instance, then renames it, so a lease file is never partially written
+    fn write(&self, lease_file: &Path, lease: &Lease) -> AppResult<()> {
+        let mut tmp = lease_file.as_os_str().to_owned();
+        tmp.push(format!(".{}.tmp", self.owner));
+        let tmp = PathBuf::from(tmp);
+
+        let json = serde_json::to_string(lease)
+            .map_err(|e| context!(e, "unable to serialize lease for {:?}", lease.path))?;
+
+        let mut file: File = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp)
+            .map_err(|e| context!(e, "unable to create lease file {:?}", tmp))?;
+        file.write_all(json.as_bytes())
+            .and_then(|_| file.sync_all())
+            .map_err(|e| context!(e, "unable to write lease file {:?}", tmp))?;
+
+        std::fs::rename(&tmp, lease_file)
+            .map_err(|e| context!(e, "unable to rename lease file {:?}", tmp))
+    }
+}
+
+// Auto-implement FromStr
+fromstr!(Claim);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn claim() {
+        let dir = std::env::temp_dir().join("clf_claim_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+
+        let yaml = format!("dir: {}\nowner: node1", dir.display());
+        let node1 = Claim::from_str(&yaml).expect("unable to read YAML");
+        assert_eq!(node1.lease, DEFAULT_CLAIM_LEASE);
+        assert!(!Claim::from_str("dir: /tmp").unwrap().owner.is_empty());
+
+        let mut node2 = node1.clone();
+        node2.owner = "node2".to_string();
+
+        let path = Path::new("/var/log/syslog");
+        let now = 1_000_000;
+
+        assert_eq!(node1.claim(path, now).unwrap(), ClaimStatus::Claimed);
+
+        // the other instance skips the logfile while the lease is not expired
+        match node2.claim(path, now + 10).unwrap() {
+            ClaimStatus::ClaimedBy(lease) => {
+                assert_eq!(lease.owner, "node1");
+                assert_eq!(lease.expires, now + DEFAULT_CLAIM_LEASE);
+            }
+            status => panic!("unexpected claim status {:?}", status),
+        }
+
+        // another logfile is not claimed
+        assert_eq!(
+            node2.claim(Path::new("/var/log/auth.log"), now).unwrap(),
+            ClaimStatus::Claimed
+        );
+
+        // the lease is renewed
+        let now = now + DEFAULT_CLAIM_LEASE - 1;
+        assert_eq!(node1.claim(path, now).unwrap(), ClaimStatus::Claimed);
+        assert!(matches!(
+            node2.claim(path, now + 10).unwrap(),
+            ClaimStatus::ClaimedBy(_)
+        ));
+
+        // the other instance takes over an expired lease
+        let now = now + DEFAULT_CLAIM_LEASE;
+        assert_eq!(node2.claim(path, now).unwrap(), ClaimStatus::Claimed);
+        assert!(matches!(
+            node1.claim(path, now).unwrap(),
+            ClaimStatus::ClaimedBy(_)
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
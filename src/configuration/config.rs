@@ -9,19 +9,36 @@
 //! The logfile could either be an accessible file path, or a command which will be executed and gets back a list of files.
 use std::path::Path;
 
-use log::debug;
+use log::{debug, warn};
 use serde::{de, Deserialize, Deserializer};
 use serde_yaml::Value;
 
-use super::{global::GlobalOptions, logsource::LogSource, search::Search};
+use super::{
+    callback::CallbackType,
+    global::{GlobalOptions, UnsupportedPolicy},
+    logsource::LogSource,
+    report::ReportSocketType,
+    search::Search,
+};
 
+use crate::logfile::snapshot::SnapshotBackend;
 use crate::misc::{
-    error::{AppError, AppResult},
-    extension::ListFiles,
+    error::{AppCustomErrorKind, AppError, AppResult},
+    extension::{HashAlgorithm, ListFiles},
 };
 
 use crate::{context, fromstr};
 
+/// Keys whose values are redacted when the configuration is printed, like HTTP authorization headers.
+const SECRET_KEYS: &[&str] = &[
+    "authorization",
+    "password",
+    "secret",
+    "token",
+    "apikey",
+    "api_key",
+];
+
 /// The main search configuration used to search patterns in a logfile. This is loaded from
 /// the YAML file found in the command line argument (or from stdin). This configuration can include a list
 /// of logfiles (given either by name or by starting an external command) to lookup and for each logfile, a list of regexes to match.
@@ -46,40 +63,26 @@ impl Config {
     pub fn from_path<P: AsRef<Path> + std::fmt::Debug>(
         file_name: P,
         context: Option<&str>,
-        show_rendered: bool,
     ) -> AppResult<Config> {
-        use tera::{Context, Tera, Value};
-
-        // read the whole file into a string
-        let config = std::fs::read_to_string(&file_name)
-            .map_err(|e| context!(e, "unable to read configuration file: {:?}", &file_name))?;
-
-        // load context or create context if specified from arguments
-        let context = if let Some(ctx) = context {
-            let json: Value = serde_json::from_str(ctx)
-                .map_err(|e| context!(e, "unable to context from JSON string {}", ctx))?;
-
-            // create context from JSON string
-            Context::from_value(json).expect("unable to add context")
-        } else {
-            Context::new()
-        };
-
-        // render the config with Tera context
-        let rendered = Tera::one_off(&config, &context, false).expect("error one_off");
-        if show_rendered {
-            println!("{}", rendered);
-            std::process::exit(0);
-        }
+        let rendered = Config::render(&file_name, context)?;
 
         // load YAML data
-        let yaml: Config = serde_yaml::from_str(&rendered)
+        let mut yaml: Config = serde_yaml::from_str(&rendered)
             .map_err(|e| context!(e, "error in reading configuration file {:?}", file_name))?;
 
         debug!(
             "sucessfully loaded YAML configuration file, nb_searches={}",
             yaml.searches.len()
         );
+
+        yaml.merge_default_options()?;
+        yaml.check_platform(std::env::consts::OS)?;
+        yaml.check_features(
+            cfg!(feature = "sockets"),
+            cfg!(feature = "blake3"),
+            cfg!(feature = "sqlite"),
+            cfg!(feature = "windows-eventlog"),
+        )?;
         Ok(yaml)
     }
 
@@ -91,15 +94,380 @@ impl Config {
             .map_err(|e| context!(e, "unable to read configuration file: {:?}", &file_name))?;
 
         // load YAML data
-        let yaml: Config = serde_yaml::from_reader(file)
+        let mut yaml: Config = serde_yaml::from_reader(file)
             .map_err(|e| context!(e, "error reading configuration file {:?}", file_name))?;
         debug!(
             "sucessfully loaded YAML configuration file, nb_searches={}",
             yaml.searches.len()
         );
 
+        yaml.merge_default_options()?;
+        yaml.check_platform(std::env::consts::OS)?;
+        yaml.check_features(
+            cfg!(feature = "sockets"),
+            cfg!(feature = "blake3"),
+            cfg!(feature = "sqlite"),
+            cfg!(feature = "windows-eventlog"),
+        )?;
         Ok(yaml)
     }
+
+    /// Loads a YAML configuration file whatever the features, like the binary does without any command line argument.
+    pub fn load<P: AsRef<Path> + std::fmt::Debug>(file_name: P) -> AppResult<Config> {
+        #[cfg(feature = "tera")]
+        return Config::from_path(file_name, None);
+
+        #[cfg(not(feature = "tera"))]
+        return Config::from_path(file_name);
+    }
+
+    /// Reads a YAML configuration file and renders it with the Tera context
+    #[cfg(feature = "tera")]
+    pub fn render<P: AsRef<Path> + std::fmt::Debug>(
+        file_name: P,
+        context: Option<&str>,
+    ) -> AppResult<String> {
+        use tera::{Context, Value};
+
+        // read the whole file into a string
+        let config = std::fs::read_to_string(&file_name)
+            .map_err(|e| context!(e, "unable to read configuration file: {:?}", &file_name))?;
+
+        // load context or create context if specified from arguments
+        let context = if let Some(ctx) = context {
+            let json: Value = serde_json::from_str(ctx)
+                .map_err(|e| context!(e, "unable to context from JSON string {}", ctx))?;
+
+            // create context from JSON string
+            Context::from_value(json).expect("unable to add context")
+        } else {
+            Context::new()
+        };
+
+        // render the config with Tera context, in a sandbox
+        crate::configuration::template::render(file_name.as_ref(), &config, &context)
+    }
+
+    /// Loads a YAML configuration file as a raw YAML value, without any validation, Tera version
+    #[cfg(feature = "tera")]
+    pub fn raw_from_path<P: AsRef<Path> + std::fmt::Debug>(
+        file_name: P,
+        context: Option<&str>,
+    ) -> AppResult<Value> {
+        let rendered = Config::render(&file_name, context)?;
+        serde_yaml::from_str(&rendered)
+            .map_err(|e| context!(e, "error in reading configuration file {:?}", file_name))
+    }
+
+    /// Loads a YAML configuration file as a raw YAML value, without any validation. Not using Tera
+    #[cfg(not(feature = "tera"))]
+    pub fn raw_from_path<P: AsRef<Path> + std::fmt::Debug>(file_name: P) -> AppResult<Value> {
+        let file = std::fs::File::open(&file_name)
+            .map_err(|e| context!(e, "unable to read configuration file: {:?}", &file_name))?;
+        serde_yaml::from_reader(file)
+            .map_err(|e| context!(e, "error reading configuration file {:?}", file_name))
+    }
+
+    /// Merges the global and search default options into the options of each tag, tag options being applied last.
+    pub fn merge_default_options(&mut self) -> AppResult<()> {
+        for search in &mut self.searches {
+            let defaults: Vec<&str> = [&self.global.default_options, &search.default_options]
+                .iter()
+                .filter_map(|x| x.as_deref())
+                .collect();
+            if defaults.is_empty() {
+                continue;
+            }
+
+            for tag in &mut search.tags {
+                tag.options = tag.options.with_defaults(&defaults)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolves a raw configuration into the one which is run, as YAML: a search is repeated for each logfile its
+    /// logfile source expands to (glob pattern, list, command or systemd units), and default options are merged into
+    /// the options of each tag. Glob patterns with `glob_rescan` are kept, as they're expanded before each search.
+    /// Secrets are redacted, and the result is checked like a configuration file.
+    pub fn resolve(mut yaml: Value) -> AppResult<Value> {
+        let global: GlobalOptions = match yaml.get("global") {
+            Some(global) => serde_yaml::from_value(global.clone())
+                .map_err(|e| context!(e, "error in global options",))?,
+            None => GlobalOptions::default(),
+        };
+        let raw_searches: Vec<Value> =
+            serde_yaml::from_value(yaml.get("searches").cloned().unwrap_or(Value::Null))
+                .map_err(|e| context!(e, "error in searches",))?;
+
+        let mut searches = Vec::new();
+        for raw in raw_searches {
+            let mut expanded = fill_logdef(Value::Sequence(vec![raw.clone()]))
+                .map_err(|e| context!(e, "error in search {:?}", raw.get("logfile")))?;
+            if raw["logfile"]["glob_rescan"] == Value::Bool(true) {
+                expanded.truncate(1);
+            }
+
+            for search in expanded {
+                let mut resolved = raw.clone();
+                if raw["logfile"]["glob_rescan"] != Value::Bool(true) {
+                    resolve_logsource(&mut resolved, &search.logfile.path)?;
+                }
+
+                // tag options are given in full
+                let defaults: Vec<&str> = [&global.default_options, &search.default_options]
+                    .iter()
+                    .filter_map(|x| x.as_deref())
+                    .collect();
+                if let Some(Value::Sequence(tags)) = resolved.get_mut("tags") {
+                    for (tag, value) in search.tags.iter().zip(tags.iter_mut()) {
+                        let options = tag.options.with_defaults(&defaults)?.normalized_list();
+                        if let Value::Mapping(value) = value {
+                            if options.is_empty() {
+                                value.remove(&Value::from("options"));
+                            } else {
+                                value.insert(Value::from("options"), Value::from(options));
+                            }
+                        }
+                    }
+                }
+                if let Value::Mapping(resolved) = &mut resolved {
+                    resolved.remove(&Value::from("default_options"));
+                }
+                searches.push(resolved);
+            }
+        }
+
+        if let Some(Value::Mapping(global)) = yaml.get_mut("global") {
+            global.remove(&Value::from("default_options"));
+        }
+        if let Value::Mapping(mapping) = &mut yaml {
+            mapping.insert(Value::from("searches"), Value::Sequence(searches));
+        }
+        redact(&mut yaml);
+
+        // what's printed is what's run
+        serde_yaml::from_value::<Config>(yaml.clone())
+            .map_err(|e| context!(e, "error in resolved configuration",))?;
+        Ok(yaml)
+    }
+
+    /// Expands again the glob patterns of logfile paths having the `glob_rescan` option, so logfiles created since the
+    /// previous search are searched too, and those deleted are not searched anymore.
+    pub fn rescan_globs(&mut self) -> AppResult<()> {
+        let mut searches = Vec::new();
+        let mut rescanned = Vec::new();
+
+        for search in &self.searches {
+            match &search.logfile.glob {
+                Some(pattern) if search.logfile.glob_rescan => {
+                    // all logfiles of a pattern are replaced at once
+                    if rescanned.contains(&pattern) {
+                        continue;
+                    }
+                    rescanned.push(pattern);
+
+                    let mut search = search.clone();
+                    search.logfile.path = LogSource::LogFile(pattern.clone());
+                    searches.extend(search.expand_glob()?);
+                }
+                _ => searches.push(search.clone()),
+            }
+        }
+
+        self.searches = searches;
+        Ok(())
+    }
+
+    /// The logfiles searched by `other` but not by this configuration, and those searched by this configuration but
+    /// not by `other`, like when a configuration is reloaded.
+    pub fn diff_searches<'a>(&'a self, other: &'a Config) -> (Vec<&'a Path>, Vec<&'a Path>) {
+        let paths = |config: &'a Config| -> Vec<&'a Path> {
+            config
+                .searches
+                .iter()
+                .map(|search| search.logfile.path().as_path())
+                .collect()
+        };
+        let (old, new) = (paths(self), paths(other));
+
+        let added = new.iter().filter(|p| !old.contains(p)).copied().collect();
+        let removed = old.iter().filter(|p| !new.contains(p)).copied().collect();
+        (added, removed)
+    }
+
+    /// Checks the configuration against the capabilities of the platform `os`, as given by `std::env::consts::OS`:
+    /// UNIX domain sockets are not supported on Windows, the systemd journal and units are only found on Linux, and
+    /// event logs on Windows. Unsupported features are either rejected, or disabled with a warning depending on the
+    /// `unsupported_options` global option.
+    pub fn check_platform(&mut self, os: &str) -> AppResult<()> {
+        let policy = &self.global.unsupported_options;
+        let unsupported = |msg: String, disabled: &str| match policy {
+            UnsupportedPolicy::reject => Err(AppError::new_custom(
+                AppCustomErrorKind::UnsupportedOnPlatform,
+                &msg,
+            )),
+            UnsupportedPolicy::downgrade => {
+                warn!("{}. {} disabled", msg, disabled);
+                Ok(())
+            }
+        };
+
+        // the search of a journal or of systemd units out of Linux, or of an event log out of Windows is removed
+        let mut removed = Vec::new();
+        for (i, search) in self.searches.iter().enumerate() {
+            let source = match &search.logfile.path {
+                LogSource::Journal(journal) if os != "linux" => {
+                    format!("the systemd journal {:?}", journal.key)
+                }
+                LogSource::SystemdUnits(units) if os != "linux" => {
+                    format!("the systemd units {}", units.pattern)
+                }
+                LogSource::EventLog(eventlog) if os != "windows" => {
+                    format!("the event log {:?}", eventlog.key)
+                }
+                _ => continue,
+            };
+            unsupported(
+                format!("searching {} is not supported on {}", source, os),
+                "The search is",
+            )?;
+            removed.push(i);
+        }
+        for i in removed.into_iter().rev() {
+            self.searches.remove(i);
+        }
+
+        if os != "windows" {
+            return Ok(());
+        }
+
+        for search in &mut self.searches {
+            for tag in &mut search.tags {
+                let callback = tag.callback.as_ref().map(|x| &x.callback);
+                if !matches!(callback, Some(CallbackType::Domain(_))) {
+                    continue;
+                }
+
+                unsupported(
+                    format!(
+                        "tag '{}' for logfile {:?} uses a UNIX domain socket callback, which is not supported on {}: use a TCP address callback instead",
+                        tag.name,
+                        search.logfile.path(),
+                        os
+                    ),
+                    "The callback is",
+                )?;
+                tag.callback = None;
+            }
+        }
+
+        if let Some(ReportSocketType::Domain(path)) =
+            self.global.report_socket.as_ref().map(|r| &r.socket)
+        {
+            unsupported(
+                format!(
+                    "the report socket {:?} is a UNIX domain socket, which is not supported on {}: use a TCP address instead",
+                    path, os
+                ),
+                "It's",
+            )?;
+            self.global.report_socket = None;
+        }
+
+        Ok(())
+    }
+
+    /// Checks the configuration against the features of the build: without `sockets`, socket callbacks and the report
+    /// socket are either rejected, or disabled with a warning depending on the `unsupported_options` global option.
+    /// Without `blake3` or `sqlite`, the blake3 hash algorithm or the sqlite snapshot backend are either rejected, or
+    /// replaced by the default ones. Without `windows-eventlog`, the searches of event logs are rejected or removed.
+    pub fn check_features(
+        &mut self,
+        sockets: bool,
+        blake3: bool,
+        sqlite: bool,
+        eventlog: bool,
+    ) -> AppResult<()> {
+        if sockets && blake3 && sqlite && eventlog {
+            return Ok(());
+        }
+
+        let policy = &self.global.unsupported_options;
+        let unsupported = |msg: String| match policy {
+            UnsupportedPolicy::reject => {
+                Err(AppError::new_custom(AppCustomErrorKind::NotBuiltIn, &msg))
+            }
+            UnsupportedPolicy::downgrade => {
+                warn!("{}. It's disabled", msg);
+                Ok(())
+            }
+        };
+
+        for search in &mut self.searches {
+            if !blake3 && search.logfile.hash_algorithm == HashAlgorithm::blake3 {
+                unsupported(format!(
+                    "logfile {:?} uses the blake3 hash algorithm, which is not built into this binary",
+                    search.logfile.path(),
+                ))?;
+                search.logfile.hash_algorithm = HashAlgorithm::default();
+            }
+
+            for tag in &mut search.tags {
+                let callback = tag.callback.as_ref().map(|x| &x.callback);
+                if sockets
+                    || !matches!(
+                        callback,
+                        Some(CallbackType::Tcp(_)) | Some(CallbackType::Domain(_))
+                    )
+                {
+                    continue;
+                }
+
+                unsupported(format!(
+                    "tag '{}' for logfile {:?} uses a socket callback, which is not built into this binary",
+                    tag.name,
+                    search.logfile.path(),
+                ))?;
+                tag.callback = None;
+            }
+        }
+
+        if !eventlog {
+            let mut removed = Vec::new();
+            for (i, search) in self.searches.iter().enumerate() {
+                if let LogSource::EventLog(eventlog) = &search.logfile.path {
+                    unsupported(format!(
+                        "searching the event log {:?} is not built into this binary",
+                        eventlog.key
+                    ))?;
+                    removed.push(i);
+                }
+            }
+            for i in removed.into_iter().rev() {
+                self.searches.remove(i);
+            }
+        }
+
+        if let Some(report_socket) = self.global.report_socket.as_ref().filter(|_| !sockets) {
+            unsupported(format!(
+                "the report socket {:?} is not built into this binary",
+                report_socket.socket
+            ))?;
+            self.global.report_socket = None;
+        }
+
+        if !sqlite && self.global.snapshot_backend == SnapshotBackend::sqlite {
+            unsupported(
+                "the sqlite snapshot backend is not built into this binary, JSON is used"
+                    .to_string(),
+            )?;
+            self.global.snapshot_backend = SnapshotBackend::json;
+        }
+
+        Ok(())
+    }
 }
 
 /// Replace the `logsource` YAML tag with the result of the script command
@@ -116,7 +484,10 @@ where
     if vec_yaml.is_err() {
         return vec_yaml;
     }
-    let mut vec_search = vec_yaml.unwrap();
+    let mut vec_search = Vec::new();
+    for search in vec_yaml.unwrap() {
+        vec_search.extend(search.expand_glob().map_err(de::Error::custom)?);
+    }
 
     // this vector wil hold new logfiles from the list returned from the script execution
     let mut vec_loglist: Vec<Search> = Vec::new();
@@ -124,7 +495,10 @@ where
     for search in &vec_search {
         match &search.logfile.path {
             // we found a logfile tag: just copy everything to the new structure
-            LogSource::LogFile(_) => continue,
+            LogSource::LogFile(_)
+            | LogSource::Journal(_)
+            | LogSource::Remote(_)
+            | LogSource::EventLog(_) => continue,
 
             // we found a logslist tag: get the list of files, and for each one, copy everything
             LogSource::LogList(cmd) => {
@@ -161,22 +535,105 @@ where
                     vec_loglist.push(cloned_search);
                 }
             }
+
+            // there's no systemd out of Linux: the search is kept as is, to be checked against the platform
+            LogSource::SystemdUnits(_) if !cfg!(target_os = "linux") => continue,
+
+            // we found a from_systemd_units tag: get the logfile or journal of each unit, and for each one, copy everything
+            LogSource::SystemdUnits(units) => {
+                let sources = units.discover().map_err(de::Error::custom)?;
+
+                for source in sources {
+                    let mut cloned_search = search.clone();
+                    cloned_search.logfile.path = source;
+                    vec_loglist.push(cloned_search);
+                }
+            }
         }
     }
 
     // add all those new logfiles we found
     vec_search.extend(vec_loglist);
 
-    // keep only valid logfiles, not logsources
-    vec_search.retain(|x| x.logfile.path.is_path());
+    // keep only valid logfiles, journals, event logs and remote logfiles, not logsources. Systemd units are only left
+    // out of Linux
+    vec_search.retain(|x| {
+        x.logfile.path.is_path()
+            || x.logfile.path.is_journal()
+            || x.logfile.path.is_eventlog()
+            || x.logfile.path.is_remote()
+            || matches!(x.logfile.path, LogSource::SystemdUnits(_))
+    });
+
+    // the lines of the standard input can only be read once
+    if vec_search
+        .iter()
+        .filter(|x| x.logfile.path.is_stdin())
+        .count()
+        > 1
+    {
+        return Err(de::Error::custom(
+            "only one search could read the standard input",
+        ));
+    }
     Ok(vec_search)
 }
 
+// replaces the logfile source of a search with the logfile or journal it was expanded to
+fn resolve_logsource(search: &mut Value, source: &LogSource) -> AppResult<()> {
+    let logfile = match search.get_mut("logfile") {
+        Some(Value::Mapping(logfile)) => logfile,
+        _ => return Ok(()),
+    };
+    for key in ["path", "list", "cmd", "journal", "from_systemd_units"] {
+        logfile.remove(&Value::from(key));
+    }
+
+    match source {
+        LogSource::LogFile(path) => {
+            logfile.insert(
+                Value::from("path"),
+                Value::from(path.to_string_lossy().to_string()),
+            );
+        }
+        LogSource::Journal(journal) => {
+            let filter = serde_yaml::to_value(&journal.filter)
+                .map_err(|e| context!(e, "unable to convert journal source {:?}", journal.key))?;
+            logfile.insert(Value::from("journal"), filter);
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+// replaces the values of secret keys, at any depth
+fn redact(yaml: &mut Value) {
+    match yaml {
+        Value::Mapping(mapping) => {
+            for (key, value) in mapping.iter_mut() {
+                let is_secret = key.as_str().is_some_and(|key| {
+                    let key = key.to_lowercase();
+                    SECRET_KEYS.iter().any(|secret| key.contains(secret))
+                });
+                if is_secret {
+                    *value = Value::from("<redacted>");
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Sequence(values) => values.iter_mut().for_each(redact),
+        _ => (),
+    }
+}
+
 #[cfg(test)]
 #[cfg(target_family = "unix")]
 mod tests {
     use super::*;
+    use crate::configuration::units::SystemdUnits;
     use std::path::PathBuf;
+    use std::str::FromStr;
 
     #[test]
     fn config() {
@@ -299,4 +756,362 @@ mod tests {
         assert!(tag.patterns.critical.is_some());
         assert!(tag.patterns.warning.is_some());
     }
+
+    #[test]
+    fn default_options() {
+        let yaml = r#"
+        global:
+          default_options: "savethresholds, criticalthreshold=10"
+        searches:
+          - logfile:
+                path: /var/log/syslog
+            default_options: "runcallback"
+            tags:
+              - name: inherited
+                patterns:
+                  critical: { regexes: ['error'] }
+              - name: overridden
+                options: "criticalthreshold=2"
+                patterns:
+                  critical: { regexes: ['error'] }
+          - logfile:
+                path: /var/log/kern.log
+            tags:
+              - name: global_only
+                patterns:
+                  critical: { regexes: ['error'] }
+        "#;
+        let mut config: Config = serde_yaml::from_str(yaml).expect("unable to read YAML");
+        config.merge_default_options().unwrap();
+
+        let tags = &config.searches[0].tags;
+        assert!(tags[0].options.savethresholds && tags[0].options.runcallback);
+        assert_eq!(tags[0].options.criticalthreshold, 10);
+        assert!(tags[1].options.savethresholds && tags[1].options.runcallback);
+        assert_eq!(tags[1].options.criticalthreshold, 2);
+
+        let tags = &config.searches[1].tags;
+        assert!(tags[0].options.savethresholds && !tags[0].options.runcallback);
+
+        // invalid default options are reported
+        config.global.default_options = Some("foo".to_string());
+        assert!(config.merge_default_options().is_err());
+    }
+
+    #[test]
+    fn check_platform() {
+        let yaml = r#"
+        global:
+          report_socket: { domain: /tmp/clf_report.sock }
+        searches:
+          - logfile:
+                path: /var/log/syslog
+            tags:
+              - name: domain
+                callback: { domain: /tmp/clf.sock }
+                patterns:
+                  critical: { regexes: ['error'] }
+              - name: tcp
+                callback: { address: 127.0.0.1:8999 }
+                patterns:
+                  critical: { regexes: ['error'] }
+          - logfile:
+                journal: { unit: sshd.service }
+            tags:
+              - name: sshd
+                patterns:
+                  critical: { regexes: ['error'] }
+        "#;
+        let mut config: Config = serde_yaml::from_str(yaml).expect("unable to read YAML");
+
+        // units are only kept as is out of Linux
+        let mut units = config.searches[1].clone();
+        units.logfile.path = LogSource::SystemdUnits(SystemdUnits {
+            pattern: "myapp-*".to_string(),
+        });
+        config.searches.push(units);
+
+        assert!(config.check_platform("linux").is_ok());
+        assert_eq!(config.searches.len(), 3);
+
+        // no systemd on macOS
+        let err = config.check_platform("macos").unwrap_err();
+        assert!(err.msg.contains("journal:unit=sshd.service"));
+        assert_eq!(config.searches.len(), 3);
+
+        config.global.unsupported_options = UnsupportedPolicy::downgrade;
+        assert!(config.check_platform("macos").is_ok());
+        assert_eq!(config.searches.len(), 1);
+        assert!(config.searches[0].tags[0].callback.is_some());
+        assert!(config.global.report_socket.is_some());
+
+        // no UNIX domain sockets on Windows
+        config.global.unsupported_options = UnsupportedPolicy::reject;
+        let err = config.check_platform("windows").unwrap_err();
+        assert!(err.msg.contains("tag 'domain'"));
+        assert!(config.searches[0].tags[0].callback.is_some());
+
+        config.global.unsupported_options = UnsupportedPolicy::downgrade;
+        assert!(config.check_platform("windows").is_ok());
+        assert!(config.searches[0].tags[0].callback.is_none());
+        assert!(config.searches[0].tags[1].callback.is_some());
+        assert!(config.global.report_socket.is_none());
+
+        // event logs are only found on Windows
+        let mut eventlog = config.searches[0].clone();
+        eventlog.logfile.path = serde_yaml::from_str("eventlog: {channel: System}").unwrap();
+        config.searches.push(eventlog);
+        assert!(config.check_platform("windows").is_ok());
+        assert_eq!(config.searches.len(), 2);
+
+        config.global.unsupported_options = UnsupportedPolicy::reject;
+        let err = config.check_platform("linux").unwrap_err();
+        assert!(err.msg.contains("eventlog:System"));
+
+        config.global.unsupported_options = UnsupportedPolicy::downgrade;
+        assert!(config.check_platform("linux").is_ok());
+        assert_eq!(config.searches.len(), 1);
+    }
+
+    #[test]
+    fn check_features() {
+        let yaml = r#"
+        global:
+          report_socket: { address: 127.0.0.1:8998 }
+        searches:
+          - logfile:
+                path: /var/log/syslog
+            tags:
+              - name: tcp
+                callback: { address: 127.0.0.1:8999 }
+                patterns:
+                  critical: { regexes: ['error'] }
+              - name: script
+                callback: { script: /usr/bin/true }
+                patterns:
+                  critical: { regexes: ['error'] }
+        "#;
+        let mut config: Config = serde_yaml::from_str(yaml).expect("unable to read YAML");
+        assert!(config.check_features(true, true, true, true).is_ok());
+        assert!(config.searches[0].tags[0].callback.is_some());
+
+        let err = config.check_features(false, true, true, true).unwrap_err();
+        assert!(err.msg.contains("tag 'tcp'"));
+        assert!(config.global.report_socket.is_some());
+
+        config.global.unsupported_options = UnsupportedPolicy::downgrade;
+        assert!(config.check_features(false, true, true, true).is_ok());
+        assert!(config.searches[0].tags[0].callback.is_none());
+        assert!(config.searches[0].tags[1].callback.is_some());
+        assert!(config.global.report_socket.is_none());
+
+        // without blake3, the default hash algorithm is used
+        let yaml = r#"
+        searches:
+          - logfile:
+                path: /var/log/syslog
+                hash_algorithm: blake3
+            tags:
+              - name: script
+                callback: { address: 127.0.0.1:8999 }
+                patterns:
+                  critical: { regexes: ['error'] }
+        "#;
+        let mut config: Config = serde_yaml::from_str(yaml).expect("unable to read YAML");
+        let err = config.check_features(true, false, true, true).unwrap_err();
+        assert!(err.msg.contains("blake3"));
+
+        config.global.unsupported_options = UnsupportedPolicy::downgrade;
+        assert!(config.check_features(true, false, true, true).is_ok());
+        assert_eq!(
+            config.searches[0].logfile.hash_algorithm,
+            HashAlgorithm::crc64
+        );
+        assert!(config.searches[0].tags[0].callback.is_some());
+
+        // without sqlite, snapshots are saved as JSON
+        config.global.unsupported_options = UnsupportedPolicy::reject;
+        config.global.snapshot_backend = SnapshotBackend::sqlite;
+        let err = config.check_features(true, true, false, true).unwrap_err();
+        assert!(err.msg.contains("sqlite"));
+
+        config.global.unsupported_options = UnsupportedPolicy::downgrade;
+        assert!(config.check_features(true, true, false, true).is_ok());
+        assert_eq!(config.global.snapshot_backend, SnapshotBackend::json);
+
+        // without windows-eventlog, event logs are not searched
+        let yaml = r#"
+        searches:
+          - logfile:
+                eventlog: { channel: Application }
+            tags:
+              - name: app
+                patterns:
+                  critical: { regexes: ['error'] }
+        "#;
+        let mut config: Config = serde_yaml::from_str(yaml).expect("unable to read YAML");
+        let err = config.check_features(true, true, true, false).unwrap_err();
+        assert!(err.msg.contains("eventlog:Application"));
+        assert_eq!(config.searches.len(), 1);
+
+        config.global.unsupported_options = UnsupportedPolicy::downgrade;
+        assert!(config.check_features(true, true, true, false).is_ok());
+        assert!(config.searches.is_empty());
+    }
+
+    #[test]
+    fn glob() {
+        let dir = std::env::temp_dir().join("clf_glob_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        for name in ["a.log", "b.log", "c.txt"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        std::os::unix::fs::symlink(dir.join("a.log"), dir.join("link.log")).unwrap();
+
+        let yaml = format!(
+            r#"
+        searches:
+          - logfile:
+                path: {}/*.log
+                glob_rescan: true
+            tags:
+              - name: error
+                patterns:
+                  critical: {{ regexes: ['error'] }}
+          - logfile:
+                path: {}/*.gz
+            tags:
+              - name: error
+                patterns:
+                  critical: {{ regexes: ['error'] }}
+        "#,
+            dir.display(),
+            dir.display()
+        );
+        let mut config: Config = serde_yaml::from_str(&yaml).expect("unable to read YAML");
+
+        // a logfile is searched once, even if a link matches too. A pattern matching nothing is kept
+        let canon = std::fs::canonicalize(&dir).unwrap();
+        let paths = |config: &Config| -> Vec<PathBuf> {
+            config
+                .searches
+                .iter()
+                .map(|search| search.logfile.path().clone())
+                .collect()
+        };
+        assert_eq!(
+            paths(&config),
+            vec![canon.join("a.log"), canon.join("b.log"), dir.join("*.gz")]
+        );
+        assert_eq!(config.searches[0].logfile.glob, Some(dir.join("*.log")));
+
+        // only patterns with glob_rescan are expanded again
+        std::fs::remove_file(dir.join("b.log")).unwrap();
+        std::fs::write(dir.join("d.log"), "").unwrap();
+        std::fs::write(dir.join("e.gz"), "").unwrap();
+        config.rescan_globs().unwrap();
+        assert_eq!(
+            paths(&config),
+            vec![canon.join("a.log"), canon.join("d.log"), dir.join("*.gz")]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resolve() {
+        let dir = std::env::temp_dir().join("clf_resolve_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        for name in ["a.log", "b.log"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+
+        let yaml = format!(
+            r#"
+        global:
+          default_options: "runcallback, criticalthreshold=10"
+        searches:
+          - logfile:
+                path: {}/*.log
+            default_options: "criticalthreshold=5"
+            tags:
+              - name: error
+                options: "savethresholds, criticalthreshold=2"
+                callback:
+                  url: http://127.0.0.1:9093/api/v2/alerts
+                  headers:
+                    Authorization: GenieKey 0123456789
+                patterns:
+                  critical: {{ regexes: ['error'] }}
+              - name: warning
+                patterns:
+                  warning: {{ regexes: ['warning'] }}
+        "#,
+            dir.display()
+        );
+        let resolved = Config::resolve(serde_yaml::from_str(&yaml).unwrap()).unwrap();
+
+        // a search for each logfile, with the options given in full
+        let canon = std::fs::canonicalize(&dir).unwrap();
+        let searches = resolved["searches"].as_sequence().unwrap();
+        assert_eq!(searches.len(), 2);
+        assert_eq!(
+            searches[1]["logfile"]["path"].as_str().unwrap(),
+            canon.join("b.log").to_str().unwrap()
+        );
+        assert!(searches[0].get("default_options").is_none());
+        assert!(resolved["global"].get("default_options").is_none());
+
+        let tags = searches[0]["tags"].as_sequence().unwrap();
+        assert_eq!(
+            tags[0]["options"].as_str().unwrap(),
+            "runcallback, savethresholds, criticalthreshold=2"
+        );
+        assert_eq!(
+            tags[1]["options"].as_str().unwrap(),
+            "runcallback, criticalthreshold=5"
+        );
+        assert_eq!(
+            tags[0]["callback"]["headers"]["Authorization"]
+                .as_str()
+                .unwrap(),
+            "<redacted>"
+        );
+
+        // the resolved configuration is run the same way
+        let config: Config = serde_yaml::from_value(resolved).unwrap();
+        assert_eq!(config.searches[0].tags[1].options.criticalthreshold, 5);
+
+        assert!(Config::resolve(serde_yaml::from_str("searches: 1").unwrap()).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn diff_searches() {
+        let yaml = |paths: &[&str]| {
+            let searches: Vec<_> = paths
+                .iter()
+                .map(|p| {
+                    format!(
+                        "  - logfile:\n      path: {}\n    tags:\n      - name: error\n        patterns:\n          critical:\n            regexes: ['ERROR']\n",
+                        p
+                    )
+                })
+                .collect();
+            let yaml = format!("searches:\n{}", searches.join(""));
+            Config::from_str(&yaml).expect("unable to read YAML")
+        };
+
+        let old = yaml(&["/var/log/syslog", "/var/log/kern.log"]);
+        let new = yaml(&["/var/log/syslog", "/var/log/auth.log"]);
+        let (added, removed) = old.diff_searches(&new);
+        assert_eq!(added, vec![Path::new("/var/log/auth.log")]);
+        assert_eq!(removed, vec![Path::new("/var/log/kern.log")]);
+
+        let (added, removed) = old.diff_searches(&old);
+        assert!(added.is_empty() && removed.is_empty());
+    }
 }
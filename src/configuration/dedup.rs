@@ -0,0 +1,109 @@
+//! Contains the configuration of alerts deduplication for a tag. Matches are grouped into incidents, identified by a key
+//! built from the matching regex and some of its capture groups. Incidents are kept in the snapshot, so a callback is
+//! only called when a new incident is found, or after a re-notify interval.
+use serde::Deserialize;
+
+use crate::configuration::pattern::PatternMatchResult;
+use crate::fromstr;
+
+/// Deduplication settings for a tag.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Dedup {
+    /// Capture groups, either by name or by position, used to build the incident key. If not set, all capture groups
+    /// are used or the whole match if the regex doesn't define any capture group.
+    pub captures: Option<Vec<String>>,
+
+    /// Number of seconds after which an open incident is notified again. 0 means never.
+    #[serde(default)]
+    pub renotify_interval: u64,
+
+    /// Number of consecutive runs without any match after which an incident is closed. 0 means never.
+    #[serde(default)]
+    pub close_after: u64,
+}
+
+impl Dedup {
+    /// Builds the incident key for a match: pattern type, regex and selected capture groups values.
+    pub fn key(&self, pattern_match: &PatternMatchResult, text: &str) -> String {
+        let values: Vec<&str> = match pattern_match.regex.captures(pattern_match.text(text)) {
+            None => Vec::new(),
+            Some(caps) => match &self.captures {
+                Some(list) => list
+                    .iter()
+                    .map(|cg| match cg.parse::<usize>() {
+                        Ok(i) => caps.get(i),
+                        Err(_) => caps.name(cg),
+                    })
+                    .map(|m| m.map_or("", |m| m.as_str()))
+                    .collect(),
+                None if caps.len() > 1 => caps
+                    .iter()
+                    .skip(1)
+                    .map(|m| m.map_or("", |m| m.as_str()))
+                    .collect(),
+                None => vec![caps.get(0).map_or("", |m| m.as_str())],
+            },
+        };
+
+        format!(
+            "{}:{}:{}",
+            <&str>::from(&pattern_match.pattern_type),
+            pattern_match.regex.as_str(),
+            values.join("|")
+        )
+    }
+}
+
+// Auto-implement FromStr
+fromstr!(Dedup);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::pattern::PatternType;
+    use regex::Regex;
+    use std::str::FromStr;
+
+    #[test]
+    fn dedup() {
+        let yaml = r#"
+            captures: ["host", "1"]
+            renotify_interval: 3600
+            close_after: 3
+        "#;
+        let dedup = Dedup::from_str(yaml).expect("unable to read YAML");
+        assert_eq!(dedup.captures.as_ref().unwrap(), &["host", "1"]);
+        assert_eq!(dedup.renotify_interval, 3600);
+        assert_eq!(dedup.close_after, 3);
+
+        let re = Regex::new(r"^ERROR (\d+) on (?P<host>\w+): (.*)$").unwrap();
+        let m = PatternMatchResult {
+            pattern_type: PatternType::critical,
+            regex: &re,
+            value: None,
+        };
+        let key = dedup.key(&m, "ERROR 42 on server01: disk full");
+        assert_eq!(
+            key,
+            r"critical:^ERROR (\d+) on (?P<host>\w+): (.*)$:server01|42"
+        );
+
+        // default: all capture groups
+        let dedup = Dedup::default();
+        let key = dedup.key(&m, "ERROR 42 on server01: disk full");
+        assert!(key.ends_with(":42|server01|disk full"));
+
+        // no capture group: whole match
+        let re = Regex::new(r"disk \w+").unwrap();
+        let m = PatternMatchResult {
+            pattern_type: PatternType::warning,
+            regex: &re,
+            value: None,
+        };
+        assert_eq!(
+            dedup.key(&m, "ERROR 42 on server01: disk full"),
+            r"warning:disk \w+:disk full"
+        );
+    }
+}
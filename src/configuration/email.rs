@@ -0,0 +1,355 @@
+//! An e-mail callback: instead of a message for each match, the matches of a run are gathered and sent in a single
+//! digest message once all logfiles are searched. Like webhooks, only plain SMTP without authentication is supported:
+//! a relay needing TLS or credentials is reached through the local MTA.
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use chrono::Local;
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+
+use super::callback::connect;
+use crate::configuration::vars::{RuntimeVars, VarType};
+use crate::misc::error::{AppCustomErrorKind, AppError, AppResult};
+use crate::misc::util::hostname;
+use crate::{context, fromstr};
+
+/// The SMTP server and the message settings of an e-mail callback.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(deny_unknown_fields)]
+pub struct Email {
+    /// address of the SMTP server, like `127.0.0.1:25`. The port defaults to 25
+    pub smtp: String,
+
+    /// sender address
+    #[serde(default = "Email::default_from")]
+    pub from: String,
+
+    /// recipient addresses
+    pub to: Vec<String>,
+
+    /// subject of the message: {count} is replaced by the number of matches and {hostname} by the host name
+    #[serde(default = "Email::default_subject")]
+    pub subject: String,
+}
+
+/// The matches of a run for an e-mail callback, not sent yet. A digest which can't be sent is kept in the snapshot, to
+/// be sent at the next run.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Digest {
+    email: Email,
+    connect_timeout: u64,
+    timeout: u64,
+    lines: Vec<String>,
+}
+
+impl Email {
+    /// Default sender address, on the local host
+    fn default_from() -> String {
+        format!("clf@{}", hostname())
+    }
+
+    /// Default subject of the message
+    fn default_subject() -> String {
+        "clf: {count} matches on {hostname}".to_string()
+    }
+
+    /// Builds the subject from its template.
+    pub fn subject(&self, count: usize) -> String {
+        self.subject
+            .replace("{count}", &count.to_string())
+            .replace("{hostname}", &hostname())
+    }
+
+    /// Builds the whole message, headers and body, with a line for each match.
+    pub fn message(&self, lines: &[String]) -> String {
+        let mut message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+            self.from,
+            self.to.join(", "),
+            self.subject(lines.len()),
+            Local::now().to_rfc2822()
+        );
+        for line in lines {
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+        message
+    }
+
+    /// Sends the message to all recipients through the SMTP server.
+    pub fn send(&self, lines: &[String], connect_timeout: u64, timeout: u64) -> AppResult<()> {
+        let address = if self
+            .smtp
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| !port.contains(']'))
+        {
+            self.smtp.clone()
+        } else {
+            format!("{}:25", self.smtp)
+        };
+        let stream = connect(&address, connect_timeout)?;
+        let timeout = Some(Duration::from_secs(timeout));
+        stream
+            .set_write_timeout(timeout)
+            .and_then(|_| stream.set_read_timeout(timeout))
+            .map_err(|e| context!(e, "unable to set socket timeout: {}", address))?;
+
+        let mut session = Session {
+            reader: BufReader::new(&stream),
+            stream: &stream,
+            address: &address,
+        };
+        session.reply(220)?;
+        session.command(&format!("EHLO {}", hostname()), 250)?;
+        session.command(&format!("MAIL FROM:<{}>", self.from), 250)?;
+        for to in &self.to {
+            session.command(&format!("RCPT TO:<{}>", to), 250)?;
+        }
+        session.command("DATA", 354)?;
+
+        // lines end with CRLF, even those of a multiline match, and a line starting with a dot is escaped by another one
+        let mut data = String::new();
+        for line in self.message(lines).lines() {
+            if line.starts_with('.') {
+                data.push('.');
+            }
+            data.push_str(line);
+            data.push_str("\r\n");
+        }
+        data.push('.');
+        session.command(&data, 250)?;
+
+        // the message is accepted, whatever the answer to QUIT
+        let _ = session.command("QUIT", 221);
+        Ok(())
+    }
+}
+
+// Auto-implement FromStr
+fromstr!(Email);
+
+impl Digest {
+    /// Starts a digest for an e-mail callback.
+    pub fn new(email: &Email, connect_timeout: u64, timeout: u64) -> Self {
+        Digest {
+            email: email.clone(),
+            connect_timeout,
+            timeout,
+            lines: Vec::new(),
+        }
+    }
+
+    /// Adds a match to the digest, as `logfile:tag:type:line_number:line`.
+    pub fn push(&mut self, vars: &RuntimeVars) {
+        let var = |name: &str| vars.get(name).map_or(String::new(), |v| v.to_string());
+
+        // a long line is split into chunks
+        let line = match (vars.get("CLF_LINE"), vars.get("CLF_LINE_CHUNKS")) {
+            (Some(line), _) => line.to_string(),
+            (None, Some(VarType::Int(chunks))) => (1..=*chunks)
+                .map(|i| var(&format!("CLF_LINE_{}", i)))
+                .collect(),
+            _ => String::new(),
+        };
+
+        self.lines.push(format!(
+            "{}:{}:{}:{}:{}",
+            var("CLF_LOGFILE"),
+            var("CLF_TAG"),
+            var("CLF_MATCHED_RE_TYPE"),
+            var("CLF_LINE_NUMBER"),
+            line
+        ));
+    }
+
+    /// Adds the matches to `digests`, those of the run to be sent once all logfiles are searched. Tags sharing the same
+    /// settings share the same message, the matches already there coming first.
+    pub fn collect(self, digests: &mut Vec<Digest>) {
+        if self.lines.is_empty() {
+            return;
+        }
+        match digests.iter_mut().find(|digest| digest.email == self.email) {
+            Some(digest) => digest.lines.extend(self.lines),
+            None => digests.push(self),
+        }
+    }
+}
+
+/// Sends the digest of each e-mail callback having matches during the run. A digest which can't be sent is given back,
+/// to be sent along with the next matches, and the error is logged.
+pub fn send_digests(digests: Vec<Digest>) -> Vec<Digest> {
+    let mut unsent = Vec::new();
+
+    for digest in digests {
+        match digest
+            .email
+            .send(&digest.lines, digest.connect_timeout, digest.timeout)
+        {
+            Ok(()) => info!(
+                "e-mail digest of {} matches sent to {:?}",
+                digest.lines.len(),
+                digest.email.to
+            ),
+            Err(e) => {
+                error!(
+                    "unable to send e-mail digest of {} matches to {:?}, kept to be sent later: {}",
+                    digest.lines.len(),
+                    digest.email.to,
+                    e
+                );
+                unsent.push(digest);
+            }
+        }
+    }
+
+    unsent
+}
+
+// an SMTP dialogue
+struct Session<'a> {
+    reader: BufReader<&'a TcpStream>,
+    stream: &'a TcpStream,
+    address: &'a str,
+}
+
+impl Session<'_> {
+    // sends a command and checks the reply code
+    fn command(&mut self, command: &str, expected: u16) -> AppResult<()> {
+        self.stream
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .map_err(|e| context!(e, "error writing to SMTP server: {}", self.address))?;
+        self.reply(expected)
+    }
+
+    // reads a reply, which could span several lines like `250-SIZE` before `250 HELP`
+    fn reply(&mut self, expected: u16) -> AppResult<()> {
+        loop {
+            let mut line = String::new();
+            self.reader
+                .read_line(&mut line)
+                .map_err(|e| context!(e, "error reading from SMTP server: {}", self.address))?;
+            debug!("SMTP reply from {}: {}", self.address, line.trim_end());
+
+            let code = line.get(..3).and_then(|code| code.parse::<u16>().ok());
+            match (code, line.as_bytes().get(3)) {
+                (Some(_), Some(b'-')) => continue,
+                (Some(code), _) if code == expected || (expected == 250 && code == 251) => {
+                    return Ok(())
+                }
+                _ => {
+                    return Err(AppError::new_custom(
+                        AppCustomErrorKind::SmtpError,
+                        &format!(
+                            "unexpected reply from SMTP server {}: {:?}",
+                            self.address,
+                            line.trim_end()
+                        ),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::str::FromStr;
+
+    #[test]
+    fn email() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let yaml = format!(
+            "smtp: {}\nfrom: clf@example.com\nto: [ops@example.com, dba@example.com]\nsubject: '{{count}} matches'",
+            listener.local_addr().unwrap()
+        );
+        let email: Email = serde_yaml::from_str(&yaml).unwrap();
+
+        // a server answering each command, the message being read until the final dot
+        let server = std::thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&socket);
+            (&socket).write_all(b"220 localhost ESMTP\r\n").unwrap();
+
+            let mut received = String::new();
+            let mut data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                received.push_str(&line);
+
+                let reply = match line.trim_end() {
+                    "." => "250 queued\r\n",
+                    _ if data => continue,
+                    "DATA" => "354 go ahead\r\n",
+                    "QUIT" => "221 bye\r\n",
+                    command if command.starts_with("EHLO") => {
+                        "250-localhost\r\n250 SIZE 1000000\r\n"
+                    }
+                    command if command.starts_with("RCPT TO:<dba") => "251 forwarded\r\n",
+                    _ => "250 OK\r\n",
+                };
+                data = reply.starts_with("354");
+                (&socket).write_all(reply.as_bytes()).unwrap();
+            }
+            received
+        });
+
+        let mut digest = Digest::new(&email, 1, 1);
+        let mut vars = RuntimeVars::default();
+        vars.insert_runtime_var("CLF_LOGFILE", "/var/log/syslog");
+        vars.insert_runtime_var("CLF_TAG", "error");
+        vars.insert_runtime_var("CLF_MATCHED_RE_TYPE", "critical");
+        vars.insert_runtime_var("CLF_LINE_NUMBER", 42u64);
+        vars.insert_runtime_var("CLF_LINE", "ERROR disk full");
+        digest.push(&vars);
+        assert_eq!(
+            digest.lines,
+            vec!["/var/log/syslog:error:critical:42:ERROR disk full"]
+        );
+
+        // a line starting with a dot is escaped, and lines of a multiline match end with CRLF
+        let mut lines = digest.lines.clone();
+        lines.push(".hidden".to_string());
+        lines.push("first\nsecond\n.third".to_string());
+        email.send(&lines, 1, 1).unwrap();
+        let received = server.join().unwrap();
+        assert!(received.contains("MAIL FROM:<clf@example.com>\r\n"));
+        assert!(received.contains("RCPT TO:<dba@example.com>\r\n"));
+        assert!(received.contains("To: ops@example.com, dba@example.com\r\n"));
+        assert!(received.contains("Subject: 3 matches\r\n"));
+        assert!(received.contains("\r\n/var/log/syslog:error:critical:42:ERROR disk full\r\n"));
+        assert!(received.contains("\r\n..hidden\r\nfirst\r\nsecond\r\n..third\r\n.\r\nQUIT\r\n"));
+        assert!(!received.replace("\r\n", "").contains('\n'));
+
+        // the message is not sent if the server can't be reached
+        let email = Email::from_str("smtp: 127.0.0.1:1\nto: [ops@example.com]").unwrap();
+        assert!(email.from.starts_with("clf@"));
+        assert!(email.send(&digest.lines, 1, 1).is_err());
+    }
+
+    #[test]
+    fn digests() {
+        let email = Email::from_str("smtp: 127.0.0.1:1\nto: [digests@example.com]").unwrap();
+        let digest = |lines: &[&str]| Digest {
+            lines: lines.iter().map(|l| l.to_string()).collect(),
+            ..Digest::new(&email, 1, 1)
+        };
+
+        // the matches of the same e-mail settings are merged, those of a previous run coming first
+        let mut digests = vec![digest(&["old"])];
+        digest(&["new"]).collect(&mut digests);
+        digest(&[]).collect(&mut digests);
+        assert_eq!(digests.len(), 1);
+
+        // a digest which can't be sent is given back
+        let kept = send_digests(digests);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].lines, vec!["old", "new"]);
+    }
+}
@@ -0,0 +1,362 @@
+//! Contains the configuration of an optional exporter, used to push counters and scan durations to a statsd or graphite
+//! endpoint once all searches are done, or to write them to a Prometheus textfile. This gives trend graphs without the
+//! need of another monitoring system.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::net::{TcpStream, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::debug;
+use serde::Deserialize;
+
+use crate::logfile::{rundata::RunData, snapshot::SnapshotList};
+use crate::misc::{
+    error::{AppError, AppResult},
+    util::{from_epoch_secs, DEFAULT_WRITE_TIMEOUT},
+};
+use crate::{context, fromstr};
+
+/// An exporter is either a statsd UDP address, a graphite TCP address (plaintext protocol) or the path of a file
+/// read by the textfile collector of the Prometheus node_exporter
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub enum ExporterType {
+    #[serde(rename = "statsd")]
+    Statsd(String),
+
+    #[serde(rename = "graphite")]
+    Graphite(String),
+
+    #[serde(rename = "prometheus")]
+    Prometheus(PathBuf),
+}
+
+/// A structure representing where and how to push metrics
+#[derive(Debug, Deserialize, Clone)]
+pub struct Exporter {
+    /// The kind of endpoint along with its address
+    #[serde(flatten)]
+    pub exporter: ExporterType,
+
+    /// Prefix added to all metric names
+    #[serde(default = "Exporter::default_prefix")]
+    pub prefix: String,
+
+    /// A timeout in seconds for connecting and sending data.
+    #[serde(default = "Exporter::default_timeout")]
+    timeout: u64,
+}
+
+/// A single metric value, sent by the exporter
+#[derive(Debug, PartialEq)]
+pub struct Metric {
+    pub name: String,
+    pub value: f64,
+}
+
+impl Exporter {
+    /// Default prefix for metric names
+    fn default_prefix() -> String {
+        "clf".to_string()
+    }
+
+    /// Default timeout in seconds when sending metrics
+    fn default_timeout() -> u64 {
+        DEFAULT_WRITE_TIMEOUT
+    }
+
+    /// Builds the list of metrics for all logfiles and tags processed during this run. Like performance data, statsd and
+    /// graphite metrics are only named after the logfile and the tag: search labels are left out, as they would be
+    /// part of the names and split the series whenever one is changed.
+    pub fn metrics(&self, snapshots: &SnapshotList) -> Vec<Metric> {
+        let mut metrics = Vec::new();
+
+        for (path, tag_name, run_data) in snapshots.current_run_data() {
+            let base = format!(
+                "{}.{}.{}",
+                self.prefix,
+                sanitize(&path.to_string_lossy()),
+                sanitize(tag_name)
+            );
+
+            let values = values(run_data);
+
+            for (name, value) in &values {
+                metrics.push(Metric {
+                    name: format!("{}.{}", base, name),
+                    value: *value,
+                });
+            }
+        }
+
+        metrics
+    }
+
+    /// Builds the content of the Prometheus textfile for all logfiles and tags processed during this run. Metrics
+    /// are gauges whose labels are the logfile path and the tag name, along with the search labels prefixed by
+    /// `label_`.
+    pub fn prometheus_text(&self, snapshots: &SnapshotList) -> String {
+        // all samples of a metric are written together, after its type
+        let mut samples: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for (path, tag_name, run_data, labels) in snapshots.labeled_run_data() {
+            let labels: String = labels
+                .iter()
+                .map(|(name, value)| {
+                    format!(
+                        ",label_{}=\"{}\"",
+                        name.replace(|c: char| !c.is_ascii_alphanumeric(), "_"),
+                        escape_label(value)
+                    )
+                })
+                .collect();
+            for (name, value) in values(run_data) {
+                samples.entry(name).or_default().push(format!(
+                    "{{logfile=\"{}\",tag=\"{}\"{}}} {}",
+                    escape_label(&path.to_string_lossy()),
+                    escape_label(tag_name),
+                    labels,
+                    value
+                ));
+            }
+        }
+
+        let prefix = self
+            .prefix
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+        let mut text = String::new();
+        for (name, samples) in &samples {
+            text.push_str(&format!("# TYPE {}_{} gauge\n", prefix, name));
+            for sample in samples {
+                text.push_str(&format!("{}_{}{}\n", prefix, name, sample));
+            }
+        }
+        text
+    }
+
+    /// Sends all metrics to the configured endpoint
+    pub fn export(&self, snapshots: &SnapshotList) -> AppResult<()> {
+        let metrics = self.metrics(snapshots);
+        let timeout = Duration::new(self.timeout, 0);
+
+        match &self.exporter {
+            ExporterType::Statsd(addr) => {
+                let socket = UdpSocket::bind("0.0.0.0:0")
+                    .map_err(|e| context!(e, "unable to bind UDP socket for statsd: {}", addr))?;
+                socket
+                    .set_write_timeout(Some(timeout))
+                    .map_err(|e| context!(e, "unable to set socket timeout: {}", addr))?;
+
+                // one datagram per metric to stay below usual MTU sizes
+                for metric in &metrics {
+                    let line = statsd_line(metric);
+                    socket.send_to(line.as_bytes(), addr).map_err(|e| {
+                        context!(e, "error sending metric: {} to address: {}", line, addr)
+                    })?;
+                }
+            }
+            ExporterType::Graphite(addr) => {
+                let mut stream = TcpStream::connect(addr)
+                    .map_err(|e| context!(e, "unable to connect to TCP address: {}", addr))?;
+                stream
+                    .set_write_timeout(Some(timeout))
+                    .map_err(|e| context!(e, "unable to set socket timeout: {}", addr))?;
+
+                let timestamp = from_epoch_secs()?;
+                let payload: String = metrics
+                    .iter()
+                    .map(|m| graphite_line(m, timestamp))
+                    .collect();
+
+                stream
+                    .write_all(payload.as_bytes())
+                    .map_err(|e| context!(e, "error writing metrics to address: {}", addr))?;
+            }
+            ExporterType::Prometheus(path) => {
+                write_textfile(path, &self.prometheus_text(snapshots))?;
+            }
+        }
+
+        debug!("{} metrics sent to {:?}", metrics.len(), &self.exporter);
+        Ok(())
+    }
+}
+
+// the values exported for a logfile & tag
+fn values(run_data: &RunData) -> Vec<(&'static str, f64)> {
+    let mut values = vec![
+        ("critical_count", run_data.counters.critical_count as f64),
+        ("warning_count", run_data.counters.warning_count as f64),
+        ("ok_count", run_data.counters.ok_count as f64),
+        ("exec_count", run_data.counters.exec_count as f64),
+        (
+            "suppressed_count",
+            run_data.counters.suppressed_count as f64,
+        ),
+        ("scan_duration", run_data.scan_duration),
+    ];
+
+    // times of the first and last critical or warning matches, once the tag has matched
+    if let (Some(first_seen), Some(last_seen)) = (run_data.first_seen, run_data.last_seen) {
+        values.push(("first_seen", first_seen as f64));
+        values.push(("last_seen", last_seen as f64));
+    }
+
+    // line counters are only there if `line_stats` is set for the tag
+    if let Some(stats) = &run_data.line_stats {
+        values.push(("lines_evaluated", stats.evaluated as f64));
+        values.push(("lines_matched", stats.matched as f64));
+        values.push(("lines_excluded", stats.excluded as f64));
+    }
+
+    values
+}
+
+// Auto-implement FromStr
+fromstr!(Exporter);
+
+// metric names use '.' as a separator, so replace any character which is not alphanumeric
+fn sanitize(s: &str) -> String {
+    s.trim_start_matches(['/', '\\'])
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+// statsd gauge format: <name>:<value>|g
+fn statsd_line(metric: &Metric) -> String {
+    format!("{}:{}|g\n", metric.name, metric.value)
+}
+
+// graphite plaintext format: <name> <value> <timestamp>
+fn graphite_line(metric: &Metric, timestamp: u64) -> String {
+    format!("{} {} {}\n", metric.name, metric.value, timestamp)
+}
+
+// backslashes, double quotes and line feeds are escaped in Prometheus label values
+fn escape_label(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// the textfile collector could read the file at any time: write a temporary file and rename it, so it's never read
+// partially written
+fn write_textfile(path: &Path, text: &str) -> AppResult<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let mut file =
+        File::create(&tmp).map_err(|e| context!(e, "unable to create textfile {:?}", tmp))?;
+    file.write_all(text.as_bytes())
+        .map_err(|e| context!(e, "unable to write textfile {:?}", tmp))?;
+
+    std::fs::rename(&tmp, path).map_err(|e| context!(e, "unable to rename textfile {:?}", tmp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn exporter() {
+        let yaml = r#"
+            statsd: 127.0.0.1:8125
+        "#;
+        let exporter = Exporter::from_str(yaml).expect("unable to read YAML");
+        assert_eq!(
+            exporter.exporter,
+            ExporterType::Statsd("127.0.0.1:8125".to_string())
+        );
+        assert_eq!(&exporter.prefix, "clf");
+
+        let yaml = r#"
+            graphite: 127.0.0.1:2003
+            prefix: nagios.clf
+        "#;
+        let exporter = Exporter::from_str(yaml).expect("unable to read YAML");
+        assert_eq!(
+            exporter.exporter,
+            ExporterType::Graphite("127.0.0.1:2003".to_string())
+        );
+        assert_eq!(&exporter.prefix, "nagios.clf");
+
+        let yaml = r#"
+            prometheus: /var/lib/node_exporter/textfile/clf.prom
+        "#;
+        let exporter = Exporter::from_str(yaml).expect("unable to read YAML");
+        assert_eq!(
+            exporter.exporter,
+            ExporterType::Prometheus(PathBuf::from("/var/lib/node_exporter/textfile/clf.prom"))
+        );
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn prometheus() {
+        use crate::configuration::logfiledef::LogFileDef;
+        use crate::logfile::snapshot::Snapshot;
+
+        let path = PathBuf::from("./tests/unittest/list_files.log");
+        let def = LogFileDef {
+            hash_window: 4096,
+            ..Default::default()
+        };
+        let mut snapshot = Snapshot::default();
+        let logfile = snapshot.logfile_mut(&path, &def).unwrap();
+        logfile.labels = BTreeMap::from([("team".to_string(), "db".to_string())]);
+        for tag_name in ["tag1", "tag2"] {
+            let run_data = logfile.rundata_for_tag(tag_name);
+            run_data.pid = std::process::id();
+            run_data.counters.critical_count = 3;
+        }
+        let mut snapshots = SnapshotList::default();
+        snapshots.insert(PathBuf::from("/tmp/snapshot.json"), snapshot);
+
+        let textfile = std::env::temp_dir().join("clf_exporter_test.prom");
+        let yaml = format!("prometheus: {}\nprefix: nagios.clf", textfile.display());
+        let exporter = Exporter::from_str(&yaml).unwrap();
+        exporter.export(&snapshots).unwrap();
+
+        let text = std::fs::read_to_string(&textfile).unwrap();
+        assert_eq!(text, exporter.prometheus_text(&snapshots));
+        assert!(text.starts_with("# TYPE nagios_clf_critical_count gauge\n"));
+        assert!(text.contains(
+            "nagios_clf_critical_count{logfile=\"./tests/unittest/list_files.log\",tag=\"tag2\",label_team=\"db\"} 3\n"
+        ));
+        assert_eq!(text.matches("# TYPE").count(), 6);
+        assert_eq!(text.lines().count(), 18);
+
+        std::fs::remove_file(&textfile).unwrap();
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!(&sanitize("/var/log/kern.log"), "var_log_kern_log");
+        assert_eq!(&sanitize("http-access get"), "http-access_get");
+
+        let metric = Metric {
+            name: "clf.var_log_syslog.tag.critical_count".to_string(),
+            value: 5.0,
+        };
+        assert_eq!(
+            &statsd_line(&metric),
+            "clf.var_log_syslog.tag.critical_count:5|g\n"
+        );
+        assert_eq!(
+            &graphite_line(&metric, 1611857382),
+            "clf.var_log_syslog.tag.critical_count 5 1611857382\n"
+        );
+
+        assert_eq!(&escape_label("C:\\log \"a\"\nb"), r#"C:\\log \"a\"\nb"#);
+    }
+}
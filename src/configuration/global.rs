@@ -1,10 +1,15 @@
 //! Contains the global configuration when processing logfiles. These values are independant from the ones solely related to a logfile when searching.
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use serde::Deserialize;
 
-use crate::configuration::{script::Script, vars::GlobalVars};
+use crate::configuration::{
+    claim::Claim, exporter::Exporter, report::ReportSocket, script::Script, vars::GlobalVars,
+};
+use crate::logfile::{record::OutputFormat, snapshot::SnapshotBackend};
+use crate::misc::nagios::NagiosError;
 use crate::misc::util::*;
 
 use crate::{fromstr, prefix_var};
@@ -20,12 +25,45 @@ pub struct GlobalOptions {
     /// A directory where matched lines will be stored.
     pub output_dir: PathBuf,
 
+    /// Name template of the files where matched lines are stored with the `keepoutput` option, either for each tag or
+    /// for each run.
+    pub output_file: String,
+
+    /// Retention time in seconds of output files. 0 means they're never deleted.
+    pub output_retention: u64,
+
+    /// Size in bytes beyond which an output file is rotated. 0 means it's never rotated.
+    pub output_max_size: u64,
+
+    /// Name template of the protocol file of each run in `output_dir`, where matched lines of tags with the `protocol`
+    /// option are written.
+    pub protocol_file: String,
+
+    /// Retention time in seconds of protocol files, including rotated ones. 0 means they're never deleted.
+    pub protocol_retention: u64,
+
+    /// Size in bytes beyond which the protocol file is rotated. 0 means it's never rotated.
+    pub protocol_max_size: u64,
+
     /// The snapshot file name. Option<> is used because if not specified here,
     pub snapshot_file: Option<PathBuf>,
 
     /// Retention time for tags.
     pub snapshot_retention: u64,
 
+    /// Number of previous snapshot files kept for recovery, as .1 to .N files. 0 means none.
+    pub snapshot_generations: usize,
+
+    /// Number of last runs whose summary is kept in the snapshot file. 0 means none.
+    pub snapshot_history: usize,
+
+    /// Whether snapshots are stored as JSON files or SQLite databases.
+    pub snapshot_backend: SnapshotBackend,
+
+    /// When set, the offsets reached while searching a logfile are saved along the way, so a crashed run is resumed
+    /// near where it stopped.
+    pub snapshot_checkpoint: Option<SnapshotCheckpoint>,
+
     /// A list of user variables if any.
     #[serde(rename = "vars")]
     pub global_vars: GlobalVars,
@@ -35,6 +73,199 @@ pub struct GlobalOptions {
 
     // A command called before the end of clf
     pub postscript: Option<Script>,
+
+    /// Default maximum time in seconds allowed to search a logfile, if not defined at the search level.
+    pub search_timeout: Option<u64>,
+
+    /// An optional statsd or graphite endpoint to which counters are pushed at the end of the run.
+    pub exporter: Option<Exporter>,
+
+    /// An optional TCP or UNIX domain socket to which the JSON report of each run is sent.
+    pub report_socket: Option<ReportSocket>,
+
+    /// The format of matched lines printed out when no callback is run: text or ndjson.
+    pub output_format: OutputFormat,
+
+    /// Options inherited by the tags of all searches, as a comma-separated list like tag options.
+    pub default_options: Option<String>,
+
+    /// What to do with options not supported on the current platform or not built in: reject the configuration, or
+    /// disable the options.
+    pub unsupported_options: UnsupportedPolicy,
+
+    /// When snapshot offsets beyond the logfile sizes are reported at startup.
+    pub offset_check: OffsetCheck,
+
+    /// When set, the tags of a logfile matching the same lines are reported.
+    pub overlap_check: Option<OverlapCheck>,
+
+    /// Maximum number of lines kept for each logfile to give the lines preceding a match, shared by all its tags.
+    pub context_max_lines: usize,
+
+    /// Maximum number of bytes kept for each logfile to give the lines preceding a match.
+    pub context_max_bytes: usize,
+
+    /// An optional claim on each logfile, so only one of several instances searches it.
+    pub claim: Option<Claim>,
+
+    /// Time in seconds between 2 searches of all logfiles with the `--follow` flag.
+    pub follow_interval: u64,
+
+    /// Time in seconds between 2 saves of the snapshots with the `--follow` flag.
+    pub follow_save_interval: u64,
+
+    /// With the `--follow` flag, only search logfiles again when their directory reports a change, or at each
+    /// `follow_interval` for all logfiles.
+    pub follow_events: bool,
+
+    /// Number of logfiles searched at the same time, each by its own thread.
+    pub max_threads: usize,
+
+    /// Whether each logfile is searched up to its end before the next one, or a slice at a time in turn.
+    pub fairness: Fairness,
+
+    /// Size of the slices of a logfile searched in turn, with the interleaved fairness.
+    pub quantum: Quantum,
+
+    /// A Tera template of the plugin output, replacing the default one. Only used with the tera feature.
+    pub output_template: Option<String>,
+
+    /// Whether Nagios performance data are added to the plugin output.
+    pub perfdata: bool,
+
+    /// The maximum size in bytes of the plugin output, 0 for no limit. Beyond, the least severe logfiles are left out.
+    pub max_output: usize,
+
+    /// The maximum number of callback calls kept for the next run for each logfile and tag once the callback failed,
+    /// the oldest ones being dropped beyond.
+    pub max_pending_callbacks: usize,
+}
+
+/// What to do when the configuration uses a feature not supported on the current platform, or not built in.
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+#[allow(non_camel_case_types)]
+pub enum UnsupportedPolicy {
+    /// the configuration file is not loaded
+    #[default]
+    reject,
+
+    /// the feature is disabled with a warning
+    downgrade,
+}
+
+/// Sanity check of snapshot offsets at startup. After logfiles are restored from a backup, offsets could be beyond the
+/// logfile sizes and nothing is read anymore.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct OffsetCheck {
+    /// ratio of the tags checked whose offset is beyond the logfile size, from which they're reported
+    pub ratio: f64,
+
+    /// Nagios status reported for these logfiles
+    pub status: NagiosError,
+}
+
+impl Default for OffsetCheck {
+    fn default() -> Self {
+        OffsetCheck {
+            ratio: DEFAULT_OFFSET_CHECK_RATIO,
+            status: NagiosError::WARNING,
+        }
+    }
+}
+
+/// Check of the tags of a logfile matching the same lines, which are counted twice in the plugin output.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct OverlapCheck {
+    /// ratio of the lines matched by a tag which are also matched by another one, from which both are reported
+    pub ratio: f64,
+
+    /// minimum number of lines matched by both tags during a run, for them to be reported
+    pub min_lines: u64,
+}
+
+impl Default for OverlapCheck {
+    fn default() -> Self {
+        OverlapCheck {
+            ratio: DEFAULT_OVERLAP_CHECK_RATIO,
+            min_lines: DEFAULT_OVERLAP_CHECK_MIN_LINES,
+        }
+    }
+}
+
+/// How the reading is shared between logfiles during a run.
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+#[allow(non_camel_case_types)]
+pub enum Fairness {
+    /// each logfile is searched up to its end before the next one
+    #[default]
+    sequential,
+
+    /// logfiles are searched one slice after the other, so a large backlog doesn't delay the other logfiles
+    interleaved,
+}
+
+/// The size of a slice with the interleaved fairness: a slice ends as soon as one of these limits is reached. 0 means
+/// no limit.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct Quantum {
+    /// number of lines read
+    pub lines: u64,
+
+    /// number of bytes read
+    pub bytes: u64,
+}
+
+impl Quantum {
+    /// Whether a slice is over after reading this number of lines and bytes.
+    pub fn is_reached(&self, lines: u64, bytes: u64) -> bool {
+        (self.lines != 0 && lines >= self.lines) || (self.bytes != 0 && bytes >= self.bytes)
+    }
+}
+
+impl Default for Quantum {
+    fn default() -> Self {
+        Quantum {
+            lines: DEFAULT_QUANTUM_LINES,
+            bytes: DEFAULT_QUANTUM_BYTES,
+        }
+    }
+}
+
+/// How often the offsets reached while searching a logfile are saved in the progress file of the snapshot: a checkpoint
+/// is saved as soon as one of these limits is reached. 0 means no limit.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct SnapshotCheckpoint {
+    /// number of lines read since the last checkpoint
+    pub lines: u64,
+
+    /// number of seconds since the last checkpoint
+    pub seconds: u64,
+}
+
+impl SnapshotCheckpoint {
+    /// Whether a checkpoint is due after reading this number of lines during this time.
+    pub fn is_due(&self, lines: u64, elapsed: Duration) -> bool {
+        lines != 0
+            && ((self.lines != 0 && lines >= self.lines)
+                || (self.seconds != 0 && elapsed.as_secs() >= self.seconds))
+    }
+}
+
+impl Default for SnapshotCheckpoint {
+    fn default() -> Self {
+        SnapshotCheckpoint {
+            lines: DEFAULT_SNAPSHOT_CHECKPOINT_LINES,
+            seconds: DEFAULT_SNAPSHOT_CHECKPOINT_SECONDS,
+        }
+    }
 }
 
 impl GlobalOptions {
@@ -50,7 +281,7 @@ impl GlobalOptions {
         self.global_vars
             .insert(prefix_var!("USER").to_string(), whoami::username());
         self.global_vars
-            .insert(prefix_var!("HOSTNAME").to_string(), whoami::hostname());
+            .insert(prefix_var!("HOSTNAME").to_string(), hostname());
         self.global_vars.insert(
             prefix_var!("PLATFORM").to_string(),
             whoami::platform().to_string(),
@@ -104,11 +335,42 @@ impl Default for GlobalOptions {
         GlobalOptions {
             script_path: path_var,
             output_dir: std::env::temp_dir(),
+            output_file: DEFAULT_OUTPUT_FILE.to_string(),
+            output_retention: DEFAULT_RETENTION,
+            output_max_size: 0,
+            protocol_file: DEFAULT_PROTOCOL_FILE.to_string(),
+            protocol_retention: DEFAULT_RETENTION,
+            protocol_max_size: 0,
             snapshot_file: None,
             snapshot_retention: DEFAULT_RETENTION,
+            snapshot_generations: 0,
+            snapshot_history: DEFAULT_SNAPSHOT_HISTORY,
+            snapshot_backend: SnapshotBackend::default(),
+            snapshot_checkpoint: None,
             global_vars: HashMap::new(),
             prescript: None,
             postscript: None,
+            search_timeout: None,
+            exporter: None,
+            report_socket: None,
+            output_format: OutputFormat::default(),
+            default_options: None,
+            unsupported_options: UnsupportedPolicy::default(),
+            offset_check: OffsetCheck::default(),
+            overlap_check: None,
+            context_max_lines: DEFAULT_CONTEXT_MAX_LINES,
+            context_max_bytes: DEFAULT_CONTEXT_MAX_BYTES,
+            claim: None,
+            follow_interval: DEFAULT_FOLLOW_INTERVAL,
+            follow_save_interval: DEFAULT_FOLLOW_SAVE_INTERVAL,
+            follow_events: false,
+            max_threads: DEFAULT_MAX_THREADS,
+            fairness: Fairness::default(),
+            quantum: Quantum::default(),
+            output_template: None,
+            perfdata: false,
+            max_output: 0,
+            max_pending_callbacks: DEFAULT_MAX_PENDING_CALLBACKS,
         }
     }
 }
@@ -125,6 +387,14 @@ mod tests {
 script_path: /usr/foo1
 snapshot_file: /usr/foo3/snap.foo
 output_dir: /usr/foo2
+output_file: clf_{hostname}.out
+output_retention: 3600
+output_max_size: 2097152
+protocol_file: clf_{hostname}.protocol
+protocol_max_size: 1048576
+search_timeout: 30
+output_format: ndjson
+context_max_lines: 20
         "#;
 
         let mut opts = GlobalOptions::from_str(yaml).expect("unable to read YAML");
@@ -132,10 +402,20 @@ output_dir: /usr/foo2
 
         assert_eq!(&opts.script_path, "/usr/foo1");
         assert_eq!(opts.output_dir, PathBuf::from("/usr/foo2"));
+        assert_eq!(&opts.output_file, "clf_{hostname}.out");
+        assert_eq!(opts.output_retention, 3600);
+        assert_eq!(opts.output_max_size, 2097152);
+        assert_eq!(&opts.protocol_file, "clf_{hostname}.protocol");
+        assert_eq!(opts.protocol_retention, DEFAULT_RETENTION);
+        assert_eq!(opts.protocol_max_size, 1048576);
         assert_eq!(
             opts.snapshot_file,
             Some(PathBuf::from("/usr/foo3/snap.foo"))
         );
+        assert_eq!(opts.search_timeout, Some(30));
+        assert_eq!(opts.output_format, OutputFormat::ndjson);
+        assert_eq!(opts.context_max_lines, 20);
+        assert_eq!(opts.context_max_bytes, DEFAULT_CONTEXT_MAX_BYTES);
 
         yaml = r#"
 script_path: /usr/foo1
@@ -151,7 +431,13 @@ vars:
         opts = GlobalOptions::from_str(yaml).expect("unable to read YAML");
         assert_eq!(&opts.script_path, "/usr/foo1");
         assert_eq!(opts.output_dir, PathBuf::from("/tmp"));
+        assert_eq!(&opts.output_file, DEFAULT_OUTPUT_FILE);
+        assert_eq!(opts.output_retention, DEFAULT_RETENTION);
+        assert_eq!(&opts.protocol_file, DEFAULT_PROTOCOL_FILE);
+        assert_eq!(opts.protocol_max_size, 0);
         assert_eq!(opts.snapshot_file, None);
+        assert!(opts.search_timeout.is_none());
+        assert_eq!(opts.output_format, OutputFormat::text);
 
         let vars = opts.global_vars;
         assert_eq!(vars.get("first_name").unwrap(), "Al");
@@ -159,4 +445,44 @@ vars:
         assert_eq!(vars.get("city").unwrap(), "Los Angeles");
         assert_eq!(vars.get("profession").unwrap(), "actor");
     }
+
+    #[test]
+    fn snapshot_checkpoint() {
+        let opts = GlobalOptions::from_str("script_path: /usr/foo1").unwrap();
+        assert!(opts.snapshot_checkpoint.is_none());
+
+        let opts = GlobalOptions::from_str("snapshot_checkpoint: { lines: 1000 }").unwrap();
+        let checkpoint = opts.snapshot_checkpoint.unwrap();
+        assert_eq!(checkpoint.lines, 1000);
+        assert_eq!(checkpoint.seconds, DEFAULT_SNAPSHOT_CHECKPOINT_SECONDS);
+        assert!(!checkpoint.is_due(999, Duration::from_secs(1)));
+        assert!(checkpoint.is_due(1000, Duration::from_secs(1)));
+        assert!(checkpoint.is_due(1, Duration::from_secs(30)));
+
+        // nothing read since the last checkpoint
+        assert!(!checkpoint.is_due(0, Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn fairness() {
+        let opts = GlobalOptions::from_str("script_path: /usr/foo1").unwrap();
+        assert_eq!(opts.fairness, Fairness::sequential);
+        assert_eq!(opts.quantum, Quantum::default());
+
+        let yaml = r#"
+fairness: interleaved
+quantum:
+  lines: 100
+        "#;
+        let opts = GlobalOptions::from_str(yaml).expect("unable to read YAML");
+        assert_eq!(opts.fairness, Fairness::interleaved);
+        assert_eq!(opts.quantum.lines, 100);
+        assert_eq!(opts.quantum.bytes, DEFAULT_QUANTUM_BYTES);
+
+        assert!(!opts.quantum.is_reached(99, 0));
+        assert!(opts.quantum.is_reached(100, 0));
+        assert!(opts.quantum.is_reached(1, DEFAULT_QUANTUM_BYTES));
+        let unlimited = Quantum { lines: 0, bytes: 0 };
+        assert!(!unlimited.is_reached(u64::MAX, u64::MAX));
+    }
 }
@@ -1,4 +1,6 @@
 //! Contains the logfile configuration for each logfile. These are not related to a search but only to the logfile itself: format (plain or JSON), optional lines to exclude, etc.
+use std::borrow::Cow;
+use std::convert::TryFrom;
 use std::path::PathBuf;
 
 use regex::Regex;
@@ -7,8 +9,12 @@ use serde_yaml::Value;
 
 use super::archive::LogArchive;
 use super::logsource::LogSource;
+use crate::logfile::container::ContainerFormat;
+use crate::misc::extension::{HashAlgorithm, HashSpec};
 use crate::misc::nagios::NagiosError;
-use crate::misc::util::DEFAULT_HASH_BUFFER_SIZE;
+use crate::misc::util::{
+    DEFAULT_HASH_BUFFER_SIZE, DEFAULT_NUL_CHECK_THRESHOLD, DEFAULT_SHRINK_WINDOW,
+};
 
 // a logfile could be of different format. Necessary to effectively read them
 #[derive(Debug, Deserialize, Clone, PartialEq)]
@@ -25,6 +31,19 @@ impl Default for LogFileFormat {
     }
 }
 
+/// What to do when a logfile is found shorter than the offset it was read up to, while its inode is the same.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[allow(non_camel_case_types)]
+pub enum ShrinkPolicy {
+    /// the logfile is handled like a rotated one, and read again from its beginning
+    #[default]
+    reset,
+
+    /// the last line read is looked for near the new end of the logfile, like when a daemon rewrites the logfile
+    /// without its first lines, and the search goes on after it. The logfile is reset if the line is not found
+    rescan_tail,
+}
+
 #[derive(Debug, Deserialize, Clone, Default)]
 #[serde(deny_unknown_fields)]
 /// Logfile attributes not dependant from a runtime search.
@@ -37,11 +56,22 @@ pub struct LogFileDef {
     #[serde(default)]
     pub format: LogFileFormat,
 
+    // character encoding of the logfile: when set, lines are decoded accordingly and capture groups holding invalid
+    // characters are flagged
+    pub encoding: Option<Encoding>,
+
+    // container logfile: the envelope of each line is removed before matching
+    pub container_format: Option<ContainerFormat>,
+
     // exclude some lines
     #[serde(default)]
     #[serde(deserialize_with = "to_regex")]
     pub exclude: Option<Regex>,
 
+    // number of header lines at the beginning of the logfile which are not matched, like the header row of a CSV file
+    #[serde(default)]
+    pub skip_header_lines: u64,
+
     // optional archive file name. If not specified, itr's just the same file + .1
     pub archive: Option<LogArchive>,
 
@@ -52,13 +82,161 @@ pub struct LogFileDef {
     // hash buffer size
     #[serde(default = "LogFileDef::default_hash_window")]
     pub hash_window: usize,
+
+    // algorithm of the rotation hash
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+
+    // number of bytes at the end of the logfile also hashed, so logfiles sharing the same header are told apart
+    #[serde(default)]
+    pub hash_tail: usize,
+
+    // what to do when the logfile shrinks in place
+    #[serde(default)]
+    pub shrink_policy: ShrinkPolicy,
+
+    // number of bytes before the end of a shrunk logfile where its last line read is looked for
+    #[serde(default = "LogFileDef::default_shrink_window")]
+    pub shrink_window: u64,
+
+    // report the logfile when too many NUL bytes are skipped, like in preallocated logfiles
+    pub nul_check: Option<NulCheck>,
+
+    // in follow mode, a path holding a glob pattern is expanded again before each search, so new logfiles are searched
+    #[serde(default)]
+    pub glob_rescan: bool,
+
+    // the glob pattern this logfile path was expanded from, if any
+    #[serde(skip)]
+    pub glob: Option<PathBuf>,
+}
+
+/// The character encoding of a logfile.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(try_from = "String")]
+#[allow(non_camel_case_types)]
+pub enum Encoding {
+    /// invalid UTF-8 sequences are replaced by U+FFFD
+    utf8,
+
+    /// ISO-8859-1: each byte is a character
+    latin1,
+
+    /// UTF-16 little endian, like most Windows logfiles
+    utf16le,
+
+    /// UTF-16 big endian
+    utf16be,
+
+    /// any other encoding compatible with ASCII, like windows-1252 or iso-8859-15, given by its WHATWG label
+    other(&'static encoding_rs::Encoding),
+}
+
+impl TryFrom<String> for Encoding {
+    type Error = String;
+
+    fn try_from(label: String) -> Result<Self, Self::Error> {
+        match label.as_str() {
+            "utf8" => Ok(Encoding::utf8),
+            "latin1" => Ok(Encoding::latin1),
+            "utf16le" => Ok(Encoding::utf16le),
+            "utf16be" => Ok(Encoding::utf16be),
+            _ => match encoding_rs::Encoding::for_label(label.as_bytes()) {
+                Some(encoding) if encoding == encoding_rs::UTF_16LE => Ok(Encoding::utf16le),
+                Some(encoding) if encoding == encoding_rs::UTF_16BE => Ok(Encoding::utf16be),
+                Some(encoding) if encoding.is_ascii_compatible() => Ok(Encoding::other(encoding)),
+                _ => Err(format!("encoding '{}' is not supported", label)),
+            },
+        }
+    }
+}
+
+impl Encoding {
+    /// Decodes a line. The boolean is `true` if replacement characters were introduced for invalid sequences.
+    pub fn decode<'a>(&self, bytes: &'a [u8]) -> (Cow<'a, str>, bool) {
+        match self {
+            Encoding::utf8 => match std::str::from_utf8(bytes) {
+                Ok(line) => (Cow::from(line), false),
+                Err(_) => (String::from_utf8_lossy(bytes), true),
+            },
+            Encoding::latin1 if bytes.is_ascii() => {
+                (Cow::from(std::str::from_utf8(bytes).unwrap()), false)
+            }
+            Encoding::latin1 => (
+                Cow::from(bytes.iter().map(|b| *b as char).collect::<String>()),
+                false,
+            ),
+            // the byte order mark could only be found at the beginning of the first line
+            Encoding::utf16le => encoding_rs::UTF_16LE.decode_with_bom_removal(bytes),
+            Encoding::utf16be => encoding_rs::UTF_16BE.decode_with_bom_removal(bytes),
+            Encoding::other(encoding) => encoding.decode_without_bom_handling(bytes),
+        }
+    }
+
+    /// Whether characters are made of 16-bit code units: lines end with a `\n` code unit, and NUL bytes are part of
+    /// characters.
+    pub fn is_utf16(&self) -> bool {
+        matches!(self, Encoding::utf16le | Encoding::utf16be)
+    }
+
+    /// The length of `bytes` truncated to at most `size` bytes, without splitting a character.
+    pub fn truncated_len(&self, bytes: &[u8], size: usize) -> usize {
+        let mut len = size.min(bytes.len());
+        match self {
+            // move back before the UTF-8 continuation bytes of a split character
+            Encoding::utf8 => {
+                while len > 0 && len < bytes.len() && bytes[len] & 0xC0 == 0x80 {
+                    len -= 1;
+                }
+            }
+            // keep whole code units, and don't end with the high surrogate of a pair
+            Encoding::utf16le | Encoding::utf16be if len < bytes.len() => {
+                len -= len % 2;
+                if len >= 2 {
+                    let unit = match self {
+                        Encoding::utf16le => u16::from_le_bytes([bytes[len - 2], bytes[len - 1]]),
+                        _ => u16::from_be_bytes([bytes[len - 2], bytes[len - 1]]),
+                    };
+                    if (0xD800..0xDC00).contains(&unit) {
+                        len -= 2;
+                    }
+                }
+            }
+            _ => (),
+        }
+        len
+    }
+}
+
+/// NUL bytes are always skipped when reading a logfile. Above a threshold, the logfile is reported.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct NulCheck {
+    /// number of NUL bytes skipped during a run from which the logfile is reported
+    pub threshold: u64,
+
+    /// Nagios status reported for the logfile
+    pub status: NagiosError,
+}
+
+impl Default for NulCheck {
+    fn default() -> Self {
+        NulCheck {
+            threshold: DEFAULT_NUL_CHECK_THRESHOLD,
+            status: NagiosError::WARNING,
+        }
+    }
 }
 
 impl LogFileDef {
-    /// Return the path variant from LogSource
+    /// Return the path variant from LogSource, or the key of a journal, remote or event log source
     pub fn path(&self) -> &PathBuf {
         match &self.path {
             LogSource::LogFile(path) => path,
+            LogSource::Journal(journal) => &journal.key,
+            LogSource::Remote(remote) => &remote.key,
+            LogSource::EventLog(eventlog) => &eventlog.key,
             _ => unimplemented!(
                 "LogSource::LogList not permitted here in {} !",
                 module_path!()
@@ -66,6 +244,15 @@ impl LogFileDef {
         }
     }
 
+    /// How the signature of the logfile is hashed
+    pub fn hash_spec(&self) -> HashSpec {
+        HashSpec {
+            algorithm: self.hash_algorithm,
+            head: self.hash_window,
+            tail: self.hash_tail,
+        }
+    }
+
     /// Get archive path
     pub fn archive_path(&self) -> PathBuf {
         match &self.archive {
@@ -91,10 +278,15 @@ impl LogFileDef {
     fn default_hash_window() -> usize {
         DEFAULT_HASH_BUFFER_SIZE
     }
+
+    // returns the default window where the last line of a shrunk logfile is looked for
+    fn default_shrink_window() -> u64 {
+        DEFAULT_SHRINK_WINDOW
+    }
 }
 
 /// A custom deserializer for the `exclude` field.
-fn to_regex<'de, D>(deserializer: D) -> Result<Option<Regex>, D::Error>
+pub fn to_regex<'de, D>(deserializer: D) -> Result<Option<Regex>, D::Error>
 where
     D: Deserializer<'de>,
 {
@@ -116,6 +308,7 @@ mod tests {
 path: /var/log/syslog
 format: json
 exclude: "^error"
+container_format: cri
 archive: 
     extension: xz
 "#;
@@ -123,6 +316,7 @@ archive:
         assert_eq!(lfd.path(), &PathBuf::from("/var/log/syslog"));
         assert_eq!(lfd.format, LogFileFormat::json);
         assert_eq!(lfd.exclude.as_ref().unwrap().as_str(), "^error");
+        assert_eq!(lfd.container_format, Some(ContainerFormat::cri));
         assert_eq!(lfd.archive_path(), PathBuf::from("/var/log/syslog.xz"));
 
         yaml = r#"
@@ -132,6 +326,7 @@ path: /var/log/syslog
         assert_eq!(lfd.path(), &PathBuf::from("/var/log/syslog"));
         assert_eq!(lfd.format, LogFileFormat::plain);
         assert!(lfd.exclude.is_none());
+        assert!(lfd.container_format.is_none());
         assert_eq!(lfd.archive_path(), PathBuf::from("/var/log/syslog.1"));
 
         // test with a regex error
@@ -165,4 +360,63 @@ format: json
         assert_eq!(lfd.format, LogFileFormat::json);
         assert!(lfd.exclude.is_none());
     }
+
+    #[test]
+    fn encoding() {
+        let lfd: LogFileDef =
+            serde_yaml::from_str("path: /var/log/syslog\nencoding: latin1").unwrap();
+        assert_eq!(lfd.encoding, Some(Encoding::latin1));
+
+        // "café" in ISO-8859-1, then in UTF-8
+        assert_eq!(
+            Encoding::latin1.decode(b"caf\xE9"),
+            (Cow::from("café"), false)
+        );
+        assert_eq!(
+            Encoding::utf8.decode("café".as_bytes()),
+            (Cow::from("café"), false)
+        );
+        assert_eq!(
+            Encoding::utf8.decode(b"caf\xE9"),
+            (Cow::from("caf\u{FFFD}"), true)
+        );
+
+        // a 2-byte character is not split
+        let bytes = "café".as_bytes();
+        assert_eq!(Encoding::utf8.truncated_len(bytes, 4), 3);
+        assert_eq!(Encoding::utf8.truncated_len(bytes, 5), 5);
+        assert_eq!(Encoding::utf8.truncated_len(bytes, 10), 5);
+        assert_eq!(Encoding::latin1.truncated_len(bytes, 4), 4);
+
+        // "café 😀" in UTF-16
+        let lfd: LogFileDef =
+            serde_yaml::from_str("path: /var/log/syslog\nencoding: utf-16le").unwrap();
+        assert_eq!(lfd.encoding, Some(Encoding::utf16le));
+        let text = "café 😀";
+        let le: Vec<u8> = text.encode_utf16().flat_map(|u| u.to_le_bytes()).collect();
+        let be: Vec<u8> = text.encode_utf16().flat_map(|u| u.to_be_bytes()).collect();
+        assert_eq!(Encoding::utf16le.decode(&le), (Cow::from(text), false));
+        assert_eq!(Encoding::utf16be.decode(&be), (Cow::from(text), false));
+        assert_eq!(
+            Encoding::utf16le.decode(&[b"\xFF\xFE".as_slice(), &le].concat()),
+            (Cow::from(text), false)
+        );
+
+        // the emoji is a surrogate pair, which is not split
+        assert_eq!(Encoding::utf16le.truncated_len(&le, 11), 10);
+        assert_eq!(Encoding::utf16be.truncated_len(&be, 12), 10);
+        assert_eq!(Encoding::utf16le.truncated_len(&le, 14), 14);
+
+        // single-byte encodings are given by their label
+        let lfd: LogFileDef =
+            serde_yaml::from_str("path: /var/log/syslog\nencoding: windows-1252").unwrap();
+        let encoding = lfd.encoding.unwrap();
+        assert_eq!(encoding, Encoding::other(encoding_rs::WINDOWS_1252));
+        assert_eq!(encoding.decode(b"\x80 5"), (Cow::from("€ 5"), false));
+        assert!(!encoding.is_utf16());
+
+        assert!(
+            serde_yaml::from_str::<LogFileDef>("path: /var/log/syslog\nencoding: ebcdic").is_err()
+        );
+    }
 }
@@ -1,14 +1,18 @@
 //! Contains the configuration of the name of a logfile: it could be either a single file, or a command giving the list of files.
+//! It could also be a selection of systemd journal entries, the logfiles of systemd units, a logfile of a remote host
+//! read over SSH, or the events of a Windows event log.
 use std::fmt::Display;
 use std::path::PathBuf;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+use crate::configuration::units::SystemdUnits;
 
 /// A `enum` matching either a logfile name if only a single logfile is defined, or a list
 /// of logfile names is case of command is given. This command is expected to return to the
 /// the standard output the list of files to check. One of the enum variant is loaded from
 /// the YAML configuration file.
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub enum LogSource {
     #[serde(rename = "path")]
@@ -19,18 +23,50 @@ pub enum LogSource {
 
     #[serde(rename = "cmd")]
     LogCommand(String),
+
+    #[serde(rename = "journal")]
+    Journal(JournalSource),
+
+    #[serde(rename = "from_systemd_units")]
+    SystemdUnits(SystemdUnits),
+
+    #[serde(rename = "remote")]
+    Remote(RemoteSource),
+
+    #[serde(rename = "eventlog")]
+    EventLog(EventLogSource),
 }
 
 impl LogSource {
     pub const fn is_path(&self) -> bool {
         matches!(*self, LogSource::LogFile(_))
     }
+
+    pub const fn is_journal(&self) -> bool {
+        matches!(*self, LogSource::Journal(_))
+    }
+
+    pub const fn is_eventlog(&self) -> bool {
+        matches!(*self, LogSource::EventLog(_))
+    }
+
+    pub const fn is_remote(&self) -> bool {
+        matches!(*self, LogSource::Remote(_))
+    }
+
+    /// The standard input is searched when the logfile path is `-`.
+    pub fn is_stdin(&self) -> bool {
+        matches!(self, LogSource::LogFile(path) if path.as_os_str() == "-")
+    }
 }
 
 impl Display for LogSource {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             LogSource::LogFile(logfile) => write!(f, "{}", logfile.display()),
+            LogSource::Journal(journal) => write!(f, "{}", journal.key.display()),
+            LogSource::Remote(remote) => write!(f, "{}", remote.key.display()),
+            LogSource::EventLog(eventlog) => write!(f, "{}", eventlog.key.display()),
             _ => unimplemented!("LogSource::LogList not permitted !"),
         }
     }
@@ -41,3 +77,230 @@ impl Default for LogSource {
         LogSource::LogFile(PathBuf::from(""))
     }
 }
+
+/// The systemd journal entries to search, as found in the configuration file. All criteria must match.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct JournalFilter {
+    /// entries of this systemd unit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+
+    /// entries of this syslog identifier
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<String>,
+
+    /// other journal matches, like `PRIORITY=3` or `_SYSTEMD_USER_UNIT=foo.service`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matches: Vec<String>,
+
+    /// journal files of this directory instead of the system journal
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub directory: Option<PathBuf>,
+}
+
+/// A journal source: the filter along with the path it's known by in the snapshot and in messages, like
+/// `journal:unit=sshd.service`.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(from = "JournalFilter")]
+pub struct JournalSource {
+    pub filter: JournalFilter,
+    pub key: PathBuf,
+}
+
+impl From<JournalFilter> for JournalSource {
+    fn from(filter: JournalFilter) -> Self {
+        let mut criteria = Vec::new();
+        if let Some(unit) = &filter.unit {
+            criteria.push(format!("unit={}", unit));
+        }
+        if let Some(identifier) = &filter.identifier {
+            criteria.push(format!("identifier={}", identifier));
+        }
+        criteria.extend(filter.matches.iter().cloned());
+        if let Some(directory) = &filter.directory {
+            criteria.push(format!("directory={}", directory.display()));
+        }
+
+        let key = if criteria.is_empty() {
+            PathBuf::from("journal")
+        } else {
+            PathBuf::from(format!("journal:{}", criteria.join(",")))
+        };
+
+        JournalSource { filter, key }
+    }
+}
+
+/// The events of a Windows event log to search, as found in the configuration file. All criteria must match.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct EventLogFilter {
+    /// name of the event log, like `Application` or `Microsoft-Windows-PowerShell/Operational`
+    pub channel: String,
+
+    /// events of these providers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub providers: Vec<String>,
+
+    /// events of this level or a more severe one: 1 critical, 2 error, 3 warning, 4 information
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<u8>,
+}
+
+/// An event log source: the filter along with the path it's known by in the snapshot and in messages, like
+/// `eventlog:Application,level=2`.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(from = "EventLogFilter")]
+pub struct EventLogSource {
+    pub filter: EventLogFilter,
+    pub key: PathBuf,
+}
+
+impl From<EventLogFilter> for EventLogSource {
+    fn from(filter: EventLogFilter) -> Self {
+        let mut criteria = vec![filter.channel.clone()];
+        criteria.extend(
+            filter
+                .providers
+                .iter()
+                .map(|provider| format!("provider={}", provider)),
+        );
+        if let Some(level) = filter.level {
+            criteria.push(format!("level={}", level));
+        }
+
+        let key = PathBuf::from(format!("eventlog:{}", criteria.join(",")));
+        EventLogSource { filter, key }
+    }
+}
+
+/// A logfile of a remote host, as found in the configuration file. It's read with the `ssh` client, so the host only
+/// needs a POSIX shell along with the `wc` and `tail` commands.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteFile {
+    /// host name or address, or a host of the ssh client configuration
+    pub host: String,
+
+    /// user to log in as, instead of the one of the ssh client configuration
+    pub user: Option<String>,
+
+    /// private key file, instead of the ones of the ssh client configuration or agent
+    pub key: Option<PathBuf>,
+
+    /// path of the logfile on the remote host
+    pub path: PathBuf,
+}
+
+/// A remote source: the remote logfile along with the path it's known by in the snapshot and in messages, like
+/// `ssh://root@appliance/var/log/messages`.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(from = "RemoteFile")]
+pub struct RemoteSource {
+    pub remote: RemoteFile,
+    pub key: PathBuf,
+}
+
+impl From<RemoteFile> for RemoteSource {
+    fn from(remote: RemoteFile) -> Self {
+        let user = remote
+            .user
+            .as_ref()
+            .map(|user| format!("{}@", user))
+            .unwrap_or_default();
+        let key = PathBuf::from(format!(
+            "ssh://{}{}/{}",
+            user,
+            remote.host,
+            remote.path.display().to_string().trim_start_matches('/')
+        ));
+
+        RemoteSource { remote, key }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn journal() {
+        let yaml = r#"
+journal:
+  unit: sshd.service
+  matches: ['PRIORITY=3']
+"#;
+        let source: LogSource = serde_yaml::from_str(yaml).expect("unable to read YAML");
+        assert!(source.is_journal() && !source.is_path());
+        assert_eq!(source.to_string(), "journal:unit=sshd.service,PRIORITY=3");
+
+        let source: LogSource = serde_yaml::from_str("journal: {}").unwrap();
+        assert_eq!(source.to_string(), "journal");
+
+        assert!(serde_yaml::from_str::<LogSource>("journal: {foo: bar}").is_err());
+    }
+
+    #[test]
+    fn eventlog() {
+        let yaml = r#"
+eventlog:
+  channel: Application
+  providers: ['MSSQLSERVER']
+  level: 2
+"#;
+        let source: LogSource = serde_yaml::from_str(yaml).expect("unable to read YAML");
+        assert!(source.is_eventlog() && !source.is_path() && !source.is_journal());
+        assert_eq!(
+            source.to_string(),
+            "eventlog:Application,provider=MSSQLSERVER,level=2"
+        );
+
+        let source: LogSource = serde_yaml::from_str("eventlog: {channel: System}").unwrap();
+        assert_eq!(source.to_string(), "eventlog:System");
+
+        assert!(serde_yaml::from_str::<LogSource>("eventlog: {}").is_err());
+    }
+
+    #[test]
+    fn remote() {
+        let yaml = r#"
+remote:
+  host: appliance
+  user: root
+  key: /home/clf/.ssh/id_ed25519
+  path: /var/log/messages
+"#;
+        let source: LogSource = serde_yaml::from_str(yaml).expect("unable to read YAML");
+        assert!(source.is_remote() && !source.is_path() && !source.is_journal());
+        assert_eq!(source.to_string(), "ssh://root@appliance/var/log/messages");
+
+        let source: LogSource =
+            serde_yaml::from_str("remote: {host: 10.0.0.1, path: messages}").unwrap();
+        assert_eq!(source.to_string(), "ssh://10.0.0.1/messages");
+
+        assert!(serde_yaml::from_str::<LogSource>("remote: {host: appliance}").is_err());
+    }
+
+    #[test]
+    fn stdin() {
+        let source: LogSource = serde_yaml::from_str("path: '-'").unwrap();
+        assert!(source.is_stdin() && source.is_path());
+
+        let source: LogSource = serde_yaml::from_str("path: /var/log/-").unwrap();
+        assert!(!source.is_stdin());
+    }
+
+    #[test]
+    fn systemd_units() {
+        let source: LogSource =
+            serde_yaml::from_str("from_systemd_units: {pattern: 'myapp-*'}").unwrap();
+        assert_eq!(
+            source,
+            LogSource::SystemdUnits(SystemdUnits {
+                pattern: "myapp-*".to_string()
+            })
+        );
+        assert!(!source.is_path() && !source.is_journal());
+    }
+}
@@ -3,13 +3,33 @@
 #[macro_use]
 pub mod callback;
 pub mod archive;
+pub mod catalog;
+pub mod claim;
 pub mod config;
+pub mod dedup;
+pub mod email;
+pub mod exporter;
 pub mod global;
 pub mod logfiledef;
 pub mod logsource;
+pub mod multiline;
 pub mod options;
 pub mod pattern;
+pub mod patterncheck;
+pub mod patterntest;
+pub mod prefilter;
+pub mod protocol;
+pub mod redact;
+pub mod report;
+pub mod requirement;
 pub mod script;
 pub mod search;
 pub mod tag;
+#[cfg(feature = "tera")]
+pub mod template;
+pub mod timestamp;
+pub mod tolerance;
+pub mod units;
 pub mod vars;
+pub mod wal;
+pub mod webhook;
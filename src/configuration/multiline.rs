@@ -0,0 +1,108 @@
+//! Contains the configuration of multiline records for a tag. Java stack traces and similar entries are written on
+//! several lines: those lines are gathered into a single record before patterns are matched, so the whole record is
+//! matched and given to the callback.
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::configuration::logfiledef::to_regex;
+use crate::fromstr;
+use crate::misc::util::DEFAULT_MULTILINE_MAX_LINES;
+
+/// How lines are gathered into records. A record starts with a line matching `start`, and goes on with the lines
+/// matching `continuation`. If only `start` is set, the lines not matching it continue the record. If only
+/// `continuation` is set, any other line starts a new record.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Multiline {
+    /// A line matching this regex starts a new record.
+    #[serde(default, deserialize_with = "to_regex")]
+    pub start: Option<Regex>,
+
+    /// A line matching this regex continues the current record.
+    #[serde(default, deserialize_with = "to_regex")]
+    pub continuation: Option<Regex>,
+
+    /// Number of seconds without the logfile being written after which the last record is complete. Until then, it's
+    /// read again at the next run. 0 means the last record is complete at the end of the logfile.
+    #[serde(default)]
+    pub timeout: u64,
+
+    /// Maximum number of lines of a record: further lines start a new record.
+    #[serde(default = "Multiline::default_max_lines")]
+    pub max_lines: usize,
+}
+
+impl Multiline {
+    /// Default maximum number of lines of a record
+    fn default_max_lines() -> usize {
+        DEFAULT_MULTILINE_MAX_LINES
+    }
+
+    /// Returns `true` if the line is part of the record started on a previous line.
+    pub fn is_continuation(&self, line: &str) -> bool {
+        match (&self.start, &self.continuation) {
+            (_, Some(continuation)) => continuation.is_match(line),
+            (Some(start), None) => !start.is_match(line),
+            (None, None) => false,
+        }
+    }
+
+    /// Returns `true` if the last record of the logfile is complete, because the logfile was not written for `timeout`
+    /// seconds. Without a modification time, like for the journal, the record is deemed complete.
+    pub fn is_idle<P: AsRef<Path>>(&self, path: P) -> bool {
+        if self.timeout == 0 {
+            return true;
+        }
+
+        match std::fs::metadata(path).and_then(|m| m.modified()) {
+            Ok(modified) => SystemTime::now()
+                .duration_since(modified)
+                .is_ok_and(|idle| idle >= Duration::from_secs(self.timeout)),
+            Err(_) => true,
+        }
+    }
+}
+
+// Auto-implement FromStr
+fromstr!(Multiline);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn multiline() {
+        let yaml = r#"
+            start: '^\d{4}-\d{2}-\d{2} '
+            timeout: 5
+        "#;
+        let multiline = Multiline::from_str(yaml).expect("unable to read YAML");
+        assert_eq!(multiline.timeout, 5);
+        assert_eq!(multiline.max_lines, DEFAULT_MULTILINE_MAX_LINES);
+        assert!(!multiline.is_continuation("2021-01-28 ERROR java.lang.NullPointerException"));
+        assert!(multiline.is_continuation("    at com.example.Main.main(Main.java:5)"));
+
+        let yaml = r#"
+            start: '^\d{4}-\d{2}-\d{2} '
+            continuation: '^\s+at |^Caused by: '
+            max_lines: 100
+        "#;
+        let multiline = Multiline::from_str(yaml).expect("unable to read YAML");
+        assert_eq!(multiline.max_lines, 100);
+        assert!(multiline.is_continuation("Caused by: java.io.IOException"));
+        assert!(!multiline.is_continuation("some other line"));
+
+        // the logfile was just written
+        let path = std::env::temp_dir().join("clf_multiline.log");
+        std::fs::write(&path, "line1\n").unwrap();
+        assert!(!Multiline::from_str("{ start: '^\\S', timeout: 60 }")
+            .unwrap()
+            .is_idle(&path));
+        assert!(multiline.is_idle(&path));
+        let _ = std::fs::remove_file(&path);
+    }
+}
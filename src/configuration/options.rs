@@ -1,9 +1,11 @@
 //! Configuration options which apply only to a search.
 use std::convert::TryFrom;
+use std::str::FromStr;
 
 use serde::Deserialize;
 
-use crate::misc::error::{AppCustomErrorKind, AppError};
+use crate::configuration::pattern::{PatternMatchResult, PatternType};
+use crate::misc::error::{AppCustomErrorKind, AppError, AppResult};
 
 /// A list of options which are specific to a search. They might or might not be used. If an option is not present, it's deemed false.
 /// By default, all options are either false, or use the default corresponding type.
@@ -13,19 +15,27 @@ pub struct SearchOptions {
     /// If `true`, the defined script will be run a first match.
     pub runcallback: bool,
 
-    /// If `true`, the matching line will be saved in an output file.
-    // TODO:
+    /// If `true`, the matching line will be saved in the output file of the run.
     pub keepoutput: bool,
 
     /// If `true`, the logfile will be search from the beginning, regardless of any saved offset.
     pub rewind: bool,
 
+    /// When rewinding, how far back from the end of the logfile the search starts at most.
+    pub rewindlimit: Option<RewindLimit>,
+
     /// a number which denotes how many lines have to match a pattern until they are considered a critical error
     pub criticalthreshold: u64,
 
     /// a number which denotes how many lines have to match a pattern until they are considered a warning error
     pub warningthreshold: u64,
 
+    /// a threshold for critical matches applied to each value of a capture group, instead of criticalthreshold
+    pub criticalthreshold_per: Option<ThresholdPer>,
+
+    /// a threshold for warning matches applied to each value of a capture group, instead of warningthreshold
+    pub warningthreshold_per: Option<ThresholdPer>,
+
     // controls whether the matching lines are written to a protocol file for later investigation
     // TODO:
     pub protocol: bool,
@@ -46,6 +56,9 @@ pub struct SearchOptions {
     /// The number of times a potential script will be called, at most.
     pub runlimit: u64,
 
+    /// At most this number of callback calls during a time window, across runs.
+    pub rate_limit: Option<RateLimit>,
+
     /// truncate the read line at specified value before lookup
     pub truncate: usize,
 
@@ -54,6 +67,300 @@ pub struct SearchOptions {
 
     /// If set, run callback if OK pattern is found
     pub runifok: bool,
+
+    /// If set, the hash of the last processed line is saved and checked at the next run to detect in-place edits
+    pub linehash: bool,
+
+    /// When the last line hash doesn't match, the number of bytes to move back before the last line to rescan
+    pub rescanbytes: u64,
+
+    /// When the last line hash doesn't match, the number of lines to move back before the last line to rescan
+    pub rescanlines: u64,
+
+    /// If set, the raw bytes of the matched line are given to the callback along with the decoded line
+    pub rawline: bool,
+
+    /// If the line is longer than this number of bytes, CLF_LINE is split into CLF_LINE_1..N chunks
+    pub chunksize: usize,
+
+    /// The number of lines preceding a match given to the callback in CLF_CONTEXT
+    pub contextlines: usize,
+
+    /// The number of matches notified whose hash is kept, so they're not notified again if read again
+    pub sentset: usize,
+
+    /// Which capture groups are given to the callback as variables: all of them, only named ones or none
+    pub capture_vars: CaptureVars,
+
+    /// The prefix of capture group variables instead of CLF_CG_
+    pub capture_prefix: Option<String>,
+
+    /// Identical matches within this window are only notified once, the duplicates being counted
+    pub dedup: Option<DupWindow>,
+
+    /// If set, matches are identical when their capture groups are, instead of their whole line
+    pub dedup_captures: bool,
+
+    /// The comma-separated list these options were built from
+    pub list: String,
+}
+
+impl SearchOptions {
+    /// Builds options from a list of default options followed by these ones. For integer options, these ones win
+    /// while boolean options are cumulative.
+    pub fn with_defaults(&self, defaults: &[&str]) -> AppResult<SearchOptions> {
+        let mut list = defaults.to_vec();
+        list.push(&self.list);
+
+        SearchOptions::try_from(list.join(","))
+    }
+
+    /// The options as a comma-separated list where each option is found once. Like when options are merged, the last
+    /// value of an option wins.
+    pub fn normalized_list(&self) -> String {
+        let mut options: Vec<(&str, &str)> = Vec::new();
+        for opt in self
+            .list
+            .split(',')
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+        {
+            let key = opt.split('=').next().unwrap_or(opt).trim();
+            options.retain(|(k, _)| *k != key);
+            options.push((key, opt));
+        }
+
+        let options: Vec<_> = options.iter().map(|(_, opt)| *opt).collect();
+        options.join(", ")
+    }
+
+    /// The key of the counter a match is added to, when a threshold per capture group value is set for its type.
+    pub fn threshold_key(&self, pattern_match: &PatternMatchResult, text: &str) -> Option<String> {
+        let per = match pattern_match.pattern_type {
+            PatternType::critical => self.criticalthreshold_per.as_ref(),
+            PatternType::warning => self.warningthreshold_per.as_ref(),
+            PatternType::ok => None,
+        }?;
+        Some(per.key(pattern_match, text))
+    }
+}
+
+/// Which capture groups of a match are given to the callback as variables, as found in the `capture_vars` option.
+/// Tags matching lots of lines with many groups would otherwise flood callbacks with positional variables.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum CaptureVars {
+    #[default]
+    all,
+    named_only,
+    none,
+}
+
+impl CaptureVars {
+    /// Whether a capture group, named or not, is given to the callback.
+    pub fn selects(&self, name: Option<&str>) -> bool {
+        match self {
+            CaptureVars::all => true,
+            CaptureVars::named_only => name.is_some(),
+            CaptureVars::none => false,
+        }
+    }
+}
+
+impl FromStr for CaptureVars {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(CaptureVars::all),
+            "named_only" => Ok(CaptureVars::named_only),
+            "none" => Ok(CaptureVars::none),
+            _ => Err(AppError::new_custom(
+                AppCustomErrorKind::UnsupportedSearchOption,
+                &format!(
+                    "capture_vars value: {} is not one of all, named_only or none",
+                    s
+                ),
+            )),
+        }
+    }
+}
+
+/// How far back from the end of the logfile a rewind starts at most, as found in the `rewindlimit` option: a number of
+/// bytes like `500MB`, a number of lines like `10000lines` or a duration like `2h`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RewindLimit {
+    Bytes(u64),
+    Lines(u64),
+    Seconds(u64),
+}
+
+impl FromStr for RewindLimit {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        type Unit = (&'static str, fn(u64) -> RewindLimit);
+
+        // longest units first, so `mb` is not taken for minutes
+        const UNITS: &[Unit] = &[
+            ("lines", RewindLimit::Lines),
+            ("kb", |n| RewindLimit::Bytes(n << 10)),
+            ("mb", |n| RewindLimit::Bytes(n << 20)),
+            ("gb", |n| RewindLimit::Bytes(n << 30)),
+            ("s", RewindLimit::Seconds),
+            ("m", |n| RewindLimit::Seconds(n * 60)),
+            ("h", |n| RewindLimit::Seconds(n * 3600)),
+            ("d", |n| RewindLimit::Seconds(n * 86400)),
+            ("", RewindLimit::Bytes),
+        ];
+
+        let lower = s.trim().to_lowercase();
+        UNITS
+            .iter()
+            .find_map(|(unit, limit)| {
+                let n = lower.strip_suffix(unit)?.trim().parse::<u64>().ok()?;
+                Some(limit(n))
+            })
+            .ok_or_else(|| {
+                AppError::new_custom(
+                    AppCustomErrorKind::UnsupportedSearchOption,
+                    &format!(
+                        "rewindlimit value: {} is not a number of bytes, lines or a duration",
+                        s
+                    ),
+                )
+            })
+    }
+}
+
+/// The window identical matches are deduplicated within, as found in the `dedup` option: a number of lines like `100`
+/// or `100lines`, or a duration like `10m`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DupWindow {
+    Lines(u64),
+    Seconds(u64),
+}
+
+impl DupWindow {
+    /// Whether a match at line `line` and time `now` is within the window of a match at line `first_line` and time
+    /// `first_time`. Line numbers start again when the logfile is rotated, so an earlier line is never within.
+    pub fn contains(&self, first_line: u64, first_time: u64, line: u64, now: u64) -> bool {
+        match *self {
+            DupWindow::Lines(n) => line >= first_line && line - first_line < n,
+            DupWindow::Seconds(n) => now.saturating_sub(first_time) < n,
+        }
+    }
+}
+
+impl FromStr for DupWindow {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const UNITS: &[(&str, u64)] = &[("s", 1), ("m", 60), ("h", 3600), ("d", 86400)];
+
+        let lower = s.trim().to_lowercase();
+        let lines = lower.strip_suffix("lines").unwrap_or(&lower);
+        let window = match lines.trim().parse::<u64>() {
+            Ok(n) => Some(DupWindow::Lines(n)),
+            Err(_) => UNITS.iter().find_map(|(unit, secs)| {
+                let n = lower.strip_suffix(unit)?.trim().parse::<u64>().ok()?;
+                Some(DupWindow::Seconds(n * secs))
+            }),
+        };
+
+        window
+            .filter(|x| !matches!(x, DupWindow::Lines(0) | DupWindow::Seconds(0)))
+            .ok_or_else(|| {
+                AppError::new_custom(
+                    AppCustomErrorKind::UnsupportedSearchOption,
+                    &format!("dedup value: {} is not a number of lines or a duration", s),
+                )
+            })
+    }
+}
+
+/// A number of callback calls allowed during a time window, as found in the `rate_limit` option: `10/60s` allows 10
+/// calls a minute. The window is a number of seconds, optionally followed by `s`, `m`, `h` or `d`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub calls: u64,
+    pub window: u64,
+}
+
+impl FromStr for RateLimit {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const UNITS: &[(&str, u64)] = &[("s", 1), ("m", 60), ("h", 3600), ("d", 86400), ("", 1)];
+
+        let rate_limit = s.split_once('/').and_then(|(calls, window)| {
+            let window = window.trim().to_lowercase();
+            let window = UNITS.iter().find_map(|(unit, secs)| {
+                Some(window.strip_suffix(unit)?.parse::<u64>().ok()? * secs)
+            })?;
+            Some(RateLimit {
+                calls: calls.trim().parse().ok()?,
+                window,
+            })
+        });
+
+        rate_limit.filter(|x| x.window != 0).ok_or_else(|| {
+            AppError::new_custom(
+                AppCustomErrorKind::UnsupportedSearchOption,
+                &format!("rate_limit value: {} is not like calls/window", s),
+            )
+        })
+    }
+}
+
+/// A threshold applied to each value of a capture group, as found in the `criticalthreshold_per` and
+/// `warningthreshold_per` options: `CLF_CG_host=5` counts matches per value of the `host` capture group, and the
+/// threshold is reached when the matches of a single host exceed 5. Capture groups could also be given by position,
+/// like `CLF_CG_1=5`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThresholdPer {
+    pub group: String,
+    pub limit: u64,
+}
+
+impl ThresholdPer {
+    /// The key of the counter of a match: the capture group and its value, which is empty if the group didn't match.
+    pub fn key(&self, pattern_match: &PatternMatchResult, text: &str) -> String {
+        let value = pattern_match
+            .regex
+            .captures(pattern_match.text(text))
+            .and_then(|caps| match self.group.parse::<usize>() {
+                Ok(i) => caps.get(i),
+                Err(_) => caps.name(&self.group),
+            })
+            .map_or("", |m| m.as_str());
+
+        format!("{}={}", self.group, value)
+    }
+}
+
+impl FromStr for ThresholdPer {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let threshold = s.split_once('=').and_then(|(var, limit)| {
+            let group = var.trim().strip_prefix("CLF_CG_")?;
+            if group.is_empty() {
+                return None;
+            }
+            Some(ThresholdPer {
+                group: group.to_string(),
+                limit: limit.trim().parse().ok()?,
+            })
+        });
+
+        threshold.ok_or_else(|| {
+            AppError::new_custom(
+                AppCustomErrorKind::UnsupportedSearchOption,
+                &format!("threshold value: {} is not like CLF_CG_name=number", s),
+            )
+        })
+    }
 }
 
 /// Convenient macro to add a boolean option
@@ -88,8 +395,11 @@ impl TryFrom<String> for SearchOptions {
             "runcallback",
             "keepoutput",
             "rewind",
+            "rewindlimit",
             "criticalthreshold",
             "warningthreshold",
+            "criticalthreshold_per",
+            "warningthreshold_per",
             "protocol",
             "savethresholds",
             "sticky",
@@ -98,6 +408,17 @@ impl TryFrom<String> for SearchOptions {
             "truncate",
             "stopat",
             "runifok",
+            "linehash",
+            "rescanbytes",
+            "rescanlines",
+            "rawline",
+            "chunksize",
+            "contextlines",
+            "rate_limit",
+            "sentset",
+            "capture_vars",
+            "capture_prefix",
+            "dedup",
         ];
 
         // create a default options structure
@@ -107,8 +428,12 @@ impl TryFrom<String> for SearchOptions {
         opt.runlimit = std::u64::MAX;
         opt.stopat = std::u64::MAX;
 
-        // convert the input list to a vector
-        let opt_list: Vec<_> = option_list.split(',').map(|x| x.trim()).collect();
+        // convert the input list to a vector, empty options being ignored
+        let opt_list: Vec<_> = option_list
+            .split(',')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .collect();
 
         // checks if there're any invalid arguments
         for opt in &opt_list {
@@ -130,7 +455,10 @@ impl TryFrom<String> for SearchOptions {
             savethresholds,
             protocol,
             fastforward,
-            runifok
+            runifok,
+            linehash,
+            rawline,
+            dedup_captures
         );
 
         // other options like key=value if any
@@ -154,9 +482,53 @@ impl TryFrom<String> for SearchOptions {
                 add_typed_option!(splitted_options, runlimit, opt, u64);
                 add_typed_option!(splitted_options, truncate, opt, usize);
                 add_typed_option!(splitted_options, stopat, opt, u64);
+                add_typed_option!(splitted_options, rescanbytes, opt, u64);
+                add_typed_option!(splitted_options, rescanlines, opt, u64);
+                add_typed_option!(splitted_options, chunksize, opt, usize);
+                add_typed_option!(splitted_options, contextlines, opt, usize);
+                add_typed_option!(splitted_options, sentset, opt, usize);
+
+                // an invalid limit is an error, as an unbounded rewind is what it prevents
+                if _key == "rewindlimit" {
+                    opt.rewindlimit = Some(_value.parse::<RewindLimit>()?);
+                }
+                if _key == "rate_limit" {
+                    opt.rate_limit = Some(_value.parse::<RateLimit>()?);
+                }
+                if _key == "dedup" {
+                    opt.dedup = Some(_value.parse::<DupWindow>()?);
+                }
+                if _key == "capture_vars" {
+                    opt.capture_vars = _value.parse::<CaptureVars>()?;
+                }
+
+                // the prefix ends up in environment variable names
+                if _key == "capture_prefix" {
+                    if _value.is_empty()
+                        || !_value
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    {
+                        return Err(AppError::new_custom(
+                            AppCustomErrorKind::UnsupportedSearchOption,
+                            &format!("capture_prefix value: {} is not a variable name", _value),
+                        ));
+                    }
+                    opt.capture_prefix = Some(_value.to_string());
+                }
+
+                // the value holds its own '='
+                let per_value = kv.split_once('=').map_or("", |(_, v)| v);
+                if _key == "criticalthreshold_per" {
+                    opt.criticalthreshold_per = Some(per_value.parse::<ThresholdPer>()?);
+                }
+                if _key == "warningthreshold_per" {
+                    opt.warningthreshold_per = Some(per_value.parse::<ThresholdPer>()?);
+                }
             }
         }
 
+        opt.list = option_list;
         Ok(opt)
     }
 }
@@ -167,7 +539,7 @@ mod tests {
 
     #[test]
     fn search_options() {
-        let opts = SearchOptions::try_from("runcallback, keepoutput, rewind, criticalthreshold=10, warningthreshold=15, protocol, savethresholds, sticky=5, runlimit=10, truncate=80".to_string()).unwrap();
+        let opts = SearchOptions::try_from("runcallback, keepoutput, rewind, criticalthreshold=10, warningthreshold=15, protocol, savethresholds, sticky=5, runlimit=10, truncate=80, linehash, rescanbytes=512, rescanlines=3, rawline, chunksize=4096, contextlines=3, sentset=1000".to_string()).unwrap();
 
         assert!(opts.runcallback);
         assert!(opts.keepoutput);
@@ -181,6 +553,182 @@ mod tests {
         assert_eq!(opts.criticalthreshold, 10);
         assert_eq!(opts.runlimit, 10);
         assert_eq!(opts.truncate, 80);
+        assert!(opts.linehash);
+        assert_eq!(opts.rescanbytes, 512);
+        assert_eq!(opts.rescanlines, 3);
+        assert!(opts.rawline);
+        assert_eq!(opts.chunksize, 4096);
+        assert_eq!(opts.contextlines, 3);
+        assert_eq!(opts.sentset, 1000);
+        assert_eq!(opts.rewindlimit, None);
         //assert_eq!(&opts.logfilemissing.unwrap(), "foo");
     }
+
+    #[test]
+    fn rewind_limit() {
+        let opts = SearchOptions::try_from("rewind, rewindlimit=500MB".to_string()).unwrap();
+        assert!(opts.rewind);
+        assert_eq!(
+            opts.rewindlimit,
+            Some(RewindLimit::Bytes(500 * 1024 * 1024))
+        );
+
+        assert_eq!(
+            RewindLimit::from_str("4096").unwrap(),
+            RewindLimit::Bytes(4096)
+        );
+        assert_eq!(
+            RewindLimit::from_str("10000lines").unwrap(),
+            RewindLimit::Lines(10000)
+        );
+        assert_eq!(
+            RewindLimit::from_str("2h").unwrap(),
+            RewindLimit::Seconds(7200)
+        );
+        assert_eq!(
+            RewindLimit::from_str("30m").unwrap(),
+            RewindLimit::Seconds(1800)
+        );
+        assert!(RewindLimit::from_str("2 weeks").is_err());
+        assert!(SearchOptions::try_from("rewindlimit=foo".to_string()).is_err());
+    }
+
+    #[test]
+    fn dedup() {
+        let opts =
+            SearchOptions::try_from("runcallback, dedup=100, dedup_captures".to_string()).unwrap();
+        assert_eq!(opts.dedup, Some(DupWindow::Lines(100)));
+        assert!(opts.dedup_captures);
+
+        let opts = SearchOptions::try_from("dedup=10m".to_string()).unwrap();
+        assert_eq!(opts.dedup, Some(DupWindow::Seconds(600)));
+        assert!(!opts.dedup_captures);
+        assert_eq!(
+            DupWindow::from_str("50lines").unwrap(),
+            DupWindow::Lines(50)
+        );
+        assert!(SearchOptions::try_from("dedup=0".to_string()).is_err());
+        assert!(SearchOptions::try_from("dedup=often".to_string()).is_err());
+
+        let window = DupWindow::Lines(10);
+        assert!(window.contains(5, 0, 14, 0));
+        assert!(!window.contains(5, 0, 15, 0));
+        assert!(!window.contains(5, 0, 2, 0));
+        let window = DupWindow::Seconds(60);
+        assert!(window.contains(5, 1000, 2, 1059));
+        assert!(!window.contains(5, 1000, 2, 1060));
+    }
+
+    #[test]
+    fn rate_limit() {
+        let opts = SearchOptions::try_from("runcallback, rate_limit=10/60s".to_string()).unwrap();
+        assert_eq!(
+            opts.rate_limit,
+            Some(RateLimit {
+                calls: 10,
+                window: 60
+            })
+        );
+
+        assert_eq!(RateLimit::from_str("100/1h").unwrap().window, 3600);
+        assert_eq!(RateLimit::from_str("5 / 30").unwrap().calls, 5);
+        assert!(RateLimit::from_str("10").is_err());
+        assert!(RateLimit::from_str("10/0s").is_err());
+        assert!(SearchOptions::try_from("rate_limit=ten/60s".to_string()).is_err());
+    }
+
+    #[test]
+    fn capture_vars() {
+        let opts = SearchOptions::try_from(
+            "runcallback, capture_vars=named_only, capture_prefix=HTTP_".to_string(),
+        )
+        .unwrap();
+        assert_eq!(opts.capture_vars, CaptureVars::named_only);
+        assert_eq!(opts.capture_prefix.as_deref(), Some("HTTP_"));
+
+        let opts = SearchOptions::try_from("runcallback".to_string()).unwrap();
+        assert_eq!(opts.capture_vars, CaptureVars::all);
+        assert!(opts.capture_prefix.is_none());
+
+        assert!(SearchOptions::try_from("capture_vars=some".to_string()).is_err());
+        assert!(SearchOptions::try_from("capture_prefix=HTTP-".to_string()).is_err());
+    }
+
+    #[test]
+    fn threshold_per() {
+        let opts = SearchOptions::try_from(
+            "runcallback, criticalthreshold_per=CLF_CG_host=5, warningthreshold_per = CLF_CG_1 = 2"
+                .to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            opts.criticalthreshold_per,
+            Some(ThresholdPer {
+                group: "host".to_string(),
+                limit: 5
+            })
+        );
+        assert_eq!(opts.warningthreshold_per.as_ref().unwrap().group, "1");
+        assert_eq!(opts.criticalthreshold, 0);
+
+        let re = regex::Regex::new(r"^(\w+) login failed for (?P<host>\w+)?").unwrap();
+        let key = |pattern_type, line| {
+            let pattern_match = PatternMatchResult {
+                pattern_type,
+                regex: &re,
+                value: None,
+            };
+            opts.threshold_key(&pattern_match, line)
+        };
+        assert_eq!(
+            key(PatternType::critical, "john login failed for web1").unwrap(),
+            "host=web1"
+        );
+        assert_eq!(
+            key(PatternType::warning, "john login failed for web1").unwrap(),
+            "1=john"
+        );
+        assert_eq!(
+            key(PatternType::critical, "john login failed for ").unwrap(),
+            "host="
+        );
+        assert!(key(PatternType::ok, "john login failed for web1").is_none());
+
+        assert!(ThresholdPer::from_str("host=5").is_err());
+        assert!(ThresholdPer::from_str("CLF_CG_=5").is_err());
+        assert!(ThresholdPer::from_str("CLF_CG_host").is_err());
+        assert!(
+            SearchOptions::try_from("criticalthreshold_per=CLF_CG_host=five".to_string()).is_err()
+        );
+    }
+
+    #[test]
+    fn with_defaults() {
+        let opts = SearchOptions::try_from("criticalthreshold=5, rewind".to_string()).unwrap();
+        let merged = opts
+            .with_defaults(&["savethresholds, criticalthreshold=10", "runcallback,"])
+            .unwrap();
+
+        assert!(merged.savethresholds);
+        assert!(merged.runcallback);
+        assert!(merged.rewind);
+        assert_eq!(merged.criticalthreshold, 5);
+        assert_eq!(merged.runlimit, u64::MAX);
+
+        // tag without any option
+        let merged = SearchOptions::default()
+            .with_defaults(&["warningthreshold=3"])
+            .unwrap();
+        assert_eq!(merged.warningthreshold, 3);
+
+        assert!(opts.with_defaults(&["foo"]).is_err());
+
+        let merged = opts
+            .with_defaults(&["savethresholds, criticalthreshold=10", "runcallback,"])
+            .unwrap();
+        assert_eq!(
+            merged.normalized_list(),
+            "savethresholds, runcallback, criticalthreshold=5, rewind"
+        );
+    }
 }
@@ -8,8 +8,10 @@ use log::{debug, trace};
 use regex::{Regex, RegexSet};
 use serde::{Deserialize, Serialize};
 
+use crate::configuration::{catalog::Preset, prefilter::Prefilter};
 use crate::context;
 use crate::fromstr;
+use crate::logfile::jsonline::JsonLine;
 use crate::misc::error::{AppCustomErrorKind, AppError};
 
 /// A helper structure for deserializing into a `RegexVec` automatically from a `Vec<String>`.
@@ -66,6 +68,9 @@ impl TryFrom<Vec<String>> for RegexBundle {
 #[derive(Debug, Deserialize, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct Pattern {
+    /// For JSON logfiles, the field whose value is matched instead of the whole line, like `level` or `http.status`.
+    field: Option<String>,
+
     /// A vector of compiled `Regex` structs which are hence all valid.
     regexes: RegexVec,
 
@@ -75,11 +80,37 @@ pub struct Pattern {
 }
 
 impl Pattern {
+    /// Builds a pattern from a list of regexes without exceptions, or `None` if the list is empty.
+    fn from_list(list: &[&str]) -> Result<Option<Self>, AppError> {
+        if list.is_empty() {
+            return Ok(None);
+        }
+
+        let regexes = RegexVec::try_from(list.iter().map(|re| re.to_string()).collect::<Vec<_>>())?;
+        Ok(Some(Pattern {
+            field: None,
+            regexes,
+            exceptions: None,
+        }))
+    }
+
+    /// The text matched by this pattern: the whole line, or the value of its field if the line is a JSON object having
+    /// this field.
+    fn text<'t>(&self, line: &'t str, json: Option<&'t JsonLine>) -> Option<&'t str> {
+        match &self.field {
+            None => Some(line),
+            Some(field) => json?.get(field),
+        }
+    }
+
+    // the text matched, if it's a field value
+    fn value(&self, text: &str) -> Option<String> {
+        self.field.as_ref().map(|_| text.to_string())
+    }
+
     /// Tests if `text` matches any of the regexes in the set.
     fn is_exception(&self, text: &str) -> bool {
-        self.exceptions
-            .as_ref()
-            .map_or(false, |x| x.0.is_match(text))
+        self.exceptions.as_ref().is_some_and(|x| x.0.is_match(text))
     }
 
     /// Try to find a match in the string `s` corresponding to the `regexes` list struct field,
@@ -147,36 +178,119 @@ impl From<&PatternType> for &'static str {
 }
 /// A structure combining patterns into 3 categories: *critical*, *warning* and *ok*.
 #[derive(Debug, Deserialize, Clone)]
+#[serde(try_from = "PatternSetDef")]
 pub struct PatternSet {
     pub critical: Option<Pattern>,
     pub warning: Option<Pattern>,
     pub ok: Option<Pattern>,
+
+    /// literals derived from the regexes, one of which is found in any matching line
+    prefilter: Option<Prefilter>,
+}
+
+/// A helper structure for deserializing a `PatternSet`, which could use a preset from the catalog. Categories defined
+/// along with a preset replace those of the preset.
+#[derive(Debug, Deserialize)]
+struct PatternSetDef {
+    preset: Option<Preset>,
+    critical: Option<Pattern>,
+    warning: Option<Pattern>,
+    ok: Option<Pattern>,
+}
+
+impl TryFrom<PatternSetDef> for PatternSet {
+    type Error = AppError;
+
+    fn try_from(def: PatternSetDef) -> Result<Self, Self::Error> {
+        let mut set = PatternSet {
+            critical: def.critical,
+            warning: def.warning,
+            ok: def.ok,
+            prefilter: None,
+        };
+
+        if let Some(preset) = def.preset {
+            let regexes = preset.regexes();
+            if set.critical.is_none() {
+                set.critical = Pattern::from_list(regexes.critical)?;
+            }
+            if set.warning.is_none() {
+                set.warning = Pattern::from_list(regexes.warning)?;
+            }
+        }
+
+        set.prefilter = set.derive_prefilter();
+        Ok(set)
+    }
 }
 
 /// When a line is matched, this is used to store which pattern and which regex in the list triggered the match.
 pub struct PatternMatchResult<'a> {
     pub pattern_type: PatternType,
     pub regex: &'a Regex,
+
+    /// the value of the JSON field matched, if not the whole line
+    pub value: Option<String>,
 }
 
 impl<'a> PatternMatchResult<'a> {
-    fn new(pattern_type: PatternType, regex: &'a Regex) -> Self {
+    fn new(pattern_type: PatternType, regex: &'a Regex, value: Option<String>) -> Self {
         PatternMatchResult {
             pattern_type,
             regex,
+            value,
         }
     }
+
+    /// The text matched by the regex, from which capture groups are taken: either the line or the JSON field value.
+    pub fn text<'t>(&'t self, line: &'t str) -> &'t str {
+        self.value.as_deref().unwrap_or(line)
+    }
 }
 
 impl PatternSet {
+    /// The prefilter derived from the critical, warning and ok regexes, if all of them require a literal.
+    pub fn prefilter(&self) -> Option<&Prefilter> {
+        self.prefilter.as_ref()
+    }
+
+    // a field value could be escaped in a JSON line, so literals are only derived if whole lines are matched
+    fn derive_prefilter(&self) -> Option<Prefilter> {
+        let patterns: Vec<_> = [&self.critical, &self.warning, &self.ok]
+            .iter()
+            .filter_map(|p| p.as_ref())
+            .collect();
+        if patterns.iter().any(|p| p.field.is_some()) {
+            return None;
+        }
+
+        Prefilter::from_regexes(
+            patterns
+                .iter()
+                .flat_map(|p| p.regexes.0.iter().map(|re| re.as_str())),
+        )
+    }
+
     /// Returns whether a critical or warning regex is involved in the match, provided no exception is matched.
     pub fn is_match(&self, text: &str) -> Option<PatternMatchResult> {
+        self.is_match_json(text, None)
+    }
+
+    /// Same as `is_match()`, with the fields of a JSON line matched by the patterns defining a field.
+    pub fn is_match_json(
+        &self,
+        line: &str,
+        json: Option<&JsonLine>,
+    ) -> Option<PatternMatchResult<'_>> {
         // try to match critical pattern first
         if let Some(critical) = &self.critical {
             trace!("critical pattern is tried");
             let ret = critical
-                .is_match(text)
-                .map(|re| PatternMatchResult::new(PatternType::critical, re));
+                .text(line, json)
+                .and_then(|text| critical.is_match(text).map(|re| (re, text)))
+                .map(|(re, text)| {
+                    PatternMatchResult::new(PatternType::critical, re, critical.value(text))
+                });
             if ret.is_some() {
                 trace!("critical pattern is matching");
                 return ret;
@@ -187,8 +301,11 @@ impl PatternSet {
         if let Some(warning) = &self.warning {
             trace!("warning pattern is tried");
             let ret = warning
-                .is_match(text)
-                .map(|re| PatternMatchResult::new(PatternType::warning, re));
+                .text(line, json)
+                .and_then(|text| warning.is_match(text).map(|re| (re, text)))
+                .map(|(re, text)| {
+                    PatternMatchResult::new(PatternType::warning, re, warning.value(text))
+                });
             if ret.is_some() {
                 trace!("warning pattern is matching");
                 return ret;
@@ -199,8 +316,9 @@ impl PatternSet {
         if let Some(ok) = &self.ok {
             trace!("ok pattern is tried");
             let ret = ok
-                .is_match(text)
-                .map(|re| PatternMatchResult::new(PatternType::ok, re));
+                .text(line, json)
+                .and_then(|text| ok.is_match(text).map(|re| (re, text)))
+                .map(|(re, text)| PatternMatchResult::new(PatternType::ok, re, ok.value(text)));
             if ret.is_some() {
                 trace!("ok pattern is matching");
                 return ret;
@@ -209,6 +327,26 @@ impl PatternSet {
 
         None
     }
+
+    /// Returns the first pattern type for which a regex matches `text`, but which has been dismissed because of an exception.
+    pub fn is_exception(&self, text: &str) -> Option<PatternType> {
+        let patterns = [
+            (&self.critical, PatternType::critical),
+            (&self.warning, PatternType::warning),
+            (&self.ok, PatternType::ok),
+        ];
+
+        // patterns matching a JSON field are not checked against the whole line
+        for (pattern, pattern_type) in patterns {
+            if let Some(p) = pattern.as_ref().filter(|p| p.field.is_none()) {
+                if p.is_exception(text) && p.regexes.0.iter().any(|re| re.is_match(text)) {
+                    return Some(pattern_type);
+                }
+            }
+        }
+
+        None
+    }
 }
 
 // Auto-implement FromStr
@@ -221,6 +359,14 @@ pub struct PatternCounters {
     pub warning_count: u64,
     pub ok_count: u64,
     pub exec_count: u64,
+
+    /// critical and warning matches hidden by the exclude regex of the logfile, if `count_suppressed` is set for the tag
+    #[serde(default)]
+    pub suppressed_count: u64,
+
+    /// matches not notified because an identical one was found within the `dedup` window
+    #[serde(default)]
+    pub dup_count: u64,
 }
 
 /// Sum is used to sum all counters of run data
@@ -234,6 +380,8 @@ impl<'a> Sum<&'a Self> for PatternCounters {
             warning_count: a.warning_count + b.warning_count,
             ok_count: a.ok_count + b.ok_count,
             exec_count: a.exec_count + b.exec_count,
+            suppressed_count: a.suppressed_count + b.suppressed_count,
+            dup_count: a.dup_count + b.dup_count,
         })
     }
 }
@@ -248,6 +396,8 @@ impl Add for PatternCounters {
             warning_count: self.warning_count + other.warning_count,
             ok_count: self.ok_count + other.ok_count,
             exec_count: self.exec_count + other.exec_count,
+            suppressed_count: self.suppressed_count + other.suppressed_count,
+            dup_count: self.dup_count + other.dup_count,
         }
     }
 }
@@ -342,6 +492,56 @@ mod tests {
         let match_text = p.is_match("RESET_ERROR: error is reset").unwrap();
         assert_eq!(match_text.pattern_type, PatternType::ok);
         assert_eq!(match_text.regex.as_str(), "^RESET_ERROR");
+
+        // exceptions
+        assert_eq!(
+            p.is_exception("SLIGHT_ERROR: core dump WARNING"),
+            Some(PatternType::critical)
+        );
+        assert_eq!(
+            p.is_exception("MINOR_ERROR: FATAL"),
+            Some(PatternType::warning)
+        );
+        assert!(p.is_exception("ERROR: core dump ").is_none());
+        assert!(p.is_exception("MINOR_ERROR: not a core dump ").is_none());
+    }
+
+    #[test]
+    fn pattern_set_json() {
+        let yaml = r#"
+            critical:
+                field: level
+                regexes: ["^(ERROR|FATAL)$"]
+                exceptions: ["^FATAL$"]
+            warning:
+                field: http.status
+                regexes: ["^5(?P<code>\\d\\d)$"]
+            ok:
+                regexes: ["recovered"]
+            "#;
+        let p: PatternSet = serde_yaml::from_str(yaml).unwrap();
+
+        let line = r#"{"level":"ERROR","msg":"disk full","http":{"status":200}}"#;
+        let json = JsonLine::parse(line);
+        let match_text = p.is_match_json(line, json.as_ref()).unwrap();
+        assert_eq!(match_text.pattern_type, PatternType::critical);
+        assert_eq!(match_text.value.as_deref(), Some("ERROR"));
+        assert_eq!(match_text.text(line), "ERROR");
+
+        // the exception is checked against the field too
+        let line = r#"{"level":"FATAL","http":{"status":503}}"#;
+        let json = JsonLine::parse(line);
+        let match_text = p.is_match_json(line, json.as_ref()).unwrap();
+        assert_eq!(match_text.pattern_type, PatternType::warning);
+        assert_eq!(match_text.text(line), "503");
+
+        // a pattern without field matches the whole line, and a field pattern doesn't match a line which is not JSON
+        let line = r#"{"level":"INFO","msg":"service recovered"}"#;
+        let json = JsonLine::parse(line);
+        let match_text = p.is_match_json(line, json.as_ref()).unwrap();
+        assert_eq!(match_text.pattern_type, PatternType::ok);
+        assert_eq!(match_text.text(line), line);
+        assert!(p.is_match("level ERROR").is_none());
     }
 
     #[test]
@@ -351,6 +551,8 @@ mod tests {
             warning_count: 2,
             ok_count: 3,
             exec_count: 4,
+            suppressed_count: 5,
+            dup_count: 6,
         };
 
         let v = vec![p; 10];
@@ -359,6 +561,8 @@ mod tests {
         assert_eq!(sum.warning_count, 20);
         assert_eq!(sum.ok_count, 30);
         assert_eq!(sum.exec_count, 40);
+        assert_eq!(sum.suppressed_count, 50);
+        assert_eq!(sum.dup_count, 60);
     }
 
     #[test]
@@ -368,12 +572,16 @@ mod tests {
             warning_count: 2,
             ok_count: 3,
             exec_count: 4,
+            suppressed_count: 5,
+            dup_count: 6,
         };
         let p2 = PatternCounters {
             critical_count: 1,
             warning_count: 2,
             ok_count: 3,
             exec_count: 4,
+            suppressed_count: 5,
+            dup_count: 6,
         };
 
         let sum = p1 + p2;
@@ -381,5 +589,7 @@ mod tests {
         assert_eq!(sum.warning_count, 4);
         assert_eq!(sum.ok_count, 6);
         assert_eq!(sum.exec_count, 8);
+        assert_eq!(sum.suppressed_count, 10);
+        assert_eq!(sum.dup_count, 12);
     }
 }
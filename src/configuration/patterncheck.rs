@@ -0,0 +1,289 @@
+//! Verification of all regular expressions of a configuration file against the regex engine clf is built with. The
+//! configuration file is read as raw YAML, so all expressions are checked even if some of them are not valid, which
+//! would otherwise stop loading the configuration at the first error.
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+use serde::{Serialize, Serializer};
+use serde_yaml::Value;
+
+use super::catalog::Preset;
+
+/// Version of the regex crate clf is built with.
+pub const REGEX_ENGINE_VERSION: &str = env!("CLF_REGEX_VERSION");
+
+/// Where an expression is used.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum ExpressionKind {
+    /// a critical, warning or ok regex
+    regex,
+
+    /// an exception of a pattern
+    exception,
+
+    /// the exclude regex of a logfile
+    exclude,
+}
+
+/// A syntax construct whose support depends on the regex engine.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum Feature {
+    /// `(?=`, `(?!`, `(?<=` or `(?<!`: not supported
+    look_around,
+
+    /// `\1` or `\k<name>`: not supported
+    backreference,
+
+    /// `(?>`: not supported
+    atomic_group,
+
+    /// `*+`, `++` or `?+`: not supported
+    possessive_quantifier,
+
+    /// `(?<name>`: only supported since regex 1.9, `(?P<name>` being supported by all versions
+    angle_named_group,
+
+    /// `\p{..}` or `\P{..}`: depends on the Unicode tables of the engine
+    unicode_class,
+}
+
+/// An expression found in the configuration file.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Expression {
+    /// the search, tag and pattern type of the expression
+    pub location: String,
+    pub kind: ExpressionKind,
+    pub expr: String,
+}
+
+/// The result of compiling an expression.
+#[derive(Debug, Serialize)]
+pub struct ExpressionCheck {
+    #[serde(flatten)]
+    pub expression: Expression,
+    pub features: Vec<Feature>,
+    #[serde(serialize_with = "as_secs")]
+    pub compile_time: Duration,
+
+    /// the compile error, if any
+    pub error: Option<String>,
+}
+
+// the compile time is given in seconds
+fn as_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+/// Returns all expressions of a configuration file read as raw YAML, including those of presets.
+pub fn expressions(config: &Value) -> Vec<Expression> {
+    let mut list = Vec::new();
+
+    let searches = config["searches"]
+        .as_sequence()
+        .cloned()
+        .unwrap_or_default();
+    for (i, search) in searches.iter().enumerate() {
+        let logfile = &search["logfile"];
+        let name = logfile["path"]
+            .as_str()
+            .or_else(|| logfile["cmd"].as_str())
+            .map(|path| format!("search #{} ({})", i + 1, path))
+            .unwrap_or_else(|| format!("search #{}", i + 1));
+
+        if let Some(exclude) = logfile["exclude"].as_str() {
+            list.push(Expression {
+                location: name.clone(),
+                kind: ExpressionKind::exclude,
+                expr: exclude.to_string(),
+            });
+        }
+
+        let tags = search["tags"].as_sequence().cloned().unwrap_or_default();
+        for tag in &tags {
+            let location = format!("{}, tag {}", name, tag["name"].as_str().unwrap_or("?"));
+            let patterns = &tag["patterns"];
+            let preset: Option<Preset> = serde_yaml::from_value(patterns["preset"].clone()).ok();
+
+            for pattern_type in ["critical", "warning", "ok"] {
+                let pattern = &patterns[pattern_type];
+                let location = format!("{}, {}", location, pattern_type);
+
+                // the preset regexes are used only if the pattern type is not defined
+                if pattern.is_null() {
+                    if let Some(preset) = preset {
+                        let regexes = preset.regexes();
+                        let preset_regexes = match pattern_type {
+                            "critical" => regexes.critical,
+                            "warning" => regexes.warning,
+                            _ => &[],
+                        };
+                        for re in preset_regexes {
+                            list.push(Expression {
+                                location: format!("{} (preset {:?})", location, preset),
+                                kind: ExpressionKind::regex,
+                                expr: re.to_string(),
+                            });
+                        }
+                    }
+                    continue;
+                }
+
+                for (key, kind) in [
+                    ("regexes", ExpressionKind::regex),
+                    ("exceptions", ExpressionKind::exception),
+                ] {
+                    let exprs = pattern[key].as_sequence().cloned().unwrap_or_default();
+                    for expr in exprs.iter().filter_map(|expr| expr.as_str()) {
+                        list.push(Expression {
+                            location: location.clone(),
+                            kind,
+                            expr: expr.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    list
+}
+
+/// Compiles an expression, timing it and listing the engine-dependent constructs it uses.
+pub fn check(expression: Expression) -> ExpressionCheck {
+    let features = features(&expression.expr);
+
+    let start = Instant::now();
+    let error = Regex::new(&expression.expr).err().map(|e| e.to_string());
+    let compile_time = start.elapsed();
+
+    ExpressionCheck {
+        expression,
+        features,
+        compile_time,
+        error,
+    }
+}
+
+// engine-dependent constructs found in the expression. Escaped characters are skipped, so `\(?=` is not a look-ahead
+fn features(expr: &str) -> Vec<Feature> {
+    let mut features = Vec::new();
+    let mut add = |feature| {
+        if !features.contains(&feature) {
+            features.push(feature);
+        }
+    };
+
+    let bytes = expr.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        match bytes[i] {
+            b'\\' => {
+                match bytes.get(i + 1) {
+                    Some(b'1'..=b'9') | Some(b'k') => add(Feature::backreference),
+                    Some(b'p') | Some(b'P') => add(Feature::unicode_class),
+                    _ => (),
+                }
+                i += 2;
+                continue;
+            }
+            b'(' if rest.starts_with(b"(?=")
+                || rest.starts_with(b"(?!")
+                || rest.starts_with(b"(?<=")
+                || rest.starts_with(b"(?<!") =>
+            {
+                add(Feature::look_around)
+            }
+            b'(' if rest.starts_with(b"(?>") => add(Feature::atomic_group),
+            b'(' if rest.starts_with(b"(?<") => add(Feature::angle_named_group),
+            // `(?+` is not a quantifier
+            b'*' | b'+' | b'?' | b'}'
+                if bytes.get(i + 1) == Some(&b'+')
+                    && !(bytes[i] == b'?' && i > 0 && bytes[i - 1] == b'(') =>
+            {
+                add(Feature::possessive_quantifier)
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+
+    features
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn features() {
+        assert!(super::features(r"^ERROR \d+ (?P<code>\w+)").is_empty());
+        assert_eq!(
+            super::features(r"foo(?=bar)|(?<!baz)qux"),
+            vec![Feature::look_around]
+        );
+        assert_eq!(super::features(r"(\w+) \1"), vec![Feature::backreference]);
+        assert_eq!(
+            super::features(r"(?<name>\w+)\p{Greek}"),
+            vec![Feature::angle_named_group, Feature::unicode_class]
+        );
+        assert_eq!(
+            super::features(r"(?>a|ab)c\d++"),
+            vec![Feature::atomic_group, Feature::possessive_quantifier]
+        );
+        // escaped characters are not constructs
+        assert!(super::features(r"\(?=\)\\d").is_empty());
+    }
+
+    #[test]
+    fn expressions() {
+        let yaml = r#"
+searches:
+  - logfile:
+      path: /var/log/syslog
+      exclude: '^#'
+    tags:
+      - name: error
+        patterns:
+          critical:
+            regexes: ['^ERROR', 'foo(?=bar)']
+            exceptions: ['^ERROR 42']
+      - name: sshd
+        patterns:
+          preset: ssh_bruteforce
+          warning:
+            regexes: ['Invalid user']
+"#;
+        let config: Value = serde_yaml::from_str(yaml).unwrap();
+        let list = super::expressions(&config);
+
+        assert_eq!(list[0].kind, ExpressionKind::exclude);
+        assert_eq!(list[0].location, "search #1 (/var/log/syslog)");
+        assert_eq!(list[1].expr, "^ERROR");
+        assert_eq!(
+            list[1].location,
+            "search #1 (/var/log/syslog), tag error, critical"
+        );
+        assert_eq!(list[3].kind, ExpressionKind::exception);
+
+        // preset regexes are only used for critical, as warning is defined
+        let preset: Vec<_> = list
+            .iter()
+            .filter(|e| e.location.contains("preset"))
+            .collect();
+        assert_eq!(
+            preset.len(),
+            Preset::ssh_bruteforce.regexes().critical.len()
+        );
+        assert!(preset.iter().all(|e| e.location.contains("critical")));
+        assert_eq!(list.last().unwrap().expr, "Invalid user");
+
+        let checks: Vec<_> = list.into_iter().map(check).collect();
+        let errors: Vec<_> = checks.iter().filter(|c| c.error.is_some()).collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].expression.expr, "foo(?=bar)");
+        assert_eq!(errors[0].features, vec![Feature::look_around]);
+    }
+}
@@ -0,0 +1,141 @@
+//! Testing the patterns of a configuration against sample lines: for each line and each tag, which pattern type and
+//! which regex matched, along with the capture groups which would be passed to the callback as CLF_CG_* variables.
+//! This helps writing regexes without having to feed a logfile and look at what the callback received.
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::configuration::{logfiledef::LogFileFormat, search::Search, vars::RuntimeVars};
+use crate::logfile::jsonline::JsonLine;
+
+/// The result of matching a line against the patterns of a tag.
+#[derive(Debug, Serialize)]
+pub struct PatternTest {
+    /// the line number in the lines tested, starting from 1
+    pub line_number: usize,
+
+    /// the logfile of the search defining the tag
+    pub logfile: PathBuf,
+
+    /// the tag name
+    pub tag: String,
+
+    /// `critical`, `warning` or `ok` for a match, `excluded` if the line matches the exclude regex of the logfile,
+    /// `none` otherwise, possibly followed by the pattern type whose exception dismissed the match
+    pub result: String,
+
+    /// the regex which matched, or the exclude regex
+    pub regex: Option<String>,
+
+    /// the CLF_CG_* variables and their values
+    pub captures: BTreeMap<String, String>,
+}
+
+/// Matches each line against all tags of the searches.
+pub fn test_lines(searches: &[Search], lines: &[String]) -> Vec<PatternTest> {
+    let mut tests = Vec::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        for search in searches {
+            let json = if search.logfile.format == LogFileFormat::json {
+                JsonLine::parse(line)
+            } else {
+                None
+            };
+
+            for tag in &search.tags {
+                let mut test = PatternTest {
+                    line_number: i + 1,
+                    logfile: search.logfile.path().clone(),
+                    tag: tag.name.clone(),
+                    result: "none".to_string(),
+                    regex: None,
+                    captures: BTreeMap::new(),
+                };
+
+                // excluded lines are not tested against patterns
+                if let Some(re) = search
+                    .logfile
+                    .exclude
+                    .as_ref()
+                    .filter(|re| re.is_match(line))
+                {
+                    test.result = "excluded".to_string();
+                    test.regex = Some(re.as_str().to_string());
+                } else if let Some(pattern_match) = tag.is_match_json(line, json.as_ref()) {
+                    test.result = <&str>::from(&pattern_match.pattern_type).to_string();
+                    test.regex = Some(pattern_match.regex.as_str().to_string());
+
+                    let mut vars = RuntimeVars::default();
+                    vars.insert_captures(pattern_match.regex, pattern_match.text(line));
+                    test.captures = vars
+                        .iter()
+                        .map(|(name, value)| (name.to_string(), value.to_string()))
+                        .collect();
+                } else if let Some(pattern_type) = tag.patterns.is_exception(line) {
+                    test.result = format!("none({} exception)", <&str>::from(&pattern_type));
+                }
+
+                tests.push(test);
+            }
+        }
+    }
+
+    tests
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pattern_test() {
+        let yaml = r#"
+logfile:
+    path: /var/log/syslog
+    exclude: "^#"
+tags:
+  - name: disk
+    patterns:
+      critical:
+        regexes: ["ERROR: disk (?P<disk>\\w+) is (\\d+)% full"]
+        exceptions: ["sdz"]
+      warning:
+        regexes: ["WARN"]
+"#;
+        let search: Search = serde_yaml::from_str(yaml).expect("unable to read YAML");
+        let lines: Vec<_> = [
+            "ERROR: disk sda is 99% full",
+            "WARN: disk sdb",
+            "# ERROR: disk sda is 99% full",
+            "ERROR: disk sdz is 99% full",
+            "INFO",
+        ]
+        .iter()
+        .map(|l| l.to_string())
+        .collect();
+
+        let tests = test_lines(&[search], &lines);
+        assert_eq!(tests.len(), 5);
+        assert_eq!(tests[0].line_number, 1);
+        assert_eq!(tests[0].logfile, PathBuf::from("/var/log/syslog"));
+        assert_eq!(tests[0].tag, "disk");
+        assert_eq!(tests[0].result, "critical");
+        assert_eq!(
+            tests[0].regex.as_deref(),
+            Some("ERROR: disk (?P<disk>\\w+) is (\\d+)% full")
+        );
+        assert_eq!(tests[0].captures["CLF_CG_0"], "ERROR: disk sda is 99% full");
+        assert_eq!(tests[0].captures["CLF_CG_disk"], "sda");
+        assert_eq!(tests[0].captures["CLF_CG_2"], "99");
+
+        assert_eq!(tests[1].result, "warning");
+        assert_eq!(tests[1].captures.len(), 1);
+        assert_eq!(tests[2].result, "excluded");
+        assert_eq!(tests[2].regex.as_deref(), Some("^#"));
+        assert_eq!(tests[3].result, "none(critical exception)");
+        assert_eq!(tests[4].result, "none");
+        assert!(tests[4].regex.is_none());
+    }
+}
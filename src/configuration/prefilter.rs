@@ -0,0 +1,200 @@
+//! A literal prefilter for the lines searched by a tag: lines containing none of its literals can't match any pattern,
+//! so no regex is evaluated for them. Literals are either given in the configuration (`prefilter: ["ERROR", "FATAL"]`),
+//! or derived from the regexes of the tag when each of them requires one literal of a small set to match. On logfiles
+//! where almost all lines are irrelevant, a substring search is much cheaper than evaluating each regex.
+use std::convert::TryFrom;
+
+use memchr::memmem::Finder;
+use regex_syntax::hir::{Hir, HirKind};
+use serde::Deserialize;
+
+/// Above this number of literals, the prefilter is not worth it and none is derived.
+const MAX_LITERALS: usize = 16;
+
+/// The literals one of which is found in any line matching the patterns of a tag.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(try_from = "Vec<String>")]
+pub struct Prefilter(Vec<Finder<'static>>);
+
+impl TryFrom<Vec<String>> for Prefilter {
+    type Error = String;
+
+    fn try_from(literals: Vec<String>) -> Result<Self, Self::Error> {
+        if literals.is_empty() || literals.iter().any(|literal| literal.is_empty()) {
+            return Err(format!(
+                "prefilter {:?} must be a list of non-empty literals",
+                literals
+            ));
+        }
+        Ok(Prefilter::new(
+            literals.iter().map(|literal| literal.as_bytes()),
+        ))
+    }
+}
+
+impl Prefilter {
+    // builds the searchers once for all lines
+    fn new<'a>(literals: impl Iterator<Item = &'a [u8]>) -> Self {
+        Prefilter(
+            literals
+                .map(|literal| Finder::new(literal).into_owned())
+                .collect(),
+        )
+    }
+
+    /// Derives a prefilter from regexes: a line matching any of them contains one of the literals. `None` is returned
+    /// if a regex doesn't require any literal, like `\d+` or `(?i)error`, as all lines need to be evaluated then.
+    pub fn from_regexes<'a>(regexes: impl Iterator<Item = &'a str>) -> Option<Self> {
+        let mut literals: Vec<Vec<u8>> = Vec::new();
+        for re in regexes {
+            let hir = regex_syntax::parse(re).ok()?;
+            for literal in required_literals(&hir)? {
+                if !literals.contains(&literal) {
+                    literals.push(literal);
+                }
+            }
+        }
+
+        if literals.is_empty() || literals.len() > MAX_LITERALS {
+            return None;
+        }
+        Some(Prefilter::new(
+            literals.iter().map(|literal| literal.as_slice()),
+        ))
+    }
+
+    /// `true` if one of the literals is found in the text, so it has to be matched against the regexes.
+    pub fn is_candidate(&self, text: &str) -> bool {
+        self.0
+            .iter()
+            .any(|finder| finder.find(text.as_bytes()).is_some())
+    }
+
+    /// The literals of the prefilter.
+    #[cfg(test)]
+    pub fn literals(&self) -> Vec<String> {
+        self.0
+            .iter()
+            .map(|finder| String::from_utf8_lossy(finder.needle()).to_string())
+            .collect()
+    }
+}
+
+// a set of literals one of which is found in any text matching the expression, or None if there's no such set
+fn required_literals(hir: &Hir) -> Option<Vec<Vec<u8>>> {
+    let literals = match hir.kind() {
+        HirKind::Literal(literal) => vec![literal.0.to_vec()],
+        HirKind::Capture(capture) => required_literals(&capture.sub)?,
+        HirKind::Repetition(repetition) if repetition.min >= 1 => {
+            required_literals(&repetition.sub)?
+        }
+
+        // any of the sub-expressions will do, the one with the longest literals is the most selective
+        HirKind::Concat(subs) => subs
+            .iter()
+            .filter_map(required_literals)
+            .max_by_key(|literals| literals.iter().map(Vec::len).min())?,
+
+        // each branch needs its own literals
+        HirKind::Alternation(subs) => {
+            let mut literals = Vec::new();
+            for sub in subs {
+                literals.extend(required_literals(sub)?);
+            }
+            literals
+        }
+        _ => return None,
+    };
+
+    if literals.iter().any(Vec::is_empty) {
+        None
+    } else {
+        Some(literals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn derived(regexes: &[&str]) -> Option<Vec<String>> {
+        Prefilter::from_regexes(regexes.iter().copied()).map(|prefilter| prefilter.literals())
+    }
+
+    #[test]
+    fn prefilter() {
+        assert_eq!(derived(&["ERROR"]).unwrap(), vec!["ERROR"]);
+        assert_eq!(
+            derived(&["^ERROR (\\d+)", "FATAL|PANIC"]).unwrap(),
+            vec!["ERROR ", "FATAL", "PANIC"]
+        );
+        assert_eq!(
+            derived(&["(ERROR|FATAL): disk (\\w+) full"]).unwrap(),
+            vec![": disk "]
+        );
+        assert_eq!(derived(&["(?:timeout)+ after"]).unwrap(), vec!["timeout"]);
+        assert!(derived(&["ERROR", "\\d+"]).is_none());
+        assert!(derived(&["(?i)error"]).is_none());
+        assert!(derived(&["ERROR|\\d+"]).is_none());
+        assert!(derived(&["(ERROR)?"]).is_none());
+        assert!(derived(&["["]).is_none());
+        assert!(derived(&[]).is_none());
+
+        let prefilter = Prefilter::from_regexes(["ERROR", "FATAL"].iter().copied()).unwrap();
+        assert!(prefilter.is_candidate("2021-01-28 FATAL disk full"));
+        assert!(!prefilter.is_candidate("2021-01-28 INFO started"));
+
+        let prefilter = Prefilter::try_from(vec!["é".to_string()]).unwrap();
+        assert!(prefilter.is_candidate("café"));
+        assert!(Prefilter::try_from(Vec::new()).is_err());
+        assert!(Prefilter::try_from(vec!["".to_string()]).is_err());
+    }
+
+    // cargo test --release prefilter_bench -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn prefilter_bench() {
+        let regexes = ["^ERROR: (\\w+) disk (\\d+) full", "FATAL \\d+"];
+        let compiled: Vec<_> = regexes
+            .iter()
+            .map(|re| regex::Regex::new(re).unwrap())
+            .collect();
+        let prefilter = Prefilter::from_regexes(regexes.iter().copied()).unwrap();
+
+        // 1 relevant line out of 1000
+        let lines: Vec<_> = (0..1_000_000)
+            .map(|i| {
+                if i % 1000 == 0 {
+                    format!("ERROR: sda disk {} full", i)
+                } else {
+                    format!("2021-01-28 12:00:00 INFO request {} served in 3ms", i)
+                }
+            })
+            .collect();
+
+        let start = Instant::now();
+        let regex_matches = lines
+            .iter()
+            .filter(|line| compiled.iter().any(|re| re.is_match(line)))
+            .count();
+        let regex_time = start.elapsed();
+
+        let start = Instant::now();
+        let prefilter_matches = lines
+            .iter()
+            .filter(|line| {
+                prefilter.is_candidate(line) && compiled.iter().any(|re| re.is_match(line))
+            })
+            .count();
+        let prefilter_time = start.elapsed();
+
+        assert_eq!(regex_matches, prefilter_matches);
+        println!(
+            "{} lines: regexes {:?}, prefilter + regexes {:?}",
+            lines.len(),
+            regex_time,
+            prefilter_time
+        );
+    }
+}
@@ -0,0 +1,339 @@
+//! The protocol used by TCP and UNIX domain socket callbacks to send payloads to a receiver. This module only depends on
+//! the standard library, serde and serde_json, so it could be reused as is to write a compatible receiver.
+//!
+//! # Framing
+//!
+//! Each message is a UTF-8 JSON object, prefixed by its length in bytes as an unsigned 16-bit integer in network
+//! order (big endian). A message is hence at most 65535 bytes long: a longer one is not written, which is an error.
+//! There's no delimiter between messages.
+//!
+//! # Messages
+//!
+//! All messages are sent by clf, the receiver never answers. They're numbered by `seq`, starting from 1 for each
+//! connection, so the receiver can check none was lost:
+//!
+//! * the first message of a connection holds the protocol version, the callback arguments if any, the global variables
+//!   and the variables of the first match: `{"protocol":1,"seq":1,"args":["arg1"],"global":{..},"vars":{..}}`
+//! * each following match is sent as: `{"seq":2,"vars":{..}}`
+//! * the last message of a connection tells how many payloads were sent before it: `{"seq":3,"end_of_run":{"sent":2}}`
+//!
+//! The `report_socket` global option uses the same framing for run reports, regardless of matches: its connection
+//! holds a single report, followed by the last message: `{"protocol":1,"seq":1,"run_report":{..}}` then
+//! `{"seq":2,"end_of_run":{"sent":1}}`.
+//!
+//! # Batches
+//!
+//! When the callback sets `batch`, several messages are coalesced into a single frame, so the receiver isn't
+//! overwhelmed by a write for each match: `{"batch":[{"protocol":2,"seq":1,..},{"seq":2,..}]}`. All frames of such a
+//! connection are batches, including the one holding the last message, and the messages of a batch are handled as
+//! if they were received one by one. A batch is at most `MAX_MESSAGE_SIZE` bytes long like any frame, except when a
+//! single message is longer than the batch size, in which case it's sent alone in its batch.
+//!
+//! # Versions
+//!
+//! The version is announced in the first message. A message without version comes from a clf release older than
+//! versioning, and is version 1. A new version only adds fields, so a receiver must ignore unknown fields, and must
+//! close the connection if the version is greater than the one it supports. Version 2 adds batches: it's only
+//! announced when messages are batched, so receivers supporting version 1 only still get unbatched messages.
+
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Version of the protocol sent by clf when messages are not batched.
+pub const PROTOCOL_VERSION: u64 = 1;
+
+/// Version of the protocol sent by clf when messages are batched, which is the latest version.
+pub const BATCH_PROTOCOL_VERSION: u64 = 2;
+
+/// Maximum length of a message.
+pub const MAX_MESSAGE_SIZE: usize = u16::MAX as usize;
+
+/// A message as received, with the variables kept as JSON values.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Message {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<u64>,
+    pub seq: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub global: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vars: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_of_run: Option<EndOfRun>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_report: Option<Value>,
+}
+
+/// The content of the last message of a connection.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct EndOfRun {
+    pub sent: u64,
+}
+
+/// Builds the message of a match. Only the first message of a connection holds the version, arguments and globals.
+pub fn payload<G: Serialize, V: Serialize>(
+    seq: u64,
+    args: &Option<Vec<String>>,
+    global_vars: &G,
+    vars: &V,
+    first_time: bool,
+    batched: bool,
+) -> Value {
+    let mut message = json!({ "seq": seq, "vars": vars });
+    if first_time {
+        message["protocol"] = json!(if batched {
+            BATCH_PROTOCOL_VERSION
+        } else {
+            PROTOCOL_VERSION
+        });
+        message["global"] = json!(global_vars);
+        if let Some(args) = args {
+            message["args"] = json!(args);
+        }
+    }
+    message
+}
+
+/// Builds the last message of a connection.
+pub fn end_of_run(seq: u64, sent: u64) -> Value {
+    json!({ "seq": seq, "end_of_run": { "sent": sent } })
+}
+
+/// Builds the message holding the report of a run, the first one of its connection.
+pub fn run_report<R: Serialize>(seq: u64, report: &R) -> Value {
+    json!({ "protocol": PROTOCOL_VERSION, "seq": seq, "run_report": report })
+}
+
+/// Size of the batch frame holding these messages.
+pub fn batch_size(messages: &[String]) -> usize {
+    r#"{"batch":[]}"#.len()
+        + messages.iter().map(|m| m.len()).sum::<usize>()
+        + messages.len().saturating_sub(1)
+}
+
+/// Builds the frame of a batch from messages already serialized.
+pub fn batch(messages: &[String]) -> String {
+    format!(r#"{{"batch":[{}]}}"#, messages.join(","))
+}
+
+/// Writes a message prefixed by its length. A message longer than `MAX_MESSAGE_SIZE` is an `InvalidInput` error, and
+/// nothing is written so the stream can still be used.
+pub fn write_frame<W: Write>(mut stream: W, json: &str) -> io::Result<()> {
+    if json.len() > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "message of {} bytes is longer than the maximum of {} bytes",
+                json.len(),
+                MAX_MESSAGE_SIZE
+            ),
+        ));
+    }
+
+    stream.write_all(&(json.len() as u16).to_be_bytes())?;
+    stream.write_all(json.as_bytes())
+}
+
+/// Reads a message. Returns `None` if the stream is closed before a new message.
+pub fn read_frame<R: Read>(mut stream: R) -> io::Result<Option<Vec<u8>>> {
+    let mut size = [0; std::mem::size_of::<u16>()];
+    match stream.read_exact(&mut size) {
+        Ok(_) => (),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let mut json = vec![0; u16::from_be_bytes(size) as usize];
+    stream.read_exact(&mut json)?;
+    Ok(Some(json))
+}
+
+/// A receiver of messages sent through a stream, checking the protocol version and the sequence numbers. The
+/// messages of a batch are received one by one.
+pub struct Receiver<R> {
+    stream: R,
+    version: Option<u64>,
+    last_seq: u64,
+    pending: VecDeque<Value>,
+}
+
+impl<R: Read> Receiver<R> {
+    /// Creates a receiver for a newly accepted connection.
+    pub fn new(stream: R) -> Self {
+        Receiver {
+            stream,
+            version: None,
+            last_seq: 0,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Protocol version announced by the sender, once the first message is received.
+    pub fn version(&self) -> Option<u64> {
+        self.version
+    }
+
+    /// Receives the next message as a `Message` or any other structure. Returns `None` when the connection is closed.
+    /// An unsupported version or a lost message is an `InvalidData` error.
+    pub fn recv<T: DeserializeOwned>(&mut self) -> io::Result<Option<T>> {
+        while self.pending.is_empty() {
+            let frame = match read_frame(&mut self.stream)? {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+            match serde_json::from_slice(&frame).map_err(invalid_data)? {
+                Value::Object(mut message) => match message.remove("batch") {
+                    Some(Value::Array(batch)) => self.pending.extend(batch),
+                    _ => self.pending.push_back(Value::Object(message)),
+                },
+                message => self.pending.push_back(message),
+            }
+        }
+        let message = self.pending.pop_front().unwrap_or_default();
+
+        if self.version.is_none() {
+            let version = message["protocol"].as_u64().unwrap_or(1);
+            if version > BATCH_PROTOCOL_VERSION {
+                return Err(invalid_data(format!(
+                    "unsupported protocol version {}, the latest supported is {}",
+                    version, BATCH_PROTOCOL_VERSION
+                )));
+            }
+            self.version = Some(version);
+        }
+
+        let seq = message["seq"].as_u64().unwrap_or(0);
+        if seq != self.last_seq + 1 {
+            return Err(invalid_data(format!(
+                "message #{} received, #{} expected",
+                seq,
+                self.last_seq + 1
+            )));
+        }
+        self.last_seq = seq;
+
+        serde_json::from_value(message)
+            .map(Some)
+            .map_err(invalid_data)
+    }
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn frames() {
+        let mut stream = Vec::new();
+        write_frame(&mut stream, r#"{"seq":1}"#).unwrap();
+        assert_eq!(&stream[..2], &[0, 9]);
+
+        // the longest message, while a longer one is not written
+        let longest = "é".repeat(MAX_MESSAGE_SIZE / 2) + "a";
+        write_frame(&mut stream, &longest).unwrap();
+        let e = write_frame(&mut stream, &"é".repeat(MAX_MESSAGE_SIZE / 2 + 1)).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+
+        let mut reader = stream.as_slice();
+        assert_eq!(read_frame(&mut reader).unwrap().unwrap(), br#"{"seq":1}"#);
+        let frame = read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!(frame, longest.as_bytes());
+        assert!(read_frame(&mut reader).unwrap().is_none());
+
+        // closed in the middle of a message
+        assert!(read_frame(&[0, 9, b'{'][..]).is_err());
+    }
+
+    #[test]
+    fn receiver() {
+        let global: HashMap<&str, &str> = vec![("CLF_HOSTNAME", "host")].into_iter().collect();
+        let args = Some(vec!["arg1".to_string()]);
+
+        let mut stream = Vec::new();
+        for (seq, first_time) in [(1, true), (2, false)] {
+            let message = payload(
+                seq,
+                &args,
+                &global,
+                &json!({ "CLF_LINE_NUMBER": seq }),
+                first_time,
+                false,
+            );
+            write_frame(&mut stream, &message.to_string()).unwrap();
+        }
+        write_frame(&mut stream, &end_of_run(3, 2).to_string()).unwrap();
+
+        let mut receiver = Receiver::new(stream.as_slice());
+        let first: Message = receiver.recv().unwrap().unwrap();
+        assert_eq!(receiver.version(), Some(PROTOCOL_VERSION));
+        assert_eq!(first.args, args);
+        assert_eq!(first.global.unwrap()["CLF_HOSTNAME"], "host");
+        assert_eq!(first.vars.unwrap()["CLF_LINE_NUMBER"], 1);
+
+        let second: Message = receiver.recv().unwrap().unwrap();
+        assert!(second.protocol.is_none() && second.global.is_none() && second.args.is_none());
+
+        let last: Message = receiver.recv().unwrap().unwrap();
+        assert_eq!(last.end_of_run, Some(EndOfRun { sent: 2 }));
+        assert!(receiver.recv::<Message>().unwrap().is_none());
+
+        // a message without version is version 1
+        let mut stream = Vec::new();
+        write_frame(&mut stream, r#"{"seq":1,"vars":{}}"#).unwrap();
+        let mut receiver = Receiver::new(stream.as_slice());
+        assert!(receiver.recv::<Message>().unwrap().is_some());
+        assert_eq!(receiver.version(), Some(1));
+
+        // unsupported version
+        let mut stream = Vec::new();
+        write_frame(&mut stream, r#"{"protocol":99,"seq":1}"#).unwrap();
+        let e = Receiver::new(stream.as_slice())
+            .recv::<Message>()
+            .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+
+        // lost message
+        let mut stream = Vec::new();
+        write_frame(&mut stream, r#"{"seq":1}"#).unwrap();
+        write_frame(&mut stream, r#"{"seq":3}"#).unwrap();
+        let mut receiver = Receiver::new(stream.as_slice());
+        assert!(receiver.recv::<Message>().is_ok());
+        assert!(receiver.recv::<Message>().is_err());
+    }
+
+    #[test]
+    fn batches() {
+        let global: HashMap<&str, &str> = HashMap::new();
+        let messages: Vec<_> = (1..=3)
+            .map(|seq| payload(seq, &None, &global, &json!({}), seq == 1, true).to_string())
+            .collect();
+        let frame = batch(&messages);
+        assert_eq!(frame.len(), batch_size(&messages));
+        assert_eq!(batch_size(&[]), batch(&[]).len());
+
+        let mut stream = Vec::new();
+        write_frame(&mut stream, &frame).unwrap();
+        write_frame(&mut stream, &batch(&[end_of_run(4, 3).to_string()])).unwrap();
+
+        // messages of batches are received one by one
+        let mut receiver = Receiver::new(stream.as_slice());
+        for seq in 1..=3 {
+            let message: Message = receiver.recv().unwrap().unwrap();
+            assert_eq!(message.seq, seq);
+        }
+        assert_eq!(receiver.version(), Some(BATCH_PROTOCOL_VERSION));
+        let last: Message = receiver.recv().unwrap().unwrap();
+        assert_eq!(last.end_of_run, Some(EndOfRun { sent: 3 }));
+        assert!(receiver.recv::<Message>().unwrap().is_none());
+    }
+}
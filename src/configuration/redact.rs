@@ -0,0 +1,134 @@
+//! Contains the redaction rules of a tag. Parts of the matched line like IP addresses or emails are replaced before the
+//! callback is called, so personal data never leaves the host. Patterns are still matched against the original line.
+use std::borrow::Cow;
+
+use regex::Regex;
+use serde::{de, Deserialize, Deserializer};
+
+use crate::configuration::vars::{PendingVars, VarType};
+use crate::{fromstr, prefix_var};
+
+/// Variables holding data from the logfile, which are redacted. Others like CLF_LOGFILE or CLF_MATCHED_RE are left
+/// as is.
+const REDACTED_VARS: &[&str] = &[
+    "CLF_LINE",
+    "CLF_CONTEXT",
+    "CLF_CG_",
+    "CLF_JSON_",
+    "CLF_INCIDENT_KEY",
+];
+
+/// A regex whose matches are replaced.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Redaction {
+    /// the text to redact
+    #[serde(deserialize_with = "to_regex")]
+    pub regex: Regex,
+
+    /// the replacement text, which could refer to capture groups like `$1`
+    #[serde(default = "Redaction::default_replacement")]
+    pub replacement: String,
+}
+
+impl Redaction {
+    /// Default replacement text
+    fn default_replacement() -> String {
+        "REDACTED".to_string()
+    }
+}
+
+/// The list of redactions of a tag, applied in order.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(transparent)]
+pub struct Redact(Vec<Redaction>);
+
+impl Redact {
+    /// Replaces all matches of all redaction regexes in `text`.
+    pub fn text<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut redacted = Cow::from(text);
+        for redaction in &self.0 {
+            if let Cow::Owned(replaced) = redaction
+                .regex
+                .replace_all(&redacted, redaction.replacement.as_str())
+            {
+                redacted = Cow::from(replaced);
+            }
+        }
+        redacted
+    }
+
+    /// Redacts the variables holding data from the logfile, before they're given to the callback.
+    pub fn vars(&self, vars: &mut PendingVars) {
+        // raw bytes can't be redacted, so they're not given at all
+        vars.remove(prefix_var!("LINE_RAW"));
+
+        for (name, value) in vars.iter_mut() {
+            if !REDACTED_VARS.iter().any(|prefix| name.starts_with(prefix)) {
+                continue;
+            }
+            if let VarType::Str(s) = value {
+                if let Cow::Owned(redacted) = self.text(s) {
+                    *s = redacted;
+                }
+            }
+        }
+    }
+}
+
+// Auto-implement FromStr
+fromstr!(Redact);
+
+// redaction regexes are compiled when the configuration is loaded
+fn to_regex<'de, D>(deserializer: D) -> Result<Regex, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    Regex::new(&s).map_err(de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn redact() {
+        let yaml = r#"
+            - regex: '\b\d{1,3}(\.\d{1,3}){3}\b'
+              replacement: '<ip>'
+            - regex: '[\w.+-]+@([\w-]+\.[\w.]+)'
+              replacement: '<email>@$1'
+            - regex: 'password=\S+'
+        "#;
+        let redact = Redact::from_str(yaml).expect("unable to read YAML");
+
+        assert_eq!(
+            redact.text("login from 10.0.0.1 by john.doe@example.com password=secret"),
+            "login from <ip> by <email>@example.com REDACTED"
+        );
+        assert!(matches!(redact.text("nothing to hide"), Cow::Borrowed(_)));
+
+        let mut vars = PendingVars::default();
+        vars.insert(
+            "CLF_LINE".to_string(),
+            VarType::Str("ERROR from 10.0.0.1".to_string()),
+        );
+        vars.insert("CLF_CG_1".to_string(), VarType::Str("10.0.0.1".to_string()));
+        vars.insert(
+            "CLF_MATCHED_RE".to_string(),
+            VarType::Str("10.0.0.1".to_string()),
+        );
+        vars.insert("CLF_LINE_NUMBER".to_string(), VarType::Int(10));
+        redact.vars(&mut vars);
+
+        assert_eq!(
+            vars["CLF_LINE"],
+            VarType::Str("ERROR from <ip>".to_string())
+        );
+        assert_eq!(vars["CLF_CG_1"], VarType::Str("<ip>".to_string()));
+        assert_eq!(vars["CLF_MATCHED_RE"], VarType::Str("10.0.0.1".to_string()));
+        assert_eq!(vars["CLF_LINE_NUMBER"], VarType::Int(10));
+    }
+}
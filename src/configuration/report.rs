@@ -0,0 +1,159 @@
+//! Contains the configuration of the report socket: after each run, the JSON report of the run is sent to a local
+//! agent through a TCP or UNIX domain socket, with the framing of socket callbacks. This is independent of callbacks,
+//! so the agent gets the run results even if nothing matched, without parsing the plugin output. Reports are only sent
+//! with the `sockets` feature.
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "sockets")]
+use std::{io::Write, time::Duration};
+
+#[cfg(all(feature = "sockets", target_family = "unix"))]
+use std::os::unix::net::UnixStream;
+
+#[cfg(feature = "sockets")]
+use crate::configuration::{callback::connect, protocol};
+#[cfg(feature = "sockets")]
+use crate::context;
+use crate::fromstr;
+use crate::misc::{
+    error::{AppError, AppResult},
+    util::DEFAULT_WRITE_TIMEOUT,
+};
+
+/// The socket is either a TCP address, or the path of a UNIX domain socket
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub enum ReportSocketType {
+    #[serde(rename = "address")]
+    Tcp(String),
+
+    #[serde(rename = "domain")]
+    Domain(PathBuf),
+}
+
+/// Where and how to send the report of each run
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReportSocket {
+    /// The kind of socket along with its address
+    #[serde(flatten)]
+    pub socket: ReportSocketType,
+
+    /// A timeout in seconds for connecting and sending the report.
+    #[serde(default = "ReportSocket::default_timeout")]
+    #[cfg_attr(not(feature = "sockets"), allow(dead_code))]
+    timeout: u64,
+}
+
+impl ReportSocket {
+    /// Default timeout in seconds when sending the report
+    fn default_timeout() -> u64 {
+        DEFAULT_WRITE_TIMEOUT
+    }
+
+    /// Sends the report in a single connection: the report message, followed by the end-of-run message.
+    #[cfg(feature = "sockets")]
+    pub fn send<R: Serialize>(&self, report: &R) -> AppResult<()> {
+        let timeout = Duration::from_secs(self.timeout);
+
+        match &self.socket {
+            ReportSocketType::Tcp(addr) => {
+                let stream = connect(addr, self.timeout)?;
+                stream
+                    .set_write_timeout(Some(timeout))
+                    .map_err(|e| context!(e, "unable to set socket timeout: {}", addr))?;
+                write_report(&stream, report)
+                    .map_err(|e| context!(e, "error sending run report to address: {}", addr))?;
+            }
+            #[cfg(target_family = "unix")]
+            ReportSocketType::Domain(path) => {
+                let stream = UnixStream::connect(path).map_err(|e| {
+                    context!(e, "unable to connect to UNIX socket address: {:?}", path)
+                })?;
+                stream
+                    .set_write_timeout(Some(timeout))
+                    .map_err(|e| context!(e, "unable to set socket timeout: {:?}", path))?;
+                write_report(&stream, report)
+                    .map_err(|e| context!(e, "error sending run report to address: {:?}", path))?;
+            }
+            #[cfg(not(target_family = "unix"))]
+            ReportSocketType::Domain(path) => {
+                return Err(AppError::new_custom(
+                    crate::misc::error::AppCustomErrorKind::UnsupportedOnPlatform,
+                    &format!("UNIX domain socket {:?} is not supported", path),
+                ))
+            }
+        }
+
+        debug!("run report sent to {:?}", &self.socket);
+        Ok(())
+    }
+
+    /// The report can't be sent without the `sockets` feature.
+    #[cfg(not(feature = "sockets"))]
+    pub fn send<R: Serialize>(&self, _report: &R) -> AppResult<()> {
+        Err(AppError::new_custom(
+            crate::misc::error::AppCustomErrorKind::NotBuiltIn,
+            &format!(
+                "report socket {:?} is not built into this binary",
+                self.socket
+            ),
+        ))
+    }
+}
+
+// Auto-implement FromStr
+fromstr!(ReportSocket);
+
+// the report is the only payload of its connection
+#[cfg(feature = "sockets")]
+fn write_report<W: Write + Copy, R: Serialize>(stream: W, report: &R) -> std::io::Result<()> {
+    protocol::write_frame(stream, &protocol::run_report(1, report).to_string())?;
+    protocol::write_frame(stream, &protocol::end_of_run(2, 1).to_string())
+}
+
+#[cfg(all(test, feature = "sockets"))]
+mod tests {
+    use super::*;
+    use crate::configuration::protocol::{Message, Receiver};
+    use std::net::TcpListener;
+    use std::str::FromStr;
+
+    #[test]
+    fn report_socket() {
+        let yaml = "domain: /run/agent.sock";
+        let report_socket = ReportSocket::from_str(yaml).expect("unable to read YAML");
+        assert_eq!(
+            report_socket.socket,
+            ReportSocketType::Domain(PathBuf::from("/run/agent.sock"))
+        );
+        assert_eq!(report_socket.timeout, DEFAULT_WRITE_TIMEOUT);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let yaml = format!("address: {}\ntimeout: 2", listener.local_addr().unwrap());
+        let report_socket = ReportSocket::from_str(&yaml).expect("unable to read YAML");
+
+        let server = std::thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let mut receiver = Receiver::new(socket);
+            let mut messages: Vec<Message> = Vec::new();
+            while let Some(message) = receiver.recv().unwrap() {
+                messages.push(message);
+            }
+            messages
+        });
+
+        report_socket
+            .send(&serde_json::json!({ "state": "OK" }))
+            .unwrap();
+        let messages = server.join().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].protocol, Some(protocol::PROTOCOL_VERSION));
+        assert_eq!(
+            messages[0].run_report,
+            Some(serde_json::json!({ "state": "OK" }))
+        );
+        assert_eq!(messages[1].end_of_run.as_ref().unwrap().sent, 1);
+    }
+}
@@ -0,0 +1,68 @@
+//! Contains the lines required around the matches of a tag. With `requires_before`, a match is only counted if a line
+//! matching another regex was found within a number of lines before it, like an error only meaningful during a
+//! transaction. With `requires_after`, a line matching the regex must be found within a number of lines after it, like
+//! an error only reported if followed by a rollback.
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::configuration::logfiledef::to_regex;
+use crate::fromstr;
+
+/// A line which must be found near a match for the match to be counted.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Requirement {
+    /// The regex of the required line.
+    #[serde(deserialize_with = "to_regex")]
+    pub regex: Option<Regex>,
+
+    /// Maximum distance in lines between the match and the required line: 1 means the line right before or after.
+    #[serde(default = "Requirement::default_lines")]
+    pub lines: u64,
+}
+
+impl Requirement {
+    /// Whether this line is the required one.
+    pub fn is_match(&self, line: &str) -> bool {
+        self.regex.as_ref().is_some_and(|re| re.is_match(line))
+    }
+
+    /// Whether a match at `line_number` is preceded by the required line, found last at `required_line`.
+    pub fn precedes(&self, required_line: Option<u64>, line_number: u64) -> bool {
+        required_line.is_some_and(|l| l < line_number && line_number - l <= self.lines)
+    }
+
+    fn default_lines() -> u64 {
+        1
+    }
+}
+
+// Auto-implement FromStr
+fromstr!(Requirement);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn requirement() {
+        let requirement =
+            Requirement::from_str("{ regex: 'BEGIN TRANSACTION', lines: 3 }").unwrap();
+        assert_eq!(requirement.lines, 3);
+        assert!(requirement.is_match("12:00:00 BEGIN TRANSACTION 42"));
+        assert!(!requirement.is_match("12:00:01 COMMIT"));
+
+        assert!(requirement.precedes(Some(7), 10));
+        assert!(requirement.precedes(Some(9), 10));
+        assert!(!requirement.precedes(Some(6), 10));
+        assert!(!requirement.precedes(Some(10), 10));
+        assert!(!requirement.precedes(Some(12), 10));
+        assert!(!requirement.precedes(None, 10));
+
+        let requirement = Requirement::from_str("regex: 'ROLLBACK'").unwrap();
+        assert_eq!(requirement.lines, 1);
+
+        assert!(Requirement::from_str("{ regex: 'ROLLBACK', distance: 3 }").is_err());
+    }
+}
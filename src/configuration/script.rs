@@ -1,10 +1,11 @@
 //! Contains the configuration of a script meant to be called either at the beginning of the search, for every line or at the end of all searches.
+use std::path::PathBuf;
 use std::process::{Command, Stdio};
 
 use serde::Deserialize;
 
 use crate::configuration::vars::GlobalVars;
-use crate::misc::{nagios::Nagios, util::DEFAULT_SCRIPT_TIMEOUT};
+use crate::misc::util::DEFAULT_SCRIPT_TIMEOUT;
 
 /// A callable script.
 #[derive(Debug, Deserialize, Clone)]
@@ -25,6 +26,37 @@ pub struct Script {
     /// exit clf with UNKNOW if script exit code is non 0
     #[serde(default)]
     pub exit_on_error: bool,
+
+    /// what to do if the script exit code is non 0. Takes precedence over `exit_on_error`
+    pub on_failure: Option<OnFailure>,
+
+    /// logfiles whose searches are skipped if the script exit code is non 0 and `on_failure` is `continue`
+    #[serde(default)]
+    pub skip_searches: Vec<PathBuf>,
+}
+
+/// What to do when a script exits with a non-zero code.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum OnFailure {
+    /// exit clf with UNKNOWN
+    abort,
+
+    /// go on with the searches, except those listed in `skip_searches`
+    r#continue,
+}
+
+/// The result of a script run.
+#[derive(Debug)]
+pub struct ScriptOutput {
+    /// pid of the script process
+    pub pid: u32,
+
+    /// `false` if the script exited with a non-zero code or timed out. Always `true` for an async script
+    pub success: bool,
+
+    /// variables exported by the script, from the `KEY=VALUE` lines of its standard output
+    pub vars: GlobalVars,
 }
 
 impl Script {
@@ -33,8 +65,17 @@ impl Script {
         DEFAULT_SCRIPT_TIMEOUT
     }
 
+    /// What to do when the script exits with a non-zero code
+    pub fn on_failure(&self) -> OnFailure {
+        self.on_failure.unwrap_or(if self.exit_on_error {
+            OnFailure::abort
+        } else {
+            OnFailure::r#continue
+        })
+    }
+
     /// Run command and optionnally wait for timeout
-    pub fn spawn(&self, vars: Option<&GlobalVars>) -> std::io::Result<u32> {
+    pub fn spawn(&self, vars: Option<&GlobalVars>) -> std::io::Result<ScriptOutput> {
         let cmd = &self.command[0];
         let args = &self.command[1..];
         trace!("script is called with arguments: {:?}", &self.command);
@@ -67,32 +108,24 @@ impl Script {
         // if async, don't wait and just leave
         if self.async_flag {
             trace!("async flag set, returning with pid:{}", pid);
-            return Ok(pid);
+            return Ok(ScriptOutput {
+                pid,
+                success: true,
+                vars: GlobalVars::new(),
+            });
         }
 
-        // try to get the exit status
-        match child.try_wait() {
+        // try to get the exit status. A script still running after its timeout is killed and considered as failed: the
+        // caller decides whether to go on, depending on `on_failure`
+        let success = match child.try_wait() {
             Ok(Some(status)) => {
-                if !status.success() && self.exit_on_error {
-                    #[cfg(not(test))]
-                    Nagios::exit_unknown(&format!(
-                        "script:{:?}, pid:{}, exit_on_error is set and exit code is:{} ",
-                        &self.command,
-                        pid,
-                        status.code().unwrap()
-                    ));
-                    #[cfg(test)]
-                    std::process::exit(0);
-                } else {
-                    info!(
-                        "script:{:?}, pid:{}, exit code is:{} ",
-                        &self.command,
-                        pid,
-                        status.code().unwrap()
-                    );
-                    #[cfg(test)]
-                    std::process::exit(0);
-                }
+                info!(
+                    "script:{:?}, pid:{}, exit code is:{:?} ",
+                    &self.command,
+                    pid,
+                    status.code()
+                );
+                status.success()
             }
             Ok(None) => {
                 let result = child.kill();
@@ -100,25 +133,22 @@ impl Script {
                     "script:{:?}, pid:{}, timeout occured, pid kill() result={:?}",
                     &self.command, pid, result
                 );
-                #[cfg(not(test))]
-                Nagios::exit_unknown(&format!(
-                    "script: {:?} timed-out, pid:{}",
-                    &self.command, pid
-                ))
+                false
             }
-            Err(e) => Nagios::exit_unknown(&format!(
-                "script {:?} couldn't start, error={} !",
-                self.command, e
-            )),
-        }
+            Err(e) => return Err(e),
+        };
 
-        let output = child.wait_with_output().expect("failed to wait on child");
+        let output = child.wait_with_output()?;
         info!(
             "stdout={:?}, stderr={:?}",
             std::str::from_utf8(&output.stdout),
             std::str::from_utf8(&output.stderr)
         );
-        Ok(pid)
+        Ok(ScriptOutput {
+            pid,
+            success,
+            vars: exported_vars(&String::from_utf8_lossy(&output.stdout)),
+        })
     }
 
     // just sleep main thread with specified timeout
@@ -132,10 +162,65 @@ impl Script {
     }
 }
 
+// each `KEY=VALUE` line of the output gives the CLF_KEY variable. Other lines are ignored
+fn exported_vars(stdout: &str) -> GlobalVars {
+    let mut vars = GlobalVars::new();
+    for line in stdout.lines() {
+        if let Some((key, value)) = line.split_once('=') {
+            let key = key.trim();
+            if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                continue;
+            }
+            let name = if key.starts_with("CLF_") {
+                key.to_string()
+            } else {
+                format!("CLF_{}", key)
+            };
+            vars.insert(name, value.trim_end_matches('\r').to_string());
+        }
+    }
+    vars
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn on_failure() {
+        let yaml = r#"
+command: ["check_db.sh"]
+on_failure: continue
+skip_searches: [/var/log/db.log]
+"#;
+        let script: Script = serde_yaml::from_str(yaml).expect("unable to read YAML");
+        assert_eq!(script.on_failure(), OnFailure::r#continue);
+        assert_eq!(script.skip_searches, vec![PathBuf::from("/var/log/db.log")]);
+
+        let script: Script =
+            serde_yaml::from_str("command: [check_db.sh]\nexit_on_error: true").unwrap();
+        assert_eq!(script.on_failure(), OnFailure::abort);
+        let script: Script = serde_yaml::from_str(
+            "command: [check_db.sh]\nexit_on_error: true\non_failure: continue",
+        )
+        .unwrap();
+        assert_eq!(script.on_failure(), OnFailure::r#continue);
+        let script: Script = serde_yaml::from_str("command: [check_db.sh]").unwrap();
+        assert_eq!(script.on_failure(), OnFailure::r#continue);
+        assert!(serde_yaml::from_str::<Script>("command: [a]\non_failure: retry").is_err());
+    }
+
+    #[test]
+    fn exported_vars() {
+        let vars = super::exported_vars(
+            "starting\nDB_STATUS=up\nCLF_ROLE=primary\r\nURL=http://db?a=b\nbad key=1\n=empty\n",
+        );
+        assert_eq!(vars.len(), 3);
+        assert_eq!(vars["CLF_DB_STATUS"], "up");
+        assert_eq!(vars["CLF_ROLE"], "primary");
+        assert_eq!(vars["CLF_URL"], "http://db?a=b");
+    }
+
     #[test]
     #[cfg(target_family = "unix")]
     fn spawn() {
@@ -146,18 +231,18 @@ async: true
 "#;
 
         let script: Script = serde_yaml::from_str(yaml).expect("unable to read YAML");
-        let _pid = script.spawn(None);
+        assert!(script.spawn(None).unwrap().success);
 
-        // sync timeout = 5s
+        // sync timeout = 2s: the script is killed and considered as failed
         let yaml = r#"
 command: ["sleep", "1000"]
 timeout: 2000   
 "#;
 
         let script: Script = serde_yaml::from_str(yaml).expect("unable to read YAML");
-        let _pid = script.spawn(None);
+        assert!(!script.spawn(None).unwrap().success);
 
-        // sync timeout = 5s
+        // sync timeout = 100ms, with an invalid date
         let yaml = r#"
 command: ["date", "'%f'"]
 timeout: 100  
@@ -165,7 +250,7 @@ exit_on_error: true
 "#;
 
         let script: Script = serde_yaml::from_str(yaml).expect("unable to read YAML");
-        let _pid = script.spawn(None);
+        assert!(!script.spawn(None).unwrap().success);
     }
 
     #[test]
@@ -1,7 +1,16 @@
 //! Contains the configuration for a search.
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use log::{debug, warn};
 use serde::Deserialize;
 
-use super::{logfiledef::LogFileDef, tag::Tag};
+use super::{logfiledef::LogFileDef, logsource::LogSource, tag::Tag};
+use crate::misc::{
+    error::{AppCustomErrorKind, AppError, AppResult},
+    nagios::NagiosError,
+};
 
 #[derive(Debug, Deserialize, Clone)]
 #[serde(deny_unknown_fields)]
@@ -12,20 +21,104 @@ pub struct Search {
 
     /// a unique identifier for this search
     pub tags: Vec<Tag>,
+
+    /// maximum time in seconds allowed to search the logfile for all tags. Overrides the global one
+    pub search_timeout: Option<u64>,
+
+    /// optional snapshot file or directory where run data for this search are kept. Overrides the global one
+    pub snapshot_file: Option<PathBuf>,
+
+    /// options inherited by all tags of this search, merged after the global ones
+    pub default_options: Option<String>,
+
+    /// labels like the team or the service owning the logfile, passed through to callbacks and match records
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
 }
 
 impl Search {
+    /// If the logfile path holds a glob pattern like `/var/log/app/*.log`, returns a search for each matching logfile,
+    /// keyed by its canonical path. If none matches, the search is kept as is, so the missing logfile is reported.
+    pub fn expand_glob(self) -> AppResult<Vec<Search>> {
+        let pattern = match &self.logfile.path {
+            LogSource::LogFile(path) if is_glob(path) => path.clone(),
+            _ => return Ok(vec![self]),
+        };
+
+        let entries = glob::glob(&pattern.to_string_lossy()).map_err(|e| {
+            AppError::new_custom(
+                AppCustomErrorKind::InvalidGlobPattern,
+                &format!("invalid glob pattern {:?}: {}", pattern, e),
+            )
+        })?;
+
+        // a logfile could be found through several links
+        let mut paths: Vec<PathBuf> = Vec::new();
+        for entry in entries {
+            match entry {
+                Ok(path) if path.is_file() => {
+                    let path = std::fs::canonicalize(&path).unwrap_or(path);
+                    if !paths.contains(&path) {
+                        paths.push(path);
+                    }
+                }
+                Ok(_) => (),
+                Err(e) => warn!("error expanding glob pattern {:?}: {}", pattern, e),
+            }
+        }
+        debug!("glob pattern {:?}: logfiles {:?}", pattern, paths);
+
+        let mut search = self;
+        search.logfile.glob = Some(pattern);
+        if paths.is_empty() {
+            return Ok(vec![search]);
+        }
+
+        Ok(paths
+            .into_iter()
+            .map(|path| {
+                let mut expanded = search.clone();
+                expanded.logfile.path = LogSource::LogFile(path);
+                expanded
+            })
+            .collect())
+    }
+
     /// Return the list of all tag names
     pub fn tag_names(&self) -> Vec<&str> {
         self.tags.iter().map(|x| x.name.as_str()).collect()
     }
+
+    /// Return what to report when the logfile is not accessible: the strictest policy of all processed tags, each tag
+    /// either overriding the logfile one or not
+    pub fn logfilemissing(&self) -> &NagiosError {
+        let default = &self.logfile.logfilemissing;
+
+        self.tags
+            .iter()
+            .filter(|tag| tag.process)
+            .map(|tag| tag.logfilemissing.as_ref().unwrap_or(default))
+            .reduce(|acc, x| acc.strictest(x))
+            .unwrap_or(default)
+    }
+
+    /// Return the search timeout, either the one defined for this search or the global default
+    pub fn timeout(&self, global_timeout: Option<u64>) -> Option<Duration> {
+        self.search_timeout
+            .or(global_timeout)
+            .map(Duration::from_secs)
+    }
+}
+
+// a path is a glob pattern if it holds any of its special characters
+fn is_glob(path: &std::path::Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::configuration::logfiledef::LogFileFormat;
-    use std::path::PathBuf;
 
     #[test]
     fn search() {
@@ -34,6 +127,9 @@ logfile:
     path: /var/log/kern.log
     format: json
     exclude: '^error'
+search_timeout: 10
+snapshot_file: /tmp/team.json
+labels: { team: db, service: billing }
 tags: 
   - name: error
     options: "runcallback"
@@ -57,7 +153,18 @@ tags:
 
         assert_eq!(s.logfile.path(), &PathBuf::from("/var/log/kern.log"));
         assert_eq!(s.logfile.format, LogFileFormat::json);
-        assert_eq!(s.logfile.exclude.unwrap().as_str(), "^error");
+        assert_eq!(s.logfile.exclude.as_ref().unwrap().as_str(), "^error");
+
+        assert_eq!(s.timeout(None), Some(Duration::from_secs(10)));
+        assert_eq!(s.timeout(Some(60)), Some(Duration::from_secs(10)));
+        assert_eq!(s.snapshot_file, Some(PathBuf::from("/tmp/team.json")));
+        assert_eq!(s.labels.get("team").unwrap(), "db");
+        assert_eq!(s.labels.get("service").unwrap(), "billing");
+
+        let mut vars = crate::configuration::vars::RuntimeVars::default();
+        vars.insert_labels(&s.labels);
+        assert_eq!(vars.len(), 2);
+        assert!(vars.contains_key("CLF_LABEL_team"));
 
         assert_eq!(s.tags.len(), 1);
         let tag = s.tags.get(0).unwrap();
@@ -74,4 +181,37 @@ tags:
             &["arg1", "arg2", "arg3"]
         );
     }
+
+    #[test]
+    fn logfilemissing() {
+        let yaml = r#"
+logfile:
+    path: /var/log/kern.log
+    logfilemissing: warning
+tags:
+  - name: info
+    logfilemissing: ok
+    patterns:
+        warning: { regexes: ['error'] }
+  - name: skipped
+    process: false
+    logfilemissing: critical
+    patterns:
+        warning: { regexes: ['error'] }
+            "#;
+        let mut s: Search = serde_yaml::from_str(yaml).expect("unable to read YAML");
+        assert_eq!(s.logfile.logfilemissing, NagiosError::WARNING);
+        assert_eq!(s.tags[0].logfilemissing, Some(NagiosError::OK));
+
+        // only one tag processed, overriding the logfile policy
+        assert_eq!(s.logfilemissing(), &NagiosError::OK);
+
+        // the strictest tag policy is used
+        s.tags[1].process = true;
+        assert_eq!(s.logfilemissing(), &NagiosError::CRITICAL);
+
+        // a tag without any override uses the logfile policy
+        s.tags[1].logfilemissing = None;
+        assert_eq!(s.logfilemissing(), &NagiosError::WARNING);
+    }
 }
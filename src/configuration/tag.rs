@@ -3,12 +3,20 @@ use serde::Deserialize;
 
 use crate::configuration::{
     callback::{Callback, CallbackHandle, ChildData},
+    dedup::Dedup,
+    multiline::Multiline,
     options::SearchOptions,
     pattern::{PatternMatchResult, PatternSet},
+    prefilter::Prefilter,
+    redact::Redact,
+    requirement::Requirement,
+    timestamp::Timestamp,
+    tolerance::Tolerance,
     vars::{GlobalVars, RuntimeVars},
 };
 
-use crate::misc::error::AppResult;
+use crate::logfile::jsonline::JsonLine;
+use crate::misc::{error::AppResult, nagios::NagiosError};
 
 use crate::fromstr;
 
@@ -34,6 +42,48 @@ pub struct Tag {
 
     /// Patterns to be checked against. These include critical and warning (along with exceptions), ok list of regexes.
     pub patterns: PatternSet,
+
+    /// Optional deduplication of alerts across runs: the callback is only called for new incidents.
+    pub dedup: Option<Dedup>,
+
+    /// Optional error budget: critical matches within the budget are reported as warnings.
+    pub tolerance: Option<Tolerance>,
+
+    /// Optional timestamp of the lines: only matches of lines written within its window are counted.
+    pub timestamp: Option<Timestamp>,
+
+    /// Optional line which must be found within a number of lines before a match, for the match to be counted.
+    pub requires_before: Option<Requirement>,
+
+    /// Optional line which must be found within a number of lines after a match, for the match to be counted.
+    pub requires_after: Option<Requirement>,
+
+    /// Optional redaction of the line and capture groups given to the callback, like IP addresses or emails.
+    pub redact: Option<Redact>,
+
+    /// Optional gathering of lines into multiline records, like Java stack traces, before patterns are matched.
+    pub multiline: Option<Multiline>,
+
+    /// What to report when the logfile is not accessible, overriding the logfile one for this tag.
+    pub logfilemissing: Option<NagiosError>,
+
+    /// Literals one of which must be found in a line for the patterns to be evaluated. If not set, they're derived from
+    /// the regexes when possible.
+    pub prefilter: Option<Prefilter>,
+
+    /// If `true`, the number of lines evaluated, matched and excluded is kept in the run data.
+    #[serde(default)]
+    pub line_stats: bool,
+
+    /// If `true`, lines hidden by the exclude regex of the logfile are still matched against the patterns, and the
+    /// critical and warning matches are counted apart, to know how much the exclusion hides.
+    #[serde(default)]
+    pub count_suppressed: bool,
+
+    /// If `true`, any critical or warning match makes the plugin exit critical, with the matched line as output,
+    /// whatever the counters and thresholds of other tags.
+    #[serde(default)]
+    pub sentinel: bool,
 }
 
 impl Tag {
@@ -42,6 +92,23 @@ impl Tag {
         self.patterns.is_match(text)
     }
 
+    /// Same as `is_match()` for a JSON line: patterns defining a field are matched against its value.
+    pub fn is_match_json(
+        &self,
+        line: &str,
+        json: Option<&JsonLine>,
+    ) -> Option<PatternMatchResult<'_>> {
+        self.patterns.is_match_json(line, json)
+    }
+
+    /// `false` if the line can't match any pattern, because none of the literals of the prefilter is found in it.
+    pub fn is_candidate(&self, line: &str) -> bool {
+        self.prefilter
+            .as_ref()
+            .or_else(|| self.patterns.prefilter())
+            .is_none_or(|prefilter| prefilter.is_candidate(line))
+    }
+
     /// Default value for processing a tag
     pub fn default_process() -> bool {
         true
@@ -0,0 +1,170 @@
+//! Tera settings used to render configuration files. Templates are sandboxed: they can only include or import the
+//! macros found in the includes directory beside the configuration file, and nothing else is read from disk. A few
+//! helpers are added to the Tera builtins for realistic formatting.
+use std::collections::HashMap;
+use std::path::Path;
+
+use chrono::{Local, Utc};
+use regex::Regex;
+use tera::{Context, Tera, Value};
+
+use crate::misc::error::{AppCustomErrorKind, AppError, AppResult};
+
+/// Directory beside the configuration file where macros are loaded from.
+pub const INCLUDES_DIR: &str = "includes";
+
+// name of the configuration template, among the included ones
+const CONFIG_TEMPLATE: &str = "__clf_config__";
+
+// name of the plugin output template in errors
+const OUTPUT_TEMPLATE: &str = "output_template";
+
+/// Renders a configuration file content with the Tera context. Macros are loaded from the `includes` directory
+/// beside the configuration file, if any.
+pub fn render(config_file: &Path, config: &str, context: &Context) -> AppResult<String> {
+    let includes = config_file
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(INCLUDES_DIR);
+
+    let mut tera = sandbox(&includes)?;
+    tera.add_raw_template(CONFIG_TEMPLATE, config)
+        .map_err(|e| template_error(e, config_file))?;
+    tera.render(CONFIG_TEMPLATE, context)
+        .map_err(|e| template_error(e, config_file))
+}
+
+/// A Tera instance with the clf helpers, whose only templates are those of the includes directory.
+pub fn sandbox(includes: &Path) -> AppResult<Tera> {
+    let mut tera = if includes.is_dir() {
+        let glob = includes.join("**").join("*");
+        Tera::new(&glob.to_string_lossy()).map_err(|e| template_error(e, includes))?
+    } else {
+        Tera::default()
+    };
+
+    register_helpers(&mut tera);
+    Ok(tera)
+}
+
+/// Renders the plugin output from the `output_template` global option, with the clf helpers.
+pub fn render_output(template: &str, context: &Context) -> AppResult<String> {
+    let mut tera = Tera::default();
+    register_helpers(&mut tera);
+    tera.render_str(template, context)
+        .map_err(|e| template_error(e, Path::new(OUTPUT_TEMPLATE)))
+}
+
+// the filters and functions added to the Tera builtins
+fn register_helpers(tera: &mut Tera) {
+    tera.register_filter("regex_extract", regex_extract);
+    tera.register_filter("basename", basename);
+    tera.register_filter("to_upper", to_upper);
+    tera.register_function("now", now);
+}
+
+// Tera errors are chained: the cause is usually in the last one
+fn template_error(e: tera::Error, path: &Path) -> AppError {
+    let mut msg = e.to_string();
+    let mut source = std::error::Error::source(&e);
+    while let Some(e) = source {
+        msg = format!("{}: {}", msg, e);
+        source = e.source();
+    }
+
+    AppError::new_custom(
+        AppCustomErrorKind::TemplateError,
+        &format!("{:?}: {}", path, msg),
+    )
+}
+
+// {{ line | regex_extract(pattern="code=(\d+)", group=1) }}: the text of the capture group (the whole match by
+// default), or an empty string if the regex doesn't match
+fn regex_extract(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let text = value
+        .as_str()
+        .ok_or_else(|| tera::Error::msg("regex_extract filter only applies to strings"))?;
+    let pattern = args
+        .get("pattern")
+        .and_then(|x| x.as_str())
+        .ok_or_else(|| tera::Error::msg("regex_extract filter needs a pattern argument"))?;
+    let group = args.get("group").and_then(|x| x.as_u64()).unwrap_or(0) as usize;
+
+    let re = Regex::new(pattern).map_err(tera::Error::msg)?;
+    let extracted = re
+        .captures(text)
+        .and_then(|caps| caps.get(group))
+        .map_or("", |m| m.as_str());
+    Ok(Value::from(extracted))
+}
+
+// {{ "/var/log/syslog" | basename }}: the file name of a path
+fn basename(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    let path = value
+        .as_str()
+        .ok_or_else(|| tera::Error::msg("basename filter only applies to strings"))?;
+    let name = Path::new(path)
+        .file_name()
+        .map_or(String::new(), |x| x.to_string_lossy().to_string());
+    Ok(Value::from(name))
+}
+
+// {{ "warning" | to_upper }}
+fn to_upper(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    let text = value
+        .as_str()
+        .ok_or_else(|| tera::Error::msg("to_upper filter only applies to strings"))?;
+    Ok(Value::from(text.to_uppercase()))
+}
+
+// {{ now(format="%Y%m%d", utc=true) }}: the current time, formatted with a strftime format (RFC 3339 by default)
+fn now(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let utc = args.get("utc").and_then(|x| x.as_bool()).unwrap_or(false);
+    let formatted = match args.get("format").and_then(|x| x.as_str()) {
+        Some(format) if utc => Utc::now().format(format).to_string(),
+        Some(format) => Local::now().format(format).to_string(),
+        None if utc => Utc::now().to_rfc3339(),
+        None => Local::now().to_rfc3339(),
+    };
+    Ok(Value::from(formatted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let dir = std::env::temp_dir().join("clf_template_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join(INCLUDES_DIR)).unwrap();
+        std::fs::write(
+            dir.join(INCLUDES_DIR).join("macros.tera"),
+            "{% macro tag(name) %}name: {{ name | to_upper }}{% endmacro tag %}",
+        )
+        .unwrap();
+
+        let config = r#"{% import "macros.tera" as m %}{{ m::tag(name="error") }}
+path: {{ path | basename }}
+code: {{ "status code=503" | regex_extract(pattern="code=(\d+)", group=1) }}
+none: '{{ "OK" | regex_extract(pattern="\d+") }}'
+year: {{ now(format="%Y", utc=true) }}"#;
+        let mut context = Context::new();
+        context.insert("path", "/var/log/syslog");
+
+        let rendered = super::render(&dir.join("config.yml"), config, &context).unwrap();
+        assert_eq!(
+            rendered,
+            format!(
+                "name: ERROR\npath: syslog\ncode: 503\nnone: ''\nyear: {}",
+                Utc::now().format("%Y")
+            )
+        );
+
+        // only templates of the includes directory can be included
+        let config = r#"{% include "/etc/passwd" %}"#;
+        assert!(super::render(&dir.join("config.yml"), config, &context).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+//! Contains the timestamp of the lines of a tag. Matches are only counted if their line was written within a window of
+//! time, whatever the offsets: a logfile read for the first time, or read again with the `rewind` option, doesn't
+//! report problems long gone. The timestamp is found in the line by a regex, and parsed with a *chrono* format.
+use std::convert::TryFrom;
+
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, TimeZone};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::configuration::logfiledef::to_regex;
+use crate::fromstr;
+
+/// How the timestamp of a line is found and parsed, and the window of the lines whose matches are counted.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Timestamp {
+    /// A regex finding the timestamp in the line: the `ts` capture group if any, otherwise the whole match.
+    #[serde(deserialize_with = "to_regex")]
+    pub regex: Option<Regex>,
+
+    /// The *chrono* format of the timestamp, like `%Y-%m-%d %H:%M:%S`. Without a time zone, the timestamp is in local
+    /// time, and without a year, it's within the last 12 months.
+    pub format: String,
+
+    /// Age in seconds of the lines whose matches are counted. 0 means all matches are counted.
+    #[serde(default)]
+    pub window: u64,
+}
+
+impl Timestamp {
+    /// The time of the line in seconds since the UNIX epoch, if its timestamp is found and could be parsed.
+    pub fn parse(&self, line: &str, now: u64) -> Option<u64> {
+        let caps = self.regex.as_ref()?.captures(line)?;
+        let text = caps.name("ts").or_else(|| caps.get(0))?.as_str();
+
+        // the format holds the time zone
+        if let Ok(time) = DateTime::parse_from_str(text, &self.format) {
+            return u64::try_from(time.timestamp()).ok();
+        }
+
+        // a syslog timestamp has no year: it's the current one, unless the time would then be in the future
+        let naive = match NaiveDateTime::parse_from_str(text, &self.format) {
+            Ok(naive) => naive,
+            Err(_) => {
+                let year = Local.timestamp_opt(now as i64, 0).single()?.year();
+                let format = format!("%Y {}", self.format);
+                let naive =
+                    NaiveDateTime::parse_from_str(&format!("{} {}", year, text), &format).ok()?;
+                if self.local_secs(naive)? > now + 86400 {
+                    naive.with_year(year - 1)?
+                } else {
+                    naive
+                }
+            }
+        };
+
+        self.local_secs(naive)
+    }
+
+    /// Whether a line written at this time is within the window.
+    pub fn is_within(&self, time: u64, now: u64) -> bool {
+        self.window == 0 || time.saturating_add(self.window) >= now
+    }
+
+    // seconds since the UNIX epoch of a timestamp in local time. Seconds since the epoch are in UTC
+    fn local_secs(&self, naive: NaiveDateTime) -> Option<u64> {
+        let secs = if self.format.contains("%s") {
+            naive.and_utc().timestamp()
+        } else {
+            Local.from_local_datetime(&naive).earliest()?.timestamp()
+        };
+        u64::try_from(secs).ok()
+    }
+}
+
+// Auto-implement FromStr
+fromstr!(Timestamp);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn timestamp() {
+        let yaml = r#"
+            regex: '^(?P<ts>\S+ \S+) '
+            format: '%Y-%m-%d %H:%M:%S'
+            window: 900
+        "#;
+        let timestamp = Timestamp::from_str(yaml).expect("unable to read YAML");
+        assert_eq!(timestamp.window, 900);
+
+        // with a time zone
+        let timestamp =
+            Timestamp::from_str("{ regex: '^\\S+', format: '%Y-%m-%dT%H:%M:%S%z', window: 900 }")
+                .unwrap();
+        let now = 1611857382;
+        assert_eq!(
+            timestamp.parse("2021-01-28T18:09:42+0000 ERROR", now),
+            Some(1611857382)
+        );
+        assert_eq!(
+            timestamp.parse("2021-01-28T19:09:42+0100 ERROR", now),
+            Some(1611857382)
+        );
+        assert!(timestamp.parse("ERROR", now).is_none());
+        assert!(timestamp.parse("2021-01-28 ERROR", now).is_none());
+
+        assert!(timestamp.is_within(now - 900, now));
+        assert!(!timestamp.is_within(now - 901, now));
+        assert!(timestamp.is_within(now + 60, now));
+
+        // seconds since the epoch, in a capture group
+        let timestamp =
+            Timestamp::from_str(r#"{ regex: 'time=(?P<ts>\d+)', format: '%s' }"#).unwrap();
+        assert_eq!(
+            timestamp.parse("ERROR time=1611857000 disk full", now),
+            Some(1611857000)
+        );
+        assert!(timestamp.is_within(0, now));
+
+        // in local time, without a year: a date after now is in the previous year
+        let timestamp =
+            Timestamp::from_str(r#"{ regex: '^\w{3} +\d+ \S+', format: '%b %d %H:%M:%S' }"#)
+                .unwrap();
+        let expected = |year| {
+            Local
+                .with_ymd_and_hms(year, 1, 28, 18, 0, 0)
+                .unwrap()
+                .timestamp() as u64
+        };
+        let now = expected(2021) + 3600;
+        assert_eq!(
+            timestamp.parse("Jan 28 18:00:00 host sshd: error", now),
+            Some(expected(2021))
+        );
+        let now = expected(2021) - 3 * 86400;
+        assert_eq!(
+            timestamp.parse("Jan 28 18:00:00 host sshd: error", now),
+            Some(expected(2020))
+        );
+
+        assert!(Timestamp::from_str("{ regex: '^\\S+', format: '%s', age: 900 }").is_err());
+    }
+}
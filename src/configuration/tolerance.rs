@@ -0,0 +1,30 @@
+//! Contains the error budget of a tag. Flaky but known issues could trigger a few critical matches now and then: as
+//! long as their number stays within the budget over the last 24 hours, they're reported as warnings, and the tag only
+//! escalates to critical once the budget is exhausted.
+use serde::Deserialize;
+
+use crate::fromstr;
+
+/// Error budget settings for a tag.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Tolerance {
+    /// Number of critical matches over a rolling day which are reported as warnings.
+    pub criticals_per_day: u64,
+}
+
+// Auto-implement FromStr
+fromstr!(Tolerance);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn tolerance() {
+        let tolerance = Tolerance::from_str("criticals_per_day: 5").expect("unable to read YAML");
+        assert_eq!(tolerance.criticals_per_day, 5);
+        assert!(Tolerance::from_str("criticals_per_hour: 5").is_err());
+    }
+}
@@ -0,0 +1,152 @@
+//! Discovery of logfiles from systemd unit definitions. The units matching a pattern are queried with `systemctl show`:
+//! when the standard output or error of a unit is written to a file (`StandardOutput=append:/var/log/app.log`), this
+//! file is searched. Otherwise, the unit's entries of the systemd journal are searched. This way, new instances of a
+//! templated service are searched without editing the configuration.
+use std::path::PathBuf;
+use std::process::Command;
+
+use log::debug;
+use serde::Deserialize;
+
+use crate::configuration::logsource::{JournalFilter, JournalSource, LogSource};
+use crate::context;
+use crate::misc::error::{AppCustomErrorKind, AppError, AppResult};
+
+/// The systemd units whose logfiles are searched, as found in the configuration file.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SystemdUnits {
+    /// a unit name or a glob pattern like `myapp-*`, matching loaded units
+    pub pattern: String,
+}
+
+impl SystemdUnits {
+    /// Queries systemd for the units matching the pattern, and returns the logfile or journal source of each one.
+    pub fn discover(&self) -> AppResult<Vec<LogSource>> {
+        let output = Command::new("systemctl")
+            .args([
+                "show",
+                "--no-pager",
+                "--property=Id,StandardOutput,StandardError",
+                &self.pattern,
+            ])
+            .output()
+            .map_err(|e| context!(e, "unable to start systemctl for units {}", self.pattern))?;
+
+        if !output.status.success() {
+            return Err(AppError::new_custom(
+                AppCustomErrorKind::SystemdUnitsError,
+                &format!(
+                    "systemctl show {} failed: {}",
+                    self.pattern,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ));
+        }
+
+        let sources = unit_sources(&String::from_utf8_lossy(&output.stdout));
+        debug!("units {}: logfiles {:?}", self.pattern, sources);
+        Ok(sources)
+    }
+}
+
+// the sources of the units described by `systemctl show`, one block of properties for each unit. The standard error is
+// written to the same place as the standard output unless set otherwise
+fn unit_sources(show: &str) -> Vec<LogSource> {
+    let mut sources = Vec::new();
+
+    for block in show.split("\n\n") {
+        let property = |name: &str| {
+            block
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix('='))
+                .filter(|value| !value.is_empty())
+        };
+
+        let unit = match property("Id") {
+            Some(unit) => unit,
+            None => continue,
+        };
+
+        // anything not written to a file goes to the journal, by default
+        let journal = || {
+            LogSource::Journal(JournalSource::from(JournalFilter {
+                unit: Some(unit.to_string()),
+                ..Default::default()
+            }))
+        };
+        let source = |output: Option<&str>| match output.and_then(output_file) {
+            Some(path) => LogSource::LogFile(path),
+            None => journal(),
+        };
+
+        let stdout = source(property("StandardOutput"));
+        let stderr = match property("StandardError") {
+            Some("inherit") | None => None,
+            stderr => Some(source(stderr)),
+        };
+
+        for source in std::iter::once(stdout).chain(stderr) {
+            if !sources.contains(&source) {
+                sources.push(source);
+            }
+        }
+    }
+
+    sources
+}
+
+// the file of an output setting like `append:/var/log/app.log`, if the output is written to a file
+fn output_file(output: &str) -> Option<PathBuf> {
+    let (kind, path) = output.split_once(':')?;
+    match kind {
+        "file" | "append" | "truncate" => Some(PathBuf::from(path)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_sources() {
+        let show = "Id=myapp-1.service
+StandardOutput=append:/var/log/myapp-1.log
+StandardError=inherit
+
+Id=myapp-2.service
+StandardOutput=file:/var/log/myapp-2.log
+StandardError=append:/var/log/myapp-2.err
+
+Id=myapp-3.service
+StandardOutput=journal
+StandardError=inherit
+
+Id=myapp-4.service
+StandardOutput=journal
+StandardError=append:/var/log/myapp-1.log
+";
+        let sources: Vec<_> = super::unit_sources(show)
+            .iter()
+            .map(|source| source.to_string())
+            .collect();
+        assert_eq!(
+            sources,
+            vec![
+                "/var/log/myapp-1.log",
+                "/var/log/myapp-2.log",
+                "/var/log/myapp-2.err",
+                "journal:unit=myapp-3.service",
+                "journal:unit=myapp-4.service"
+            ]
+        );
+
+        assert!(super::unit_sources("").is_empty());
+        assert_eq!(output_file("journal+console"), None);
+        assert_eq!(
+            output_file("truncate:/tmp/a:b"),
+            Some(PathBuf::from("/tmp/a:b"))
+        );
+    }
+}
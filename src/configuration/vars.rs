@@ -1,7 +1,7 @@
 //! Contains the definition of all structures for handling variables, either user-defined as used in the global tag, or
 //! the runtime ones populated each time a pattern is matched.
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
 use std::hash::Hash;
 use std::ops::{Deref, DerefMut};
@@ -9,6 +9,8 @@ use std::ops::{Deref, DerefMut};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+use crate::configuration::options::CaptureVars;
+use crate::logfile::jsonline::JsonLine;
 use crate::misc::util::{CAPTURE_GROUPS, CAPTURE_GROUPS_LENGTH, DEFAULT_CONTAINER_CAPACITY};
 
 use super::pattern::PatternType;
@@ -33,7 +35,7 @@ macro_rules! prefix_var {
 }
 
 // A variable sent through a JSON string could be either a string or an integer
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 #[serde(untagged)]
 pub enum VarType<T> {
     Str(T),
@@ -107,16 +109,19 @@ impl<T: Display> Display for VarType<T> {
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(deny_unknown_fields)]
+#[serde(transparent)]
 /// A generic variable structure.
 pub struct Vars<K: Hash + Eq, V> {
-    #[serde(flatten)]
     inner: HashMap<K, V>,
 }
 
 /// Runtime vars are created for each matched line. Using `Cow` minimizes string allocations.
 pub type RuntimeVars<'a> = Vars<Cow<'a, str>, VarType<&'a str>>;
 
+/// Runtime vars of a match whose callback is not called yet. They're owned, to be kept in the snapshot until the
+/// callback is successfully called.
+pub type PendingVars = Vars<String, VarType<String>>;
+
 /// user vars are optionally defined in the global configuration tag.
 pub type GlobalVars = HashMap<String, String>;
 
@@ -181,8 +186,67 @@ impl<'a> Vars<Cow<'a, str>, VarType<&'a str>> {
         self.inner.insert(Cow::from(name), value.into());
     }
 
+    /// Add the line as CLF_LINE, or if it's longer than `chunk_size` bytes, as CLF_LINE_1..N chunks along with
+    /// CLF_LINE_CHUNKS, the number of chunks. Chunks are never split inside an UTF-8 character.
+    pub fn insert_line(&mut self, line: &'a str, chunk_size: usize) {
+        if chunk_size == 0 || line.len() <= chunk_size {
+            self.insert_runtime_var(prefix_var!("LINE"), line);
+            return;
+        }
+
+        let mut rest = line;
+        let mut i = 0usize;
+        while !rest.is_empty() {
+            let mut end = chunk_size.min(rest.len());
+            while !rest.is_char_boundary(end) {
+                end -= 1;
+            }
+
+            // chunk size is lower than the size of this character: keep it whole
+            if end == 0 {
+                end = rest.chars().next().map_or(1, |c| c.len_utf8());
+            }
+
+            let (chunk, tail) = rest.split_at(end);
+            i += 1;
+            self.inner
+                .insert(prefix_var!("LINE_", i), VarType::from(chunk));
+            rest = tail;
+        }
+
+        self.insert_runtime_var(prefix_var!("LINE_CHUNKS"), i);
+    }
+
+    /// Add search labels as CLF_LABEL_<name> variables.
+    pub fn insert_labels(&mut self, labels: &'a BTreeMap<String, String>) {
+        for (name, value) in labels {
+            self.inner
+                .insert(prefix_var!("LABEL_", name), VarType::from(value.as_str()));
+        }
+    }
+
+    /// Add the fields of a JSON line as CLF_JSON_<field> variables, like CLF_JSON_level or CLF_JSON_http.status.
+    pub fn insert_json(&mut self, json: &'a JsonLine) {
+        for (name, value) in json.iter() {
+            self.inner
+                .insert(prefix_var!("JSON_", name), VarType::from(value.as_str()));
+        }
+    }
+
     /// Add variables taken from the capture group names or ids.
     pub fn insert_captures(&mut self, re: &Regex, text: &'a str) -> usize {
+        self.insert_captures_with(re, text, CaptureVars::all, None)
+    }
+
+    /// Add variables taken from the capture groups selected by `capture_vars`, named with `prefix` instead of CLF_CG_
+    /// if any. The number of capture groups is returned whatever the groups selected.
+    pub fn insert_captures_with(
+        &mut self,
+        re: &Regex,
+        text: &'a str,
+        capture_vars: CaptureVars,
+        prefix: Option<&str>,
+    ) -> usize {
         // get the captures
         let caps = re.captures(text).unwrap();
 
@@ -191,6 +255,10 @@ impl<'a> Vars<Cow<'a, str>, VarType<&'a str>> {
 
         // now loop and get text corresponding to either name or position
         for (i, cg_name) in re.capture_names().enumerate() {
+            if !capture_vars.selects(cg_name) {
+                continue;
+            }
+
             match cg_name {
                 None => {
                     if let Some(m) = caps.get(i) {
@@ -198,16 +266,21 @@ impl<'a> Vars<Cow<'a, str>, VarType<&'a str>> {
                         if i > CAPTURE_GROUPS_LENGTH - 1 {
                             panic!("your're only limited to 30 capture groups !")
                         }
-                        self.inner
-                            //.insert(prefix_var!("CG_", i), VarType::from(m.as_str()));
-                            .insert(Cow::from(CAPTURE_GROUPS[i]), VarType::from(m.as_str()));
+                        let name = match prefix {
+                            None => Cow::from(CAPTURE_GROUPS[i]),
+                            Some(prefix) => Cow::from(format!("{}{}", prefix, i)),
+                        };
+                        self.inner.insert(name, VarType::from(m.as_str()));
                     }
                 }
                 Some(cap_name) => {
                     if let Some(m) = caps.name(cap_name) {
                         // variable will be: CLF_FOO (example)
-                        self.inner
-                            .insert(prefix_var!("CG_", cap_name), VarType::from(m.as_str()));
+                        let name = match prefix {
+                            None => prefix_var!("CG_", cap_name),
+                            Some(prefix) => Cow::from(format!("{}{}", prefix, cap_name)),
+                        };
+                        self.inner.insert(name, VarType::from(m.as_str()));
                     }
                 }
             }
@@ -215,6 +288,68 @@ impl<'a> Vars<Cow<'a, str>, VarType<&'a str>> {
 
         nbcaps
     }
+
+    /// An owned copy of these variables, to call the callback later.
+    pub fn to_pending(&self) -> PendingVars {
+        let mut pending = PendingVars::default();
+        for (name, value) in &self.inner {
+            let value = match value {
+                VarType::Str(s) => VarType::Str(s.to_string()),
+                VarType::Int(i) => VarType::Int(*i),
+            };
+            pending.insert(name.to_string(), value);
+        }
+        pending
+    }
+
+    /// Runtime vars borrowed from the variables of a pending callback call.
+    pub fn from_pending(pending: &'a PendingVars) -> Self {
+        let mut vars = RuntimeVars::default();
+        for (name, value) in pending.iter() {
+            let value = match value {
+                VarType::Str(s) => VarType::Str(s.as_str()),
+                VarType::Int(i) => VarType::Int(*i),
+            };
+            vars.insert(Cow::from(name.as_str()), value);
+        }
+        vars
+    }
+
+    /// Add a CLF_CG_<id>_VALID or CLF_CG_<name>_VALID variable for each capture group selected by `capture_vars`, set
+    /// to false if the captured text holds a replacement character introduced when decoding the line.
+    pub fn insert_captures_validity(
+        &mut self,
+        re: &Regex,
+        text: &'a str,
+        lossy: bool,
+        capture_vars: CaptureVars,
+        prefix: Option<&str>,
+    ) {
+        let caps = re.captures(text).unwrap();
+        let prefix = prefix.unwrap_or("CLF_CG_");
+
+        for (i, cg_name) in re.capture_names().enumerate() {
+            if !capture_vars.selects(cg_name) {
+                continue;
+            }
+
+            let m = match cg_name {
+                None => caps.get(i),
+                Some(cap_name) => caps.name(cap_name),
+            };
+            if let Some(m) = m {
+                let valid = !lossy || !m.as_str().contains(char::REPLACEMENT_CHARACTER);
+                let name = match cg_name {
+                    None => format!("{}{}_VALID", prefix, i),
+                    Some(cap_name) => format!("{}{}_VALID", prefix, cap_name),
+                };
+                self.inner.insert(
+                    Cow::from(name),
+                    VarType::from(if valid { "true" } else { "false" }),
+                );
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -277,4 +412,103 @@ mod tests {
         let _json = serde_json::json!({ "vars": vars }).to_string();
         //println!("{:#?}", json);
     }
+
+    #[test]
+    fn insert_captures_with() {
+        let re = Regex::new(r"^(\w+) (?P<status>\d+)").unwrap();
+        let text = "GET 404";
+
+        let mut vars = RuntimeVars::default();
+        assert_eq!(
+            vars.insert_captures_with(&re, text, CaptureVars::named_only, None),
+            3
+        );
+        assert_eq!(vars.len(), 1);
+        assert!(matches!(vars.get("CLF_CG_status").unwrap(), VarType::Str(x) if x == &"404"));
+
+        let mut vars = RuntimeVars::default();
+        vars.insert_captures_with(&re, text, CaptureVars::all, Some("HTTP_"));
+        assert!(matches!(vars.get("HTTP_0").unwrap(), VarType::Str(x) if x == &"GET 404"));
+        assert!(matches!(vars.get("HTTP_1").unwrap(), VarType::Str(x) if x == &"GET"));
+        assert!(matches!(vars.get("HTTP_status").unwrap(), VarType::Str(x) if x == &"404"));
+
+        let mut vars = RuntimeVars::default();
+        vars.insert_captures_with(&re, text, CaptureVars::none, None);
+        vars.insert_captures_validity(&re, text, true, CaptureVars::none, None);
+        assert!(vars.is_empty());
+    }
+
+    #[test]
+    fn pending() {
+        let mut vars = RuntimeVars::default();
+        vars.insert_runtime_var("CLF_LOGFILE", "/var/log/foo");
+        vars.insert_runtime_var("CLF_LINE_NUMBER", 42u64);
+
+        // pending vars are kept in the snapshot
+        let json = serde_json::to_string(&vars.to_pending()).unwrap();
+        let pending: PendingVars = serde_json::from_str(&json).unwrap();
+        assert_eq!(pending.get("CLF_LINE_NUMBER"), Some(&VarType::Int(42)));
+
+        let vars = RuntimeVars::from_pending(&pending);
+        assert_eq!(vars.len(), 2);
+        assert!(
+            matches!(vars.get("CLF_LOGFILE").unwrap(), VarType::Str(x) if x == &"/var/log/foo")
+        );
+        assert!(matches!(
+            vars.get("CLF_LINE_NUMBER").unwrap(),
+            VarType::Int(42)
+        ));
+    }
+
+    #[test]
+    fn insert_captures_validity() {
+        let re = Regex::new(r"^user (\S+) from (?P<HOST>\S+)").unwrap();
+        let text = "user jos\u{FFFD} from host1";
+
+        let is = |vars: &RuntimeVars, name: &str, value: &str| matches!(vars.get(name).unwrap(), VarType::Str(x) if x == &value);
+
+        let mut vars = RuntimeVars::default();
+        vars.insert_captures_validity(&re, text, true, CaptureVars::all, None);
+        assert!(is(&vars, "CLF_CG_0_VALID", "false"));
+        assert!(is(&vars, "CLF_CG_1_VALID", "false"));
+        assert!(is(&vars, "CLF_CG_HOST_VALID", "true"));
+
+        // the replacement character was in the logfile
+        let mut vars = RuntimeVars::default();
+        vars.insert_captures_validity(&re, text, false, CaptureVars::all, None);
+        assert!(is(&vars, "CLF_CG_1_VALID", "true"));
+    }
+
+    #[test]
+    fn insert_line() {
+        let mut vars = RuntimeVars::default();
+        vars.insert_line("short line", 0);
+        assert!(matches!(vars.get("CLF_LINE").unwrap(), VarType::Str(x) if x == &"short line"));
+
+        let mut vars = RuntimeVars::default();
+        vars.insert_line("short line", 10);
+        assert!(vars.contains_key("CLF_LINE"));
+        assert!(!vars.contains_key("CLF_LINE_CHUNKS"));
+
+        let mut vars = RuntimeVars::default();
+        vars.insert_line("0123456789abcdefghijk", 10);
+        assert!(!vars.contains_key("CLF_LINE"));
+        assert!(matches!(
+            vars.get("CLF_LINE_CHUNKS").unwrap(),
+            VarType::Int(3)
+        ));
+        assert!(matches!(vars.get("CLF_LINE_1").unwrap(), VarType::Str(x) if x == &"0123456789"));
+        assert!(matches!(vars.get("CLF_LINE_2").unwrap(), VarType::Str(x) if x == &"abcdefghij"));
+        assert!(matches!(vars.get("CLF_LINE_3").unwrap(), VarType::Str(x) if x == &"k"));
+
+        // 'é' is 2 bytes long and is not split
+        let mut vars = RuntimeVars::default();
+        vars.insert_line("abé", 3);
+        assert!(matches!(
+            vars.get("CLF_LINE_CHUNKS").unwrap(),
+            VarType::Int(2)
+        ));
+        assert!(matches!(vars.get("CLF_LINE_1").unwrap(), VarType::Str(x) if x == &"ab"));
+        assert!(matches!(vars.get("CLF_LINE_2").unwrap(), VarType::Str(x) if x == &"é"));
+    }
 }
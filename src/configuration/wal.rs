@@ -0,0 +1,174 @@
+//! A local queue in which socket callbacks write their payloads before sending them. It's an append-only file of
+//! JSON lines, emptied once all payloads are delivered. If a payload can't be delivered, because the receiver is down
+//! or clf crashed, it stays in the queue and is sent first at the next run.
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::misc::error::{AppError, AppResult};
+use crate::{context, fromstr};
+
+/// When the queue file is flushed to disk.
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+#[allow(non_camel_case_types)]
+pub enum FsyncPolicy {
+    /// after each write: no payload is lost even if the host crashes
+    #[default]
+    always,
+
+    /// left to the OS: no payload is lost if clf crashes, but could be if the host crashes
+    never,
+}
+
+/// The queue definition, as found in the callback configuration.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Wal {
+    /// path of the queue file, created if it doesn't exist
+    pub path: PathBuf,
+
+    /// when the queue file is flushed to disk
+    #[serde(default)]
+    pub fsync: FsyncPolicy,
+}
+
+impl Wal {
+    /// Appends a payload at the end of the queue.
+    pub fn append<T: Serialize>(&self, payload: &T) -> AppResult<()> {
+        let json = serde_json::to_string(payload)
+            .map_err(|e| context!(e, "unable to serialize payload for queue {:?}", self.path))?;
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&self.path)
+            .map_err(|e| context!(e, "unable to open queue {:?}", self.path))?;
+
+        // a line partially written when clf crashed is ended first, so this payload is not lost along with it
+        if !self.ends_with_newline(&mut file)? {
+            writeln!(file).map_err(|e| context!(e, "unable to write to queue {:?}", self.path))?;
+        }
+
+        writeln!(file, "{}", json)
+            .map_err(|e| context!(e, "unable to write to queue {:?}", self.path))?;
+
+        self.sync(&file)
+    }
+
+    /// Returns all payloads in the queue, oldest first. A line which is not valid JSON, like a line partially written
+    /// when clf crashed, is skipped.
+    pub fn entries(&self) -> AppResult<Vec<Value>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let data = std::fs::read_to_string(&self.path)
+            .map_err(|e| context!(e, "unable to read queue {:?}", self.path))?;
+
+        let entries = data
+            .lines()
+            .filter(|line| !line.is_empty())
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(value) => Some(value),
+                Err(e) => {
+                    warn!("skipping invalid entry in queue {:?}: {}", self.path, e);
+                    None
+                }
+            })
+            .collect();
+
+        Ok(entries)
+    }
+
+    /// Empties the queue, once all its payloads are delivered.
+    pub fn clear(&self) -> AppResult<()> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+
+        let file = File::create(&self.path)
+            .map_err(|e| context!(e, "unable to truncate queue {:?}", self.path))?;
+
+        self.sync(&file)
+    }
+
+    // true if the queue is empty or its last line is complete
+    fn ends_with_newline(&self, file: &mut File) -> AppResult<bool> {
+        let mut last = [b'\n'];
+        if file
+            .seek(SeekFrom::End(0))
+            .map_err(|e| context!(e, "unable to seek queue {:?}", self.path))?
+            > 0
+        {
+            file.seek(SeekFrom::End(-1))
+                .and_then(|_| file.read_exact(&mut last))
+                .map_err(|e| context!(e, "unable to read queue {:?}", self.path))?;
+        }
+        Ok(last[0] == b'\n')
+    }
+
+    // flush to disk depending on the policy
+    fn sync(&self, file: &File) -> AppResult<()> {
+        if self.fsync == FsyncPolicy::always {
+            file.sync_data()
+                .map_err(|e| context!(e, "unable to flush queue {:?}", self.path))?;
+        }
+        Ok(())
+    }
+}
+
+// Auto-implement FromStr
+fromstr!(Wal);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::str::FromStr;
+
+    #[test]
+    fn wal() {
+        let mut path = std::env::temp_dir();
+        path.push("clf_wal_test.ndjson");
+        let _ = std::fs::remove_file(&path);
+
+        let yaml = format!("path: {}\nfsync: never", path.display());
+        let wal = Wal::from_str(&yaml).expect("unable to read YAML");
+        assert_eq!(wal.fsync, FsyncPolicy::never);
+        assert_eq!(
+            Wal::from_str("path: /tmp/queue").unwrap().fsync,
+            FsyncPolicy::always
+        );
+
+        assert!(wal.entries().unwrap().is_empty());
+
+        wal.append(&json!({"CLF_LINE_NUMBER": 1})).unwrap();
+        wal.append(&json!({"CLF_LINE_NUMBER": 2})).unwrap();
+
+        // a torn write is skipped
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, r#"{{"CLF_LINE_NU"#).unwrap();
+
+        wal.append(&json!({"CLF_LINE_NUMBER": 3})).unwrap();
+
+        let entries = wal.entries().unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                json!({"CLF_LINE_NUMBER": 1}),
+                json!({"CLF_LINE_NUMBER": 2}),
+                json!({"CLF_LINE_NUMBER": 3})
+            ]
+        );
+
+        wal.clear().unwrap();
+        assert!(wal.entries().unwrap().is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
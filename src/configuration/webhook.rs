@@ -0,0 +1,258 @@
+//! A minimal HTTP client to POST payloads to a webhook, like Alertmanager or Opsgenie. Only plain HTTP is supported,
+//! as clf is not built with a TLS library: HTTPS endpoints are reached through a local TLS proxy.
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use super::callback::connect;
+use crate::context;
+use crate::misc::{
+    error::{AppCustomErrorKind, AppError, AppResult},
+    util::backoff,
+};
+
+/// A webhook URL split into the address to connect to, and the request target.
+#[derive(Debug, PartialEq)]
+pub struct Endpoint {
+    /// host and port, like `127.0.0.1:9093` or `[::1]:80`
+    pub address: String,
+
+    /// host as given in the URL, for the `Host` header
+    pub host: String,
+
+    /// path and query, like `/api/v2/alerts`
+    pub target: String,
+}
+
+impl Endpoint {
+    /// Parses an `http://host[:port][/path]` URL.
+    pub fn from_url(url: &str) -> AppResult<Endpoint> {
+        let rest = match url.split_once("://") {
+            Some(("http", rest)) => rest,
+            Some(("https", _)) => {
+                return Err(AppError::new_custom(
+                    AppCustomErrorKind::UnsupportedOnPlatform,
+                    &format!(
+                        "HTTPS is not supported by clf, use a TLS proxy to reach URL: {}",
+                        url
+                    ),
+                ))
+            }
+            _ => {
+                return Err(AppError::new_custom(
+                    AppCustomErrorKind::InvalidAddress,
+                    &format!("URL is not an http:// URL: {}", url),
+                ))
+            }
+        };
+
+        let (host, target) = match rest.find(['/', '?']) {
+            Some(pos) if rest[pos..].starts_with('?') => {
+                (&rest[..pos], format!("/{}", &rest[pos..]))
+            }
+            Some(pos) => (&rest[..pos], rest[pos..].to_string()),
+            None => (rest, "/".to_string()),
+        };
+        if host.is_empty() {
+            return Err(AppError::new_custom(
+                AppCustomErrorKind::InvalidAddress,
+                &format!("host is missing in URL: {}", url),
+            ));
+        }
+
+        // the port is after the brackets of an IPv6 address
+        let has_port = host
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| !port.contains(']'));
+        let address = if has_port {
+            host.to_string()
+        } else {
+            format!("{}:80", host)
+        };
+
+        Ok(Endpoint {
+            address,
+            host: host.to_string(),
+            target,
+        })
+    }
+}
+
+/// POSTs a JSON body to the endpoint, retrying up to `retries` times when the endpoint can't be reached or answers
+/// with a server error, after `retry_delay` milliseconds doubled at each retry. Client errors are not retried.
+pub fn post(
+    endpoint: &Endpoint,
+    headers: &BTreeMap<String, String>,
+    body: &str,
+    connect_timeout: u64,
+    timeout: u64,
+    retries: u32,
+    retry_delay: u64,
+) -> AppResult<()> {
+    let mut attempt = 0;
+    loop {
+        let e = match request(endpoint, headers, body, connect_timeout, timeout) {
+            Ok(status) if (200..300).contains(&status) => return Ok(()),
+            Ok(status) => {
+                let e = AppError::new_custom(
+                    AppCustomErrorKind::HttpError,
+                    &format!("HTTP status {} from {}", status, endpoint.host),
+                );
+                if status < 500 && status != 429 {
+                    return Err(e);
+                }
+                e
+            }
+            Err(e) => e,
+        };
+
+        if attempt == retries {
+            return Err(e);
+        }
+        warn!("{}, retrying ({}/{})", e, attempt + 1, retries);
+        std::thread::sleep(backoff(retry_delay, attempt));
+        attempt += 1;
+    }
+}
+
+// sends a single request, returning the status code of the response
+fn request(
+    endpoint: &Endpoint,
+    headers: &BTreeMap<String, String>,
+    body: &str,
+    connect_timeout: u64,
+    timeout: u64,
+) -> AppResult<u16> {
+    let mut stream = connect(&endpoint.address, connect_timeout)?;
+    let timeout = Some(Duration::from_secs(timeout));
+    stream
+        .set_write_timeout(timeout)
+        .and_then(|_| stream.set_read_timeout(timeout))
+        .map_err(|e| context!(e, "unable to set socket timeout: {}", endpoint.address))?;
+
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: clf/{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        endpoint.target,
+        endpoint.host,
+        env!("CARGO_PKG_VERSION"),
+        body.len()
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    request.push_str(body);
+
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|e| context!(e, "error writing HTTP request to: {}", endpoint.host))?;
+
+    // only the status line is needed
+    let mut status_line = String::new();
+    BufReader::new(&stream)
+        .read_line(&mut status_line)
+        .map_err(|e| context!(e, "error reading HTTP response from: {}", endpoint.host))?;
+    debug!(
+        "HTTP response from {}: {}",
+        endpoint.host,
+        status_line.trim()
+    );
+
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| {
+            AppError::new_custom(
+                AppCustomErrorKind::HttpError,
+                &format!(
+                    "invalid HTTP response from {}: {:?}",
+                    endpoint.host,
+                    status_line.trim()
+                ),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    #[test]
+    fn from_url() {
+        let endpoint = Endpoint::from_url("http://127.0.0.1:9093/api/v2/alerts").unwrap();
+        assert_eq!(endpoint.address, "127.0.0.1:9093");
+        assert_eq!(endpoint.host, "127.0.0.1:9093");
+        assert_eq!(endpoint.target, "/api/v2/alerts");
+
+        let endpoint = Endpoint::from_url("http://alerts.example.com?team=ops").unwrap();
+        assert_eq!(endpoint.address, "alerts.example.com:80");
+        assert_eq!(endpoint.target, "/?team=ops");
+
+        let endpoint = Endpoint::from_url("http://[::1]").unwrap();
+        assert_eq!(endpoint.address, "[::1]:80");
+        assert_eq!(endpoint.target, "/");
+        assert_eq!(
+            Endpoint::from_url("http://[::1]:8080/").unwrap().address,
+            "[::1]:8080"
+        );
+
+        assert!(Endpoint::from_url("https://api.opsgenie.com/v2/alerts").is_err());
+        assert!(Endpoint::from_url("127.0.0.1:9093").is_err());
+        assert!(Endpoint::from_url("http:///alerts").is_err());
+    }
+
+    #[test]
+    fn post() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+        // the server fails the first time, so the request is sent again
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in [
+                "HTTP/1.1 503 Unavailable\r\n\r\n",
+                "HTTP/1.1 202 Accepted\r\n\r\n",
+            ] {
+                let (mut socket, _) = listener.accept().unwrap();
+                let mut request = vec![0; 4096];
+                let size = socket.read(&mut request).unwrap();
+                requests.push(String::from_utf8_lossy(&request[..size]).to_string());
+                socket.write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+
+        let endpoint = Endpoint::from_url(&url).unwrap();
+        let headers: BTreeMap<_, _> = vec![("Authorization".to_string(), "Bearer xyz".to_string())]
+            .into_iter()
+            .collect();
+        super::post(&endpoint, &headers, r#"{"seq":1}"#, 1, 1, 1, 10).unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].starts_with("POST /hook HTTP/1.1\r\n"));
+        assert!(requests[1].contains("Authorization: Bearer xyz\r\n"));
+        assert!(requests[1].contains("Content-Length: 9\r\n"));
+        assert!(requests[1].ends_with("\r\n\r\n{\"seq\":1}"));
+
+        // client errors are not retried
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint =
+            Endpoint::from_url(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut request = vec![0; 4096];
+            let _ = socket.read(&mut request).unwrap();
+            socket
+                .write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n")
+                .unwrap();
+        });
+        assert!(super::post(&endpoint, &BTreeMap::new(), "{}", 1, 1, 3, 10).is_err());
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,326 @@
+//! A minimal C ABI over the searches of logfiles, to embed log scanning in programs not written in Rust. It's only built with
+//! the `ffi` feature, and is meant to be built as a shared library:
+//!
+//! ```bash
+//! $ cargo rustc --lib --release --features ffi --crate-type cdylib
+//! ```
+//!
+//! `clf_run()` loads the configuration and the snapshot, searches all logfiles, saves the snapshot and gives back the
+//! report of the run as a JSON string, which must be freed with `clf_free_string()`:
+//!
+//! ```c
+//! int clf_run(const char *config_path, const char *snapshot_path, char **out_report_json);
+//! void clf_free_string(char *s);
+//! ```
+//!
+//! No panic crosses the boundary: it's caught and reported as `CLF_ERR_PANIC`.
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::ptr;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use wait_timeout::ChildExt;
+
+use crate::configuration::{callback::ChildData, config::Config};
+use crate::context;
+use crate::logfile::snapshot::{RunReport, Snapshot, SnapshotList};
+use crate::misc::{error::AppError, signal, util::DEFAULT_KILL_GRACE_PERIOD};
+use crate::{run_search, SearchRun};
+
+/// The run succeeded, the report is given back.
+pub const CLF_OK: c_int = 0;
+
+/// One of the pointer arguments is NULL.
+pub const CLF_ERR_NULL_ARGUMENT: c_int = 1;
+
+/// The configuration or snapshot path is not valid UTF-8.
+pub const CLF_ERR_INVALID_UTF8: c_int = 2;
+
+/// The configuration file can't be read or is not valid.
+pub const CLF_ERR_CONFIG: c_int = 3;
+
+/// The snapshot file can't be read or written.
+pub const CLF_ERR_SNAPSHOT: c_int = 4;
+
+/// The search failed.
+pub const CLF_ERR_SEARCH: c_int = 5;
+
+/// The report of the run can't be serialized.
+pub const CLF_ERR_REPORT: c_int = 6;
+
+/// A panic was caught.
+pub const CLF_ERR_PANIC: c_int = 7;
+
+// the report given back: the aggregate counters of the run, along with the counters of each logfile and tag
+#[derive(Serialize)]
+struct FfiReport<'a> {
+    criticals: u64,
+    warnings: u64,
+    unknowns: u64,
+
+    #[serde(flatten)]
+    report: RunReport<'a>,
+}
+
+/// Runs the searches of the configuration file `config_path`, from and to the snapshot file `snapshot_path`. On return,
+/// `*out_report_json` points to the JSON report of the run if `CLF_OK` is returned, or to `{"error": "..."}`
+/// otherwise. It's left NULL if no string could be built, and must be freed with `clf_free_string()`. A logfile which
+/// can't be read is in the errors of the report, the other logfiles being searched anyway.
+///
+/// Scripts started by callbacks are waited for during their timeout before returning, and terminated afterwards unless
+/// they are meant to outlive *clf*.
+///
+/// # Safety
+///
+/// `config_path` and `snapshot_path` must be NULL or point to NUL-terminated strings, and `out_report_json` must be
+/// NULL or a valid pointer to write to.
+#[no_mangle]
+pub unsafe extern "C" fn clf_run(
+    config_path: *const c_char,
+    snapshot_path: *const c_char,
+    out_report_json: *mut *mut c_char,
+) -> c_int {
+    if out_report_json.is_null() {
+        return CLF_ERR_NULL_ARGUMENT;
+    }
+    *out_report_json = ptr::null_mut();
+
+    let (code, json) = match (path_arg(config_path), path_arg(snapshot_path)) {
+        (Ok(config_path), Ok(snapshot_path)) => {
+            match panic::catch_unwind(AssertUnwindSafe(|| run(config_path, snapshot_path))) {
+                Ok(Ok(json)) => (CLF_OK, json),
+                Ok(Err((code, e))) => (code, error_json(&e.to_string())),
+                Err(payload) => {
+                    let msg = payload
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| payload.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| String::from("unknown panic"));
+                    (CLF_ERR_PANIC, error_json(&format!("panic: {}", msg)))
+                }
+            }
+        }
+        (Err(code), _) | (_, Err(code)) => (code, error_json("invalid argument")),
+    };
+
+    // serde_json escapes control characters, so there's no NUL byte in the JSON string
+    if let Ok(json) = CString::new(json) {
+        *out_report_json = json.into_raw();
+    }
+    code
+}
+
+/// Frees a string given back by `clf_run()`. A NULL pointer is ignored.
+///
+/// # Safety
+///
+/// `s` must be NULL or a string given back by `clf_run()`, not already freed.
+#[no_mangle]
+pub unsafe extern "C" fn clf_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+// converts a C string argument into a path
+unsafe fn path_arg<'a>(arg: *const c_char) -> Result<&'a str, c_int> {
+    if arg.is_null() {
+        return Err(CLF_ERR_NULL_ARGUMENT);
+    }
+    CStr::from_ptr(arg)
+        .to_str()
+        .map_err(|_| CLF_ERR_INVALID_UTF8)
+}
+
+// loads, searches and saves, giving back the JSON report or the error code to return
+fn run(config_path: &str, snapshot_path: &str) -> Result<String, (c_int, AppError)> {
+    let now = Instant::now();
+
+    let config = Config::load(config_path).map_err(|e| (CLF_ERR_CONFIG, e))?;
+    let mut snapshot = Snapshot::load(snapshot_path).map_err(|e| (CLF_ERR_SNAPSHOT, e))?;
+
+    // a logfile which can't be read is in the errors of the report, like with the binary
+    let SearchRun {
+        children,
+        access_errors,
+    } = run_search(&config, &mut snapshot);
+    wait_children(children);
+
+    let global = &config.global;
+    snapshot
+        .save(
+            snapshot_path,
+            global.snapshot_retention,
+            global.snapshot_generations,
+            global.snapshot_backend,
+        )
+        .map_err(|e| (CLF_ERR_SNAPSHOT, e))?;
+
+    // the report is built from the same counters as the plugin output
+    let mut snapshots = SnapshotList::default();
+    snapshots.insert(PathBuf::from(snapshot_path), snapshot);
+
+    let summary = snapshots.summary(&access_errors, now.elapsed());
+    let report = FfiReport {
+        criticals: summary.criticals,
+        warnings: summary.warnings,
+        unknowns: summary.errors,
+        report: snapshots.run_report(&access_errors, now.elapsed()),
+    };
+
+    serde_json::to_string(&report).map_err(|e| {
+        (
+            CLF_ERR_REPORT,
+            context!(e, "unable to serialize the run report",),
+        )
+    })
+}
+
+// waits for scripts during their remaining timeout, and terminates the ones still running if they're not meant to
+// outlive clf
+fn wait_children(children: Vec<ChildData>) {
+    for started_child in children.iter().filter(|x| x.child.is_some()) {
+        let mut child = started_child.child.as_ref().unwrap().borrow_mut();
+
+        let elapsed = started_child
+            .start_time
+            .map(|t| t.elapsed())
+            .unwrap_or_default();
+        let remaining = Duration::from_secs(started_child.timeout).saturating_sub(elapsed);
+
+        match child.wait_timeout(remaining) {
+            Ok(Some(_)) => continue,
+            Ok(None) if started_child.kill_on_exit => {
+                let grace = Duration::from_secs(DEFAULT_KILL_GRACE_PERIOD);
+                if let Err(e) = signal::terminate(&mut child, grace) {
+                    debug!("error:{} trying to kill process pid:{}", e, child.id());
+                }
+            }
+            Ok(None) => debug!("process {} left running", child.id()),
+            Err(e) => debug!("error:{} waiting for process pid:{}", e, child.id()),
+        }
+    }
+}
+
+// the JSON string given back on error
+fn error_json(msg: &str) -> String {
+    serde_json::json!({ "error": msg }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // calls clf_run() as a C program would, giving back the code and the JSON string
+    fn call(config_path: Option<&str>, snapshot_path: &str) -> (c_int, serde_json::Value) {
+        let config_path = config_path.map(|p| CString::new(p).unwrap());
+        let snapshot_path = CString::new(snapshot_path).unwrap();
+        let mut out: *mut c_char = ptr::null_mut();
+
+        let code = unsafe {
+            clf_run(
+                config_path.as_ref().map_or(ptr::null(), |p| p.as_ptr()),
+                snapshot_path.as_ptr(),
+                &mut out,
+            )
+        };
+        assert!(!out.is_null());
+
+        let json = unsafe { CStr::from_ptr(out) }.to_str().unwrap().to_string();
+        unsafe { clf_free_string(out) };
+        (code, serde_json::from_str(&json).unwrap())
+    }
+
+    #[test]
+    fn ffi_run() {
+        let dir = std::env::temp_dir();
+        let logfile = dir.join(format!("clf_ffi_{}.log", std::process::id()));
+        let config = dir.join(format!("clf_ffi_{}.yml", std::process::id()));
+        let snapshot = dir.join(format!("clf_ffi_{}.json", std::process::id()));
+
+        std::fs::write(
+            &logfile,
+            "ERROR disk full\nWARNING disk almost full\nERROR disk full\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &config,
+            format!(
+                "searches:\n  - logfile:\n      path: {}\n    tags:\n      - name: disk\n        patterns:\n          critical: {{ regexes: ['ERROR'] }}\n          warning: {{ regexes: ['WARNING'] }}",
+                logfile.display()
+            ),
+        )
+        .unwrap();
+        let _ = std::fs::remove_file(&snapshot);
+
+        let (code, report) = call(config.to_str(), snapshot.to_str().unwrap());
+        assert_eq!(code, CLF_OK);
+        assert_eq!(report["criticals"], 2);
+        assert_eq!(report["warnings"], 1);
+        assert_eq!(report["unknowns"], 0);
+        assert_eq!(report["errors"], serde_json::json!([]));
+        assert_eq!(report["state"], "CRITICAL");
+        assert_eq!(report["tags"][0]["tag"], "disk");
+        assert_eq!(report["tags"][0]["critical_count"], 2);
+        assert!(snapshot.exists());
+
+        // the snapshot is read on: nothing new is found
+        let (code, report) = call(config.to_str(), snapshot.to_str().unwrap());
+        assert_eq!(code, CLF_OK);
+        assert_eq!(report["criticals"], 0);
+
+        // errors are mapped to codes, with a message
+        let (code, report) = call(None, snapshot.to_str().unwrap());
+        assert_eq!(code, CLF_ERR_NULL_ARGUMENT);
+        assert!(report["error"].is_string());
+
+        let (code, report) = call(Some("/foo/clf.yml"), snapshot.to_str().unwrap());
+        assert_eq!(code, CLF_ERR_CONFIG);
+        assert!(report["error"].is_string());
+
+        let code = unsafe { clf_run(ptr::null(), ptr::null(), ptr::null_mut()) };
+        assert_eq!(code, CLF_ERR_NULL_ARGUMENT);
+
+        for path in &[logfile, config, snapshot] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn ffi_missing_logfile() {
+        let dir = std::env::temp_dir();
+        let logfile = dir.join(format!("clf_ffi_present_{}.log", std::process::id()));
+        let missing = dir.join(format!("clf_ffi_missing_{}.log", std::process::id()));
+        let config = dir.join(format!("clf_ffi_missing_{}.yml", std::process::id()));
+        let snapshot = dir.join(format!("clf_ffi_missing_{}.json", std::process::id()));
+
+        std::fs::write(&logfile, "ERROR disk full\n").unwrap();
+        std::fs::write(
+            &config,
+            format!(
+                "searches:\n  - logfile:\n      path: {}\n    tags:\n      - name: missing\n        patterns:\n          critical: {{ regexes: ['ERROR'] }}\n  - logfile:\n      path: {}\n    tags:\n      - name: present\n        patterns:\n          critical: {{ regexes: ['ERROR'] }}",
+                missing.display(),
+                logfile.display()
+            ),
+        )
+        .unwrap();
+        let _ = std::fs::remove_file(&snapshot);
+
+        // the missing logfile is reported, and the other one is searched anyway
+        let (code, report) = call(config.to_str(), snapshot.to_str().unwrap());
+        assert_eq!(code, CLF_OK);
+        assert_eq!(report["criticals"], 1);
+        assert_eq!(report["unknowns"], 1);
+        assert_eq!(report["state"], "CRITICAL");
+        assert_eq!(report["tags"][0]["tag"], "present");
+        assert_eq!(report["errors"][0]["logfile"], missing.to_str().unwrap());
+        assert_eq!(report["errors"][0]["state"], "UNKNOWN");
+
+        for path in &[logfile, config, snapshot] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
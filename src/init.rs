@@ -1,41 +1,96 @@
 //! All preliminary steps to prepare reading files
+use std::collections::BTreeMap;
 use std::fs::OpenOptions;
-use std::path::PathBuf;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
 
+use chrono::{TimeZone, Utc};
 use simplelog::*;
 
-use crate::configuration::{config::Config, script::Script};
-use crate::logfile::snapshot::Snapshot;
-use crate::misc::extension::Expect;
-use crate::misc::nagios::Nagios;
-use crate::{args::CliOptions, configuration::vars::GlobalVars};
+use crate::configuration::{
+    config::Config,
+    global::GlobalOptions,
+    logfiledef::LogFileDef,
+    pattern::PatternCounters,
+    patterncheck::{check, expressions, REGEX_ENGINE_VERSION},
+    patterntest::test_lines,
+    script::{OnFailure, Script, ScriptOutput},
+};
+use crate::logfile::{
+    annotate::annotate,
+    compression::CompressionScheme,
+    logfileerror::LogFileAccessErrorList,
+    progress::ProgressFile,
+    snapshot::{RunRecord, Snapshot, SnapshotIssue, SnapshotList},
+};
+use crate::misc::error::{AppCustomErrorKind, AppError, AppResult};
+use crate::misc::nagios::{Nagios, NagiosError};
+use crate::misc::util::from_epoch_secs;
+use crate::{
+    args::{
+        AnnotateOptions, CliOptions, SnapshotDiffOptions, SnapshotHistoryOptions,
+        SnapshotRemapOptions, SnapshotResetOptions, TestPatternOptions, VerifyPatternsOptions,
+    },
+    configuration::vars::GlobalVars,
+};
+
+// helper functions to exit in case of error
+pub trait Expect<T> {
+    fn expect_critical(self, text: &str) -> T;
+}
+
+impl<T, E: std::fmt::Debug> Expect<T> for std::result::Result<T, E> {
+    fn expect_critical(self, msg: &str) -> T {
+        match self {
+            Ok(inner) => inner,
+            Err(e) => Nagios::exit_critical(&format!("{}, error: {:?}", msg, e)),
+        }
+    }
+}
 
 /// Create a new config struct
 pub fn init_config(options: &CliOptions) -> Config {
+    // print the rendered configuration file if requested and exit
     #[cfg(feature = "tera")]
-    let _config = Config::from_path(
-        &options.config_file,
-        options.tera_context.as_deref(),
-        options.show_rendered,
-    );
-
-    #[cfg(not(feature = "tera"))]
-    let _config = Config::from_path(&options.config_file);
+    if options.show_rendered {
+        match Config::render(&options.config_file, options.tera_context.as_deref()) {
+            Ok(rendered) => {
+                println!("{}", rendered);
+                std::process::exit(0);
+            }
+            Err(e) => Nagios::exit_critical(&format!(
+                "error rendering config file: {:?}, error: {}",
+                &options.config_file, e
+            )),
+        }
+    }
 
-    // check for loading errors
-    if let Err(ref e) = _config {
-        Nagios::exit_critical(&format!(
+    match load_config(options) {
+        Ok(config) => config,
+        Err(e) => Nagios::exit_critical(&format!(
             "error loading config file: {:?}, error: {}",
             &options.config_file, e
-        ));
+        )),
     }
+}
 
-    let mut config = _config.unwrap();
+/// Loads the configuration file given on the command line, along with process and extra variables.
+pub fn load_config(options: &CliOptions) -> AppResult<Config> {
+    #[cfg(feature = "tera")]
+    let mut config = Config::from_path(&options.config_file, options.tera_context.as_deref())?;
+
+    #[cfg(not(feature = "tera"))]
+    let mut config = Config::from_path(&options.config_file)?;
 
     // add process environment variables and optional extra variables
     config.global.insert_process_vars(&options.config_file);
     config.global.insert_extra_vars(&options.extra_vars);
 
+    // output format could be overriden from the command line
+    if let Some(output_format) = &options.output_format {
+        config.global.output_format = output_format.clone();
+    }
+
     // list all variables to log
     let all_vars: Vec<_> = config
         .global
@@ -46,7 +101,24 @@ pub fn init_config(options: &CliOptions) -> Config {
 
     info!("global variables: {}", all_vars.join(" "));
 
+    Ok(config)
+}
+
+/// Loads the configuration file again, in follow mode. Prescripts are not run again: the variables they exported are
+/// kept, as well as the searches they skipped.
+pub fn reload_config(
+    options: &CliOptions,
+    prescript_vars: &GlobalVars,
+    skipped_searches: &[PathBuf],
+) -> AppResult<Config> {
+    let mut config = load_config(options)?;
+
+    config.global.global_vars.extend(prescript_vars.clone());
     config
+        .searches
+        .retain(|search| !skipped_searches.contains(search.logfile.path()));
+
+    Ok(config)
 }
 
 /// Create new logger and optionally delete logfile is bigger than cli value
@@ -115,33 +187,36 @@ pub fn init_log(options: &CliOptions) {
     info!("options: {:?}", &options);
 }
 
-/// Load the snapshot file: if option "-p" is present, use it, or use the config tag or build a new name from config file
-pub fn load_snapshot(
-    options: &CliOptions,
-    config_snapshot_file: &Option<PathBuf>,
-) -> (Snapshot, PathBuf) {
+/// Build the snapshot file name: if option "-p" is present, use it, or use the config tag or build a new name from config file
+pub fn snapshot_file(options: &CliOptions, config_snapshot_file: &Option<PathBuf>) -> PathBuf {
     // if option "-p" is present, use it, or use the config tag or build a new name from config file
-    let snapfile = if options.snapshot_file.is_some() {
+    if options.snapshot_file.is_some() {
         options.snapshot_file.as_ref().unwrap().clone()
     // it's given as a command line argument as '--snapshot'
     } else if config_snapshot_file.is_some() {
         // or it's using what's defined in the configuration file
-        let conf_file_or_dir = config_snapshot_file.as_ref().unwrap();
-
-        // if what is specified is a directory, use this to build the final snapshot file
-        if conf_file_or_dir.is_dir() {
-            Snapshot::build_name(&options.config_file, Some(conf_file_or_dir))
-        } else {
-            conf_file_or_dir.clone()
-        }
+        search_snapshot_file(options, config_snapshot_file.as_ref().unwrap())
     } else {
         // otherwise, the snapshot file is build from the config file, adding .json extension
         Snapshot::build_name(&options.config_file, None)
-    };
+    }
+}
+
+/// Build the snapshot file name from what is defined in the configuration file, either globally or for a search
+pub fn search_snapshot_file(options: &CliOptions, conf_file_or_dir: &PathBuf) -> PathBuf {
+    // if what is specified is a directory, use this to build the final snapshot file
+    if conf_file_or_dir.is_dir() {
+        Snapshot::build_name(&options.config_file, Some(conf_file_or_dir))
+    } else {
+        conf_file_or_dir.clone()
+    }
+}
 
+/// Load the snapshot file, optionally deleting it first
+pub fn load_snapshot(options: &CliOptions, snapfile: &PathBuf) -> Snapshot {
     // delete snapshot file if requested
     if options.delete_snapfile {
-        if let Err(e) = std::fs::remove_file(&snapfile) {
+        if let Err(e) = std::fs::remove_file(snapfile) {
             // 'not found' could be a viable error
             if e.kind() != std::io::ErrorKind::NotFound {
                 error!(
@@ -152,24 +227,34 @@ pub fn load_snapshot(
         } else {
             info!("deleting snapshot file {:?}", &snapfile);
         }
+
+        // along with the offsets a previous run didn't save
+        if let Err(e) = ProgressFile::new(snapfile).clear() {
+            error!("{}", e);
+        }
     }
     info!("using snapshot file:{}", &snapfile.display());
 
     // read snapshot data from file
-    let snapshot = Snapshot::load(&snapfile)
+    let snapshot = Snapshot::load(snapfile)
         .expect_critical(&format!("unable to load snapshot file: {:?},", &snapfile));
     info!(
         "loaded snapshot file {:?}, data = {:#?}",
         &snapfile, &snapshot
     );
 
-    (snapshot, snapfile)
+    snapshot
 }
 
 /// Saves snapshot file into provided path
-pub fn save_snapshot(snapshot: &mut Snapshot, snapfile: &PathBuf, retention: u64) {
+pub fn save_snapshot(snapshot: &mut Snapshot, snapfile: &PathBuf, global: &GlobalOptions) {
     debug!("saving snapshot file {}", &snapfile.display());
-    if let Err(e) = snapshot.save(&snapfile, retention) {
+    if let Err(e) = snapshot.save(
+        snapfile,
+        global.snapshot_retention,
+        global.snapshot_generations,
+        global.snapshot_backend,
+    ) {
         Nagios::exit_critical(&format!(
             "unable to save snapshot file: {:?}, error: {}",
             &snapfile, e
@@ -177,8 +262,8 @@ pub fn save_snapshot(snapshot: &mut Snapshot, snapfile: &PathBuf, retention: u64
     }
 }
 
-/// Spawn a prescript and returns its pid
-pub fn spawn_prescript(prescript: &Script, vars: Option<&GlobalVars>) -> u32 {
+/// Spawn a prescript and returns its pid, exit status and exported variables
+pub fn spawn_prescript(prescript: &Script, vars: Option<&GlobalVars>) -> ScriptOutput {
     let result = prescript.spawn(vars);
 
     // check rc
@@ -192,7 +277,9 @@ pub fn spawn_prescript(prescript: &Script, vars: Option<&GlobalVars>) -> u32 {
 
     // now it's safe to unwrap to get pid
     debug_assert!(result.is_ok());
-    result.unwrap()
+    let output = result.unwrap();
+    exit_on_failure(prescript, &output);
+    output
 }
 
 /// Spawn postscript
@@ -207,12 +294,728 @@ pub fn spawn_postscript(postscript: &mut Script, pids: &[u32]) {
     let result = postscript.spawn(None);
 
     // check rc
-    if let Err(e) = &result {
-        error!("error: {} spawning command: {:?}", e, postscript.command);
+    match result {
+        Err(e) => error!("error: {} spawning command: {:?}", e, postscript.command),
+        Ok(output) => {
+            info!(
+                "postcript command successfully executed, pid={}",
+                output.pid
+            );
+            exit_on_failure(postscript, &output);
+        }
+    }
+}
+
+// exits with UNKNOWN if the script failed or timed out, and is meant to abort clf
+fn exit_on_failure(script: &Script, output: &ScriptOutput) {
+    if !output.success && script.on_failure() == OnFailure::abort {
+        Nagios::exit_unknown(&format!(
+            "script:{:?}, pid:{}, on_failure is abort and the script failed or timed out",
+            &script.command, output.pid
+        ));
+    }
+}
+
+/// Annotate a logfile with the decisions taken by all searches defined for it, and exit
+pub fn annotate_logfile(config: &Config, options: &AnnotateOptions) -> ! {
+    let path = options.file.canonicalize().expect_critical(&format!(
+        "error trying to canonicalize logfile: {}",
+        options.file.display()
+    ));
+
+    // only searches for this logfile are used
+    let searches: Vec<_> = config
+        .searches
+        .iter()
+        .filter(|search| {
+            search
+                .logfile
+                .path()
+                .canonicalize()
+                .is_ok_and(|p| p == path)
+        })
+        .collect();
+
+    if searches.is_empty() {
+        Nagios::exit_critical(&format!(
+            "no search is defined for logfile {:?} in the configuration file",
+            path
+        ));
+    }
+
+    // annotated lines are written either to a file or to stdout
+    let result = match &options.out {
+        Some(out) => {
+            let mut file = std::fs::File::create(out)
+                .expect_critical(&format!("unable to create file: {}", out.display()));
+            annotate(&path, &searches, &mut file)
+        }
+        None => annotate(&path, &searches, &mut std::io::stdout()),
+    };
+
+    match result {
+        Ok(lines) => Nagios::exit_ok(&format!("{} lines annotated", lines)),
+        Err(e) => Nagios::exit_critical(&format!("error annotating logfile {:?}: {}", path, e)),
+    }
+}
+
+/// Verify all snapshot files used by the searches against the filesystem, print out a report and return the Nagios exit code
+pub fn verify_snapshots(
+    config: &Config,
+    options: &CliOptions,
+    default_snapfile: &Path,
+) -> NagiosError {
+    // group searches by the snapshot file they're using
+    let mut snapfiles: BTreeMap<PathBuf, Vec<_>> = BTreeMap::new();
+    for search in &config.searches {
+        let snapfile = match &search.snapshot_file {
+            Some(file_or_dir) => search_snapshot_file(options, file_or_dir),
+            None => default_snapfile.to_path_buf(),
+        };
+        snapfiles.entry(snapfile).or_default().push(search);
+    }
+
+    let mut report = Vec::new();
+    let mut nb_issues = 0;
+    for (snapfile, searches) in &snapfiles {
+        // a missing snapshot file is not an issue: it's created at the first run
+        if !snapfile.exists() {
+            report.push(format!("{}: snapshot file not found", snapfile.display()));
+            continue;
+        }
+
+        let snapshot = Snapshot::load(snapfile)
+            .expect_critical(&format!("unable to load snapshot file: {:?},", &snapfile));
+
+        for (path, issue) in snapshot.verify(searches) {
+            nb_issues += 1;
+            report.push(format!(
+                "{}: {}: {}",
+                snapfile.display(),
+                path.display(),
+                issue
+            ));
+        }
+    }
+
+    let nagios_error = if nb_issues == 0 {
+        NagiosError::OK
+    } else {
+        NagiosError::WARNING
+    };
+    println!(
+        "{}: {} snapshot files verified, {} issues found",
+        String::from(&nagios_error),
+        snapfiles.len(),
+        nb_issues
+    );
+    for line in &report {
+        println!("{}", line);
+    }
+
+    nagios_error
+}
+
+/// Print the differences between 2 snapshot files, either as a table or as JSON, and return the Nagios exit code
+pub fn diff_snapshots(options: &SnapshotDiffOptions) -> NagiosError {
+    // a missing snapshot file would be loaded as an empty one
+    let load = |path: &Path| {
+        if !path.exists() {
+            Nagios::exit_critical(&format!("snapshot file {:?} not found", path));
+        }
+        Snapshot::load(path).expect_critical(&format!("unable to load snapshot file: {:?},", path))
+    };
+    let old = load(&options.old);
+    let new = load(&options.new);
+
+    let diffs = old.diff(&new);
+
+    if options.json {
+        let json = serde_json::to_string_pretty(&diffs)
+            .expect_critical("unable to serialize snapshot differences");
+        println!("{}", json);
+    } else {
+        let mut rows = vec![[
+            "PATH", "TAG", "CHANGE", "OFFSET", "LINES", "CRITICAL", "WARNING", "OK",
+        ]
+        .map(String::from)];
+        for diff in &diffs {
+            rows.push([
+                diff.path.display().to_string(),
+                diff.tag.to_string(),
+                format!("{:?}", diff.change),
+                format!("{:+}", diff.offset),
+                format!("{:+}", diff.lines),
+                format!("{:+}", diff.critical),
+                format!("{:+}", diff.warning),
+                format!("{:+}", diff.ok),
+            ]);
+        }
+        print_table(&rows);
+        println!("{} differences found", diffs.len());
+    }
+
+    NagiosError::OK
+}
+
+/// Save the offsets of all tags of the snapshot files used by the searches under the checkpoint `name`, print out a
+/// summary and return the Nagios exit code
+pub fn create_checkpoint(
+    config: &Config,
+    options: &CliOptions,
+    default_snapfile: &Path,
+    name: &str,
+) -> NagiosError {
+    let snapfiles = snapshot_files(config, options, default_snapfile);
+    let now = from_epoch_secs().expect_critical("unable to get the current time");
+
+    let mut nb_tags = 0;
+    let mut nb_snapfiles = 0;
+    for snapfile in &snapfiles {
+        // nothing to save yet
+        if !snapfile.exists() {
+            println!("{}: snapshot file not found", snapfile.display());
+            continue;
+        }
+
+        let mut snapshot = Snapshot::load(snapfile)
+            .expect_critical(&format!("unable to load snapshot file: {:?},", &snapfile));
+        nb_tags += snapshot.create_checkpoint(name, now);
+        nb_snapfiles += 1;
+
+        save_snapshot(&mut snapshot, snapfile, &config.global);
+    }
+
+    println!(
+        "{}: checkpoint {} created for {} tags in {} snapshot files",
+        String::from(&NagiosError::OK),
+        name,
+        nb_tags,
+        nb_snapfiles
+    );
+
+    NagiosError::OK
+}
+
+/// Move the entries of the logfiles moved from a directory to another one in the snapshot files used by the searches,
+/// print out the entries moved and return the Nagios exit code
+pub fn remap_snapshots(
+    config: &Config,
+    options: &CliOptions,
+    default_snapfile: &Path,
+    remap: &SnapshotRemapOptions,
+) -> NagiosError {
+    // the logfiles were moved, so only the new directory could be canonicalized
+    let to = remap
+        .to
+        .canonicalize()
+        .expect_critical(&format!("unable to canonicalize directory: {:?}", remap.to));
+
+    // signatures are hashed like when the moved logfile is searched
+    let hash_spec = |path: &Path| {
+        config
+            .searches
+            .iter()
+            .find(|search| search.logfile.path() == path)
+            .map_or_else(
+                || LogFileDef::default().hash_spec(),
+                |search| search.logfile.hash_spec(),
+            )
+    };
+
+    let mut rows = vec![["SNAPSHOT", "FROM", "TO"].map(String::from)];
+    let mut nb_errors = 0;
+    for snapfile in snapshot_files(config, options, default_snapfile) {
+        if !snapfile.exists() {
+            continue;
+        }
+
+        let mut snapshot = Snapshot::load(&snapfile)
+            .expect_critical(&format!("unable to load snapshot file: {:?},", &snapfile));
+        let remapped = snapshot.remap(&remap.from, &to, hash_spec);
+        if remapped.is_empty() {
+            continue;
+        }
+
+        for (old_path, new_path) in &remapped {
+            let new_path = match new_path {
+                Ok(path) => path.display().to_string(),
+                Err(e) => {
+                    nb_errors += 1;
+                    format!("not moved: {}", e)
+                }
+            };
+            rows.push([
+                snapfile.display().to_string(),
+                old_path.display().to_string(),
+                new_path,
+            ]);
+        }
+
+        save_snapshot(&mut snapshot, &snapfile, &config.global);
+    }
+
+    let nagios_error = if nb_errors == 0 {
+        NagiosError::OK
+    } else {
+        NagiosError::WARNING
+    };
+    println!(
+        "{}: {} logfiles remapped, {} not moved",
+        String::from(&nagios_error),
+        rows.len() - 1 - nb_errors,
+        nb_errors
+    );
+    if rows.len() > 1 {
+        print_table(&rows);
+    }
+
+    nagios_error
+}
+
+/// Clear the counters and offsets of a logfile, or of one of its tags, in the snapshot files used by the searches, print
+/// out the number of tags reset and return the Nagios exit code
+pub fn reset_snapshots(
+    config: &Config,
+    options: &CliOptions,
+    default_snapfile: &Path,
+    reset: &SnapshotResetOptions,
+) -> NagiosError {
+    // logfiles are kept under their canonical path, but a deleted logfile is only found by its declared path
+    let logfile = reset
+        .logfile
+        .canonicalize()
+        .unwrap_or_else(|_| reset.logfile.clone());
+
+    let mut nb_tags = 0;
+    let mut nb_snapfiles = 0;
+    for snapfile in snapshot_files(config, options, default_snapfile) {
+        if !snapfile.exists() {
+            continue;
+        }
+
+        let mut snapshot = Snapshot::load(&snapfile)
+            .expect_critical(&format!("unable to load snapshot file: {:?},", &snapfile));
+        let reset_tags = snapshot.reset(&logfile, reset.tag.as_deref());
+        if reset_tags == 0 {
+            continue;
+        }
+        nb_tags += reset_tags;
+        nb_snapfiles += 1;
+
+        // the snapshot file is replaced at once, never left half written
+        save_snapshot(&mut snapshot, &snapfile, &config.global);
+    }
+
+    // nothing found is likely a typo in the logfile path or the tag name
+    let nagios_error = if nb_tags == 0 {
+        NagiosError::WARNING
     } else {
+        NagiosError::OK
+    };
+    println!(
+        "{}: {} tags of logfile {} reset in {} snapshot files",
+        String::from(&nagios_error),
+        nb_tags,
+        reset.logfile.display(),
+        nb_snapfiles
+    );
+
+    nagios_error
+}
+
+/// Print the summary of the last runs kept in the snapshot files used by the searches, either as a table or as JSON,
+/// and return the Nagios exit code
+pub fn snapshot_history(
+    config: &Config,
+    options: &CliOptions,
+    default_snapfile: &Path,
+    history: &SnapshotHistoryOptions,
+) -> NagiosError {
+    // logfiles are kept under their canonical path
+    let logfile = history
+        .logfile
+        .as_ref()
+        .map(|path| path.canonicalize().unwrap_or_else(|_| path.clone()));
+
+    let mut histories = BTreeMap::new();
+    for snapfile in snapshot_files(config, options, default_snapfile) {
+        // no run yet
+        if !snapfile.exists() {
+            continue;
+        }
+
+        let snapshot = Snapshot::load(&snapfile)
+            .expect_critical(&format!("unable to load snapshot file: {:?},", &snapfile));
+
+        // only keep the runs which searched the logfile, if any
+        let runs: Vec<RunRecord> = snapshot
+            .history()
+            .iter()
+            .filter_map(|run| match &logfile {
+                Some(path) => run.logfiles.get(path).map(|counters| RunRecord {
+                    logfiles: vec![(path.clone(), counters.clone())].into_iter().collect(),
+                    overlaps: run
+                        .overlaps
+                        .iter()
+                        .filter(|(p, _)| *p == path)
+                        .map(|(p, overlaps)| (p.clone(), overlaps.clone()))
+                        .collect(),
+                    ..run.clone()
+                }),
+                None => Some(run.clone()),
+            })
+            .collect();
+        histories.insert(snapfile, runs);
+    }
+
+    if history.json {
+        let json = serde_json::to_string_pretty(&histories)
+            .expect_critical("unable to serialize snapshot history");
+        println!("{}", json);
+    } else {
+        let mut rows = vec![[
+            "SNAPSHOT", "TIME", "EXIT", "DURATION", "LOGFILES", "CRITICAL", "WARNING", "OK",
+        ]
+        .map(String::from)];
+        for (snapfile, runs) in &histories {
+            for run in runs {
+                let counters: PatternCounters = run.logfiles.values().sum();
+                rows.push([
+                    snapfile.display().to_string(),
+                    Utc.timestamp_opt(run.time as i64, 0).single().map_or_else(
+                        || run.time.to_string(),
+                        |time| time.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    ),
+                    run.exit.clone(),
+                    format!("{:.3}", run.duration),
+                    run.logfiles.len().to_string(),
+                    counters.critical_count.to_string(),
+                    counters.warning_count.to_string(),
+                    counters.ok_count.to_string(),
+                ]);
+            }
+        }
+        print_table(&rows);
+        println!(
+            "{} runs found",
+            histories.values().map(|runs| runs.len()).sum::<usize>()
+        );
+    }
+
+    NagiosError::OK
+}
+
+/// Move the offsets of all loaded snapshots back to those of the checkpoint `name`. Exits if none of the snapshots
+/// holds this checkpoint
+pub fn replay_checkpoint(snapshots: &mut SnapshotList, name: &str) {
+    let mut found = false;
+    let mut nb_tags = 0;
+    for (snapfile, snapshot) in snapshots.iter_mut() {
+        match snapshot.replay_from(name) {
+            Some(replayed) => {
+                found = true;
+                nb_tags += replayed;
+            }
+            None => warn!("no checkpoint {} in snapshot file {:?}", name, snapfile),
+        }
+    }
+
+    if !found {
+        Nagios::exit_critical(&format!("checkpoint {} not found", name));
+    }
+    info!("replaying {} tags from checkpoint {}", nb_tags, name);
+}
+
+/// Prints the version and the optional features of this binary, along with the compression schemes and callbacks they
+/// enable, and return the Nagios exit code
+pub fn print_build_info() -> NagiosError {
+    let features = [
+        ("bzip2", cfg!(feature = "bzip2")),
+        ("xz", cfg!(feature = "xz")),
+        ("zstd", cfg!(feature = "zstd")),
+        ("lz4", cfg!(feature = "lz4")),
+        ("blake3", cfg!(feature = "blake3")),
+        ("sockets", cfg!(feature = "sockets")),
+        ("sqlite", cfg!(feature = "sqlite")),
+        ("tera", cfg!(feature = "tera")),
+        ("windows-eventlog", cfg!(feature = "windows-eventlog")),
+    ];
+    let features: Vec<_> = features
+        .iter()
+        .map(|(name, built)| format!("{}{}", if *built { '+' } else { '-' }, name))
+        .collect();
+
+    let schemes = [
+        CompressionScheme::Gzip,
+        CompressionScheme::Bzip2,
+        CompressionScheme::Xz,
+        CompressionScheme::Zstd,
+        CompressionScheme::Lz4,
+    ];
+    let schemes: Vec<_> = schemes
+        .iter()
+        .filter(|s| s.is_built_in())
+        .map(|s| format!("{:?}", s).to_lowercase())
+        .collect();
+
+    let mut callbacks = vec!["script"];
+    if cfg!(feature = "sockets") {
+        callbacks.extend(["address", "domain"]);
+    }
+    callbacks.extend(["url", "email"]);
+
+    println!("clf {}", env!("CARGO_PKG_VERSION"));
+    println!("features: {}", features.join(" "));
+    println!("compression: {}", schemes.join(", "));
+    println!("callbacks: {}", callbacks.join(", "));
+
+    NagiosError::OK
+}
+
+/// Compile all expressions of the configuration file and print the results, either as a table or as JSON, and return
+/// the Nagios exit code. The configuration file is read as raw YAML, so all invalid expressions are reported
+pub fn verify_patterns(options: &CliOptions, verify: &VerifyPatternsOptions) -> NagiosError {
+    #[cfg(feature = "tera")]
+    let yaml = Config::raw_from_path(&options.config_file, options.tera_context.as_deref());
+
+    #[cfg(not(feature = "tera"))]
+    let yaml = Config::raw_from_path(&options.config_file);
+
+    let yaml = yaml.expect_critical(&format!(
+        "error loading config file: {:?}",
+        &options.config_file
+    ));
+
+    let checks: Vec<_> = expressions(&yaml).into_iter().map(check).collect();
+    let nb_errors = checks.iter().filter(|c| c.error.is_some()).count();
+    let nagios_error = if nb_errors == 0 {
+        NagiosError::OK
+    } else {
+        NagiosError::CRITICAL
+    };
+
+    if verify.json {
+        let json = serde_json::json!({
+            "engine": format!("regex {}", REGEX_ENGINE_VERSION),
+            "expressions": checks,
+        });
+        let json = serde_json::to_string_pretty(&json)
+            .expect_critical("unable to serialize pattern verification");
+        println!("{}", json);
+        return nagios_error;
+    }
+
+    println!(
+        "{}: {} expressions compiled with regex {}, {} errors found",
+        String::from(&nagios_error),
+        checks.len(),
+        REGEX_ENGINE_VERSION,
+        nb_errors
+    );
+
+    let mut rows = vec![[
+        "STATUS",
+        "TIME",
+        "KIND",
+        "LOCATION",
+        "FEATURES",
+        "EXPRESSION",
+    ]
+    .map(String::from)];
+    for c in &checks {
+        let features: Vec<_> = c.features.iter().map(|f| format!("{:?}", f)).collect();
+        rows.push([
+            if c.error.is_some() { "error" } else { "ok" }.to_string(),
+            format!("{:.1?}", c.compile_time),
+            format!("{:?}", c.expression.kind),
+            c.expression.location.clone(),
+            features.join(","),
+            c.expression.expr.clone(),
+        ]);
+    }
+    print_table(&rows);
+
+    for c in &checks {
+        if let Some(error) = &c.error {
+            println!("{}: {}", c.expression.location, error);
+        }
+    }
+
+    nagios_error
+}
+
+/// Prints the configuration which is run as YAML, and return the Nagios exit code
+pub fn print_config(options: &CliOptions) -> NagiosError {
+    #[cfg(feature = "tera")]
+    let yaml = Config::raw_from_path(&options.config_file, options.tera_context.as_deref());
+
+    #[cfg(not(feature = "tera"))]
+    let yaml = Config::raw_from_path(&options.config_file);
+
+    let yaml = yaml.and_then(Config::resolve).expect_critical(&format!(
+        "error loading config file: {:?}",
+        &options.config_file
+    ));
+    let yaml = serde_yaml::to_string(&yaml).expect_critical("unable to serialize configuration");
+    print!("{}", yaml);
+
+    NagiosError::OK
+}
+
+/// Match the lines given on the command line, or read from the standard input, against the patterns of all tags, and
+/// print the results either as a table or as JSON, and return the Nagios exit code
+pub fn test_pattern(config: &Config, test: &TestPatternOptions) -> NagiosError {
+    let lines = if test.lines.is_empty() {
+        std::io::stdin()
+            .lock()
+            .lines()
+            .collect::<Result<Vec<_>, _>>()
+            .expect_critical("unable to read lines from the standard input")
+    } else {
+        test.lines.clone()
+    };
+
+    let tests = test_lines(&config.searches, &lines);
+    let nb_matches = tests
+        .iter()
+        .filter(|t| t.regex.is_some() && t.result != "excluded")
+        .count();
+
+    if test.json {
+        let json = serde_json::to_string_pretty(&tests)
+            .expect_critical("unable to serialize pattern tests");
+        println!("{}", json);
+        return NagiosError::OK;
+    }
+
+    println!(
+        "{}: {} lines tested, {} matches found",
+        String::from(&NagiosError::OK),
+        lines.len(),
+        nb_matches
+    );
+
+    let mut rows =
+        vec![["LINE", "LOGFILE", "TAG", "RESULT", "REGEX", "CAPTURES"].map(String::from)];
+    for t in &tests {
+        let captures: Vec<_> = t
+            .captures
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        rows.push([
+            t.line_number.to_string(),
+            t.logfile.display().to_string(),
+            t.tag.clone(),
+            t.result.clone(),
+            t.regex.clone().unwrap_or_default(),
+            captures.join(" "),
+        ]);
+    }
+    print_table(&rows);
+
+    NagiosError::OK
+}
+
+// the snapshot files used by the searches, sorted and without duplicates
+fn snapshot_files(config: &Config, options: &CliOptions, default_snapfile: &Path) -> Vec<PathBuf> {
+    let mut snapfiles: Vec<_> = config
+        .searches
+        .iter()
+        .map(|search| match &search.snapshot_file {
+            Some(file_or_dir) => search_snapshot_file(options, file_or_dir),
+            None => default_snapfile.to_path_buf(),
+        })
+        .collect();
+    snapfiles.sort();
+    snapfiles.dedup();
+    snapfiles
+}
+
+// print rows as a table, each column being as large as its largest value
+fn print_table<const N: usize>(rows: &[[String; N]]) {
+    let widths: Vec<_> = (0..N)
+        .map(|i| rows.iter().map(|row| row[i].len()).max().unwrap_or(0))
+        .collect();
+    for row in rows {
+        let cells: Vec<_> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+}
+
+/// Load all snapshot files used by the searches and check their offsets against the logfile sizes. Offsets beyond the
+/// logfile sizes are either reset, or reported as logfile errors if there're too many of them
+pub fn check_offsets(
+    config: &Config,
+    options: &CliOptions,
+    default_snapfile: &Path,
+    snapshots: &mut SnapshotList,
+    access_errors: &mut LogFileAccessErrorList,
+) {
+    for search in &config.searches {
+        let snapfile = match &search.snapshot_file {
+            Some(file_or_dir) => search_snapshot_file(options, file_or_dir),
+            None => default_snapfile.to_path_buf(),
+        };
+        snapshots
+            .entry(snapfile)
+            .or_insert_with_key(|snapfile| load_snapshot(options, snapfile));
+    }
+
+    // gather tags from all snapshots
+    let mut checked = 0;
+    let mut beyond_eof = Vec::new();
+    for snapshot in snapshots.values() {
+        let (nb_tags, issues) = snapshot.offsets_beyond_eof();
+        checked += nb_tags;
+        beyond_eof.extend(
+            issues
+                .into_iter()
+                .map(|(path, issue)| (path.clone(), issue)),
+        );
+    }
+
+    if beyond_eof.is_empty() {
+        return;
+    }
+
+    if options.heal_offsets {
+        let healed: usize = snapshots.values_mut().map(|x| x.heal_offsets()).sum();
+        warn!("{} snapshot offsets beyond the logfile size reset", healed);
+        return;
+    }
+
+    // a few of them is expected, e.g. for a logfile truncated in place
+    let ratio = beyond_eof.len() as f64 / checked as f64;
+    if ratio < config.global.offset_check.ratio {
         info!(
-            "postcript command successfully executed, pid={}",
-            result.unwrap()
-        )
+            "{} out of {} snapshot offsets are beyond the logfile size",
+            beyond_eof.len(),
+            checked
+        );
+        return;
+    }
+
+    warn!(
+        "!!! {} out of {} snapshot offsets are beyond the logfile size, probably because logfiles were restored from a backup. These logfiles are not read anymore: use --heal-offsets to reset them",
+        beyond_eof.len(),
+        checked
+    );
+    for (path, issue) in beyond_eof {
+        if let SnapshotIssue::OffsetBeyondSize { tag, offset, size } = issue {
+            let error = AppError::new_custom(
+                AppCustomErrorKind::SeekPosBeyondEof,
+                &format!(
+                    "tag '{}' offset {} is beyond the logfile size {}, use --heal-offsets to reset it",
+                    tag, offset, size
+                ),
+            );
+            access_errors.set_error(&path, error, &config.global.offset_check.status);
+        }
     }
 }
@@ -0,0 +1,130 @@
+//! The library the *clf* binary is built upon, to embed log scanning in other Rust programs without spawning *clf*.
+//!
+//! * [`Config`] is the YAML configuration: global options, and the searches of logfiles along with their tags
+//! * [`Snapshot`] holds, for each logfile, the [`LogFile`] with the offsets and counters of each tag reached at the
+//!   previous run, loaded from and saved to the snapshot file
+//! * [`run_search()`] searches all logfiles of a configuration from the offsets of a snapshot, calling callbacks on
+//!   matches. It gives back a [`SearchRun`], with the logfiles which couldn't be read in a
+//!   [`LogFileAccessErrorList`]
+//!
+//! ```no_run
+//! use clf::{run_search, Config, Snapshot};
+//!
+//! # fn main() -> clf::AppResult<()> {
+//! let config = Config::load("/etc/clf/clf.yml")?;
+//! let mut snapshot = Snapshot::load("/var/lib/clf/clf.json")?;
+//!
+//! // scripts started by callbacks are given back, to be waited for, along with the logfiles which couldn't be read
+//! let run = run_search(&config, &mut snapshot);
+//! for (path, error) in run.access_errors.iter() {
+//!     eprintln!("unable to search {:?}: {}", path, error.error);
+//! }
+//!
+//! let global = &config.global;
+//! snapshot.save(
+//!     "/var/lib/clf/clf.json",
+//!     global.snapshot_retention,
+//!     global.snapshot_generations,
+//!     global.snapshot_backend,
+//! )?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The binary adds what's only meaningful for a Nagios plugin: prescripts and postscripts, claims, threads, the
+//! follow mode and the plugin output. Only the types above are the API of the library: the modules are public for the
+//! binary, which is built upon them, but are hidden and could change at any time.
+#[macro_use]
+extern crate log;
+
+#[doc(hidden)]
+pub mod configuration;
+#[doc(hidden)]
+pub mod logfile;
+#[doc(hidden)]
+pub mod misc;
+#[doc(hidden)]
+pub mod search;
+
+// C ABI, to be built as a shared library
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+// helpers to run searches from tests
+#[cfg(any(test, feature = "testkit"))]
+pub mod testkit;
+
+pub use configuration::{callback::ChildData, config::Config};
+pub use logfile::{
+    logfile::LogFile,
+    logfileerror::{LogFileAccessError, LogFileAccessErrorList},
+    snapshot::Snapshot,
+};
+pub use misc::error::{AppError, AppResult};
+
+use logfile::output::RunOutputs;
+use search::{drain_queues, resume_logfile, search_logfile, SearchOptions};
+
+/// What's given back by [`run_search()`].
+#[derive(Debug, Default)]
+pub struct SearchRun {
+    /// scripts started by callbacks, to be waited for
+    pub children: Vec<ChildData>,
+
+    /// logfiles which couldn't be read, e.g. missing ones, along with the error and the Nagios status to report
+    pub access_errors: LogFileAccessErrorList,
+}
+
+/// Searches the logfiles of all searches of the configuration, from the offsets of the snapshot which are updated,
+/// along with the archives of the logfiles rotated since the last run, like with the binary. A logfile which can't be
+/// read doesn't stop the run: it's given back in the access errors, along with the Nagios status it's reported with,
+/// the other logfiles being searched anyway. Matches for e-mail callbacks are sent once all logfiles are searched,
+/// those which can't be sent being kept in the snapshot for the next run. Scripts started by callbacks are returned, to
+/// be waited for.
+pub fn run_search(config: &Config, snapshot: &mut Snapshot) -> SearchRun {
+    // payloads left in the queues of callbacks by a previous run are sent first
+    drain_queues(config);
+
+    let options = SearchOptions::default();
+    let outputs = RunOutputs::new(&config.global);
+    let mut access_errors = LogFileAccessErrorList::default();
+    let mut children = Vec::new();
+
+    let mut sliced: Vec<_> = config
+        .searches
+        .iter()
+        .filter(|search| {
+            search_logfile(
+                config,
+                &options,
+                search,
+                snapshot,
+                &mut access_errors,
+                &mut children,
+                &outputs,
+            )
+        })
+        .collect();
+
+    // with the interleaved fairness, the searches not complete after their first slice go on in turn
+    while !sliced.is_empty() {
+        sliced.retain(|search| {
+            resume_logfile(
+                config,
+                &options,
+                search,
+                snapshot,
+                &mut access_errors,
+                &mut children,
+            )
+        });
+    }
+
+    // matches for e-mail callbacks are sent in a single message
+    snapshot.send_digests();
+
+    SearchRun {
+        children,
+        access_errors,
+    }
+}
@@ -0,0 +1,326 @@
+//! Annotation of a logfile: each line is written prefixed with the decision taken for each tag. This gives a complete
+//! trace of how a configuration classifies a logfile, without calling any callback nor updating the snapshot.
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+#[cfg(feature = "bzip2")]
+use bzip2::read::BzDecoder;
+use flate2::read::GzDecoder;
+#[cfg(feature = "lz4")]
+use lz4_flex::frame::FrameDecoder as Lz4Decoder;
+use regex::Regex;
+#[cfg(feature = "xz")]
+use xz2::read::XzDecoder;
+#[cfg(feature = "zstd")]
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+use crate::configuration::{pattern::PatternType, search::Search, tag::Tag};
+use crate::context;
+use crate::logfile::{compression::CompressionScheme, logfile::LogFile, rundata::RunData};
+use crate::misc::{
+    error::{AppError, AppResult},
+    util::DEFAULT_STRING_CAPACITY,
+};
+
+/// The decision taken for a line and a tag.
+#[derive(Debug)]
+pub enum Decision<'a> {
+    /// The line is one of the `skip_header_lines` header lines of the logfile
+    Header,
+
+    /// The line matches the `exclude` regex of the logfile
+    Excluded(&'a Regex),
+
+    /// No regex matched, or a match has been dismissed by an exception for this pattern type
+    NoMatch(Option<PatternType>),
+
+    /// A critical or warning regex matched. The counter is compared to the threshold to know whether a callback is triggered
+    Matched {
+        pattern_type: PatternType,
+        regex: &'a Regex,
+        count: u64,
+        threshold: u64,
+    },
+
+    /// An ok regex matched: counters are reset and a callback is only triggered if `runifok` is set
+    Ok { regex: &'a Regex, runifok: bool },
+}
+
+impl<'a> fmt::Display for Decision<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Decision::Header => write!(f, "header"),
+            Decision::Excluded(re) => write!(f, "excluded('{}')", re.as_str()),
+            Decision::NoMatch(None) => write!(f, "none"),
+            Decision::NoMatch(Some(pattern_type)) => {
+                write!(f, "none({} exception)", <&str>::from(pattern_type))
+            }
+            Decision::Matched {
+                pattern_type,
+                regex,
+                count,
+                threshold,
+            } => {
+                let reasoning = if count > threshold {
+                    "threshold reached"
+                } else {
+                    "threshold not reached"
+                };
+                write!(
+                    f,
+                    "{}('{}', {}: count={}, threshold={})",
+                    <&str>::from(pattern_type),
+                    regex.as_str(),
+                    reasoning,
+                    count,
+                    threshold
+                )
+            }
+            Decision::Ok { regex, runifok } => write!(
+                f,
+                "ok('{}', counters reset, runifok={})",
+                regex.as_str(),
+                runifok
+            ),
+        }
+    }
+}
+
+/// Returns the decision for a line and a tag. Counters are updated the same way `FullReader` does.
+pub fn decide<'a>(
+    line: &str,
+    tag: &'a Tag,
+    exclude: Option<&'a Regex>,
+    run_data: &mut RunData,
+) -> Decision<'a> {
+    // excluded lines are not even tested against patterns
+    if let Some(re) = exclude {
+        if re.is_match(line) {
+            return Decision::Excluded(re);
+        }
+    }
+
+    match tag.is_match(line) {
+        None => Decision::NoMatch(tag.patterns.is_exception(line)),
+        Some(pattern_match) => {
+            let key = tag.options.threshold_key(&pattern_match, line);
+            run_data.increment_counters(&pattern_match.pattern_type, key.as_deref());
+            let reached = run_data.is_threshold_reached(
+                &pattern_match.pattern_type,
+                &tag.options,
+                key.as_deref(),
+            );
+
+            match pattern_match.pattern_type {
+                PatternType::critical => Decision::Matched {
+                    pattern_type: PatternType::critical,
+                    regex: pattern_match.regex,
+                    count: run_data.counters.critical_count,
+                    threshold: tag.options.criticalthreshold,
+                },
+                PatternType::warning => Decision::Matched {
+                    pattern_type: PatternType::warning,
+                    regex: pattern_match.regex,
+                    count: run_data.counters.warning_count,
+                    threshold: tag.options.warningthreshold,
+                },
+                PatternType::ok => Decision::Ok {
+                    regex: pattern_match.regex,
+                    runifok: reached,
+                },
+            }
+        }
+    }
+}
+
+/// Reads all lines from `reader` and writes them to `writer`, prefixed with the line number and the decision
+/// of each processed tag of the searches. Returns the number of lines read.
+pub fn annotate_from_reader<R: BufRead, W: Write>(
+    mut reader: R,
+    searches: &[&Search],
+    writer: &mut W,
+) -> AppResult<u64> {
+    // all tags along with the exclude regex and number of header lines of their logfile, and counters starting from 0
+    let mut tags: Vec<(&Tag, Option<&Regex>, u64, RunData)> = searches
+        .iter()
+        .flat_map(|search| {
+            search
+                .tags
+                .iter()
+                .filter(|tag| tag.process)
+                .map(move |tag| {
+                    (
+                        tag,
+                        search.logfile.exclude.as_ref(),
+                        search.logfile.skip_header_lines,
+                        RunData::default(),
+                    )
+                })
+        })
+        .collect();
+
+    let mut buffer = Vec::with_capacity(DEFAULT_STRING_CAPACITY);
+    let mut line_number = 0;
+
+    loop {
+        let bytes_read = reader
+            .read_until(b'\n', &mut buffer)
+            .map_err(|e| context!(e, "error reading line {}", line_number + 1))?;
+        if bytes_read == 0 {
+            break;
+        }
+        line_number += 1;
+
+        if line_number == 1 {
+            LogFile::strip_bom(&mut buffer);
+        }
+        let mut line = String::from_utf8_lossy(&buffer);
+        LogFile::purge_line(&mut line);
+
+        let decisions: Vec<String> = tags
+            .iter_mut()
+            .map(|(tag, exclude, header_lines, run_data)| {
+                let decision = if line_number <= *header_lines {
+                    Decision::Header
+                } else {
+                    decide(&line, tag, *exclude, run_data)
+                };
+                format!("{}={}", tag.name, decision)
+            })
+            .collect();
+
+        writeln!(writer, "{}:{}:{}", line_number, decisions.join(";"), line)
+            .map_err(|e| context!(e, "error writing annotation for line {}", line_number))?;
+
+        buffer.clear();
+    }
+
+    Ok(line_number)
+}
+
+/// Annotates the logfile `path`, which could be compressed, using the tags of the searches.
+pub fn annotate<P: AsRef<Path>, W: Write>(
+    path: P,
+    searches: &[&Search],
+    writer: &mut W,
+) -> AppResult<u64> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| context!(e, "unable to open file:{:?}", path))?;
+
+    // create a specific reader for each compression scheme
+    let compression =
+        CompressionScheme::from(path.extension().map(|x| x.to_str().unwrap_or_default()));
+    match compression {
+        CompressionScheme::Gzip => {
+            annotate_from_reader(BufReader::new(GzDecoder::new(file)), searches, writer)
+        }
+        #[cfg(feature = "bzip2")]
+        CompressionScheme::Bzip2 => {
+            annotate_from_reader(BufReader::new(BzDecoder::new(file)), searches, writer)
+        }
+        #[cfg(feature = "xz")]
+        CompressionScheme::Xz => {
+            annotate_from_reader(BufReader::new(XzDecoder::new(file)), searches, writer)
+        }
+        #[cfg(feature = "zstd")]
+        CompressionScheme::Zstd => {
+            let decoder = ZstdDecoder::new(file)
+                .map_err(|e| context!(e, "unable to create zstd decoder:{:?}", path))?;
+            annotate_from_reader(BufReader::new(decoder), searches, writer)
+        }
+        #[cfg(feature = "lz4")]
+        CompressionScheme::Lz4 => {
+            annotate_from_reader(BufReader::new(Lz4Decoder::new(file)), searches, writer)
+        }
+        CompressionScheme::Uncompressed => {
+            annotate_from_reader(BufReader::new(file), searches, writer)
+        }
+        #[cfg(not(all(feature = "bzip2", feature = "xz", feature = "zstd", feature = "lz4")))]
+        scheme => Err(scheme.not_built_in()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annotate_lines() {
+        let yaml = r#"
+logfile:
+    path: /var/log/syslog
+    exclude: "^#"
+tags:
+  - name: error
+    options: "criticalthreshold=1"
+    patterns:
+      critical:
+        regexes: ["ERROR"]
+        exceptions: ["ERROR 42"]
+      warning:
+        regexes: ["WARN"]
+      ok:
+        regexes: ["RESET"]
+  - name: skipped
+    process: false
+    patterns:
+      critical:
+        regexes: ["ERROR"]
+"#;
+        let search: Search = serde_yaml::from_str(yaml).expect("unable to read YAML");
+        let text = "# ERROR commented\nERROR 1\nERROR 2\nERROR 42\nWARN 1\nRESET\nfoo\n";
+
+        let mut out = Vec::new();
+        let lines = annotate_from_reader(text.as_bytes(), &[&search], &mut out).unwrap();
+        assert_eq!(lines, 7);
+
+        let annotated: Vec<_> = std::str::from_utf8(&out).unwrap().lines().collect();
+        assert_eq!(annotated[0], "1:error=excluded('^#'):# ERROR commented");
+        assert_eq!(
+            annotated[1],
+            "2:error=critical('ERROR', threshold not reached: count=1, threshold=1):ERROR 1"
+        );
+        assert_eq!(
+            annotated[2],
+            "3:error=critical('ERROR', threshold reached: count=2, threshold=1):ERROR 2"
+        );
+        assert_eq!(annotated[3], "4:error=none(critical exception):ERROR 42");
+        assert_eq!(
+            annotated[4],
+            "5:error=warning('WARN', threshold reached: count=1, threshold=0):WARN 1"
+        );
+        assert_eq!(
+            annotated[5],
+            "6:error=ok('RESET', counters reset, runifok=false):RESET"
+        );
+        assert_eq!(annotated[6], "7:error=none:foo");
+    }
+
+    #[test]
+    fn annotate_header() {
+        let yaml = r#"
+logfile:
+    path: /var/log/app.csv
+    skip_header_lines: 1
+tags:
+  - name: error
+    patterns:
+      critical:
+        regexes: ["^ERROR"]
+"#;
+        let search: Search = serde_yaml::from_str(yaml).expect("unable to read YAML");
+        let text = "\u{FEFF}ERROR,header\nERROR,one\n";
+
+        let mut out = Vec::new();
+        annotate_from_reader(text.as_bytes(), &[&search], &mut out).unwrap();
+
+        let annotated: Vec<_> = std::str::from_utf8(&out).unwrap().lines().collect();
+        assert_eq!(annotated[0], "1:error=header:ERROR,header");
+        assert_eq!(
+            annotated[1],
+            "2:error=critical('^ERROR', threshold reached: count=1, threshold=0):ERROR,one"
+        );
+    }
+}
@@ -1,6 +1,8 @@
 //! Manage different types of compression for a logfile.
 use serde::{Deserialize, Serialize};
 
+use crate::misc::error::{AppCustomErrorKind, AppError};
+
 #[serde(rename_all = "lowercase")]
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 /// A list of possible compression methods for a logfile.
@@ -8,6 +10,8 @@ pub enum CompressionScheme {
     Gzip,
     Bzip2,
     Xz,
+    Zstd,
+    Lz4,
     Uncompressed,
 }
 
@@ -18,6 +22,23 @@ impl CompressionScheme {
     pub fn is_compressed(&self) -> bool {
         self != &CompressionScheme::Uncompressed
     }
+
+    /// True if logfiles compressed this way can be read: bzip2, xz, zstd and lz4 depend on the features of the build.
+    pub fn is_built_in(&self) -> bool {
+        (cfg!(feature = "bzip2") || !matches!(self, CompressionScheme::Bzip2))
+            && (cfg!(feature = "xz") || !matches!(self, CompressionScheme::Xz))
+            && (cfg!(feature = "zstd") || !matches!(self, CompressionScheme::Zstd))
+            && (cfg!(feature = "lz4") || !matches!(self, CompressionScheme::Lz4))
+    }
+
+    /// The error of a logfile compressed in a way which is not built in.
+    #[allow(dead_code)]
+    pub fn not_built_in(&self) -> AppError {
+        AppError::new_custom(
+            AppCustomErrorKind::NotBuiltIn,
+            &format!("{:?} decompression is not built into this binary", self),
+        )
+    }
 }
 
 /// Conversion from a file extension.
@@ -29,6 +50,8 @@ impl From<Option<&str>> for CompressionScheme {
                 "gz" => CompressionScheme::Gzip,
                 "bz2" => CompressionScheme::Bzip2,
                 "xz" => CompressionScheme::Xz,
+                "zst" => CompressionScheme::Zstd,
+                "lz4" => CompressionScheme::Lz4,
                 _ => CompressionScheme::Uncompressed,
             },
         }
@@ -66,5 +89,13 @@ mod tests {
             CompressionScheme::from(Some("bz2")),
             CompressionScheme::Bzip2
         );
+        assert_eq!(
+            CompressionScheme::from(Some("zst")),
+            CompressionScheme::Zstd
+        );
+        assert_eq!(CompressionScheme::from(Some("lz4")), CompressionScheme::Lz4);
+
+        assert!(CompressionScheme::Gzip.is_built_in());
+        assert_eq!(CompressionScheme::Xz.is_built_in(), cfg!(feature = "xz"));
     }
 }
@@ -0,0 +1,153 @@
+//! Manage container logfiles, as written on Kubernetes nodes. Each line is an envelope around the message logged
+//! by the container, either a Docker JSON-file entry or a CRI line. Long messages are split into several partial lines.
+use serde::Deserialize;
+
+use crate::context;
+use crate::misc::error::{AppCustomErrorKind, AppError, AppResult};
+
+/// The format of a container logfile.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum ContainerFormat {
+    /// `{"log":"message\n","stream":"stdout","time":"2021-01-28T18:49:42.123456789Z"}`
+    docker,
+
+    /// `2021-01-28T18:49:42.123456789Z stdout F message`
+    cri,
+}
+
+/// A Docker JSON-file entry.
+#[derive(Debug, Deserialize)]
+struct DockerEntry {
+    log: String,
+    stream: String,
+    time: String,
+}
+
+/// The message along with its envelope data.
+#[derive(Debug, Default, PartialEq)]
+pub struct ContainerRecord {
+    /// the message logged by the container, without its end of line
+    pub message: String,
+
+    /// either stdout or stderr
+    pub stream: String,
+
+    /// timestamp set by the container runtime
+    pub time: String,
+
+    /// true if the message continues on the next line
+    pub partial: bool,
+}
+
+impl ContainerRecord {
+    /// Adds a partial record to this one. The stream and time are those of the first part.
+    pub fn append(&mut self, other: ContainerRecord) {
+        self.message.push_str(&other.message);
+        self.partial = other.partial;
+    }
+}
+
+impl ContainerFormat {
+    /// Unwraps the envelope of a line, end of line being already removed.
+    pub fn parse(&self, line: &str) -> AppResult<ContainerRecord> {
+        match self {
+            ContainerFormat::docker => {
+                let entry: DockerEntry = serde_json::from_str(line)
+                    .map_err(|e| context!(e, "line is not in Docker JSON format: {}", line))?;
+
+                // a Docker message without a trailing newline is continued on the next entry
+                let partial = !entry.log.ends_with('\n');
+                let message = entry.log.trim_end_matches(&['\n', '\r'][..]).to_string();
+
+                Ok(ContainerRecord {
+                    message,
+                    stream: entry.stream,
+                    time: entry.time,
+                    partial,
+                })
+            }
+            ContainerFormat::cri => {
+                let mut fields = line.splitn(4, ' ');
+                let (time, stream, flags) = match (fields.next(), fields.next(), fields.next()) {
+                    (Some(time), Some(stream), Some(flags)) => (time, stream, flags),
+                    _ => {
+                        return Err(AppError::new_custom(
+                            AppCustomErrorKind::InvalidContainerLine,
+                            &format!("line is not in CRI format: {}", line),
+                        ))
+                    }
+                };
+
+                // flags are separated by ':', the first one being either P (partial) or F (full)
+                let partial = flags.split(':').next() == Some("P");
+
+                Ok(ContainerRecord {
+                    message: fields.next().unwrap_or_default().to_string(),
+                    stream: stream.to_string(),
+                    time: time.to_string(),
+                    partial,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn docker() {
+        let format = ContainerFormat::docker;
+
+        let record = format
+            .parse(r#"{"log":"ERROR: \"disk\" full\n","stream":"stderr","time":"2021-01-28T18:49:42.123456789Z"}"#)
+            .unwrap();
+        assert_eq!(record.message, r#"ERROR: "disk" full"#);
+        assert_eq!(record.stream, "stderr");
+        assert_eq!(record.time, "2021-01-28T18:49:42.123456789Z");
+        assert!(!record.partial);
+
+        let mut record = format
+            .parse(r#"{"log":"ERROR: ","stream":"stdout","time":"2021-01-28T18:49:42.1Z"}"#)
+            .unwrap();
+        assert!(record.partial);
+        record.append(
+            format
+                .parse(r#"{"log":"disk full\n","stream":"stdout","time":"2021-01-28T18:49:42.2Z"}"#)
+                .unwrap(),
+        );
+        assert_eq!(record.message, "ERROR: disk full");
+        assert_eq!(record.time, "2021-01-28T18:49:42.1Z");
+        assert!(!record.partial);
+
+        assert!(format.parse("ERROR: disk full").is_err());
+    }
+
+    #[test]
+    fn cri() {
+        let format = ContainerFormat::cri;
+
+        let record = format
+            .parse("2021-01-28T18:49:42.123456789Z stdout F ERROR: disk full")
+            .unwrap();
+        assert_eq!(record.message, "ERROR: disk full");
+        assert_eq!(record.stream, "stdout");
+        assert_eq!(record.time, "2021-01-28T18:49:42.123456789Z");
+        assert!(!record.partial);
+
+        let record = format
+            .parse("2021-01-28T18:49:42.123456789Z stderr P ERROR: ")
+            .unwrap();
+        assert_eq!(record.message, "ERROR: ");
+        assert!(record.partial);
+
+        let record = format
+            .parse("2021-01-28T18:49:42.123456789Z stderr F")
+            .unwrap();
+        assert_eq!(record.message, "");
+
+        assert!(format.parse("ERROR: disk").is_err());
+    }
+}
@@ -0,0 +1,114 @@
+//! A window of the last lines read from a logfile, used to give callbacks the lines preceding a match. A single window
+//! is kept for each logfile and shared by all its tags, so reading the same lines for several tags doesn't store them
+//! several times. The window is bounded both in number of lines and in bytes.
+use std::collections::VecDeque;
+
+/// The last lines read from a logfile, along with the line number of the oldest one.
+#[derive(Debug, Default, Clone)]
+pub struct ContextBuffer {
+    /// lines kept, oldest first, without their end of line
+    lines: VecDeque<String>,
+
+    /// line number of the first line kept
+    first_line: u64,
+
+    /// sum of the lengths of all lines kept
+    bytes: usize,
+
+    /// maximum number of lines kept
+    max_lines: usize,
+
+    /// maximum number of bytes kept
+    max_bytes: usize,
+}
+
+impl ContextBuffer {
+    /// Sets the bounds of the window, dropping the oldest lines if it's now too large.
+    pub fn set_limits(&mut self, max_lines: usize, max_bytes: usize) {
+        self.max_lines = max_lines;
+        self.max_bytes = max_bytes;
+        self.evict();
+    }
+
+    /// Adds a line just read. A line already in the window, because another tag already read it, is not added again.
+    /// If the line doesn't follow the last one kept, the window is restarted from this line.
+    pub fn push(&mut self, line_number: u64, line: &str) {
+        let next_line = self.first_line + self.lines.len() as u64;
+
+        if !self.lines.is_empty() && line_number >= self.first_line && line_number < next_line {
+            return;
+        }
+
+        if self.lines.is_empty() || line_number != next_line {
+            self.lines.clear();
+            self.bytes = 0;
+            self.first_line = line_number;
+        }
+
+        self.bytes += line.len();
+        self.lines.push_back(line.to_string());
+        self.evict();
+    }
+
+    /// Returns at most `n` lines preceding the line `line_number`, oldest first. Fewer lines are returned if they were
+    /// dropped from the window.
+    pub fn before(&self, line_number: u64, n: usize) -> Vec<&str> {
+        let start = line_number.saturating_sub(n as u64).max(self.first_line);
+
+        (start..line_number)
+            .filter_map(|i| self.lines.get((i - self.first_line) as usize))
+            .map(|line| line.as_str())
+            .collect()
+    }
+
+    // drops the oldest lines until the window fits into its bounds
+    fn evict(&mut self) {
+        while self.lines.len() > self.max_lines || self.bytes > self.max_bytes {
+            match self.lines.pop_front() {
+                Some(line) => {
+                    self.bytes -= line.len();
+                    self.first_line += 1;
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn context_buffer() {
+        let mut context = ContextBuffer::default();
+        context.set_limits(3, 1024);
+
+        for i in 1..=5 {
+            context.push(i, &format!("line {}", i));
+        }
+        assert_eq!(context.before(6, 2), vec!["line 4", "line 5"]);
+        assert_eq!(context.before(5, 10), vec!["line 3", "line 4"]);
+        assert!(context.before(3, 2).is_empty());
+
+        // a second tag reading the same lines doesn't change the window
+        context.push(4, "line 4");
+        assert_eq!(context.before(6, 3), vec!["line 3", "line 4", "line 5"]);
+
+        // reading from another place restarts the window
+        context.push(1, "line 1");
+        context.push(2, "line 2");
+        assert_eq!(context.before(3, 3), vec!["line 1", "line 2"]);
+        assert!(context.before(6, 3).is_empty());
+
+        // bounded in bytes
+        context.set_limits(3, 12);
+        assert_eq!(context.before(3, 3), vec!["line 1", "line 2"]);
+        context.push(3, "line 3");
+        assert_eq!(context.before(4, 3), vec!["line 2", "line 3"]);
+
+        // a line larger than the window is not kept
+        context.push(4, "a very long line");
+        assert!(context.before(5, 3).is_empty());
+    }
+}
@@ -0,0 +1,268 @@
+//! A file opened and read by a thread, so a read blocked on a hung mount (e.g. NFS) doesn't stall the run past the
+//! search timeout. Once the deadline is reached, the reader gives up with a `TimedOut` error and the search is
+//! abandoned at the last line processed. The thread is left behind, and ends whenever the blocked read returns.
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Instant;
+
+use crate::context;
+use crate::logfile::seeker::Seeker;
+use crate::misc::error::{AppCustomErrorKind, AppError, AppResult};
+
+// what the thread is asked to do
+enum Request {
+    Read(usize),
+    Seek(SeekFrom),
+}
+
+// what it did
+enum Reply {
+    Opened,
+    Read(Vec<u8>),
+    Seeked(u64),
+}
+
+/// The reading end of a file read by a thread, up to a deadline.
+pub struct DeadlineReader {
+    requests: Sender<Request>,
+    replies: Receiver<io::Result<Reply>>,
+    deadline: Instant,
+    timed_out: bool,
+}
+
+impl DeadlineReader {
+    /// Opens the file `path` from a thread, unless the deadline is reached before.
+    pub fn open(path: &Path, deadline: Instant) -> io::Result<Self> {
+        let path = path.to_path_buf();
+        DeadlineReader::spawn(move || File::open(path), deadline)
+    }
+
+    /// Starts the thread which creates the reader with `open`, and then reads it on request.
+    pub fn spawn<R, F>(open: F, deadline: Instant) -> io::Result<Self>
+    where
+        R: Read + Seek,
+        F: FnOnce() -> io::Result<R> + Send + 'static,
+    {
+        let (requests, requested) = channel();
+        let (replied, replies) = channel();
+
+        thread::spawn(move || {
+            let mut reader = match open() {
+                Ok(reader) => reader,
+                Err(e) => {
+                    let _ = replied.send(Err(e));
+                    return;
+                }
+            };
+            if replied.send(Ok(Reply::Opened)).is_err() {
+                return;
+            }
+
+            // the requests end when the reader is dropped, and replies can't be sent anymore once it gave up
+            for request in requested {
+                let reply = match request {
+                    Request::Read(len) => {
+                        let mut buf = vec![0; len];
+                        reader.read(&mut buf).map(|n| {
+                            buf.truncate(n);
+                            Reply::Read(buf)
+                        })
+                    }
+                    Request::Seek(pos) => reader.seek(pos).map(Reply::Seeked),
+                };
+                if replied.send(reply).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut reader = DeadlineReader {
+            requests,
+            replies,
+            deadline,
+            timed_out: false,
+        };
+        reader.wait().map(|_| reader)
+    }
+
+    // waits for the reply of the thread until the deadline
+    fn wait(&mut self) -> io::Result<Reply> {
+        if self.timed_out {
+            return Err(io::Error::new(
+                ErrorKind::TimedOut,
+                "deadline already reached",
+            ));
+        }
+
+        let timeout = self.deadline.saturating_duration_since(Instant::now());
+        match self.replies.recv_timeout(timeout) {
+            Ok(reply) => reply,
+            Err(RecvTimeoutError::Timeout) => {
+                self.timed_out = true;
+                Err(io::Error::new(ErrorKind::TimedOut, "deadline reached"))
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::new(
+                ErrorKind::BrokenPipe,
+                "reading thread has ended",
+            )),
+        }
+    }
+
+    // sends a request to the thread and waits for its reply
+    fn call(&mut self, request: Request) -> io::Result<Reply> {
+        if !self.timed_out && self.requests.send(request).is_err() {
+            return Err(io::Error::new(
+                ErrorKind::BrokenPipe,
+                "reading thread has ended",
+            ));
+        }
+        self.wait()
+    }
+}
+
+impl Read for DeadlineReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.call(Request::Read(buf.len()))? {
+            Reply::Read(bytes) => {
+                buf[..bytes.len()].copy_from_slice(&bytes);
+                Ok(bytes.len())
+            }
+            _ => Err(io::Error::new(ErrorKind::InvalidData, "unexpected reply")),
+        }
+    }
+}
+
+impl Seek for DeadlineReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self.call(Request::Seek(pos))? {
+            Reply::Seeked(offset) => Ok(offset),
+            _ => Err(io::Error::new(ErrorKind::InvalidData, "unexpected reply")),
+        }
+    }
+}
+
+impl Seeker for BufReader<DeadlineReader> {
+    fn set_offset(&mut self, offset: u64) -> AppResult<u64> {
+        self.seek(SeekFrom::Start(offset)).map_err(|e| {
+            if e.kind() == ErrorKind::TimedOut {
+                AppError::new_custom(
+                    AppCustomErrorKind::SearchTimeout,
+                    &format!("search timeout reached seeking offset {}", offset),
+                )
+            } else {
+                context!(e, "error seeking offset {}", offset)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, Cursor};
+    use std::time::Duration;
+
+    // gives its bytes, and then blocks like a read on a hung mount
+    struct Blocking(Cursor<&'static [u8]>);
+
+    impl Read for Blocking {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => loop {
+                    thread::park();
+                },
+                n => Ok(n),
+            }
+        }
+    }
+
+    impl Seek for Blocking {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.0.seek(pos)
+        }
+    }
+
+    #[test]
+    fn deadline_reader() {
+        let deadline = Instant::now() + Duration::from_millis(300);
+        let reader =
+            DeadlineReader::spawn(|| Ok(Blocking(Cursor::new(b"line1\nline2\n"))), deadline)
+                .unwrap();
+        let mut reader = BufReader::new(reader);
+
+        reader.set_offset(6).unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "line2\n");
+
+        // the next read blocks: it's given up at the deadline
+        let err = reader.read_line(&mut line).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(Instant::now() < deadline + Duration::from_secs(2));
+        assert_eq!(
+            reader.read_line(&mut line).unwrap_err().kind(),
+            ErrorKind::TimedOut
+        );
+
+        // errors when opening are given back
+        let ret = DeadlineReader::open(
+            Path::new("/foo/clf.log"),
+            Instant::now() + Duration::from_secs(5),
+        );
+        assert_eq!(ret.err().unwrap().kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn blocked_lookup() {
+        use std::str::FromStr;
+
+        use crate::configuration::{global::GlobalOptions, logfiledef::LogFileDef, tag::Tag};
+        use crate::logfile::{
+            logfile::LogFile,
+            lookup::{FullReader, Lookup},
+        };
+        use crate::misc::error::InternalError;
+
+        let global = GlobalOptions::from_str("script_path: /usr/bin").unwrap();
+        let tag = Tag::from_str(
+            r#"
+            name: blocked
+            options: "rewind"
+            patterns:
+                critical: {
+                    regexes: [
+                        '^ERROR',
+                    ],
+                }
+        "#,
+        )
+        .unwrap();
+        let def = LogFileDef {
+            hash_window: 4096,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path("tests/unittest/adhoc.txt", Some(def)).unwrap();
+
+        // the lines before the blocked read are processed, and the search is given up at the deadline
+        let deadline = Instant::now() + Duration::from_millis(300);
+        logfile.deadline = Some(deadline + Duration::from_secs(60));
+        let reader = DeadlineReader::spawn(
+            || Ok(Blocking(Cursor::new(b"ERROR 1\nINFO 2\nERROR 3\n"))),
+            deadline,
+        )
+        .unwrap();
+        let ret = Lookup::<FullReader>::reader(&mut logfile, BufReader::new(reader), &tag, &global);
+        assert!(
+            matches!(ret.unwrap_err().error_kind, InternalError::Custom(x) if x == AppCustomErrorKind::SearchTimeout)
+        );
+        assert!(Instant::now() < deadline + Duration::from_secs(2));
+
+        let run_data = logfile.run_data.get("blocked").unwrap();
+        assert_eq!(run_data.last_line, 3);
+        assert_eq!(run_data.last_offset, 23);
+        assert_eq!(run_data.counters.critical_count, 2);
+    }
+}
@@ -0,0 +1,109 @@
+//! A reader of the events of a Windows event log. Events are read from the `Get-WinEvent` PowerShell cmdlet, written as
+//! JSON lines holding their record number as their cursor along with their message, so they're searched like the
+//! entries of the systemd journal. Only built with the `windows-eventlog` feature.
+use std::io::BufReader;
+use std::process::{ChildStdout, Command};
+
+use crate::configuration::logsource::{EventLogFilter, EventLogSource};
+use crate::logfile::journal::JournalReader;
+use crate::misc::error::AppResult;
+
+/// Starts PowerShell to read the events of an event log source, after the record number of the cursor if any.
+pub fn spawn(
+    source: &EventLogSource,
+    cursor: Option<&str>,
+) -> AppResult<JournalReader<BufReader<ChildStdout>>> {
+    let record = cursor.and_then(|cursor| cursor.parse::<u64>().ok());
+
+    let mut command = Command::new("powershell");
+    command.args([
+        "-NoProfile",
+        "-NonInteractive",
+        "-Command",
+        &get_winevent(&source.filter, record),
+    ]);
+    JournalReader::from_command(command, "powershell", &source.key)
+}
+
+// the XPath query selecting the events of the filter after the record number
+fn xpath(filter: &EventLogFilter, record: Option<u64>) -> String {
+    let mut criteria = Vec::new();
+
+    if let Some(record) = record {
+        criteria.push(format!("EventRecordID>{}", record));
+    }
+    if !filter.providers.is_empty() {
+        let providers: Vec<_> = filter
+            .providers
+            .iter()
+            .map(|provider| format!("Provider[@Name='{}']", provider))
+            .collect();
+        criteria.push(format!("({})", providers.join(" or ")));
+    }
+    if let Some(level) = filter.level {
+        criteria.push(format!("Level>=1 and Level<={}", level));
+    }
+
+    if criteria.is_empty() {
+        "*".to_string()
+    } else {
+        format!("*[System[{}]]", criteria.join(" and "))
+    }
+}
+
+// the PowerShell command writing the events as JSON lines, oldest first, their messages without carriage returns. No
+// event found is not an error
+fn get_winevent(filter: &EventLogFilter, record: Option<u64>) -> String {
+    let quote = |s: &str| format!("'{}'", s.replace('\'', "''"));
+
+    format!(
+        "[Console]::OutputEncoding = [Text.Encoding]::UTF8; \
+         try {{ Get-WinEvent -LogName {} -FilterXPath {} -Oldest -ErrorAction Stop | \
+         ForEach-Object {{ [pscustomobject]@{{ __CURSOR = [string]$_.RecordId; MESSAGE = ([string]$_.Message).TrimEnd() -replace '\\r', '' }} | ConvertTo-Json -Compress }} }} \
+         catch {{ if ($_.FullyQualifiedErrorId -notlike 'NoMatchingEventsFound*') {{ [Console]::Error.WriteLine($_); exit 1 }} }}",
+        quote(&filter.channel),
+        quote(&xpath(filter, record))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufRead;
+
+    #[test]
+    fn xpath() {
+        let mut filter = EventLogFilter {
+            channel: "Application".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(super::xpath(&filter, None), "*");
+
+        filter.providers = vec![
+            "MSSQLSERVER".to_string(),
+            "Service Control Manager".to_string(),
+        ];
+        filter.level = Some(2);
+        assert_eq!(
+            super::xpath(&filter, Some(1234)),
+            "*[System[EventRecordID>1234 and (Provider[@Name='MSSQLSERVER'] or Provider[@Name='Service Control Manager']) and Level>=1 and Level<=2]]"
+        );
+
+        let command = get_winevent(&filter, Some(1234));
+        assert!(command.contains("-LogName 'Application' -FilterXPath '*[System[EventRecordID>1234 and (Provider[@Name=''MSSQLSERVER'']"));
+    }
+
+    #[test]
+    fn events() {
+        // events as written by the PowerShell command
+        let events = r#"{"__CURSOR":"1234","MESSAGE":"The service entered\nthe stopped state."}
+{"__CURSOR":"1235","MESSAGE":"Login failed for user 'sa'."}
+"#;
+        let mut reader = JournalReader::new(events.as_bytes());
+
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "The service entered the stopped state.\n");
+        assert_eq!(reader.cursor_at(line.len() as u64), Some("1234"));
+    }
+}
@@ -0,0 +1,306 @@
+//! A reader of systemd journal entries. Entries are read from `journalctl`, which uses the sd-journal API, so clf isn't
+//! linked to libsystemd and still runs on hosts without systemd. Each entry is given to the lookup as a line holding
+//! its message, and the cursor of each entry is kept so the next search resumes after the last entry processed. The
+//! events of a Windows event log are read the same way, their record number being their cursor.
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::configuration::logsource::{JournalFilter, JournalSource};
+use crate::context;
+use crate::logfile::seeker::Seeker;
+use crate::misc::error::{AppCustomErrorKind, AppError, AppResult};
+
+// number of entries whose cursor is kept: the last processed line could be a few lines before the last one read
+const CURSORS_KEPT: usize = 1024;
+
+// the fields of a journal entry which are used
+#[derive(Debug, Deserialize)]
+struct Entry {
+    #[serde(rename = "__CURSOR")]
+    cursor: String,
+
+    // a string, or an array of bytes when the message is not valid UTF-8
+    #[serde(rename = "MESSAGE", default)]
+    message: Value,
+}
+
+/// Journal entries read as lines of messages, ended by a newline. Offsets are bytes counted from the cursor the reader
+/// was started from.
+pub struct JournalReader<R> {
+    // the entries as JSON lines, as output by `journalctl --output=json`
+    entries: R,
+    json: Vec<u8>,
+
+    // the message of the current entry, and what's already consumed
+    line: Vec<u8>,
+    pos: usize,
+    cursor: Option<String>,
+    eof: bool,
+
+    // offset after the last byte consumed, and offsets after each entry fully consumed along with their cursor
+    offset: u64,
+    cursors: VecDeque<(u64, String)>,
+
+    // the program giving the entries, and its process
+    program: &'static str,
+    child: Option<Child>,
+}
+
+impl JournalReader<BufReader<ChildStdout>> {
+    /// Starts `journalctl` to read the entries of a journal source, after the cursor if any.
+    pub fn spawn(source: &JournalSource, cursor: Option<&str>) -> AppResult<Self> {
+        let mut command = Command::new("journalctl");
+        command.args(journalctl_args(&source.filter, cursor));
+        JournalReader::from_command(command, "journalctl", &source.key)
+    }
+
+    /// Starts the `program` of this command, which writes entries as JSON lines holding their `__CURSOR` and
+    /// `MESSAGE`, like `journalctl --output=json`.
+    pub fn from_command(
+        mut command: Command,
+        program: &'static str,
+        key: &Path,
+    ) -> AppResult<Self> {
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| context!(e, "unable to start {} for {}", program, key.display()))?;
+
+        let stdout = child.stdout.take().ok_or_else(|| {
+            AppError::new_custom(
+                AppCustomErrorKind::JournalError,
+                &format!("unable to read {} output", program),
+            )
+        })?;
+
+        let mut reader = JournalReader::new(BufReader::new(stdout));
+        reader.program = program;
+        reader.child = Some(child);
+        Ok(reader)
+    }
+}
+
+impl<R: BufRead> JournalReader<R> {
+    /// Reads entries from JSON lines.
+    pub fn new(entries: R) -> Self {
+        JournalReader {
+            entries,
+            json: Vec::new(),
+            line: Vec::new(),
+            pos: 0,
+            cursor: None,
+            eof: false,
+            offset: 0,
+            cursors: VecDeque::new(),
+            program: "journalctl",
+            child: None,
+        }
+    }
+
+    /// The cursor of the entry ending at this offset, if it's one of the last entries read.
+    pub fn cursor_at(&self, offset: u64) -> Option<&str> {
+        self.cursors
+            .iter()
+            .rev()
+            .find(|(end, _)| *end == offset)
+            .map(|(_, cursor)| cursor.as_str())
+    }
+
+    /// Waits for `journalctl`, or the program giving the entries, to end, which is an error if it failed. If all entries were not read, like when the
+    /// search timeout is reached, it's stopped.
+    pub fn finish(mut self) -> AppResult<()> {
+        let mut child = match self.child.take() {
+            Some(child) => child,
+            None => return Ok(()),
+        };
+
+        if !self.eof {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(());
+        }
+
+        let status = child
+            .wait()
+            .map_err(|e| context!(e, "unable to wait for {}", self.program))?;
+        if status.success() {
+            return Ok(());
+        }
+
+        let mut stderr = String::new();
+        if let Some(mut output) = child.stderr.take() {
+            let _ = output.read_to_string(&mut stderr);
+        }
+        Err(AppError::new_custom(
+            AppCustomErrorKind::JournalError,
+            &format!("{} {}: {}", self.program, status, stderr.trim()),
+        ))
+    }
+
+    // reads the next entry as the current line, which is empty at the end of the entries
+    fn next_entry(&mut self) -> io::Result<()> {
+        self.line.clear();
+        self.pos = 0;
+        self.cursor = None;
+
+        self.json.clear();
+        if self.entries.read_until(b'\n', &mut self.json)? == 0 {
+            self.eof = true;
+            return Ok(());
+        }
+
+        let entry: Entry = serde_json::from_slice(&self.json)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        match entry.message {
+            Value::String(message) => self.line.extend(message.into_bytes()),
+            Value::Array(bytes) => self
+                .line
+                .extend(bytes.iter().filter_map(|b| b.as_u64()).map(|b| b as u8)),
+            _ => (),
+        }
+
+        // a message is a single line
+        for b in self.line.iter_mut().filter(|b| **b == b'\n' || **b == 0) {
+            *b = b' ';
+        }
+        self.line.push(b'\n');
+        self.cursor = Some(entry.cursor);
+
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for JournalReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let size = available.len().min(buf.len());
+        buf[..size].copy_from_slice(&available[..size]);
+        self.consume(size);
+        Ok(size)
+    }
+}
+
+impl<R: BufRead> BufRead for JournalReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.line.len() && !self.eof {
+            self.next_entry()?;
+        }
+        Ok(&self.line[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let amt = amt.min(self.line.len() - self.pos);
+        self.pos += amt;
+        self.offset += amt as u64;
+
+        if self.pos == self.line.len() {
+            if let Some(cursor) = self.cursor.take() {
+                if self.cursors.len() == CURSORS_KEPT {
+                    self.cursors.pop_front();
+                }
+                self.cursors.push_back((self.offset, cursor));
+            }
+        }
+    }
+}
+
+/// The journal is positioned by the cursor when `journalctl` is started: offsets are just counted from there.
+impl<R> Seeker for JournalReader<R> {
+    fn set_offset(&mut self, offset: u64) -> AppResult<u64> {
+        self.offset = offset;
+        Ok(offset)
+    }
+}
+
+// the arguments of `journalctl` to read the entries of the filter after the cursor
+fn journalctl_args(filter: &JournalFilter, cursor: Option<&str>) -> Vec<String> {
+    let mut args = vec![
+        "--output=json".to_string(),
+        "--no-pager".to_string(),
+        "--quiet".to_string(),
+    ];
+
+    if let Some(cursor) = cursor {
+        args.push(format!("--after-cursor={}", cursor));
+    }
+    if let Some(directory) = &filter.directory {
+        args.push(format!("--directory={}", directory.display()));
+    }
+    if let Some(unit) = &filter.unit {
+        args.push(format!("--unit={}", unit));
+    }
+    if let Some(identifier) = &filter.identifier {
+        args.push(format!("--identifier={}", identifier));
+    }
+    args.extend(filter.matches.iter().cloned());
+
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTRIES: &str = r#"{"__CURSOR":"s=1;i=1","MESSAGE":"Accepted password for root","_PID":"42"}
+{"__CURSOR":"s=1;i=2","MESSAGE":"first line\nsecond line"}
+{"__CURSOR":"s=1;i=3","MESSAGE":[102,111,111,255]}
+{"__CURSOR":"s=1;i=4"}
+"#;
+
+    #[test]
+    fn reader() {
+        let mut reader = JournalReader::new(ENTRIES.as_bytes());
+        reader.set_offset(100).unwrap();
+
+        let mut lines = Vec::new();
+        let mut buffer = Vec::new();
+        while reader.read_until(b'\n', &mut buffer).unwrap() != 0 {
+            lines.push(std::mem::take(&mut buffer));
+        }
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], b"Accepted password for root\n");
+        assert_eq!(lines[1], b"first line second line\n");
+        assert_eq!(lines[2], b"foo\xff\n");
+        assert_eq!(lines[3], b"\n");
+
+        // cursors are found from the offsets after each entry, starting from the offset set
+        assert_eq!(reader.cursor_at(127), Some("s=1;i=1"));
+        assert_eq!(reader.cursor_at(127 + 23), Some("s=1;i=2"));
+        assert_eq!(reader.cursor_at(127 + 23 + 5 + 1), Some("s=1;i=4"));
+        assert!(reader.cursor_at(128).is_none());
+        assert!(reader.finish().is_ok());
+
+        // not a journal entry
+        let mut reader = JournalReader::new(&b"not json\n"[..]);
+        assert!(reader.fill_buf().is_err());
+    }
+
+    #[test]
+    fn journalctl_args() {
+        let filter = JournalFilter {
+            unit: Some("sshd.service".to_string()),
+            matches: vec!["PRIORITY=3".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            super::journalctl_args(&filter, Some("s=1;i=2")),
+            vec![
+                "--output=json",
+                "--no-pager",
+                "--quiet",
+                "--after-cursor=s=1;i=2",
+                "--unit=sshd.service",
+                "PRIORITY=3"
+            ]
+        );
+    }
+}
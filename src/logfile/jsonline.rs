@@ -0,0 +1,77 @@
+//! Manage logfiles whose lines are JSON objects, like those written by structured loggers. Patterns could match the
+//! value of a field instead of the whole line, and all fields are given to callbacks.
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Value};
+
+/// The fields of a JSON line, as text. Fields of nested objects are named by their path, like `http.status`.
+#[derive(Debug, Default, PartialEq)]
+pub struct JsonLine(BTreeMap<String, String>);
+
+impl JsonLine {
+    /// Parses a line, end of line being already removed. Returns `None` if it's not a JSON object.
+    pub fn parse(line: &str) -> Option<JsonLine> {
+        match serde_json::from_str(line) {
+            Ok(Value::Object(object)) => {
+                let mut fields = BTreeMap::new();
+                flatten(&object, "", &mut fields);
+                Some(JsonLine(fields))
+            }
+            _ => None,
+        }
+    }
+
+    /// The value of a field. Strings are given without their quotes, arrays are given as JSON, and null values are
+    /// missing.
+    pub fn get(&self, field: &str) -> Option<&str> {
+        self.0.get(field).map(|value| value.as_str())
+    }
+
+    /// Iterates through all fields along with their value.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.0.iter()
+    }
+}
+
+// adds the fields of an object, prefixing the names of the fields of nested objects
+fn flatten(object: &Map<String, Value>, prefix: &str, fields: &mut BTreeMap<String, String>) {
+    for (name, value) in object {
+        let name = format!("{}{}", prefix, name);
+        match value {
+            Value::Null => (),
+            Value::String(s) => {
+                fields.insert(name, s.clone());
+            }
+            Value::Object(nested) => flatten(nested, &format!("{}.", name), fields),
+            _ => {
+                fields.insert(name, value.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let line = r#"{"level":"ERROR","msg":"disk full","http":{"status":503,"client":{"ip":"10.0.0.1"}},"tags":["a","b"],"retry":true,"trace":null}"#;
+        let json = JsonLine::parse(line).unwrap();
+
+        assert_eq!(json.get("level"), Some("ERROR"));
+        assert_eq!(json.get("msg"), Some("disk full"));
+        assert_eq!(json.get("http.status"), Some("503"));
+        assert_eq!(json.get("http.client.ip"), Some("10.0.0.1"));
+        assert_eq!(json.get("tags"), Some(r#"["a","b"]"#));
+        assert_eq!(json.get("retry"), Some("true"));
+        assert!(json.get("trace").is_none());
+        assert!(json.get("http").is_none());
+        assert_eq!(json.iter().count(), 6);
+
+        // not a JSON object
+        assert!(JsonLine::parse("ERROR: disk full").is_none());
+        assert!(JsonLine::parse("[1, 2]").is_none());
+        assert!(JsonLine::parse(r#"{"level":"#).is_none());
+    }
+}
@@ -1,27 +1,55 @@
 //! A structure representing a logfile, with all its related attributes. Those attributes are
 //! coming from the processing of the log file, every time it's read to look for patterns.
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, ErrorKind, Read};
 use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
 
+#[cfg(feature = "bzip2")]
 use bzip2::read::BzDecoder;
 use flate2::read::GzDecoder;
-use log::{debug, error};
+use log::{debug, error, warn};
+#[cfg(feature = "lz4")]
+use lz4_flex::frame::FrameDecoder as Lz4Decoder;
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "xz")]
 use xz2::read::XzDecoder;
+#[cfg(feature = "zstd")]
+use zstd::stream::read::Decoder as ZstdDecoder;
 
 use crate::configuration::{
-    callback::ChildData, global::GlobalOptions, logfiledef::LogFileDef, pattern::PatternCounters,
+    callback::ChildData,
+    email::Digest,
+    global::{GlobalOptions, OverlapCheck, Quantum},
+    logfiledef::{LogFileDef, ShrinkPolicy},
+    logsource::LogSource,
+    pattern::PatternCounters,
     tag::Tag,
 };
 use crate::context;
+#[cfg(feature = "windows-eventlog")]
+use crate::logfile::eventlog;
 use crate::logfile::{
-    compression::CompressionScheme, logfileid::LogFileID, lookup::Lookup, rundata::RunData,
+    compression::CompressionScheme,
+    context::ContextBuffer,
+    deadline::DeadlineReader,
+    journal::JournalReader,
+    logfileid::LogFileID,
+    lookup::Lookup,
+    output::OutputFile,
+    progress::ProgressFile,
+    remote::RemoteReader,
+    rundata::RunData,
+    seeker::Seeker,
+    stdin::{stdin, STDIN_WAIT},
 };
 use crate::misc::error::{AppCustomErrorKind, AppError, AppResult};
-use crate::misc::extension::ReadFs;
+use crate::misc::extension::{HashSpec, ReadFs, Signature};
+use crate::misc::signal::shutdown_requested;
+use crate::misc::util::{from_epoch_secs, UTF8_BOM};
 
 /// A wrapper to get logfile information and its related attributes.
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
@@ -35,6 +63,82 @@ pub struct LogFile {
 
     /// Run time data that are stored each time a logfile is searched for patterns.
     pub run_data: HashMap<String, RunData>,
+
+    /// Optional point in time after which the search is abandoned, set from the search timeout
+    #[serde(skip)]
+    pub deadline: Option<Instant>,
+
+    /// The last lines read, shared by all tags to give the lines preceding a match
+    #[serde(skip)]
+    pub context: ContextBuffer,
+
+    /// Labels of the search, set from the search definition
+    #[serde(skip)]
+    pub labels: BTreeMap<String, String>,
+
+    /// Fraction of lines whose decision path is traced, set from the command line
+    #[serde(skip)]
+    pub trace_lines: f64,
+
+    /// Size of the slices when logfiles are searched in turn, set from the global options
+    #[serde(skip)]
+    pub quantum: Option<Quantum>,
+
+    /// Tags whose search stopped at the end of a slice, to be resumed at the next slice
+    #[serde(skip)]
+    pub slices: HashMap<String, Slice>,
+
+    /// The output files of the run, where matched lines are kept with the `keepoutput` option
+    #[serde(skip)]
+    pub output: Option<Arc<OutputFile>>,
+
+    /// The protocol files of the run, where matched lines are written with the `protocol` option
+    #[serde(skip)]
+    pub protocol: Option<Arc<OutputFile>>,
+
+    /// The progress file of the snapshot, where offsets are saved along the way with the `snapshot_checkpoint` global
+    /// option
+    #[serde(skip)]
+    pub progress: Option<Arc<ProgressFile>>,
+
+    /// When set from the command line, callback calls are kept pending instead of being done
+    #[serde(skip)]
+    pub dry_run: bool,
+
+    /// Tags which matched the same lines during the run, with the `overlap_check` global option
+    #[serde(skip)]
+    pub overlaps: Vec<TagOverlap>,
+
+    /// Whether the standard input searched is still open, so the end of the lines read so far is the end of a slice
+    #[serde(skip)]
+    pub stream_open: bool,
+
+    /// Matches for e-mail callbacks during the run, until they're sent once all logfiles are searched
+    #[serde(skip)]
+    pub digests: Vec<Digest>,
+}
+
+/// Two tags of a logfile matching the same lines during a run, so these lines are counted twice.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TagOverlap {
+    /// names of the tags, in alphabetical order
+    pub tags: [String; 2],
+
+    /// number of lines matched by both tags
+    pub lines: u64,
+
+    /// ratio of these lines over the lines matched by the tag with the fewest matches
+    pub ratio: f64,
+}
+
+/// What's kept from the search of a tag between 2 slices of the same run.
+#[derive(Debug, Clone, Default)]
+pub struct Slice {
+    /// incidents found since the beginning of the run
+    pub seen_incidents: HashSet<String>,
+
+    /// time spent reading during the previous slices
+    pub scan_duration: f64,
 }
 
 impl LogFile {
@@ -52,11 +156,22 @@ impl LogFile {
         }
 
         // now update all fields
-        logfile.id.update(path, logfile.definition.hash_window)?;
+        logfile.id.update(path, logfile.definition.hash_spec())?;
 
         Ok(logfile)
     }
 
+    /// Creates a `LogFile` for a journal or remote source. There's no local file to check: its path is the key of the
+    /// source.
+    pub fn from_source(def: LogFileDef) -> LogFile {
+        let mut logfile = LogFile::default();
+        logfile.id.declared_path = def.path().clone();
+        logfile.id.canon_path = def.path().clone();
+        logfile.definition = def;
+
+        logfile
+    }
+
     /// Set definition coming from config file
     pub fn set_definition(&mut self, def: LogFileDef) {
         self.definition = def;
@@ -64,9 +179,24 @@ impl LogFile {
 
     /// Recalculate the signature to check whether it has changed
     pub fn hash_been_rotated(&self) -> AppResult<bool> {
+        // a journal or an event log is read from the cursor of the last entry processed, whatever its files, and a
+        // remote logfile is checked when it's read. The standard input is never rotated
+        if self.definition.path.is_journal()
+            || self.definition.path.is_eventlog()
+            || self.definition.path.is_remote()
+            || self.definition.path.is_stdin()
+        {
+            return Ok(false);
+        }
+
         // get most recent signature
         let old_signature = &self.id.signature;
-        let new_signature = self.id.canon_path.signature(self.definition.hash_window)?;
+        // the hash is calculated like the saved one, even if the algorithm was changed since
+        let new_signature = self.id.canon_path.signature(HashSpec {
+            algorithm: old_signature.algorithm,
+            head: self.definition.hash_window,
+            tail: 0,
+        })?;
 
         trace!(
             "file = {:?}, current signature = {:?}, recalculated = {:?}",
@@ -95,15 +225,74 @@ impl LogFile {
                     ),
                 ))
             }
+            // if hashes are different we can assume this is a new file
+            else if old_signature.hash.unwrap() != new_signature.hash.unwrap() {
+                Ok(true)
+            }
+            // bytes before the saved size don't change as long as the file is only appended to, so its end is also
+            // compared if it was hashed
+            else if let Some(tail_hash) = old_signature.tail_hash {
+                if new_signature.size < old_signature.size {
+                    return Ok(true);
+                }
+                let new_tail_hash = Signature::hash(
+                    &self.id.canon_path,
+                    old_signature.algorithm,
+                    old_signature.size - old_signature.tail_window,
+                    old_signature.tail_window as usize,
+                )?;
+                Ok(new_tail_hash != tail_hash)
+            }
             // if hashes are equal we can assume file has not been rotated
-            else if old_signature.hash.unwrap() == new_signature.hash.unwrap() {
+            else {
                 Ok(false)
             }
-            // if not we can assume this is a new file
-            else {
-                Ok(true)
+        }
+    }
+
+    /// With the `rescan_tail` shrink policy, when the logfile is shorter than the offset a tag was read up to while
+    /// its inode is the same, the last line read by each tag is looked for near the new end of the logfile. Returns
+    /// `true` if all tags were moved after their last line, the signature being updated so the logfile doesn't look
+    /// rotated. Otherwise, the logfile is handled as usual.
+    pub fn relocate_after_shrink(&mut self) -> AppResult<bool> {
+        if self.definition.shrink_policy != ShrinkPolicy::rescan_tail
+            || self.definition.path.is_journal()
+            || self.definition.path.is_eventlog()
+            || self.definition.path.is_remote()
+            || self.definition.path.is_stdin()
+            || self.id.compression.is_compressed()
+        {
+            return Ok(false);
+        }
+
+        let signature = self.id.canon_path.signature(self.definition.hash_spec())?;
+        if signature.dev != self.id.signature.dev || signature.inode != self.id.signature.inode {
+            return Ok(false);
+        }
+
+        let path = &self.id.canon_path;
+        let window = self.definition.shrink_window;
+        let mut relocated = false;
+        for (tag_name, run_data) in self
+            .run_data
+            .iter_mut()
+            .filter(|(_, run_data)| run_data.last_offset > signature.size)
+        {
+            if !run_data.relocate_last_line(path, window)? {
+                warn!(
+                    "last line read for tag {} not found after logfile {} shrank",
+                    tag_name,
+                    path.display()
+                );
+                return Ok(false);
             }
+            relocated = true;
+        }
+
+        if relocated {
+            self.id.signature = signature;
         }
+        Ok(relocated)
     }
 
     // pub fn get_signatures(&self) -> (Signature, Signature) {
@@ -127,32 +316,58 @@ impl LogFile {
 
     /// Either delete \n or \r\n for end of line if line is ending by these
     pub fn purge_line(line: &mut Cow<str>) {
-        if let Some(last_char) = line.chars().last() {
-            if last_char == '\n' {
-                line.to_mut().pop();
-            }
+        let mut len = line.len();
+        if line.ends_with('\n') {
+            len -= 1;
         }
         #[cfg(target_family = "windows")]
-        if let Some(last_char) = line.chars().last() {
-            if last_char == '\r' {
-                line.to_mut().pop();
-            }
+        if line[..len].ends_with('\r') {
+            len -= 1;
+        }
+
+        // a borrowed line is only sliced, so valid UTF-8 lines are never copied
+        match line {
+            Cow::Borrowed(s) => *s = &s[..len],
+            Cow::Owned(s) => s.truncate(len),
+        }
+    }
+
+    /// The bytes of a line without its end of line, like `purge_line()`.
+    pub fn raw_line(buffer: &[u8]) -> &[u8] {
+        let raw = buffer.strip_suffix(b"\n").unwrap_or(buffer);
+        #[cfg(target_family = "windows")]
+        let raw = raw.strip_suffix(b"\r").unwrap_or(raw);
+        raw
+    }
+
+    /// Removes the UTF-8 byte order mark at the beginning of the first line of a logfile. Offsets still count its
+    /// bytes, so the logfile is read again from the right position.
+    pub fn strip_bom(buffer: &mut Vec<u8>) {
+        if buffer.starts_with(UTF8_BOM) {
+            buffer.drain(..UTF8_BOM.len());
         }
     }
 
-    /// Sum all counters from `rundata` for all tags, but excluding the value of id
+    /// Sum all counters from `rundata` for all tags, but excluding the value of id. Criticals within the error budget of
+    /// a tag are counted as warnings
     pub fn sum_counters(&self, id: u32) -> PatternCounters {
         self.run_data
             .values()
             .filter(|x| x.pid == id)
-            .map(|x| &x.counters)
-            .sum()
+            .map(|x| x.reported_counters())
+            .fold(PatternCounters::default(), |acc, x| acc + x)
     }
 
-    /// Last error occuring when reading this logfile
+    /// Last error occuring when reading this logfile. The error could occur before the tag is searched for the first
+    /// time, like for a logfile compressed in a way which is not built in: it's reported as part of this run, and kept
+    /// in the snapshot like a tag searched.
     pub fn set_error(&mut self, error: AppError, tag_name: &str) {
-        debug_assert!(self.run_data.contains_key(tag_name));
-        self.run_data.get_mut(tag_name).unwrap().last_error = Some(error);
+        let now = from_epoch_secs().unwrap_or_default();
+        let run_data = self.rundata_for_tag(tag_name);
+        run_data.pid = std::process::id();
+        run_data.last_run = now as f64;
+        run_data.last_run_secs = now;
+        run_data.last_error = Some(error);
     }
 
     /// Reset counters and offsets for a specific tag
@@ -161,8 +376,12 @@ impl LogFile {
 
         let tag = self.run_data.get_mut(tag_name).unwrap();
         tag.counters = PatternCounters::default();
+        tag.keyed_counters.clear();
         tag.last_line = 0;
         tag.last_offset = 0;
+        tag.counted_offset = 0;
+        tag.required_line = None;
+        tag.journal_cursor = None;
     }
 
     /// Reset offsets for a specific tag
@@ -172,6 +391,9 @@ impl LogFile {
         let tag = self.run_data.get_mut(tag_name).unwrap();
         tag.last_line = 0;
         tag.last_offset = 0;
+        tag.counted_offset = 0;
+        tag.required_line = None;
+        tag.journal_cursor = None;
     }
 
     /// Copy counters from another logfile
@@ -180,7 +402,9 @@ impl LogFile {
         debug_assert!(other.run_data.contains_key(tag_name));
 
         let tag = self.run_data.get_mut(tag_name).unwrap();
-        tag.counters = other.run_data.get(tag_name).unwrap().counters.clone();
+        let other = other.run_data.get(tag_name).unwrap();
+        tag.counters = other.counters.clone();
+        tag.keyed_counters = other.keyed_counters.clone();
     }
 
     ///Just a wrapper function for a file.
@@ -192,10 +416,126 @@ impl LogFile {
     where
         Self: Lookup<T>,
     {
-        // open target file
-        let file = File::open(&self.id.canon_path)
-            .map_err(|e| context!(e, "unable to open file:{:?}", &self.id.canon_path))?;
+        // journal entries are read from journalctl, and events of an event log from PowerShell, after the last entry
+        // processed unless they're read again
+        if self.definition.path.is_journal() || self.definition.path.is_eventlog() {
+            let resumed = self.slices.contains_key(&tag.name);
+            let cursor = match self.run_data.get(&tag.name) {
+                Some(run_data) if !tag.options.rewind || resumed => run_data.journal_cursor.clone(),
+                _ => None,
+            };
+            let mut reader = match &self.definition.path {
+                LogSource::Journal(journal) => JournalReader::spawn(journal, cursor.as_deref())?,
+                #[cfg(feature = "windows-eventlog")]
+                LogSource::EventLog(eventlog) => eventlog::spawn(eventlog, cursor.as_deref())?,
+                source => {
+                    return Err(AppError::new_custom(
+                        AppCustomErrorKind::NotBuiltIn,
+                        &format!("searching {} is not built into this binary", source),
+                    ))
+                }
+            };
+            let ret = Lookup::<T>::reader(self, &mut reader, tag, global_options);
+
+            // resume after the last entry processed, which is not always the last one read
+            let run_data = self.rundata_for_tag(&tag.name);
+            if let Some(cursor) = reader.cursor_at(run_data.last_offset) {
+                run_data.journal_cursor = Some(cursor.to_string());
+            }
+
+            let finished = reader.finish();
+            return ret.and_then(|children| finished.map(|_| children));
+        }
+
+        // a remote logfile is read by ssh from the last offset, unless it's read again or it's shorter since
+        if let LogSource::Remote(remote) = self.definition.path.clone() {
+            let resumed = self.slices.contains_key(&tag.name);
+            // an error of ssh is reported for this run
+            let run_data = self.rundata_for_tag(&tag.name);
+            run_data.pid = std::process::id();
+            let offset = if !tag.options.rewind || resumed {
+                run_data.last_offset
+            } else {
+                0
+            };
+            let timeout = self
+                .deadline
+                .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            let mut reader = RemoteReader::spawn(&remote, offset, timeout)?;
+            if reader.start() != offset {
+                info!(
+                    "remote logfile {} is shorter than offset={}, probably rotated",
+                    remote.key.display(),
+                    offset
+                );
+                self.reset_tag_offsets(&tag.name);
+            }
+            let ret = Lookup::<T>::reader(self, &mut reader, tag, global_options);
+
+            let finished = reader.finish();
+            return ret.and_then(|children| finished.map(|_| children));
+        }
+
+        // the standard input is read from the offset reached by the tag since clf started, offsets of a previous run
+        // being those of another stream
+        if self.definition.path.is_stdin() {
+            let current_pid = std::process::id();
+            if self.rundata_for_tag(&tag.name).pid != current_pid {
+                self.reset_tag_offsets(&tag.name);
+            }
+            let run_data = self.rundata_for_tag(&tag.name);
+            let (mut reader, open) = stdin().reader(run_data.last_offset, STDIN_WAIT);
+            if reader.offset() != run_data.last_offset {
+                warn!(
+                    "lines of the standard input already dropped, tag {} is read from offset={}",
+                    tag.name,
+                    reader.offset()
+                );
+                run_data.last_offset = reader.offset();
+            }
+
+            self.stream_open = open;
+            let ret = Lookup::<T>::reader(self, &mut reader, tag, global_options);
+            self.stream_open = false;
+            return ret;
+        }
 
+        // with a search timeout, the file is read by a thread, given up if a read is blocked up to the deadline
+        let path = self.id.canon_path.clone();
+        match self.deadline {
+            Some(deadline) => {
+                let file = DeadlineReader::open(&path, deadline).map_err(|e| {
+                    if e.kind() == ErrorKind::TimedOut {
+                        AppError::new_custom(
+                            AppCustomErrorKind::SearchTimeout,
+                            &format!("search timeout reached opening logfile {:?}", path),
+                        )
+                    } else {
+                        context!(e, "unable to open file:{:?}", path)
+                    }
+                })?;
+                self.lookup_file(file, tag, global_options)
+            }
+            None => {
+                let file =
+                    File::open(&path).map_err(|e| context!(e, "unable to open file:{:?}", path))?;
+                self.lookup_file(file, tag, global_options)
+            }
+        }
+    }
+
+    // searches an opened file, depending on its compression
+    fn lookup_file<T, F>(
+        &mut self,
+        file: F,
+        tag: &Tag,
+        global_options: &GlobalOptions,
+    ) -> AppResult<Vec<ChildData>>
+    where
+        Self: Lookup<T>,
+        F: Read,
+        BufReader<F>: Seeker,
+    {
         // if file is compressed, we need to call a specific reader
         // create a specific reader for each compression scheme
         match self.id.compression {
@@ -205,23 +545,41 @@ impl LogFile {
                 //self.lookup_from_reader(reader, wrapper)
                 Lookup::<T>::reader(self, reader, tag, global_options)
             }
+            #[cfg(feature = "bzip2")]
             CompressionScheme::Bzip2 => {
                 let decoder = BzDecoder::new(file);
                 let reader = BufReader::new(decoder);
                 //self.lookup_from_reader(reader, wrapper)
                 Lookup::<T>::reader(self, reader, tag, global_options)
             }
+            #[cfg(feature = "xz")]
             CompressionScheme::Xz => {
                 let decoder = XzDecoder::new(file);
                 let reader = BufReader::new(decoder);
                 //self.lookup_from_reader(reader, wrapper)
                 Lookup::<T>::reader(self, reader, tag, global_options)
             }
+            #[cfg(feature = "zstd")]
+            CompressionScheme::Zstd => {
+                let decoder = ZstdDecoder::new(file).map_err(|e| {
+                    context!(e, "unable to create zstd decoder:{:?}", &self.id.canon_path)
+                })?;
+                let reader = BufReader::new(decoder);
+                Lookup::<T>::reader(self, reader, tag, global_options)
+            }
+            #[cfg(feature = "lz4")]
+            CompressionScheme::Lz4 => {
+                let decoder = Lz4Decoder::new(file);
+                let reader = BufReader::new(decoder);
+                Lookup::<T>::reader(self, reader, tag, global_options)
+            }
             CompressionScheme::Uncompressed => {
                 let reader = BufReader::new(file);
                 //self.lookup_from_reader(reader, wrapper)
                 Lookup::<T>::reader(self, reader, tag, global_options)
             }
+            #[cfg(not(all(feature = "bzip2", feature = "xz", feature = "zstd", feature = "lz4")))]
+            _ => Err(self.id.compression.not_built_in()),
         }
     }
 
@@ -234,9 +592,58 @@ impl LogFile {
     ) where
         Self: Lookup<T>,
     {
-        for tag in tags.iter().filter(|t| t.process) {
+        // the search starts again from the beginning of the run, even if it stopped at a slice during the previous run
+        let tags: Vec<_> = tags.iter().filter(|t| t.process).collect();
+        for tag in &tags {
+            self.slices.remove(&tag.name);
+            if let Some(run_data) = self.run_data.get_mut(&tag.name) {
+                run_data.matched_lines.clear();
+            }
+        }
+        self.lookup_each::<T>(global_options, tags, children_list);
+    }
+
+    /// Goes on with the search of the tags which stopped at the end of the previous slice.
+    pub fn resume_tags<T>(
+        &mut self,
+        global_options: &GlobalOptions,
+        tags: &[Tag],
+        children_list: &mut Vec<ChildData>,
+    ) where
+        Self: Lookup<T>,
+    {
+        let tags: Vec<_> = tags
+            .iter()
+            .filter(|t| t.process && self.slices.contains_key(&t.name))
+            .collect();
+        self.lookup_each::<T>(global_options, tags, children_list);
+    }
+
+    /// Whether the search of some of these tags stopped at the end of a slice, and is to be resumed.
+    pub fn is_sliced(&self, tags: &[Tag]) -> bool {
+        tags.iter().any(|t| self.slices.contains_key(&t.name))
+    }
+
+    fn lookup_each<T>(
+        &mut self,
+        global_options: &GlobalOptions,
+        tags: Vec<&Tag>,
+        children_list: &mut Vec<ChildData>,
+    ) where
+        Self: Lookup<T>,
+    {
+        for tag in &tags {
+            // no need to search the remaining tags if clf is shutting down
+            if shutdown_requested() {
+                break;
+            }
             debug!("searching for tag: {}", &tag.name);
 
+            // an error from a previous search, when logfiles are followed, is not reported again
+            if let Some(run_data) = self.run_data.get_mut(&tag.name) {
+                run_data.last_error = None;
+            }
+
             // now we can search for the pattern and save the child handle if a script was called
             match self.lookup::<T>(tag, global_options) {
                 // script might be started, giving back a `Child` structure with process features like pid etc
@@ -261,7 +668,83 @@ impl LogFile {
                 }
             }
         }
+
+        // lines of the standard input read by all tags are not needed anymore
+        if self.definition.path.is_stdin() {
+            let offset = tags
+                .iter()
+                .filter_map(|tag| self.run_data.get(&tag.name))
+                .map(|run_data| run_data.last_offset)
+                .min();
+            if let Some(offset) = offset {
+                stdin().consume(offset);
+            }
+        }
+
+        // overlaps are checked once all tags are searched up to the end of the logfile
+        if let Some(check) = &global_options.overlap_check {
+            if self.slices.is_empty() {
+                self.check_overlaps(check);
+            }
+        }
+    }
+
+    /// Finds the tags which matched the same lines during this run, and reports them in the clf log.
+    pub fn check_overlaps(&mut self, check: &OverlapCheck) {
+        let current_pid = std::process::id();
+        let mut matched: Vec<_> = self
+            .run_data
+            .iter()
+            .filter(|(_, run_data)| run_data.pid == current_pid)
+            .map(|(tag_name, run_data)| (tag_name, &run_data.matched_lines))
+            .filter(|(_, lines)| !lines.is_empty())
+            .collect();
+        matched.sort();
+
+        let mut overlaps = Vec::new();
+        for (i, (tag1, lines1)) in matched.iter().enumerate() {
+            for (tag2, lines2) in &matched[i + 1..] {
+                let lines = common_lines(lines1, lines2);
+                let ratio = lines as f64 / lines1.len().min(lines2.len()) as f64;
+                if lines < check.min_lines || ratio < check.ratio {
+                    continue;
+                }
+
+                warn!(
+                    "tags overlap: logfile={} tags={},{} lines={} ratio={:.2}",
+                    self.id.canon_path.display(),
+                    tag1,
+                    tag2,
+                    lines,
+                    ratio
+                );
+                overlaps.push(TagOverlap {
+                    tags: [tag1.to_string(), tag2.to_string()],
+                    lines,
+                    ratio,
+                });
+            }
+        }
+
+        self.overlaps = overlaps;
+    }
+}
+
+// number of line numbers found in both lists, each one being sorted as lines are read in order
+fn common_lines(lines1: &[u64], lines2: &[u64]) -> u64 {
+    let (mut i, mut j, mut common) = (0, 0, 0);
+    while i < lines1.len() && j < lines2.len() {
+        match lines1[i].cmp(&lines2[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                common += 1;
+                i += 1;
+                j += 1;
+            }
+        }
     }
+    common
 }
 
 #[cfg(test)]
@@ -273,38 +756,34 @@ mod tests {
 
     use crate::configuration::vars::VarType;
 
+    #[cfg_attr(not(feature = "sockets"), allow(dead_code))]
     #[derive(Debug, Deserialize)]
     struct JSONStream {
+        pub seq: u64,
         pub args: Option<Vec<String>>,
+        #[serde(default)]
         pub vars: std::collections::HashMap<String, VarType<String>>,
+        pub end_of_run: Option<EndOfRun>,
+    }
+
+    #[cfg_attr(not(feature = "sockets"), allow(dead_code))]
+    #[derive(Debug, Deserialize)]
+    struct EndOfRun {
+        pub sent: u64,
     }
 
     // utility fn to receive JSON from a stream
     #[cfg(target_family = "unix")]
+    #[cfg_attr(not(feature = "sockets"), allow(dead_code))]
     fn get_json_from_stream<T: std::io::Read>(
         socket: &mut T,
     ) -> Result<JSONStream, std::io::Error> {
+        use crate::configuration::protocol;
         use std::io::{Error, ErrorKind};
 
-        // try to read size first
-        let mut size_buffer = [0; std::mem::size_of::<u16>()];
-        let bytes_read = socket.read(&mut size_buffer)?;
-        //dbg!(bytes_read);
-        if bytes_read == 0 {
-            return Err(Error::new(ErrorKind::Interrupted, "socket closed"));
-        }
-
-        let json_size = u16::from_be_bytes(size_buffer);
-
-        // read JSON raw data
-        let mut json_buffer = vec![0; json_size as usize];
-        socket.read_exact(&mut json_buffer).unwrap();
-
-        // get JSON
-        let s = std::str::from_utf8(&json_buffer).unwrap();
-
-        let json: JSONStream = serde_json::from_str(&s).unwrap();
-        Ok(json)
+        let json = protocol::read_frame(socket)?
+            .ok_or_else(|| Error::new(ErrorKind::Interrupted, "socket closed"))?;
+        Ok(serde_json::from_slice(&json).unwrap())
     }
 
     #[test]
@@ -313,6 +792,7 @@ mod tests {
         let s = "this an example\n";
         let mut cow: Cow<str> = Cow::Borrowed(s);
         LogFile::purge_line(&mut cow);
+        assert!(matches!(cow, Cow::Borrowed(_)));
         assert_eq!(cow.into_owned(), "this an example");
     }
 
@@ -325,6 +805,20 @@ mod tests {
         assert_eq!(cow.into_owned(), "this an example");
     }
 
+    #[test]
+    fn strip_bom() {
+        let mut buffer = b"\xEF\xBB\xBFERROR\n".to_vec();
+        LogFile::strip_bom(&mut buffer);
+        assert_eq!(buffer, b"ERROR\n");
+
+        // only a leading BOM is removed
+        LogFile::strip_bom(&mut buffer);
+        assert_eq!(buffer, b"ERROR\n");
+        let mut buffer = b"a\xEF\xBB\xBF".to_vec();
+        LogFile::strip_bom(&mut buffer);
+        assert_eq!(buffer.len(), 4);
+    }
+
     #[test]
     #[cfg(target_os = "linux")]
     fn new() {
@@ -380,7 +874,7 @@ mod tests {
     }
 
     #[test]
-    #[cfg(target_family = "unix")]
+    #[cfg(all(feature = "sockets", target_family = "unix"))]
     fn from_reader() {
         let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
 
@@ -422,6 +916,7 @@ mod tests {
         let child = std::thread::spawn(move || {
             // create a listener
             let listener = std::net::TcpListener::bind("127.0.0.1:8999").unwrap();
+            let mut nb_received = 0;
             match listener.accept() {
                 Ok((mut socket, _addr)) => loop {
                     let json = get_json_from_stream(&mut socket);
@@ -433,6 +928,14 @@ mod tests {
                     let json_data = json.unwrap();
                     //dbg!(&json_data);
 
+                    // payloads are received in order, the last one telling how many were sent
+                    nb_received += 1;
+                    assert_eq!(json_data.seq, nb_received);
+                    if let Some(end_of_run) = json_data.end_of_run {
+                        assert_eq!(end_of_run.sent, nb_received - 1);
+                        break;
+                    }
+
                     match json_data
                         .vars
                         .get("CLF_MATCHED_RE_TYPE")
@@ -485,4 +988,1432 @@ mod tests {
         let _ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&mut tag, &global);
         let _res = child.join();
     }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn lookup_timeout() {
+        use crate::misc::error::InternalError;
+
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+
+        let yaml = r#"
+            name: timeout
+            options: "rewind"
+            patterns:
+                critical: {
+                    regexes: [
+                        '^ERROR',
+                    ],
+                }
+        "#;
+        let tag = Tag::from_str(yaml).expect("unable to read YAML");
+
+        let def = LogFileDef {
+            hash_window: 4096,
+            ..Default::default()
+        };
+
+        let mut logfile = LogFile::from_path("tests/unittest/adhoc.txt", Some(def)).unwrap();
+
+        // deadline is already reached: nothing is read
+        logfile.deadline = Some(std::time::Instant::now());
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(
+            matches!(ret.unwrap_err().error_kind, InternalError::Custom(x) if x == AppCustomErrorKind::SearchTimeout)
+        );
+        assert_eq!(
+            logfile.run_data.get("timeout").map_or(0, |r| r.last_line),
+            0
+        );
+
+        // no deadline: the whole file is read
+        logfile.deadline = None;
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        assert_ne!(logfile.run_data.get("timeout").unwrap().last_line, 0);
+    }
+
+    #[test]
+    fn lookup_slices() {
+        use crate::logfile::lookup::FullReader;
+
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+
+        let yaml = r#"
+            name: sliced
+            options: "rewind"
+            patterns:
+                critical: {
+                    regexes: [
+                        '^ERROR',
+                    ],
+                }
+        "#;
+        let tags = vec![Tag::from_str(yaml).expect("unable to read YAML")];
+
+        let def = LogFileDef {
+            hash_window: 4096,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path("tests/unittest/adhoc.txt", Some(def)).unwrap();
+        logfile.quantum = Some(Quantum { lines: 2, bytes: 0 });
+
+        // the 6 lines are read 2 at a time, and counters are kept from one slice to the next. EOF is found by the
+        // last slice
+        let mut children = Vec::new();
+        logfile.lookup_tags::<FullReader>(&global, &tags, &mut children);
+        for (last_line, critical_count) in [(2, 2), (4, 4), (6, 5)] {
+            assert!(logfile.is_sliced(&tags));
+            let run_data = logfile.run_data.get("sliced").unwrap();
+            assert_eq!(run_data.last_line, last_line);
+            assert_eq!(run_data.counters.critical_count, critical_count);
+            logfile.resume_tags::<FullReader>(&global, &tags, &mut children);
+        }
+
+        assert!(!logfile.is_sliced(&tags));
+        let run_data = logfile.run_data.get("sliced").unwrap();
+        assert_eq!(run_data.start_line, 0);
+        assert_eq!(run_data.last_line, 6);
+        assert_eq!(run_data.counters.critical_count, 5);
+
+        // a new run starts from the beginning, as the tag rewinds
+        logfile.lookup_tags::<FullReader>(&global, &tags, &mut children);
+        assert_eq!(logfile.run_data.get("sliced").unwrap().last_line, 2);
+    }
+
+    #[test]
+    fn lookup_stream() {
+        use crate::logfile::lookup::{FullReader, Lookup};
+        use crate::logfile::stdin::StreamBuffer;
+        use std::time::Duration;
+
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+        let yaml = r#"
+            name: stream
+            patterns:
+                critical: { regexes: ['^ERROR'] }
+        "#;
+        let tags = vec![Tag::from_str(yaml).expect("unable to read YAML")];
+
+        let def = LogFileDef {
+            path: LogSource::LogFile(std::path::PathBuf::from("-")),
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_source(def);
+        assert!(!logfile.hash_been_rotated().unwrap());
+
+        // the end of the lines read so far is the end of a slice while the standard input is open
+        let stream = StreamBuffer::new();
+        stream.fill(&b"ERROR 1\nINFO\n"[..]);
+        let (reader, _) = stream.reader(0, Duration::ZERO);
+        logfile.stream_open = true;
+        Lookup::<FullReader>::reader(&mut logfile, reader, &tags[0], &global).unwrap();
+        assert!(logfile.is_sliced(&tags));
+        let run_data = &logfile.run_data["stream"];
+        assert_eq!(run_data.last_offset, 13);
+        assert_eq!(run_data.counters.critical_count, 1);
+
+        // counters are kept up to the end of the standard input
+        let stream = StreamBuffer::new();
+        stream.fill(&b"ERROR 1\nINFO\nERROR 2\n"[..]);
+        let (reader, _) = stream.reader(13, Duration::ZERO);
+        logfile.stream_open = false;
+        Lookup::<FullReader>::reader(&mut logfile, reader, &tags[0], &global).unwrap();
+        assert!(!logfile.is_sliced(&tags));
+        let run_data = &logfile.run_data["stream"];
+        assert_eq!(run_data.last_offset, 21);
+        assert_eq!(run_data.last_line, 3);
+        assert_eq!(run_data.counters.critical_count, 2);
+    }
+
+    #[test]
+    fn lookup_checkpoint() {
+        use crate::logfile::progress::ProgressFile;
+
+        let global = GlobalOptions::from_str("snapshot_checkpoint: { lines: 4, seconds: 0 }")
+            .expect("unable to read YAML");
+
+        let yaml = r#"
+            name: checkpointed
+            patterns:
+                critical: {
+                    regexes: [
+                        '^ERROR',
+                    ],
+                }
+        "#;
+        let tag = Tag::from_str(yaml).expect("unable to read YAML");
+
+        let def = LogFileDef {
+            hash_window: 4096,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path("tests/unittest/adhoc.txt", Some(def)).unwrap();
+        let progress = Arc::new(ProgressFile::new(
+            &std::env::temp_dir().join("clf_lookup_checkpoint.json"),
+        ));
+        progress.clear().unwrap();
+        logfile.progress = Some(Arc::clone(&progress));
+
+        // the 6 lines are read at once, with a checkpoint after the 4th one
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        assert_eq!(logfile.run_data["checkpointed"].last_line, 6);
+
+        let entries = progress.load().unwrap();
+        let reached = &entries[Path::new("tests/unittest/adhoc.txt")].run_data["checkpointed"];
+        assert_eq!(reached.last_line, 4);
+        assert_eq!(reached.counters.critical_count, 4);
+        assert!(reached.last_offset < logfile.run_data["checkpointed"].last_offset);
+
+        progress.clear().unwrap();
+    }
+
+    #[test]
+    fn lookup_checkpoint_dry_run() {
+        use crate::logfile::progress::ProgressFile;
+
+        let global = GlobalOptions::from_str("snapshot_checkpoint: { lines: 1, seconds: 0 }")
+            .expect("unable to read YAML");
+
+        let yaml = r#"
+            name: checkpointed
+            patterns:
+                critical: {
+                    regexes: [
+                        '^ERROR',
+                    ],
+                }
+        "#;
+        let tag = Tag::from_str(yaml).expect("unable to read YAML");
+
+        let def = LogFileDef {
+            hash_window: 4096,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path("tests/unittest/adhoc.txt", Some(def)).unwrap();
+        let progress = Arc::new(ProgressFile::new(
+            &std::env::temp_dir().join("clf_lookup_checkpoint_dry_run.json"),
+        ));
+        progress.clear().unwrap();
+        logfile.progress = Some(Arc::clone(&progress));
+        logfile.dry_run = true;
+
+        // a dry run leaves no progress file which the next run would resume from
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        assert_eq!(logfile.run_data["checkpointed"].last_line, 6);
+        assert!(!progress.path().exists());
+    }
+
+    #[test]
+    #[cfg(all(feature = "sockets", target_family = "unix"))]
+    fn lookup_dedup() {
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+
+        // listen on a free port, given to the callback
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+
+        let yaml = format!(
+            r#"
+            name: dedup
+            options: "runcallback,rewind"
+            callback: {{
+                address: "{}",
+            }}
+            patterns:
+                critical: {{
+                    regexes: [
+                        '^ERROR: opening file "([a-z0-9/]*)" from node ([\w\.]+), error = (\d)',
+                    ],
+                }}
+                warning: {{
+                    regexes: [
+                        '^WARNING: opening file "([a-z0-9/]*)" from node ([\w\.]+), error = (\d)',
+                    ],
+                }}
+            dedup:
+                captures: ["2"]
+                close_after: 1
+        "#,
+            listener.local_addr().unwrap()
+        );
+        let tag = Tag::from_str(&yaml).expect("unable to read YAML");
+
+        let def = LogFileDef {
+            hash_window: 4096,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path("tests/unittest/adhoc.txt", Some(def)).unwrap();
+
+        // a TCP server just reading all data sent
+        std::thread::spawn(move || {
+            for mut socket in listener.incoming().flatten() {
+                while get_json_from_stream(&mut socket).is_ok() {}
+            }
+        });
+
+        // only one callback call per node and pattern type
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        let run_data = logfile.run_data.get("dedup").unwrap();
+        assert_eq!(run_data.counters.exec_count, 3);
+        assert_eq!(run_data.incidents.len(), 3);
+
+        // incidents are still open for the next run: no callback is called
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        let run_data = logfile.run_data.get("dedup").unwrap();
+        assert_eq!(run_data.counters.exec_count, 0);
+        assert_eq!(run_data.counters.critical_count, 5);
+        assert_eq!(run_data.incidents.len(), 3);
+    }
+
+    #[test]
+    #[cfg(all(feature = "sockets", target_family = "unix"))]
+    fn lookup_pending_cap() {
+        let global = GlobalOptions::from_str("script_path: /usr/bin\nmax_pending_callbacks: 2")
+            .expect("unable to read YAML");
+
+        // listen on a free port, given to the callback
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let yaml = format!(
+            r#"
+            name: capped
+            options: "runcallback,rewind"
+            callback: {{
+                address: "{}",
+                retries: 0,
+            }}
+            patterns:
+                critical: {{
+                    regexes: [
+                        '^ERROR',
+                    ],
+                }}
+        "#,
+            addr
+        );
+        let tag = Tag::from_str(&yaml).expect("unable to read YAML");
+
+        let def = LogFileDef {
+            hash_window: 4096,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path("tests/unittest/adhoc.txt", Some(def)).unwrap();
+
+        // a TCP server just reading all data sent, for a single connection
+        let server = std::thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            while get_json_from_stream(&mut socket).is_ok() {}
+        });
+
+        // more matches than the cap: they're all notified
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        let run_data = logfile.run_data.get("capped").unwrap();
+        assert_eq!(run_data.counters.critical_count, 5);
+        assert_eq!(run_data.counters.exec_count, 5);
+        assert!(run_data.pending_callbacks.is_empty());
+        server.join().unwrap();
+
+        // the receiver is down: only the last calls are kept for the next run
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_err());
+        let run_data = logfile.run_data.get("capped").unwrap();
+        assert_eq!(run_data.counters.exec_count, 0);
+        let line_numbers: Vec<_> = run_data
+            .pending_callbacks
+            .iter()
+            .map(|callback| callback.vars["CLF_LINE_NUMBER"].to_string())
+            .collect();
+        assert_eq!(line_numbers, vec!["4", "5"]);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn lookup_container() {
+        use std::io::Write;
+
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+
+        let yaml = r#"
+            name: cri
+            patterns:
+                critical: {
+                    regexes: [
+                        '^ERROR: disk full$',
+                    ],
+                }
+        "#;
+        let tag = Tag::from_str(yaml).expect("unable to read YAML");
+
+        // the last message is not complete yet
+        let path = std::env::temp_dir().join("clf_lookup_container.log");
+        let complete = "2021-01-28T18:49:42.1Z stdout F INFO: starting\n\
+                        2021-01-28T18:49:42.2Z stderr P ERROR: disk \n\
+                        2021-01-28T18:49:42.3Z stderr F full\n";
+        let partial = "2021-01-28T18:49:42.4Z stderr P ERROR: disk \n";
+        std::fs::write(&path, format!("{}{}", complete, partial)).unwrap();
+
+        let def = LogFileDef {
+            hash_window: 4096,
+            container_format: Some(crate::logfile::container::ContainerFormat::cri),
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path(&path, Some(def)).unwrap();
+
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        let run_data = logfile.run_data.get("cri").unwrap();
+        assert_eq!(run_data.counters.critical_count, 1);
+        assert_eq!(run_data.last_line, 3);
+        assert_eq!(run_data.last_offset, complete.len() as u64);
+
+        // the message is now complete: it's read from its first part
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"2021-01-28T18:49:42.5Z stderr F full\n")
+            .unwrap();
+
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        let run_data = logfile.run_data.get("cri").unwrap();
+        assert_eq!(run_data.counters.critical_count, 1);
+        assert_eq!(run_data.last_line, 5);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    #[cfg(all(feature = "sockets", target_family = "unix"))]
+    fn lookup_json() {
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+
+        // listen on a free port, given to the callback
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+
+        let yaml = format!(
+            r#"
+            name: json
+            options: "runcallback"
+            callback: {{
+                address: "{}",
+            }}
+            patterns:
+                critical: {{
+                    field: level,
+                    regexes: [
+                        '^ERROR$',
+                    ],
+                }}
+                warning: {{
+                    field: http.status,
+                    regexes: [
+                        '^5(\d\d)$',
+                    ],
+                }}
+        "#,
+            listener.local_addr().unwrap()
+        );
+        let tag = Tag::from_str(&yaml).expect("unable to read YAML");
+
+        // the message holds ERROR but only the level field is matched
+        let path = std::env::temp_dir().join("clf_lookup_json.log");
+        std::fs::write(
+            &path,
+            r#"{"level":"INFO","msg":"ERROR count is 0"}
+{"level":"ERROR","msg":"disk full","host":"server01"}
+{"level":"INFO","msg":"request","http":{"status":503}}
+not a JSON line ERROR
+"#,
+        )
+        .unwrap();
+
+        let def = LogFileDef {
+            hash_window: 4096,
+            format: crate::configuration::logfiledef::LogFileFormat::json,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path(&path, Some(def)).unwrap();
+
+        // a TCP server sending back the variables received
+        let server = std::thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            while let Ok(json) = get_json_from_stream(&mut socket) {
+                if json.end_of_run.is_some() {
+                    break;
+                }
+                received.push(json.vars);
+            }
+            received
+        });
+
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        let run_data = logfile.run_data.get("json").unwrap();
+        assert_eq!(run_data.counters.critical_count, 1);
+        assert_eq!(run_data.counters.warning_count, 1);
+
+        // all fields are given, and capture groups are taken from the field matched
+        let received = server.join().unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0]["CLF_JSON_msg"].to_string(), "disk full");
+        assert_eq!(received[0]["CLF_JSON_host"].to_string(), "server01");
+        assert_eq!(received[1]["CLF_JSON_http.status"].to_string(), "503");
+        assert_eq!(received[1]["CLF_CG_1"].to_string(), "03");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    #[cfg(all(feature = "sockets", target_family = "unix"))]
+    fn lookup_dup_window() {
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+
+        // listen on a free port, given to the callback
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+
+        let yaml = format!(
+            r#"
+            name: dup
+            options: "runcallback,dedup=3lines,dedup_captures"
+            callback: {{
+                address: "{}",
+            }}
+            patterns:
+                critical: {{
+                    regexes: [
+                        '^ERROR on (\w+)',
+                    ],
+                }}
+        "#,
+            listener.local_addr().unwrap()
+        );
+        let tag = Tag::from_str(&yaml).expect("unable to read YAML");
+
+        // the 4th line is out of the window of the first one
+        let path = std::env::temp_dir().join("clf_lookup_dup_window.log");
+        std::fs::write(
+            &path,
+            "ERROR on node1 at 10:00\nERROR on node1 at 10:01\nERROR on node1 at 10:02\nERROR on node1 at 10:03\nERROR on node2 at 10:04\n",
+        )
+        .unwrap();
+
+        let def = LogFileDef {
+            hash_window: 64,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path(&path, Some(def)).unwrap();
+
+        // a TCP server sending back the variables received
+        let server = std::thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            while let Ok(json) = get_json_from_stream(&mut socket) {
+                if json.end_of_run.is_some() {
+                    break;
+                }
+                received.push(json.vars);
+            }
+            received
+        });
+
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        let run_data = logfile.run_data.get("dup").unwrap();
+        assert_eq!(run_data.counters.critical_count, 5);
+        assert_eq!(run_data.counters.dup_count, 2);
+        assert_eq!(run_data.counters.exec_count, 3);
+
+        // duplicates are counted in the callback of the first occurrence
+        let received = server.join().unwrap();
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[0]["CLF_DUP_COUNT"].to_string(), "2");
+        assert_eq!(received[1]["CLF_LINE_NUMBER"].to_string(), "4");
+        assert_eq!(received[1]["CLF_DUP_COUNT"].to_string(), "0");
+        assert_eq!(received[2]["CLF_CG_1"].to_string(), "node2");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn lookup_line_stats() {
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+
+        let yaml = r#"
+            name: stats
+            options: "rewind"
+            line_stats: true
+            patterns:
+                critical: {
+                    regexes: [
+                        '^ERROR',
+                    ],
+                }
+                ok: {
+                    regexes: [
+                        '^OK',
+                    ],
+                }
+        "#;
+        let mut tag = Tag::from_str(yaml).expect("unable to read YAML");
+
+        let path = std::env::temp_dir().join("clf_lookup_line_stats.log");
+        std::fs::write(
+            &path,
+            "# comment\nERROR one\nINFO\nOK\n# ERROR\nERROR two\n",
+        )
+        .unwrap();
+
+        let def = LogFileDef {
+            hash_window: 4096,
+            exclude: Some(regex::Regex::new("^#").unwrap()),
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path(&path, Some(def)).unwrap();
+
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        let stats = logfile.run_data.get("stats").unwrap().line_stats.clone();
+        assert_eq!(
+            stats,
+            Some(crate::logfile::rundata::LineStats {
+                evaluated: 6,
+                matched: 3,
+                excluded: 2
+            })
+        );
+
+        // counters are not kept without the option
+        tag.line_stats = false;
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        assert!(logfile.run_data.get("stats").unwrap().line_stats.is_none());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn lookup_max_line_length() {
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+        let tag = Tag::from_str("name: length\npatterns:\n  critical: {regexes: ['^ERROR']}")
+            .expect("unable to read YAML");
+
+        let path = std::env::temp_dir().join("clf_lookup_max_line_length.log");
+        std::fs::write(&path, format!("ERROR one\n{}\n", "x".repeat(3000))).unwrap();
+
+        let def = LogFileDef {
+            hash_window: 4096,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path(&path, Some(def)).unwrap();
+
+        // the longest line is kept, and halved at each run without longer lines
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        assert_eq!(logfile.run_data["length"].max_line_length, 3001);
+        assert_eq!(logfile.run_data["length"].buffer_capacity(), 4096);
+
+        std::fs::write(&path, format!("ERROR one\n{}\nINFO\n", "x".repeat(3000))).unwrap();
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        assert_eq!(logfile.run_data["length"].max_line_length, 1500);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn lookup_rewind_savethresholds() {
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+
+        let yaml = r#"
+            name: saved
+            options: "rewind,savethresholds"
+            patterns:
+                critical: {
+                    regexes: [
+                        '^ERROR',
+                    ],
+                }
+        "#;
+        let tag = Tag::from_str(yaml).expect("unable to read YAML");
+
+        let path = std::env::temp_dir().join("clf_lookup_rewind_savethresholds.log");
+        std::fs::write(&path, "ERROR one\nINFO\nERROR two\n").unwrap();
+
+        let def = LogFileDef {
+            hash_window: 4096,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path(&path, Some(def)).unwrap();
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        assert_eq!(logfile.run_data["saved"].counters.critical_count, 2);
+
+        // the whole logfile is read again, but only new matches are counted
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        assert_eq!(logfile.run_data["saved"].counters.critical_count, 2);
+
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        std::io::Write::write_all(&mut file, b"ERROR three\n").unwrap();
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        let run_data = &logfile.run_data["saved"];
+        assert_eq!(run_data.counters.critical_count, 3);
+        assert_eq!(run_data.counted_offset, run_data.last_offset);
+
+        // a truncated logfile is counted again
+        std::fs::write(&path, "ERROR four\n").unwrap();
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        assert_eq!(logfile.run_data["saved"].counters.critical_count, 4);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn shrink_policy() {
+        use crate::configuration::logfiledef::ShrinkPolicy;
+
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+        let tag = Tag::from_str("name: shrunk\npatterns:\n  critical: { regexes: ['^ERROR'] }")
+            .expect("unable to read YAML");
+
+        let path = std::env::temp_dir().join("clf_shrink_policy.log");
+        std::fs::write(&path, "INFO one\nINFO two\nERROR three\nINFO four\n").unwrap();
+
+        let def = LogFileDef {
+            hash_window: 8,
+            shrink_policy: ShrinkPolicy::rescan_tail,
+            shrink_window: 1024,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path(&path, Some(def)).unwrap();
+        logfile
+            .lookup::<crate::logfile::lookup::FullReader>(&tag, &global)
+            .unwrap();
+        assert_eq!(logfile.run_data["shrunk"].counters.critical_count, 1);
+        assert!(!logfile.relocate_after_shrink().unwrap());
+
+        // the first lines are removed in place, and a new one is written
+        std::fs::write(&path, "ERROR three\nINFO four\nERROR five\n").unwrap();
+        assert!(logfile.relocate_after_shrink().unwrap());
+        assert!(!logfile.hash_been_rotated().unwrap());
+        let run_data = &logfile.run_data["shrunk"];
+        assert_eq!((run_data.last_offset, run_data.last_line), (22, 2));
+
+        logfile
+            .lookup::<crate::logfile::lookup::FullReader>(&tag, &global)
+            .unwrap();
+        assert_eq!(logfile.run_data["shrunk"].counters.critical_count, 1);
+        assert_eq!(logfile.run_data["shrunk"].last_line, 3);
+
+        // the last line is not found anymore: the logfile is handled like a rotated one
+        std::fs::write(&path, "ERROR six\n").unwrap();
+        assert!(!logfile.relocate_after_shrink().unwrap());
+        assert!(logfile.hash_been_rotated().unwrap());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn lookup_rewindlimit() {
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+
+        let yaml = r#"
+            name: bounded
+            options: "rewind,rewindlimit=2lines"
+            patterns:
+                critical: {
+                    regexes: [
+                        '^ERROR',
+                    ],
+                }
+        "#;
+        let tag = Tag::from_str(yaml).expect("unable to read YAML");
+
+        let path = std::env::temp_dir().join("clf_lookup_rewindlimit.log");
+        std::fs::write(&path, "ERROR one\nINFO\nERROR two\n").unwrap();
+
+        let def = LogFileDef {
+            hash_window: 4096,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path(&path, Some(def)).unwrap();
+
+        // only the last 2 lines are read, but line numbers are those of the logfile
+        for _ in 0..2 {
+            let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+            assert!(ret.is_ok());
+            let run_data = &logfile.run_data["bounded"];
+            assert_eq!(run_data.counters.critical_count, 1);
+            assert_eq!(run_data.start_offset, 10);
+            assert_eq!(run_data.start_line, 1);
+            assert_eq!(run_data.last_line, 3);
+        }
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn lookup_multiline() {
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+
+        let path = std::env::temp_dir().join("clf_lookup_multiline.log");
+        std::fs::write(
+            &path,
+            "2021-01-28 ERROR NullPointerException\n    at com.example.A.run(A.java:10)\n    at com.example.Main.main(Main.java:5)\n2021-01-28 INFO started\n2021-01-28 WARN slow\n    at com.example.B.run(B.java:3)\n",
+        )
+        .unwrap();
+
+        for (timeout, warning_count, last_line) in [(0, 1, 6), (3600, 0, 4)] {
+            let yaml = format!(
+                r#"
+                name: java
+                multiline:
+                    start: '^\d{{4}}-\d{{2}}-\d{{2}} '
+                    timeout: {}
+                patterns:
+                    critical: {{
+                        regexes: [
+                            'ERROR(?s:.*)Main\.java',
+                        ],
+                    }}
+                    warning: {{
+                        regexes: [
+                            '^\S+ WARN',
+                        ],
+                    }}
+                "#,
+                timeout
+            );
+            let tag = Tag::from_str(&yaml).expect("unable to read YAML");
+
+            // the last record is complete at the end of the logfile, unless it could still be written
+            let def = LogFileDef {
+                hash_window: 4096,
+                ..Default::default()
+            };
+            let mut logfile = LogFile::from_path(&path, Some(def)).unwrap();
+            let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+            assert!(ret.is_ok());
+            let run_data = &logfile.run_data["java"];
+            assert_eq!(run_data.counters.critical_count, 1);
+            assert_eq!(run_data.counters.warning_count, warning_count);
+            assert_eq!(run_data.last_line, last_line);
+        }
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn lookup_utf16() {
+        use crate::configuration::logfiledef::Encoding;
+
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+        let yaml = r#"
+            name: utf16
+            patterns:
+                critical: {
+                    regexes: [
+                        '^ERROR café',
+                        'naïve$',
+                    ],
+                }
+        "#;
+        let tag = Tag::from_str(yaml).expect("unable to read YAML");
+
+        // a logfile with a byte order mark
+        let text = "\u{FEFF}ERROR café\nINFO\nWARN naïve\n";
+        for encoding in [Encoding::utf16le, Encoding::utf16be] {
+            let bytes: Vec<u8> = text
+                .encode_utf16()
+                .flat_map(|u| match encoding {
+                    Encoding::utf16le => u.to_le_bytes(),
+                    _ => u.to_be_bytes(),
+                })
+                .collect();
+            let path = std::env::temp_dir().join("clf_lookup_utf16.log");
+            std::fs::write(&path, &bytes).unwrap();
+
+            let def = LogFileDef {
+                hash_window: 4,
+                encoding: Some(encoding),
+                ..Default::default()
+            };
+            let mut logfile = LogFile::from_path(&path, Some(def)).unwrap();
+            let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+            assert!(ret.is_ok());
+            let run_data = &logfile.run_data["utf16"];
+            assert_eq!(run_data.counters.critical_count, 2);
+            assert_eq!(run_data.last_line, 3);
+            assert_eq!(run_data.last_offset, bytes.len() as u64);
+
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+
+    #[test]
+    fn lookup_prefilter() {
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+
+        let path = std::env::temp_dir().join("clf_lookup_prefilter.log");
+        std::fs::write(
+            &path,
+            "ERROR disk full\nINFO started\nFATAL disk full\nWARN error 42\n",
+        )
+        .unwrap();
+
+        // literals are derived from the regexes
+        let yaml = r#"
+            name: derived
+            line_stats: true
+            patterns:
+                critical: { regexes: ['^(ERROR|FATAL) disk'] }
+                warning: { regexes: ['error \d+'] }
+        "#;
+        let tag = Tag::from_str(yaml).expect("unable to read YAML");
+        assert_eq!(
+            tag.patterns.prefilter().unwrap().literals(),
+            vec![" disk", "error "]
+        );
+        assert!(!tag.is_candidate("INFO started"));
+
+        // a prefilter from the configuration is used instead, even if it skips matching lines
+        let yaml = r#"
+            name: configured
+            line_stats: true
+            prefilter: ["FATAL", "WARN"]
+            patterns:
+                critical: { regexes: ['^(ERROR|FATAL) disk'] }
+                warning: { regexes: ['error \d+'] }
+        "#;
+        let configured = Tag::from_str(yaml).expect("unable to read YAML");
+
+        let def = LogFileDef {
+            hash_window: 4,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path(&path, Some(def)).unwrap();
+        for tag in [&tag, &configured] {
+            logfile
+                .lookup::<crate::logfile::lookup::FullReader>(tag, &global)
+                .unwrap();
+        }
+
+        let run_data = &logfile.run_data["derived"];
+        assert_eq!(run_data.counters.critical_count, 2);
+        assert_eq!(run_data.counters.warning_count, 1);
+        assert_eq!(run_data.line_stats.as_ref().unwrap().evaluated, 4);
+
+        let run_data = &logfile.run_data["configured"];
+        assert_eq!(run_data.counters.critical_count, 1);
+        assert_eq!(run_data.counters.warning_count, 1);
+        assert_eq!(run_data.last_line, 4);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn lookup_overlap() {
+        let global = GlobalOptions::from_str(
+            "script_path: /usr/bin\noverlap_check:\n  ratio: 0.6\n  min_lines: 2",
+        )
+        .expect("unable to read YAML");
+
+        let path = std::env::temp_dir().join("clf_lookup_overlap.log");
+        std::fs::write(
+            &path,
+            "ERROR disk full\nERROR disk full\nERROR timeout\nWARN slow\nERROR timeout\n",
+        )
+        .unwrap();
+
+        let tags: Vec<Tag> = ["error: '^ERROR'", "disk: 'disk'", "timeout: 'timeout'"]
+            .iter()
+            .map(|def| {
+                let (name, re) = def.split_once(": ").unwrap();
+                Tag::from_str(&format!(
+                    "name: {}\npatterns:\n  critical: {{ regexes: [{}] }}",
+                    name, re
+                ))
+                .unwrap()
+            })
+            .collect();
+
+        let def = LogFileDef {
+            hash_window: 4096,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path(&path, Some(def)).unwrap();
+        logfile.lookup_tags::<crate::logfile::lookup::FullReader>(&global, &tags, &mut Vec::new());
+
+        // both disk & timeout lines are also matched by the error tag
+        assert_eq!(
+            logfile.overlaps,
+            vec![
+                TagOverlap {
+                    tags: ["disk".to_string(), "error".to_string()],
+                    lines: 2,
+                    ratio: 1.0
+                },
+                TagOverlap {
+                    tags: ["error".to_string(), "timeout".to_string()],
+                    lines: 2,
+                    ratio: 1.0
+                },
+            ]
+        );
+        assert_eq!(common_lines(&[1, 2, 5, 8], &[2, 3, 8, 9]), 2);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn lookup_suppressed() {
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+
+        let path = std::env::temp_dir().join("clf_lookup_suppressed.log");
+        std::fs::write(
+            &path,
+            "ERROR disk full\nERROR test disk full\nWARN test error 42\nINFO test ok\n",
+        )
+        .unwrap();
+
+        let yaml = r#"
+            name: suppressed
+            count_suppressed: true
+            patterns:
+                critical: { regexes: ['^ERROR'] }
+                warning: { regexes: ['^WARN'] }
+                ok: { regexes: ['ok$'] }
+        "#;
+        let tag = Tag::from_str(yaml).expect("unable to read YAML");
+
+        let def = LogFileDef {
+            exclude: Some(regex::Regex::new("test").unwrap()),
+            hash_window: 4,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path(&path, Some(def)).unwrap();
+        logfile
+            .lookup::<crate::logfile::lookup::FullReader>(&tag, &global)
+            .unwrap();
+
+        // excluded ok matches are not counted
+        let counters = &logfile.run_data["suppressed"].counters;
+        assert_eq!(counters.critical_count, 1);
+        assert_eq!(counters.warning_count, 0);
+        assert_eq!(counters.suppressed_count, 2);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn lookup_rawline() {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+        let path = std::env::temp_dir().join("clf_lookup_rawline.log");
+        std::fs::write(&path, b"ERROR caf\xe9 closed\nINFO\n").unwrap();
+
+        let yaml = r#"
+            name: raw
+            options: "runcallback, rawline"
+            patterns:
+                critical: { regexes: ['^ERROR'] }
+        "#;
+        let tag = Tag::from_str(yaml).expect("unable to read YAML");
+
+        let def = LogFileDef {
+            hash_window: 4,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path(&path, Some(def)).unwrap();
+        logfile.dry_run = true;
+        logfile
+            .lookup::<crate::logfile::lookup::FullReader>(&tag, &global)
+            .unwrap();
+
+        // the lossy line is given along with the original bytes
+        let vars = &logfile.run_data["raw"].pending_callbacks[0].vars;
+        assert!(vars["CLF_LINE"].to_string().contains('\u{FFFD}'));
+        assert_eq!(
+            STANDARD.decode(vars["CLF_LINE_RAW"].to_string()).unwrap(),
+            b"ERROR caf\xe9 closed"
+        );
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn lookup_timestamp() {
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+        let path = std::env::temp_dir().join("clf_lookup_timestamp.log");
+        let now = crate::misc::util::from_epoch_secs().unwrap();
+        std::fs::write(
+            &path,
+            format!(
+                "{} ERROR old\n{} ERROR recent\nERROR without time\n",
+                now - 3600,
+                now - 60
+            ),
+        )
+        .unwrap();
+
+        let yaml = r#"
+            name: recent
+            options: "runcallback"
+            timestamp:
+                regex: '^\d+'
+                format: '%s'
+                window: 900
+            patterns:
+                critical: { regexes: ['ERROR'] }
+        "#;
+        let tag = Tag::from_str(yaml).expect("unable to read YAML");
+
+        let def = LogFileDef {
+            hash_window: 4,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path(&path, Some(def)).unwrap();
+        logfile.dry_run = true;
+        logfile
+            .lookup::<crate::logfile::lookup::FullReader>(&tag, &global)
+            .unwrap();
+
+        // the old line is not counted, unlike the line without a timestamp
+        let run_data = &logfile.run_data["recent"];
+        assert_eq!(run_data.counters.critical_count, 2);
+        let vars = &run_data.pending_callbacks[0].vars;
+        assert_eq!(vars["CLF_LINE_NUMBER"].to_string(), "2");
+        assert_eq!(
+            vars["CLF_TIMESTAMP_PARSED"].to_string(),
+            (now - 60).to_string()
+        );
+        assert!(!run_data.pending_callbacks[1]
+            .vars
+            .contains_key("CLF_TIMESTAMP_PARSED"));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn lookup_requirements() {
+        use std::io::Write;
+
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+        let path = std::env::temp_dir().join("clf_lookup_requirements.log");
+        std::fs::write(
+            &path,
+            "BEGIN\nERROR a\nROLLBACK\nfoo\nfoo\nERROR b\nBEGIN\nERROR c\nROLLBACK\nBEGIN\nERROR d\n",
+        )
+        .unwrap();
+
+        let yaml = r#"
+            name: rollback
+            options: "runcallback"
+            requires_before: { regex: '^BEGIN', lines: 2 }
+            requires_after: { regex: '^ROLLBACK' }
+            patterns:
+                critical: { regexes: ['^ERROR'] }
+        "#;
+        let tag = Tag::from_str(yaml).expect("unable to read YAML");
+
+        let def = LogFileDef {
+            hash_window: 4,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path(&path, Some(def)).unwrap();
+        logfile.dry_run = true;
+        logfile
+            .lookup::<crate::logfile::lookup::FullReader>(&tag, &global)
+            .unwrap();
+
+        // no transaction before the second error. The last one could still be followed by a rollback: it's read
+        // again at next run
+        let run_data = &logfile.run_data["rollback"];
+        assert_eq!(run_data.counters.critical_count, 2);
+        let line_numbers: Vec<_> = run_data
+            .pending_callbacks
+            .iter()
+            .map(|callback| callback.vars["CLF_LINE_NUMBER"].to_string())
+            .collect();
+        assert_eq!(line_numbers, vec!["2", "8"]);
+        assert_eq!(run_data.last_line, 10);
+        assert_eq!(run_data.required_line, Some(10));
+
+        // the transaction started at the previous run
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"ROLLBACK\n")
+            .unwrap();
+        logfile
+            .lookup::<crate::logfile::lookup::FullReader>(&tag, &global)
+            .unwrap();
+        let run_data = &logfile.run_data["rollback"];
+        assert_eq!(run_data.counters.critical_count, 1);
+        let callback = run_data.pending_callbacks.last().unwrap();
+        assert_eq!(callback.vars["CLF_LINE_NUMBER"].to_string(), "11");
+        assert_eq!(run_data.last_line, 11);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn lookup_sentinel() {
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+        let path = std::env::temp_dir().join("clf_lookup_sentinel.log");
+        std::fs::write(
+            &path,
+            "INFO\nKernel panic - not syncing\nKernel panic again\n",
+        )
+        .unwrap();
+
+        // the threshold is never reached, but the sentinel match is kept
+        let yaml = r#"
+            name: panic
+            sentinel: true
+            options: "criticalthreshold=10"
+            patterns:
+                critical: { regexes: ['^Kernel panic'] }
+        "#;
+        let tag = Tag::from_str(yaml).expect("unable to read YAML");
+
+        let def = LogFileDef {
+            hash_window: 4,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path(&path, Some(def)).unwrap();
+        logfile
+            .lookup::<crate::logfile::lookup::FullReader>(&tag, &global)
+            .unwrap();
+        assert_eq!(
+            logfile.run_data["panic"].sentinel_line.as_deref(),
+            Some("Kernel panic - not syncing")
+        );
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn lookup_journal() {
+        use crate::configuration::logsource::{JournalFilter, JournalSource};
+        use crate::logfile::lookup::{FullReader, Lookup};
+
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+        let yaml = r#"
+            name: sshd
+            patterns:
+                critical: {
+                    regexes: [
+                        '^Failed password',
+                    ],
+                }
+        "#;
+        let tag = Tag::from_str(yaml).expect("unable to read YAML");
+
+        let def = LogFileDef {
+            path: LogSource::Journal(JournalSource::from(JournalFilter::default())),
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_source(def);
+        assert_eq!(logfile.id.canon_path, std::path::PathBuf::from("journal"));
+        assert!(!logfile.hash_been_rotated().unwrap());
+
+        let entries = r#"{"__CURSOR":"c1","MESSAGE":"Failed password for root"}
+{"__CURSOR":"c2","MESSAGE":"Accepted password for root"}
+"#;
+        let mut reader = JournalReader::new(entries.as_bytes());
+        let ret = Lookup::<FullReader>::reader(&mut logfile, &mut reader, &tag, &global);
+        assert!(ret.is_ok());
+        let run_data = &logfile.run_data["sshd"];
+        assert_eq!(run_data.counters.critical_count, 1);
+        assert_eq!(reader.cursor_at(run_data.last_offset), Some("c2"));
+
+        // offsets go on from the last run, the journal being read after the cursor
+        let last_offset = run_data.last_offset;
+        let entries = r#"{"__CURSOR":"c3","MESSAGE":"Failed password for admin"}
+"#;
+        let mut reader = JournalReader::new(entries.as_bytes());
+        let ret = Lookup::<FullReader>::reader(&mut logfile, &mut reader, &tag, &global);
+        assert!(ret.is_ok());
+        let run_data = &logfile.run_data["sshd"];
+        assert_eq!(run_data.counters.critical_count, 1);
+        assert_eq!(run_data.last_line, 3);
+        assert_eq!(run_data.last_offset, last_offset + 26);
+        assert_eq!(reader.cursor_at(run_data.last_offset), Some("c3"));
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn lookup_bom_header() {
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+
+        let yaml = r#"
+            name: csv
+            options: "rewind"
+            patterns:
+                critical: {
+                    regexes: [
+                        '^ERROR',
+                    ],
+                }
+        "#;
+        let tag = Tag::from_str(yaml).expect("unable to read YAML");
+
+        let path = std::env::temp_dir().join("clf_lookup_bom_header.log");
+        let data = "\u{FEFF}ERROR,header\nINFO,one\nERROR,two\n";
+        std::fs::write(&path, data).unwrap();
+
+        // the BOM is stripped before matching, but offsets include it
+        let def = LogFileDef {
+            hash_window: 4096,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path(&path, Some(def.clone())).unwrap();
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        let run_data = logfile.run_data.get("csv").unwrap();
+        assert_eq!(run_data.counters.critical_count, 2);
+        assert_eq!(run_data.last_offset, data.len() as u64);
+
+        // the header line is not matched
+        let def = LogFileDef {
+            skip_header_lines: 1,
+            ..def
+        };
+        let mut logfile = LogFile::from_path(&path, Some(def)).unwrap();
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        let run_data = logfile.run_data.get("csv").unwrap();
+        assert_eq!(run_data.counters.critical_count, 1);
+        assert_eq!(run_data.last_line, 3);
+        assert_eq!(run_data.last_offset, data.len() as u64);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn lookup_nul_bytes() {
+        use std::io::Write;
+
+        let global = GlobalOptions::from_str("script_path: /usr/bin").expect("unable to read YAML");
+
+        let yaml = r#"
+            name: nul
+            patterns:
+                critical: {
+                    regexes: [
+                        '^ERROR',
+                    ],
+                }
+        "#;
+        let tag = Tag::from_str(yaml).expect("unable to read YAML");
+
+        // a preallocated logfile: NUL bytes in a line, and NUL padding at the end
+        let path = std::env::temp_dir().join("clf_lookup_nul_bytes.log");
+        let lines = "ERROR one\n\0\0\0ERROR two\n";
+        let mut data = lines.as_bytes().to_vec();
+        data.extend(vec![0u8; 100_000]);
+        std::fs::write(&path, &data).unwrap();
+
+        let def = LogFileDef {
+            hash_window: 4096,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path(&path, Some(def)).unwrap();
+
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        let run_data = logfile.run_data.get("nul").unwrap();
+        assert_eq!(run_data.counters.critical_count, 2);
+        assert_eq!(run_data.nul_bytes, 100_003);
+
+        // the padding is not read past
+        assert_eq!(run_data.last_line, 2);
+        assert_eq!(run_data.last_offset, lines.len() as u64);
+
+        // the padding is overwritten with a new line
+        let mut file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        use std::io::{Seek, SeekFrom};
+        file.seek(SeekFrom::Start(lines.len() as u64)).unwrap();
+        file.write_all(b"ERROR three\n").unwrap();
+
+        let ret = logfile.lookup::<crate::logfile::lookup::FullReader>(&tag, &global);
+        assert!(ret.is_ok());
+        let run_data = logfile.run_data.get("nul").unwrap();
+        assert_eq!(run_data.counters.critical_count, 1);
+        assert_eq!(run_data.last_line, 3);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn hash_algorithm() {
+        use crate::misc::extension::HashAlgorithm;
+        use std::io::Write;
+
+        // 2 logfiles sharing the same header
+        let header = "# generated by app v1.0\n";
+        let path = std::env::temp_dir().join("clf_hash_algorithm.log");
+        std::fs::write(&path, format!("{}first run\n", header)).unwrap();
+
+        let def = LogFileDef {
+            hash_window: header.len(),
+            hash_algorithm: HashAlgorithm::blake3,
+            hash_tail: 8,
+            ..Default::default()
+        };
+        let mut logfile = LogFile::from_path(&path, Some(def)).unwrap();
+        let signature = logfile.id.signature.clone();
+        assert_eq!(signature.algorithm, HashAlgorithm::blake3);
+        assert_eq!(signature.tail_window, 8);
+        assert_eq!(
+            signature.hash.unwrap(),
+            HashAlgorithm::blake3.digest(header.as_bytes())
+        );
+        assert_eq!(
+            signature.tail_hash.unwrap(),
+            HashAlgorithm::blake3.digest(b"rst run\n")
+        );
+
+        // appending lines doesn't change the hashes of the saved signature
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"second run\n").unwrap();
+        assert!(!logfile.hash_been_rotated().unwrap());
+
+        // the saved algorithm is used, even if the configuration has changed
+        logfile.definition.hash_algorithm = HashAlgorithm::xxhash64;
+        assert!(!logfile.hash_been_rotated().unwrap());
+
+        // the file is replaced in place by another one with the same header: only its end tells the difference
+        std::fs::write(&path, format!("{}other app\nsecond run\n", header)).unwrap();
+        assert!(logfile.hash_been_rotated().unwrap());
+
+        // without the tail, it looks like the same file
+        logfile.id.signature.tail_hash = None;
+        assert!(!logfile.hash_been_rotated().unwrap());
+
+        let _ = std::fs::remove_file(&path);
+    }
 }
@@ -13,6 +13,7 @@ pub struct LogFileAccessError {
 }
 
 /// A list of logfile errors.
+#[derive(Debug)]
 pub struct LogFileAccessErrorList(HashMap<PathBuf, LogFileAccessError>);
 
 impl Default for LogFileAccessErrorList {
@@ -33,6 +34,11 @@ impl LogFileAccessErrorList {
     pub fn iter(&self) -> Iter<'_, PathBuf, LogFileAccessError> {
         self.0.iter()
     }
+
+    /// Adds the errors of another list, like the one of a worker.
+    pub fn merge(&mut self, other: LogFileAccessErrorList) {
+        self.0.extend(other.0);
+    }
 }
 
 impl Deref for LogFileAccessErrorList {
@@ -6,7 +6,7 @@ use serde::{Deserialize, Serialize};
 use crate::context;
 use crate::logfile::compression::CompressionScheme;
 use crate::misc::error::{AppError, AppResult};
-use crate::misc::extension::{ReadFs, Signature};
+use crate::misc::extension::{HashSpec, ReadFs, Signature};
 
 /// Logfile variable fields that change depending on the path.
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
@@ -42,7 +42,11 @@ impl LogFileID {
     }
 
     /// Update some logfile fields with up to date path values. This is used when detecting rotation for logfiles
-    pub fn update<P: AsRef<Path>>(&mut self, path: P, hash_buffer_size: usize) -> AppResult<()> {
+    pub fn update<P: AsRef<Path>, S: Into<HashSpec>>(
+        &mut self,
+        path: P,
+        hash_spec: S,
+    ) -> AppResult<()> {
         // check if we can really use the file
         self.declared_path = PathBuf::from(path.as_ref());
 
@@ -58,7 +62,7 @@ impl LogFileID {
         self.compression = CompressionScheme::from(self.extension.as_deref());
 
         // // get inode & dev ID
-        self.signature = canon.signature(hash_buffer_size)?;
+        self.signature = canon.signature(hash_spec)?;
         trace!(
             "current signature for {:?} is {:?}",
             &canon,
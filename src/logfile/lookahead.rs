@@ -0,0 +1,119 @@
+//! Lines read ahead of the line being processed, for tags with the `requires_after` option: whether a match is counted
+//! depends on the lines following it. Those lines are read and kept until the first line can be decided: either a
+//! required line follows it, or enough lines follow it without one. At the end of the logfile, lines still waiting for
+//! their following lines are not processed, so offsets stop before them and they're read again at next run.
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+
+use crate::configuration::{logfiledef::Encoding, requirement::Requirement};
+
+// a line read ahead: its bytes, the number of bytes read, NUL bytes skipped and lines of the record, and whether it's
+// a required line
+struct AheadLine {
+    buffer: Vec<u8>,
+    bytes_read: usize,
+    nul_bytes: usize,
+    lines: u64,
+    required: bool,
+}
+
+/// The lines read ahead, along with the end of the logfile once reached.
+#[derive(Default)]
+pub struct Lookahead {
+    lines: VecDeque<AheadLine>,
+    end: Option<AheadLine>,
+}
+
+impl Lookahead {
+    /// Gives the next line to process in `buffer`, like `read` does, along with whether the required line follows it.
+    /// Lines are read with `read` until this is known. When it's not known at the end of the logfile, the end is
+    /// returned with the lines kept.
+    pub fn next<F>(
+        &mut self,
+        buffer: &mut Vec<u8>,
+        requirement: &Requirement,
+        encoding: Option<Encoding>,
+        mut read: F,
+    ) -> std::io::Result<(usize, usize, u64, bool)>
+    where
+        F: FnMut(&mut Vec<u8>) -> std::io::Result<(usize, usize, u64)>,
+    {
+        // lines are only kept up to the distance of the required line
+        let window = usize::try_from(requirement.lines).unwrap_or(usize::MAX);
+
+        loop {
+            // all lines kept are within the distance of the first one
+            let required = self.lines.iter().skip(1).any(|line| line.required);
+            if required || self.lines.len() > window {
+                let line = self.lines.pop_front().unwrap();
+                buffer.extend_from_slice(&line.buffer);
+                return Ok((line.bytes_read, line.nul_bytes, line.lines, required));
+            }
+
+            // the end of the logfile is given back as it was read, once lines kept are processed
+            if let Some(end) = &self.end {
+                if self.lines.is_empty() {
+                    buffer.extend_from_slice(&end.buffer);
+                    return Ok((end.bytes_read, end.nul_bytes, end.lines, false));
+                }
+                return Ok((0, 0, 0, false));
+            }
+
+            let mut line = Vec::new();
+            let (bytes_read, nul_bytes, lines) = read(&mut line)?;
+            let text = match encoding {
+                Some(encoding) => encoding.decode(&line).0,
+                None => String::from_utf8_lossy(&line),
+            };
+            let ahead = AheadLine {
+                required: requirement.is_match(text.trim_end()),
+                buffer: line,
+                bytes_read,
+                nul_bytes,
+                lines,
+            };
+
+            // EOF or NUL padding
+            if bytes_read == 0 || nul_bytes == bytes_read {
+                self.end = Some(ahead);
+            } else {
+                self.lines.push_back(ahead);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufRead;
+    use std::str::FromStr;
+
+    #[test]
+    fn lookahead() {
+        let requirement = Requirement::from_str("{ regex: 'ROLLBACK', lines: 2 }").unwrap();
+        let mut reader = &b"ERROR 1\nfoo\nbar\nERROR 2\nROLLBACK\nERROR 3\nfoo\n"[..];
+        let mut lookahead = Lookahead::default();
+
+        let mut next = || {
+            let mut buffer = Vec::new();
+            let (bytes_read, _, _, required) = lookahead
+                .next(&mut buffer, &requirement, None, |line| {
+                    reader.read_until(b'\n', line).map(|n| (n, 0, 1))
+                })
+                .unwrap();
+            (String::from_utf8(buffer).unwrap(), bytes_read, required)
+        };
+
+        // the rollback is too far from the first error
+        assert_eq!(next(), ("ERROR 1\n".to_string(), 8, false));
+        assert_eq!(next(), ("foo\n".to_string(), 4, false));
+        assert_eq!(next(), ("bar\n".to_string(), 4, true));
+        assert_eq!(next(), ("ERROR 2\n".to_string(), 8, true));
+        assert_eq!(next(), ("ROLLBACK\n".to_string(), 9, false));
+
+        // the last lines could still be followed by a rollback: they're not given
+        assert_eq!(next(), (String::new(), 0, false));
+        assert_eq!(next(), (String::new(), 0, false));
+    }
+}
@@ -1,24 +1,41 @@
 //! This is where the main function used to loop and where callback call is defined.
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::io::BufRead;
-use std::time::SystemTime;
+use std::path::Path;
+use std::time::{Instant, SystemTime};
 
+use base64::{engine::general_purpose::STANDARD, Engine};
 use log::{debug, error, info, trace};
 
 use crate::misc::{
-    error::{AppError, AppResult},
+    error::{AppCustomErrorKind, AppError, AppResult},
+    signal::shutdown_requested,
     util::*,
 };
 
 use crate::configuration::{
     callback::{CallbackHandle, ChildData},
+    dedup::Dedup,
     global::GlobalOptions,
+    logfiledef::{Encoding, LogFileFormat, ShrinkPolicy},
+    multiline::Multiline,
     options::SearchOptions,
-    pattern::PatternCounters,
+    pattern::{PatternCounters, PatternType},
     tag::Tag,
-    vars::RuntimeVars,
+    vars::{RuntimeVars, VarType},
 };
 
-use crate::logfile::{logfile::LogFile, seeker::Seeker};
+use crate::logfile::{
+    container::ContainerRecord,
+    jsonline::JsonLine,
+    logfile::{LogFile, Slice},
+    lookahead::Lookahead,
+    record::MatchRecord,
+    rundata::{LineStats, PendingCallback, RunData},
+    seeker::Seeker,
+    trace::{LineTracer, Outcome},
+};
 
 use crate::{context, prefix_var};
 pub trait Lookup<T> {
@@ -37,7 +54,7 @@ pub struct BypassReader;
 pub struct FullReader;
 
 // this will call the relevant reader
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum ReaderCallType {
     BypassReaderCall,
     FullReaderCall,
@@ -57,7 +74,8 @@ impl Lookup<FullReader> for LogFile {
     ///     - get a mutable reference on `RunData` structure
     ///     - reset thresholds if `savethresholds` is set: those thresholds trigger a callback whenever they are reached
     ///     - set current file pointers (offset and line number) to the last ones recorded in the `RunData` structure. If local option
-    ///       is set to `rewind`, read from the beginning of the file and set offsets accordingly
+    ///       is set to `rewind`, read from the beginning of the file, or from `rewindlimit` before its end, and set offsets
+    ///       accordingly
     ///
     /// 3. loop to read each line of the file
     ///     - read a line as a byte Vec and convert (lossy) to UTF-8
@@ -65,7 +83,10 @@ impl Lookup<FullReader> for LogFile {
     ///     - if yes:
     ///         - test if thresholds are reached. If not loop
     ///         - add rumtime variables, only related to the current line, pattern etc
-    ///         - if a script is defined to be called, call the script and save the `Child` return structure
+    ///         - if a script is defined to be called, keep these variables to call it once the logfile is read
+    ///
+    /// 4. call the callback for each match kept, and save the `Child` return structures. Offsets are saved even if a
+    ///    callback fails: the matches not notified are kept in the `RunData` structure to be notified at the next run
     fn reader<R: BufRead + Seeker>(
         &mut self,
         mut reader: R,
@@ -85,15 +106,15 @@ impl Lookup<FullReader> for LogFile {
         //let mut reader = LogReader::from_path(&self.id.canon_path)?;
         let path = self.id.canon_path.clone();
 
-        // uses the same buffer
-        let mut buffer = Vec::with_capacity(DEFAULT_STRING_CAPACITY);
+        // keep track of the time spent reading for this tag
+        let start = Instant::now();
 
         // define a new child handle. This is an Option because the script couldn't be called if not requested so
         let mut children = Vec::new();
 
-        // initialize line & byte counters
-        let mut bytes_count = 0;
-        let mut current_line_number = 0;
+        // line & byte counters, set below from the snapshot or the rewind point
+        let mut bytes_count: u64;
+        let mut current_line_number: u64;
 
         // to keep handles: stream etc
         let mut handle = CallbackHandle::default();
@@ -107,6 +128,74 @@ impl Lookup<FullReader> for LogFile {
             exclude_re = Some(self.definition.exclude.clone().unwrap());
         }
 
+        // same for the search deadline and the container format
+        let deadline = self.deadline;
+        let container_format = self.definition.container_format.clone();
+        let json_format = self.definition.format == LogFileFormat::json;
+        let labels = self.labels.clone();
+        let trace_lines = self.trace_lines;
+        let dry_run = self.dry_run;
+        let stream_open = self.stream_open;
+        let skip_header_lines = self.definition.skip_header_lines;
+        let encoding = self.definition.encoding;
+        let quantum = self.quantum.clone();
+        let output = self.output.clone().filter(|_| tag.options.keepoutput);
+        let protocol = self.protocol.clone().filter(|_| tag.options.protocol);
+        let progress = self
+            .progress
+            .clone()
+            .filter(|_| !dry_run)
+            .zip(global_options.snapshot_checkpoint.as_ref());
+        let id = self.id.clone();
+
+        // a search stopped at the end of the previous slice goes on from there, with the counters of this run
+        let resumed = self.slices.remove(&tag.name);
+        let mut paused = false;
+
+        // a container message could be split into several partial lines: keep the first parts along with the offsets
+        // at the beginning of the message, to read it again at next run if the message is not complete
+        let mut partial_record: Option<ContainerRecord> = None;
+        let mut record_start = (0, 0);
+
+        // the first line of the next multiline record, read along with the previous record
+        let mut held_line: Option<HeldLine> = None;
+
+        // the lines read ahead of the line processed, with the `requires_after` option
+        let mut lookahead = Lookahead::default();
+
+        // in-place edits and truncations can only be checked on plain files
+        let plain_file = !self.id.compression.is_compressed()
+            && !self.definition.path.is_journal()
+            && !self.definition.path.is_eventlog()
+            && !self.definition.path.is_remote()
+            && !self.definition.path.is_stdin();
+        let check_last_line = tag.options.linehash && plain_file;
+
+        // the last line is also looked for when the logfile shrinks, with the `rescan_tail` shrink policy
+        let hash_lines = tag.options.linehash
+            || (plain_file && self.definition.shrink_policy == ShrinkPolicy::rescan_tail);
+
+        // keep hash & length of the last line read: if the line is not processed, restore previous ones
+        let mut last_line_hash: Option<u64> = None;
+        let mut last_line_length = 0;
+
+        // when alerts are deduplicated, keep track of the incidents found during this run
+        let dedup = tag.dedup.as_ref();
+        let mut seen_incidents = HashSet::new();
+        let now = from_epoch_secs()?;
+
+        // critical matches of this run, counted against the error budget
+        let mut criticals = 0;
+
+        // the window of preceding lines is shared by all tags of this logfile: take it while reading and give it back
+        // at the end
+        let context_lines = tag.options.contextlines;
+        let mut context = std::mem::take(&mut self.context);
+        context.set_limits(
+            global_options.context_max_lines,
+            global_options.context_max_bytes,
+        );
+
         //------------------------------------------------------------------------------------
         // 2. reset `RunData` fields depending on local options
         //------------------------------------------------------------------------------------
@@ -118,50 +207,234 @@ impl Lookup<FullReader> for LogFile {
         // store pid: it'll be used for output message
         run_data.pid = std::process::id();
 
-        // if we don't need to read the file from the beginning, adjust counters and set offset
-        if tag.options.rewind {
-            run_data.start_offset = 0;
-            run_data.start_line = 0;
-        } else {
-            run_data.start_offset = run_data.last_offset;
-            run_data.start_line = run_data.last_line;
+        // uses the same buffer, sized after the longest lines of the previous runs
+        let mut buffer = Vec::with_capacity(run_data.buffer_capacity());
+        let mut max_line_length = 0;
+
+        let mut counted_offset = 0;
+        let mut previous_scan_duration = 0.0;
+        if let Some(slice) = resumed {
+            if hash_lines {
+                last_line_hash = run_data.last_line_hash;
+                last_line_length = run_data.last_line_length;
+            }
             bytes_count = run_data.last_offset;
             current_line_number = run_data.last_line;
+            counted_offset = run_data.counted_offset;
+            seen_incidents = slice.seen_incidents;
+            previous_scan_duration = slice.scan_duration;
 
-            // move to previous offset
             reader.set_offset(run_data.last_offset)?;
-        }
+            info!(
+                "resuming read from offset={}, line={}",
+                bytes_count, current_line_number
+            );
+        } else {
+            // if we don't need to read the file from the beginning, adjust counters and set offset
+            if tag.options.rewind {
+                // a bounded rewind starts near the end of file, which is only possible on plain files
+                let (offset, line) = match tag.options.rewindlimit {
+                    Some(limit) if plain_file => run_data.rewind_start(&path, limit, now)?,
+                    _ => (0, 0),
+                };
+                run_data.start_offset = offset;
+                run_data.start_line = line;
+                bytes_count = offset;
+                current_line_number = line;
 
-        info!(
-            "starting read from last offset={}, last line={}",
-            bytes_count, current_line_number
-        );
+                if offset != 0 {
+                    reader.set_offset(offset)?;
+                }
+            } else {
+                // if the last line processed has been rewritten since, move back to rescan it
+                if check_last_line
+                    && run_data.verify_last_line(
+                        &path,
+                        tag.options.rescanbytes,
+                        tag.options.rescanlines,
+                    )?
+                {
+                    info!(
+                        "last line of logfile {} has changed, rescanning from offset={}, line={}",
+                        path.display(),
+                        run_data.last_offset,
+                        run_data.last_line
+                    );
+                }
+                if hash_lines {
+                    last_line_hash = run_data.last_line_hash;
+                    last_line_length = run_data.last_line_length;
+                }
+
+                run_data.start_offset = run_data.last_offset;
+                run_data.start_line = run_data.last_line;
+                bytes_count = run_data.last_offset;
+                current_line_number = run_data.last_line;
+
+                // move to previous offset
+                reader.set_offset(run_data.last_offset)?;
+            }
+
+            info!(
+                "starting read from last offset={}, last line={}",
+                bytes_count, current_line_number
+            );
+
+            // reset exec count
+            run_data.counters.exec_count = 0;
+            run_data.counters.suppressed_count = 0;
+            run_data.counters.dup_count = 0;
+            run_data.nul_bytes = 0;
+
+            // resets thresholds if requested
+            // this will count number of matches for warning & critical, to see if this matches the thresholds
+            // first is warning, second is critical
+            if !tag.options.savethresholds {
+                run_data.counters.critical_count = 0;
+                run_data.counters.warning_count = 0;
+                run_data.keyed_counters.clear();
+            }
+
+            // saved counters already include the matches up to this offset, unless the logfile has been truncated since
+            if tag.options.savethresholds {
+                counted_offset = run_data.counted_offset;
+                if plain_file && std::fs::metadata(&path).is_ok_and(|m| m.len() < counted_offset) {
+                    counted_offset = 0;
+                }
+            }
 
-        // reset exec count
-        run_data.counters.exec_count = 0;
+            run_data.sentinel_line = None;
+            run_data.output_file = None;
+            run_data.max_line_length /= 2;
 
-        // resets thresholds if requested
-        // this will count number of matches for warning & critical, to see if this matches the thresholds
-        // first is warning, second is critical
-        if !tag.options.savethresholds {
-            run_data.counters.critical_count = 0;
-            run_data.counters.warning_count = 0;
+            // line counters are only kept for the current run
+            run_data.line_stats = if tag.line_stats {
+                Some(LineStats::default())
+            } else {
+                None
+            };
         }
+        let slice_start = (bytes_count, current_line_number);
+        let mut checkpoint_start = (current_line_number, Instant::now());
 
         //------------------------------------------------------------------------------------
         // 3. loop to read each line of the file
         //------------------------------------------------------------------------------------
         loop {
-            // read until '\n' (which is included in the buffer)
-            let ret = reader.read_until(b'\n', &mut buffer);
+            // if the search timeout is reached, stop here: offsets are those of the last processed line
+            if let Err(e) = check_deadline(deadline, &path).and_then(|_| check_shutdown(&path)) {
+                error!("{}", e);
+                early_ret = Some(e);
+                break;
+            }
+
+            // at the end of the slice, the other logfiles are searched before going on. A container message is
+            // never split between 2 slices
+            if let Some(quantum) = &quantum {
+                if partial_record.is_none()
+                    && quantum.is_reached(
+                        current_line_number - slice_start.1,
+                        bytes_count - slice_start.0,
+                    )
+                {
+                    debug!(
+                        "end of slice for logfile {:?}, tag {}, line#={}",
+                        &path, tag.name, current_line_number
+                    );
+                    paused = true;
+                    break;
+                }
+            }
+
+            // the offsets reached are saved along the way, for the next run to resume from there if this one stops
+            // before the snapshot is saved. Like slices, a checkpoint never splits a container message
+            if let Some((progress, checkpoint)) = &progress {
+                if partial_record.is_none()
+                    && checkpoint.is_due(
+                        current_line_number.saturating_sub(checkpoint_start.0),
+                        checkpoint_start.1.elapsed(),
+                    )
+                {
+                    let mut reached = run_data.clone();
+                    reached.last_offset = bytes_count;
+                    reached.last_line = current_line_number;
+                    reached.last_line_hash = last_line_hash;
+                    reached.last_line_length = last_line_length;
+                    reached.counted_offset = if tag.options.savethresholds {
+                        counted_offset.max(bytes_count)
+                    } else {
+                        0
+                    };
+                    reached.last_run_secs = now;
+                    reached.last_run = now as f64;
+
+                    debug!(
+                        "checkpoint for logfile {:?}, tag {}, line#={}",
+                        &path, tag.name, current_line_number
+                    );
+                    if let Err(e) = progress.save(&id, &tag.name, &reached) {
+                        error!("{}", e);
+                    }
+                    checkpoint_start = (current_line_number, Instant::now());
+                }
+            }
+
+            // read until '\n' (which is included in the buffer), or a whole multiline record. NUL bytes are skipped
+            let mut read = |buffer: &mut Vec<u8>| match &tag.multiline {
+                Some(multiline) => read_record_skip_nul(
+                    &mut reader,
+                    buffer,
+                    &mut held_line,
+                    multiline,
+                    encoding,
+                    &path,
+                ),
+                None => read_line(&mut reader, buffer, encoding)
+                    .map(|(bytes_read, nul_bytes)| (bytes_read, nul_bytes, 1)),
+            };
+
+            // with `requires_after`, lines are read ahead to know whether the required line follows
+            let mut followed = true;
+            let ret = match &tag.requires_after {
+                Some(requirement) => lookahead
+                    .next(&mut buffer, requirement, encoding, read)
+                    .map(|(bytes_read, nul_bytes, lines, required)| {
+                        followed = required;
+                        (bytes_read, nul_bytes, lines)
+                    }),
+                None => read(&mut buffer),
+            };
+            let nul_bytes = ret.as_ref().map_or(0, |(_, nul_bytes, _)| *nul_bytes);
+            let record_lines = ret.as_ref().map_or(1, |(_, _, lines)| *lines);
+            let ret = ret.map(|(bytes_read, _, _)| bytes_read);
+            max_line_length = max_line_length.max(buffer.len());
 
-            // truncate the line if asked
+            // hash the whole line before any truncation. The hash of a line with NUL bytes can't be compared to the
+            // logfile content
+            let line_hash = if hash_lines && nul_bytes == 0 {
+                Some(crc::crc64::checksum_iso(&buffer))
+            } else {
+                None
+            };
+
+            // a byte order mark would prevent anchored regexes from matching the first line
+            if bytes_count == 0 {
+                LogFile::strip_bom(&mut buffer);
+            }
+
+            // truncate the line if asked, without splitting a character if the encoding is known
             if tag.options.truncate != 0 {
-                buffer.truncate(tag.options.truncate);
+                let size = encoding.map_or(tag.options.truncate, |encoding| {
+                    encoding.truncated_len(&buffer, tag.options.truncate)
+                });
+                buffer.truncate(size);
             }
 
             // to deal with UTF-8 conversion problems, use the lossy method. It will replace non-UTF-8 chars with ?
-            let mut line = String::from_utf8_lossy(&buffer);
+            let (mut line, lossy) = match encoding {
+                Some(encoding) => encoding.decode(&buffer),
+                None => (String::from_utf8_lossy(&buffer), false),
+            };
 
             // delete '\n' or '\r\n' from the eol
             LogFile::purge_line(&mut line);
@@ -169,20 +442,72 @@ impl Lookup<FullReader> for LogFile {
             // read_line() returns a Result<usize>
             match ret {
                 Ok(bytes_read) => {
+                    run_data.nul_bytes += nul_bytes as u64;
+
+                    // NUL padding at the end of a preallocated logfile is like EOF: it's read again at next run,
+                    // once overwritten with new lines
+                    if nul_bytes != 0 && nul_bytes == bytes_read {
+                        debug!(
+                            "{} NUL bytes skipped at the end of logfile {:?}",
+                            nul_bytes, &path
+                        );
+                        buffer.clear();
+                        if partial_record.is_some() {
+                            (bytes_count, current_line_number) = record_start;
+                            last_line_hash = None;
+                        }
+                        break;
+                    }
+
                     // EOF: save last file address to restart from this address for next run
                     if bytes_read == 0 {
+                        // an incomplete container message will be read again
+                        if partial_record.is_some() {
+                            (bytes_count, current_line_number) = record_start;
+                            last_line_hash = None;
+                        }
+
+                        // the next lines of the standard input are read at the next slice
+                        paused = stream_open;
                         break;
                     }
 
-                    // we've been reading a new line successfully
-                    current_line_number += 1;
+                    // we've been reading a new line successfully. A multiline record is numbered after its first line
+                    current_line_number += record_lines;
                     bytes_count += bytes_read as u64;
+                    let line_number = current_line_number + 1 - record_lines;
+
+                    // a large logfile could take longer than the systemd watchdog interval
+                    #[cfg(target_os = "linux")]
+                    if current_line_number.is_multiple_of(WATCHDOG_LINES) {
+                        crate::misc::systemd::watchdog();
+                    }
+
+                    // save line features, in case it's the last one
+                    let previous_line = (last_line_hash, last_line_length);
+                    last_line_hash = line_hash;
+                    last_line_length = bytes_read as u64;
                     trace!(
                         "read one line: current_line_number={}, bytes_count={}",
                         current_line_number,
                         bytes_count
                     );
 
+                    // keep the line in the window only if this tag needs it
+                    if context_lines != 0 && current_line_number > skip_header_lines {
+                        for (i, text) in line.split('\n').enumerate() {
+                            context.push(line_number + i as u64, text.trim_end_matches('\r'));
+                        }
+                    }
+
+                    // the last required line before the matches of this tag is kept even across runs
+                    let required_line = run_data.required_line;
+                    if let Some(requirement) = &tag.requires_before {
+                        if current_line_number > skip_header_lines && requirement.is_match(&line) {
+                            run_data.required_line = Some(line_number);
+                        }
+                    }
+
                     // do we just need to go to EOF ? Only in case of first run
                     if tag.options.fastforward && run_data.start_offset == 0 {
                         buffer.clear();
@@ -191,29 +516,104 @@ impl Lookup<FullReader> for LogFile {
 
                     // if stopat is reached, stop here. We stop before processing the line, so we need to decrement the bytes read
                     // because it was already incremented before
-                    if tag.options.stopat == current_line_number {
-                        current_line_number -= 1;
+                    if (line_number..=current_line_number).contains(&tag.options.stopat) {
+                        current_line_number -= record_lines;
                         bytes_count -= bytes_read as u64;
+                        (last_line_hash, last_line_length) = previous_line;
+                        if partial_record.is_some() {
+                            (bytes_count, current_line_number) = record_start;
+                            last_line_hash = None;
+                        }
                         break;
                     }
 
+                    // header lines are not matched
+                    if current_line_number <= skip_header_lines {
+                        buffer.clear();
+                        continue;
+                    }
+
+                    // for container logfiles, the message is matched instead of the whole line
+                    let mut container_record: Option<ContainerRecord> = None;
+                    if let Some(format) = &container_format {
+                        match format.parse(&line) {
+                            Ok(record) => {
+                                let record = match partial_record.take() {
+                                    Some(mut first_parts) => {
+                                        first_parts.append(record);
+                                        first_parts
+                                    }
+                                    None => {
+                                        record_start = (
+                                            bytes_count - bytes_read as u64,
+                                            current_line_number - 1,
+                                        );
+                                        record
+                                    }
+                                };
+
+                                // wait for the next part
+                                if record.partial {
+                                    partial_record = Some(record);
+                                    buffer.clear();
+                                    continue;
+                                }
+
+                                line = Cow::from(record.message.clone());
+                                container_record = Some(record);
+                            }
+                            Err(e) => debug!("{}, line#={}", e, current_line_number),
+                        }
+                    }
+
+                    // the decision path of a sample of lines is logged
+                    let mut tracer =
+                        LineTracer::sample(trace_lines, &path, &tag.name, line_number, &line);
+
+                    if let Some(stats) = run_data.line_stats.as_mut() {
+                        stats.evaluated += 1;
+                    }
+
                     // check for excluded lines
                     if let Some(ref re) = exclude_re {
+                        tracer.exclude(re);
                         if re.is_match(&line) {
+                            tracer.end(Outcome::excluded);
+                            if let Some(stats) = run_data.line_stats.as_mut() {
+                                stats.excluded += 1;
+                            }
+                            if tag.count_suppressed && is_suppressed_match(tag, &line, json_format)
+                            {
+                                run_data.counters.suppressed_count += 1;
+                            }
                             buffer.clear();
                             continue;
                         }
                     }
 
-                    trace!("====> line#={}, line={}", current_line_number, &line);
+                    // most lines can't match, don't evaluate regexes for them
+                    if !tag.is_candidate(&line) {
+                        tracer.end(Outcome::prefiltered);
+                        buffer.clear();
+                        continue;
+                    }
+
+                    // for JSON logfiles, patterns could match the value of a field
+                    let json = if json_format {
+                        JsonLine::parse(&line)
+                    } else {
+                        None
+                    };
+
+                    trace!("====> line#={}, line={}", line_number, &line);
 
                     // is there a match, regarding also exceptions?
-                    if let Some(pattern_match) = tag.is_match(&line) {
+                    if let Some(pattern_match) = tag.is_match_json(&line, json.as_ref()) {
                         debug!(
                             "found a match tag={}, line={}, line#={}, re=({:?},{}), critical_count={}, warning_count={}, ok_count={}",
                             tag.name,
                             &line,
-                            current_line_number,
+                            line_number,
                             pattern_match.pattern_type,
                             pattern_match.regex.as_str(),
                             run_data.counters.critical_count,
@@ -221,12 +621,114 @@ impl Lookup<FullReader> for LogFile {
                             run_data.counters.ok_count,
                         );
 
-                        // increment counters depending on found pattern
-                        run_data.increment_counters(&pattern_match.pattern_type);
+                        // a match already counted is neither counted nor notified again
+                        if bytes_count <= counted_offset {
+                            trace!("match already counted, line#={}", line_number);
+                            tracer.end(Outcome::already_counted);
+                            buffer.clear();
+                            continue;
+                        }
+
+                        // only the matches of lines written within the window are counted. A line whose timestamp
+                        // can't be found is counted
+                        let line_time = tag.timestamp.as_ref().and_then(|t| t.parse(&line, now));
+                        if let (Some(timestamp), Some(time)) = (&tag.timestamp, line_time) {
+                            if !timestamp.is_within(time, now) {
+                                trace!("match outside the time window, line#={}", line_number);
+                                tracer.end(Outcome::outside_window);
+                                buffer.clear();
+                                continue;
+                            }
+                        }
+
+                        // the required lines must be found within their distance before and after the match
+                        let preceded = tag
+                            .requires_before
+                            .as_ref()
+                            .is_none_or(|r| r.precedes(required_line, line_number));
+                        if !preceded || !followed {
+                            trace!("required line not found, line#={}", line_number);
+                            tracer.end(Outcome::requirement_not_met);
+                            buffer.clear();
+                            continue;
+                        }
+
+                        // keep the matched line as evidence in the output file of the tag, and in its protocol
+                        // file. This is not fatal for the search
+                        let mut protocol_file = None;
+                        if output.is_some() || protocol.is_some() {
+                            let record = MatchRecord::new(
+                                &path,
+                                &tag.name,
+                                &labels,
+                                &pattern_match,
+                                line_number,
+                                bytes_count - bytes_read as u64,
+                                &line,
+                            );
+                            if let Some(output) = &output {
+                                match output.write(&record) {
+                                    Ok(output_file) => run_data.output_file = Some(output_file),
+                                    Err(e) => error!("unable to keep matched line: {}", e),
+                                }
+                            }
+                            if let Some(protocol) = &protocol {
+                                match protocol.write(&record) {
+                                    Ok(path) => protocol_file = Some(path),
+                                    Err(e) => {
+                                        error!("unable to write matched line to protocol: {}", e)
+                                    }
+                                }
+                            }
+                        }
+
+                        // increment counters depending on found pattern, and on the captured value if thresholds
+                        // are per capture group value
+                        let threshold_key = tag.options.threshold_key(&pattern_match, &line);
+                        run_data.increment_counters(
+                            &pattern_match.pattern_type,
+                            threshold_key.as_deref(),
+                        );
+                        if pattern_match.pattern_type == PatternType::critical {
+                            criticals += 1;
+                        }
+                        if pattern_match.pattern_type != PatternType::ok {
+                            run_data.seen(now);
+                            if tag.sentinel && run_data.sentinel_line.is_none() {
+                                run_data.sentinel_line = Some(line.to_string());
+                            }
+                            if global_options.overlap_check.is_some() {
+                                run_data.matched_lines.push(line_number);
+                            }
+                        }
+                        if let Some(stats) = run_data.line_stats.as_mut() {
+                            stats.matched += 1;
+                        }
+
+                        // an ok pattern closes all incidents, otherwise a match is part of an incident
+                        let incident_key = match dedup {
+                            Some(_) if pattern_match.pattern_type == PatternType::ok => {
+                                run_data.incidents.clear();
+                                None
+                            }
+                            Some(dedup) => {
+                                let key = dedup.key(&pattern_match, &line);
+                                run_data.incident_seen(&key, now);
+                                seen_incidents.insert(key.clone());
+                                Some(key)
+                            }
+                            None => None,
+                        };
 
                         // when a threshold is reached, give up
-                        if !run_data.is_threshold_reached(&pattern_match.pattern_type, &tag.options)
-                        {
+                        let reached = run_data.is_threshold_reached(
+                            &pattern_match.pattern_type,
+                            &tag.options,
+                            threshold_key.as_deref(),
+                        );
+                        tracer.matched(&pattern_match, &run_data.counters, &tag.options, reached);
+                        if !reached {
+                            tracer.end(Outcome::threshold_not_reached);
                             trace!(
                                 "threshold is not yet reached: current critical={}, warning={}",
                                 run_data.counters.critical_count,
@@ -236,8 +738,44 @@ impl Lookup<FullReader> for LogFile {
                             continue;
                         }
 
+                        // an identical match found within the window is only counted
+                        if let (Some(window), false) = (
+                            &tag.options.dedup,
+                            pattern_match.pattern_type == PatternType::ok,
+                        ) {
+                            let key = if tag.options.dedup_captures {
+                                Dedup::default().key(&pattern_match, &line)
+                            } else {
+                                line.to_string()
+                            };
+                            let key = crc::crc64::checksum_iso(key.as_bytes());
+                            let hash = match_hash(&path, &tag.name, line_number, &line);
+                            if run_data.is_duplicate(key, line_number, now, hash, window) {
+                                trace!("duplicate match, line#={}", line_number);
+                                run_data.counters.dup_count += 1;
+                                tracer.end(Outcome::duplicate);
+                                buffer.clear();
+                                continue;
+                            }
+                        }
+
                         // if we've been asked to trigger the script, first add relevant variables
                         if tag.options.runcallback {
+                            // an incident already notified is not notified again, unless the re-notify interval is elapsed
+                            if let (Some(dedup), Some(key)) = (dedup, &incident_key) {
+                                if !run_data.is_incident_notifiable(
+                                    key,
+                                    now,
+                                    dedup.renotify_interval,
+                                ) || run_data.is_incident_pending(key)
+                                {
+                                    debug!("incident already notified, key={}", key);
+                                    tracer.end(Outcome::already_notified);
+                                    buffer.clear();
+                                    continue;
+                                }
+                            }
+
                             let mut vars = RuntimeVars::default();
 
                             // create variables which will be set as environment variables when script is called
@@ -246,11 +784,39 @@ impl Lookup<FullReader> for LogFile {
                                 path.to_str().unwrap_or("error converting PathBuf"),
                             );
                             vars.insert_runtime_var(prefix_var!("TAG"), tag.name.as_str());
-                            vars.insert_runtime_var(
-                                prefix_var!("LINE_NUMBER"),
-                                current_line_number,
-                            );
-                            vars.insert_runtime_var(prefix_var!("LINE"), &line);
+
+                            // the protocol file the matched line was just written to
+                            if let Some(protocol_file) = &protocol_file {
+                                vars.insert_runtime_var(
+                                    prefix_var!("PROTOCOLFILE"),
+                                    protocol_file.to_str().unwrap_or("error converting PathBuf"),
+                                );
+                            }
+                            vars.insert_labels(&labels);
+                            if let Some(json) = &json {
+                                vars.insert_json(json);
+                            }
+                            vars.insert_runtime_var(prefix_var!("LINE_NUMBER"), line_number);
+                            vars.insert_line(&line, tag.options.chunksize);
+
+                            // the bytes of the line as read, for lines which couldn't be decoded losslessly
+                            let raw_line;
+                            if tag.options.rawline {
+                                raw_line = STANDARD.encode(LogFile::raw_line(&buffer));
+                                vars.insert_runtime_var(prefix_var!("LINE_RAW"), raw_line.as_str());
+                            }
+
+                            // add preceding lines, if requested
+                            let context_text;
+                            if context_lines != 0 {
+                                context_text =
+                                    context.before(line_number, context_lines).join("\n");
+                                vars.insert_runtime_var(
+                                    prefix_var!("CONTEXT"),
+                                    context_text.as_str(),
+                                );
+                            }
+
                             vars.insert_runtime_var(
                                 prefix_var!("MATCHED_RE"),
                                 pattern_match.regex.as_str(),
@@ -261,8 +827,23 @@ impl Lookup<FullReader> for LogFile {
                             );
 
                             // insert number of captures and capture groups
-                            let nb_caps = vars.insert_captures(pattern_match.regex, &line);
+                            let capture_prefix = tag.options.capture_prefix.as_deref();
+                            let nb_caps = vars.insert_captures_with(
+                                pattern_match.regex,
+                                pattern_match.text(&line),
+                                tag.options.capture_vars,
+                                capture_prefix,
+                            );
                             vars.insert_runtime_var(prefix_var!("NB_CG"), nb_caps);
+                            if encoding.is_some() {
+                                vars.insert_captures_validity(
+                                    pattern_match.regex,
+                                    pattern_match.text(&line),
+                                    lossy,
+                                    tag.options.capture_vars,
+                                    capture_prefix,
+                                );
+                            }
 
                             // add counters
                             vars.insert_runtime_var(
@@ -278,51 +859,102 @@ impl Lookup<FullReader> for LogFile {
                                 run_data.counters.ok_count,
                             );
 
-                            debug!("added variables: {:?}", vars);
+                            // how long the problem has been ongoing
+                            if let (Some(first_seen), Some(last_seen)) =
+                                (run_data.first_seen, run_data.last_seen)
+                            {
+                                vars.insert_runtime_var(prefix_var!("FIRST_SEEN"), first_seen);
+                                vars.insert_runtime_var(prefix_var!("LAST_SEEN"), last_seen);
+                            }
 
-                            // now call script if upper run limit is not reached yet
-                            if run_data.counters.exec_count < tag.options.runlimit {
-                                // in case of a callback error, stop iterating and save state here
-                                match tag.callback_call(
-                                    Some(&global_options.script_path),
-                                    &global_options.global_vars,
-                                    &vars,
-                                    &mut handle,
-                                ) {
-                                    Ok(child) => {
-                                        // save child structure
-                                        if let Some(c) = child {
-                                            children.push(c);
-                                        }
-
-                                        // increment number of script executions or number of JSON data sent
-                                        run_data.counters.exec_count += 1;
-                                        trace!("callback successfully called");
-                                    }
-                                    Err(e) => {
-                                        error!(
-                                            "error <{}> when calling callback <{:#?}>",
-                                            e, tag.callback
-                                        );
+                            // add container stream and time
+                            if let Some(record) = &container_record {
+                                vars.insert_runtime_var(
+                                    prefix_var!("CONTAINER_STREAM"),
+                                    record.stream.as_str(),
+                                );
+                                vars.insert_runtime_var(
+                                    prefix_var!("CONTAINER_TIME"),
+                                    record.time.as_str(),
+                                );
+                            }
 
-                                        // reset counters
-                                        current_line_number -= 1;
-                                        bytes_count -= bytes_read as u64;
+                            // add the time of the line, if its timestamp was parsed
+                            if let Some(time) = line_time {
+                                vars.insert_runtime_var(prefix_var!("TIMESTAMP_PARSED"), time);
+                            }
 
-                                        // same for run data
-                                        run_data.decrement_counters(&pattern_match.pattern_type);
+                            // add incident key
+                            if let Some(key) = &incident_key {
+                                vars.insert_runtime_var(prefix_var!("INCIDENT_KEY"), key.as_str());
+                            }
 
-                                        early_ret = Some(e);
-                                        break;
-                                    }
-                                };
+                            // duplicates found before the callback is called are counted there
+                            if tag.options.dedup.is_some() {
+                                vars.insert_runtime_var(prefix_var!("DUP_COUNT"), 0u64);
                             }
-                        };
+
+                            debug!("added variables: {:?}", vars);
+
+                            // the callback is called once the logfile is read, if upper run limit is not reached yet
+                            // and the rate limit allows it. Calls not done yet count for the run limit
+                            if run_data.counters.exec_count
+                                + run_data.pending_callbacks.len() as u64
+                                >= tag.options.runlimit
+                            {
+                                tracer.end(Outcome::run_limit_reached);
+                            } else if !tag
+                                .options
+                                .rate_limit
+                                .is_none_or(|rate_limit| run_data.is_rate_allowed(now, &rate_limit))
+                            {
+                                debug!(
+                                    "rate limit reached for tag={}, line#={}",
+                                    tag.name, line_number
+                                );
+                                tracer.end(Outcome::rate_limited);
+                            } else {
+                                // personal data is redacted before leaving the host
+                                let mut pending = vars.to_pending();
+                                if let Some(redact) = &tag.redact {
+                                    redact.vars(&mut pending);
+                                }
+
+                                // the key is kept with the pending call, so it's the same if the call is retried
+                                let hash = match_hash(&path, &tag.name, line_number, &line);
+                                pending.insert(
+                                    prefix_var!("IDEMPOTENCY_KEY").to_string(),
+                                    VarType::Str(idempotency_key(hash)),
+                                );
+                                // the call is counted, and the incident notified, once the callback is called
+                                run_data.queue_callback(PendingCallback {
+                                    hash,
+                                    vars: pending,
+                                    incident: incident_key.clone(),
+                                });
+                                tracer.end(Outcome::callback);
+                            }
+                        } else {
+                            tracer.end(Outcome::no_callback);
+                        }
+                    } else {
+                        tracer.no_match(tag);
+                        tracer.end(Outcome::no_match);
                     }
 
                     // reset buffer to not accumulate data
                     buffer.clear();
                 }
+                // a read blocked up to the search deadline is given up: offsets are those of the last processed line
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                    let e = AppError::new_custom(
+                        AppCustomErrorKind::SearchTimeout,
+                        &format!("search timeout reached reading logfile {:?}", path),
+                    );
+                    error!("{}", e);
+                    early_ret = Some(e);
+                    break;
+                }
                 // a rare IO error could occur here
                 Err(e) => {
                     error!("read_line() error kind: {:?}, line: {}", e.kind(), line);
@@ -338,9 +970,51 @@ impl Lookup<FullReader> for LogFile {
             };
         }
 
+        //------------------------------------------------------------------------------------
+        // 4. call the callback for each match found, including those not notified during previous runs. In a dry
+        // run, they're only reported
+        //------------------------------------------------------------------------------------
+        if dry_run {
+            debug!(
+                "dry run: {} callback calls not done for tag={}",
+                run_data.pending_callbacks.len(),
+                tag.name
+            );
+        } else if let Err(e) = notify(
+            tag,
+            global_options,
+            run_data,
+            now,
+            &mut handle,
+            &mut children,
+        ) {
+            early_ret.get_or_insert(e);
+        }
+
+        // criticals within the error budget are reported as warnings
+        if let Some(tolerance) = &tag.tolerance {
+            run_data.tolerate(criticals, now, tolerance);
+        }
+
         // save current offset and line number
         run_data.last_offset = bytes_count;
         run_data.last_line = current_line_number;
+        run_data.last_line_hash = last_line_hash;
+        run_data.last_line_length = last_line_length;
+        run_data.max_line_length = run_data.max_line_length.max(max_line_length);
+        run_data.counted_offset = if tag.options.savethresholds {
+            counted_offset.max(bytes_count)
+        } else {
+            0
+        };
+
+        // first occurrences are kept only within the window
+        run_data.expire_duplicates(current_line_number, now, tag.options.dedup.as_ref());
+
+        // incidents are closed only if the whole logfile has been read
+        if let (Some(dedup), None, false) = (dedup, &early_ret, paused) {
+            run_data.close_incidents(&seen_incidents, dedup.close_after);
+        }
 
         trace!(
             "bytes_count={}, line_number={}, critical={}, warning={}",
@@ -356,10 +1030,18 @@ impl Lookup<FullReader> for LogFile {
             .map_err(|e| context!(e, "error calculating durations",))?;
         run_data.last_run = time.as_secs_f64();
         run_data.last_run_secs = time.as_secs();
+        let scan_duration = previous_scan_duration + start.elapsed().as_secs_f64();
+        run_data.scan_duration = scan_duration;
 
         // criticalthreshold or warning thresholds are set, need to reflect reality for error counts
-        // need to test against thresholds in case of high values
-        counters_calculation(&mut run_data.counters, &tag.options);
+        // need to test against thresholds in case of high values. This is done once the whole logfile is read
+        if !paused {
+            counters_calculation(
+                &mut run_data.counters,
+                &mut run_data.keyed_counters,
+                &tag.options,
+            );
+        }
 
         info!(
             "========================> end processing logfile for tag:{}, bytes_count={}, line_number={}, callback execution: {}, critical={}, warning={}",
@@ -372,6 +1054,30 @@ impl Lookup<FullReader> for LogFile {
             run_data.counters.warning_count,
         );
 
+        // give the window back for the next tags
+        self.context = context;
+
+        // keep what's needed to go on at the next slice
+        if paused {
+            self.slices.insert(
+                tag.name.clone(),
+                Slice {
+                    seen_incidents,
+                    scan_duration,
+                },
+            );
+        }
+
+        // tell the receiver how many payloads were sent, if any socket was used
+        if let Err(e) = handle.end_of_run() {
+            error!("error sending end-of-run message: {}", e);
+        }
+
+        // matches for an e-mail callback are sent once all logfiles are searched
+        if let Some(digest) = handle.take_digest() {
+            digest.collect(&mut self.digests);
+        }
+
         // return error if we got one or the list of children from calling the script
         match early_ret {
             None => Ok(children),
@@ -380,8 +1086,128 @@ impl Lookup<FullReader> for LogFile {
     }
 }
 
+// calls the callback for each pending match, the oldest first. Matches notified are removed and counted: on error or
+// shutdown, the remaining ones are kept to be notified at the next run, up to `max_pending_callbacks`, while offsets
+// still move forward. With the `sentset` option, matches already notified are skipped
+fn notify(
+    tag: &Tag,
+    global_options: &GlobalOptions,
+    run_data: &mut RunData,
+    now: u64,
+    handle: &mut CallbackHandle,
+    children: &mut Vec<ChildData>,
+) -> AppResult<()> {
+    let mut pending = std::mem::take(&mut run_data.pending_callbacks);
+    let sentset = tag.options.sentset;
+    let mut notified = 0;
+    let mut ret = Ok(());
+
+    for pending_callback in pending.iter() {
+        if shutdown_requested() {
+            break;
+        }
+
+        if sentset != 0 && run_data.sent.contains(&pending_callback.hash) {
+            debug!(
+                "match already notified, hash={:016x}",
+                pending_callback.hash
+            );
+            notified += 1;
+            continue;
+        }
+
+        let vars = RuntimeVars::from_pending(&pending_callback.vars);
+        match tag.callback_call(
+            Some(&global_options.script_path),
+            &global_options.global_vars,
+            &vars,
+            handle,
+        ) {
+            Ok(child) => {
+                // save child structure
+                if let Some(c) = child {
+                    children.push(c);
+                }
+                notified += 1;
+                if sentset != 0 {
+                    run_data.sent(pending_callback.hash, sentset);
+                }
+
+                // increment number of script executions or number of JSON data sent
+                run_data.counters.exec_count += 1;
+
+                // the incident is now notified
+                if let Some(key) = &pending_callback.incident {
+                    run_data.incident_notified(key, now);
+                }
+                trace!("callback successfully called");
+            }
+            Err(e) => {
+                error!(
+                    "error <{}> when calling callback <{:#?}>, {} calls left for next run",
+                    e,
+                    tag.callback,
+                    pending.len() - notified
+                );
+                ret = Err(e);
+                break;
+            }
+        }
+    }
+
+    pending.drain(..notified);
+    run_data.pending_callbacks = pending;
+    run_data.cap_pending_callbacks(global_options.max_pending_callbacks);
+    ret
+}
+
+// the hash identifying a match, whatever the run it's read in
+fn match_hash(path: &Path, tag_name: &str, line_number: u64, line: &str) -> u64 {
+    let data = format!(
+        "{}\0{}\0{}\0{}",
+        path.display(),
+        tag_name,
+        line_number,
+        line
+    );
+    crc::crc64::checksum_iso(data.as_bytes())
+}
+
+// the idempotency key given to the callback: the hash of the match along with the run which found it
+fn idempotency_key(hash: u64) -> String {
+    let data = format!("{:016x}\0{}", hash, run_uuid());
+    format!("{:016x}", crc::crc64::checksum_iso(data.as_bytes()))
+}
+
+// returns a timeout error if the search deadline is reached
+fn check_deadline(deadline: Option<Instant>, path: &Path) -> AppResult<()> {
+    match deadline {
+        Some(deadline) if Instant::now() >= deadline => Err(AppError::new_custom(
+            AppCustomErrorKind::SearchTimeout,
+            &format!("search timeout reached for logfile {:?}", path),
+        )),
+        _ => Ok(()),
+    }
+}
+
+// returns an interrupted error if a shutdown signal was received
+fn check_shutdown(path: &Path) -> AppResult<()> {
+    if shutdown_requested() {
+        Err(AppError::new_custom(
+            AppCustomErrorKind::Interrupted,
+            &format!("shutdown requested while reading logfile {:?}", path),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
 // manage error counters depending on options
-fn counters_calculation(counters: &mut PatternCounters, options: &SearchOptions) {
+fn counters_calculation(
+    counters: &mut PatternCounters,
+    keyed_counters: &mut HashMap<String, PatternCounters>,
+    options: &SearchOptions,
+) {
     // do we need to save our thresholds ?
     if options.savethresholds {
         // critical errors
@@ -426,56 +1252,274 @@ fn counters_calculation(counters: &mut PatternCounters, options: &SearchOptions)
             }
         }
     }
+
+    // with thresholds per capture group value, errors are the matches above the threshold of each value
+    if let Some(per) = &options.criticalthreshold_per {
+        counters.critical_count =
+            keyed_excess(keyed_counters, per.limit, options.savethresholds, |c| {
+                &mut c.critical_count
+            });
+    }
+    if let Some(per) = &options.warningthreshold_per {
+        counters.warning_count =
+            keyed_excess(keyed_counters, per.limit, options.savethresholds, |c| {
+                &mut c.warning_count
+            });
+    }
+
+    // values without any saved match are not kept
+    keyed_counters.retain(|_, c| c.critical_count != 0 || c.warning_count != 0);
+}
+
+// sums the matches above the limit of each value. Like for global thresholds, saved counters only keep the delta
+fn keyed_excess(
+    keyed_counters: &mut HashMap<String, PatternCounters>,
+    limit: u64,
+    savethresholds: bool,
+    count: fn(&mut PatternCounters) -> &mut u64,
+) -> u64 {
+    let mut excess = 0;
+    for counters in keyed_counters.values_mut() {
+        let count = count(counters);
+        excess += count.saturating_sub(limit);
+        if savethresholds && *count >= limit {
+            *count -= limit;
+        }
+    }
+    excess
+}
+
+// whether an excluded line would have been a critical or warning match
+fn is_suppressed_match(tag: &Tag, line: &str, json_format: bool) -> bool {
+    if !tag.is_candidate(line) {
+        return false;
+    }
+    let json = if json_format {
+        JsonLine::parse(line)
+    } else {
+        None
+    };
+    tag.is_match_json(line, json.as_ref())
+        .is_some_and(|pattern_match| pattern_match.pattern_type != PatternType::ok)
+}
+
+// reads a line, depending on the encoding of the logfile. Returns the number of bytes read and the number of NUL bytes
+// skipped
+fn read_line<R: BufRead>(
+    reader: &mut R,
+    buffer: &mut Vec<u8>,
+    encoding: Option<Encoding>,
+) -> std::io::Result<(usize, usize)> {
+    match encoding {
+        Some(Encoding::utf16le) => read_line_utf16(reader, buffer, [b'\n', 0]),
+        Some(Encoding::utf16be) => read_line_utf16(reader, buffer, [0, b'\n']),
+        _ => read_line_skip_nul(reader, buffer),
+    }
+}
+
+// reads a UTF-16 line until the '\n' code unit, which is included in the buffer. NUL bytes are part of the characters,
+// so none is skipped
+fn read_line_utf16<R: BufRead>(
+    reader: &mut R,
+    buffer: &mut Vec<u8>,
+    newline: [u8; 2],
+) -> std::io::Result<(usize, usize)> {
+    let start = buffer.len();
+    while reader.read_until(newline[1], buffer)? != 0 {
+        let line = &buffer[start..];
+        if line.len().is_multiple_of(2) && line.ends_with(&newline) {
+            break;
+        }
+    }
+
+    Ok((buffer.len() - start, 0))
+}
+
+// reads a line like `read_until(b'\n')`, but without appending NUL bytes to the buffer, so megabytes of NUL padding
+// are skipped without being kept in memory. Returns the number of bytes read, including NUL bytes, and the number
+// of NUL bytes skipped
+fn read_line_skip_nul<R: BufRead>(
+    reader: &mut R,
+    buffer: &mut Vec<u8>,
+) -> std::io::Result<(usize, usize)> {
+    let mut bytes_read = 0;
+    let mut nul_bytes = 0;
+
+    loop {
+        let available = match reader.fill_buf() {
+            Ok(available) => available,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if available.is_empty() {
+            break;
+        }
+
+        let (eol, used) = match available.iter().position(|b| *b == b'\n') {
+            Some(pos) => (true, pos + 1),
+            None => (false, available.len()),
+        };
+
+        let before = buffer.len();
+        buffer.extend(available[..used].iter().filter(|b| **b != 0));
+        nul_bytes += used - (buffer.len() - before);
+
+        reader.consume(used);
+        bytes_read += used;
+
+        if eol {
+            break;
+        }
+    }
+
+    Ok((bytes_read, nul_bytes))
+}
+
+// a line read ahead, with the number of bytes read and NUL bytes skipped
+type HeldLine = (Vec<u8>, usize, usize);
+
+// reads a multiline record: lines are read until one is not a continuation of the record. This line is held to start
+// the next record. Returns the number of bytes read, the number of NUL bytes skipped and the number of lines of the
+// record. The last record of the logfile is only returned if complete, otherwise it's read again at next run
+fn read_record_skip_nul<R: BufRead>(
+    reader: &mut R,
+    buffer: &mut Vec<u8>,
+    held: &mut Option<HeldLine>,
+    multiline: &Multiline,
+    encoding: Option<Encoding>,
+    path: &Path,
+) -> std::io::Result<(usize, usize, u64)> {
+    // the first line of the record is the one held, if any
+    let (first, mut bytes_read, mut nul_bytes) = match held.take() {
+        Some(line) => line,
+        None => {
+            let mut line = Vec::new();
+            let (bytes_read, nul_bytes) = read_line(reader, &mut line, encoding)?;
+            (line, bytes_read, nul_bytes)
+        }
+    };
+    buffer.extend_from_slice(&first);
+
+    // EOF or NUL padding are returned as is
+    if bytes_read == 0 || nul_bytes == bytes_read {
+        return Ok((bytes_read, nul_bytes, 1));
+    }
+
+    let mut lines = 1;
+    loop {
+        let mut line = Vec::new();
+        let (next_read, next_nul) = read_line(reader, &mut line, encoding)?;
+
+        // the end of the logfile is reached while the record could still be written
+        if next_read == 0 || next_nul == next_read {
+            if next_read != 0 {
+                *held = Some((line, next_read, next_nul));
+            }
+            if multiline.is_idle(path) {
+                return Ok((bytes_read, nul_bytes, lines));
+            }
+            buffer.clear();
+            *held = None;
+            return Ok((0, 0, 0));
+        }
+
+        let text = match encoding {
+            Some(encoding) => encoding.decode(&line).0,
+            None => String::from_utf8_lossy(&line),
+        };
+        if lines >= multiline.max_lines as u64 || !multiline.is_continuation(text.trim_end()) {
+            *held = Some((line, next_read, next_nul));
+            return Ok((bytes_read, nul_bytes, lines));
+        }
+
+        buffer.extend_from_slice(&line);
+        bytes_read += next_read;
+        nul_bytes += next_nul;
+        lines += 1;
+    }
 }
 
 impl Lookup<BypassReader> for LogFile {
-    /// In this case, the reader just read each line and prints out the lines matching the regexes.
-    /// No computation of counters in made
-    /// TODO: add line number
+    /// In this case, the reader just read each line and prints out the lines matching the regexes, along with
+    /// their line number and byte offset. No computation of counters in made
     fn reader<R: BufRead + Seeker>(
         &mut self,
-        reader: R,
+        mut reader: R,
         tag: &Tag,
-        _global_options: &GlobalOptions,
+        global_options: &GlobalOptions,
     ) -> AppResult<Vec<ChildData>> {
-        for (line_number, line) in reader.lines().enumerate() {
-            let text = {
-                if let Err(e) = line {
+        let mut buffer = Vec::with_capacity(DEFAULT_STRING_CAPACITY);
+        let mut line_number = 0;
+        let mut offset = 0;
+
+        loop {
+            // give up if the search timeout is reached
+            check_deadline(self.deadline, &self.id.canon_path)?;
+
+            let (bytes_read, _) = read_line(&mut reader, &mut buffer, self.definition.encoding)
+                .map_err(|e| {
                     error!(
                         "error {} reading logfile {} using BypassReader",
                         e,
                         &self.id.canon_path.display()
                     );
-                    return Err(AppError::from_error(
+                    context!(
                         e,
-                        &format!(
-                            "error reading logfile {:?} at line {}",
-                            self.id.canon_path, line_number
-                        ),
-                    ));
-                }
-                line.unwrap()
-            };
+                        "error reading logfile {:?} at line {}",
+                        self.id.canon_path,
+                        line_number
+                    )
+                })?;
 
-            // is there a match ?
-            if let Some(pattern_match) = tag.is_match(&text) {
-                // print out also captures
-                let mut vars = RuntimeVars::default();
-                vars.insert_captures(pattern_match.regex, &text);
+            // EOF
+            if bytes_read == 0 {
+                break;
+            }
+            line_number += 1;
+
+            // header lines are not matched
+            if line_number <= self.definition.skip_header_lines {
+                offset += bytes_read as u64;
+                buffer.clear();
+                continue;
+            }
 
-                // cap0 is the whole match, no need to keep it as the full line is printed anyway
-                vars.retain(|k, _| k != &String::from("CLF_CAPTURE0"));
+            if offset == 0 {
+                LogFile::strip_bom(&mut buffer);
+            }
 
-                eprintln!(
-                    "{}:{}:{}:{}:[{}]:{}",
-                    &self.id.canon_path.display(),
+            let mut line = match self.definition.encoding {
+                Some(encoding) => encoding.decode(&buffer).0,
+                None => String::from_utf8_lossy(&buffer),
+            };
+            LogFile::purge_line(&mut line);
+
+            // is there a match ?
+            if !tag.is_candidate(&line) {
+                offset += bytes_read as u64;
+                buffer.clear();
+                continue;
+            }
+            let json = if self.definition.format == LogFileFormat::json {
+                JsonLine::parse(&line)
+            } else {
+                None
+            };
+            if let Some(pattern_match) = tag.is_match_json(&line, json.as_ref()) {
+                let record = MatchRecord::new(
+                    &self.id.canon_path,
                     &tag.name,
-                    <&str>::from(&pattern_match.pattern_type),
+                    &self.labels,
+                    &pattern_match,
                     line_number,
-                    vars,
-                    text
+                    offset,
+                    &line,
                 );
+                eprintln!("{}", record.format(&global_options.output_format)?);
             }
+
+            offset += bytes_read as u64;
+            buffer.clear();
         }
 
         Ok(Vec::new())
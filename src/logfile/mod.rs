@@ -2,10 +2,27 @@
 #[macro_use]
 #[warn(clippy::module_inception)]
 pub mod logfile;
+pub mod annotate;
 pub mod compression;
+pub mod container;
+pub mod context;
+pub mod deadline;
+#[cfg(feature = "windows-eventlog")]
+pub mod eventlog;
+pub mod journal;
+pub mod jsonline;
 pub mod logfileerror;
 pub mod logfileid;
+pub mod lookahead;
 pub mod lookup;
+pub mod output;
+pub mod progress;
+pub mod record;
+pub mod remote;
 pub mod rundata;
 pub mod seeker;
 pub mod snapshot;
+pub mod sqlite;
+pub mod stdin;
+pub mod trace;
+pub mod watcher;
@@ -0,0 +1,442 @@
+//! The output files, where matched lines of tags with the `keepoutput` option are written, so the raw evidence of each
+//! run is kept. Files are created in the output directory at the first matched line, with a name built from a template:
+//! with the {logfile} and {tag} placeholders, each tag has its own output file, appended across runs, otherwise there's
+//! one for each run. Output files older than the retention time are deleted when the first one is opened.
+//!
+//! The protocol files of a run are built the same way, for tags with the `protocol` option, like the protocol files of
+//! *check_logfiles*. Files are rotated once they reach their maximum size: a full file is renamed with a .1, .2, ..
+//! suffix.
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Local};
+use log::{debug, error, info};
+
+use crate::configuration::global::GlobalOptions;
+use crate::context;
+use crate::logfile::record::{MatchRecord, OutputFormat};
+use crate::misc::error::{AppError, AppResult};
+use crate::misc::util::hostname;
+
+/// The files of the current run where matched lines are written, shared by all logfiles searched.
+#[derive(Debug)]
+pub struct RunOutputs {
+    /// the output files, for tags with the `keepoutput` option
+    pub output: Arc<OutputFile>,
+
+    /// the protocol files, for tags with the `protocol` option
+    pub protocol: Arc<OutputFile>,
+}
+
+impl RunOutputs {
+    /// Prepares the output and protocol files of this run from the global options.
+    pub fn new(global: &GlobalOptions) -> RunOutputs {
+        RunOutputs {
+            output: Arc::new(OutputFile::new(global)),
+            protocol: Arc::new(OutputFile::protocol(global)),
+        }
+    }
+}
+
+/// The output files of the current run, shared by all logfiles searched.
+#[derive(Debug)]
+pub struct OutputFile {
+    /// what the file is for, in messages
+    kind: &'static str,
+
+    /// directory where output files are created
+    dir: PathBuf,
+
+    /// file name template
+    template: String,
+
+    /// output files older than this number of seconds are deleted. 0 means they're kept
+    retention: u64,
+
+    /// format of the matched lines
+    format: OutputFormat,
+
+    /// a file is rotated once it reaches this size in bytes. 0 means it's never rotated
+    max_size: u64,
+
+    /// time of the run, used in the file name
+    start: DateTime<Local>,
+
+    /// the files opened at their first matched line
+    files: Mutex<OpenFiles>,
+}
+
+// the files opened during the run, and whether old files were deleted
+#[derive(Debug, Default)]
+struct OpenFiles {
+    purged: bool,
+    files: HashMap<PathBuf, File>,
+}
+
+impl OutputFile {
+    /// Prepares the output file of this run from the global options. Nothing is created until a line is written.
+    pub fn new(global: &GlobalOptions) -> OutputFile {
+        OutputFile {
+            kind: "output",
+            dir: global.output_dir.clone(),
+            template: global.output_file.clone(),
+            retention: global.output_retention,
+            format: global.output_format.clone(),
+            max_size: global.output_max_size,
+            start: Local::now(),
+            files: Mutex::new(OpenFiles::default()),
+        }
+    }
+
+    /// Prepares the protocol file of this run from the global options, in the output directory. Matched lines are
+    /// written as text.
+    pub fn protocol(global: &GlobalOptions) -> OutputFile {
+        OutputFile {
+            kind: "protocol",
+            dir: global.output_dir.clone(),
+            template: global.protocol_file.clone(),
+            retention: global.protocol_retention,
+            format: OutputFormat::text,
+            max_size: global.protocol_max_size,
+            start: Local::now(),
+            files: Mutex::new(OpenFiles::default()),
+        }
+    }
+
+    /// Builds the file name of the lines of a logfile and tag from the template: {date} is replaced by the run date as
+    /// YYYYMMDD, {time} by the run time as HHMMSS, {pid} by the process id, {hostname} by the host name, {logfile} by
+    /// the logfile path and {tag} by the tag name, where path separators and spaces are replaced by underscores.
+    pub fn file_name(&self, logfile: &Path, tag: &str) -> String {
+        self.template
+            .replace("{date}", &self.start.format("%Y%m%d").to_string())
+            .replace("{time}", &self.start.format("%H%M%S").to_string())
+            .replace("{pid}", &std::process::id().to_string())
+            .replace("{hostname}", &hostname())
+            .replace("{logfile}", &name_part(&logfile.to_string_lossy()))
+            .replace("{tag}", &name_part(tag))
+    }
+
+    /// Appends a matched line to the output file of its logfile and tag, opening it if it's the first one, and returns
+    /// its path. A file which would grow beyond the maximum size is rotated first.
+    pub fn write(&self, record: &MatchRecord) -> AppResult<PathBuf> {
+        let line = record.format(&self.format)?;
+        let path = self.dir.join(self.file_name(record.path, record.tag));
+
+        let mut open_files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        if !open_files.purged {
+            open_files.purged = true;
+            if let Err(e) = self.purge() {
+                error!(
+                    "unable to delete old {} files in {:?}: {}",
+                    self.kind, self.dir, e
+                );
+            }
+        }
+
+        // a file could be appended to since a previous run
+        let size = std::fs::metadata(&path).map_or(0, |m| m.len());
+        if self.max_size != 0 && size != 0 && size + line.len() as u64 + 1 > self.max_size {
+            open_files.files.remove(&path);
+            self.rotate(&path)?;
+        }
+
+        if !open_files.files.contains_key(&path) {
+            let file = self.open(&path)?;
+            open_files.files.insert(path.clone(), file);
+        }
+
+        // unwrap is safe: the file was just opened
+        let file = open_files.files.get_mut(&path).unwrap();
+        writeln!(file, "{}", line)
+            .map_err(|e| context!(e, "unable to write to {} file {:?}", self.kind, path))?;
+        Ok(path)
+    }
+
+    // opens the output file to append lines to it
+    fn open(&self, path: &Path) -> AppResult<File> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .map_err(|e| context!(e, "unable to create {} file {:?}", self.kind, path))?;
+        info!("matched lines are written to {} file {:?}", self.kind, path);
+
+        Ok(file)
+    }
+
+    // renames the full file with the first suffix .1, .2, .. not used yet, so the next line is written to a new file
+    fn rotate(&self, path: &Path) -> AppResult<()> {
+        let rotated = (1..)
+            .map(|n| PathBuf::from(format!("{}.{}", path.display(), n)))
+            .find(|rotated| !rotated.exists())
+            .unwrap();
+        debug!("rotating {} file {:?} to {:?}", self.kind, path, rotated);
+        std::fs::rename(path, &rotated)
+            .map_err(|e| context!(e, "unable to rotate {} file {:?}", self.kind, path))
+    }
+
+    // deletes the output files older than the retention time. Output files are those whose name starts and ends like
+    // the template, outside of its placeholders, along with their rotated files
+    fn purge(&self) -> AppResult<()> {
+        let (prefix, suffix) = self.fixed_parts();
+        if self.retention == 0 || (prefix.is_empty() && suffix.is_empty()) {
+            return Ok(());
+        }
+        let oldest = SystemTime::now() - Duration::from_secs(self.retention);
+
+        let entries = std::fs::read_dir(&self.dir)
+            .map_err(|e| context!(e, "unable to read output directory {:?}", self.dir))?;
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let name = match name.rsplit_once('.') {
+                Some((name, n)) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => name,
+                _ => &name,
+            };
+            if name.len() < prefix.len() + suffix.len()
+                || !name.starts_with(prefix)
+                || !name.ends_with(suffix)
+            {
+                continue;
+            }
+
+            let modified = entry.metadata().and_then(|m| m.modified());
+            if matches!(modified, Ok(modified) if modified < oldest) {
+                debug!("deleting old {} file {:?}", self.kind, entry.path());
+                std::fs::remove_file(entry.path()).map_err(|e| {
+                    context!(e, "unable to delete {} file {:?}", self.kind, entry.path())
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // the parts of the template before the first placeholder and after the last one
+    fn fixed_parts(&self) -> (&str, &str) {
+        match (self.template.find('{'), self.template.rfind('}')) {
+            (Some(first), Some(last)) if first < last => {
+                (&self.template[..first], &self.template[last + 1..])
+            }
+            _ => (&self.template, ""),
+        }
+    }
+}
+
+// a logfile path or tag name as part of a file name
+fn name_part(name: &str) -> String {
+    name.trim_start_matches(['/', '\\'])
+        .replace(['/', '\\', ':', ' '], "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::pattern::{PatternMatchResult, PatternType};
+    use regex::Regex;
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
+    #[test]
+    fn output_file() {
+        let dir = std::env::temp_dir().join("clf_output_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+
+        let global = GlobalOptions {
+            output_dir: dir.clone(),
+            output_file: "clf_{date}_{pid}.out".to_string(),
+            ..Default::default()
+        };
+        let output = OutputFile::new(&global);
+
+        let name = output.file_name(Path::new("/var/log/syslog"), "error");
+        assert!(name.starts_with("clf_"));
+        assert!(name.ends_with(&format!("_{}.out", std::process::id())));
+        assert_eq!(output.fixed_parts(), ("clf_", ".out"));
+
+        // an old output file is deleted when the output file is created, but not the other files
+        std::fs::write(dir.join("clf_20200101_1.out"), "old").unwrap();
+        std::fs::write(dir.join("other.out"), "other").unwrap();
+        let old = SystemTime::now() - Duration::from_secs(global.output_retention + 10);
+        File::options()
+            .write(true)
+            .open(dir.join("clf_20200101_1.out"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        // nothing is created until a line is written
+        assert!(!dir.join(&name).exists());
+
+        let re = Regex::new(r"^ERROR (\d+)").unwrap();
+        let pattern_match = PatternMatchResult {
+            pattern_type: PatternType::critical,
+            regex: &re,
+            value: None,
+        };
+        let no_labels = BTreeMap::new();
+        let mut paths = Vec::new();
+        for line_number in 1..=2 {
+            let record = MatchRecord::new(
+                Path::new("/var/log/syslog"),
+                "error",
+                &no_labels,
+                &pattern_match,
+                line_number,
+                0,
+                "ERROR 42",
+            );
+            paths.push(output.write(&record).unwrap());
+        }
+
+        let path = dir.join(name);
+        assert_eq!(paths, vec![path.clone(), path.clone()]);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "/var/log/syslog:error:critical:1:0:[CLF_CG_1=42]:ERROR 42\n/var/log/syslog:error:critical:2:0:[CLF_CG_1=42]:ERROR 42\n"
+        );
+        assert!(!dir.join("clf_20200101_1.out").exists());
+        assert!(dir.join("other.out").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn protocol_file() {
+        let dir = std::env::temp_dir().join("clf_protocol_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+
+        let global = GlobalOptions {
+            output_dir: dir.clone(),
+            output_format: OutputFormat::ndjson,
+            protocol_file: "clf_{pid}.protocol".to_string(),
+            protocol_max_size: 100,
+            ..Default::default()
+        };
+        let protocol = OutputFile::protocol(&global);
+
+        // an old rotated protocol file is deleted
+        std::fs::write(dir.join("clf_1.protocol.1"), "old").unwrap();
+        let old = SystemTime::now() - Duration::from_secs(global.protocol_retention + 10);
+        File::options()
+            .write(true)
+            .open(dir.join("clf_1.protocol.1"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        let re = Regex::new(r"^ERROR (\d+)").unwrap();
+        let pattern_match = PatternMatchResult {
+            pattern_type: PatternType::critical,
+            regex: &re,
+            value: None,
+        };
+        let no_labels = BTreeMap::new();
+        let write = |line_number| {
+            let record = MatchRecord::new(
+                Path::new("/var/log/syslog"),
+                "error",
+                &no_labels,
+                &pattern_match,
+                line_number,
+                0,
+                "ERROR 42",
+            );
+            protocol.write(&record).unwrap()
+        };
+
+        // each line is written as text in 58 bytes, whatever the output format: the file is rotated at the second and third
+        // lines
+        let paths: Vec<_> = (1..=3).map(write).collect();
+        let path = paths[2].clone();
+        assert!(paths.iter().all(|p| p == &path));
+        assert_eq!(
+            path,
+            dir.join(format!("clf_{}.protocol", std::process::id()))
+        );
+        let line = |n| {
+            format!(
+                "/var/log/syslog:error:critical:{}:0:[CLF_CG_1=42]:ERROR 42\n",
+                n
+            )
+        };
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), line(3));
+        assert_eq!(
+            std::fs::read_to_string(format!("{}.1", path.display())).unwrap(),
+            line(1)
+        );
+        assert_eq!(
+            std::fs::read_to_string(format!("{}.2", path.display())).unwrap(),
+            line(2)
+        );
+        assert!(!dir.join("clf_1.protocol.1").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tag_output_files() {
+        let dir = std::env::temp_dir().join("clf_tag_output_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+
+        let global = GlobalOptions {
+            output_dir: dir.clone(),
+            output_max_size: 120,
+            ..Default::default()
+        };
+
+        let re = Regex::new(r"^ERROR").unwrap();
+        let pattern_match = PatternMatchResult {
+            pattern_type: PatternType::critical,
+            regex: &re,
+            value: None,
+        };
+        let no_labels = BTreeMap::new();
+        let write = |output: &OutputFile, tag, line_number| {
+            let record = MatchRecord::new(
+                Path::new("/var/log/my app.log"),
+                tag,
+                &no_labels,
+                &pattern_match,
+                line_number,
+                0,
+                "ERROR",
+            );
+            output.write(&record).unwrap()
+        };
+
+        // each tag has its own file
+        let output = OutputFile::new(&global);
+        let error_file = dir.join("clf_var_log_my_app.log_error.out");
+        let panic_file = dir.join("clf_var_log_my_app.log_panic.out");
+        assert_eq!(write(&output, "error", 1), error_file);
+        assert_eq!(write(&output, "panic", 2), panic_file);
+
+        // files are appended to at the next run, and rotated once full
+        let output = OutputFile::new(&global);
+        assert_eq!(write(&output, "error", 3), error_file);
+        assert_eq!(write(&output, "error", 4), error_file);
+
+        let line = |tag, n| format!("/var/log/my app.log:{}:critical:{}:0:[]:ERROR\n", tag, n);
+        assert_eq!(
+            std::fs::read_to_string(&error_file).unwrap(),
+            line("error", 4)
+        );
+        assert_eq!(
+            std::fs::read_to_string(format!("{}.1", error_file.display())).unwrap(),
+            line("error", 1) + &line("error", 3)
+        );
+        assert_eq!(
+            std::fs::read_to_string(&panic_file).unwrap(),
+            line("panic", 2)
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
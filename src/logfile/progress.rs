@@ -0,0 +1,152 @@
+//! The progress file of a snapshot, where the offsets reached while searching logfiles are saved along the way with the
+//! `snapshot_checkpoint` global option. The snapshot is only saved at the end of a run: if clf crashes or is killed
+//! while searching a large logfile, the progress file left next to the snapshot file lets the next run resume near
+//! where it stopped, instead of reading the logfile again and calling callbacks twice. The file is written to a
+//! temporary file and renamed, so it's never left truncated, and it's deleted once the snapshot is saved.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::context;
+use crate::logfile::{logfileid::LogFileID, rundata::RunData, snapshot::Snapshot};
+use crate::misc::error::{AppError, AppResult};
+
+/// The run data of the tags of a logfile, as they were at their last checkpoint.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct ProgressEntry {
+    /// the logfile searched
+    pub id: LogFileID,
+
+    /// run data of each tag
+    pub run_data: BTreeMap<String, RunData>,
+}
+
+/// The progress file of a snapshot file, shared by all logfiles searched with this snapshot.
+#[derive(Debug)]
+pub struct ProgressFile {
+    path: PathBuf,
+    entries: Mutex<BTreeMap<PathBuf, ProgressEntry>>,
+}
+
+impl ProgressFile {
+    /// The progress file of this snapshot file, named after it with the `.progress` extension.
+    pub fn new(snapshot_file: &Path) -> Self {
+        ProgressFile {
+            path: Snapshot::generation_name(snapshot_file, "progress"),
+            entries: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The path of the progress file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the entries left by a previous run, none if the file doesn't exist.
+    pub fn load(&self) -> AppResult<BTreeMap<PathBuf, ProgressEntry>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(e) => return Err(context!(e, "unable to open progress file {:?}", self.path)),
+        };
+
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| context!(e, "unable to load progress file {:?}", self.path))
+    }
+
+    /// Saves the run data of a tag of the logfile, along with those of the other logfiles and tags saved before.
+    pub fn save(&self, id: &LogFileID, tag_name: &str, run_data: &RunData) -> AppResult<()> {
+        // logfiles searched at the same time by several threads write the file in turn
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = entries
+            .entry(id.declared_path.clone())
+            .or_insert_with(|| ProgressEntry {
+                id: id.clone(),
+                ..Default::default()
+            });
+        entry
+            .run_data
+            .insert(tag_name.to_string(), run_data.clone());
+
+        let tmp_file = Snapshot::generation_name(&self.path, "tmp");
+        let file = File::create(&tmp_file)
+            .map_err(|e| context!(e, "unable to create progress file {:?}", tmp_file))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, &*entries)
+            .map_err(|e| context!(e, "unable to serialize progress file {:?}", tmp_file))?;
+        writer
+            .into_inner()
+            .map_err(|e| {
+                let e = e.into_error();
+                context!(e, "unable to write progress file {:?}", tmp_file)
+            })?
+            .sync_all()
+            .map_err(|e| context!(e, "unable to flush progress file {:?}", tmp_file))?;
+
+        Snapshot::replace(&tmp_file, &self.path)
+    }
+
+    /// Forgets all entries and deletes the file, once the snapshot is saved.
+    pub fn clear(&self) -> AppResult<()> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(context!(
+                e,
+                "unable to delete progress file {:?}",
+                self.path
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_file() {
+        let snapshot_file = std::env::temp_dir().join("clf_progress_test.json");
+        let progress = ProgressFile::new(&snapshot_file);
+        assert_eq!(
+            progress.path(),
+            std::env::temp_dir().join("clf_progress_test.json.progress")
+        );
+        progress.clear().unwrap();
+        assert!(progress.load().unwrap().is_empty());
+
+        let id = LogFileID {
+            declared_path: PathBuf::from("/var/log/syslog"),
+            ..Default::default()
+        };
+        let mut run_data = RunData {
+            last_offset: 100,
+            last_line: 10,
+            ..Default::default()
+        };
+        progress.save(&id, "error", &run_data).unwrap();
+        run_data.last_offset = 200;
+        progress.save(&id, "warning", &run_data).unwrap();
+        run_data.last_offset = 300;
+        progress.save(&id, "error", &run_data).unwrap();
+
+        // a new run reads what was saved
+        let entries = ProgressFile::new(&snapshot_file).load().unwrap();
+        let entry = &entries[Path::new("/var/log/syslog")];
+        assert_eq!(entry.id.declared_path, PathBuf::from("/var/log/syslog"));
+        assert_eq!(entry.run_data["error"].last_offset, 300);
+        assert_eq!(entry.run_data["warning"].last_offset, 200);
+        assert!(!Snapshot::generation_name(progress.path(), "tmp").exists());
+
+        progress.clear().unwrap();
+        assert!(!progress.path().exists());
+    }
+}
@@ -0,0 +1,186 @@
+//! A record of a matched line, shared by all outputs printing matches: either plain text or NDJSON (one JSON object per line).
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::configuration::{pattern::PatternMatchResult, vars::RuntimeVars};
+use crate::context;
+use crate::misc::error::{AppCustomErrorKind, AppError, AppResult};
+
+/// The format used to print out match records.
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+#[allow(non_camel_case_types)]
+pub enum OutputFormat {
+    #[default]
+    text,
+    ndjson,
+}
+
+/// Used from cli options.
+impl FromStr for OutputFormat {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::text),
+            "ndjson" => Ok(OutputFormat::ndjson),
+            _ => Err(AppError::new_custom(
+                AppCustomErrorKind::UnsupportedOutputFormat,
+                &format!("{} output format is not supported", s),
+            )),
+        }
+    }
+}
+
+/// All data of a matched line. Fields are always written in this order.
+#[derive(Debug, Serialize)]
+pub struct MatchRecord<'a> {
+    /// logfile path
+    pub path: &'a Path,
+
+    /// tag name
+    pub tag: &'a str,
+
+    /// search labels, omitted if none
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: &'a BTreeMap<String, String>,
+
+    /// critical, warning or ok
+    pub pattern_type: &'static str,
+
+    /// line number, starting from 1
+    pub line_number: u64,
+
+    /// byte offset of the beginning of the line. For compressed logfiles, this is an offset in the uncompressed data
+    pub offset: u64,
+
+    /// capture groups, sorted by name
+    pub captures: BTreeMap<String, String>,
+
+    /// the whole line, without its end of line
+    pub line: &'a str,
+}
+
+impl<'a> MatchRecord<'a> {
+    /// Builds a new record from a match.
+    pub fn new(
+        path: &'a Path,
+        tag: &'a str,
+        labels: &'a BTreeMap<String, String>,
+        pattern_match: &PatternMatchResult,
+        line_number: u64,
+        offset: u64,
+        line: &'a str,
+    ) -> Self {
+        let mut vars = RuntimeVars::default();
+        vars.insert_captures(pattern_match.regex, pattern_match.text(line));
+
+        // capture group 0 is the whole match, no need to keep it as the full line is printed anyway
+        let captures = vars
+            .iter()
+            .filter(|(k, _)| k != &"CLF_CG_0")
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        MatchRecord {
+            path,
+            tag,
+            labels,
+            pattern_type: <&str>::from(&pattern_match.pattern_type),
+            line_number,
+            offset,
+            captures,
+            line,
+        }
+    }
+
+    /// Formats the record as a single line, without end of line.
+    pub fn format(&self, format: &OutputFormat) -> AppResult<String> {
+        match format {
+            OutputFormat::text => {
+                let captures: Vec<_> = self
+                    .captures
+                    .iter()
+                    .map(|(k, v)| format!("{}={}", k, v))
+                    .collect();
+                Ok(format!(
+                    "{}:{}:{}:{}:{}:[{}]:{}",
+                    self.path.display(),
+                    self.tag,
+                    self.pattern_type,
+                    self.line_number,
+                    self.offset,
+                    captures.join(","),
+                    self.line
+                ))
+            }
+            OutputFormat::ndjson => serde_json::to_string(self).map_err(|e| {
+                context!(
+                    e,
+                    "unable to serialize record for line {}",
+                    self.line_number
+                )
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::pattern::PatternType;
+    use regex::Regex;
+
+    #[test]
+    fn format() {
+        let re = Regex::new(r"^ERROR (\d+) on (?P<host>\w+)").unwrap();
+        let pattern_match = PatternMatchResult {
+            pattern_type: PatternType::critical,
+            regex: &re,
+            value: None,
+        };
+        let path = Path::new("/var/log/syslog");
+        let no_labels = BTreeMap::new();
+        let record = MatchRecord::new(
+            path,
+            "error",
+            &no_labels,
+            &pattern_match,
+            12,
+            1024,
+            "ERROR 42 on server01",
+        );
+
+        assert_eq!(
+            record.format(&OutputFormat::text).unwrap(),
+            "/var/log/syslog:error:critical:12:1024:[CLF_CG_1=42,CLF_CG_host=server01]:ERROR 42 on server01"
+        );
+        assert_eq!(
+            record.format(&OutputFormat::ndjson).unwrap(),
+            r#"{"path":"/var/log/syslog","tag":"error","pattern_type":"critical","line_number":12,"offset":1024,"captures":{"CLF_CG_1":"42","CLF_CG_host":"server01"},"line":"ERROR 42 on server01"}"#
+        );
+
+        // labels are only written if any
+        let labels = BTreeMap::from([("team".to_string(), "db".to_string())]);
+        let record = MatchRecord::new(
+            path,
+            "error",
+            &labels,
+            &pattern_match,
+            12,
+            1024,
+            "ERROR 42 on server01",
+        );
+        assert!(record.format(&OutputFormat::ndjson).unwrap().starts_with(
+            r#"{"path":"/var/log/syslog","tag":"error","labels":{"team":"db"},"pattern_type""#
+        ));
+
+        assert_eq!(
+            OutputFormat::from_str("ndjson").unwrap(),
+            OutputFormat::ndjson
+        );
+        assert!(OutputFormat::from_str("xml").is_err());
+    }
+}
@@ -0,0 +1,291 @@
+//! A reader of logfiles of remote hosts, like appliances where clf can't be installed. The logfile is read with the
+//! `ssh` client, using the keys, agent and known hosts of the user running clf, from the offset it was read up to. The
+//! remote shell first prints the offset the logfile is read from, which is 0 when the logfile is shorter than the
+//! offset asked for: the logfile was rotated or truncated since. SFTP is not used: the bytes are streamed by `tail -c`
+//! on the remote host, so only a POSIX shell is needed there.
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::configuration::logsource::{RemoteFile, RemoteSource};
+use crate::context;
+use crate::logfile::seeker::Seeker;
+use crate::misc::error::{AppCustomErrorKind, AppError, AppResult};
+
+/// The bytes of a remote logfile, after the offset it's read from.
+pub struct RemoteReader<R> {
+    reader: R,
+    start: u64,
+    eof: bool,
+    child: Option<Child>,
+    stderr: Option<JoinHandle<String>>,
+}
+
+impl RemoteReader<BufReader<ChildStdout>> {
+    /// Starts `ssh` to read the remote logfile from this offset, within the time left before the search timeout if any.
+    pub fn spawn(source: &RemoteSource, offset: u64, timeout: Option<Duration>) -> AppResult<Self> {
+        let mut child = Command::new("ssh")
+            .args(ssh_args(&source.remote, offset, timeout))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| context!(e, "unable to start ssh for {}", source.key.display()))?;
+
+        let stdout = child.stdout.take().ok_or_else(|| {
+            AppError::new_custom(AppCustomErrorKind::RemoteError, "unable to read ssh output")
+        })?;
+
+        // errors are read along the way, so ssh never waits for its standard error to be read
+        let stderr = child.stderr.take().map(|mut output| {
+            thread::spawn(move || {
+                let mut stderr = String::new();
+                let _ = output.read_to_string(&mut stderr);
+                stderr
+            })
+        });
+
+        let mut reader = RemoteReader::new(BufReader::new(stdout));
+        reader.child = Some(child);
+        reader.stderr = stderr;
+
+        // without the offset, the reason is most likely found in the errors of ssh
+        match reader.read_start() {
+            Ok(()) => Ok(reader),
+            Err(e) => {
+                reader.finish()?;
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<R: BufRead> RemoteReader<R> {
+    /// Reads the bytes of a remote logfile, preceded by the line of the offset they start from.
+    pub fn new(reader: R) -> Self {
+        RemoteReader {
+            reader,
+            start: 0,
+            eof: false,
+            child: None,
+            stderr: None,
+        }
+    }
+
+    /// The offset the remote logfile is read from.
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// Reads the offset the remote logfile is read from, which comes before its bytes.
+    pub fn read_start(&mut self) -> AppResult<()> {
+        let mut line = String::new();
+        if self
+            .reader
+            .read_line(&mut line)
+            .map_err(|e| context!(e, "unable to read remote logfile",))?
+            == 0
+        {
+            self.eof = true;
+        }
+
+        let start = line.trim().parse::<u64>();
+        self.start = start.map_err(|e| context!(e, "unexpected remote offset {:?}", line))?;
+        Ok(())
+    }
+
+    /// Waits for `ssh` to end, which is an error if it failed. If the logfile was not read up to its end, like when
+    /// the search timeout is reached, it's stopped.
+    pub fn finish(mut self) -> AppResult<()> {
+        let mut child = match self.child.take() {
+            Some(child) => child,
+            None => return Ok(()),
+        };
+
+        if !self.eof {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(());
+        }
+
+        let status = child
+            .wait()
+            .map_err(|e| context!(e, "unable to wait for ssh",))?;
+        let stderr = self
+            .stderr
+            .take()
+            .and_then(|handle| handle.join().ok())
+            .unwrap_or_default();
+        if status.success() {
+            return Ok(());
+        }
+
+        Err(AppError::new_custom(
+            AppCustomErrorKind::RemoteError,
+            &format!("ssh {}: {}", status, stderr.trim()),
+        ))
+    }
+}
+
+impl<R: BufRead> Read for RemoteReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let size = available.len().min(buf.len());
+        buf[..size].copy_from_slice(&available[..size]);
+        self.consume(size);
+        Ok(size)
+    }
+}
+
+impl<R: BufRead> BufRead for RemoteReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let available = self.reader.fill_buf()?;
+        self.eof = available.is_empty();
+        Ok(available)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.reader.consume(amt)
+    }
+}
+
+/// The remote logfile is positioned when `ssh` is started: offsets are just counted from there.
+impl<R> Seeker for RemoteReader<R> {
+    fn set_offset(&mut self, offset: u64) -> AppResult<u64> {
+        Ok(offset)
+    }
+}
+
+// the arguments of `ssh` to read the remote logfile from the offset. Prompts would hang the search, so only keys are used.
+// With a search timeout, an unreachable host or a dead connection are given up before the timeout: the server is
+// checked 3 times, ssh's default ServerAliveCountMax, within the time left
+fn ssh_args(remote: &RemoteFile, offset: u64, timeout: Option<Duration>) -> Vec<String> {
+    let mut args = vec!["-o".to_string(), "BatchMode=yes".to_string()];
+
+    if let Some(timeout) = timeout {
+        let secs = timeout.as_secs().max(1);
+        args.push("-o".to_string());
+        args.push(format!("ConnectTimeout={}", secs));
+        args.push("-o".to_string());
+        args.push(format!("ServerAliveInterval={}", (secs / 3).max(1)));
+    }
+
+    if let Some(key) = &remote.key {
+        args.push("-i".to_string());
+        args.push(key.display().to_string());
+    }
+    if let Some(user) = &remote.user {
+        args.push("-l".to_string());
+        args.push(user.to_string());
+    }
+    args.push("--".to_string());
+    args.push(remote.host.to_string());
+    args.push(remote_command(&remote.path, offset));
+
+    args
+}
+
+// the command run by the remote shell: the offset the logfile is read from, followed by the bytes of the logfile
+fn remote_command(path: &Path, offset: u64) -> String {
+    let path = path.display().to_string().replace('\'', r"'\''");
+    format!(
+        r#"f='{}'; [ -r "$f" ] || {{ echo "unable to read $f" >&2; exit 1; }}; o={}; [ $(wc -c < "$f") -lt $o ] && o=0; echo $o; exec tail -c +$((o + 1)) "$f""#,
+        path, offset
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn reader() {
+        let mut reader = RemoteReader::new(&b"12\nfoo\nbar\n"[..]);
+        reader.read_start().unwrap();
+        assert_eq!(reader.start(), 12);
+
+        let mut lines = Vec::new();
+        let mut buffer = Vec::new();
+        while reader.read_until(b'\n', &mut buffer).unwrap() != 0 {
+            lines.push(std::mem::take(&mut buffer));
+        }
+        assert_eq!(lines, vec![b"foo\n".to_vec(), b"bar\n".to_vec()]);
+        assert!(reader.finish().is_ok());
+
+        // not an offset
+        let mut reader = RemoteReader::new(&b"unable to read\n"[..]);
+        assert!(reader.read_start().is_err());
+    }
+
+    #[test]
+    fn ssh_args() {
+        let remote = RemoteFile {
+            host: "appliance".to_string(),
+            user: Some("root".to_string()),
+            key: Some(PathBuf::from("/home/clf/.ssh/id_ed25519")),
+            path: PathBuf::from("/var/log/messages"),
+        };
+        let args = super::ssh_args(&remote, 12, None);
+        assert_eq!(
+            &args[..8],
+            &[
+                "-o",
+                "BatchMode=yes",
+                "-i",
+                "/home/clf/.ssh/id_ed25519",
+                "-l",
+                "root",
+                "--",
+                "appliance"
+            ]
+        );
+        assert_eq!(args[8], super::remote_command(&remote.path, 12));
+
+        // the host is given up within the search timeout
+        let args = super::ssh_args(&remote, 12, Some(Duration::from_secs(30)));
+        assert_eq!(
+            &args[..6],
+            &[
+                "-o",
+                "BatchMode=yes",
+                "-o",
+                "ConnectTimeout=30",
+                "-o",
+                "ServerAliveInterval=10"
+            ]
+        );
+        let args = super::ssh_args(&remote, 12, Some(Duration::from_millis(200)));
+        assert_eq!(
+            &args[3..6],
+            &["ConnectTimeout=1", "-o", "ServerAliveInterval=1"]
+        );
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn remote_command() {
+        let path = std::env::temp_dir().join("clf_remote_'test.log");
+        std::fs::write(&path, "line 1\nline 2\n").unwrap();
+
+        let run = |offset| {
+            let output = Command::new("sh")
+                .args(["-c", &super::remote_command(&path, offset)])
+                .output()
+                .unwrap();
+            (output.status.success(), output.stdout)
+        };
+
+        assert_eq!(run(0), (true, b"0\nline 1\nline 2\n".to_vec()));
+        assert_eq!(run(7), (true, b"7\nline 2\n".to_vec()));
+        assert_eq!(run(14), (true, b"14\n".to_vec()));
+
+        // the logfile is shorter: it's read from the beginning
+        assert_eq!(run(100), (true, b"0\nline 1\nline 2\n".to_vec()));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(!run(0).0);
+    }
+}
@@ -1,11 +1,22 @@
 //! A structure representing all the data specific to a run.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
 use chrono::prelude::*;
 use serde::{Deserialize, Serialize, Serializer};
 
-use crate::misc::error::AppError;
+use crate::misc::{
+    error::{AppError, AppResult},
+    util::{DEFAULT_STRING_CAPACITY, MAX_STRING_CAPACITY},
+};
+use crate::{context, prefix_var};
 
-use crate::configuration::options::SearchOptions;
+use crate::configuration::options::{DupWindow, RateLimit, RewindLimit, SearchOptions};
 use crate::configuration::pattern::{PatternCounters, PatternType};
+use crate::configuration::tolerance::Tolerance;
+use crate::configuration::vars::{PendingVars, VarType};
 
 /// A wrapper to store log file processing data.
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
@@ -25,6 +36,32 @@ pub struct RunData {
     /// last line number during the last search
     pub last_line: u64,
 
+    /// hash of the last processed line, if the `linehash` option is set
+    #[serde(default)]
+    pub last_line_hash: Option<u64>,
+
+    /// length in bytes of the last processed line, including the end of line
+    #[serde(default)]
+    pub last_line_length: u64,
+
+    /// length in bytes of the longest line read, halved at each run so a few long lines don't keep a large buffer
+    /// forever. The buffer lines are read into is sized after it
+    #[serde(default)]
+    pub max_line_length: usize,
+
+    /// offset up to which matches have been added to the saved counters, if the `savethresholds` option is set. When
+    /// the logfile is read again from an earlier offset, e.g. with `rewind`, those matches are not counted twice
+    #[serde(default)]
+    pub counted_offset: u64,
+
+    /// line number of the last line matching the regex of the `requires_before` option of the tag, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub required_line: Option<u64>,
+
+    /// cursor of the last entry processed, for a journal source. The next search resumes after this entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub journal_cursor: Option<String>,
+
     /// last time logfile were processed: printable date/time
     #[serde(serialize_with = "timestamp_to_string", skip_deserializing)]
     pub last_run: f64,
@@ -36,11 +73,215 @@ pub struct RunData {
     /// keep all counters here
     pub counters: PatternCounters,
 
+    /// critical and warning counters per capture group value, when the `criticalthreshold_per` or
+    /// `warningthreshold_per` option is set
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub keyed_counters: HashMap<String, PatternCounters>,
+
+    /// time spent in seconds searching the logfile for this tag during the last run
+    #[serde(default)]
+    pub scan_duration: f64,
+
+    /// number of NUL bytes skipped during the last run
+    #[serde(default)]
+    pub nul_bytes: u64,
+
+    /// lines evaluated, matched and excluded during the last run, only if `line_stats` is set for the tag
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line_stats: Option<LineStats>,
+
+    /// open incidents when alerts deduplication is set for the tag, keyed by the incident key
+    #[serde(default)]
+    pub incidents: HashMap<String, Incident>,
+
+    /// matches whose callback is not called yet, the oldest first. They're notified once the logfile is read, and
+    /// those not notified because of a callback error are notified at the next run
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pending_callbacks: Vec<PendingCallback>,
+
+    /// hashes of the last matches notified when the `sentset` option is set, the oldest first. A match read again is
+    /// not notified twice
+    #[serde(default, skip_serializing_if = "VecDeque::is_empty")]
+    pub sent: VecDeque<u64>,
+
+    /// first occurrences of matches when the `dedup` option is set, keyed by the hash of the line or capture groups
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub duplicates: HashMap<u64, Duplicate>,
+
+    /// time in seconds of the first critical or warning match, kept across runs and logfile rotations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<u64>,
+
+    /// time in seconds of the last critical or warning match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<u64>,
+
+    /// times in seconds of the last critical matches when an error budget is set for the tag, the oldest first. Only
+    /// one more than the budget is kept
+    #[serde(default, skip_serializing_if = "VecDeque::is_empty")]
+    pub critical_times: VecDeque<u64>,
+
+    /// times in seconds of the last callback calls when the `rate_limit` option is set, the oldest first
+    #[serde(default, skip_serializing_if = "VecDeque::is_empty")]
+    pub callback_times: VecDeque<u64>,
+
+    /// `true` if the critical matches of the current run are within the error budget, and reported as warnings
+    #[serde(skip)]
+    pub tolerated: bool,
+
+    /// line numbers of the critical and warning matches of the current run, only kept with the `overlap_check` global
+    /// option
+    #[serde(skip)]
+    pub matched_lines: Vec<u64>,
+
+    /// first line matched by a critical or warning pattern of a sentinel tag during the current run
+    #[serde(skip)]
+    pub sentinel_line: Option<String>,
+
+    /// the output file matched lines were written to with the `keepoutput` option, reported in the plugin output
+    #[serde(skip)]
+    pub output_file: Option<PathBuf>,
+
     // last error when reading a logfile
     #[serde(serialize_with = "error_to_string", skip_deserializing)]
     pub last_error: Option<AppError>,
 }
 
+/// A match whose callback is not called yet.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PendingCallback {
+    /// hash of the logfile, tag, line number and line of the match
+    pub hash: u64,
+
+    /// variables given to the callback
+    pub vars: PendingVars,
+
+    /// key of the incident the match is part of, when alerts are deduplicated. The incident is notified once the
+    /// callback is called
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub incident: Option<String>,
+}
+
+/// The first occurrence of a match, whose identical matches within the `dedup` window are not notified.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct Duplicate {
+    /// line number of the first occurrence
+    pub line: u64,
+
+    /// time in seconds of the first occurrence
+    pub time: u64,
+
+    /// hash of the first occurrence, the same as its pending callback
+    pub hash: u64,
+
+    /// number of identical matches found since
+    pub count: u64,
+}
+
+/// Line counters of a tag, to compare the number of matches with the number of lines read.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct LineStats {
+    /// number of lines checked against the patterns, or excluded
+    pub evaluated: u64,
+
+    /// number of lines matching a critical, warning or ok pattern
+    pub matched: u64,
+
+    /// number of lines skipped because of the exclude regex of the logfile
+    pub excluded: u64,
+}
+
+/// An incident is a group of identical matches, kept across runs until it's closed.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct Incident {
+    /// time in seconds when the incident was first found
+    pub first_seen: u64,
+
+    /// time in seconds of the last callback call for this incident, 0 if never called
+    pub last_notified: u64,
+
+    /// number of matches since the incident is open
+    pub count: u64,
+
+    /// number of consecutive runs without any match
+    pub missing_runs: u64,
+}
+
+// the offset of the first line starting at or after `offset`
+fn line_after(file: &mut File, offset: u64) -> AppResult<u64> {
+    if offset == 0 {
+        return Ok(0);
+    }
+
+    // the line starts after the first newline found from the byte preceding the offset
+    file.seek(SeekFrom::Start(offset - 1))
+        .map_err(|e| context!(e, "error seeking file at offset {}", offset - 1))?;
+    let mut buffer = [0u8; 4096];
+    let mut pos = offset - 1;
+    loop {
+        let n = file
+            .read(&mut buffer)
+            .map_err(|e| context!(e, "error reading file at offset {}", pos))?;
+        if n == 0 {
+            return Ok(pos);
+        }
+        if let Some(i) = buffer[..n].iter().position(|b| *b == b'\n') {
+            return Ok(pos + i as u64 + 1);
+        }
+        pos += n as u64;
+    }
+}
+
+// the offset of the beginning of the `n`th line before the end of file, read backwards
+fn nth_last_line(file: &mut File, size: u64, n: u64) -> AppResult<u64> {
+    let mut buffer = [0u8; 4096];
+    let mut end = size;
+    let mut found = 0;
+
+    // the newline ending the last line doesn't start a line
+    let mut last_byte = true;
+    while end > 0 && n > 0 {
+        let start = end.saturating_sub(buffer.len() as u64);
+        let chunk = &mut buffer[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))
+            .map_err(|e| context!(e, "error seeking file at offset {}", start))?;
+        file.read_exact(chunk)
+            .map_err(|e| context!(e, "error reading file at offset {}", start))?;
+
+        for (i, b) in chunk.iter().enumerate().rev() {
+            if *b == b'\n' && !last_byte {
+                found += 1;
+                if found == n {
+                    return Ok(start + i as u64 + 1);
+                }
+            }
+            last_byte = false;
+        }
+        end = start;
+    }
+
+    Ok(0)
+}
+
+// the number of lines between 2 offsets
+fn count_lines(file: &mut File, from: u64, to: u64) -> AppResult<u64> {
+    file.seek(SeekFrom::Start(from))
+        .map_err(|e| context!(e, "error seeking file at offset {}", from))?;
+
+    let mut buffer = [0u8; 65536];
+    let mut region = file.take(to - from);
+    let mut lines = 0;
+    loop {
+        let n = region
+            .read(&mut buffer)
+            .map_err(|e| context!(e, "error reading file at offset {}", from))?;
+        if n == 0 {
+            return Ok(lines);
+        }
+        lines += buffer[..n].iter().filter(|b| **b == b'\n').count() as u64;
+    }
+}
+
 /// Converts the timestamp to a human readable string in the snapshot.
 pub fn timestamp_to_string<S>(value: &f64, serializer: S) -> Result<S::Ok, S::Error>
 where
@@ -67,28 +308,391 @@ where
 }
 
 impl RunData {
-    /// increment or decrement counters
-    pub fn increment_counters(&mut self, pattern_type: &PatternType) {
+    /// increment counters
+    pub fn increment_counters(&mut self, pattern_type: &PatternType, key: Option<&str>) {
         match pattern_type {
             PatternType::critical => self.counters.critical_count += 1,
             PatternType::warning => self.counters.warning_count += 1,
             PatternType::ok => self.counters.ok_count += 1,
         }
+
+        // matches are also counted per capture group value
+        if let Some(key) = key {
+            let counters = self.keyed_counters.entry(key.to_string()).or_default();
+            match pattern_type {
+                PatternType::critical => counters.critical_count += 1,
+                PatternType::warning => counters.warning_count += 1,
+                PatternType::ok => counters.ok_count += 1,
+            }
+        }
     }
-    pub fn decrement_counters(&mut self, pattern_type: &PatternType) {
-        match pattern_type {
-            PatternType::critical => {
-                debug_assert!(self.counters.critical_count != 0);
-                self.counters.critical_count -= 1
+
+    /// Returns `true` if a callback call at `now` is within the rate limit, and keeps its time if so.
+    pub fn is_rate_allowed(&mut self, now: u64, rate_limit: &RateLimit) -> bool {
+        self.callback_times
+            .retain(|time| now.saturating_sub(*time) < rate_limit.window);
+        if self.callback_times.len() as u64 >= rate_limit.calls {
+            return false;
+        }
+        self.callback_times.push_back(now);
+        true
+    }
+
+    /// Keeps the hash of a match notified, along with the last `size` ones.
+    pub fn sent(&mut self, hash: u64, size: usize) {
+        self.sent.push_back(hash);
+        while self.sent.len() > size {
+            self.sent.pop_front();
+        }
+    }
+
+    /// Capacity of the buffer lines are read into: enough for the longest lines read during the last runs, up to
+    /// `MAX_STRING_CAPACITY` bytes. Longer lines still grow the buffer.
+    pub fn buffer_capacity(&self) -> usize {
+        if self.max_line_length == 0 {
+            DEFAULT_STRING_CAPACITY
+        } else {
+            self.max_line_length
+                .next_power_of_two()
+                .min(MAX_STRING_CAPACITY)
+        }
+    }
+
+    /// Counters reported in the plugin output: critical matches within the error budget are reported as warnings.
+    pub fn reported_counters(&self) -> PatternCounters {
+        let mut counters = self.counters.clone();
+        if self.tolerated {
+            counters.warning_count += counters.critical_count;
+            counters.critical_count = 0;
+        }
+        counters
+    }
+
+    /// Adds the critical matches found at `now` to those of the last day, and checks whether they're still within the
+    /// error budget.
+    pub fn tolerate(&mut self, criticals: u64, now: u64, tolerance: &Tolerance) {
+        let budget = tolerance.criticals_per_day;
+
+        self.critical_times
+            .retain(|time| now.saturating_sub(*time) < 24 * 3600);
+        for _ in 0..criticals.min(budget + 1) {
+            self.critical_times.push_back(now);
+        }
+        while self.critical_times.len() as u64 > budget + 1 {
+            self.critical_times.pop_front();
+        }
+
+        self.tolerated = self.critical_times.len() as u64 <= budget;
+    }
+
+    /// Checks whether the last processed line is still the same, by comparing its hash. If not, the line has been
+    /// edited in place, so offsets are moved back at the beginning of a line, at least `rescan_bytes` and
+    /// `rescan_lines` lines before the last line, to rescan it. Returns `true` if offsets have been moved back.
+    pub fn verify_last_line<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        rescan_bytes: u64,
+        rescan_lines: u64,
+    ) -> AppResult<bool> {
+        // nothing to verify if no hash was saved
+        let saved_hash = match self.last_line_hash {
+            None => return Ok(false),
+            Some(hash) => hash,
+        };
+        debug_assert!(self.last_line_length <= self.last_offset);
+        let line_start = self.last_offset - self.last_line_length;
+
+        let mut file = File::open(path.as_ref())
+            .map_err(|e| context!(e, "unable to open file {:?}", path.as_ref()))?;
+
+        // move back to the newline before the first line to rescan, if further than the bytes to move back. The file
+        // might be shorter than the last line
+        let mut rescan_start = line_start.saturating_sub(rescan_bytes);
+        if rescan_lines != 0 {
+            let size = file
+                .metadata()
+                .map_err(|e| context!(e, "unable to read metadata of file {:?}", path.as_ref()))?
+                .len()
+                .min(line_start);
+            let first_line = nth_last_line(&mut file, size, rescan_lines)?;
+            rescan_start = rescan_start.min(first_line.saturating_sub(1));
+        }
+
+        // read the whole region from the rescan point to the last offset
+        file.seek(SeekFrom::Start(rescan_start))
+            .map_err(|e| context!(e, "error seeking file {:?}", path.as_ref()))?;
+
+        let mut region = Vec::with_capacity((self.last_offset - rescan_start) as usize);
+        file.take(self.last_offset - rescan_start)
+            .read_to_end(&mut region)
+            .map_err(|e| context!(e, "error reading file {:?}", path.as_ref()))?;
+
+        // compare the last line hash with the current one. The file might also be shorter than expected
+        let split = region.len().min((line_start - rescan_start) as usize);
+        let (before, last_line) = region.split_at(split);
+        if crc::crc64::checksum_iso(last_line) == saved_hash {
+            return Ok(false);
+        }
+
+        // move back to the beginning of a line: skip the first partial line of the region, unless it's the beginning of file
+        let aligned = if rescan_start == 0 {
+            0
+        } else {
+            before
+                .iter()
+                .position(|b| *b == b'\n')
+                .map_or(before.len(), |pos| pos + 1)
+        };
+
+        // the number of lines to rescan is the last line and all full lines before it in the region
+        let lines_back = before[aligned..].iter().filter(|b| **b == b'\n').count() as u64 + 1;
+
+        self.last_offset = rescan_start + aligned as u64;
+        self.last_line = self.last_line.saturating_sub(lines_back);
+        self.last_line_hash = None;
+        self.last_line_length = 0;
+
+        Ok(true)
+    }
+
+    /// Looks for the last processed line in the last `window` bytes of a logfile which shrank, e.g. because a daemon
+    /// rewrote it without its first lines. If found, offsets are moved just after its last occurrence, the line number
+    /// being counted again from the beginning of the logfile. Returns `false` if the line is not found, offsets being
+    /// left unchanged.
+    pub fn relocate_last_line<P: AsRef<Path>>(&mut self, path: P, window: u64) -> AppResult<bool> {
+        // nothing to look for if no hash was saved
+        let saved_hash = match self.last_line_hash {
+            None => return Ok(false),
+            Some(hash) => hash,
+        };
+
+        let mut content = Vec::new();
+        File::open(path.as_ref())
+            .and_then(|mut file| file.read_to_end(&mut content))
+            .map_err(|e| context!(e, "error reading file {:?}", path.as_ref()))?;
+
+        // only lines starting in the window are candidates
+        let window_start = content.len().saturating_sub(window as usize);
+        let mut line_start = 0;
+        let mut found = None;
+        for line in content.split_inclusive(|b| *b == b'\n') {
+            if line_start >= window_start
+                && line.len() as u64 == self.last_line_length
+                && crc::crc64::checksum_iso(line) == saved_hash
+            {
+                found = Some(line_start + line.len());
             }
-            PatternType::warning => {
-                debug_assert!(self.counters.warning_count != 0);
-                self.counters.warning_count -= 1
+            line_start += line.len();
+        }
+
+        match found {
+            None => Ok(false),
+            Some(offset) => {
+                self.last_offset = offset as u64;
+                self.last_line = content[..offset].iter().filter(|b| **b == b'\n').count() as u64;
+                Ok(true)
             }
-            PatternType::ok => {
-                debug_assert!(self.counters.ok_count != 0);
-                self.counters.ok_count -= 1
+        }
+    }
+
+    /// Returns the offset and line number where a rewind starts, at most `limit` back from the end of the logfile. The
+    /// offset is aligned on the beginning of a line, and the line number is counted from the last offset saved. A
+    /// duration is converted to bytes using the rate the logfile was written at since the previous run: if unknown,
+    /// the logfile is read from the beginning.
+    pub fn rewind_start<P: AsRef<Path>>(
+        &self,
+        path: P,
+        limit: RewindLimit,
+        now: u64,
+    ) -> AppResult<(u64, u64)> {
+        let mut file = File::open(path.as_ref())
+            .map_err(|e| context!(e, "unable to open file {:?}", path.as_ref()))?;
+        let size = file
+            .metadata()
+            .map_err(|e| context!(e, "unable to read metadata of file {:?}", path.as_ref()))?
+            .len();
+
+        let start = match limit {
+            RewindLimit::Bytes(bytes) => line_after(&mut file, size.saturating_sub(bytes))?,
+            RewindLimit::Lines(lines) => nth_last_line(&mut file, size, lines)?,
+            RewindLimit::Seconds(secs) => {
+                let elapsed = now.saturating_sub(self.last_run_secs);
+                let written = size.saturating_sub(self.last_offset);
+                if self.last_run_secs == 0 || elapsed == 0 || written == 0 {
+                    return Ok((0, 0));
+                }
+                let bytes = (written as f64 / elapsed as f64 * secs as f64) as u64;
+                line_after(&mut file, size.saturating_sub(bytes))?
             }
+        };
+
+        // line numbers are known at the last offset, unless the logfile has been truncated since
+        let (offset, line) = if self.last_offset <= size {
+            (self.last_offset, self.last_line)
+        } else {
+            (0, 0)
+        };
+        let line = if start < offset {
+            line.saturating_sub(count_lines(&mut file, start, offset)?)
+        } else {
+            line + count_lines(&mut file, offset, start)?
+        };
+
+        Ok((start, line))
+    }
+
+    /// Timestamps later than `now` are set to `now`, because of a clock jump since they were saved. Returns the
+    /// number of timestamps clamped.
+    pub fn clamp_timestamps(&mut self, now: u64) -> u64 {
+        let mut clamped = 0;
+        let mut clamp = |timestamp: &mut u64| {
+            if *timestamp > now {
+                *timestamp = now;
+                clamped += 1;
+            }
+        };
+
+        clamp(&mut self.last_run_secs);
+        self.first_seen.iter_mut().for_each(&mut clamp);
+        self.last_seen.iter_mut().for_each(&mut clamp);
+        self.critical_times.iter_mut().for_each(&mut clamp);
+        self.callback_times.iter_mut().for_each(&mut clamp);
+        for duplicate in self.duplicates.values_mut() {
+            clamp(&mut duplicate.time);
+        }
+        for incident in self.incidents.values_mut() {
+            clamp(&mut incident.first_seen);
+            clamp(&mut incident.last_notified);
+        }
+
+        clamped
+    }
+
+    /// Records a critical or warning match found at `now`
+    pub fn seen(&mut self, now: u64) {
+        self.first_seen.get_or_insert(now);
+        self.last_seen = Some(now);
+    }
+
+    /// Records a match for the incident `key`, opening it if not yet known
+    pub fn incident_seen(&mut self, key: &str, now: u64) {
+        let incident = self
+            .incidents
+            .entry(key.to_string())
+            .or_insert_with(|| Incident {
+                first_seen: now,
+                ..Default::default()
+            });
+        incident.count += 1;
+        incident.missing_runs = 0;
+    }
+
+    /// Returns `true` if a match identical to the one of key `key` was found within the window, counting it. The count
+    /// is given as CLF_DUP_COUNT to the callback of the first occurrence, if not called yet. Otherwise, the match is
+    /// kept as the first occurrence of `hash`.
+    pub fn is_duplicate(
+        &mut self,
+        key: u64,
+        line: u64,
+        now: u64,
+        hash: u64,
+        window: &DupWindow,
+    ) -> bool {
+        match self.duplicates.get_mut(&key) {
+            Some(dup) if window.contains(dup.line, dup.time, line, now) => {
+                dup.count += 1;
+                let (hash, count) = (dup.hash, dup.count);
+                if let Some(pending) = self
+                    .pending_callbacks
+                    .iter_mut()
+                    .rev()
+                    .find(|p| p.hash == hash)
+                {
+                    pending
+                        .vars
+                        .insert(prefix_var!("DUP_COUNT").to_string(), VarType::Int(count));
+                }
+                true
+            }
+            _ => {
+                self.duplicates.insert(
+                    key,
+                    Duplicate {
+                        line,
+                        time: now,
+                        hash,
+                        count: 0,
+                    },
+                );
+                false
+            }
+        }
+    }
+
+    /// Forgets the first occurrences out of the window at line `line` and time `now`, or all of them without window.
+    pub fn expire_duplicates(&mut self, line: u64, now: u64, window: Option<&DupWindow>) {
+        match window {
+            None => self.duplicates.clear(),
+            Some(window) => self
+                .duplicates
+                .retain(|_, dup| window.contains(dup.line, dup.time, line, now)),
+        }
+    }
+
+    /// Return `true` if the incident `key` was never notified, or if the re-notify interval is elapsed
+    pub fn is_incident_notifiable(&self, key: &str, now: u64, renotify_interval: u64) -> bool {
+        match self.incidents.get(key) {
+            None => true,
+            Some(incident) => {
+                incident.last_notified == 0
+                    || (renotify_interval != 0
+                        && now.saturating_sub(incident.last_notified) >= renotify_interval)
+            }
+        }
+    }
+
+    /// Returns `true` if a callback call of the incident `key` is waiting to be done
+    pub fn is_incident_pending(&self, key: &str) -> bool {
+        self.pending_callbacks
+            .iter()
+            .any(|pending| pending.incident.as_deref() == Some(key))
+    }
+
+    /// Keeps a callback call to be done, along with those not done yet
+    pub fn queue_callback(&mut self, pending: PendingCallback) {
+        self.pending_callbacks.push(pending);
+    }
+
+    /// Once callbacks are called, keeps at most `max` calls left for the next run, the oldest ones being dropped
+    pub fn cap_pending_callbacks(&mut self, max: usize) {
+        let dropped = self.pending_callbacks.len().saturating_sub(max);
+        if dropped != 0 {
+            warn!(
+                "{} callback calls not done are dropped, only the last {} are kept",
+                dropped, max
+            );
+            self.pending_callbacks.drain(..dropped);
+        }
+    }
+
+    /// Records the callback call for the incident `key`
+    pub fn incident_notified(&mut self, key: &str, now: u64) {
+        if let Some(incident) = self.incidents.get_mut(key) {
+            incident.last_notified = now;
+        }
+    }
+
+    /// At the end of a run, incidents not seen are closed after `close_after` consecutive runs
+    pub fn close_incidents(&mut self, seen: &HashSet<String>, close_after: u64) {
+        for (key, incident) in self.incidents.iter_mut() {
+            if !seen.contains(key) {
+                incident.missing_runs += 1;
+            }
+        }
+
+        if close_after != 0 {
+            self.incidents
+                .retain(|_, incident| incident.missing_runs < close_after);
         }
     }
 
@@ -97,23 +701,37 @@ impl RunData {
         &mut self,
         pattern_type: &PatternType,
         options: &SearchOptions,
+        key: Option<&str>,
     ) -> bool {
         trace!(
-            "pattern_type={:?}, runifok={}",
+            "pattern_type={:?}, runifok={}, key={:?}",
             pattern_type,
-            options.runifok
+            options.runifok,
+            key
         );
+
+        // with a threshold per capture group value, only the counter of the match value is compared
+        let keyed = key.and_then(|key| self.keyed_counters.get(key));
+
         // increments thresholds and compare with possible defined limits and accumulate counters for plugin output
         match pattern_type {
             PatternType::critical => {
                 //self.counters.critical_count += 1;
-                if self.counters.critical_count <= options.criticalthreshold {
+                let reached = match (keyed, &options.criticalthreshold_per) {
+                    (Some(counters), Some(per)) => counters.critical_count > per.limit,
+                    _ => self.counters.critical_count > options.criticalthreshold,
+                };
+                if !reached {
                     return false;
                 }
             }
             PatternType::warning => {
                 //self.counters.warning_count += 1;
-                if self.counters.warning_count <= options.warningthreshold {
+                let reached = match (keyed, &options.warningthreshold_per) {
+                    (Some(counters), Some(per)) => counters.warning_count > per.limit,
+                    _ => self.counters.warning_count > options.warningthreshold,
+                };
+                if !reached {
                     return false;
                 }
             }
@@ -122,6 +740,7 @@ impl RunData {
                 //self.counters.ok_count += 1;
                 self.counters.critical_count = 0;
                 self.counters.warning_count = 0;
+                self.keyed_counters.clear();
 
                 // no need to process further: don't call a script if runifok is not set
                 return options.runifok;
@@ -135,6 +754,7 @@ impl RunData {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::convert::TryFrom;
 
     #[test]
     fn is_threshold_reached() {
@@ -146,19 +766,387 @@ mod tests {
 
         opts.criticalthreshold = 4;
         opts.warningthreshold = 4;
-        assert!(s.is_threshold_reached(&PatternType::critical, &opts));
+        assert!(s.is_threshold_reached(&PatternType::critical, &opts, None));
         //assert_eq!(s.counters.critical_count, 6);
 
         opts.criticalthreshold = 10;
         opts.warningthreshold = 10;
-        assert!(!s.is_threshold_reached(&PatternType::warning, &opts));
+        assert!(!s.is_threshold_reached(&PatternType::warning, &opts, None));
         //assert_eq!(s.counters.warning_count, 6);
 
         opts.criticalthreshold = 1;
         opts.warningthreshold = 1;
         opts.runifok = true;
-        assert!(s.is_threshold_reached(&PatternType::ok, &opts));
+        assert!(s.is_threshold_reached(&PatternType::ok, &opts, None));
         //assert_eq!(s.counters.critical_count, 0);
         //assert_eq!(s.counters.warning_count, 0);
     }
+
+    #[test]
+    fn is_keyed_threshold_reached() {
+        let opts =
+            SearchOptions::try_from("criticalthreshold_per=CLF_CG_host=2".to_string()).unwrap();
+        let mut s = RunData::default();
+
+        // matches of different hosts don't add up
+        for key in ["host=web1", "host=web1", "host=web2"] {
+            s.increment_counters(&PatternType::critical, Some(key));
+            assert!(!s.is_threshold_reached(&PatternType::critical, &opts, Some(key)));
+        }
+        assert_eq!(s.counters.critical_count, 3);
+
+        s.increment_counters(&PatternType::critical, Some("host=web1"));
+        assert!(s.is_threshold_reached(&PatternType::critical, &opts, Some("host=web1")));
+        assert_eq!(s.keyed_counters["host=web1"].critical_count, 3);
+
+        // counters are kept in the snapshot
+        let json = serde_json::to_string(&s).unwrap();
+        let saved: RunData = serde_json::from_str(&json).unwrap();
+        assert_eq!(saved.keyed_counters["host=web2"].critical_count, 1);
+
+        // an ok pattern resets all counters
+        s.is_threshold_reached(&PatternType::ok, &opts, None);
+        assert!(s.keyed_counters.is_empty());
+    }
+
+    #[test]
+    fn verify_last_line() {
+        let path = std::env::temp_dir().join("clf_verify_last_line.log");
+        std::fs::write(&path, "line1\nline2\nprogress 10%\n").unwrap();
+
+        let saved = RunData {
+            last_offset: 25,
+            last_line: 3,
+            last_line_length: 13,
+            last_line_hash: Some(crc::crc64::checksum_iso(b"progress 10%\n")),
+            ..Default::default()
+        };
+        let mut s = saved.clone();
+
+        // same line: nothing changes
+        assert!(!s.verify_last_line(&path, 0, 0).unwrap());
+        assert_eq!(s.last_offset, 25);
+        assert_eq!(s.last_line, 3);
+
+        // last line rewritten: move back at its beginning
+        std::fs::write(&path, "line1\nline2\nprogress 90%\n").unwrap();
+        assert!(s.verify_last_line(&path, 0, 0).unwrap());
+        assert_eq!(s.last_offset, 12);
+        assert_eq!(s.last_line, 2);
+
+        // move back some bytes more: a partial line is skipped
+        let mut s = saved.clone();
+        assert!(s.verify_last_line(&path, 4, 0).unwrap());
+        assert_eq!(s.last_offset, 12);
+        assert_eq!(s.last_line, 2);
+
+        // offset is aligned on the beginning of line2
+        let mut s = saved.clone();
+        assert!(s.verify_last_line(&path, 7, 0).unwrap());
+        assert_eq!(s.last_offset, 6);
+        assert_eq!(s.last_line, 1);
+
+        // move back one line more: line2 is rescanned too
+        let mut s = saved.clone();
+        assert!(s.verify_last_line(&path, 0, 1).unwrap());
+        assert_eq!(s.last_offset, 6);
+        assert_eq!(s.last_line, 1);
+
+        // the furthest of bytes and lines
+        let mut s = saved.clone();
+        assert!(s.verify_last_line(&path, 7, 1).unwrap());
+        assert_eq!(s.last_offset, 6);
+        assert_eq!(s.last_line, 1);
+
+        // more lines than before the last line: rescan from the beginning
+        let mut s = saved;
+        assert!(s.verify_last_line(&path, 0, 5).unwrap());
+        assert_eq!(s.last_offset, 0);
+        assert_eq!(s.last_line, 0);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn relocate_last_line() {
+        let path = std::env::temp_dir().join("clf_relocate_last_line.log");
+        let saved = RunData {
+            last_offset: 36,
+            last_line: 6,
+            last_line_length: 6,
+            last_line_hash: Some(crc::crc64::checksum_iso(b"line6\n")),
+            ..Default::default()
+        };
+
+        // the first 3 lines are removed, and new lines are written since
+        std::fs::write(&path, "line4\nline5\nline6\nline7\n").unwrap();
+        let mut s = saved.clone();
+        assert!(s.relocate_last_line(&path, 1024).unwrap());
+        assert_eq!(s.last_offset, 18);
+        assert_eq!(s.last_line, 3);
+
+        // the line is not in the window
+        let mut s = saved.clone();
+        assert!(!s.relocate_last_line(&path, 8).unwrap());
+        assert_eq!(s.last_offset, 36);
+
+        // nor in the logfile
+        std::fs::write(&path, "line7\nline8\n").unwrap();
+        let mut s = saved;
+        assert!(!s.relocate_last_line(&path, 1024).unwrap());
+        assert_eq!(s.last_line, 6);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn rewind_start() {
+        let path = std::env::temp_dir().join("clf_rewind_start.log");
+        std::fs::write(&path, "line1\nline2\nline3\nline4\n").unwrap();
+
+        // last read up to line3
+        let s = RunData {
+            last_offset: 18,
+            last_line: 3,
+            last_run_secs: 1000,
+            ..Default::default()
+        };
+
+        // a partial line is skipped
+        assert_eq!(
+            s.rewind_start(&path, RewindLimit::Bytes(15), 1000).unwrap(),
+            (12, 2)
+        );
+        assert_eq!(
+            s.rewind_start(&path, RewindLimit::Bytes(1000), 1000)
+                .unwrap(),
+            (0, 0)
+        );
+        assert_eq!(
+            s.rewind_start(&path, RewindLimit::Lines(3), 1000).unwrap(),
+            (6, 1)
+        );
+        assert_eq!(
+            s.rewind_start(&path, RewindLimit::Lines(1), 1000).unwrap(),
+            (18, 3)
+        );
+
+        // 6 bytes written in 60s: the last minute starts at line4, while the last 10 minutes are the whole logfile
+        assert_eq!(
+            s.rewind_start(&path, RewindLimit::Seconds(60), 1060)
+                .unwrap(),
+            (18, 3)
+        );
+        assert_eq!(
+            s.rewind_start(&path, RewindLimit::Seconds(600), 1060)
+                .unwrap(),
+            (0, 0)
+        );
+
+        // unknown rate
+        assert_eq!(
+            RunData::default()
+                .rewind_start(&path, RewindLimit::Seconds(60), 1060)
+                .unwrap(),
+            (0, 0)
+        );
+
+        // line numbers are counted forward from the last offset
+        let s = RunData {
+            last_offset: 6,
+            last_line: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            s.rewind_start(&path, RewindLimit::Lines(1), 1000).unwrap(),
+            (18, 3)
+        );
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn incidents() {
+        let mut s = RunData::default();
+
+        // a new incident is notified once
+        s.incident_seen("key1", 1000);
+        assert!(s.is_incident_notifiable("key1", 1000, 3600));
+        s.incident_notified("key1", 1000);
+        s.incident_seen("key1", 1010);
+        assert!(!s.is_incident_notifiable("key1", 1010, 3600));
+        assert_eq!(s.incidents["key1"].count, 2);
+        assert_eq!(s.incidents["key1"].first_seen, 1000);
+
+        // and again after the re-notify interval
+        assert!(s.is_incident_notifiable("key1", 4600, 3600));
+        assert!(!s.is_incident_notifiable("key1", 4600, 0));
+
+        // closed after 2 runs without any match
+        s.incident_seen("key2", 1000);
+        let mut seen = HashSet::new();
+        seen.insert("key2".to_string());
+        s.close_incidents(&seen, 2);
+        assert_eq!(s.incidents.len(), 2);
+        s.close_incidents(&seen, 2);
+        assert_eq!(s.incidents.len(), 1);
+        assert!(s.incidents.contains_key("key2"));
+    }
+
+    #[test]
+    fn queue_callback() {
+        let mut s = RunData::default();
+        for hash in 0..5 {
+            s.queue_callback(PendingCallback {
+                hash,
+                vars: PendingVars::default(),
+                incident: (hash == 4).then(|| "key1".to_string()),
+            });
+        }
+        assert_eq!(s.pending_callbacks.len(), 5);
+
+        // only the last calls are kept
+        s.cap_pending_callbacks(3);
+        let hashes: Vec<_> = s.pending_callbacks.iter().map(|p| p.hash).collect();
+        assert_eq!(hashes, vec![2, 3, 4]);
+        assert!(s.is_incident_pending("key1"));
+        assert!(!s.is_incident_pending("key2"));
+    }
+
+    #[test]
+    fn duplicates() {
+        let window = DupWindow::Lines(10);
+        let mut s = RunData::default();
+        s.pending_callbacks.push(PendingCallback {
+            hash: 0xabc,
+            vars: PendingVars::default(),
+            incident: None,
+        });
+
+        // the count of duplicates is given to the pending callback of the first occurrence
+        assert!(!s.is_duplicate(1, 5, 1000, 0xabc, &window));
+        assert!(s.is_duplicate(1, 8, 1000, 0xdef, &window));
+        assert!(s.is_duplicate(1, 14, 1000, 0xdef, &window));
+        assert_eq!(s.duplicates[&1].count, 2);
+        assert_eq!(
+            s.pending_callbacks[0].vars.get("CLF_DUP_COUNT"),
+            Some(&VarType::Int(2))
+        );
+
+        // out of the window, the match is a new first occurrence
+        assert!(!s.is_duplicate(1, 15, 1000, 0xdef, &window));
+        assert_eq!(s.duplicates[&1].hash, 0xdef);
+        assert!(!s.is_duplicate(2, 16, 1000, 0x123, &window));
+
+        s.expire_duplicates(25, 1000, Some(&window));
+        assert_eq!(s.duplicates.len(), 1);
+        assert!(s.duplicates.contains_key(&2));
+        s.expire_duplicates(25, 1000, None);
+        assert!(s.duplicates.is_empty());
+    }
+
+    #[test]
+    fn is_rate_allowed() {
+        let rate_limit = RateLimit {
+            calls: 2,
+            window: 60,
+        };
+        let mut s = RunData::default();
+
+        assert!(s.is_rate_allowed(1000, &rate_limit));
+        assert!(s.is_rate_allowed(1030, &rate_limit));
+        assert!(!s.is_rate_allowed(1050, &rate_limit));
+        assert_eq!(s.callback_times, [1000, 1030]);
+
+        // the window moves on
+        assert!(s.is_rate_allowed(1060, &rate_limit));
+        assert!(!s.is_rate_allowed(1089, &rate_limit));
+        assert!(s.is_rate_allowed(1090, &rate_limit));
+        assert_eq!(s.callback_times, [1060, 1090]);
+    }
+
+    #[test]
+    fn seen() {
+        let mut s = RunData::default();
+        s.seen(1000);
+        s.seen(2000);
+        assert_eq!((s.first_seen, s.last_seen), (Some(1000), Some(2000)));
+
+        // the last one is clamped after a clock jump back
+        assert_eq!(s.clamp_timestamps(1500), 1);
+        assert_eq!((s.first_seen, s.last_seen), (Some(1000), Some(1500)));
+    }
+
+    #[test]
+    fn sent() {
+        let mut s = RunData::default();
+        for hash in 1..=4 {
+            s.sent(hash, 3);
+        }
+        assert_eq!(s.sent, vec![2, 3, 4]);
+
+        let json = serde_json::to_string(&s).unwrap();
+        let s: RunData = serde_json::from_str(&json).unwrap();
+        assert_eq!(s.sent, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn tolerate() {
+        let tolerance = Tolerance {
+            criticals_per_day: 3,
+        };
+        let mut s = RunData::default();
+        s.counters.critical_count = 2;
+
+        // within the budget
+        s.tolerate(2, 1000, &tolerance);
+        assert!(s.tolerated);
+        let counters = s.reported_counters();
+        assert_eq!(counters.critical_count, 0);
+        assert_eq!(counters.warning_count, 2);
+        assert_eq!(s.counters.critical_count, 2);
+
+        // the budget is exhausted
+        s.tolerate(2, 2000, &tolerance);
+        assert!(!s.tolerated);
+        assert_eq!(s.critical_times, [1000, 1000, 2000, 2000]);
+        assert_eq!(s.reported_counters().critical_count, 2);
+
+        // a day later, only the last criticals are counted
+        s.tolerate(1, 1000 + 24 * 3600, &tolerance);
+        assert!(s.tolerated);
+        assert_eq!(s.critical_times.len(), 3);
+        s.tolerate(100, 2000 + 24 * 3600, &tolerance);
+        assert!(!s.tolerated);
+        assert_eq!(s.critical_times.len(), 4);
+    }
+
+    #[test]
+    fn buffer_capacity() {
+        let mut s = RunData::default();
+        assert_eq!(s.buffer_capacity(), DEFAULT_STRING_CAPACITY);
+
+        s.max_line_length = 80;
+        assert_eq!(s.buffer_capacity(), 128);
+        s.max_line_length = 5000;
+        assert_eq!(s.buffer_capacity(), 8192);
+        s.max_line_length = 1_000_000;
+        assert_eq!(s.buffer_capacity(), MAX_STRING_CAPACITY);
+    }
+
+    #[test]
+    fn clamp_timestamps() {
+        let mut s = RunData {
+            last_run_secs: 2000,
+            ..Default::default()
+        };
+        s.incident_seen("key", 1500);
+        s.incident_notified("key", 2500);
+
+        assert_eq!(s.clamp_timestamps(1800), 2);
+        assert_eq!(s.last_run_secs, 1800);
+        assert_eq!(s.incidents["key"].first_seen, 1500);
+        assert_eq!(s.incidents["key"].last_notified, 1800);
+        assert_eq!(s.clamp_timestamps(1800), 0);
+    }
 }
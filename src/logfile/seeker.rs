@@ -3,9 +3,15 @@
 use std::fs::File;
 use std::io::{BufReader, Read, Seek, SeekFrom};
 
+#[cfg(feature = "bzip2")]
 use bzip2::read::BzDecoder;
 use flate2::read::GzDecoder;
+#[cfg(feature = "lz4")]
+use lz4_flex::frame::FrameDecoder as Lz4Decoder;
+#[cfg(feature = "xz")]
 use xz2::read::XzDecoder;
+#[cfg(feature = "zstd")]
+use zstd::stream::read::Decoder as ZstdDecoder;
 
 use crate::context;
 use crate::misc::error::{AppCustomErrorKind, AppError, AppResult};
@@ -26,6 +32,13 @@ impl Seeker for BufReader<File> {
     }
 }
 
+/// A reader could be lent to the lookup, to be used again afterwards.
+impl<S: Seeker + ?Sized> Seeker for &mut S {
+    fn set_offset(&mut self, offset: u64) -> AppResult<u64> {
+        (**self).set_offset(offset)
+    }
+}
+
 /// Implementing for `R: Read` helps testing wuth `Cursor` type.
 impl<R> Seeker for BufReader<GzDecoder<R>>
 where
@@ -36,6 +49,7 @@ where
     }
 }
 
+#[cfg(feature = "bzip2")]
 impl<R> Seeker for BufReader<BzDecoder<R>>
 where
     R: Read,
@@ -45,6 +59,7 @@ where
     }
 }
 
+#[cfg(feature = "xz")]
 impl<R> Seeker for BufReader<XzDecoder<R>>
 where
     R: Read,
@@ -54,6 +69,26 @@ where
     }
 }
 
+#[cfg(feature = "zstd")]
+impl<R> Seeker for BufReader<ZstdDecoder<'static, BufReader<R>>>
+where
+    R: Read,
+{
+    fn set_offset(&mut self, offset: u64) -> AppResult<u64> {
+        _set_offset(self, offset)
+    }
+}
+
+#[cfg(feature = "lz4")]
+impl<R> Seeker for BufReader<Lz4Decoder<R>>
+where
+    R: Read,
+{
+    fn set_offset(&mut self, offset: u64) -> AppResult<u64> {
+        _set_offset(self, offset)
+    }
+}
+
 #[doc(hidden)]
 // This method is common to all compression ad-hoc seek method.
 fn _set_offset<R>(mut reader: R, offset: u64) -> AppResult<u64>
@@ -164,4 +199,32 @@ ZABCDEFGHIJKLMNOPQRSTUVWXY
             matches!(err.error_kind, InternalError::Custom(x) if x == AppCustomErrorKind::SeekPosBeyondEof)
         );
     }
+
+    #[test]
+    #[cfg(any(feature = "zstd", feature = "lz4"))]
+    fn set_offset_zstd_lz4() {
+        let data = "ABCDEFGHIJKLMNOPQRSTUVWXYZ\nBCDEFGHIJKLMNOPQRSTUVWXYZA\n";
+        let mut buffer = [0; 1];
+
+        #[cfg(feature = "zstd")]
+        {
+            let zstd_data = zstd::stream::encode_all(data.as_bytes(), 0).unwrap();
+            let mut reader = BufReader::new(ZstdDecoder::new(Cursor::new(zstd_data)).unwrap());
+            assert!(reader.set_offset(28).is_ok());
+            reader.read_exact(&mut buffer).unwrap();
+            assert_eq!(buffer[0], b'C');
+        }
+
+        #[cfg(feature = "lz4")]
+        {
+            let mut e = lz4_flex::frame::FrameEncoder::new(Vec::new());
+            e.write_all(data.as_bytes()).unwrap();
+            let lz4_data = e.finish().unwrap();
+            let mut reader = BufReader::new(Lz4Decoder::new(Cursor::new(lz4_data)));
+            assert!(reader.set_offset(1).is_ok());
+            reader.read_exact(&mut buffer).unwrap();
+            assert_eq!(buffer[0], b'B');
+            assert!(reader.set_offset(10000).is_err());
+        }
+    }
 }
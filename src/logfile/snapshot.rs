@@ -1,20 +1,41 @@
-//! A repository for all runtime logfile searches. These values are kept as a JSON file and reused each time the process is run.
-use std::collections::HashMap;
-use std::fmt::Debug;
+//! A repository for all runtime logfile searches. These values are kept as a JSON file, or a SQLite database, and reused each time the process is run.
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::{self, Debug};
 use std::fs::File;
-use std::io::{BufReader, ErrorKind};
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
-use log::debug;
+use chrono::{TimeZone, Utc};
+use log::{debug, error, warn};
 use serde::{Deserialize, Serialize};
 
-use crate::configuration::{logfiledef::LogFileDef, pattern::PatternCounters};
+use crate::configuration::{
+    email::{self, Digest},
+    global::GlobalOptions,
+    logfiledef::LogFileDef,
+    pattern::PatternCounters,
+    search::Search,
+};
+#[cfg(feature = "tera")]
+use crate::configuration::{template, vars::GlobalVars};
 use crate::context;
-use crate::logfile::{logfile::LogFile, logfileerror::LogFileAccessErrorList};
+use crate::logfile::{
+    compression::CompressionScheme,
+    logfile::{LogFile, TagOverlap},
+    logfileerror::LogFileAccessErrorList,
+    logfileid::LogFileID,
+    progress::{ProgressEntry, ProgressFile},
+    rundata::RunData,
+    sqlite,
+};
 use crate::misc::{
-    error::{AppError, AppResult},
-    nagios::{NagiosError, NagiosExit},
-    util::from_epoch_secs,
+    error::{AppCustomErrorKind, AppError, AppResult},
+    extension::{HashSpec, Signature},
+    nagios::{NagiosError, NagiosExit, RunSummary},
+    util::{from_epoch_secs, hostname},
 };
 
 /// This structure will keep all run time information for each logfile searched. This is
@@ -27,12 +48,161 @@ pub struct Snapshot {
 
     //last_run:
     snapshot: HashMap<PathBuf, LogFile>,
+
+    // named copies of the offsets of all tags, to later read logfiles again from this point
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    checkpoints: BTreeMap<String, Checkpoint>,
+
+    // summaries of the last runs, the oldest first
+    #[serde(default, skip_serializing_if = "VecDeque::is_empty")]
+    history: VecDeque<RunRecord>,
+
+    // e-mail digests which couldn't be sent, to be sent at the next run
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    digests: Vec<Digest>,
+
+    // number of timestamps found in the future when loading the snapshot, and set to the current time
+    #[serde(skip)]
+    clamped_count: u64,
+
+    // logfiles read from the database or last written to it: only those could be deleted from it, as other ones could
+    // have been added by another process
+    #[serde(skip)]
+    stored: HashSet<PathBuf>,
+
+    // where the offsets reached while searching logfiles are saved along the way
+    #[serde(skip)]
+    progress: Option<Arc<ProgressFile>>,
+}
+
+/// How the snapshot is stored. A snapshot is loaded whatever its format, so the backend could be changed at any time.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[allow(non_camel_case_types)]
+pub enum SnapshotBackend {
+    /// a JSON file, written at once
+    #[default]
+    json,
+
+    /// a SQLite database, where only the logfiles which changed are written
+    sqlite,
+}
+
+/// The offsets of all tags when a checkpoint was created.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Checkpoint {
+    /// time when the checkpoint was created, in seconds since the UNIX epoch
+    pub created: u64,
+
+    /// offsets of each logfile, by path
+    pub logfiles: HashMap<PathBuf, CheckpointLogfile>,
+}
+
+/// The offsets of the tags of a logfile when a checkpoint was created.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CheckpointLogfile {
+    /// signature of the logfile, to check it has not been rotated since the checkpoint
+    pub signature: Signature,
+
+    /// offsets of each tag, by tag name
+    pub tags: HashMap<String, TagOffsets>,
+}
+
+/// The position of the last line processed for a tag.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TagOffsets {
+    pub last_offset: u64,
+    pub last_line: u64,
+    pub last_line_hash: Option<u64>,
+    pub last_line_length: u64,
+}
+
+/// The summary of a run, kept in the history of the snapshot.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RunRecord {
+    /// time when the run ended, in seconds since the UNIX epoch
+    pub time: u64,
+
+    /// plugin exit status of the run
+    pub exit: String,
+
+    /// duration of the run in seconds
+    pub duration: f64,
+
+    /// counters of all tags of each logfile searched during the run
+    pub logfiles: BTreeMap<PathBuf, PatternCounters>,
+
+    /// tags which matched the same lines, for each logfile where they're found with the `overlap_check` global option
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub overlaps: BTreeMap<PathBuf, Vec<TagOverlap>>,
+}
+
+/// The report of a run sent to the report socket: the plugin status, along with the counters and offsets of each
+/// logfile and tag searched, and the logfiles which couldn't be read.
+#[derive(Debug, Serialize)]
+pub struct RunReport<'a> {
+    pub hostname: String,
+
+    /// time when the run ended, in seconds since the UNIX epoch
+    pub time: u64,
+
+    /// plugin exit status of the run
+    pub state: String,
+
+    /// duration of the run in seconds
+    pub duration: f64,
+
+    /// logfiles and tags searched, sorted by path and tag name
+    pub tags: Vec<TagReport<'a>>,
+
+    /// logfiles which couldn't be read, sorted by path
+    pub errors: Vec<ErrorReport<'a>>,
+}
+
+/// The result of a tag in the run report.
+#[derive(Debug, Serialize)]
+pub struct TagReport<'a> {
+    pub logfile: &'a Path,
+    pub tag: &'a str,
+    /// labels of the search, omitted if none
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: &'a BTreeMap<String, String>,
+    pub state: String,
+    #[serde(flatten)]
+    pub counters: &'a PatternCounters,
+    pub last_offset: u64,
+    pub last_line: u64,
+    pub scan_duration: f64,
+}
+
+/// A logfile access error in the run report.
+#[derive(Debug, Serialize)]
+pub struct ErrorReport<'a> {
+    pub logfile: &'a Path,
+    pub state: String,
+    pub error: String,
+}
+
+impl From<&RunData> for TagOffsets {
+    fn from(run_data: &RunData) -> Self {
+        TagOffsets {
+            last_offset: run_data.last_offset,
+            last_line: run_data.last_line,
+            last_line_hash: run_data.last_line_hash,
+            last_line_length: run_data.last_line_length,
+        }
+    }
 }
 
 impl Default for Snapshot {
     fn default() -> Self {
         Snapshot {
             snapshot: HashMap::new(),
+            checkpoints: BTreeMap::new(),
+            history: VecDeque::new(),
+            digests: Vec::new(),
+            clamped_count: 0,
+            stored: HashSet::new(),
+            progress: None,
         }
     }
 }
@@ -69,14 +239,58 @@ impl Snapshot {
         snapshot_file
     }
 
-    /// Deserialize a snapshot from a JSON file.
+    /// Deserialize a snapshot from a JSON file or a SQLite database.
     pub fn load<P: AsRef<Path> + Debug>(snapshot_file: P) -> AppResult<Snapshot> {
+        let mut snapshot = if sqlite::is_sqlite(snapshot_file.as_ref()) {
+            let stored = sqlite::load(snapshot_file.as_ref())?;
+            Snapshot {
+                stored: stored.logfiles.keys().cloned().collect(),
+                snapshot: stored.logfiles,
+                checkpoints: stored.checkpoints,
+                history: stored.history,
+                digests: stored.digests,
+                ..Default::default()
+            }
+        } else {
+            Snapshot::load_json(&snapshot_file)?.unwrap_or_default()
+        };
+
+        // a run which stopped before saving the snapshot left the offsets it reached in the progress file
+        let progress = ProgressFile::new(snapshot_file.as_ref());
+        match progress.load() {
+            Ok(entries) if !entries.is_empty() => {
+                let resumed = snapshot.resume(entries);
+                warn!(
+                    "previous run didn't complete: {} tags resumed from progress file {:?}",
+                    resumed,
+                    progress.path()
+                );
+            }
+            Ok(_) => (),
+            Err(e) => error!("{}", e),
+        }
+        snapshot.progress = Some(Arc::new(progress));
+
+        // the clock might have jumped back since last run
+        snapshot.clamp_timestamps(from_epoch_secs()?);
+        if snapshot.clamped_count != 0 {
+            warn!(
+                "!!! clock skew detected: {} timestamps in snapshot file {:?} are in the future and have been set to the current time",
+                snapshot.clamped_count, snapshot_file
+            );
+        }
+
+        Ok(snapshot)
+    }
+
+    // deserialize a JSON snapshot file, `None` if not found
+    fn load_json<P: AsRef<Path> + Debug>(snapshot_file: P) -> AppResult<Option<Snapshot>> {
         // open file, and create a new one if not found
         let json_file = match File::open(&snapshot_file) {
             Ok(file) => file,
             Err(e) => {
                 if e.kind() == ErrorKind::NotFound {
-                    return Ok(Snapshot::default());
+                    return Ok(None);
                 } else {
                     return Err(AppError::from_error(
                         e,
@@ -89,16 +303,61 @@ impl Snapshot {
         let reader = BufReader::new(json_file);
 
         // deserialize JSON
-        let snapshot: Snapshot = serde_json::from_reader(reader)
-            .map_err(|e| context!(e, "unable load snapshot file: {:?}", snapshot_file))?;
-        Ok(snapshot)
+        serde_json::from_reader(reader)
+            .map(Some)
+            .map_err(|e| context!(e, "unable load snapshot file: {:?}", snapshot_file))
+    }
+
+    // moves in the run data saved in the progress file, except those of a logfile rotated since the snapshot was saved:
+    // the rotation is then handled again from the offsets of the snapshot. Returns the number of tags resumed
+    fn resume(&mut self, entries: BTreeMap<PathBuf, ProgressEntry>) -> usize {
+        let mut resumed = 0;
+
+        for (path, entry) in entries {
+            let logfile = self.snapshot.entry(path).or_insert_with(|| LogFile {
+                id: entry.id.clone(),
+                ..Default::default()
+            });
+
+            let signature = &logfile.id.signature;
+            if signature.inode != entry.id.signature.inode
+                || signature.dev != entry.id.signature.dev
+            {
+                debug!(
+                    "logfile {:?} rotated since its checkpoint, not resumed",
+                    entry.id.declared_path
+                );
+                continue;
+            }
+
+            resumed += entry.run_data.len();
+            logfile.run_data.extend(entry.run_data);
+        }
+
+        resumed
+    }
+
+    /// The progress file of the snapshot, once loaded from its file.
+    pub fn progress(&self) -> Option<Arc<ProgressFile>> {
+        self.progress.clone()
+    }
+
+    /// Sets all timestamps later than `now` to `now`, and keeps the number of timestamps clamped.
+    pub fn clamp_timestamps(&mut self, now: u64) {
+        for logfile in self.snapshot.values_mut() {
+            for run_data in logfile.run_data.values_mut() {
+                self.clamped_count += run_data.clamp_timestamps(now);
+            }
+        }
     }
 
-    /// Serialize snapshot data to a JSON file.
+    /// Serialize snapshot data to a JSON file, or to a SQLite database.
     pub fn save<P: AsRef<Path> + Debug>(
         &mut self,
         snapshot_file: P,
         snapshot_retention: u64,
+        snapshot_generations: usize,
+        backend: SnapshotBackend,
     ) -> AppResult<()> {
         let seconds_from_epoch = from_epoch_secs()?;
 
@@ -106,21 +365,143 @@ impl Snapshot {
         debug!("checking retention time for snapshot");
         for logfile in self.snapshot.values_mut() {
             let run_data = logfile.rundata_mut();
-            run_data.retain(|_, v| seconds_from_epoch - v.last_run_secs < snapshot_retention);
+            run_data.retain(|_, v| {
+                seconds_from_epoch.saturating_sub(v.last_run_secs) < snapshot_retention
+            });
         }
 
         // because of before deletion, some logfiles might not include run_data anymore. So no need to keep them
         self.snapshot.retain(|_, v| !v.run_data.is_empty());
 
-        // then just saves this file.
-        let json_file = File::create(&snapshot_file)
-            .map_err(|e| context!(e, "unable create snapshot file: {:?}", snapshot_file))?;
-        serde_json::to_writer_pretty(json_file, self)
+        // once the snapshot is saved, the offsets of the progress file are outdated
+        let expired = |logfile: &LogFile| {
+            logfile
+                .run_data
+                .values()
+                .all(|v| seconds_from_epoch.saturating_sub(v.last_run_secs) >= snapshot_retention)
+        };
+        self.write(
+            snapshot_file.as_ref(),
+            snapshot_generations,
+            backend,
+            expired,
+        )?;
+        self.stored = self.snapshot.keys().cloned().collect();
+        match &self.progress {
+            Some(progress) => progress.clear(),
+            None => Ok(()),
+        }
+    }
+
+    // writes the snapshot to its file, keeping previous generations. Logfiles of the database not in the snapshot are
+    // deleted if they were stored by this process, or if `expired`
+    fn write(
+        &self,
+        snapshot_file: &Path,
+        snapshot_generations: usize,
+        backend: SnapshotBackend,
+        expired: impl Fn(&LogFile) -> bool,
+    ) -> AppResult<()> {
+        let removable =
+            |path: &Path, logfile: &LogFile| self.stored.contains(path) || expired(logfile);
+        let tmp_file = Snapshot::generation_name(snapshot_file, "tmp");
+
+        // a database is updated in place, unless it replaces a JSON file
+        if backend == SnapshotBackend::sqlite {
+            if !snapshot_file.exists() || sqlite::is_sqlite(snapshot_file) {
+                Snapshot::keep_generations(snapshot_file, snapshot_generations)?;
+                return sqlite::save(
+                    snapshot_file,
+                    &self.snapshot,
+                    removable,
+                    &self.checkpoints,
+                    &self.history,
+                    &self.digests,
+                );
+            }
+
+            let _ = std::fs::remove_file(&tmp_file);
+            sqlite::save(
+                &tmp_file,
+                &self.snapshot,
+                removable,
+                &self.checkpoints,
+                &self.history,
+                &self.digests,
+            )?;
+            Snapshot::keep_generations(snapshot_file, snapshot_generations)?;
+            return Snapshot::replace(&tmp_file, snapshot_file);
+        }
+
+        // then write a temporary file next to the snapshot file, and flush it to disk
+        let json_file = File::create(&tmp_file)
+            .map_err(|e| context!(e, "unable create snapshot file: {:?}", tmp_file))?;
+        let mut writer = BufWriter::new(json_file);
+        serde_json::to_writer_pretty(&mut writer, self)
             .map_err(|e| context!(e, "to_writer_pretty() error",))?;
+        writer
+            .into_inner()
+            .map_err(|e| {
+                let e = e.into_error();
+                context!(e, "unable to write snapshot file: {:?}", tmp_file)
+            })?
+            .sync_all()
+            .map_err(|e| context!(e, "unable to flush snapshot file: {:?}", tmp_file))?;
+
+        Snapshot::keep_generations(snapshot_file, snapshot_generations)?;
+        Snapshot::replace(&tmp_file, snapshot_file)
+    }
 
+    // keeps previous generations of the snapshot file: .1 is the most recent one
+    fn keep_generations(snapshot_file: &Path, snapshot_generations: usize) -> AppResult<()> {
+        if snapshot_generations != 0 && snapshot_file.exists() {
+            for i in (1..snapshot_generations).rev() {
+                let older = Snapshot::generation_name(snapshot_file, &i.to_string());
+                if older.exists() {
+                    let newer = Snapshot::generation_name(snapshot_file, &(i + 1).to_string());
+                    std::fs::rename(&older, &newer)
+                        .map_err(|e| context!(e, "unable to rename snapshot file: {:?}", older))?;
+                }
+            }
+
+            // copied rather than renamed, so the snapshot file is never missing
+            let previous = Snapshot::generation_name(snapshot_file, "1");
+            std::fs::copy(snapshot_file, &previous)
+                .map_err(|e| context!(e, "unable to copy snapshot file: {:?}", snapshot_file))?;
+        }
         Ok(())
     }
 
+    /// Replaces the snapshot file by the temporary one.
+    pub fn replace(tmp_file: &Path, snapshot_file: &Path) -> AppResult<()> {
+        // the snapshot file is replaced at once: it's either the previous one or the new one, never a truncated one
+        std::fs::rename(tmp_file, snapshot_file)
+            .map_err(|e| context!(e, "unable to rename snapshot file: {:?}", tmp_file))?;
+
+        // the rename itself is only durable once the directory is flushed
+        #[cfg(target_family = "unix")]
+        if let Some(dir) = snapshot_file.parent() {
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            File::open(dir)
+                .and_then(|d| d.sync_all())
+                .map_err(|e| context!(e, "unable to flush directory: {:?}", dir))?;
+        }
+
+        Ok(())
+    }
+
+    /// Builds the name of a temporary file or of a previous generation of the snapshot file, by adding an extension.
+    pub fn generation_name(snapshot_file: &Path, ext: &str) -> PathBuf {
+        let mut name = snapshot_file.as_os_str().to_os_string();
+        name.push(".");
+        name.push(ext);
+        PathBuf::from(name)
+    }
+
     /// Creates a new `LogfiFile` struct if not found, or retrieve an already stored one in
     /// the snapshot.
     pub fn logfile_mut(&mut self, path: &PathBuf, def: &LogFileDef) -> AppResult<&mut LogFile> {
@@ -131,7 +512,15 @@ impl Snapshot {
                 "snapshot is not containing path {:?}, creating a new entry",
                 path
             );
-            let logfile = LogFile::from_path(&path, Some(def.clone()))?;
+            let logfile = if def.path.is_journal()
+                || def.path.is_eventlog()
+                || def.path.is_remote()
+                || def.path.is_stdin()
+            {
+                LogFile::from_source(def.clone())
+            } else {
+                LogFile::from_path(path, Some(def.clone()))?
+            };
             let opt = self.snapshot.insert(path.clone(), logfile);
             debug_assert!(opt.is_none());
             debug_assert!(self.snapshot.contains_key(path));
@@ -148,21 +537,573 @@ impl Snapshot {
         Ok(logfile)
     }
 
-    /// Builds the final output message displayed by the plugin
-    pub fn exit_message(&self, access_errors: &LogFileAccessErrorList) -> NagiosError {
+    /// Moves the entry of a logfile, if any, to a new snapshot, so this logfile could be searched apart from the others.
+    pub fn take_shard(&mut self, path: &Path) -> Snapshot {
+        let mut shard = Snapshot {
+            progress: self.progress.clone(),
+            ..Default::default()
+        };
+        if let Some((path, logfile)) = self.snapshot.remove_entry(path) {
+            shard.snapshot.insert(path, logfile);
+        }
+        shard
+    }
+
+    /// Moves back the entries of a shard, once its logfile is searched.
+    pub fn merge_shard(&mut self, shard: Snapshot) {
+        self.snapshot.extend(shard.snapshot);
+    }
+
+    /// Iterates through all run data created by the current process, along with their logfile path and tag name
+    pub fn current_run_data(&self) -> impl Iterator<Item = (&PathBuf, &String, &RunData)> {
+        self.labeled_run_data()
+            .map(|(path, tag_name, run_data, _)| (path, tag_name, run_data))
+    }
+
+    /// Like `current_run_data`, along with the labels of the search of each logfile
+    pub fn labeled_run_data(
+        &self,
+    ) -> impl Iterator<Item = (&PathBuf, &String, &RunData, &BTreeMap<String, String>)> {
         let current_pid = std::process::id();
 
-        // calculate the summation of all pattern counts for all logfiles
-        let pattern_sum = self
+        self.snapshot.iter().flat_map(move |(path, logfile)| {
+            logfile
+                .run_data
+                .iter()
+                .filter(move |(_, run_data)| run_data.pid == current_pid)
+                .map(move |(tag_name, run_data)| (path, tag_name, run_data, &logfile.labels))
+        })
+    }
+
+    /// Cross-checks all entries against the filesystem and the searches using this snapshot, without reading any
+    /// logfile. Entries are returned sorted by path.
+    pub fn verify(&self, searches: &[&Search]) -> Vec<(&PathBuf, SnapshotIssue)> {
+        let mut paths: Vec<_> = self.snapshot.keys().collect();
+        paths.sort();
+
+        let mut issues = Vec::new();
+        for path in paths {
+            let logfile = &self.snapshot[path];
+
+            // the logfile is not searched anymore
+            let search = match searches.iter().find(|s| s.logfile.path() == path) {
+                Some(search) => search,
+                None => {
+                    issues.push((path, SnapshotIssue::Stale));
+                    continue;
+                }
+            };
+
+            // a journal, event log, remote source or the standard input has no local size
+            let source = &search.logfile.path;
+            let no_size = source.is_journal()
+                || source.is_eventlog()
+                || source.is_remote()
+                || source.is_stdin();
+            let size = match (no_size, path.metadata()) {
+                (true, _) => None,
+                (false, Ok(metadata)) => Some(metadata.len()),
+                (false, Err(_)) => {
+                    issues.push((path, SnapshotIssue::Missing));
+                    continue;
+                }
+            };
+
+            // signature is calculated using the hash window of the configuration file
+            let mut logfile = logfile.clone();
+            logfile.set_definition(search.logfile.clone());
+            match logfile.hash_been_rotated() {
+                Ok(true) => {
+                    issues.push((path, SnapshotIssue::Rotated));
+                    continue;
+                }
+                Ok(false) => (),
+                Err(e) => issues.push((path, SnapshotIssue::Undetermined(e.to_string()))),
+            }
+
+            let tag_names = search.tag_names();
+            let mut tags: Vec<_> = logfile.run_data.iter().collect();
+            tags.sort_by(|a, b| a.0.cmp(b.0));
+
+            for (tag_name, run_data) in tags {
+                if !tag_names.contains(&tag_name.as_str()) {
+                    issues.push((path, SnapshotIssue::UnknownTag(tag_name.clone())));
+                }
+                // offsets of compressed logfiles are in the uncompressed data, so can't be compared to the size
+                else if let Some(size) = size.filter(|size| {
+                    logfile.id.compression == CompressionScheme::Uncompressed
+                        && run_data.last_offset > *size
+                }) {
+                    issues.push((
+                        path,
+                        SnapshotIssue::OffsetBeyondSize {
+                            tag: tag_name.clone(),
+                            offset: run_data.last_offset,
+                            size,
+                        },
+                    ));
+                }
+            }
+        }
+
+        issues
+    }
+
+    /// Returns the number of tags checked, along with the tags whose last offset is beyond the logfile size. Only
+    /// existing and uncompressed logfiles are checked.
+    pub fn offsets_beyond_eof(&self) -> (usize, Vec<(&PathBuf, SnapshotIssue)>) {
+        let mut checked = 0;
+        let mut issues = Vec::new();
+
+        for (path, logfile) in &self.snapshot {
+            if logfile.id.compression != CompressionScheme::Uncompressed {
+                continue;
+            }
+            let size = match path.metadata() {
+                Ok(metadata) => metadata.len(),
+                Err(_) => continue,
+            };
+
+            for (tag_name, run_data) in &logfile.run_data {
+                checked += 1;
+                if run_data.last_offset > size {
+                    issues.push((
+                        path,
+                        SnapshotIssue::OffsetBeyondSize {
+                            tag: tag_name.clone(),
+                            offset: run_data.last_offset,
+                            size,
+                        },
+                    ));
+                }
+            }
+        }
+
+        (checked, issues)
+    }
+
+    /// Resets the offsets beyond the logfile size, so these logfiles are read again from their beginning. Returns the
+    /// number of tags reset.
+    pub fn heal_offsets(&mut self) -> usize {
+        let to_reset: Vec<_> = self
+            .offsets_beyond_eof()
+            .1
+            .into_iter()
+            .filter_map(|(path, issue)| match issue {
+                SnapshotIssue::OffsetBeyondSize { tag, .. } => Some((path.clone(), tag)),
+                _ => None,
+            })
+            .collect();
+
+        for (path, tag) in &to_reset {
+            if let Some(logfile) = self.snapshot.get_mut(path) {
+                logfile.reset_tag_offsets(tag);
+            }
+        }
+
+        to_reset.len()
+    }
+
+    /// Saves the offsets of all tags under the checkpoint `name`, replacing any checkpoint with the same name.
+    /// Returns the number of tags saved.
+    pub fn create_checkpoint(&mut self, name: &str, now: u64) -> usize {
+        let logfiles: HashMap<_, _> = self
+            .snapshot
+            .iter()
+            .map(|(path, logfile)| {
+                let tags = logfile
+                    .run_data
+                    .iter()
+                    .map(|(tag_name, run_data)| (tag_name.clone(), TagOffsets::from(run_data)))
+                    .collect();
+                let checkpoint = CheckpointLogfile {
+                    signature: logfile.id.signature.clone(),
+                    tags,
+                };
+                (path.clone(), checkpoint)
+            })
+            .collect();
+
+        let nb_tags = logfiles.values().map(|x| x.tags.len()).sum();
+        self.checkpoints.insert(
+            name.to_string(),
+            Checkpoint {
+                created: now,
+                logfiles,
+            },
+        );
+
+        nb_tags
+    }
+
+    /// Moves the offsets of all tags back to those of the checkpoint `name`, so logfiles are read again from this
+    /// point. Counters are kept. Tags of logfiles rotated since the checkpoint, or not searched anymore, are not
+    /// moved. Returns the number of tags moved, or `None` if there's no such checkpoint.
+    pub fn replay_from(&mut self, name: &str) -> Option<usize> {
+        let checkpoint = self.checkpoints.get(name)?;
+        let mut nb_tags = 0;
+
+        for (path, saved) in &checkpoint.logfiles {
+            let logfile = match self.snapshot.get_mut(path) {
+                Some(logfile) => logfile,
+                None => continue,
+            };
+
+            // offsets are those of another file
+            let signature = &logfile.id.signature;
+            if signature.inode != saved.signature.inode || signature.dev != saved.signature.dev {
+                warn!(
+                    "logfile {:?} has been rotated since checkpoint {}, it is not replayed",
+                    path, name
+                );
+                continue;
+            }
+
+            for (tag_name, offsets) in &saved.tags {
+                if let Some(run_data) = logfile.run_data.get_mut(tag_name) {
+                    run_data.last_offset = offsets.last_offset;
+                    run_data.last_line = offsets.last_line;
+                    run_data.last_line_hash = offsets.last_line_hash;
+                    run_data.last_line_length = offsets.last_line_length;
+                    nb_tags += 1;
+                }
+            }
+        }
+
+        Some(nb_tags)
+    }
+
+    /// Appends the summary of the current run to the history, keeping only the last `max_runs` runs.
+    pub fn record_run(&mut self, time: u64, exit: &NagiosError, duration: f64, max_runs: usize) {
+        let current_pid = std::process::id();
+        let searched = || {
+            self.snapshot
+                .iter()
+                .filter(|(_, logfile)| logfile.run_data.values().any(|x| x.pid == current_pid))
+        };
+        let logfiles = searched()
+            .map(|(path, logfile)| (path.clone(), logfile.sum_counters(current_pid)))
+            .collect();
+        let overlaps = searched()
+            .filter(|(_, logfile)| !logfile.overlaps.is_empty())
+            .map(|(path, logfile)| (path.clone(), logfile.overlaps.clone()))
+            .collect();
+
+        self.history.push_back(RunRecord {
+            time,
+            exit: String::from(exit),
+            duration,
+            logfiles,
+            overlaps,
+        });
+        while self.history.len() > max_runs {
+            self.history.pop_front();
+        }
+    }
+
+    /// Summaries of the last runs, the oldest first
+    pub fn history(&self) -> &VecDeque<RunRecord> {
+        &self.history
+    }
+
+    /// Takes the e-mail digests which couldn't be sent at a previous run, along with the matches for e-mail callbacks of
+    /// this run, logfile by logfile.
+    pub fn take_digests(&mut self) -> Vec<Digest> {
+        let mut digests = std::mem::take(&mut self.digests);
+
+        let mut paths: Vec<_> = self.snapshot.keys().cloned().collect();
+        paths.sort();
+        for path in paths {
+            if let Some(logfile) = self.snapshot.get_mut(&path) {
+                for digest in std::mem::take(&mut logfile.digests) {
+                    digest.collect(&mut digests);
+                }
+            }
+        }
+
+        digests
+    }
+
+    /// Sends the e-mail digests of the run, along with those not sent at a previous run. Those which can't be sent are
+    /// kept, to be sent at the next run.
+    pub fn send_digests(&mut self) {
+        let digests = self.take_digests();
+        let unsent = email::send_digests(digests);
+        self.keep_digests(unsent);
+    }
+
+    /// Keeps e-mail digests which couldn't be sent, to send them at the next run.
+    pub fn keep_digests(&mut self, digests: Vec<Digest>) {
+        self.digests.extend(digests);
+    }
+
+    /// Moves the entries of the logfiles under the `from` directory to the same paths under `to`, once these logfiles
+    /// were moved, so they're read on from their offsets. The canonical path and the signature of each entry are those
+    /// of the moved logfile, hashed with the spec given for its new path. Checkpoints and history follow. Returns the
+    /// old path of each entry found, sorted, along with its new canonical path or the reason it was not moved.
+    pub fn remap(
+        &mut self,
+        from: &Path,
+        to: &Path,
+        hash_spec: impl Fn(&Path) -> HashSpec,
+    ) -> Vec<(PathBuf, AppResult<PathBuf>)> {
+        let mut old_paths: Vec<_> = self
+            .snapshot
+            .keys()
+            .filter(|path| path.starts_with(from))
+            .cloned()
+            .collect();
+        old_paths.sort();
+
+        old_paths
+            .into_iter()
+            .map(|old_path| {
+                let new_path = to.join(old_path.strip_prefix(from).unwrap_or(&old_path));
+                let remapped = self.remap_logfile(&old_path, &new_path, hash_spec(&new_path));
+                (old_path, remapped)
+            })
+            .collect()
+    }
+
+    // moves the entry of a logfile to its new path
+    fn remap_logfile(
+        &mut self,
+        old_path: &Path,
+        new_path: &Path,
+        hash_spec: HashSpec,
+    ) -> AppResult<PathBuf> {
+        let mut id = LogFileID::default();
+        id.update(new_path, hash_spec)?;
+        let canon_path = id.canon_path.clone();
+        if self.snapshot.contains_key(&canon_path) {
+            return Err(AppError::new_custom(
+                AppCustomErrorKind::SnapshotConflict,
+                &format!("{:?} is already in the snapshot", canon_path),
+            ));
+        }
+
+        let mut logfile = self.snapshot.remove(old_path).unwrap();
+        logfile.id = id;
+        self.snapshot.insert(canon_path.clone(), logfile);
+
+        for checkpoint in self.checkpoints.values_mut() {
+            if let Some(mut saved) = checkpoint.logfiles.remove(old_path) {
+                saved.signature = self.snapshot[&canon_path].id.signature.clone();
+                checkpoint.logfiles.insert(canon_path.clone(), saved);
+            }
+        }
+        for run in self.history.iter_mut() {
+            if let Some(counters) = run.logfiles.remove(old_path) {
+                run.logfiles.insert(canon_path.clone(), counters);
+            }
+            if let Some(overlaps) = run.overlaps.remove(old_path) {
+                run.overlaps.insert(canon_path.clone(), overlaps);
+            }
+        }
+
+        Ok(canon_path)
+    }
+
+    /// Clears the counters and offsets of a tag of a logfile, or of all its tags if none is given, so the logfile is
+    /// read again from its beginning and thresholds are armed again. The logfile is found by its canonical or declared
+    /// path. Returns the number of tags reset.
+    pub fn reset(&mut self, path: &Path, tag_name: Option<&str>) -> usize {
+        let logfile = match self
             .snapshot
-            .values() // Vec<LogFile>
-            .map(|x| x.sum_counters(current_pid)) // Vec<PatternCounters>
+            .values_mut()
+            .find(|logfile| logfile.id.canon_path == path || logfile.id.declared_path == path)
+        {
+            Some(logfile) => logfile,
+            None => return 0,
+        };
+
+        let tags: Vec<_> = logfile
+            .run_data
+            .keys()
+            .filter(|name| tag_name.is_none_or(|tag_name| tag_name == name.as_str()))
+            .cloned()
+            .collect();
+        for tag in &tags {
+            logfile.reset_tag(tag);
+        }
+
+        tags.len()
+    }
+
+    /// Compares this snapshot to a newer one, tag by tag. Only tags added, removed or whose offsets or counters have
+    /// changed are returned, sorted by path and tag name.
+    pub fn diff<'a>(&'a self, new: &'a Snapshot) -> Vec<TagDiff<'a>> {
+        let mut paths: Vec<_> = self.snapshot.keys().chain(new.snapshot.keys()).collect();
+        paths.sort();
+        paths.dedup();
+
+        // a missing tag is compared as if it was never read
+        let never_read = RunData::default();
+
+        let mut diffs = Vec::new();
+        for path in paths {
+            let old_tags = self.snapshot.get(path).map(|x| &x.run_data);
+            let new_tags = new.snapshot.get(path).map(|x| &x.run_data);
+
+            let mut tags: Vec<_> = old_tags
+                .into_iter()
+                .chain(new_tags)
+                .flat_map(|x| x.keys())
+                .collect();
+            tags.sort();
+            tags.dedup();
+
+            for tag in tags {
+                let old = old_tags.and_then(|x| x.get(tag));
+                let new = new_tags.and_then(|x| x.get(tag));
+                let change = match (old, new) {
+                    (None, _) => DiffKind::added,
+                    (_, None) => DiffKind::removed,
+                    _ => DiffKind::changed,
+                };
+
+                let diff = TagDiff::new(
+                    path,
+                    tag,
+                    change,
+                    old.unwrap_or(&never_read),
+                    new.unwrap_or(&never_read),
+                );
+                if diff.change != DiffKind::changed || diff.has_changes() {
+                    diffs.push(diff);
+                }
+            }
+        }
+
+        diffs
+    }
+}
+
+/// How a tag differs between 2 snapshots.
+#[derive(Debug, Serialize, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum DiffKind {
+    added,
+    removed,
+    changed,
+}
+
+/// The differences of a tag between 2 snapshots, as new values minus old values.
+#[derive(Debug, Serialize)]
+pub struct TagDiff<'a> {
+    pub path: &'a Path,
+    pub tag: &'a str,
+    pub change: DiffKind,
+    pub offset: i64,
+    pub lines: i64,
+    pub critical: i64,
+    pub warning: i64,
+    pub ok: i64,
+}
+
+impl<'a> TagDiff<'a> {
+    fn new(path: &'a Path, tag: &'a str, change: DiffKind, old: &RunData, new: &RunData) -> Self {
+        let delta = |old: u64, new: u64| new as i64 - old as i64;
+
+        TagDiff {
+            path,
+            tag,
+            change,
+            offset: delta(old.last_offset, new.last_offset),
+            lines: delta(old.last_line, new.last_line),
+            critical: delta(old.counters.critical_count, new.counters.critical_count),
+            warning: delta(old.counters.warning_count, new.counters.warning_count),
+            ok: delta(old.counters.ok_count, new.counters.ok_count),
+        }
+    }
+
+    // true if any offset or counter is different
+    fn has_changes(&self) -> bool {
+        [
+            self.offset,
+            self.lines,
+            self.critical,
+            self.warning,
+            self.ok,
+        ]
+        .iter()
+        .any(|x| *x != 0)
+    }
+}
+
+/// An inconsistency found between a snapshot entry and the filesystem or the configuration file.
+#[derive(Debug, PartialEq)]
+pub enum SnapshotIssue {
+    /// the logfile is not searched in the configuration file
+    Stale,
+
+    /// the logfile doesn't exist anymore
+    Missing,
+
+    /// the logfile signature changed
+    Rotated,
+
+    /// the logfile signature can't be compared
+    Undetermined(String),
+
+    /// the tag is not defined for this logfile in the configuration file
+    UnknownTag(String),
+
+    /// the last offset is beyond the logfile size
+    OffsetBeyondSize { tag: String, offset: u64, size: u64 },
+}
+
+impl fmt::Display for SnapshotIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotIssue::Stale => write!(f, "stale entry, logfile is not searched in the configuration file. Fix: the entry will be deleted after the snapshot retention, or delete the snapshot file"),
+            SnapshotIssue::Missing => write!(f, "logfile not found. Fix: check the logfile path in the configuration file"),
+            SnapshotIssue::Rotated => write!(f, "logfile signature changed, probably rotated. Fix: none, the next run reads the archived logfile and then the new one from its beginning"),
+            SnapshotIssue::Undetermined(e) => write!(f, "logfile signature can't be checked: {}. Fix: lower hash_window for this logfile", e),
+            SnapshotIssue::UnknownTag(tag) => write!(f, "tag '{}' is not defined for this logfile. Fix: none, the tag is deleted at the next run", tag),
+            SnapshotIssue::OffsetBeyondSize { tag, offset, size } => write!(f, "tag '{}' offset {} is beyond the logfile size {}, probably truncated in place. Fix: use the rewind option once, or delete the snapshot file", tag, offset, size),
+        }
+    }
+}
+
+/// A list of snapshots, keyed by their file name. Each search can save its run data into a specific snapshot file.
+#[derive(Debug, Default)]
+pub struct SnapshotList(HashMap<PathBuf, Snapshot>);
+
+impl SnapshotList {
+    /// Iterates through all run data created by the current process, for all snapshots
+    pub fn current_run_data(&self) -> impl Iterator<Item = (&PathBuf, &String, &RunData)> {
+        self.0
+            .values()
+            .flat_map(|snapshot| snapshot.current_run_data())
+    }
+
+    /// Like `current_run_data`, along with the labels of the search of each logfile
+    pub fn labeled_run_data(
+        &self,
+    ) -> impl Iterator<Item = (&PathBuf, &String, &RunData, &BTreeMap<String, String>)> {
+        self.0
+            .values()
+            .flat_map(|snapshot| snapshot.labeled_run_data())
+    }
+
+    /// Sums the counters of all logfiles of all snapshots along with access errors, for the plugin exit status
+    pub fn global_exit(&self, access_errors: &LogFileAccessErrorList) -> NagiosExit {
+        let current_pid = std::process::id();
+
+        // all logfiles from all snapshots
+        let logfiles = || self.0.values().flat_map(|x| x.snapshot.iter());
+
+        // calculate the summation of all pattern counts for all logfiles
+        let pattern_sum = logfiles()
+            .map(|(_, x)| x.sum_counters(current_pid)) // Vec<PatternCounters>
             .fold(PatternCounters::default(), |acc, x| acc + x); // PatternCounters
 
         // build the nagios exit counters
         let mut global_exit = NagiosExit::default();
         global_exit.critical_count = pattern_sum.critical_count;
         global_exit.warning_count = pattern_sum.warning_count;
+        global_exit.dup_count = pattern_sum.dup_count;
 
         // add critical, warning or unknown error count with access errors
         for (_, access_error) in access_errors.iter() {
@@ -175,7 +1116,7 @@ impl Snapshot {
         }
 
         // unknown is a special case: we sum the number of cases where an error occurred in RunData structures
-        for logfile in self.snapshot.values() {
+        for (_, logfile) in logfiles() {
             global_exit.unknown_count += logfile
                 .run_data
                 .values()
@@ -183,33 +1124,388 @@ impl Snapshot {
                 .count() as u64;
         }
 
+        // the first sentinel match, in the order of logfiles and tags, is given in the output
+        global_exit.sentinel = self
+            .current_run_data()
+            .filter_map(|(path, tag_name, run_data)| {
+                run_data
+                    .sentinel_line
+                    .as_ref()
+                    .map(|line| (path, tag_name, line))
+            })
+            .min()
+            .map(|(_, _, line)| line.clone());
+
+        global_exit
+    }
+
+    /// The summary line of the run, from the same counters as the plugin output
+    pub fn summary(
+        &self,
+        access_errors: &LogFileAccessErrorList,
+        duration: Duration,
+    ) -> RunSummary {
+        let global_exit = self.global_exit(access_errors);
+        let files: HashSet<_> = self.current_run_data().map(|(path, _, _)| path).collect();
+
+        RunSummary {
+            state: NagiosError::from(&global_exit),
+            criticals: global_exit.critical_count,
+            warnings: global_exit.warning_count,
+            files: files.len(),
+            duration: duration.as_secs_f64(),
+            errors: global_exit.unknown_count,
+        }
+    }
+
+    /// The report of the run sent to the report socket, from the same counters as the plugin output
+    pub fn run_report<'a>(
+        &'a self,
+        access_errors: &'a LogFileAccessErrorList,
+        duration: Duration,
+    ) -> RunReport<'a> {
+        let mut tags: Vec<_> = self
+            .labeled_run_data()
+            .map(|(path, tag_name, run_data, labels)| TagReport {
+                logfile: path,
+                tag: tag_name,
+                labels,
+                state: String::from(&NagiosError::from(&NagiosExit::from(run_data))),
+                counters: &run_data.counters,
+                last_offset: run_data.last_offset,
+                last_line: run_data.last_line,
+                scan_duration: run_data.scan_duration,
+            })
+            .collect();
+        tags.sort_by(|a, b| (a.logfile, a.tag).cmp(&(b.logfile, b.tag)));
+
+        let mut errors: Vec<_> = access_errors
+            .iter()
+            .map(|(path, access_error)| ErrorReport {
+                logfile: path,
+                state: String::from(&access_error.nagios_error),
+                error: access_error.error.to_string(),
+            })
+            .collect();
+        errors.sort_by(|a, b| a.logfile.cmp(b.logfile));
+
+        RunReport {
+            hostname: hostname(),
+            time: from_epoch_secs().unwrap_or_default(),
+            state: String::from(&NagiosError::from(&self.global_exit(access_errors))),
+            duration: duration.as_secs_f64(),
+            tags,
+            errors,
+        }
+    }
+
+    /// Builds the report of a dry run: the counters of each logfile and tag searched, along with the callback calls which
+    /// would have been done
+    pub fn dry_run_report(&self) -> String {
+        let mut run_data: Vec<_> = self.current_run_data().collect();
+        run_data.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+
+        let mut report = String::new();
+        for (path, tag_name, run_data) in run_data {
+            let counters = &run_data.counters;
+            report.push_str(&format!(
+                "{}(tag={}): critical={}, warning={}, ok={}, {} callback calls",
+                path.display(),
+                tag_name,
+                counters.critical_count,
+                counters.warning_count,
+                counters.ok_count,
+                run_data.pending_callbacks.len()
+            ));
+            if let (Some(first_seen), Some(last_seen)) = (run_data.first_seen, run_data.last_seen) {
+                report.push_str(&format!(
+                    ", first seen {}, last seen {}",
+                    utc_time(first_seen),
+                    utc_time(last_seen)
+                ));
+            }
+            report.push('\n');
+
+            for pending in &run_data.pending_callbacks {
+                let var = |name: &str| {
+                    pending
+                        .vars
+                        .get(name)
+                        .map(|value| value.to_string())
+                        .unwrap_or_default()
+                };
+                report.push_str(&format!(
+                    "    line {} ({}): {}\n",
+                    var("CLF_LINE_NUMBER"),
+                    var("CLF_MATCHED_RE_TYPE"),
+                    var("CLF_LINE")
+                ));
+            }
+        }
+
+        report
+    }
+
+    /// Builds the final output message displayed by the plugin, for all snapshots
+    pub fn exit_message(
+        &self,
+        access_errors: &LogFileAccessErrorList,
+        global: &GlobalOptions,
+    ) -> NagiosError {
+        let global_exit = self.global_exit(access_errors);
         let nagios_error = NagiosError::from(&global_exit);
-        println!("{}", global_exit);
 
-        // loop through all run data
-        for (path, logfile) in &self.snapshot {
-            for (tag_name, run_data) in &logfile.run_data {
-                if run_data.pid == current_pid {
-                    let nagios_exit = NagiosExit::from(run_data);
-                    println!("{}(tag={}) - {}", path.display(), tag_name, nagios_exit);
+        // the output is composed by the template if any, unless it can't be rendered
+        #[cfg(feature = "tera")]
+        if let Some(output_template) = &global.output_template {
+            let context = self.output_context(&global_exit, access_errors, &global.global_vars);
+            match template::render_output(output_template, &context) {
+                Ok(output) => {
+                    println!("{}", output.trim_end());
+                    return nagios_error;
                 }
+                Err(e) => error!("unable to render output template: {}", e),
             }
         }
 
-        // then list access errors
+        let status = if global.perfdata {
+            format!("{} | {}", global_exit, self.perfdata(&global_exit))
+        } else {
+            global_exit.to_string()
+        };
+
+        // the long output: all run data, then access errors
+        let mut long_output = Vec::new();
+        for (path, tag_name, run_data) in self.current_run_data() {
+            let nagios_exit = NagiosExit::from(run_data);
+            let mut line = format!("{}(tag={}) - {}", path.display(), tag_name, nagios_exit);
+
+            // where matched lines are kept with the keepoutput option
+            if let Some(output_file) = &run_data.output_file {
+                line.push_str(&format!(" - output: {}", output_file.display()));
+            }
+            long_output.push((NagiosError::from(&nagios_exit), line));
+        }
         for (path, access_error) in access_errors.iter() {
-            println!(
-                "{} - {}: {}",
-                path.display(),
-                String::from(&access_error.nagios_error),
-                access_error.error
-            );
+            long_output.push((
+                access_error.nagios_error.clone(),
+                format!(
+                    "{} - {}: {}",
+                    path.display(),
+                    String::from(&access_error.nagios_error),
+                    access_error.error
+                ),
+            ));
+        }
+
+        // and timestamps clamped because of a clock skew
+        let clamped_count: u64 = self.0.values().map(|x| x.clamped_count).sum();
+        if clamped_count != 0 {
+            long_output.push((
+                NagiosError::OK,
+                format!(
+                    "clock skew: {} snapshot timestamps in the future clamped",
+                    clamped_count
+                ),
+            ));
+        }
+
+        // performance data of each logfile and tag come after the long output
+        let mut perfdata = self.logfile_perfdata();
+        if !global.perfdata || perfdata.is_empty() {
+            perfdata.clear();
+        } else {
+            perfdata[0].insert_str(0, "| ");
+        }
+
+        let mut lines = vec![status];
+        if global.max_output == 0 {
+            lines.extend(long_output.into_iter().map(|(_, line)| line));
+            lines.extend(perfdata);
+        } else {
+            let room = global.max_output.saturating_sub(lines[0].len() + 1);
+            let long_output = fit_output(long_output, room);
+
+            // performance data are only kept if they fit along with the whole long output
+            let size: usize = long_output
+                .iter()
+                .chain(perfdata.iter())
+                .map(|line| line.len() + 1)
+                .sum();
+            lines.extend(long_output);
+            if size <= room {
+                lines.extend(perfdata);
+            }
+        }
+
+        for line in lines {
+            println!("{}", line);
         }
 
         nagios_error
     }
 }
 
+impl SnapshotList {
+    /// The performance data of the run, in the Nagios format: the counters of the plugin output, along with the lines
+    /// read and the time spent reading all logfiles.
+    pub fn perfdata(&self, global_exit: &NagiosExit) -> String {
+        let (lines_read, duration) =
+            self.current_run_data()
+                .fold((0, 0.0), |(lines, duration), (_, _, run_data)| {
+                    (
+                        lines + lines_read(run_data),
+                        duration + run_data.scan_duration,
+                    )
+                });
+
+        format!(
+            "critical={};; warning={};; unknown={};; lines_read={};; duration={:.3}s;;",
+            global_exit.critical_count,
+            global_exit.warning_count,
+            global_exit.unknown_count,
+            lines_read,
+            duration
+        )
+    }
+
+    /// The performance data of each logfile and tag searched, sorted by path, one line each. Labels are the logfile
+    /// path and the tag name, like `'/var/log/syslog:kernel critical'`. Search labels are left out: performance data
+    /// have no dimensions, so they would be part of the label and graphs would start over whenever one is changed.
+    pub fn logfile_perfdata(&self) -> Vec<String> {
+        let mut run_data: Vec<_> = self.current_run_data().collect();
+        run_data.sort_by(|x, y| (x.0, x.1).cmp(&(y.0, y.1)));
+
+        run_data
+            .into_iter()
+            .map(|(path, tag_name, run_data)| {
+                let label = format!("{}:{}", path.display(), tag_name).replace('\'', "''");
+                let nagios_exit = NagiosExit::from(run_data);
+                format!(
+                    "'{label} critical'={};; '{label} warning'={};; '{label} unknown'={};; '{label} lines_read'={};; '{label} duration'={:.3}s;;",
+                    nagios_exit.critical_count,
+                    nagios_exit.warning_count,
+                    nagios_exit.unknown_count,
+                    lines_read(run_data),
+                    run_data.scan_duration,
+                    label = label
+                )
+            })
+            .collect()
+    }
+
+    /// The values given to the output template: the global state and counters, and those of each logfile and tag along
+    /// with access errors, sorted by path.
+    #[cfg(feature = "tera")]
+    pub fn output_context(
+        &self,
+        global_exit: &NagiosExit,
+        access_errors: &LogFileAccessErrorList,
+        global_vars: &GlobalVars,
+    ) -> tera::Context {
+        let mut run_data: Vec<_> = self.current_run_data().collect();
+        run_data.sort_by(|x, y| (x.0, x.1).cmp(&(y.0, y.1)));
+        let logfiles: Vec<_> = run_data
+            .into_iter()
+            .map(|(path, tag_name, run_data)| {
+                let nagios_exit = NagiosExit::from(run_data);
+                serde_json::json!({
+                    "path": path,
+                    "tag": tag_name,
+                    "status": String::from(&NagiosError::from(&nagios_exit)),
+                    "critical_count": nagios_exit.critical_count,
+                    "warning_count": nagios_exit.warning_count,
+                    "unknown_count": nagios_exit.unknown_count,
+                    "ok_count": run_data.counters.ok_count,
+                    "error": nagios_exit.error_msg,
+                    "sentinel": nagios_exit.sentinel,
+                    "scan_duration": run_data.scan_duration,
+                })
+            })
+            .collect();
+
+        let mut errors: Vec<_> = access_errors.iter().collect();
+        errors.sort_by(|x, y| x.0.cmp(y.0));
+        let errors: Vec<_> = errors
+            .into_iter()
+            .map(|(path, access_error)| {
+                serde_json::json!({
+                    "path": path,
+                    "status": String::from(&access_error.nagios_error),
+                    "error": access_error.error.to_string(),
+                })
+            })
+            .collect();
+
+        let mut context = tera::Context::new();
+        context.insert("output", &global_exit.to_string());
+        context.insert("status", &String::from(&NagiosError::from(global_exit)));
+        context.insert("critical_count", &global_exit.critical_count);
+        context.insert("warning_count", &global_exit.warning_count);
+        context.insert("unknown_count", &global_exit.unknown_count);
+        context.insert("sentinel", &global_exit.sentinel);
+        context.insert("hostname", &hostname());
+        context.insert("vars", global_vars);
+        context.insert("logfiles", &logfiles);
+        context.insert("access_errors", &errors);
+        context.insert("perfdata", &self.perfdata(global_exit));
+        context.insert("logfile_perfdata", &self.logfile_perfdata());
+        context
+    }
+}
+
+impl Deref for SnapshotList {
+    type Target = HashMap<PathBuf, Snapshot>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for SnapshotList {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+// the lines of the long output fitting in `room` bytes. If they don't all fit, the most severe ones are kept first, and
+// the others are summarized as `+N more`
+fn fit_output(mut long_output: Vec<(NagiosError, String)>, room: usize) -> Vec<String> {
+    let size: usize = long_output.iter().map(|(_, line)| line.len() + 1).sum();
+    if size <= room {
+        return long_output.into_iter().map(|(_, line)| line).collect();
+    }
+
+    // the sort is stable: lines of the same severity are kept in order
+    long_output.sort_by_key(|(nagios_error, _)| std::cmp::Reverse(nagios_error.severity()));
+
+    let mut room = room.saturating_sub(format!("+{} more", long_output.len()).len() + 1);
+    let mut lines = Vec::new();
+    for (_, line) in &long_output {
+        if line.len() + 1 > room {
+            break;
+        }
+        room -= line.len() + 1;
+        lines.push(line.clone());
+    }
+
+    lines.push(format!("+{} more", long_output.len() - lines.len()));
+    lines
+}
+
+// lines read during the run for a logfile and tag
+fn lines_read(run_data: &RunData) -> u64 {
+    run_data.last_line.saturating_sub(run_data.start_line)
+}
+
+// a time in seconds since the UNIX epoch, as a UTC date & time
+fn utc_time(secs: u64) -> String {
+    Utc.timestamp_opt(secs as i64, 0).single().map_or_else(
+        || secs.to_string(),
+        |time| time.format("%Y-%m-%d %H:%M:%S").to_string(),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use std::path::PathBuf;
@@ -390,6 +1686,25 @@ mod tests {
         assert!(keys.contains(&&PathBuf::from("/var/log/syslog")));
     }
 
+    #[test]
+    fn clamp_timestamps() {
+        let mut data: Snapshot = serde_json::from_str(SNAPSHOT_SAMPLE).unwrap();
+        let nb_run_data: usize = data.snapshot.values().map(|x| x.run_data.len()).sum();
+
+        // all last runs are before now
+        data.clamp_timestamps(1611857382);
+        assert_eq!(data.clamped_count, 0);
+
+        // simulate a clock jump back
+        data.clamp_timestamps(1611857000);
+        assert_eq!(data.clamped_count, nb_run_data as u64);
+        assert!(data
+            .snapshot
+            .values()
+            .flat_map(|x| x.run_data.values())
+            .all(|x| x.last_run_secs == 1611857000));
+    }
+
     #[test]
     #[cfg(target_os = "linux")]
     fn logfile_mut() {
@@ -415,6 +1730,471 @@ mod tests {
         assert_eq!(data.snapshot.len(), 6);
     }
 
+    #[test]
+    fn snapshot_list() {
+        let mut team1: Snapshot = serde_json::from_str(SNAPSHOT_SAMPLE).unwrap();
+        let team2: Snapshot = serde_json::from_str(SNAPSHOT_SAMPLE).unwrap();
+
+        // simulate a run for only one tag, with a callback call not done
+        let logfile = team1
+            .snapshot
+            .get_mut(&PathBuf::from("/var/log/syslog"))
+            .unwrap();
+        logfile.labels = BTreeMap::from([("team".to_string(), "db".to_string())]);
+        let run_data = logfile.run_data.get_mut("syslog_kernel").unwrap();
+        run_data.pid = std::process::id();
+        run_data.seen(1611857382);
+        let vars = serde_json::from_str(
+            r#"{"CLF_LINE_NUMBER": 12, "CLF_MATCHED_RE_TYPE": "warning", "CLF_LINE": "kernel: oops"}"#,
+        )
+        .unwrap();
+        run_data
+            .pending_callbacks
+            .push(crate::logfile::rundata::PendingCallback {
+                hash: 0,
+                vars,
+                incident: None,
+            });
+
+        let mut list = SnapshotList::default();
+        list.insert(PathBuf::from("/tmp/team1.json"), team1);
+        list.insert(PathBuf::from("/tmp/team2.json"), team2);
+
+        let run_data: Vec<_> = list.current_run_data().collect();
+        assert_eq!(run_data.len(), 1);
+        assert_eq!(run_data[0].0, &PathBuf::from("/var/log/syslog"));
+        assert_eq!(run_data[0].1, "syslog_kernel");
+        assert_eq!(run_data[0].2.counters.warning_count, 1400);
+
+        // only the logfile searched by this process is summarized
+        let summary = list.summary(
+            &LogFileAccessErrorList::default(),
+            Duration::from_millis(1500),
+        );
+        assert_eq!(summary.files, 1);
+        assert_eq!(summary.warnings, 1400);
+        assert_eq!(summary.errors, 0);
+        assert_eq!(summary.duration, 1.5);
+
+        let report = serde_json::to_value(list.run_report(
+            &LogFileAccessErrorList::default(),
+            Duration::from_millis(1500),
+        ))
+        .unwrap();
+        assert_eq!(report["state"], "WARNING");
+        assert_eq!(report["tags"].as_array().unwrap().len(), 1);
+        assert_eq!(report["tags"][0]["logfile"], "/var/log/syslog");
+        assert_eq!(report["tags"][0]["warning_count"], 1400);
+        assert_eq!(report["tags"][0]["labels"]["team"], "db");
+        assert!(report["errors"].as_array().unwrap().is_empty());
+
+        assert_eq!(
+            list.dry_run_report(),
+            "/var/log/syslog(tag=syslog_kernel): critical=0, warning=1400, ok=0, 1 callback calls, first seen 2021-01-28 18:09:42, last seen 2021-01-28 18:09:42\n    line 12 (warning): kernel: oops\n"
+        );
+    }
+
+    #[test]
+    fn perfdata() {
+        let mut snapshot: Snapshot = serde_json::from_str(SNAPSHOT_SAMPLE).unwrap();
+        let run_data = snapshot
+            .snapshot
+            .get_mut(&PathBuf::from("/var/log/syslog"))
+            .unwrap()
+            .run_data
+            .get_mut("syslog_kernel")
+            .unwrap();
+        run_data.pid = std::process::id();
+        run_data.start_line = 100;
+        run_data.last_line = 301;
+        run_data.scan_duration = 0.5;
+        let mut list = SnapshotList::default();
+        list.insert(PathBuf::from("/tmp/team1.json"), snapshot);
+
+        let global_exit = list.global_exit(&LogFileAccessErrorList::default());
+        assert_eq!(
+            list.perfdata(&global_exit),
+            "critical=0;; warning=1400;; unknown=0;; lines_read=201;; duration=0.500s;;"
+        );
+        assert_eq!(
+            list.logfile_perfdata(),
+            vec!["'/var/log/syslog:syslog_kernel critical'=0;; '/var/log/syslog:syslog_kernel warning'=1400;; '/var/log/syslog:syslog_kernel unknown'=0;; '/var/log/syslog:syslog_kernel lines_read'=201;; '/var/log/syslog:syslog_kernel duration'=0.500s;;"]
+        );
+    }
+
+    #[test]
+    fn fit_output() {
+        let long_output = || {
+            vec![
+                (NagiosError::OK, "/var/log/a(tag=a) - OK".to_string()),
+                (
+                    NagiosError::WARNING,
+                    "/var/log/b(tag=b) - WARNING".to_string(),
+                ),
+                (
+                    NagiosError::CRITICAL,
+                    "/var/log/c(tag=c) - CRITICAL".to_string(),
+                ),
+                (NagiosError::UNKNOWN, "/var/log/d - UNKNOWN".to_string()),
+                (
+                    NagiosError::CRITICAL,
+                    "/var/log/e(tag=e) - CRITICAL".to_string(),
+                ),
+            ]
+        };
+
+        // everything fits: the order is kept
+        let lines = super::fit_output(long_output(), 1000);
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "/var/log/a(tag=a) - OK");
+
+        // the most severe lines come first, in order
+        let lines = super::fit_output(long_output(), 100);
+        assert_eq!(
+            lines,
+            vec![
+                "/var/log/c(tag=c) - CRITICAL",
+                "/var/log/e(tag=e) - CRITICAL",
+                "/var/log/b(tag=b) - WARNING",
+                "+2 more"
+            ]
+        );
+        assert!(lines.iter().map(|line| line.len() + 1).sum::<usize>() <= 100);
+
+        // no room left
+        assert_eq!(super::fit_output(long_output(), 5), vec!["+5 more"]);
+    }
+
+    #[test]
+    #[cfg(feature = "tera")]
+    fn output_context() {
+        let mut snapshot: Snapshot = serde_json::from_str(SNAPSHOT_SAMPLE).unwrap();
+        for run_data in snapshot
+            .snapshot
+            .values_mut()
+            .flat_map(|logfile| logfile.run_data.values_mut())
+        {
+            run_data.pid = std::process::id();
+        }
+        let mut list = SnapshotList::default();
+        list.insert(PathBuf::from("/tmp/team1.json"), snapshot);
+
+        let access_errors = LogFileAccessErrorList::default();
+        let global_exit = list.global_exit(&access_errors);
+        let mut vars = GlobalVars::new();
+        vars.insert("site".to_string(), "paris".to_string());
+        let context = list.output_context(&global_exit, &access_errors, &vars);
+
+        let output_template = r#"{{ status }} on {{ vars.site }}: {{ critical_count }} errors | criticals={{ critical_count }}
+{% for l in logfiles %}{{ l.path | basename }}/{{ l.tag }}: {{ l.status }} ({{ l.warning_count }} warnings)
+{% endfor %}"#;
+        let output = template::render_output(output_template, &context).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(
+            lines[0],
+            format!(
+                "{} on paris: {} errors | criticals={}",
+                String::from(&NagiosError::from(&global_exit)),
+                global_exit.critical_count,
+                global_exit.critical_count
+            )
+        );
+        assert_eq!(lines.len(), 1 + list.current_run_data().count());
+        assert!(lines.contains(&"syslog/syslog_kernel: WARNING (1400 warnings)"));
+
+        assert!(template::render_output("{{ unknown_var }}", &context).is_err());
+    }
+
+    #[test]
+    fn shard() {
+        let mut data: Snapshot = serde_json::from_str(SNAPSHOT_SAMPLE).unwrap();
+        let nb_logfiles = data.snapshot.len();
+
+        let mut shard = data.take_shard(&PathBuf::from("/var/log/syslog"));
+        assert_eq!(shard.snapshot.len(), 1);
+        assert_eq!(data.snapshot.len(), nb_logfiles - 1);
+        assert!(data
+            .take_shard(&PathBuf::from("/var/log/foo"))
+            .snapshot
+            .is_empty());
+
+        shard
+            .snapshot
+            .get_mut(&PathBuf::from("/var/log/syslog"))
+            .unwrap()
+            .run_data
+            .get_mut("syslog_kernel")
+            .unwrap()
+            .counters
+            .warning_count += 1;
+        data.merge_shard(shard);
+        assert_eq!(data.snapshot.len(), nb_logfiles);
+        assert_eq!(
+            data.snapshot[&PathBuf::from("/var/log/syslog")].run_data["syslog_kernel"]
+                .counters
+                .warning_count,
+            1401
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn verify() {
+        let path = std::env::temp_dir().join("clf_snapshot_verify.log");
+        std::fs::write(&path, "0123456789\n".repeat(10)).unwrap();
+
+        let yaml = format!(
+            r#"
+logfile:
+    path: {}
+    hash_window: 10
+tags:
+  - name: error
+    patterns:
+        critical: {{ regexes: ['error'] }}
+            "#,
+            path.display()
+        );
+        let search: Search = serde_yaml::from_str(&yaml).expect("unable to read YAML");
+        let searches = vec![&search];
+
+        let mut data = Snapshot::default();
+        let logfile = data.logfile_mut(&path, &search.logfile).unwrap();
+        logfile.rundata_for_tag("error").last_offset = 110;
+        assert!(data.verify(&searches).is_empty());
+
+        // offset beyond the size, unknown tag and stale entry
+        let logfile = data.logfile_mut(&path, &search.logfile).unwrap();
+        logfile.rundata_for_tag("error").last_offset = 200;
+        logfile.rundata_for_tag("warning");
+        data.snapshot.insert(
+            PathBuf::from("/var/log/foo.log"),
+            data.snapshot[&path].clone(),
+        );
+        let issues = data.verify(&searches);
+        assert_eq!(issues.len(), 3);
+        assert_eq!(
+            issues[0].1,
+            SnapshotIssue::OffsetBeyondSize {
+                tag: "error".to_string(),
+                offset: 200,
+                size: 110
+            }
+        );
+        assert_eq!(
+            issues[1].1,
+            SnapshotIssue::UnknownTag("warning".to_string())
+        );
+        assert_eq!(issues[2].1, SnapshotIssue::Stale);
+
+        // same inode, but first bytes changed
+        std::fs::write(&path, "9876543210\n".repeat(10)).unwrap();
+        let issues = data.verify(&searches);
+        assert_eq!(issues[0].1, SnapshotIssue::Rotated);
+
+        std::fs::remove_file(&path).unwrap();
+        let issues = data.verify(&searches);
+        assert_eq!(issues[0].1, SnapshotIssue::Missing);
+    }
+
+    #[test]
+    fn heal_offsets() {
+        let path = std::env::temp_dir().join("clf_snapshot_heal.log");
+        std::fs::write(&path, "0123456789\n".repeat(10)).unwrap();
+
+        let yaml = format!(
+            "logfile:\n    path: {}\n    hash_window: 10\ntags: []",
+            path.display()
+        );
+        let search: Search = serde_yaml::from_str(&yaml).expect("unable to read YAML");
+
+        let mut data = Snapshot::default();
+        let logfile = data.logfile_mut(&path, &search.logfile).unwrap();
+        logfile.rundata_for_tag("error").last_offset = 110;
+        logfile.rundata_for_tag("warning").last_offset = 500;
+        logfile.rundata_for_tag("warning").last_line = 50;
+
+        // a missing logfile is not checked
+        data.snapshot.insert(
+            PathBuf::from("/var/log/foo.log"),
+            data.snapshot[&path].clone(),
+        );
+
+        let (checked, issues) = data.offsets_beyond_eof();
+        assert_eq!(checked, 2);
+        assert_eq!(issues.len(), 1);
+        assert!(
+            matches!(&issues[0].1, SnapshotIssue::OffsetBeyondSize { tag, offset: 500, size: 110 } if tag == "warning")
+        );
+
+        assert_eq!(data.heal_offsets(), 1);
+        assert!(data.offsets_beyond_eof().1.is_empty());
+        let run_data = &data.snapshot[&path].run_data;
+        assert_eq!(run_data["warning"].last_offset, 0);
+        assert_eq!(run_data["warning"].last_line, 0);
+        assert_eq!(run_data["error"].last_offset, 110);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn remap() {
+        let dir = std::env::temp_dir().join("clf_snapshot_remap");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("term.log"), "0123456789\n".repeat(10)).unwrap();
+
+        let mut data: Snapshot = serde_json::from_str(SNAPSHOT_SAMPLE).unwrap();
+        data.create_checkpoint("before_move", 1611857382);
+        let old_path = PathBuf::from("/var/log/apt/term.log");
+        data.history.push_back(RunRecord {
+            time: 1611857382,
+            exit: "OK".to_string(),
+            duration: 0.1,
+            logfiles: vec![(old_path.clone(), PatternCounters::default())]
+                .into_iter()
+                .collect(),
+            overlaps: BTreeMap::new(),
+        });
+        let offset = data.snapshot[&old_path].run_data["apt"].last_offset;
+
+        // history.log was not moved
+        let remapped = data.remap(Path::new("/var/log/apt"), &dir, |_| HashSpec::from(16));
+        assert_eq!(remapped.len(), 2);
+        assert_eq!(remapped[0].0, PathBuf::from("/var/log/apt/history.log"));
+        assert!(remapped[0].1.is_err());
+        assert_eq!(remapped[1].0, old_path);
+        let new_path = remapped[1].1.as_ref().unwrap().clone();
+        assert_eq!(new_path, dir.join("term.log").canonicalize().unwrap());
+
+        // offsets are kept along with the new signature
+        assert!(!data.snapshot.contains_key(&old_path));
+        let logfile = &data.snapshot[&new_path];
+        assert_eq!(logfile.id.canon_path, new_path);
+        assert_eq!(logfile.id.signature.size, 110);
+        assert!(logfile.id.signature.hash.is_some());
+        assert_eq!(logfile.run_data["apt"].last_offset, offset);
+        assert!(data
+            .snapshot
+            .contains_key(&PathBuf::from("/var/log/apt/history.log")));
+
+        let saved = &data.checkpoints["before_move"].logfiles[&new_path];
+        assert_eq!(saved.signature, logfile.id.signature);
+        assert!(data.history[0].logfiles.contains_key(&new_path));
+
+        // already moved
+        let mut data: Snapshot = serde_json::from_str(SNAPSHOT_SAMPLE).unwrap();
+        data.remap(Path::new("/var/log/apt"), &dir, |_| HashSpec::from(16));
+        data.snapshot.insert(old_path.clone(), LogFile::default());
+        let remapped = data.remap(Path::new("/var/log/apt"), &dir, |_| HashSpec::from(16));
+        assert!(remapped[1].1.is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reset() {
+        let mut data: Snapshot = serde_json::from_str(SNAPSHOT_SAMPLE).unwrap();
+        let path = PathBuf::from("/var/log/apt/term.log");
+        let run_data = data.snapshot.get_mut(&path).unwrap().rundata_for_tag("apt");
+        run_data.counters.critical_count = 3;
+        assert_ne!(run_data.last_offset, 0);
+
+        // unknown tag or logfile
+        assert_eq!(data.reset(&path, Some("foo")), 0);
+        assert_eq!(data.reset(Path::new("/var/log/messages"), None), 0);
+        assert_eq!(
+            data.snapshot[&path].run_data["apt"].counters.critical_count,
+            3
+        );
+
+        assert_eq!(data.reset(&path, Some("apt")), 1);
+        let run_data = &data.snapshot[&path].run_data["apt"];
+        assert_eq!(run_data.counters.critical_count, 0);
+        assert_eq!(run_data.last_offset, 0);
+        assert_eq!(run_data.last_line, 0);
+
+        // other logfiles are left as is
+        let other = &data.snapshot[&PathBuf::from("/var/log/apt/history.log")];
+        assert!(other
+            .run_data
+            .values()
+            .any(|run_data| run_data.last_offset != 0));
+        let nb_tags = other.run_data.len();
+        assert_eq!(
+            data.reset(Path::new("/var/log/apt/history.log"), None),
+            nb_tags
+        );
+    }
+
+    #[test]
+    fn checkpoint() {
+        let mut data: Snapshot = serde_json::from_str(SNAPSHOT_SAMPLE).unwrap();
+        assert!(data.replay_from("before_upgrade").is_none());
+        assert_eq!(data.create_checkpoint("before_upgrade", 1611857382), 6);
+
+        // logfiles are read further, and one of them is rotated
+        for logfile in data.snapshot.values_mut() {
+            for run_data in logfile.run_data.values_mut() {
+                run_data.last_offset += 1000;
+                run_data.last_line += 10;
+            }
+        }
+        let syslog = PathBuf::from("/var/log/syslog");
+        data.snapshot.get_mut(&syslog).unwrap().id.signature.inode += 1;
+
+        // checkpoints are kept in the snapshot file
+        let json = serde_json::to_string(&data).unwrap();
+        let mut data: Snapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(data.checkpoints["before_upgrade"].created, 1611857382);
+
+        assert_eq!(data.replay_from("before_upgrade"), Some(4));
+        let kern = &data.snapshot[&PathBuf::from("/var/log/kern.log")].run_data["kern_kernel"];
+        assert_eq!((kern.last_offset, kern.last_line), (392201, 3885));
+        assert_eq!(kern.counters.warning_count, 3885);
+        let syslog = &data.snapshot[&syslog].run_data["syslog_kernel"];
+        assert_eq!(syslog.last_offset, 334147 + 1000);
+
+        // a snapshot without checkpoints is saved as before
+        let data: Snapshot = serde_json::from_str(SNAPSHOT_SAMPLE).unwrap();
+        assert!(!serde_json::to_string(&data)
+            .unwrap()
+            .contains("checkpoints"));
+    }
+
+    #[test]
+    fn record_run() {
+        let mut data: Snapshot = serde_json::from_str(SNAPSHOT_SAMPLE).unwrap();
+
+        // only logfiles searched by this process are kept
+        let kern = PathBuf::from("/var/log/kern.log");
+        for run_data in data.snapshot.get_mut(&kern).unwrap().run_data.values_mut() {
+            run_data.pid = std::process::id();
+        }
+
+        for time in 1..=4 {
+            data.record_run(time, &NagiosError::WARNING, 0.5, 3);
+        }
+        let json = serde_json::to_string(&data).unwrap();
+        let data: Snapshot = serde_json::from_str(&json).unwrap();
+
+        // the oldest run has been dropped
+        let history = data.history();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].time, 2);
+        assert_eq!(history[2].exit, "WARNING");
+        assert_eq!(history[2].logfiles.len(), 1);
+        assert_eq!(history[2].logfiles[&kern].warning_count, 3885 + 3867);
+
+        // no history is kept
+        let mut data: Snapshot = serde_json::from_str(SNAPSHOT_SAMPLE).unwrap();
+        data.record_run(1, &NagiosError::OK, 0.5, 0);
+        assert!(data.history().is_empty());
+        assert!(!serde_json::to_string(&data)
+            .unwrap()
+            .contains("\"history\""));
+    }
+
     #[test]
     #[cfg(target_os = "linux")]
     fn build_name() {
@@ -430,4 +2210,205 @@ mod tests {
             Path::new("/var/config.json")
         );
     }
+
+    #[test]
+    fn diff() {
+        let old: Snapshot = serde_json::from_str(SNAPSHOT_SAMPLE).unwrap();
+        let mut new: Snapshot = serde_json::from_str(SNAPSHOT_SAMPLE).unwrap();
+        assert!(old.diff(&new).is_empty());
+
+        // one tag is read further, another one is removed and a new one added
+        let kern = new
+            .snapshot
+            .get_mut(&PathBuf::from("/var/log/kern.log"))
+            .unwrap();
+        let run_data = kern.run_data.get_mut("kern_kernel").unwrap();
+        run_data.last_offset += 100;
+        run_data.last_line += 2;
+        run_data.counters.warning_count -= 5;
+        let removed = kern.run_data.remove("kern_nokernel").unwrap();
+        kern.run_data.insert("kern_new".to_string(), removed);
+
+        let diffs = old.diff(&new);
+        assert_eq!(diffs.len(), 3);
+
+        assert_eq!(diffs[0].tag, "kern_kernel");
+        assert_eq!(diffs[0].change, DiffKind::changed);
+        assert_eq!((diffs[0].offset, diffs[0].lines), (100, 2));
+        assert_eq!((diffs[0].critical, diffs[0].warning), (0, -5));
+
+        assert_eq!(diffs[1].tag, "kern_new");
+        assert_eq!(diffs[1].change, DiffKind::added);
+        assert_eq!(diffs[1].offset, 392201);
+
+        assert_eq!(diffs[2].tag, "kern_nokernel");
+        assert_eq!(diffs[2].change, DiffKind::removed);
+        assert_eq!(diffs[2].warning, -3867);
+    }
+
+    #[test]
+    fn save() {
+        let mut dir = std::env::temp_dir();
+        dir.push("clf_snapshot_save");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let snapfile = dir.join("snapshot.json");
+
+        let mut snapshot: Snapshot = serde_json::from_str(SNAPSHOT_SAMPLE).unwrap();
+        for _ in 0..4 {
+            snapshot
+                .save(&snapfile, u64::MAX, 2, SnapshotBackend::json)
+                .unwrap();
+        }
+
+        // no temporary file is left, and only 2 generations are kept
+        let mut files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec!["snapshot.json", "snapshot.json.1", "snapshot.json.2"]
+        );
+
+        let previous = Snapshot::load(Snapshot::generation_name(&snapfile, "2")).unwrap();
+        assert_eq!(previous.snapshot.len(), snapshot.snapshot.len());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resume() {
+        let mut dir = std::env::temp_dir();
+        dir.push("clf_snapshot_resume");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let snapfile = dir.join("snapshot.json");
+
+        let mut snapshot: Snapshot = serde_json::from_str(SNAPSHOT_SAMPLE).unwrap();
+        snapshot
+            .save(&snapfile, u64::MAX, 0, SnapshotBackend::json)
+            .unwrap();
+
+        // a run stopped while searching these logfiles
+        let progress = ProgressFile::new(&snapfile);
+        let run_data = RunData {
+            last_offset: 400000,
+            last_line: 4000,
+            last_run_secs: 1611857400,
+            ..Default::default()
+        };
+        let mut kern = snapshot.snapshot[Path::new("/var/log/kern.log")].id.clone();
+        progress.save(&kern, "kern_kernel", &run_data).unwrap();
+        let mut rotated = snapshot.snapshot[Path::new("/var/log/apt/term.log")]
+            .id
+            .clone();
+        rotated.signature.inode += 1;
+        progress.save(&rotated, "apt", &run_data).unwrap();
+        kern.declared_path = PathBuf::from("/var/log/new.log");
+        progress.save(&kern, "new", &run_data).unwrap();
+
+        // offsets are resumed, except for the rotated logfile
+        let mut loaded = Snapshot::load(&snapfile).unwrap();
+        let offset = |loaded: &Snapshot, path: &str, tag: &str| {
+            loaded.snapshot[Path::new(path)].run_data[tag].last_offset
+        };
+        assert_eq!(offset(&loaded, "/var/log/kern.log", "kern_kernel"), 400000);
+        assert_eq!(offset(&loaded, "/var/log/apt/term.log", "apt"), 98607);
+        assert_eq!(offset(&loaded, "/var/log/new.log", "new"), 400000);
+
+        // the progress file is deleted once the snapshot is saved
+        assert!(progress.path().exists());
+        loaded
+            .save(&snapfile, u64::MAX, 0, SnapshotBackend::json)
+            .unwrap();
+        assert!(!progress.path().exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "sqlite")]
+    fn save_sqlite() {
+        let mut dir = std::env::temp_dir();
+        dir.push("clf_snapshot_save_sqlite");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let snapfile = dir.join("snapshot.json");
+
+        // a JSON snapshot is replaced by a database
+        let mut snapshot: Snapshot = serde_json::from_str(SNAPSHOT_SAMPLE).unwrap();
+        snapshot.record_run(1000, &NagiosError::OK, 0.5, 10);
+        snapshot
+            .save(&snapfile, u64::MAX, 1, SnapshotBackend::json)
+            .unwrap();
+        snapshot
+            .save(&snapfile, u64::MAX, 1, SnapshotBackend::sqlite)
+            .unwrap();
+        assert!(sqlite::is_sqlite(&snapfile));
+        assert!(!sqlite::is_sqlite(&Snapshot::generation_name(
+            &snapfile, "1"
+        )));
+
+        let loaded = Snapshot::load(&snapfile).unwrap();
+        assert_eq!(loaded.snapshot.len(), snapshot.snapshot.len());
+        assert_eq!(loaded.history().len(), 1);
+        for (path, logfile) in &snapshot.snapshot {
+            assert_eq!(loaded.snapshot[path].run_data.len(), logfile.run_data.len());
+        }
+
+        // the database is updated in place, logfiles removed from the snapshot being deleted
+        let mut snapshot = loaded;
+        let removed = snapshot.snapshot.keys().next().unwrap().clone();
+        snapshot.snapshot.remove(&removed);
+        snapshot
+            .save(&snapfile, u64::MAX, 0, SnapshotBackend::sqlite)
+            .unwrap();
+        let loaded = Snapshot::load(&snapfile).unwrap();
+        assert_eq!(loaded.snapshot.len(), snapshot.snapshot.len());
+        assert!(!loaded.snapshot.contains_key(&removed));
+
+        // a logfile added by another process is kept, unless its retention time has expired
+        let mut other = Snapshot::load(&snapfile).unwrap();
+        let added = PathBuf::from("/var/log/other.log");
+        let mut logfile = other.snapshot.values().next().unwrap().clone();
+        logfile
+            .run_data
+            .values_mut()
+            .for_each(|v| v.last_run_secs = 1000);
+        other.snapshot.insert(added.clone(), logfile);
+        other
+            .save(&snapfile, u64::MAX, 0, SnapshotBackend::sqlite)
+            .unwrap();
+
+        snapshot
+            .save(&snapfile, u64::MAX, 0, SnapshotBackend::sqlite)
+            .unwrap();
+        assert!(Snapshot::load(&snapfile)
+            .unwrap()
+            .snapshot
+            .contains_key(&added));
+
+        let retention = from_epoch_secs().unwrap() - 2000;
+        snapshot
+            .save(&snapfile, retention, 0, SnapshotBackend::sqlite)
+            .unwrap();
+        assert!(!Snapshot::load(&snapfile)
+            .unwrap()
+            .snapshot
+            .contains_key(&added));
+
+        // and back to JSON
+        snapshot
+            .save(&snapfile, u64::MAX, 0, SnapshotBackend::json)
+            .unwrap();
+        assert!(!sqlite::is_sqlite(&snapfile));
+        assert_eq!(
+            Snapshot::load(&snapfile).unwrap().snapshot.len(),
+            snapshot.snapshot.len()
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,228 @@
+//! An alternative store for snapshots: a SQLite database where each logfile is kept in its own row as JSON, along with
+//! checkpoints and the history of runs. On hosts with thousands of logfiles, only the rows of the logfiles which changed
+//! are written at each run, and all of them in a single transaction: the database is never left half-written, and
+//! another process accessing it waits for the transaction to end. Only built with the `sqlite` feature.
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "sqlite")]
+use std::time::Duration;
+
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+
+use crate::configuration::email::Digest;
+#[cfg(feature = "sqlite")]
+use crate::context;
+use crate::logfile::{
+    logfile::LogFile,
+    snapshot::{Checkpoint, RunRecord},
+};
+#[cfg(not(feature = "sqlite"))]
+use crate::misc::error::AppCustomErrorKind;
+use crate::misc::error::{AppError, AppResult};
+
+/// The first bytes of any SQLite database file.
+const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+
+/// Time to wait for another process to release the database.
+#[cfg(feature = "sqlite")]
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+// rows are only created the first time the database is used
+#[cfg(feature = "sqlite")]
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS logfiles (path TEXT PRIMARY KEY, logfile TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS state (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+";
+
+/// The content of a snapshot, as stored in the database.
+#[derive(Debug, Default)]
+pub struct SqliteSnapshot {
+    pub logfiles: HashMap<PathBuf, LogFile>,
+    pub checkpoints: BTreeMap<String, Checkpoint>,
+    pub history: VecDeque<RunRecord>,
+    pub digests: Vec<Digest>,
+}
+
+/// Whether the file is a SQLite database, whatever its extension.
+pub fn is_sqlite(path: &Path) -> bool {
+    let mut header = [0u8; 16];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok_and(|_| header == SQLITE_HEADER)
+}
+
+/// Reads all logfiles, checkpoints, the history of runs and e-mail digests not sent from the database.
+#[cfg(feature = "sqlite")]
+pub fn load(path: &Path) -> AppResult<SqliteSnapshot> {
+    let conn = open(path)?;
+    let mut snapshot = SqliteSnapshot::default();
+
+    let mut select = conn
+        .prepare("SELECT path, logfile FROM logfiles")
+        .map_err(|e| context!(e, "unable to read snapshot database {:?}", path))?;
+    let rows = select
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })
+        .map_err(|e| context!(e, "unable to read snapshot database {:?}", path))?;
+    for row in rows {
+        let (logfile_path, json) =
+            row.map_err(|e| context!(e, "unable to read snapshot database {:?}", path))?;
+        let logfile: LogFile = serde_json::from_str(&json)
+            .map_err(|e| context!(e, "unable to load logfile {} from {:?}", logfile_path, path))?;
+        snapshot
+            .logfiles
+            .insert(PathBuf::from(logfile_path), logfile);
+    }
+
+    if let Some(json) = state(&conn, path, "checkpoints")? {
+        snapshot.checkpoints = serde_json::from_str(&json)
+            .map_err(|e| context!(e, "unable to load checkpoints from {:?}", path))?;
+    }
+    if let Some(json) = state(&conn, path, "history")? {
+        snapshot.history = serde_json::from_str(&json)
+            .map_err(|e| context!(e, "unable to load history from {:?}", path))?;
+    }
+    if let Some(json) = state(&conn, path, "digests")? {
+        snapshot.digests = serde_json::from_str(&json)
+            .map_err(|e| context!(e, "unable to load e-mail digests from {:?}", path))?;
+    }
+
+    Ok(snapshot)
+}
+
+/// Writes the logfiles which changed since they were read and saves checkpoints, the history of runs and e-mail
+/// digests not sent, all in a single transaction. Logfiles not in the snapshot anymore are only deleted if `removable`,
+/// as others could have been added by another process sharing the database.
+#[cfg(feature = "sqlite")]
+pub fn save(
+    path: &Path,
+    logfiles: &HashMap<PathBuf, LogFile>,
+    removable: impl Fn(&Path, &LogFile) -> bool,
+    checkpoints: &BTreeMap<String, Checkpoint>,
+    history: &VecDeque<RunRecord>,
+    digests: &[Digest],
+) -> AppResult<()> {
+    let mut conn = open(path)?;
+    let tx = conn
+        .transaction_with_behavior(TransactionBehavior::Immediate)
+        .map_err(|e| context!(e, "unable to start transaction on {:?}", path))?;
+
+    {
+        // a row whose JSON is unchanged is not written again. Going through a JSON value sorts the keys of maps, so
+        // the same logfile data always gives the same JSON
+        let mut upsert = tx
+            .prepare(
+                "INSERT INTO logfiles (path, logfile) VALUES (?1, ?2)
+                 ON CONFLICT(path) DO UPDATE SET logfile = excluded.logfile WHERE logfile <> excluded.logfile",
+            )
+            .map_err(|e| context!(e, "unable to write snapshot database {:?}", path))?;
+        for (logfile_path, logfile) in logfiles {
+            let json = serde_json::to_value(logfile)
+                .and_then(|value| serde_json::to_string(&value))
+                .map_err(|e| context!(e, "unable to serialize logfile {:?}", logfile_path))?;
+            upsert
+                .execute(params![logfile_path.to_string_lossy(), json])
+                .map_err(|e| {
+                    context!(e, "unable to save logfile {:?} to {:?}", logfile_path, path)
+                })?;
+        }
+
+        // logfiles removed from the snapshot, e.g. because of the retention time
+        let mut select = tx
+            .prepare("SELECT path, logfile FROM logfiles")
+            .map_err(|e| context!(e, "unable to read snapshot database {:?}", path))?;
+        let rows: Vec<(String, String)> = select
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .and_then(|rows| rows.collect::<Result<_, _>>())
+            .map_err(|e| context!(e, "unable to read snapshot database {:?}", path))?;
+        for (logfile_path, _) in rows.iter().filter(|(p, json)| {
+            let p = Path::new(p.as_str());
+            !logfiles.contains_key(p)
+                && serde_json::from_str::<LogFile>(json).is_ok_and(|l| removable(p, &l))
+        }) {
+            tx.execute("DELETE FROM logfiles WHERE path = ?1", [logfile_path])
+                .map_err(|e| {
+                    context!(
+                        e,
+                        "unable to delete logfile {} from {:?}",
+                        logfile_path,
+                        path
+                    )
+                })?;
+        }
+
+        let checkpoints = serde_json::to_string(checkpoints)
+            .map_err(|e| context!(e, "unable to serialize checkpoints",))?;
+        let history = serde_json::to_string(history)
+            .map_err(|e| context!(e, "unable to serialize history",))?;
+        let digests = serde_json::to_string(digests)
+            .map_err(|e| context!(e, "unable to serialize e-mail digests",))?;
+        for (key, value) in [
+            ("checkpoints", checkpoints),
+            ("history", history),
+            ("digests", digests),
+        ] {
+            tx.execute(
+                "INSERT INTO state (key, value) VALUES (?1, ?2)
+                 ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                params![key, value],
+            )
+            .map_err(|e| context!(e, "unable to save {} to {:?}", key, path))?;
+        }
+    }
+
+    tx.commit()
+        .map_err(|e| context!(e, "unable to commit snapshot database {:?}", path))
+}
+
+/// The database can't be read without the `sqlite` feature.
+#[cfg(not(feature = "sqlite"))]
+pub fn load(path: &Path) -> AppResult<SqliteSnapshot> {
+    Err(not_built_in(path))
+}
+
+/// The database can't be written without the `sqlite` feature.
+#[cfg(not(feature = "sqlite"))]
+pub fn save(
+    path: &Path,
+    _logfiles: &HashMap<PathBuf, LogFile>,
+    _removable: impl Fn(&Path, &LogFile) -> bool,
+    _checkpoints: &BTreeMap<String, Checkpoint>,
+    _history: &VecDeque<RunRecord>,
+    _digests: &[Digest],
+) -> AppResult<()> {
+    Err(not_built_in(path))
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn not_built_in(path: &Path) -> AppError {
+    AppError::new_custom(
+        AppCustomErrorKind::NotBuiltIn,
+        &format!("SQLite snapshot {:?} is not built into this binary", path),
+    )
+}
+
+// opens the database, creating the tables if needed
+#[cfg(feature = "sqlite")]
+fn open(path: &Path) -> AppResult<Connection> {
+    let conn = Connection::open(path)
+        .map_err(|e| context!(e, "unable to open snapshot database {:?}", path))?;
+    conn.busy_timeout(BUSY_TIMEOUT)
+        .and_then(|_| conn.execute_batch(SCHEMA))
+        .map_err(|e| context!(e, "unable to create tables in snapshot database {:?}", path))?;
+    Ok(conn)
+}
+
+// a value of the state table
+#[cfg(feature = "sqlite")]
+fn state(conn: &Connection, path: &Path, key: &str) -> AppResult<Option<String>> {
+    conn.query_row("SELECT value FROM state WHERE key = ?1", [key], |row| {
+        row.get(0)
+    })
+    .optional()
+    .map_err(|e| context!(e, "unable to read {} from {:?}", key, path))
+}
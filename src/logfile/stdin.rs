@@ -0,0 +1,194 @@
+//! The standard input, searched like a logfile when the logfile path of a search is `-`, e.g. in a pipeline like
+//! `journalctl -f | clf --config clf.yml`. Lines are read by a thread into a buffer shared by the tags of the search,
+//! each one reading it from its own offset in the stream. Offsets only apply to the current run. While the standard
+//! input is open, a tag reaching the end of the lines read so far stops at the end of a slice, and goes on with the next
+//! lines once the other logfiles are searched. Bytes read by all tags are dropped from the buffer.
+use std::io::{BufRead, Cursor, Read};
+use std::sync::{Condvar, Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::error;
+
+use crate::logfile::seeker::Seeker;
+use crate::misc::error::AppResult;
+
+/// How long a tag waits for new lines before its slice ends, when it read all those of the standard input so far.
+pub const STDIN_WAIT: Duration = Duration::from_millis(500);
+
+// the bytes not dropped yet, from the offset `start` of the stream, and whether the stream is still open
+struct Stream {
+    start: u64,
+    bytes: Vec<u8>,
+    open: bool,
+}
+
+/// The bytes of a stream read by a thread, shared by the tags searching it.
+pub struct StreamBuffer {
+    stream: Mutex<Stream>,
+    available: Condvar,
+}
+
+// the standard input, read once its first search starts
+static STDIN: StreamBuffer = StreamBuffer::new();
+static STDIN_READER: Once = Once::new();
+
+/// The standard input, whose lines are read by a thread started at the first call.
+pub fn stdin() -> &'static StreamBuffer {
+    STDIN_READER.call_once(|| {
+        thread::spawn(|| STDIN.fill(std::io::stdin().lock()));
+    });
+    &STDIN
+}
+
+impl StreamBuffer {
+    /// An empty stream, open until `fill()` reaches its end.
+    pub const fn new() -> Self {
+        StreamBuffer {
+            stream: Mutex::new(Stream {
+                start: 0,
+                bytes: Vec::new(),
+                open: true,
+            }),
+            available: Condvar::new(),
+        }
+    }
+
+    /// Reads the lines of `reader` up to its end, waking up the tags waiting for them. Only whole lines are given to
+    /// tags, except the last one.
+    pub fn fill<R: BufRead>(&self, mut reader: R) {
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) => break,
+                Ok(_) => {
+                    self.lock().bytes.extend_from_slice(&line);
+                    self.available.notify_all();
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    error!("unable to read the standard input: {}", e);
+                    break;
+                }
+            }
+        }
+
+        self.lock().open = false;
+        self.available.notify_all();
+    }
+
+    /// The bytes of the stream from `offset`, once there are new ones or after `wait`, along with whether the stream is
+    /// still open. Bytes already dropped can't be read anymore: they're read from the first byte kept.
+    pub fn reader(&self, offset: u64, wait: Duration) -> (StreamReader, bool) {
+        let deadline = Instant::now() + wait;
+        let mut stream = self.lock();
+        while stream.open && offset >= stream.start + stream.bytes.len() as u64 {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                break;
+            }
+            stream = self
+                .available
+                .wait_timeout(stream, timeout)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+
+        let offset = offset.clamp(stream.start, stream.start + stream.bytes.len() as u64);
+        let bytes = stream.bytes[(offset - stream.start) as usize..].to_vec();
+        let reader = StreamReader {
+            offset,
+            bytes: Cursor::new(bytes),
+        };
+        (reader, stream.open)
+    }
+
+    /// Drops the bytes before `offset`, read by all tags.
+    pub fn consume(&self, offset: u64) {
+        let mut stream = self.lock();
+        let len = offset
+            .saturating_sub(stream.start)
+            .min(stream.bytes.len() as u64);
+        stream.bytes.drain(..len as usize);
+        stream.start += len;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Stream> {
+        self.stream.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for StreamBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The bytes of a stream from an offset, as they were when the reader was created.
+pub struct StreamReader {
+    offset: u64,
+    bytes: Cursor<Vec<u8>>,
+}
+
+impl StreamReader {
+    /// The offset in the stream of the first byte.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+impl Read for StreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.bytes.read(buf)
+    }
+}
+
+impl BufRead for StreamReader {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.bytes.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.bytes.consume(amt)
+    }
+}
+
+/// The stream is positioned when the reader is created: offsets are just counted from there.
+impl Seeker for StreamReader {
+    fn set_offset(&mut self, offset: u64) -> AppResult<u64> {
+        Ok(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_buffer() {
+        let stream = StreamBuffer::new();
+        let read = |offset| {
+            let (mut reader, open) = stream.reader(offset, Duration::from_millis(10));
+            let mut text = String::new();
+            reader.read_to_string(&mut text).unwrap();
+            (reader.offset(), text, open)
+        };
+
+        // nothing read yet
+        assert_eq!(read(0), (0, String::new(), true));
+
+        stream.fill(&b"line 1\nline 2\nline 3"[..]);
+        assert_eq!(read(0), (0, "line 1\nline 2\nline 3".to_string(), false));
+        assert_eq!(read(7), (7, "line 2\nline 3".to_string(), false));
+
+        // the first line is read by all tags
+        stream.consume(7);
+        assert_eq!(read(7), (7, "line 2\nline 3".to_string(), false));
+        assert_eq!(read(0), (7, "line 2\nline 3".to_string(), false));
+        assert_eq!(read(100), (20, String::new(), false));
+
+        stream.consume(100);
+        assert_eq!(read(20), (20, String::new(), false));
+    }
+}
@@ -0,0 +1,248 @@
+//! Structured trace of the decision path of a sample of the lines read, to find out why a line triggered or didn't
+//! trigger a callback on large logfiles, without the flood of the Trace log level. Each sampled line is logged as a
+//! single JSON object in the clf log.
+use std::path::Path;
+
+use log::info;
+use serde::Serialize;
+
+use crate::configuration::{
+    options::SearchOptions,
+    pattern::{PatternCounters, PatternMatchResult},
+    tag::Tag,
+};
+
+/// What finally happened to a traced line.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum Outcome {
+    /// the line matches the `exclude` regex of the logfile
+    excluded,
+
+    /// none of the literals of the prefilter is found in the line, so no regex was evaluated
+    prefiltered,
+
+    /// no regex matched, or the match has been dismissed by an exception
+    no_match,
+
+    /// the match was already counted by a previous run, as the `savethresholds` option is set
+    already_counted,
+
+    /// the line was written before the window of the `timestamp` of the tag
+    outside_window,
+
+    /// the line required before or after the match by the `requires_before` or `requires_after` of the tag is missing
+    requirement_not_met,
+
+    /// a regex matched but its threshold is not reached yet
+    threshold_not_reached,
+
+    /// the incident of the line was already notified
+    already_notified,
+
+    /// an identical match was found within the `dedup` window
+    duplicate,
+
+    /// the `runcallback` option is not set
+    no_callback,
+
+    /// the `runlimit` option is reached
+    run_limit_reached,
+
+    /// the `rate_limit` option is reached for the current time window
+    rate_limited,
+
+    /// the callback is to be called, once the logfile is read
+    callback,
+}
+
+/// The decision path of a line for a tag.
+#[derive(Debug, Serialize)]
+pub struct LineTrace {
+    logfile: String,
+    tag: String,
+    line_number: u64,
+    line: String,
+
+    /// the exclude regex of the logfile, if it was tried
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exclude: Option<String>,
+
+    /// the pattern type which dismissed a match because of an exception
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exception: Option<&'static str>,
+
+    /// the pattern type and regex which matched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pattern_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    regex: Option<String>,
+
+    /// counters and thresholds once the line is matched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    critical_count: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warning_count: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    criticalthreshold: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warningthreshold: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    threshold_reached: Option<bool>,
+
+    outcome: Option<Outcome>,
+}
+
+/// Traces a line if it's sampled, and does nothing otherwise.
+#[derive(Debug, Default)]
+pub struct LineTracer(Option<LineTrace>);
+
+impl LineTracer {
+    /// Starts tracing a line with a probability of `ratio`.
+    pub fn sample(ratio: f64, path: &Path, tag: &str, line_number: u64, line: &str) -> Self {
+        if ratio <= 0.0 || rand::random::<f64>() >= ratio {
+            return LineTracer(None);
+        }
+
+        LineTracer(Some(LineTrace {
+            logfile: path.display().to_string(),
+            tag: tag.to_string(),
+            line_number,
+            line: line.to_string(),
+            exclude: None,
+            exception: None,
+            pattern_type: None,
+            regex: None,
+            critical_count: None,
+            warning_count: None,
+            criticalthreshold: None,
+            warningthreshold: None,
+            threshold_reached: None,
+            outcome: None,
+        }))
+    }
+
+    #[cfg(test)]
+    fn is_sampled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Records the exclude regex tried on the line.
+    pub fn exclude(&mut self, re: &regex::Regex) {
+        if let Some(trace) = &mut self.0 {
+            trace.exclude = Some(re.as_str().to_string());
+        }
+    }
+
+    /// Records that no regex matched, and whether it's because of an exception.
+    pub fn no_match(&mut self, tag: &Tag) {
+        if let Some(trace) = &mut self.0 {
+            trace.exception = tag
+                .patterns
+                .is_exception(&trace.line)
+                .map(|pattern_type| <&str>::from(&pattern_type));
+        }
+    }
+
+    /// Records the match along with the counters and thresholds.
+    pub fn matched(
+        &mut self,
+        pattern_match: &PatternMatchResult,
+        counters: &PatternCounters,
+        options: &SearchOptions,
+        reached: bool,
+    ) {
+        if let Some(trace) = &mut self.0 {
+            trace.pattern_type = Some(<&str>::from(&pattern_match.pattern_type));
+            trace.regex = Some(pattern_match.regex.as_str().to_string());
+            trace.critical_count = Some(counters.critical_count);
+            trace.warning_count = Some(counters.warning_count);
+            trace.criticalthreshold = Some(options.criticalthreshold);
+            trace.warningthreshold = Some(options.warningthreshold);
+            trace.threshold_reached = Some(reached);
+        }
+    }
+
+    /// Logs the trace with its outcome. Nothing is logged afterwards.
+    pub fn end(&mut self, outcome: Outcome) {
+        if let Some(json) = self.finish(outcome) {
+            info!("line trace: {}", json);
+        }
+    }
+
+    // sets the outcome and returns the JSON trace, if the line is traced
+    fn finish(&mut self, outcome: Outcome) -> Option<String> {
+        let mut trace = self.0.take()?;
+        trace.outcome = Some(outcome);
+        serde_json::to_string(&trace)
+            .map_err(|e| info!("unable to serialize line trace: {}", e))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn line_tracer() {
+        let path = Path::new("/var/log/syslog");
+
+        assert!(!LineTracer::sample(0.0, path, "error", 1, "ERROR").is_sampled());
+        assert!(LineTracer::sample(1.0, path, "error", 1, "ERROR").is_sampled());
+
+        // roughly the requested fraction is sampled
+        let sampled = (0..10_000)
+            .filter(|i| LineTracer::sample(0.1, path, "error", *i, "ERROR").is_sampled())
+            .count();
+        assert!((500..1500).contains(&sampled), "{}", sampled);
+
+        let tag = Tag::from_str(
+            r#"
+name: error
+process: true
+options: "criticalthreshold=2"
+patterns:
+    critical: {
+        regexes: ["^ERROR"],
+        exceptions: ["^ERROR 42"]
+    }
+"#,
+        )
+        .unwrap();
+
+        // dismissed by an exception
+        let mut tracer = LineTracer::sample(1.0, path, "error", 1, "ERROR 42");
+        tracer.no_match(&tag);
+        let json: serde_json::Value =
+            serde_json::from_str(&tracer.finish(Outcome::no_match).unwrap()).unwrap();
+        assert_eq!(json["exception"], "critical");
+        assert_eq!(json["outcome"], "no_match");
+        assert!(json.get("pattern_type").is_none());
+
+        let mut tracer = LineTracer::sample(1.0, path, "error", 2, "ERROR 43");
+        tracer.exclude(&regex::Regex::new("^#").unwrap());
+        let pattern_match = tag.is_match("ERROR 43").unwrap();
+        let counters = PatternCounters {
+            critical_count: 1,
+            ..Default::default()
+        };
+        tracer.matched(&pattern_match, &counters, &tag.options, false);
+
+        let json: serde_json::Value =
+            serde_json::from_str(&tracer.finish(Outcome::threshold_not_reached).unwrap()).unwrap();
+        assert_eq!(json["line_number"], 2);
+        assert_eq!(json["exclude"], "^#");
+        assert_eq!(json["pattern_type"], "critical");
+        assert_eq!(json["regex"], "^ERROR");
+        assert_eq!(json["critical_count"], 1);
+        assert_eq!(json["criticalthreshold"], 2);
+        assert_eq!(json["threshold_reached"], false);
+        assert_eq!(json["outcome"], "threshold_not_reached");
+
+        // nothing is traced once ended
+        assert!(!tracer.is_sampled());
+        assert!(tracer.finish(Outcome::callback).is_none());
+    }
+}
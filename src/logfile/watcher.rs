@@ -0,0 +1,482 @@
+//! Watching logfiles in follow mode: with the `follow_events` global option, the directories holding the logfiles are
+//! watched through inotify on Linux and ReadDirectoryChangesW on Windows, so only the logfiles which changed are
+//! searched again, as soon as they change, instead of searching all logfiles at each follow interval.
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::misc::error::AppResult;
+
+/// What the operating system reported for a watched directory.
+#[derive(Debug)]
+enum Event {
+    /// a file of the directory was created, written, renamed or deleted
+    Changed(PathBuf, OsString),
+
+    /// events were lost, so any logfile could have changed
+    Overflow,
+}
+
+/// The logfiles watched, grouped by directory. Their file names are mapped to their paths as given in the searches.
+type Watched = HashMap<PathBuf, HashMap<OsString, PathBuf>>;
+
+/// Watches the directories of a set of logfiles.
+pub struct Watcher {
+    watched: Watched,
+    inner: platform::Inner,
+}
+
+impl Watcher {
+    /// Starts watching the directories of the logfiles.
+    pub fn new<'a>(paths: impl Iterator<Item = &'a Path>) -> AppResult<Self> {
+        let mut watched = Watched::new();
+        for path in paths {
+            if let Some(name) = path.file_name() {
+                let dir = match path.parent() {
+                    Some(dir) if dir != Path::new("") => dir.to_path_buf(),
+                    _ => PathBuf::from("."),
+                };
+                watched
+                    .entry(dir)
+                    .or_default()
+                    .insert(name.to_os_string(), path.to_path_buf());
+            }
+        }
+
+        let inner = platform::Inner::new(watched.keys())?;
+        Ok(Watcher { watched, inner })
+    }
+
+    /// `true` if the logfiles watched are exactly these ones.
+    pub fn watches<'a>(&self, paths: impl Iterator<Item = &'a Path>) -> bool {
+        let watched: HashSet<_> = self
+            .watched
+            .values()
+            .flat_map(|names| names.values().map(PathBuf::as_path))
+            .collect();
+        paths.collect::<HashSet<_>>() == watched
+    }
+
+    /// Waits at most `timeout` for changes, and returns the paths of the logfiles which changed.
+    pub fn changes(&mut self, timeout: Duration) -> AppResult<HashSet<PathBuf>> {
+        let mut changed = HashSet::new();
+        for event in self.inner.wait(timeout)? {
+            match event {
+                Event::Changed(dir, name) => {
+                    if let Some(path) = self.watched.get(&dir).and_then(|names| names.get(&name)) {
+                        changed.insert(path.clone());
+                    }
+                }
+                Event::Overflow => changed.extend(
+                    self.watched
+                        .values()
+                        .flat_map(|names| names.values().cloned()),
+                ),
+            }
+        }
+        Ok(changed)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::collections::HashMap;
+    use std::ffi::{CString, OsStr};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use super::Event;
+    use crate::context;
+    use crate::misc::error::{AppError, AppResult};
+
+    // a name is at most NAME_MAX bytes, so the buffer always holds at least one event
+    const BUFFER_SIZE: usize = 64 * 1024;
+
+    pub struct Inner {
+        fd: libc::c_int,
+        dirs: HashMap<libc::c_int, PathBuf>,
+    }
+
+    impl Inner {
+        pub fn new<'a>(dirs: impl Iterator<Item = &'a PathBuf>) -> AppResult<Self> {
+            let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+            if fd == -1 {
+                let e = std::io::Error::last_os_error();
+                return Err(context!(e, "unable to initialize inotify",));
+            }
+            let mut inner = Inner {
+                fd,
+                dirs: HashMap::new(),
+            };
+
+            let mask = libc::IN_MODIFY
+                | libc::IN_CLOSE_WRITE
+                | libc::IN_CREATE
+                | libc::IN_DELETE
+                | libc::IN_MOVED_FROM
+                | libc::IN_MOVED_TO;
+            for dir in dirs {
+                let cdir = CString::new(dir.as_os_str().as_bytes()).map_err(|e| {
+                    let e = std::io::Error::from(e);
+                    context!(e, "invalid directory name {:?}", dir)
+                })?;
+                let wd = unsafe { libc::inotify_add_watch(fd, cdir.as_ptr(), mask) };
+                if wd == -1 {
+                    let e = std::io::Error::last_os_error();
+                    return Err(context!(e, "unable to watch directory {:?}", dir));
+                }
+                inner.dirs.insert(wd, dir.clone());
+            }
+
+            Ok(inner)
+        }
+
+        pub fn wait(&mut self, timeout: Duration) -> AppResult<Vec<Event>> {
+            let mut pollfd = libc::pollfd {
+                fd: self.fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ready = unsafe { libc::poll(&mut pollfd, 1, timeout.as_millis() as libc::c_int) };
+            if ready == -1 {
+                let e = std::io::Error::last_os_error();
+                if e.kind() == std::io::ErrorKind::Interrupted {
+                    return Ok(Vec::new());
+                }
+                return Err(context!(e, "error waiting for inotify events",));
+            }
+
+            // all pending events are read at once
+            let mut events = Vec::new();
+            let mut buffer = vec![0u8; BUFFER_SIZE];
+            loop {
+                let n = unsafe {
+                    libc::read(
+                        self.fd,
+                        buffer.as_mut_ptr() as *mut libc::c_void,
+                        buffer.len(),
+                    )
+                };
+                if n == -1 {
+                    let e = std::io::Error::last_os_error();
+                    if e.kind() == std::io::ErrorKind::WouldBlock {
+                        break;
+                    }
+                    return Err(context!(e, "error reading inotify events",));
+                }
+                if n == 0 {
+                    break;
+                }
+
+                let mut offset = 0;
+                while offset < n as usize {
+                    let event: libc::inotify_event = unsafe {
+                        std::ptr::read_unaligned(
+                            buffer[offset..].as_ptr() as *const libc::inotify_event
+                        )
+                    };
+                    let start = offset + std::mem::size_of::<libc::inotify_event>();
+                    offset = start + event.len as usize;
+
+                    if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                        events.push(Event::Overflow);
+                        continue;
+                    }
+
+                    // the name is padded with NUL bytes
+                    let name = &buffer[start..offset];
+                    let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+                    if let Some(dir) = self.dirs.get(&event.wd) {
+                        events.push(Event::Changed(
+                            dir.clone(),
+                            OsStr::from_bytes(name).to_os_string(),
+                        ));
+                    }
+                }
+            }
+
+            Ok(events)
+        }
+    }
+
+    impl Drop for Inner {
+        fn drop(&mut self) {
+            unsafe { libc::close(self.fd) };
+        }
+    }
+}
+
+#[cfg(target_family = "windows")]
+mod platform {
+    use std::ffi::OsString;
+    use std::os::windows::ffi::{OsStrExt, OsStringExt};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use windows_sys::Win32::Foundation::{
+        CloseHandle, GetLastError, ERROR_IO_INCOMPLETE, HANDLE, INVALID_HANDLE_VALUE, WAIT_FAILED,
+    };
+    use windows_sys::Win32::Storage::FileSystem::{
+        CreateFileW, ReadDirectoryChangesW, FILE_FLAG_BACKUP_SEMANTICS, FILE_FLAG_OVERLAPPED,
+        FILE_LIST_DIRECTORY, FILE_NOTIFY_CHANGE_FILE_NAME, FILE_NOTIFY_CHANGE_LAST_WRITE,
+        FILE_NOTIFY_CHANGE_SIZE, FILE_NOTIFY_INFORMATION, FILE_SHARE_DELETE, FILE_SHARE_READ,
+        FILE_SHARE_WRITE, OPEN_EXISTING,
+    };
+    use windows_sys::Win32::System::Threading::{CreateEventW, ResetEvent, WaitForMultipleObjects};
+    use windows_sys::Win32::System::IO::{CancelIo, GetOverlappedResult, OVERLAPPED};
+
+    use super::Event;
+    use crate::context;
+    use crate::misc::error::{AppError, AppResult};
+
+    // size of the buffer receiving the changes of a directory, as DWORDs to be properly aligned
+    const BUFFER_SIZE: usize = 16 * 1024;
+
+    // WaitForMultipleObjects() can't wait for more handles, other directories are checked at each wait
+    const MAXIMUM_WAIT_OBJECTS: usize = 64;
+
+    // a directory with a pending ReadDirectoryChangesW() call. The overlapped structure and the buffer are boxed, as
+    // the system writes to them until the call completes
+    struct Directory {
+        path: PathBuf,
+        handle: HANDLE,
+        overlapped: Box<OVERLAPPED>,
+        buffer: Box<[u32; BUFFER_SIZE]>,
+    }
+
+    pub struct Inner {
+        dirs: Vec<Directory>,
+    }
+
+    impl Directory {
+        fn new(path: &PathBuf) -> AppResult<Self> {
+            let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+            let handle = unsafe {
+                CreateFileW(
+                    wide.as_ptr(),
+                    FILE_LIST_DIRECTORY,
+                    FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+                    std::ptr::null(),
+                    OPEN_EXISTING,
+                    FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OVERLAPPED,
+                    std::ptr::null_mut(),
+                )
+            };
+            if handle == INVALID_HANDLE_VALUE {
+                let e = std::io::Error::last_os_error();
+                return Err(context!(e, "unable to open directory {:?}", path));
+            }
+
+            let mut overlapped: Box<OVERLAPPED> = Box::new(unsafe { std::mem::zeroed() });
+            overlapped.hEvent = unsafe { CreateEventW(std::ptr::null(), 1, 0, std::ptr::null()) };
+            if overlapped.hEvent.is_null() {
+                let e = std::io::Error::last_os_error();
+                unsafe { CloseHandle(handle) };
+                return Err(context!(
+                    e,
+                    "unable to create event for directory {:?}",
+                    path
+                ));
+            }
+
+            let mut dir = Directory {
+                path: path.clone(),
+                handle,
+                overlapped,
+                buffer: Box::new([0u32; BUFFER_SIZE]),
+            };
+            dir.watch()?;
+            Ok(dir)
+        }
+
+        // starts waiting for the next changes
+        fn watch(&mut self) -> AppResult<()> {
+            unsafe { ResetEvent(self.overlapped.hEvent) };
+            let ok = unsafe {
+                ReadDirectoryChangesW(
+                    self.handle,
+                    self.buffer.as_mut_ptr() as *mut std::ffi::c_void,
+                    (BUFFER_SIZE * 4) as u32,
+                    0,
+                    FILE_NOTIFY_CHANGE_FILE_NAME
+                        | FILE_NOTIFY_CHANGE_SIZE
+                        | FILE_NOTIFY_CHANGE_LAST_WRITE,
+                    std::ptr::null_mut(),
+                    &mut *self.overlapped,
+                    None,
+                )
+            };
+            if ok == 0 {
+                let e = std::io::Error::last_os_error();
+                return Err(context!(e, "unable to watch directory {:?}", self.path));
+            }
+            Ok(())
+        }
+
+        // the changes of the directory if the pending call completed
+        fn events(&mut self, events: &mut Vec<Event>) -> AppResult<()> {
+            let mut bytes = 0u32;
+            let ok = unsafe { GetOverlappedResult(self.handle, &*self.overlapped, &mut bytes, 0) };
+            if ok == 0 {
+                if unsafe { GetLastError() } == ERROR_IO_INCOMPLETE {
+                    return Ok(());
+                }
+                let e = std::io::Error::last_os_error();
+                return Err(context!(
+                    e,
+                    "error reading changes of directory {:?}",
+                    self.path
+                ));
+            }
+
+            // no byte means the buffer was too small to hold all changes
+            if bytes == 0 {
+                events.push(Event::Overflow);
+            }
+
+            let buffer = self.buffer.as_ptr() as *const u8;
+            let mut offset = 0usize;
+            while bytes != 0 {
+                let info = unsafe { &*(buffer.add(offset) as *const FILE_NOTIFY_INFORMATION) };
+                let name = unsafe {
+                    std::slice::from_raw_parts(
+                        info.FileName.as_ptr(),
+                        info.FileNameLength as usize / 2,
+                    )
+                };
+                events.push(Event::Changed(self.path.clone(), OsString::from_wide(name)));
+
+                if info.NextEntryOffset == 0 {
+                    break;
+                }
+                offset += info.NextEntryOffset as usize;
+            }
+
+            self.watch()
+        }
+    }
+
+    impl Drop for Directory {
+        fn drop(&mut self) {
+            unsafe {
+                CancelIo(self.handle);
+                CloseHandle(self.handle);
+                CloseHandle(self.overlapped.hEvent);
+            }
+        }
+    }
+
+    impl Inner {
+        pub fn new<'a>(dirs: impl Iterator<Item = &'a PathBuf>) -> AppResult<Self> {
+            let dirs = dirs.map(Directory::new).collect::<AppResult<_>>()?;
+            Ok(Inner { dirs })
+        }
+
+        pub fn wait(&mut self, timeout: Duration) -> AppResult<Vec<Event>> {
+            let handles: Vec<HANDLE> = self
+                .dirs
+                .iter()
+                .take(MAXIMUM_WAIT_OBJECTS)
+                .map(|dir| dir.overlapped.hEvent)
+                .collect();
+            if !handles.is_empty() {
+                let ret = unsafe {
+                    WaitForMultipleObjects(
+                        handles.len() as u32,
+                        handles.as_ptr(),
+                        0,
+                        timeout.as_millis() as u32,
+                    )
+                };
+                if ret == WAIT_FAILED {
+                    let e = std::io::Error::last_os_error();
+                    return Err(context!(e, "error waiting for directory changes",));
+                }
+            }
+
+            let mut events = Vec::new();
+            for dir in self.dirs.iter_mut() {
+                dir.events(&mut events)?;
+            }
+            Ok(events)
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_family = "windows")))]
+mod platform {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use super::Event;
+    use crate::misc::error::{AppCustomErrorKind, AppError, AppResult};
+
+    pub struct Inner;
+
+    impl Inner {
+        pub fn new<'a>(_dirs: impl Iterator<Item = &'a PathBuf>) -> AppResult<Self> {
+            Err(AppError::new_custom(
+                AppCustomErrorKind::UnsupportedOnPlatform,
+                "watching logfiles is only supported on Linux and Windows",
+            ))
+        }
+
+        pub fn wait(&mut self, _timeout: Duration) -> AppResult<Vec<Event>> {
+            Ok(Vec::new())
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(target_os = "linux")]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn watcher() {
+        let dir = std::env::temp_dir().join("clf_watcher");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let watched = dir.join("watched.log");
+        let other = dir.join("other.log");
+        std::fs::write(&watched, "INFO\n").unwrap();
+        std::fs::write(&other, "INFO\n").unwrap();
+
+        let mut watcher = Watcher::new(std::iter::once(watched.as_path())).unwrap();
+        assert!(watcher.watches(std::iter::once(watched.as_path())));
+        assert!(!watcher.watches(vec![watched.as_path(), other.as_path()].into_iter()));
+        assert!(watcher
+            .changes(Duration::from_millis(10))
+            .unwrap()
+            .is_empty());
+
+        // files not watched in the same directory are ignored
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&other)
+            .and_then(|mut f| f.write_all(b"ERROR\n"))
+            .unwrap();
+        assert!(watcher
+            .changes(Duration::from_millis(100))
+            .unwrap()
+            .is_empty());
+
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&watched)
+            .and_then(|mut f| f.write_all(b"ERROR\n"))
+            .unwrap();
+        let changed = watcher.changes(Duration::from_secs(1)).unwrap();
+        assert_eq!(changed, HashSet::from([watched.clone()]));
+
+        // a rotation is a change too
+        std::fs::rename(&watched, dir.join("watched.log.1")).unwrap();
+        let changed = watcher.changes(Duration::from_secs(1)).unwrap();
+        assert!(changed.contains(&watched));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,7 +0,0 @@
-mod config;
-mod logfile;
-mod misc;
-
-fn main() {
-    println!("Hello, world!");
-}
@@ -18,6 +18,24 @@ pub enum AppCustomErrorKind {
     OsStringConversionError,
     FileSizeIsLessThanHashWindow,
     PhantomCloneError,
+    SearchTimeout,
+    Interrupted,
+    LogfileClaimed,
+    NulBytes,
+    InvalidContainerLine,
+    UnsupportedOutputFormat,
+    InvalidAddress,
+    UnsupportedOnPlatform,
+    JournalError,
+    HttpError,
+    SystemdUnitsError,
+    TemplateError,
+    SmtpError,
+    InvalidGlobPattern,
+    InvalidRawLine,
+    RemoteError,
+    SnapshotConflict,
+    NotBuiltIn,
     #[cfg(target_family = "windows")]
     WindowsApiError,
 }
@@ -43,6 +61,44 @@ impl fmt::Display for AppCustomErrorKind {
                 write!(f, "conversion from OsString failed")
             }
             AppCustomErrorKind::PhantomCloneError => write!(f, "no error"),
+            AppCustomErrorKind::SearchTimeout => {
+                write!(f, "search timeout reached before end of logfile")
+            }
+            AppCustomErrorKind::Interrupted => {
+                write!(f, "search interrupted by a shutdown signal")
+            }
+            AppCustomErrorKind::LogfileClaimed => {
+                write!(f, "logfile claimed by another instance")
+            }
+            AppCustomErrorKind::NulBytes => write!(f, "NUL bytes found in logfile"),
+            AppCustomErrorKind::InvalidContainerLine => {
+                write!(f, "line is not a valid container log entry")
+            }
+            AppCustomErrorKind::UnsupportedOutputFormat => {
+                write!(f, "the specified output format is not supported")
+            }
+            AppCustomErrorKind::InvalidAddress => write!(f, "the TCP address is not valid"),
+            AppCustomErrorKind::UnsupportedOnPlatform => {
+                write!(f, "the feature is not supported on this platform")
+            }
+            AppCustomErrorKind::JournalError => write!(f, "unable to read the systemd journal"),
+            AppCustomErrorKind::HttpError => write!(f, "the HTTP request failed"),
+            AppCustomErrorKind::SystemdUnitsError => {
+                write!(f, "unable to query the systemd units")
+            }
+            AppCustomErrorKind::TemplateError => {
+                write!(f, "unable to render the configuration template")
+            }
+            AppCustomErrorKind::SmtpError => write!(f, "the SMTP server refused the message"),
+            AppCustomErrorKind::InvalidGlobPattern => write!(f, "the glob pattern is not valid"),
+            AppCustomErrorKind::InvalidRawLine => write!(f, "the raw line could not be decoded"),
+            AppCustomErrorKind::RemoteError => write!(f, "unable to read the remote logfile"),
+            AppCustomErrorKind::SnapshotConflict => {
+                write!(f, "the logfile already has an entry in the snapshot")
+            }
+            AppCustomErrorKind::NotBuiltIn => {
+                write!(f, "the feature is not built into this binary")
+            }
             #[cfg(target_family = "windows")]
             AppCustomErrorKind::WindowsApiError => write!(f, "Windows API error"),
         }
@@ -59,6 +115,8 @@ pub enum InternalError {
     Json(serde_json::Error),
     SystemTime(std::time::SystemTimeError),
     Utf8(std::str::Utf8Error),
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
     Custom(AppCustomErrorKind),
 }
 
@@ -80,6 +138,8 @@ from_error!(serde_json::Error, InternalError::Json);
 from_error!(std::time::SystemTimeError, InternalError::SystemTime);
 from_error!(num::ParseIntError, InternalError::Parse);
 from_error!(std::str::Utf8Error, InternalError::Utf8);
+#[cfg(feature = "sqlite")]
+from_error!(rusqlite::Error, InternalError::Sqlite);
 
 /// Custom error which will be used for all errors conversions and throughout the code.
 #[derive(Debug)]
@@ -120,6 +180,8 @@ impl fmt::Display for AppError {
             InternalError::SystemTime(ref err) => {
                 write!(f, "system time error: {} ({})", self.msg, err)
             }
+            #[cfg(feature = "sqlite")]
+            InternalError::Sqlite(ref err) => write!(f, "SQLite error: {} ({})", self.msg, err),
             InternalError::Custom(ref err) => write!(f, "custom error: {} ({})", self.msg, err),
         }
     }
@@ -186,6 +248,13 @@ mod tests {
         assert!(matches!(err.error_kind, InternalError::Utf8(_)));
         println!("{}", err);
 
+        #[cfg(feature = "sqlite")]
+        {
+            let err = sqlite().unwrap_err();
+            assert!(matches!(err.error_kind, InternalError::Sqlite(_)));
+            println!("{}", err);
+        }
+
         let err = custom();
         assert!(matches!(err.error_kind, InternalError::Custom(_)));
         println!("{}", err);
@@ -263,6 +332,15 @@ mod tests {
         Ok(s.to_string())
     }
 
+    #[cfg(feature = "sqlite")]
+    fn sqlite() -> AppResult<()> {
+        let conn = rusqlite::Connection::open_in_memory()
+            .map_err(|e| context!(e, "unable to open database",))?;
+        conn.execute_batch("SELECT * FROM foo")
+            .map_err(|e| context!(e, "unable to query table {}", "foo"))?;
+        Ok(())
+    }
+
     fn custom() -> AppError {
         let path = "/foo/foo.foo";
         let custom_err = AppError::new_custom(
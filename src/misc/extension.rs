@@ -1,5 +1,5 @@
 //! Traits defined here to extend Rust standard structures.
-use std::io::{BufReader, Read};
+use std::io::{BufReader, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::{
@@ -15,7 +15,6 @@ use regex::Regex;
 use serde::{Deserialize, Serialize};
 
 use crate::misc::error::{AppCustomErrorKind, AppError, AppResult};
-use crate::misc::nagios::Nagios;
 
 // specific linking for Windows signature
 #[cfg(target_family = "windows")]
@@ -32,6 +31,68 @@ pub struct Signature {
     pub dev: u64,
     pub size: u64,
     pub hash: Option<u64>,
+
+    /// algorithm of the hashes: a signature is only compared to one calculated the same way
+    #[serde(default)]
+    pub algorithm: HashAlgorithm,
+
+    /// number of bytes hashed before `size`, which don't change as long as the logfile is only appended to
+    #[serde(default)]
+    pub tail_window: u64,
+
+    #[serde(default)]
+    pub tail_hash: Option<u64>,
+}
+
+/// The algorithm used to hash the first bytes of a logfile.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[allow(non_camel_case_types)]
+pub enum HashAlgorithm {
+    #[default]
+    crc64,
+    xxhash64,
+    blake3,
+}
+
+impl HashAlgorithm {
+    /// Hashes the buffer into a 64-bit value.
+    pub fn digest(&self, buffer: &[u8]) -> u64 {
+        match self {
+            HashAlgorithm::crc64 => crc::crc64::checksum_iso(buffer),
+            HashAlgorithm::xxhash64 => xxhash_rust::xxh64::xxh64(buffer, 0),
+            #[cfg(feature = "blake3")]
+            HashAlgorithm::blake3 => {
+                let hash = blake3::hash(buffer);
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(&hash.as_bytes()[..8]);
+                u64::from_le_bytes(bytes)
+            }
+
+            // the configuration is checked against the build, but a snapshot could have been saved by another build:
+            // the default algorithm gives another hash, as for a logfile which was rotated
+            #[cfg(not(feature = "blake3"))]
+            HashAlgorithm::blake3 => HashAlgorithm::default().digest(buffer),
+        }
+    }
+}
+
+/// How the hashes of a signature are calculated: the first `head` bytes of the logfile, and optionally its last
+/// `tail` bytes.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct HashSpec {
+    pub algorithm: HashAlgorithm,
+    pub head: usize,
+    pub tail: usize,
+}
+
+impl From<usize> for HashSpec {
+    fn from(head: usize) -> Self {
+        HashSpec {
+            algorithm: HashAlgorithm::default(),
+            head,
+            tail: 0,
+        }
+    }
 }
 
 // specific to Windows
@@ -43,30 +104,58 @@ struct WinSign {
 }
 
 impl Signature {
-    fn hash<P: AsRef<Path> + Debug>(path: P, hash_buffer_size: usize) -> AppResult<u64> {
-        use crc::crc64;
-        debug_assert!(hash_buffer_size != 0);
-        trace!("hash_buffer_size = {}", hash_buffer_size);
+    /// Hashes `length` bytes of the file, starting at `offset`.
+    pub fn hash<P: AsRef<Path> + Debug>(
+        path: P,
+        algorithm: HashAlgorithm,
+        offset: u64,
+        length: usize,
+    ) -> AppResult<u64> {
+        debug_assert!(length != 0);
+        trace!("offset = {}, length = {}", offset, length);
 
         // open file
         let mut file = File::open(path.as_ref())
             .map_err(|e| context!(e, "unable to open file for calculating hash {:?}", path))?;
+        file.seek(SeekFrom::Start(offset))
+            .map_err(|e| context!(e, "path={:?}, seek({})", path, offset))?;
 
-        //let mut reader = BufReader::new(&file);
-        let mut buffer = vec![0; hash_buffer_size];
+        let mut buffer = vec![0; length];
 
         file.read_exact(&mut buffer)
             .map_err(|e| context!(e, "path={:?}, read_exact()", path))?;
 
-        // calculate xxhash64
-        let hash = crc64::checksum_iso(&buffer);
+        let hash = algorithm.digest(&buffer);
         debug!(
-            "path={:?}, hash_buffer_size={}, hash={}",
-            path, hash_buffer_size, hash
+            "path={:?}, algorithm={:?}, offset={}, length={}, hash={}",
+            path, algorithm, offset, length, hash
         );
 
         Ok(hash)
     }
+
+    // calculates the hashes once the size is known. The head hash is only calculated if the file size is larger
+    // than the head window
+    fn set_hashes<P: AsRef<Path> + Debug>(&mut self, path: P, spec: &HashSpec) -> AppResult<()> {
+        self.algorithm = spec.algorithm;
+        self.hash = if self.size < spec.head as u64 {
+            None
+        } else {
+            Some(Signature::hash(&path, spec.algorithm, 0, spec.head)?)
+        };
+
+        if spec.tail != 0 && self.size >= spec.tail as u64 {
+            self.tail_window = spec.tail as u64;
+            self.tail_hash = Some(Signature::hash(
+                &path,
+                spec.algorithm,
+                self.size - self.tail_window,
+                spec.tail,
+            )?);
+        }
+
+        Ok(())
+    }
 }
 
 /// All `PathBuf` utility functions.
@@ -74,7 +163,7 @@ pub trait ReadFs {
     fn is_match(self, re: &Regex) -> bool;
     fn is_usable(&self) -> AppResult<()>;
     fn list_files(&self, regex: &str) -> AppResult<Vec<PathBuf>>;
-    fn signature(&self, hash_buffer_size: usize) -> AppResult<Signature>;
+    fn signature<S: Into<HashSpec>>(&self, spec: S) -> AppResult<Signature>;
 }
 
 impl ReadFs for PathBuf {
@@ -126,7 +215,7 @@ impl ReadFs for PathBuf {
 
     // get inode and dev from file and calculate hash
     #[cfg(target_family = "unix")]
-    fn signature(&self, hash_buffer_size: usize) -> AppResult<Signature> {
+    fn signature<S: Into<HashSpec>>(&self, spec: S) -> AppResult<Signature> {
         use std::os::unix::fs::MetadataExt;
 
         // first get metadata fields for signature
@@ -134,19 +223,15 @@ impl ReadFs for PathBuf {
             .metadata()
             .map_err(|e| context!(e, "error fetching metadata for file {:?} ", self))?;
 
-        let mut signature = Signature::default();
-        signature.inode = metadata.ino();
-        signature.dev = metadata.dev();
-        signature.size = metadata.size();
-
-        // only calculate hash if file size is larger than hash buffer size
-        signature.hash = if signature.size < hash_buffer_size as u64 {
-            None
-        } else {
-            let hash = Signature::hash(&self, hash_buffer_size)?;
-            Some(hash)
+        let mut signature = Signature {
+            inode: metadata.ino(),
+            dev: metadata.dev(),
+            size: metadata.size(),
+            ..Default::default()
         };
 
+        signature.set_hashes(self, &spec.into())?;
+
         Ok(signature)
     }
 
@@ -154,7 +239,7 @@ impl ReadFs for PathBuf {
     // needs to convert a regular Rust string to an UTF16 unicode null-terminated string
     // this is because Win32 APIs needs a LPWCSTR type which a pointer on a null-terminated
     // UTF16 string
-    fn signature(&self, hash_buffer_size: usize) -> AppResult<Signature> {
+    fn signature<S: Into<HashSpec>>(&self, spec: S) -> AppResult<Signature> {
         use std::os::windows::fs::MetadataExt;
         use widestring::U16CString;
 
@@ -190,13 +275,7 @@ impl ReadFs for PathBuf {
         signature.dev = win_sign.dev;
         signature.size = metadata.file_size();
 
-        // only calculate hash if file size is larger than hash buffer size
-        signature.hash = if signature.size < hash_buffer_size as u64 {
-            None
-        } else {
-            let hash = Signature::hash(&self, hash_buffer_size)?;
-            Some(hash)
-        };
+        signature.set_hashes(self, &spec.into())?;
 
         Ok(signature)
     }
@@ -329,23 +408,6 @@ impl<R: Read> JsonRead for BufReader<R> {
     }
 }
 
-// helper functions to exit in case of error
-pub trait Expect<T> {
-    fn expect_critical(self, text: &str) -> T;
-}
-
-impl<T, E: Debug> Expect<T> for std::result::Result<T, E> {
-    fn expect_critical(self, msg: &str) -> T {
-        match self {
-            Ok(inner) => inner,
-            Err(e) => unwrap_failed(msg, &e),
-        }
-    }
-}
-fn unwrap_failed(msg: &str, error: &dyn Debug) -> ! {
-    Nagios::exit_critical(&format!("{}, error: {:?}", msg, error))
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
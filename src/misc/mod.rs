@@ -5,4 +5,8 @@ pub mod error;
 pub mod extension;
 pub mod macros;
 pub mod nagios;
+pub mod rundir;
+pub mod signal;
+#[cfg(target_os = "linux")]
+pub mod systemd;
 pub mod util;
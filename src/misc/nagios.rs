@@ -53,6 +53,27 @@ impl Default for NagiosError {
     }
 }
 
+impl NagiosError {
+    /// The order used when aggregating errors: critical first, then warning, unknown and ok
+    pub fn severity(&self) -> u8 {
+        match self {
+            NagiosError::OK => 0,
+            NagiosError::UNKNOWN => 1,
+            NagiosError::WARNING => 2,
+            NagiosError::CRITICAL => 3,
+        }
+    }
+
+    /// Returns the strictest error of both
+    pub fn strictest<'a>(&'a self, other: &'a NagiosError) -> &'a NagiosError {
+        if other.severity() > self.severity() {
+            other
+        } else {
+            self
+        }
+    }
+}
+
 /// Simple conversion from a string
 impl FromStr for NagiosError {
     type Err = ();
@@ -115,14 +136,22 @@ pub struct NagiosExit {
 
     /// Optional error if an error occured reading file
     pub error_msg: Option<String>,
+
+    /// Line matched by a sentinel tag, which makes the exit state critical whatever the counters
+    pub sentinel: Option<String>,
+
+    /// Number of matches not notified because of the `dedup` option.
+    pub dup_count: u64,
 }
 
 impl From<&RunData> for NagiosExit {
     fn from(run_data: &RunData) -> Self {
         let mut nagios_exit = NagiosExit::default();
 
-        nagios_exit.critical_count = run_data.counters.critical_count;
-        nagios_exit.warning_count = run_data.counters.warning_count;
+        let counters = run_data.reported_counters();
+        nagios_exit.critical_count = counters.critical_count;
+        nagios_exit.warning_count = counters.warning_count;
+        nagios_exit.dup_count = counters.dup_count;
         if run_data.last_error.is_some() {
             nagios_exit.unknown_count = 1;
             let error_msg = format!("{}", run_data.last_error.as_ref().unwrap());
@@ -130,6 +159,7 @@ impl From<&RunData> for NagiosExit {
         } else {
             nagios_exit.error_msg = None;
         }
+        nagios_exit.sentinel = run_data.sentinel_line.clone();
         nagios_exit
     }
 }
@@ -138,62 +168,118 @@ impl From<&RunData> for NagiosExit {
 impl From<&NagiosExit> for NagiosError {
     fn from(m: &NagiosExit) -> Self {
         match m {
+            // a sentinel match dominates all counters
+            NagiosExit {
+                sentinel: Some(_), ..
+            } => NagiosError::CRITICAL,
+
             // neither errors nor warnings
             NagiosExit {
                 critical_count: 0,
                 warning_count: 0,
                 unknown_count: 0,
-                error_msg: _,
+                ..
             } => NagiosError::OK,
 
             // unkowns only
             NagiosExit {
                 critical_count: 0,
                 warning_count: 0,
-                unknown_count: _,
-                error_msg: _,
+                ..
             } => NagiosError::UNKNOWN,
 
             // only warnings errors
             NagiosExit {
-                critical_count: 0,
-                warning_count: _,
-                unknown_count: _,
-                error_msg: _,
+                critical_count: 0, ..
             } => NagiosError::WARNING,
 
             // critical errors
-            NagiosExit {
-                critical_count: _,
-                warning_count: _,
-                unknown_count: _,
-                error_msg: _,
-            } => NagiosError::CRITICAL,
+            NagiosExit { .. } => NagiosError::CRITICAL,
         }
     }
 }
 
+/// Version of the summary line format, increased only if a key is changed or removed.
+pub const SUMMARY_VERSION: u32 = 1;
+
+/// A single-line summary of the run, printed after the plugin output for log scrapers. Its format is stable: keys keep
+/// their name, order and meaning, new keys are only added at the end.
+#[derive(Debug, Default)]
+pub struct RunSummary {
+    /// the plugin exit status
+    pub state: NagiosError,
+
+    /// number of critical matches, including critical access errors
+    pub criticals: u64,
+
+    /// number of warning matches, including warning access errors
+    pub warnings: u64,
+
+    /// number of logfiles searched during the run
+    pub files: usize,
+
+    /// duration of the run in seconds
+    pub duration: f64,
+
+    /// number of errors reading logfiles, reported as unknowns
+    pub errors: u64,
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "CLF_SUMMARY version={} state={} criticals={} warnings={} files={} duration={:.3} errors={}",
+            SUMMARY_VERSION,
+            String::from(&self.state),
+            self.criticals,
+            self.warnings,
+            self.files,
+            self.duration,
+            self.errors
+        )
+    }
+}
+
 /// Formatted string used to output to NRPE
 impl fmt::Display for NagiosExit {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         // get error code from counters
         let nagios_err = NagiosError::from(self);
 
+        // duplicates are only given if any
+        let duplicates = if self.dup_count != 0 {
+            format!(", duplicates:{}", self.dup_count)
+        } else {
+            String::new()
+        };
+
+        // the sentinel line is given verbatim first
+        if let Some(line) = &self.sentinel {
+            write!(f, "{:?}: sentinel match: {} ", nagios_err, line)?;
+            return write!(
+                f,
+                "(errors:{}, warnings:{}, unknowns:{}{})",
+                self.critical_count, self.warning_count, self.unknown_count, duplicates
+            );
+        }
+
         // output is depending whether we found an error
         if self.error_msg.is_none() {
             write!(
                 f,
-                "{:?}: (errors:{}, warnings:{}, unknowns:{})",
-                nagios_err, self.critical_count, self.warning_count, self.unknown_count
+                "{:?}: (errors:{}, warnings:{}, unknowns:{}{})",
+                nagios_err, self.critical_count, self.warning_count, self.unknown_count, duplicates
             )
         } else {
             write!(
                 f,
-                "{:?}: (errors:{}, warnings:{}, unknowns:{}) - error: {}",
+                "{:?}: (errors:{}, warnings:{}, unknowns:{}{}) - error: {}",
                 nagios_err,
                 self.critical_count,
                 self.warning_count,
                 self.unknown_count,
+                duplicates,
                 self.error_msg.as_ref().unwrap()
             )
         }
@@ -212,6 +298,8 @@ mod tests {
             warning_count: 100,
             unknown_count: 0,
             error_msg: None,
+            sentinel: None,
+            dup_count: 0,
         };
         assert_eq!(
             &format!("{}", m),
@@ -223,6 +311,12 @@ mod tests {
             &format!("{}", m),
             "CRITICAL: (errors:10, warnings:100, unknowns:1)"
         );
+
+        m.dup_count = 7;
+        assert_eq!(
+            &format!("{}", m),
+            "CRITICAL: (errors:10, warnings:100, unknowns:1, duplicates:7)"
+        );
     }
 
     #[test]
@@ -232,6 +326,8 @@ mod tests {
             warning_count: 0,
             unknown_count: 0,
             error_msg: None,
+            sentinel: None,
+            dup_count: 0,
         };
         assert_eq!(NagiosError::from(&m), NagiosError::OK);
 
@@ -277,4 +373,45 @@ mod tests {
         assert_eq!(nexit.unknown_count, 1);
         assert!(nexit.error_msg.is_some());
     }
+
+    #[test]
+    fn sentinel() {
+        let mut s = RunData::default();
+        s.counters.warning_count = 1;
+        s.sentinel_line = Some("Kernel panic - not syncing: Fatal exception".to_string());
+
+        let nexit = NagiosExit::from(&s);
+        assert_eq!(NagiosError::from(&nexit), NagiosError::CRITICAL);
+        assert_eq!(
+            &format!("{}", nexit),
+            "CRITICAL: sentinel match: Kernel panic - not syncing: Fatal exception (errors:0, warnings:1, unknowns:0)"
+        );
+    }
+
+    // the summary line format is documented as stable: this test must not be changed, except to add new keys at the end
+    #[test]
+    fn summary() {
+        let mut summary = RunSummary {
+            state: NagiosError::CRITICAL,
+            criticals: 3,
+            warnings: 1,
+            files: 2,
+            duration: 0.0123,
+            errors: 1,
+        };
+        assert_eq!(
+            &summary.to_string(),
+            "CLF_SUMMARY version=1 state=CRITICAL criticals=3 warnings=1 files=2 duration=0.012 errors=1"
+        );
+
+        summary = RunSummary {
+            state: NagiosError::OK,
+            duration: 12.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            &summary.to_string(),
+            "CLF_SUMMARY version=1 state=OK criticals=0 warnings=0 files=0 duration=12.000 errors=0"
+        );
+    }
 }
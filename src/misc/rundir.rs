@@ -0,0 +1,127 @@
+//! The working directory of a run, where files given to callbacks like raw lines are written. Each run has its own
+//! directory `run-<uuid>` in the output directory, so concurrent runs never share a file. It's given to scripts as
+//! CLF_RUN_DIR and only created when a script is called. Run directories older than the output retention time are
+//! deleted at startup.
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+
+use log::{debug, info};
+
+use crate::misc::{
+    error::{AppError, AppResult},
+    util::run_uuid,
+};
+
+/// Prefix of the names of run directories.
+const RUN_DIR_PREFIX: &str = "run-";
+
+// the path of the directory of this run, and whether it's created
+static RUN_DIR: OnceLock<PathBuf> = OnceLock::new();
+static CREATED: OnceLock<PathBuf> = OnceLock::new();
+
+/// Sets the directory of this run in the output directory, and deletes the run directories older than `retention`
+/// seconds, unless it's 0.
+pub fn init(output_dir: &Path, retention: u64) -> AppResult<()> {
+    let _ = RUN_DIR.set(output_dir.join(format!("{}{}", RUN_DIR_PREFIX, run_uuid())));
+    if retention != 0 {
+        purge(output_dir, Duration::from_secs(retention))?;
+    }
+    Ok(())
+}
+
+/// The directory of this run, created with permissions for the current user only if it doesn't exist yet. Without
+/// `init()`, it's created in the temporary directory.
+pub fn create() -> AppResult<&'static Path> {
+    if let Some(dir) = CREATED.get() {
+        return Ok(dir);
+    }
+
+    let dir = RUN_DIR
+        .get_or_init(|| std::env::temp_dir().join(format!("{}{}", RUN_DIR_PREFIX, run_uuid())));
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(target_family = "unix")]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder
+        .create(dir)
+        .map_err(|e| context!(e, "unable to create run directory {:?}", dir))?;
+    info!("run directory {:?} created", dir);
+
+    Ok(CREATED.get_or_init(|| dir.clone()))
+}
+
+// deletes the run directories whose last modification is older than the retention time
+fn purge(output_dir: &Path, retention: Duration) -> AppResult<()> {
+    let oldest = SystemTime::now() - retention;
+
+    let entries = std::fs::read_dir(output_dir)
+        .map_err(|e| context!(e, "unable to read output directory {:?}", output_dir))?;
+    for entry in entries.flatten() {
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(RUN_DIR_PREFIX)
+            || !entry.file_type().is_ok_and(|t| t.is_dir())
+        {
+            continue;
+        }
+
+        let modified = entry.metadata().and_then(|m| m.modified());
+        if matches!(modified, Ok(modified) if modified < oldest) {
+            debug!("deleting old run directory {:?}", entry.path());
+            std::fs::remove_dir_all(entry.path())
+                .map_err(|e| context!(e, "unable to delete run directory {:?}", entry.path()))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn run_dir() {
+        let dir = std::env::temp_dir().join("clf_rundir_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+
+        // an old run directory is deleted, but not a recent one nor other files
+        let old = SystemTime::now() - Duration::from_secs(7200);
+        for name in ["run-old", "run-recent"] {
+            std::fs::create_dir(dir.join(name)).unwrap();
+            std::fs::write(dir.join(name).join("line_0.raw"), "ERROR").unwrap();
+        }
+        std::fs::write(dir.join("run-file"), "").unwrap();
+        File::open(dir.join("run-old"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        File::options()
+            .write(true)
+            .open(dir.join("run-file"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        purge(&dir, Duration::from_secs(3600)).unwrap();
+        assert!(!dir.join("run-old").exists());
+        assert!(dir.join("run-recent").exists());
+        assert!(dir.join("run-file").exists());
+
+        // the run directory is created once
+        let run_dir = create().unwrap();
+        assert!(run_dir.is_dir());
+        assert!(run_dir
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .ends_with(run_uuid()));
+        assert_eq!(create().unwrap(), run_dir);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
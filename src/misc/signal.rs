@@ -0,0 +1,138 @@
+//! Shutdown management. When clf receives SIGTERM, SIGINT or SIGHUP, the searches are stopped at the next line so the
+//! snapshot is still saved, and the scripts still running are terminated instead of being orphaned. In follow mode,
+//! SIGHUP asks for the configuration to be reloaded instead.
+use std::io;
+use std::process::{Child, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// set by the signal handlers
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+/// Installs the handler of shutdown signals. Nothing is done on Windows.
+pub fn install_handlers() {
+    #[cfg(target_family = "unix")]
+    for signal in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+        // safe because the handler only stores an atomic
+        unsafe {
+            libc::signal(
+                signal,
+                on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            );
+        }
+    }
+}
+
+#[cfg(target_family = "unix")]
+extern "C" fn on_signal(_signal: libc::c_int) {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+}
+
+/// Installs the handler of SIGHUP asking for a reload, instead of a shutdown. Nothing is done on Windows.
+pub fn install_reload_handler() {
+    #[cfg(target_family = "unix")]
+    // safe because the handler only stores an atomic
+    unsafe {
+        libc::signal(
+            libc::SIGHUP,
+            on_reload as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+#[cfg(target_family = "unix")]
+extern "C" fn on_reload(_signal: libc::c_int) {
+    RELOAD.store(true, Ordering::SeqCst);
+}
+
+/// true if a shutdown signal has been received.
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+/// Asks for a reload, like SIGHUP does on UNIX.
+#[cfg(target_family = "windows")]
+pub fn request_reload() {
+    RELOAD.store(true, Ordering::SeqCst);
+}
+
+/// true if a reload has been asked for since the last call to `take_reload()`.
+pub fn reload_requested() -> bool {
+    RELOAD.load(Ordering::SeqCst)
+}
+
+/// true if a reload has been asked for, which is then considered as done.
+pub fn take_reload() -> bool {
+    RELOAD.swap(false, Ordering::SeqCst)
+}
+
+/// Asks a child process to terminate with SIGTERM, and kills it if it's still running after the grace period.
+/// On Windows, the process is killed right away.
+pub fn terminate(child: &mut Child, grace: Duration) -> io::Result<ExitStatus> {
+    #[cfg(target_family = "unix")]
+    {
+        use wait_timeout::ChildExt;
+
+        // safe because the pid is the one of our own child, not yet waited for
+        if unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) } == 0 {
+            if let Some(status) = child.wait_timeout(grace)? {
+                return Ok(status);
+            }
+        }
+    }
+    #[cfg(target_family = "windows")]
+    let _ = grace;
+
+    child.kill()?;
+    child.wait()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use std::time::Instant;
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn terminate() {
+        // a process exits on SIGTERM
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        let start = Instant::now();
+        let status = super::terminate(&mut child, Duration::from_secs(5)).unwrap();
+        assert!(!status.success());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // a process ignoring SIGTERM is killed after the grace period
+        let mut child = Command::new("sh")
+            .args(["-c", "trap '' TERM; exec sleep 10"])
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        let start = Instant::now();
+        super::terminate(&mut child, Duration::from_millis(500)).unwrap();
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed >= Duration::from_millis(400) && elapsed < Duration::from_secs(5),
+            "{:?}",
+            elapsed
+        );
+
+        assert!(!shutdown_requested());
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn reload() {
+        install_reload_handler();
+        assert!(!reload_requested());
+
+        // safe because the process sends the signal to itself, and SIGHUP is handled
+        unsafe { libc::raise(libc::SIGHUP) };
+        assert!(reload_requested());
+        assert!(take_reload());
+        assert!(!take_reload());
+        assert!(!shutdown_requested());
+    }
+}
@@ -0,0 +1,146 @@
+//! Notifications to systemd when clf is started as a `Type=notify` service: systemd is told when clf is ready, the
+//! watchdog is pinged while logfiles are searched, and the status of the last run is reported before stopping. Nothing
+//! is sent if clf is not started by systemd.
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::error;
+
+// last time the watchdog was pinged
+static LAST_PING: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// A state sent to systemd.
+#[derive(Debug, PartialEq)]
+pub enum State {
+    /// the configuration is loaded and the searches start
+    Ready,
+
+    /// clf is still alive
+    Watchdog,
+
+    /// clf is stopping
+    Stopping,
+
+    /// a free-form status displayed by `systemctl status`
+    Status(String),
+}
+
+impl State {
+    // the datagram content, as defined by sd_notify(3)
+    fn message(&self) -> String {
+        match self {
+            State::Ready => "READY=1".to_string(),
+            State::Watchdog => "WATCHDOG=1".to_string(),
+            State::Stopping => "STOPPING=1".to_string(),
+            State::Status(status) => format!("STATUS={}", status.replace('\n', " ")),
+        }
+    }
+}
+
+/// Sends a state to systemd. Returns `false` if clf is not started by systemd.
+pub fn notify(state: State) -> io::Result<bool> {
+    match std::env::var("NOTIFY_SOCKET") {
+        Ok(socket) => send(&socket, &state).map(|_| true),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Pings the watchdog if the service has one, at most twice per watchdog interval as advised by systemd.
+pub fn watchdog() {
+    let interval = match watchdog_interval(
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+    ) {
+        Some(interval) => interval,
+        None => return,
+    };
+
+    let mut last_ping = LAST_PING.lock().unwrap_or_else(|e| e.into_inner());
+    if !matches!(*last_ping, Some(last) if last.elapsed() < interval / 2) {
+        if let Err(e) = notify(State::Watchdog) {
+            error!("unable to ping systemd watchdog: {}", e);
+        }
+        *last_ping = Some(Instant::now());
+    }
+}
+
+// the watchdog interval set by systemd, if it's meant for this process
+fn watchdog_interval(usec: Option<&str>, pid: Option<&str>) -> Option<Duration> {
+    if let Some(pid) = pid {
+        if pid.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+
+    match usec?.parse::<u64>().ok()? {
+        0 => None,
+        usec => Some(Duration::from_micros(usec)),
+    }
+}
+
+// a socket name starting with '@' is in the abstract namespace
+fn send(socket: &str, state: &State) -> io::Result<()> {
+    let datagram = UnixDatagram::unbound()?;
+    let message = state.message();
+
+    match socket.strip_prefix('@') {
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            datagram.send_to_addr(message.as_bytes(), &addr)?;
+        }
+        None => {
+            datagram.send_to(message.as_bytes(), socket)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn send() {
+        let path = std::env::temp_dir().join("clf_systemd_test.sock");
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        let mut buffer = [0; 64];
+        for (state, message) in [
+            (State::Ready, "READY=1"),
+            (State::Watchdog, "WATCHDOG=1"),
+            (
+                State::Status("OK:\n2 logfiles".to_string()),
+                "STATUS=OK: 2 logfiles",
+            ),
+        ] {
+            super::send(path.to_str().unwrap(), &state).unwrap();
+            let size = receiver.recv(&mut buffer).unwrap();
+            assert_eq!(&buffer[..size], message.as_bytes());
+        }
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn watchdog_interval() {
+        let pid = std::process::id().to_string();
+        assert_eq!(
+            super::watchdog_interval(Some("30000000"), None),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            super::watchdog_interval(Some("30000000"), Some(&pid)),
+            Some(Duration::from_secs(30))
+        );
+
+        // no watchdog, or the watchdog of another process
+        assert!(super::watchdog_interval(None, None).is_none());
+        assert!(super::watchdog_interval(Some("0"), None).is_none());
+        assert!(super::watchdog_interval(Some("30000000"), Some("1")).is_none());
+    }
+}
@@ -1,3 +1,4 @@
+use std::sync::OnceLock;
 use std::time::{Duration, SystemTime};
 
 use crate::context;
@@ -8,24 +9,101 @@ use crate::misc::error::{AppError, AppResult};
 /// A default value for the retention of data in the snapshot file.
 pub const DEFAULT_RETENTION: u64 = 86000 * 7;
 
+/// Default number of runs kept in the history of the snapshot file.
+pub const DEFAULT_SNAPSHOT_HISTORY: usize = 20;
+
+/// Default name template of the output files where matched lines are kept, one for each tag.
+pub const DEFAULT_OUTPUT_FILE: &str = "clf_{logfile}_{tag}.out";
+
+/// Default name template of the protocol files where matched lines are written.
+pub const DEFAULT_PROTOCOL_FILE: &str = "clf_{date}_{time}_{pid}.protocol";
+
 /// Default capacity for all `Vec` or `HashMap` pre-allocations
 pub const DEFAULT_CONTAINER_CAPACITY: usize = 30;
 
 /// Default capacity for all strings pre-allocations
 pub const DEFAULT_STRING_CAPACITY: usize = 1024;
 
+/// Maximum capacity of the buffer lines are read into, when it's sized after the longest lines of the logfile
+pub const MAX_STRING_CAPACITY: usize = 64 * 1024;
+
 /// We define here the maximum size for the logger file (in Mb).
 pub const MAX_LOGGER_SIZE: u64 = 50;
 
 /// Default hash buffer size
 pub const DEFAULT_HASH_BUFFER_SIZE: usize = 4096;
 
+/// The UTF-8 byte order mark some tools write at the beginning of files
+pub const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
 // default time for waiting to spawned scripts
 pub const DEFAULT_SCRIPT_TIMEOUT: u64 = 10;
 
 // default write socket timeout
 pub const DEFAULT_WRITE_TIMEOUT: u64 = 5;
 
+// default connect timeout for each address of a TCP callback
+pub const DEFAULT_CONNECT_TIMEOUT: u64 = 3;
+
+// default number of times an HTTP callback request is sent again, or a socket connection tried again, after a failure
+pub const DEFAULT_CALLBACK_RETRIES: u32 = 2;
+
+// default delay in milliseconds before the first retry of a callback, doubled at each retry
+pub const DEFAULT_RETRY_DELAY: u64 = 500;
+
+// default maximum size in bytes of a batch of socket payloads
+pub const DEFAULT_BATCH_MAX_SIZE: usize = 32768;
+
+// default time in milliseconds after which a batch of socket payloads is sent
+pub const DEFAULT_BATCH_MAX_DELAY: u64 = 1000;
+
+// time in seconds given to a script to exit after SIGTERM, before being killed
+pub const DEFAULT_KILL_GRACE_PERIOD: u64 = 5;
+
+// default duration in seconds of a claim on a logfile
+pub const DEFAULT_CLAIM_LEASE: u64 = 600;
+
+// default bounds of the window of lines preceding a match
+pub const DEFAULT_CONTEXT_MAX_LINES: usize = 100;
+pub const DEFAULT_CONTEXT_MAX_BYTES: usize = 64 * 1024;
+
+// default ratio of snapshot offsets beyond the logfile sizes from which they're reported
+pub const DEFAULT_OFFSET_CHECK_RATIO: f64 = 0.5;
+
+// default ratio of lines matched by 2 tags, and minimum number of such lines, from which tags overlaps are reported
+pub const DEFAULT_OVERLAP_CHECK_RATIO: f64 = 0.5;
+pub const DEFAULT_OVERLAP_CHECK_MIN_LINES: u64 = 10;
+
+// default time in seconds between 2 searches of all logfiles in follow mode, and between 2 saves of the snapshots
+pub const DEFAULT_FOLLOW_INTERVAL: u64 = 5;
+pub const DEFAULT_FOLLOW_SAVE_INTERVAL: u64 = 60;
+
+// default number of logfiles searched at the same time
+pub const DEFAULT_MAX_THREADS: usize = 1;
+
+// default number of callback calls kept for the next run, for each logfile and tag
+pub const DEFAULT_MAX_PENDING_CALLBACKS: usize = 10_000;
+
+// default size of the slices of a logfile searched in turn with the other logfiles
+pub const DEFAULT_QUANTUM_LINES: u64 = 10_000;
+pub const DEFAULT_QUANTUM_BYTES: u64 = 1024 * 1024;
+
+// default number of lines and seconds between 2 checkpoints of the offsets reached while searching a logfile
+pub const DEFAULT_SNAPSHOT_CHECKPOINT_LINES: u64 = 100_000;
+pub const DEFAULT_SNAPSHOT_CHECKPOINT_SECONDS: u64 = 30;
+
+// default maximum number of lines of a multiline record
+pub const DEFAULT_MULTILINE_MAX_LINES: usize = 500;
+
+// number of lines read between 2 checks of the systemd watchdog
+pub const WATCHDOG_LINES: u64 = 10_000;
+
+// default number of NUL bytes skipped in a logfile during a run from which it's reported
+pub const DEFAULT_NUL_CHECK_THRESHOLD: u64 = 1024 * 1024;
+
+// number of bytes before the end of a shrunk logfile where its last line read is looked for
+pub const DEFAULT_SHRINK_WINDOW: u64 = 64 * 1024;
+
 // to save some string allocation, we can define a list of capture groups variables upfront
 pub const CAPTURE_GROUPS: &'static [&'static str] = &[
     "CLF_CG_0",
@@ -74,3 +152,34 @@ pub fn from_epoch_secs() -> AppResult<u64> {
     let from_epoch = from_epoch()?;
     Ok(from_epoch.as_secs())
 }
+
+/// The host name, or `localhost` if it can't be found.
+pub fn hostname() -> String {
+    whoami::fallible::hostname().unwrap_or_else(|_| "localhost".to_string())
+}
+
+/// The time to wait before retrying after `attempt` retries: `delay` milliseconds, doubled at each retry.
+pub fn backoff(delay: u64, attempt: u32) -> Duration {
+    Duration::from_millis(delay.saturating_mul(1 << attempt.min(16)))
+}
+
+/// A random UUID identifying the current run, like `0b6a3f4e-2c1d-4f5e-9a8b-7c6d5e4f3a2b`.
+pub fn run_uuid() -> &'static str {
+    static RUN_UUID: OnceLock<String> = OnceLock::new();
+    RUN_UUID.get_or_init(|| {
+        // random version 4, variant 1
+        let mut bytes: [u8; 16] = rand::random();
+        bytes[6] = (bytes[6] & 0x0F) | 0x40;
+        bytes[8] = (bytes[8] & 0x3F) | 0x80;
+
+        let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        format!(
+            "{}-{}-{}-{}-{}",
+            &hex[..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..]
+        )
+    })
+}
@@ -0,0 +1,669 @@
+//! Searches of logfiles, shared by the *clf* binary and the library: a logfile is searched from the offsets of its
+//! snapshot, along with the archives rotated since the last run. Searches could be stopped at the end of a slice, to
+//! be resumed in turn with the other searches.
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Instant, UNIX_EPOCH};
+
+use log::{debug, info, warn};
+
+use crate::configuration::{callback::ChildData, config::Config, global::Fairness, search::Search};
+use crate::logfile::{
+    logfile::LogFile,
+    logfileerror::LogFileAccessErrorList,
+    lookup::{BypassReader, FullReader, ReaderCallType},
+    output::RunOutputs,
+    snapshot::Snapshot,
+};
+use crate::misc::{
+    error::{AppCustomErrorKind, AppError, InternalError},
+    extension::{ReadFs, Signature},
+};
+
+/// How logfiles are searched, given by the command line of the binary.
+#[derive(Debug)]
+pub struct SearchOptions {
+    /// whether lines are read, or the logfile only opened
+    pub reader_type: ReaderCallType,
+
+    /// callbacks are not called and no file is written
+    pub dry_run: bool,
+
+    /// ratio of lines traced to debug the configuration
+    pub trace_lines: f64,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            reader_type: ReaderCallType::FullReaderCall,
+            dry_run: false,
+            trace_lines: 0.0,
+        }
+    }
+}
+
+/// Sends the payloads left in the queues of callbacks by previous runs, before any logfile is searched, so they're
+/// delivered even if no new line matches. A queue shared by several tags is only drained once.
+pub fn drain_queues(config: &Config) {
+    let mut drained = HashSet::new();
+
+    for tag in config.searches.iter().flat_map(|search| &search.tags) {
+        let callback = match &tag.callback {
+            Some(callback) => callback,
+            None => continue,
+        };
+        match &callback.queue {
+            Some(queue) if drained.insert(queue.path.clone()) => (),
+            _ => continue,
+        }
+
+        match callback.drain_queue(&config.global.global_vars) {
+            Ok(0) => (),
+            Ok(sent) => info!("{} queued payloads sent for tag {}", sent, tag.name),
+            Err(e) => warn!("{}, queued payloads are kept until next run", e),
+        }
+    }
+}
+
+/// Searches the logfile of a search, along with its archive if it has been rotated since the last run. Returns whether
+/// the search stopped at the end of a slice, to be resumed in turn with the other searches.
+pub fn search_logfile(
+    config: &Config,
+    options: &SearchOptions,
+    search: &Search,
+    snapshot: &mut Snapshot,
+    access_errors: &mut LogFileAccessErrorList,
+    children_list: &mut Vec<ChildData>,
+    outputs: &RunOutputs,
+) -> bool {
+    let reader_type = &options.reader_type;
+    let progress = snapshot.progress();
+
+    // create a LogFile struct or get it from snapshot
+    let logfile_from_snapshot = {
+        let temp = snapshot.logfile_mut(search.logfile.path(), &search.logfile);
+        if let Err(e) = temp {
+            error!(
+                "error fetching logfile {} from snapshot: {}",
+                search.logfile.path().display(),
+                e,
+            );
+
+            // this is a error for this logfile which boils down to a Nagios unknown error
+            access_errors.set_error(search.logfile.path(), e, search.logfilemissing());
+            return false;
+        }
+        temp.unwrap()
+    };
+
+    // the search is abandoned if it takes more than the search timeout, if any
+    logfile_from_snapshot.deadline = search
+        .timeout(config.global.search_timeout)
+        .map(|timeout| Instant::now() + timeout);
+
+    // labels are given to callbacks and match records
+    logfile_from_snapshot.labels = search.labels.clone();
+
+    // a sample of lines could be traced to debug the configuration
+    logfile_from_snapshot.trace_lines = options.trace_lines;
+
+    // matched lines are kept in the output files with the keepoutput option, and in the protocol files with the
+    // protocol option, except in a dry run where callbacks are not called either
+    logfile_from_snapshot.output = if options.dry_run {
+        None
+    } else {
+        Some(Arc::clone(&outputs.output))
+    };
+    logfile_from_snapshot.protocol = if options.dry_run {
+        None
+    } else {
+        Some(Arc::clone(&outputs.protocol))
+    };
+    logfile_from_snapshot.dry_run = options.dry_run;
+
+    // in case the configuration file changed since the last run and for a logfile, the tags configuration
+    // changed, we need to adjust. There're some cases where there could be more tags in the snapshot than
+    // in the configuration file. So we need to keep in the snapshot only those in the config file, and those of
+    // another search of this logfile stopped at a slice.
+    let tag_names = search.tag_names();
+    let slices = &logfile_from_snapshot.slices;
+    logfile_from_snapshot
+        .run_data
+        .retain(|k, _| tag_names.contains(&k.as_str()) || slices.contains_key(k));
+
+    // a logfile rewritten without its first lines is read after the last line found again, instead of being reset
+    match logfile_from_snapshot.relocate_after_shrink() {
+        Ok(true) => info!(
+            "logfile {} has shrunk, reading after the last lines found again",
+            logfile_from_snapshot.id.canon_path.display()
+        ),
+        Ok(false) => (),
+        Err(e) => error!(
+            "error on looking for the last lines of logfile {}: {}",
+            logfile_from_snapshot.id.canon_path.display(),
+            e
+        ),
+    }
+
+    // check if the rotation occured. This means the logfile signature has changed
+    trace!(
+        "checking if logfile {:?} has changed",
+        logfile_from_snapshot.id.canon_path.display()
+    );
+    // a logfile too short to be hashed is read on from the offsets of the snapshot
+    let logfile_is_archived = match logfile_from_snapshot.hash_been_rotated() {
+        Ok(rotated) => rotated,
+        Err(AppError {
+            error_kind: InternalError::Custom(AppCustomErrorKind::FileSizeIsLessThanHashWindow),
+            ..
+        }) => false,
+        Err(e) => {
+            error!(
+                "error on fetching metadata on logfile {}: {}",
+                logfile_from_snapshot.id.canon_path.display(),
+                e
+            );
+            return false;
+        }
+    };
+
+    if logfile_is_archived {
+        info!(
+            "logfile {} has changed, probably archived and rotated",
+            logfile_from_snapshot.id.canon_path.display()
+        );
+
+        // the archive read at the last run is found again, along with those rotated since and never read
+        let last_run_secs = logfile_from_snapshot
+            .run_data
+            .values()
+            .map(|run_data| run_data.last_run_secs)
+            .max()
+            .unwrap_or(0);
+        let (archive_path, missed) =
+            archives_to_read(search, &logfile_from_snapshot.id.signature, last_run_secs);
+        trace!(
+            "archived logfile = {:?}, missed = {:?}",
+            &archive_path,
+            &missed
+        );
+
+        // clone search and assign archive logfile instead of original logfile
+        let mut archived_logfile = logfile_from_snapshot.clone();
+        match &archive_path {
+            Some(archive_path) => search_archive(
+                config,
+                options,
+                search,
+                &mut archived_logfile,
+                archive_path,
+                children_list,
+            ),
+            None => warn!(
+                "archive of logfile {} read at the last run not found, reading archives modified since from their beginning",
+                search.logfile.path().display()
+            ),
+        }
+
+        // archives never read are read from their beginning, the oldest first
+        for path in missed.iter().rev() {
+            info!("reading missed archive {}", path.display());
+            let mut missed_logfile = archived_logfile.clone();
+            for tag in &search.tags {
+                if missed_logfile.run_data.contains_key(&tag.name) {
+                    missed_logfile.reset_tag_offsets(&tag.name);
+                }
+            }
+            search_archive(
+                config,
+                options,
+                search,
+                &mut missed_logfile,
+                path,
+                children_list,
+            );
+            archived_logfile = missed_logfile;
+        }
+
+        // reset run_data into original search because this is a new file
+        for tag in &search.tags {
+            if !tag.options.savethresholds {
+                logfile_from_snapshot.reset_tag(&tag.name);
+            } else {
+                logfile_from_snapshot.reset_tag_offsets(&tag.name);
+                logfile_from_snapshot.copy_counters(&archived_logfile, &tag.name);
+            }
+        }
+    }
+
+    // the archive is read at once, but the logfile could be searched a slice at a time
+    logfile_from_snapshot.quantum = match config.global.fairness {
+        Fairness::interleaved => Some(config.global.quantum.clone()),
+        Fairness::sequential => None,
+    };
+
+    // offsets are saved along the way with the snapshot_checkpoint global option, but not those of archives. Nothing is
+    // saved by a dry run, nor when lines are not read, or the next run would resume from there
+    logfile_from_snapshot.progress = progress.filter(|_| {
+        config.global.snapshot_checkpoint.is_some()
+            && !options.dry_run
+            && reader_type == &ReaderCallType::FullReaderCall
+    });
+
+    // call adequate reader according to command line
+    if reader_type == &ReaderCallType::BypassReaderCall {
+        logfile_from_snapshot.lookup_tags::<BypassReader>(
+            &config.global,
+            &search.tags,
+            children_list,
+        );
+    } else if reader_type == &ReaderCallType::FullReaderCall {
+        logfile_from_snapshot.lookup_tags::<FullReader>(
+            &config.global,
+            &search.tags,
+            children_list,
+        );
+    }
+
+    end_of_search(search, logfile_from_snapshot, access_errors)
+}
+
+// the archive of a rotated logfile to read on from the offsets of the last run, if found, and the archives to read from
+// their beginning, the most recent first. Without discovery, the archive is found by its name and no archive is missed.
+// With discovery, the archive read at the last run is the one with the same inode, or else a copy of it, and those
+// rotated after it are missed. If none is found, e.g. when archives are compressed right away, the archives modified
+// since the last run are all missed: reading one of them from the offsets of another file would skip or split lines
+fn archives_to_read(
+    search: &Search,
+    signature: &Signature,
+    last_run_secs: u64,
+) -> (Option<PathBuf>, Vec<PathBuf>) {
+    let archive = match &search.logfile.archive {
+        Some(archive) if archive.discover => archive,
+        _ => return (Some(search.logfile.archive_path()), Vec::new()),
+    };
+
+    let mut archives = match archive.discover(search.logfile.path()) {
+        Ok(archives) => archives,
+        Err(e) => {
+            error!(
+                "error looking for archives of logfile {}: {}",
+                search.logfile.path().display(),
+                e
+            );
+            Vec::new()
+        }
+    };
+    if archives.is_empty() {
+        return (Some(search.logfile.archive_path()), Vec::new());
+    }
+
+    let previous = archives
+        .iter()
+        .position(|path| {
+            path.signature(search.logfile.hash_spec())
+                .is_ok_and(|s| s.dev == signature.dev && s.inode == signature.inode)
+        })
+        .or_else(|| {
+            archives
+                .iter()
+                .position(|path| is_copy(path, signature, search.logfile.hash_window))
+        });
+    match previous {
+        Some(i) => {
+            let missed = archives.drain(..i).collect();
+            (Some(archives.remove(0)), missed)
+        }
+        None => {
+            // better read an archive twice than miss it, if its modification time can't be read
+            archives.retain(|path| {
+                std::fs::metadata(path)
+                    .and_then(|m| m.modified())
+                    .map_or(true, |modified| {
+                        modified
+                            .duration_since(UNIX_EPOCH)
+                            .map_or(0, |d| d.as_secs())
+                            >= last_run_secs
+                    })
+            });
+            (None, archives)
+        }
+    }
+}
+
+// whether an archive is a copy of the logfile read at the last run, e.g. when it was copied to another archive while
+// rotated twice, or with copytruncate: it's not shorter, and the bytes hashed in the signature are the same. A logfile
+// shorter than the hash window at the last run has no hash to compare with, so no copy is found
+fn is_copy(path: &Path, signature: &Signature, hash_window: usize) -> bool {
+    let head_hash = match signature.hash {
+        Some(hash) => hash,
+        None => return false,
+    };
+    if std::fs::metadata(path).map_or(true, |m| m.len() < signature.size) {
+        return false;
+    }
+
+    let same_hash = |offset, length, hash| {
+        Signature::hash(path, signature.algorithm, offset, length).is_ok_and(|h| h == hash)
+    };
+    same_hash(0, hash_window, head_hash)
+        && signature.tail_hash.is_none_or(|tail_hash| {
+            same_hash(
+                signature.size - signature.tail_window,
+                signature.tail_window as usize,
+                tail_hash,
+            )
+        })
+}
+
+// searches an archive of a logfile with the offsets of the logfile
+fn search_archive(
+    config: &Config,
+    options: &SearchOptions,
+    search: &Search,
+    archived_logfile: &mut LogFile,
+    archive_path: &Path,
+    children_list: &mut Vec<ChildData>,
+) {
+    archived_logfile.progress = None;
+    if let Err(e) = archived_logfile
+        .id
+        .update(archive_path, archived_logfile.definition.hash_spec())
+    {
+        error!(
+            "error on updating core data on logfile {}: {}",
+            archive_path.display(),
+            e
+        )
+    }
+
+    // call adequate reader according to command line
+    if options.reader_type == ReaderCallType::BypassReaderCall {
+        archived_logfile.lookup_tags::<BypassReader>(&config.global, &search.tags, children_list);
+    } else if options.reader_type == ReaderCallType::FullReaderCall {
+        archived_logfile.lookup_tags::<FullReader>(&config.global, &search.tags, children_list);
+    }
+}
+
+/// Searches the next slice of a search which stopped at the end of the previous one. Returns whether it's still not
+/// complete.
+pub fn resume_logfile(
+    config: &Config,
+    options: &SearchOptions,
+    search: &Search,
+    snapshot: &mut Snapshot,
+    access_errors: &mut LogFileAccessErrorList,
+    children_list: &mut Vec<ChildData>,
+) -> bool {
+    let logfile = match snapshot.logfile_mut(search.logfile.path(), &search.logfile) {
+        Ok(logfile) => logfile,
+        Err(e) => {
+            error!(
+                "error fetching logfile {} from snapshot: {}",
+                search.logfile.path().display(),
+                e,
+            );
+            return false;
+        }
+    };
+
+    debug!("resuming search into logfile: {:?}", search.logfile.path());
+    if options.reader_type == ReaderCallType::FullReaderCall {
+        logfile.resume_tags::<FullReader>(&config.global, &search.tags, children_list);
+    }
+
+    end_of_search(search, logfile, access_errors)
+}
+
+// once all tags of a search are complete, checks what was found along the way. Returns whether the search is not
+// complete yet
+fn end_of_search(
+    search: &Search,
+    logfile: &LogFile,
+    access_errors: &mut LogFileAccessErrorList,
+) -> bool {
+    if logfile.is_sliced(&search.tags) {
+        return true;
+    }
+    // NUL bytes are skipped, but a lot of them is reported if requested
+    if let Some(nul_check) = &search.logfile.nul_check {
+        let pid = std::process::id();
+        let nul_bytes = logfile
+            .run_data
+            .values()
+            .filter(|run_data| run_data.pid == pid)
+            .map(|run_data| run_data.nul_bytes)
+            .max()
+            .unwrap_or(0);
+
+        if nul_bytes >= nul_check.threshold {
+            warn!(
+                "{} NUL bytes skipped in logfile {:?}",
+                nul_bytes,
+                search.logfile.path()
+            );
+            let e = AppError::new_custom(
+                AppCustomErrorKind::NulBytes,
+                &format!("{} NUL bytes skipped", nul_bytes),
+            );
+            access_errors.set_error(search.logfile.path(), e, &nul_check.status);
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testkit::{FakeLogFile, Scan};
+
+    #[test]
+    fn missing_logfile_not_stopping_run() {
+        let present = FakeLogFile::new("missing_present.log").line("ERROR disk full");
+        let missing = std::env::temp_dir().join(format!("clf_missing_{}.log", std::process::id()));
+
+        let yaml = format!(
+            r#"
+            searches:
+                - logfile:
+                    path: {}
+                    logfilemissing: warning
+                  tags:
+                    - name: missing
+                      patterns:
+                        critical: {{ regexes: ['ERROR'] }}
+                - logfile:
+                    path: {}
+                  tags:
+                    - name: present
+                      patterns:
+                        critical: {{ regexes: ['ERROR'] }}
+            "#,
+            missing.display(),
+            present.path().display()
+        );
+        let mut scan = Scan::new(&yaml).unwrap();
+
+        // the missing logfile is given back, and the other one is searched anyway
+        scan.run().unwrap().assert_counters("present", 1, 0, 0);
+        assert_eq!(scan.access_errors.len(), 1);
+        let error = scan.access_errors.get(&missing).unwrap();
+        assert_eq!(
+            error.nagios_error,
+            crate::misc::nagios::NagiosError::WARNING
+        );
+    }
+
+    #[test]
+    fn digests_kept_in_snapshot() {
+        let first = FakeLogFile::new("digests_first.log").line("ERROR disk full");
+        let second = FakeLogFile::new("digests_second.log")
+            .line("INFO started")
+            .line("ERROR no space left");
+
+        // nothing listens on this port: the digest can't be sent
+        let yaml = format!(
+            r#"
+            searches:
+                - logfile:
+                    path: {}
+                  tags:
+                    - name: disk
+                      options: "runcallback"
+                      callback:
+                        email:
+                          smtp: 127.0.0.1:1
+                          to: [ops@example.com]
+                        timeout: 1
+                      patterns:
+                        critical: {{ regexes: ['ERROR'] }}
+                - logfile:
+                    path: {}
+                  tags:
+                    - name: space
+                      options: "runcallback"
+                      callback:
+                        email:
+                          smtp: 127.0.0.1:1
+                          to: [ops@example.com]
+                        timeout: 1
+                      patterns:
+                        critical: {{ regexes: ['ERROR'] }}
+            "#,
+            first.path().display(),
+            second.path().display()
+        );
+        let mut scan = Scan::new(&yaml).unwrap();
+        scan.run().unwrap();
+
+        // the matches of both logfiles are in a single digest, kept in the snapshot for the next run
+        let digests = serde_json::to_value(scan.snapshot.take_digests()).unwrap();
+        assert_eq!(digests.as_array().unwrap().len(), 1);
+        let lines = digests[0]["lines"].as_array().unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0]
+            .as_str()
+            .unwrap()
+            .ends_with(":disk:critical:1:ERROR disk full"));
+        assert!(lines[1]
+            .as_str()
+            .unwrap()
+            .ends_with(":space:critical:2:ERROR no space left"));
+    }
+
+    #[test]
+    #[cfg(all(feature = "sockets", target_family = "unix"))]
+    fn queue_drained_without_matches() {
+        use crate::configuration::{protocol, vars::RuntimeVars};
+
+        let logfile = FakeLogFile::new("queue_drained.log").line("INFO started");
+        let addr =
+            std::env::temp_dir().join(format!("clf_queue_drained_{}.sock", std::process::id()));
+        let queue =
+            std::env::temp_dir().join(format!("clf_queue_drained_{}.ndjson", std::process::id()));
+        let _ = std::fs::remove_file(&addr);
+        let _ = std::fs::remove_file(&queue);
+
+        let yaml = format!(
+            r#"
+            searches:
+                - logfile:
+                    path: {}
+                  tags:
+                    - name: disk
+                      callback:
+                        domain: {}
+                        queue:
+                          path: {}
+                      patterns:
+                        critical: {{ regexes: ['ERROR'] }}
+            "#,
+            logfile.path().display(),
+            addr.display(),
+            queue.display()
+        );
+        let mut scan = Scan::new(&yaml).unwrap();
+
+        // a payload left in the queue by a previous run
+        let callback = scan.config.searches[0].tags[0].callback.as_ref().unwrap();
+        let mut vars = RuntimeVars::default();
+        vars.insert_runtime_var("CLF_LINE_NUMBER", 1_u64);
+        callback.queue.as_ref().unwrap().append(&vars).unwrap();
+
+        let listener = std::os::unix::net::UnixListener::bind(&addr).unwrap();
+        let child = std::thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut frames = Vec::new();
+            while let Some(frame) = protocol::read_frame(&mut socket).unwrap() {
+                frames.push(serde_json::from_slice::<serde_json::Value>(&frame).unwrap());
+            }
+            frames
+        });
+
+        // no line matches, the queued payload is sent anyway
+        scan.run().unwrap().assert_counters("disk", 0, 0, 0);
+        let frames = child.join().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert!(!frames[0]["vars"]["CLF_LINE_NUMBER"].is_null());
+        assert_eq!(frames[1]["end_of_run"]["sent"], 1);
+
+        let callback = scan.config.searches[0].tags[0].callback.as_ref().unwrap();
+        assert!(callback
+            .queue
+            .as_ref()
+            .unwrap()
+            .entries()
+            .unwrap()
+            .is_empty());
+        std::fs::remove_file(&addr).unwrap();
+        std::fs::remove_file(&queue).unwrap();
+    }
+
+    #[test]
+    fn archives_rotated_twice() {
+        let logfile = FakeLogFile::new("rotated_twice.log").line("ERROR disk full");
+
+        // the logfile is hashed as a whole, so a copy of it is found from its signature
+        let yaml = format!(
+            r#"
+            searches:
+                - logfile:
+                    path: {}
+                    hash_window: 16
+                    archive:
+                      discover: true
+                  tags:
+                    - name: disk
+                      options: "savethresholds"
+                      patterns:
+                        critical: {{ regexes: ['ERROR'] }}
+            "#,
+            logfile.path().display()
+        );
+        let mut scan = Scan::new(&yaml).unwrap();
+        scan.run().unwrap().assert_counters("disk", 1, 0, 0);
+
+        // rotated twice between runs, the archive read at the last run being copied to another inode. Its inode is
+        // kept, not to be given to the new logfile
+        let archive = |i| std::path::PathBuf::from(format!("{}.{}", logfile.path().display(), i));
+        logfile.append(&["ERROR disk full again"]);
+        let kept = std::path::PathBuf::from(format!("{}.kept", logfile.path().display()));
+        std::fs::hard_link(logfile.path(), &kept).unwrap();
+        std::fs::rename(logfile.path(), archive(1)).unwrap();
+        std::fs::write(logfile.path(), "ERROR disk still full\n").unwrap();
+        std::fs::copy(archive(1), archive(2)).unwrap();
+        std::fs::rename(logfile.path(), archive(1)).unwrap();
+        std::fs::write(logfile.path(), "ERROR disk full forever\n").unwrap();
+
+        // the copy of the archive is read on from the last run, then the other archive from its beginning, and the
+        // logfile
+        scan.run().unwrap().assert_counters("disk", 4, 0, 0);
+        assert_eq!(scan.run_data(logfile.path(), "disk").unwrap().last_line, 1);
+
+        for path in [archive(1), archive(2), kept] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Helpers to run searches from Rust tests, without spawning the *clf* binary: fake logfiles are written to the
+//! temporary directory, a configuration is read from a YAML string and searched with an in-memory snapshot, and the
+//! resulting run data can be checked. This module is only built for tests, or with the `testkit` feature.
+//!
+//! ```ignore
+//! let logfile = FakeLogFile::new("app.log").line("ERROR disk full").line("INFO started");
+//! let mut scan = Scan::new(&format!(
+//!     "searches:\n  - logfile:\n      path: {}\n    tags:\n      - name: error\n        patterns:\n          critical: {{ regexes: ['ERROR'] }}",
+//!     logfile.path().display()
+//! ))?;
+//! scan.run();
+//! scan.assert_counters("error", 1, 0, 0);
+//! ```
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::configuration::{config::Config, pattern::PatternCounters};
+use crate::logfile::{logfileerror::LogFileAccessErrorList, rundata::RunData, snapshot::Snapshot};
+use crate::misc::error::{AppError, AppResult};
+use crate::run_search;
+
+/// A logfile written to the temporary directory, deleted when dropped.
+#[derive(Debug)]
+pub struct FakeLogFile {
+    path: PathBuf,
+}
+
+impl FakeLogFile {
+    /// Creates an empty logfile. The process id is part of its name, so tests running at the same time don't share it.
+    pub fn new(name: &str) -> FakeLogFile {
+        let path =
+            std::env::temp_dir().join(format!("clf_testkit_{}_{}", std::process::id(), name));
+        std::fs::write(&path, "")
+            .unwrap_or_else(|e| panic!("unable to create fake logfile {:?}: {}", path, e));
+        FakeLogFile { path }
+    }
+
+    /// Appends a line to the logfile.
+    pub fn line(self, line: &str) -> FakeLogFile {
+        self.append(&[line]);
+        self
+    }
+
+    /// Appends lines to the logfile.
+    pub fn lines(self, lines: &[&str]) -> FakeLogFile {
+        self.append(lines);
+        self
+    }
+
+    /// Appends lines to the logfile, e.g. between two runs of the same scan.
+    pub fn append(&self, lines: &[&str]) {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .unwrap_or_else(|e| panic!("unable to open fake logfile {:?}: {}", self.path, e));
+        for line in lines {
+            writeln!(file, "{}", line)
+                .unwrap_or_else(|e| panic!("unable to write fake logfile {:?}: {}", self.path, e));
+        }
+    }
+
+    /// The path of the logfile, to be used in the configuration.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for FakeLogFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// The searches of a configuration, along with their snapshot kept in memory between runs.
+#[derive(Debug)]
+pub struct Scan {
+    /// the configuration read from YAML
+    pub config: Config,
+
+    /// run data of all logfiles searched so far
+    pub snapshot: Snapshot,
+
+    /// logfiles which couldn't be read during the last run
+    pub access_errors: LogFileAccessErrorList,
+}
+
+impl Scan {
+    /// Reads the configuration from a YAML string. Default options are merged into tag options, like for a
+    /// configuration file.
+    pub fn new(yaml: &str) -> AppResult<Scan> {
+        let mut config = Config::from_str(yaml)
+            .map_err(|e| AppError::from_error(e, "error in reading configuration"))?;
+        config.merge_default_options()?;
+
+        Ok(Scan {
+            config,
+            snapshot: Snapshot::default(),
+            access_errors: LogFileAccessErrorList::default(),
+        })
+    }
+
+    /// Searches all logfiles, from the offsets of the previous run if any. Callbacks, if any, are called but their
+    /// processes are not waited for.
+    pub fn run(&mut self) -> AppResult<&mut Scan> {
+        self.access_errors = run_search(&self.config, &mut self.snapshot).access_errors;
+        Ok(self)
+    }
+
+    /// The run data of a tag for a logfile, if it was searched during the last run.
+    pub fn run_data(&self, path: &Path, tag_name: &str) -> Option<&RunData> {
+        self.snapshot
+            .current_run_data()
+            .find(|(p, t, _)| p.as_path() == path && t.as_str() == tag_name)
+            .map(|(_, _, run_data)| run_data)
+    }
+
+    /// The counters of a tag, summed over all logfiles it was searched in.
+    pub fn counters(&self, tag_name: &str) -> PatternCounters {
+        self.snapshot
+            .current_run_data()
+            .filter(|(_, t, _)| t.as_str() == tag_name)
+            .map(|(_, _, run_data)| &run_data.counters)
+            .sum()
+    }
+
+    /// Panics unless the critical, warning and ok counters of a tag are those expected.
+    pub fn assert_counters(&self, tag_name: &str, critical: u64, warning: u64, ok: u64) {
+        let counters = self.counters(tag_name);
+        assert_eq!(
+            (
+                counters.critical_count,
+                counters.warning_count,
+                counters.ok_count
+            ),
+            (critical, warning, ok),
+            "unexpected (critical, warning, ok) counters for tag {}",
+            tag_name
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan() {
+        let logfile = FakeLogFile::new("scan.log").lines(&[
+            "2021-01-28 ERROR disk full",
+            "2021-01-28 WARN disk almost full",
+            "2021-01-28 INFO started",
+        ]);
+        let yaml = format!(
+            r#"
+            searches:
+                - logfile:
+                    path: {}
+                  tags:
+                    - name: disk
+                      patterns:
+                        critical: {{ regexes: ['ERROR'] }}
+                        warning: {{ regexes: ['WARN'] }}
+            "#,
+            logfile.path().display()
+        );
+        let mut scan = Scan::new(&yaml).unwrap();
+
+        scan.run().unwrap().assert_counters("disk", 1, 1, 0);
+        assert_eq!(scan.run_data(logfile.path(), "disk").unwrap().last_line, 3);
+
+        // the next run starts where the previous one stopped
+        logfile.append(&["2021-01-28 ERROR disk full again"]);
+        scan.run().unwrap();
+        let run_data = scan.run_data(logfile.path(), "disk").unwrap();
+        assert_eq!(run_data.counters.critical_count, 1);
+        assert_eq!(run_data.last_line, 4);
+
+        let path = logfile.path().to_path_buf();
+        drop(logfile);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn scan_rotated() {
+        let logfile = FakeLogFile::new("rotated.log").line("2021-01-28 ERROR disk full");
+        let yaml = format!(
+            r#"
+            searches:
+                - logfile:
+                    path: {}
+                  tags:
+                    - name: disk
+                      options: "savethresholds"
+                      patterns:
+                        critical: {{ regexes: ['ERROR'] }}
+            "#,
+            logfile.path().display()
+        );
+        let mut scan = Scan::new(&yaml).unwrap();
+        scan.run().unwrap().assert_counters("disk", 1, 0, 0);
+
+        // lines written before the rotation are read from the archive, then the new logfile from its beginning
+        logfile.append(&["2021-01-28 ERROR disk full again"]);
+        let archive = PathBuf::from(format!("{}.1", logfile.path().display()));
+        std::fs::rename(logfile.path(), &archive).unwrap();
+        std::fs::write(logfile.path(), "2021-01-28 ERROR disk still full\n").unwrap();
+
+        // counters are kept with the savethresholds option
+        scan.run().unwrap().assert_counters("disk", 3, 0, 0);
+        assert_eq!(scan.run_data(logfile.path(), "disk").unwrap().last_line, 1);
+
+        std::fs::remove_file(&archive).unwrap();
+    }
+}
@@ -7,12 +7,14 @@ use clap::{App, Arg};
 
 use bzip2::read::BzDecoder;
 use flate2::read::GzDecoder;
+use lz4_flex::frame::FrameDecoder as Lz4Decoder;
 use xz2::read::XzDecoder;
+use zstd::stream::read::Decoder as ZstdDecoder;
 
 use clf::logfile::compression::CompressionScheme;
 
 fn main() {
-    let matches = App::new("Uncompress gzip, bzip2, xz files")
+    let matches = App::new("Uncompress gzip, bzip2, xz, zstd, lz4 files")
     .version("0.1")
     .author("Alain Viguier dandyvica@gmail.com")
     .about(r#"An executable to read compressed files using compression methods defined in the crate"#)
@@ -50,6 +52,16 @@ fn main() {
             let reader = BufReader::new(decoder);
             read_file(reader);
         }
+        CompressionScheme::Zstd => {
+            let decoder = ZstdDecoder::new(file).expect("unable to create zstd decoder");
+            let reader = BufReader::new(decoder);
+            read_file(reader);
+        }
+        CompressionScheme::Lz4 => {
+            let decoder = Lz4Decoder::new(file);
+            let reader = BufReader::new(decoder);
+            read_file(reader);
+        }
         CompressionScheme::Uncompressed => {
             let reader = BufReader::new(file);
             read_file(reader);
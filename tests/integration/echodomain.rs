@@ -3,6 +3,8 @@ use std::env;
 use std::fs::File;
 use std::io::prelude::*;
 
+use clf::configuration::protocol::Receiver;
+
 mod testcase;
 use testcase::JSONStream;
 
@@ -29,18 +31,13 @@ fn main() -> std::io::Result<()> {
                 //     .set_read_timeout(Some(std::time::Duration::new(3, 0)))
                 //     .expect("Couldn't set read timeout");
 
-                // loop to receive data
-                loop {
-                    let json = JSONStream::get_json_from_stream(&mut socket);
-                    if json.is_err() {
-                        break;
-                    }
-
-                    let j = json.unwrap();
+                // loop to receive data, the messages of a batch one by one
+                let mut receiver = Receiver::new(&mut socket);
+                while let Ok(Some(j)) = receiver.recv::<JSONStream>() {
                     let serialized = serde_json::to_string(&j).unwrap();
 
-                    if file.is_some() {
-                        let _ = write!(file.as_ref().unwrap(), "{}\n", serialized);
+                    if let Some(mut file) = file.as_ref() {
+                        let _ = writeln!(file, "{}", serialized);
                     } else {
                         println!("{}", serialized)
                     }
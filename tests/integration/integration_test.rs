@@ -981,6 +981,13 @@ fn main() {
 
                         let j = json.unwrap();
 
+                        // payloads are received in order, the last one telling how many were sent
+                        assert_eq!(j.seq, nb_received);
+                        if let Some(end_of_run) = j.end_of_run {
+                            assert_eq!(end_of_run.sent, nb_received - 1);
+                            break;
+                        }
+
                         // all asserts here
                         if j.args.is_some() {
                             assert_eq!(j.args.unwrap(), &["arg1", "arg2", "arg3"]);
@@ -1076,6 +1083,13 @@ fn main() {
 
                         let j = json.unwrap();
 
+                        // payloads are received in order, the last one telling how many were sent
+                        assert_eq!(j.seq, nb_received);
+                        if let Some(end_of_run) = j.end_of_run {
+                            assert_eq!(end_of_run.sent, nb_received - 1);
+                            break;
+                        }
+
                         // all asserts here
                         // all asserts here
                         if j.args.is_some() {
@@ -1167,6 +1181,9 @@ fn main() {
                             break;
                         }
                         let j = json.unwrap();
+                        if j.end_of_run.is_some() {
+                            break;
+                        }
 
                         let line_number: u64 = j.vars.get("CLF_LINE_NUMBER").unwrap().as_u64();
                         if line_number == 7 {
@@ -12,6 +12,8 @@ use rand::{thread_rng, Rng};
 use regex::Regex;
 use simplelog::*;
 
+use clf::configuration::protocol;
+
 /// Helper macro to assert values in snapshot
 #[macro_export]
 macro_rules! jassert {
@@ -458,36 +460,25 @@ impl VarType {
 }
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JSONStream {
+    pub seq: u64,
     pub args: Option<Vec<String>>,
     pub global: Option<HashMap<String, String>>,
+    #[serde(default)]
     pub vars: HashMap<String, VarType>,
+    pub end_of_run: Option<EndOfRun>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EndOfRun {
+    pub sent: u64,
 }
 
 // utility fn to receive JSON from a stream
 impl JSONStream {
     pub fn get_json_from_stream<T: std::io::Read>(socket: &mut T) -> std::io::Result<JSONStream> {
-        // try to read size first
-        let mut size_buffer = [0; std::mem::size_of::<u16>()];
-
-        let bytes_read = socket.read(&mut size_buffer)?;
-        if bytes_read == 0 {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Interrupted,
-                "socket closed",
-            ));
-        }
-
-        let json_size = u16::from_be_bytes(size_buffer);
-
-        // read JSON raw data
-        let mut json_buffer = vec![0; json_size as usize];
-        socket.read_exact(&mut json_buffer).unwrap();
-
-        // get JSON
-        let s = std::str::from_utf8(&json_buffer).unwrap();
-        //println!("s={}", s);
-        let json: JSONStream = serde_json::from_str(&s).unwrap();
-        Ok(json)
+        let json = protocol::read_frame(socket)?
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Interrupted, "socket closed"))?;
+        Ok(serde_json::from_slice(&json).unwrap())
     }
 }
 